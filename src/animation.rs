@@ -0,0 +1,238 @@
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Quaternion, Vector3};
+
+use crate::model::{ModelVertex, Socket};
+use crate::skinning::SkinVertex;
+
+// ===== JOINT TRANSFORM =====
+// A joint's transform relative to its parent, kept split up so keyframes can
+// be blended part by part
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::zero(),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Transform {
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+// ===== SKELETON =====
+#[derive(Clone)]
+pub struct Joint {
+    pub name: String,
+    pub parent: Option<usize>,
+    // Where the joint sits when no clip moves it
+    pub rest: Transform,
+}
+
+// Joints are ordered parents first, so a pose resolves in one pass
+#[derive(Clone)]
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+}
+
+impl Skeleton {
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.joints.iter().position(|joint| joint.name == name)
+    }
+
+    pub fn rest_pose(&self) -> Vec<Transform> {
+        self.joints.iter().map(|joint| joint.rest).collect()
+    }
+
+    // Each joint's model-space transform for a pose
+    pub fn global_matrices(&self, pose: &[Transform]) -> Vec<Matrix4<f32>> {
+        let mut globals = Vec::<Matrix4<f32>>::with_capacity(self.joints.len());
+        for (joint, local) in self.joints.iter().zip(pose) {
+            let global = match joint.parent {
+                Some(parent) => globals[parent] * local.matrix(),
+                None => local.matrix(),
+            };
+            globals.push(global);
+        }
+        globals
+    }
+}
+
+// ===== ANIMATION CLIPS =====
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+}
+
+// One value per keyframe time
+#[derive(Clone)]
+pub enum Keyframes {
+    Translation(Vec<Vector3<f32>>),
+    Rotation(Vec<Quaternion<f32>>),
+    Scale(Vec<Vector3<f32>>),
+}
+
+// Animates one part of one joint's transform
+#[derive(Clone)]
+pub struct Channel {
+    pub joint: usize,
+    pub times: Vec<f32>,
+    pub interpolation: Interpolation,
+    pub keyframes: Keyframes,
+}
+
+impl Channel {
+    // The keyframes around `time` and how far it is between them
+    fn keys(&self, time: f32) -> (usize, usize, f32) {
+        let next = self.times.partition_point(|&t| t <= time);
+        if next == 0 {
+            return (0, 0, 0.0);
+        }
+        if next == self.times.len() {
+            return (next - 1, next - 1, 0.0);
+        }
+        let previous = next - 1;
+        let span = self.times[next] - self.times[previous];
+        let factor = match self.interpolation {
+            Interpolation::Step => 0.0,
+            Interpolation::Linear if span > 0.0 => (time - self.times[previous]) / span,
+            Interpolation::Linear => 0.0,
+        };
+        (previous, next, factor)
+    }
+
+    fn sample(&self, time: f32, transform: &mut Transform) {
+        if self.times.is_empty() {
+            return;
+        }
+        let (a, b, t) = self.keys(time);
+        match &self.keyframes {
+            Keyframes::Translation(values) => {
+                transform.translation = values[a].lerp(values[b], t);
+            }
+            Keyframes::Rotation(values) => {
+                // Around the short way
+                let (from, to) = (values[a], values[b]);
+                let to = if from.dot(to) < 0.0 { -to } else { to };
+                transform.rotation = from.nlerp(to, t);
+            }
+            Keyframes::Scale(values) => {
+                transform.scale = values[a].lerp(values[b], t);
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AnimationClip {
+    pub name: String,
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+}
+
+impl AnimationClip {
+    // Overwrites the animated parts of `pose`, everything else keeps its value
+    pub fn sample(&self, time: f32, pose: &mut [Transform]) {
+        for channel in &self.channels {
+            channel.sample(time, &mut pose[channel.joint]);
+        }
+    }
+}
+
+// ===== SKINS =====
+// How one mesh is bound to the skeleton. `vertices` index into `joints`,
+// which index into the skeleton.
+#[derive(Clone)]
+pub struct MeshSkin {
+    pub mesh: usize,
+    pub joints: Vec<usize>,
+    pub inverse_bind_matrices: Vec<Matrix4<f32>>,
+    pub bind_pose: Vec<ModelVertex>,
+    pub vertices: Vec<SkinVertex>,
+}
+
+// Everything a loaded model needs to be animated
+#[derive(Clone)]
+pub struct Rig {
+    pub skeleton: Skeleton,
+    pub skins: Vec<MeshSkin>,
+    pub clips: Vec<AnimationClip>,
+}
+
+// ===== POSE EVALUATION =====
+// Plays one clip of a rig on a loop, evaluating the skeleton's pose every
+// frame. `joint_matrices` turns the pose into what a `SkinnedMesh` uploads.
+pub struct Animator {
+    pub rig: Rig,
+    pub clip: usize,
+    pub time: f32,
+    pub speed: f32,
+    pub playing: bool,
+    globals: Vec<Matrix4<f32>>,
+}
+
+impl Animator {
+    pub fn new(rig: Rig) -> Self {
+        let mut animator = Self {
+            rig,
+            clip: 0,
+            time: 0.0,
+            speed: 1.0,
+            playing: true,
+            globals: Vec::new(),
+        };
+        animator.update(0.0);
+        animator
+    }
+
+    pub fn clip(&self) -> Option<&AnimationClip> {
+        self.rig.clips.get(self.clip)
+    }
+
+    // Starts the clip over from the beginning
+    pub fn play(&mut self, clip: usize) {
+        self.clip = clip;
+        self.time = 0.0;
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        let mut pose = self.rig.skeleton.rest_pose();
+        if let Some(clip) = self.rig.clips.get(self.clip) {
+            if self.playing && clip.duration > 0.0 {
+                self.time = (self.time + dt * self.speed).rem_euclid(clip.duration);
+            }
+            clip.sample(self.time, &mut pose);
+        }
+        self.globals = self.rig.skeleton.global_matrices(&pose);
+    }
+
+    // Where the current pose has a socket, in model space
+    pub fn socket_transform(&self, socket: &Socket) -> Matrix4<f32> {
+        match socket.joint.and_then(|joint| self.globals.get(joint)) {
+            Some(global) => *global,
+            None => socket.transform,
+        }
+    }
+
+    // One matrix per joint of the skin, from its bind pose to where the current pose has it
+    pub fn joint_matrices(&self, skin: &MeshSkin) -> Vec<Matrix4<f32>> {
+        skin.joints
+            .iter()
+            .zip(&skin.inverse_bind_matrices)
+            .map(|(&joint, inverse_bind)| self.globals[joint] * inverse_bind)
+            .collect()
+    }
+}
@@ -0,0 +1,489 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+
+use crate::animation::Rig;
+use crate::color::ColorSpace;
+use crate::error::Result;
+use crate::model::{Lod, Material, MaterialUniform, Model};
+use crate::resources;
+use crate::simplify::LodSettings;
+use crate::texture::Texture;
+
+// Levels of detail looked for past the model itself
+const MAX_LODS: usize = 4;
+
+// Refers to an asset held by `Assets`. Cheap to copy around; it stays valid
+// until the reference it came with is given back with `unload_*`.
+pub struct Handle<T> {
+    id: u64,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Handle({})", self.id)
+    }
+}
+
+struct Entry<T> {
+    key: String,
+    asset: T,
+    refs: u32,
+}
+
+// One kind of asset, looked up by handle or by what it was loaded from
+struct Storage<T> {
+    entries: HashMap<u64, Entry<T>>,
+    ids: HashMap<String, u64>,
+}
+
+impl<T> Storage<T> {
+    fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            ids: HashMap::new(),
+        }
+    }
+
+    // Another reference to something that's already loaded
+    fn acquire(&mut self, key: &str) -> Option<Handle<T>> {
+        let id = *self.ids.get(key)?;
+        self.entries.get_mut(&id).unwrap().refs += 1;
+        Some(Handle {
+            id,
+            marker: PhantomData,
+        })
+    }
+
+    fn insert(&mut self, id: u64, key: String, asset: T) -> Handle<T> {
+        self.ids.insert(key.clone(), id);
+        self.entries.insert(
+            id,
+            Entry {
+                key,
+                asset,
+                refs: 1,
+            },
+        );
+        Handle {
+            id,
+            marker: PhantomData,
+        }
+    }
+
+    fn get(&self, handle: Handle<T>) -> Option<&T> {
+        self.entries.get(&handle.id).map(|entry| &entry.asset)
+    }
+
+    fn get_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        self.entries
+            .get_mut(&handle.id)
+            .map(|entry| &mut entry.asset)
+    }
+
+    // Whether that was the last reference and the asset is gone
+    fn release(&mut self, handle: Handle<T>) -> bool {
+        let Some(entry) = self.entries.get_mut(&handle.id) else {
+            return false;
+        };
+        entry.refs -= 1;
+        if entry.refs > 0 {
+            return false;
+        }
+        let entry = self.entries.remove(&handle.id).unwrap();
+        self.ids.remove(&entry.key);
+        tracing::info!("Unloaded {}", entry.key);
+        true
+    }
+}
+
+// ===== ASSET MANAGER =====
+// Everything loaded from res/ goes through here. Loads are keyed by the file
+// they come from, so asking for one twice gives back the same handle and GPU
+// resources instead of reading it again, and every load counts a reference
+// that `unload_*` gives back. Materials share the textures they load, a model
+// holds on to its textures until it's unloaded itself.
+pub struct Assets {
+    // Generates levels of detail for models that don't come with their own
+    pub lod_settings: Option<LodSettings>,
+    // Recomputes every model's normals, keeping edges sharper than this hard.
+    // Meshes without normals get them either way, see `smoothing_angle`.
+    pub normal_smoothing: Option<cgmath::Deg<f32>>,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    next_id: u64,
+    textures: Storage<Texture>,
+    models: Storage<Model>,
+    rigs: HashMap<u64, Rig>,
+    model_textures: HashMap<u64, Vec<Handle<Texture>>>,
+    // Textures requested while a model is loading, see `load_model`
+    recording: Option<Vec<Handle<Texture>>>,
+    // What the model that's loading should generate, see `simplifying`
+    simplifying: Option<LodSettings>,
+    // Stand-ins for maps a material doesn't have, made once and shared
+    white: Option<Texture>,
+    white_srgb: Option<Texture>,
+    flat_normal: Option<Texture>,
+}
+
+impl Assets {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        Self {
+            lod_settings: None,
+            normal_smoothing: None,
+            device: device.clone(),
+            queue: queue.clone(),
+            next_id: 0,
+            textures: Storage::new(),
+            models: Storage::new(),
+            rigs: HashMap::new(),
+            model_textures: HashMap::new(),
+            recording: None,
+            simplifying: None,
+            white: None,
+            white_srgb: None,
+            flat_normal: None,
+        }
+    }
+
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    // What loaders smooth normals with, `None` to keep the file's. Missing
+    // normals are smoothed across edges up to 45 degrees by default.
+    pub fn smoothing_angle(&self, has_normals: bool) -> Option<cgmath::Deg<f32>> {
+        match self.normal_smoothing {
+            Some(angle) => Some(angle),
+            None if !has_normals => Some(cgmath::Deg(45.0)),
+            None => None,
+        }
+    }
+
+    // While a model loads, the levels of detail its loader should generate
+    pub fn simplifying(&self) -> Option<LodSettings> {
+        self.simplifying
+    }
+
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn record(&mut self, handle: Handle<Texture>) -> Handle<Texture> {
+        if let Some(recording) = &mut self.recording {
+            recording.push(handle);
+        }
+        handle
+    }
+
+    // An image file in res/. KTX2 and DDS files keep their BCn compression
+    // where the device supports it.
+    pub async fn load_texture(
+        &mut self,
+        file_name: &str,
+        color_space: ColorSpace,
+    ) -> Result<Handle<Texture>> {
+        let key = format!("{} ({:?})", file_name, color_space);
+        if let Some(handle) = self.textures.acquire(&key) {
+            return Ok(self.record(handle));
+        }
+        let texture = Texture::from_path(&self.device, &self.queue, file_name, color_space).await?;
+        Ok(self.insert_texture(key, texture))
+    }
+
+    // A texture made from image files rather than loaded as it is, like a
+    // normal map computed from a height map. `build` gets the images in the
+    // order of `files`, `key` names the result.
+    pub async fn load_derived_texture(
+        &mut self,
+        key: &str,
+        files: &[&str],
+        color_space: ColorSpace,
+        build: impl FnOnce(Vec<image::DynamicImage>) -> image::DynamicImage,
+    ) -> Result<Handle<Texture>> {
+        let key = format!("{} ({:?})", key, color_space);
+        if let Some(handle) = self.textures.acquire(&key) {
+            return Ok(self.record(handle));
+        }
+        let mut images = Vec::with_capacity(files.len());
+        for file_name in files {
+            images.push(resources::decode_image(
+                &resources::load_binary(file_name).await?,
+            )?);
+        }
+        let texture = Texture::from_image_with_format(
+            &self.device,
+            &self.queue,
+            &build(images),
+            Some(&key),
+            color_space.texture_format(),
+        )?;
+        Ok(self.insert_texture(key, texture))
+    }
+
+    // An image that's already in memory, like one packed into a glTF buffer.
+    // `key` names it for deduplication, `file#image` for instance.
+    pub fn load_texture_from_memory(
+        &mut self,
+        key: &str,
+        data: &[u8],
+        color_space: ColorSpace,
+    ) -> Result<Handle<Texture>> {
+        let key = format!("{} ({:?})", key, color_space);
+        if let Some(handle) = self.textures.acquire(&key) {
+            return Ok(self.record(handle));
+        }
+        let texture = Texture::from_encoded(&self.device, &self.queue, data, &key, color_space)?;
+        Ok(self.insert_texture(key, texture))
+    }
+
+    fn insert_texture(&mut self, key: String, texture: Texture) -> Handle<Texture> {
+        let id = self.next_id();
+        let handle = self.textures.insert(id, key, texture);
+        self.record(handle)
+    }
+
+    // A cubemap stored as px/nx/py/ny/pz/nz.png in `dir`
+    pub async fn load_cubemap(&mut self, dir: &str) -> anyhow::Result<Handle<Texture>> {
+        let key = format!("{}/ (cubemap)", dir);
+        if let Some(handle) = self.textures.acquire(&key) {
+            return Ok(handle);
+        }
+        let cubemap = resources::load_cubemap(dir, &self.device, &self.queue).await?;
+        let id = self.next_id();
+        Ok(self.textures.insert(id, key, cubemap))
+    }
+
+    // An OBJ or glTF model along with the textures its materials use. A glTF
+    // file's skeleton and clips come with it, see `rig`. Skinned meshes get
+    // vertex buffers the skinning pass can write to when `skinning` is set.
+    // Levels of detail come from `<model>.lod1.obj`, `<model>.lod2.obj` and
+    // so on next to it, in the model's own format, or are generated with
+    // `lod_settings` when there are none.
+    pub async fn load_model(
+        &mut self,
+        file_name: &str,
+        layout: &wgpu::BindGroupLayout,
+        skinning: bool,
+    ) -> Result<Handle<Model>> {
+        if let Some(handle) = self.models.acquire(file_name) {
+            return Ok(handle);
+        }
+
+        self.recording = Some(Vec::new());
+        let has_lods = resources::load_binary(&lod_file_name(file_name, 1))
+            .await
+            .is_ok();
+        self.simplifying = self.lod_settings.filter(|_| !has_lods);
+        let mut loaded = Model::load(file_name, self, layout, skinning).await;
+        self.simplifying = None;
+        // Animated models keep to their skinned meshes
+        if let Ok((model, None)) = &mut loaded {
+            if has_lods {
+                self.load_lods(file_name, model, layout).await;
+            }
+        }
+        let textures = self.recording.take().unwrap_or_default();
+        let (model, rig) = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                for texture in textures {
+                    self.textures.release(texture);
+                }
+                return Err(e);
+            }
+        };
+
+        let id = self.next_id();
+        self.model_textures.insert(id, textures);
+        if let Some(rig) = rig {
+            self.rigs.insert(id, rig);
+        }
+        Ok(self.models.insert(id, file_name.to_string(), model))
+    }
+
+    // A model made in code rather than loaded, like one of the `shapes`.
+    // `key` names it, asking for a key that's already in gives that back and
+    // drops `model`.
+    pub fn insert_model(&mut self, key: &str, model: Model) -> Handle<Model> {
+        if let Some(handle) = self.models.acquire(key) {
+            return handle;
+        }
+        let id = self.next_id();
+        self.models.insert(id, key.to_string(), model)
+    }
+
+    // A material without maps, just the factors, for models made in code
+    pub fn plain_material(
+        &mut self,
+        name: &str,
+        factors: MaterialUniform,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Material {
+        let (albedo, normal, white) = (
+            self.white_srgb_texture(),
+            self.flat_normal_texture(),
+            self.white_texture(),
+        );
+        Material::new(
+            &self.device,
+            name,
+            albedo,
+            normal,
+            white.clone(),
+            white.clone(),
+            white,
+            factors,
+            layout,
+        )
+    }
+
+    // Stops at the first level that's missing. A level's meshes are matched
+    // to the model's materials by name, the materials it loads itself are
+    // dropped, though not the textures they share with the model's.
+    async fn load_lods(
+        &mut self,
+        file_name: &str,
+        model: &mut Model,
+        layout: &wgpu::BindGroupLayout,
+    ) {
+        for level in 1..=MAX_LODS {
+            let lod_file = lod_file_name(file_name, level);
+            if resources::load_binary(&lod_file).await.is_err() {
+                break;
+            }
+            let lod = match Model::load(&lod_file, self, layout, false).await {
+                Ok((lod, _)) => lod,
+                Err(e) => {
+                    tracing::warn!("Couldn't load level of detail {}: {}", lod_file, e.report());
+                    break;
+                }
+            };
+            let mut meshes = lod.meshes;
+            for mesh in &mut meshes {
+                let name = lod.materials.get(mesh.material).map(|m| m.name.as_str());
+                mesh.material = model
+                    .materials
+                    .iter()
+                    .position(|material| Some(material.name.as_str()) == name)
+                    .unwrap_or(0);
+            }
+            model.lods.push(Lod {
+                meshes,
+                screen_size: Lod::default_screen_size(level),
+            });
+        }
+    }
+
+    // Panics if the handle's asset was unloaded
+    pub fn texture(&self, handle: Handle<Texture>) -> &Texture {
+        self.textures
+            .get(handle)
+            .expect("texture handle used after it was unloaded")
+    }
+
+    // Panics if the handle's asset was unloaded
+    pub fn model(&self, handle: Handle<Model>) -> &Model {
+        self.models
+            .get(handle)
+            .expect("model handle used after it was unloaded")
+    }
+
+    // For changing a loaded model in place, like swapping a material's
+    // texture. Panics if the handle's asset was unloaded.
+    pub fn model_mut(&mut self, handle: Handle<Model>) -> &mut Model {
+        self.models
+            .get_mut(handle)
+            .expect("model handle used after it was unloaded")
+    }
+
+    // The skeleton and animation clips that came with a glTF model
+    pub fn rig(&self, handle: Handle<Model>) -> Option<&Rig> {
+        self.rigs.get(&handle.id)
+    }
+
+    // Gives back one reference. The texture is unloaded with the last, or
+    // once no model uses it either. Returns whether it was.
+    pub fn unload_texture(&mut self, handle: Handle<Texture>) -> bool {
+        self.textures.release(handle)
+    }
+
+    // Gives back one reference, unloading the model and its references to
+    // its textures with the last. Returns whether it was unloaded.
+    pub fn unload_model(&mut self, handle: Handle<Model>) -> bool {
+        if !self.models.release(handle) {
+            return false;
+        }
+        self.rigs.remove(&handle.id);
+        for texture in self.model_textures.remove(&handle.id).unwrap_or_default() {
+            self.textures.release(texture);
+        }
+        true
+    }
+
+    // Opaque white for data maps: metallic-roughness, occlusion and emissive
+    pub fn white_texture(&mut self) -> Texture {
+        let (device, queue) = (&self.device, &self.queue);
+        self.white
+            .get_or_insert_with(|| resources::default_white_texture(device, queue))
+            .clone()
+    }
+
+    // Opaque white for a missing albedo map
+    pub fn white_srgb_texture(&mut self) -> Texture {
+        let (device, queue) = (&self.device, &self.queue);
+        self.white_srgb
+            .get_or_insert_with(|| {
+                Texture::solid_color(
+                    device,
+                    queue,
+                    [255, 255, 255, 255],
+                    ColorSpace::Srgb.texture_format(),
+                    "default_albedo",
+                )
+            })
+            .clone()
+    }
+
+    // A normal map that leaves the surface normal as it is
+    pub fn flat_normal_texture(&mut self) -> Texture {
+        let (device, queue) = (&self.device, &self.queue);
+        self.flat_normal
+            .get_or_insert_with(|| resources::default_normal_texture(device, queue))
+            .clone()
+    }
+}
+
+// `dir/model.obj` has its first level of detail in `dir/model.lod1.obj`
+fn lod_file_name(file_name: &str, level: usize) -> String {
+    let path = std::path::Path::new(file_name);
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    path.with_extension(format!("lod{}.{}", level, extension))
+        .to_string_lossy()
+        .to_string()
+}
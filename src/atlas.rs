@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+
+use anyhow::bail;
+
+use crate::color::ColorSpace;
+use crate::resources;
+use crate::texture::Texture;
+
+// Where a sprite ended up in its atlas, in the atlas's texture coordinates
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AtlasRegion {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+impl AtlasRegion {
+    // Turns coordinates across the sprite on its own into coordinates in the atlas
+    pub fn remap(&self, uv: [f32; 2]) -> [f32; 2] {
+        [
+            self.min[0] + uv[0] * (self.max[0] - self.min[0]),
+            self.min[1] + uv[1] * (self.max[1] - self.min[1]),
+        ]
+    }
+
+    // min.xy, max.xy, the way a shader takes it
+    pub fn to_vec4(&self) -> [f32; 4] {
+        [self.min[0], self.min[1], self.max[0], self.max[1]]
+    }
+}
+
+// ===== ATLAS PACKING =====
+// Collects small sprites for particles and the HUD and packs them into one
+// texture, so everything drawing from them shares a bind group. Sprites go on
+// shelves tallest first, in the smallest power of two square that fits.
+pub struct AtlasBuilder {
+    // Texels each sprite's edge is repeated outwards, so filtering and the
+    // smaller mips don't pull in the neighbors
+    pub padding: u32,
+    pub max_size: u32,
+    sprites: Vec<(String, image::RgbaImage)>,
+}
+
+impl AtlasBuilder {
+    pub fn new(max_size: u32) -> Self {
+        Self {
+            padding: 2,
+            max_size,
+            sprites: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, name: &str, image: &image::DynamicImage) {
+        self.sprites.push((name.to_string(), image.to_rgba8()));
+    }
+
+    // An image file in res/, named after its path
+    pub async fn load(&mut self, file_name: &str) -> anyhow::Result<()> {
+        let image = resources::decode_image(&resources::load_binary(file_name).await?)?;
+        self.add(file_name, &image);
+        Ok(())
+    }
+
+    // Top left corner of each sprite, in the order they were added, or None
+    // when they don't fit in `size`
+    fn pack(&self, size: u32) -> Option<Vec<[u32; 2]>> {
+        let mut order = (0..self.sprites.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| std::cmp::Reverse(self.sprites[i].1.height()));
+        let mut corners = vec![[0; 2]; self.sprites.len()];
+        let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+        for i in order {
+            let (width, height) = self.sprites[i].1.dimensions();
+            let (width, height) = (width + 2 * self.padding, height + 2 * self.padding);
+            if x + width > size {
+                // Next shelf up
+                (x, y, shelf_height) = (0, y + shelf_height, 0);
+            }
+            if x + width > size || y + height > size {
+                return None;
+            }
+            corners[i] = [x + self.padding, y + self.padding];
+            x += width;
+            shelf_height = shelf_height.max(height);
+        }
+        Some(corners)
+    }
+
+    pub fn build(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        label: &str,
+        color_space: ColorSpace,
+    ) -> anyhow::Result<TextureAtlas> {
+        if let Some((name, _)) = self
+            .sprites
+            .iter()
+            .find(|(_, image)| image.width() == 0 || image.height() == 0)
+        {
+            bail!("sprite {} is empty", name);
+        }
+        let area = self
+            .sprites
+            .iter()
+            .map(|(_, image)| {
+                (image.width() + 2 * self.padding) as u64
+                    * (image.height() + 2 * self.padding) as u64
+            })
+            .sum::<u64>();
+        let mut size = ((area as f64).sqrt() as u32).next_power_of_two().max(1);
+        let corners = loop {
+            if size > self.max_size {
+                bail!(
+                    "{} sprites don't fit in a {}x{} atlas",
+                    self.sprites.len(),
+                    self.max_size,
+                    self.max_size
+                );
+            }
+            match self.pack(size) {
+                Some(corners) => break corners,
+                None => size *= 2,
+            }
+        };
+
+        let mut atlas = image::RgbaImage::new(size, size);
+        let mut regions = HashMap::new();
+        let padding = self.padding as i64;
+        for ((name, sprite), [x, y]) in self.sprites.iter().zip(corners) {
+            let (width, height) = sprite.dimensions();
+            for ty in -padding..height as i64 + padding {
+                for tx in -padding..width as i64 + padding {
+                    let texel = sprite.get_pixel(
+                        tx.clamp(0, width as i64 - 1) as u32,
+                        ty.clamp(0, height as i64 - 1) as u32,
+                    );
+                    atlas.put_pixel((x as i64 + tx) as u32, (y as i64 + ty) as u32, *texel);
+                }
+            }
+            let scale = 1.0 / size as f32;
+            regions.insert(
+                name.clone(),
+                AtlasRegion {
+                    min: [x as f32 * scale, y as f32 * scale],
+                    max: [(x + width) as f32 * scale, (y + height) as f32 * scale],
+                },
+            );
+        }
+
+        let texture = Texture::from_image_with_format(
+            device,
+            queue,
+            &image::DynamicImage::ImageRgba8(atlas),
+            Some(label),
+            color_space.texture_format(),
+        )?;
+        Ok(TextureAtlas {
+            texture,
+            regions,
+            size,
+        })
+    }
+}
+
+pub struct TextureAtlas {
+    pub texture: Texture,
+    regions: HashMap<String, AtlasRegion>,
+    size: u32,
+}
+
+impl TextureAtlas {
+    pub fn region(&self, name: &str) -> Option<AtlasRegion> {
+        self.regions.get(name).copied()
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder(sizes: &[(u32, u32)]) -> AtlasBuilder {
+        let mut builder = AtlasBuilder::new(256);
+        for (i, &(width, height)) in sizes.iter().enumerate() {
+            let image = image::DynamicImage::new_rgba8(width, height);
+            builder.add(&i.to_string(), &image);
+        }
+        builder
+    }
+
+    #[test]
+    fn sprites_and_padding_dont_overlap() {
+        let builder = builder(&[(16, 16), (8, 24), (30, 4), (10, 10), (20, 12)]);
+        let corners = builder.pack(64).unwrap();
+        // Each sprite with its padding, as min and max corners
+        let padding = builder.padding;
+        let boxes = builder
+            .sprites
+            .iter()
+            .zip(&corners)
+            .map(|((_, image), &[x, y])| {
+                let (width, height) = image.dimensions();
+                (
+                    [x - padding, y - padding],
+                    [x + width + padding, y + height + padding],
+                )
+            })
+            .collect::<Vec<_>>();
+        for (i, (min, max)) in boxes.iter().enumerate() {
+            assert!(max[0] <= 64 && max[1] <= 64, "sprite {} outside", i);
+            for (other_min, other_max) in &boxes[i + 1..] {
+                let apart = max[0] <= other_min[0]
+                    || other_max[0] <= min[0]
+                    || max[1] <= other_min[1]
+                    || other_max[1] <= min[1];
+                assert!(apart, "sprite {} overlaps another", i);
+            }
+        }
+    }
+
+    #[test]
+    fn too_small_doesnt_pack() {
+        let builder = builder(&[(16, 16), (16, 16)]);
+        // 20 texels each with the padding
+        assert!(builder.pack(32).is_none());
+        assert!(builder.pack(40).is_some());
+    }
+
+    #[test]
+    fn remap_goes_across_the_region() {
+        let region = AtlasRegion {
+            min: [0.25, 0.5],
+            max: [0.5, 1.0],
+        };
+        assert_eq!(region.remap([0.0, 0.0]), [0.25, 0.5]);
+        assert_eq!(region.remap([0.5, 0.5]), [0.375, 0.75]);
+        assert_eq!(region.remap([1.0, 1.0]), [0.5, 1.0]);
+    }
+}
@@ -0,0 +1,466 @@
+use std::any::Any;
+
+use wgpu::util::DeviceExt;
+
+use crate::post_process::PostEffect;
+
+// Number of half-resolution steps in the blur chain
+pub const BLOOM_LEVELS: usize = 5;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomUniform {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+    _padding: f32,
+    direction: [f32; 2],
+    _padding2: [f32; 2],
+}
+
+// ===== BLOOM LEVEL =====
+// One step of the mip chain. `view` holds the blurred result and `scratch` is
+// the intermediate target between the horizontal and vertical blur.
+struct BloomLevel {
+    view: wgpu::TextureView,
+    scratch: wgpu::TextureView,
+    view_bind_group: wgpu::BindGroup,
+    scratch_bind_group: wgpu::BindGroup,
+}
+
+// ===== BLOOM =====
+// Bright pass -> downsample + separable blur per level -> upsample back up the
+// chain -> add onto a copy of the scene before tonemapping, so bright things
+// like the fire bleed light into their surroundings.
+pub struct Bloom {
+    pub enabled: bool,
+    pub threshold: f32, // Scene brightness where bloom starts
+    pub knee: f32,      // Width of the soft transition around the threshold
+    pub intensity: f32,
+
+    format: wgpu::TextureFormat,
+    horizontal_buffer: wgpu::Buffer,
+    vertical_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    levels: Vec<BloomLevel>,
+
+    bright_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    blur_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+    copy_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+}
+
+impl Bloom {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let threshold = 1.0;
+        let knee = 0.5;
+        let intensity = 0.6;
+
+        // The blur direction is the only thing that differs between passes
+        let create_buffer = |label: &str, direction: [f32; 2]| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: bytemuck::cast_slice(&[BloomUniform {
+                    threshold,
+                    knee,
+                    intensity,
+                    _padding: 0.0,
+                    direction,
+                    _padding2: [0.0; 2],
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            })
+        };
+        let horizontal_buffer = create_buffer("Bloom Horizontal Buffer", [1.0, 0.0]);
+        let vertical_buffer = create_buffer("Bloom Vertical Buffer", [0.0, 1.0]);
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("bloom_bind_group_layout"),
+        });
+
+        let levels = Self::create_levels(
+            device,
+            &bind_group_layout,
+            &sampler,
+            &horizontal_buffer,
+            &vertical_buffer,
+            config.width,
+            config.height,
+            format,
+        );
+
+        // ===== PIPELINES =====
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Bloom Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("bloom.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        // Upsample and composite add onto what is already in the target
+        let additive = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent::OVER,
+        };
+        let create_pipeline = |label: &str, entry_point: &str, blend: wgpu::BlendState| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[], // Fullscreen triangle is generated in the shader
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(blend),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let bright_pipeline = create_pipeline(
+            "Bloom Bright Pipeline",
+            "fs_bright",
+            wgpu::BlendState::REPLACE,
+        );
+        let downsample_pipeline = create_pipeline(
+            "Bloom Downsample Pipeline",
+            "fs_downsample",
+            wgpu::BlendState::REPLACE,
+        );
+        let blur_pipeline =
+            create_pipeline("Bloom Blur Pipeline", "fs_blur", wgpu::BlendState::REPLACE);
+        let upsample_pipeline = create_pipeline("Bloom Upsample Pipeline", "fs_upsample", additive);
+        let copy_pipeline =
+            create_pipeline("Bloom Copy Pipeline", "fs_copy", wgpu::BlendState::REPLACE);
+        let composite_pipeline =
+            create_pipeline("Bloom Composite Pipeline", "fs_composite", additive);
+
+        Self {
+            enabled: true,
+            threshold,
+            knee,
+            intensity,
+            format,
+            horizontal_buffer,
+            vertical_buffer,
+            sampler,
+            bind_group_layout,
+            levels,
+            bright_pipeline,
+            downsample_pipeline,
+            blur_pipeline,
+            upsample_pipeline,
+            copy_pipeline,
+            composite_pipeline,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        view: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+        buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("bloom_bind_group"),
+        })
+    }
+
+    // Separate textures per level rather than mips of one texture, so no pass
+    // ever reads and writes the same texture (GL doesn't like that)
+    #[allow(clippy::too_many_arguments)]
+    fn create_levels(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        horizontal_buffer: &wgpu::Buffer,
+        vertical_buffer: &wgpu::Buffer,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> Vec<BloomLevel> {
+        let create_target = |width: u32, height: u32, label: &str| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+
+        (0..BLOOM_LEVELS)
+            .map(|i| {
+                let level_width = (width >> (i + 1)).max(1);
+                let level_height = (height >> (i + 1)).max(1);
+                let view = create_target(level_width, level_height, "Bloom Level");
+                let scratch = create_target(level_width, level_height, "Bloom Scratch");
+                let view_bind_group =
+                    Self::create_bind_group(device, layout, &view, sampler, horizontal_buffer);
+                let scratch_bind_group =
+                    Self::create_bind_group(device, layout, &scratch, sampler, vertical_buffer);
+                BloomLevel {
+                    view,
+                    scratch,
+                    view_bind_group,
+                    scratch_bind_group,
+                }
+            })
+            .collect()
+    }
+
+    fn fullscreen_pass(
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+        target: &wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.draw(0..3, 0..1);
+        crate::stats::count_draw();
+    }
+}
+
+impl PostEffect for Bloom {
+    fn name(&self) -> &'static str {
+        "bloom"
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // The chain follows the window size
+    fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.levels = Self::create_levels(
+            device,
+            &self.bind_group_layout,
+            &self.sampler,
+            &self.horizontal_buffer,
+            &self.vertical_buffer,
+            width,
+            height,
+            self.format,
+        );
+    }
+
+    fn update(&self, queue: &wgpu::Queue) {
+        let uniform = |direction: [f32; 2]| BloomUniform {
+            threshold: self.threshold,
+            knee: self.knee,
+            intensity: self.intensity,
+            _padding: 0.0,
+            direction,
+            _padding2: [0.0; 2],
+        };
+        queue.write_buffer(
+            &self.horizontal_buffer,
+            0,
+            bytemuck::cast_slice(&[uniform([1.0, 0.0])]),
+        );
+        queue.write_buffer(
+            &self.vertical_buffer,
+            0,
+            bytemuck::cast_slice(&[uniform([0.0, 1.0])]),
+        );
+    }
+
+    // Run the whole chain and write the scene plus its glow into `output`
+    fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+    ) {
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+        // The input moves between the chain's targets, so it's bound per frame
+        let input_bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            input,
+            &self.sampler,
+            &self.horizontal_buffer,
+        );
+
+        // ===== DOWN THE CHAIN =====
+        for (i, level) in self.levels.iter().enumerate() {
+            if i == 0 {
+                Self::fullscreen_pass(
+                    encoder,
+                    "Bloom Bright Pass",
+                    &self.bright_pipeline,
+                    &input_bind_group,
+                    &level.view,
+                    clear,
+                );
+            } else {
+                Self::fullscreen_pass(
+                    encoder,
+                    "Bloom Downsample Pass",
+                    &self.downsample_pipeline,
+                    &self.levels[i - 1].view_bind_group,
+                    &level.view,
+                    clear,
+                );
+            }
+            Self::fullscreen_pass(
+                encoder,
+                "Bloom Horizontal Blur Pass",
+                &self.blur_pipeline,
+                &level.view_bind_group,
+                &level.scratch,
+                clear,
+            );
+            Self::fullscreen_pass(
+                encoder,
+                "Bloom Vertical Blur Pass",
+                &self.blur_pipeline,
+                &level.scratch_bind_group,
+                &level.view,
+                clear,
+            );
+        }
+
+        // ===== BACK UP THE CHAIN =====
+        for i in (0..BLOOM_LEVELS - 1).rev() {
+            Self::fullscreen_pass(
+                encoder,
+                "Bloom Upsample Pass",
+                &self.upsample_pipeline,
+                &self.levels[i + 1].view_bind_group,
+                &self.levels[i].view,
+                wgpu::LoadOp::Load,
+            );
+        }
+
+        // Scene first, then the glow added on top
+        Self::fullscreen_pass(
+            encoder,
+            "Bloom Copy Pass",
+            &self.copy_pipeline,
+            &input_bind_group,
+            output,
+            clear,
+        );
+        Self::fullscreen_pass(
+            encoder,
+            "Bloom Composite Pass",
+            &self.composite_pipeline,
+            &self.levels[0].view_bind_group,
+            output,
+            wgpu::LoadOp::Load,
+        );
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -0,0 +1,104 @@
+// ===== BLOOM =====
+// Bright pass, downsample, separable blur and upsample steps for the bloom mip
+// chain. Every pass is a fullscreen triangle reading one source texture.
+
+struct BloomUniform {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+    direction: vec2<f32>, // Blur axis, (1, 0) or (0, 1)
+};
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> bloom: BloomUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// Three vertices covering the whole screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn source_texel() -> vec2<f32> {
+    return 1.0 / vec2<f32>(textureDimensions(t_source));
+}
+
+// 4 bilinear taps = a 4x4 box around the pixel, which keeps the downsample stable
+fn box_filter(uv: vec2<f32>) -> vec3<f32> {
+    let d = source_texel();
+    var color = textureSample(t_source, s_source, uv + vec2<f32>(-d.x, -d.y)).rgb;
+    color += textureSample(t_source, s_source, uv + vec2<f32>(d.x, -d.y)).rgb;
+    color += textureSample(t_source, s_source, uv + vec2<f32>(-d.x, d.y)).rgb;
+    color += textureSample(t_source, s_source, uv + vec2<f32>(d.x, d.y)).rgb;
+    return color * 0.25;
+}
+
+// Keep only what is brighter than the threshold, with a soft knee so the
+// cutoff doesn't show up as a hard edge
+@fragment
+fn fs_bright(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Clamp so single very bright particles don't turn into flickering blobs
+    let color = min(box_filter(in.uv), vec3<f32>(64.0));
+    let brightness = max(color.r, max(color.g, color.b));
+    var soft = clamp(brightness - bloom.threshold + bloom.knee, 0.0, 2.0 * bloom.knee);
+    soft = soft * soft / (4.0 * bloom.knee + 0.0001);
+    let contribution = max(soft, brightness - bloom.threshold) / max(brightness, 0.0001);
+    return vec4<f32>(color * contribution, 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(box_filter(in.uv), 1.0);
+}
+
+// 9-tap gaussian along one axis, folded into 5 bilinear taps
+@fragment
+fn fs_blur(in: VertexOutput) -> @location(0) vec4<f32> {
+    let offset = source_texel() * bloom.direction;
+    var color = textureSample(t_source, s_source, in.uv).rgb * 0.2270270270;
+    color += textureSample(t_source, s_source, in.uv + offset * 1.3846153846).rgb * 0.3162162162;
+    color += textureSample(t_source, s_source, in.uv - offset * 1.3846153846).rgb * 0.3162162162;
+    color += textureSample(t_source, s_source, in.uv + offset * 3.2307692308).rgb * 0.0702702703;
+    color += textureSample(t_source, s_source, in.uv - offset * 3.2307692308).rgb * 0.0702702703;
+    return vec4<f32>(color, 1.0);
+}
+
+// 3x3 tent filter from the smaller level, added onto the larger one by blending
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let d = source_texel();
+    var color = textureSample(t_source, s_source, in.uv).rgb * 4.0;
+    color += textureSample(t_source, s_source, in.uv + vec2<f32>(-d.x, 0.0)).rgb * 2.0;
+    color += textureSample(t_source, s_source, in.uv + vec2<f32>(d.x, 0.0)).rgb * 2.0;
+    color += textureSample(t_source, s_source, in.uv + vec2<f32>(0.0, -d.y)).rgb * 2.0;
+    color += textureSample(t_source, s_source, in.uv + vec2<f32>(0.0, d.y)).rgb * 2.0;
+    color += textureSample(t_source, s_source, in.uv + vec2<f32>(-d.x, -d.y)).rgb;
+    color += textureSample(t_source, s_source, in.uv + vec2<f32>(d.x, -d.y)).rgb;
+    color += textureSample(t_source, s_source, in.uv + vec2<f32>(-d.x, d.y)).rgb;
+    color += textureSample(t_source, s_source, in.uv + vec2<f32>(d.x, d.y)).rgb;
+    return vec4<f32>(color / 16.0, 1.0);
+}
+
+// Copy of the scene for the composite to add onto
+@fragment
+fn fs_copy(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(textureSample(t_source, s_source, in.uv).rgb, 1.0);
+}
+
+// Final blur added back onto the HDR scene
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(textureSample(t_source, s_source, in.uv).rgb * bloom.intensity, 1.0);
+}
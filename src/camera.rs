@@ -0,0 +1,356 @@
+use cgmath::prelude::*;
+use winit::{
+    event::{ElementState, KeyEvent, WindowEvent},
+    keyboard::{KeyCode, PhysicalKey},
+};
+
+use crate::{frustum, model, raycast, texture};
+
+// Orthographic views show as much as a perspective one would at the
+// target's distance, so switching keeps what's being looked at framed and
+// moving closer still zooms in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Projection {
+    #[default]
+    Perspective,
+    Orthographic,
+}
+
+impl Projection {
+    pub fn toggled(self) -> Self {
+        match self {
+            Projection::Perspective => Projection::Orthographic,
+            Projection::Orthographic => Projection::Perspective,
+        }
+    }
+}
+
+// ===== CAMERA =====
+#[derive(Debug, Clone, Copy)]
+pub struct Camera {
+    pub(crate) eye: cgmath::Point3<f32>,
+    pub(crate) target: cgmath::Point3<f32>,
+    pub(crate) up: cgmath::Vector3<f32>,
+    pub(crate) aspect: f32,
+    pub(crate) fovy: f32,
+    pub(crate) znear: f32,
+    pub(crate) zfar: f32,
+    pub(crate) depth_mode: texture::DepthMode,
+    pub(crate) projection: Projection,
+}
+
+impl Camera {
+    // ===== PROJECTION SETTINGS =====
+    // Degrees from the bottom of the view to the top
+    pub fn fovy(&self) -> f32 {
+        self.fovy
+    }
+
+    // Kept between 1 and 170 degrees
+    pub fn set_fovy(&mut self, fovy: f32) {
+        self.fovy = fovy.clamp(1.0, 170.0);
+    }
+
+    // Distances to the near and far planes
+    pub fn clip_planes(&self) -> (f32, f32) {
+        (self.znear, self.zfar)
+    }
+
+    // Anything closer than `znear` or further than `zfar` is clipped. Keep
+    // the ratio between them down, depth gets coarser the further it is.
+    pub fn set_clip_planes(&mut self, znear: f32, zfar: f32) -> anyhow::Result<()> {
+        if !(znear > 0.0 && zfar > znear && zfar.is_finite()) {
+            anyhow::bail!(
+                "Clip planes need 0 < near < far, got {} and {}",
+                znear,
+                zfar
+            );
+        }
+        self.znear = znear;
+        self.zfar = zfar;
+        Ok(())
+    }
+
+    pub(crate) fn build_view_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up)
+    }
+
+    // Half the height of an orthographic view
+    pub(crate) fn ortho_half_height(&self) -> f32 {
+        use cgmath::MetricSpace;
+        self.eye.distance(self.target) * (cgmath::Deg(self.fovy) / 2.0).tan()
+    }
+
+    // OpenGL-style, from `near` to `far` in front of the camera
+    pub(crate) fn projection_between(&self, near: f32, far: f32) -> cgmath::Matrix4<f32> {
+        match self.projection {
+            Projection::Perspective => {
+                cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, near, far)
+            }
+            Projection::Orthographic => {
+                let half_height = self.ortho_half_height();
+                let half_width = half_height * self.aspect;
+                cgmath::ortho(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    near,
+                    far,
+                )
+            }
+        }
+    }
+
+    pub(crate) fn build_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        // Reverse-Z is the same projection with near and far swapped
+        let (near, far) = match self.depth_mode {
+            texture::DepthMode::Standard => (self.znear, self.zfar),
+            texture::DepthMode::ReverseZ => (self.zfar, self.znear),
+        };
+        OPENGL_TO_WGPU_MATRIX * self.projection_between(near, far)
+    }
+
+    pub(crate) fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        // 1.
+        let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
+        // 2.
+        let proj = self.build_projection_matrix();
+
+        // 3.
+        proj * view
+    }
+
+    pub fn frustum(&self) -> frustum::Frustum {
+        frustum::Frustum::from_view_proj(self.build_view_projection_matrix())
+    }
+
+    // Roughly how much of the screen's height a sphere covers, unbounded once
+    // the camera is inside it
+    pub fn screen_size(&self, sphere: &model::BoundingSphere) -> f32 {
+        use cgmath::MetricSpace;
+
+        if self.projection == Projection::Orthographic {
+            return sphere.radius / self.ortho_half_height();
+        }
+        let distance = self.eye.distance(sphere.center);
+        if distance <= sphere.radius {
+            return f32::INFINITY;
+        }
+        sphere.radius / (distance * (cgmath::Deg(self.fovy) / 2.0).tan())
+    }
+
+    // World units covering `fraction` of the view's height at `point`, for
+    // things that keep their size on screen however far away they are
+    pub fn units_across(&self, point: cgmath::Point3<f32>, fraction: f32) -> f32 {
+        use cgmath::MetricSpace;
+
+        let half_height = match self.projection {
+            Projection::Perspective => {
+                self.eye.distance(point) * (cgmath::Deg(self.fovy) / 2.0).tan()
+            }
+            Projection::Orthographic => self.ortho_half_height(),
+        };
+        half_height * 2.0 * fraction
+    }
+
+    // Looks at the middle of the box from as far back along the current view
+    // as it takes to fit all of it on screen, whatever its size
+    pub fn frame_aabb(&mut self, aabb: &model::Aabb) {
+        let radius = aabb.size().magnitude() * 0.5;
+        // Half the field of view the narrower way
+        let tan = (cgmath::Deg(self.fovy) / 2.0).tan();
+        let half_fov = (tan * self.aspect.min(1.0)).atan();
+        // Looking along -Z when the eye is on the target
+        let back = match (self.eye - self.target).normalize() {
+            back if back.x.is_finite() => back,
+            _ => cgmath::Vector3::unit_z(),
+        };
+        self.target = aabb.center();
+        self.eye = self.target + back * (radius / half_fov.sin()).max(self.znear * 2.0);
+    }
+
+    // Out from the camera through the point `x` and `y` across the view,
+    // from 0 to 1 starting at the top left. Starts on the near plane, which
+    // for orthographic views is where every ray starts parallel.
+    pub fn screen_to_ray(&self, x: f32, y: f32) -> raycast::Ray {
+        let inverse = (self.projection_between(self.znear, self.zfar) * self.build_view_matrix())
+            .invert()
+            .unwrap_or(cgmath::Matrix4::identity());
+        let unproject = |z| {
+            cgmath::Point3::from_homogeneous(
+                inverse * cgmath::Vector4::new(x * 2.0 - 1.0, 1.0 - y * 2.0, z, 1.0),
+            )
+        };
+        let near = unproject(-1.0);
+        raycast::Ray::new(near, unproject(1.0) - near)
+    }
+}
+#[rustfmt::skip]
+pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::from_cols(
+    cgmath::Vector4::new(1.0, 0.0, 0.0, 0.0),
+    cgmath::Vector4::new(0.0, 1.0, 0.0, 0.0),
+    cgmath::Vector4::new(0.0, 0.0, 0.5, 0.0),
+    cgmath::Vector4::new(0.0, 0.0, 0.5, 1.0),
+);
+
+// We need this for Rust to store our data correctly for the shaders
+#[repr(C)]
+// This is so we can store this in a buffer
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct CameraUniform {
+    // We can't use cgmath with bytemuck directly, so we'll have
+    // to convert the Matrix4 into a 4x4 f32 array
+    pub(crate) view_proj: [[f32; 4]; 4],
+    // The view matrix on its own lets the shader find view-space depth
+    // for picking a shadow cascade
+    pub(crate) view: [[f32; 4]; 4],
+    // Used by fullscreen passes to rebuild world positions from depth
+    pub(crate) inv_view_proj: [[f32; 4]; 4],
+    pub(crate) view_position: [f32; 4],
+    // Screen-space effects work in view space and need the projection alone
+    pub(crate) proj: [[f32; 4]; 4],
+    pub(crate) inv_proj: [[f32; 4]; 4],
+    // Last frame's view_proj, so the scene pass can work out per-pixel velocity
+    pub(crate) prev_view_proj: [[f32; 4]; 4],
+}
+
+impl CameraUniform {
+    pub(crate) fn new() -> Self {
+        use cgmath::SquareMatrix;
+        Self {
+            view_proj: cgmath::Matrix4::identity().into(),
+            view: cgmath::Matrix4::identity().into(),
+            inv_view_proj: cgmath::Matrix4::identity().into(),
+            view_position: [0.0; 4],
+            proj: cgmath::Matrix4::identity().into(),
+            inv_proj: cgmath::Matrix4::identity().into(),
+            prev_view_proj: cgmath::Matrix4::identity().into(),
+        }
+    }
+
+    pub(crate) fn update_view_proj(&mut self, camera: &Camera) {
+        let view_proj = camera.build_view_projection_matrix();
+        self.prev_view_proj = self.view_proj;
+        self.view_proj = view_proj.into();
+        self.view = camera.build_view_matrix().into();
+        self.inv_view_proj = view_proj
+            .invert()
+            .unwrap_or_else(cgmath::Matrix4::identity)
+            .into();
+        self.view_position = camera.eye.to_homogeneous().into();
+        let proj = camera.build_projection_matrix();
+        self.proj = proj.into();
+        self.inv_proj = proj
+            .invert()
+            .unwrap_or_else(cgmath::Matrix4::identity)
+            .into();
+        // if NaN models wont appear
+        // tracing::info!("Projection Matrix {:?}", self.view_proj);
+    }
+}
+pub struct CameraController {
+    pub(crate) speed: f32,
+    pub(crate) is_forward_pressed: bool,
+    pub(crate) is_backward_pressed: bool,
+    pub(crate) is_left_pressed: bool,
+    pub(crate) is_right_pressed: bool,
+}
+
+impl CameraController {
+    pub(crate) fn new(speed: f32) -> Self {
+        Self {
+            speed,
+            is_forward_pressed: false,
+            is_backward_pressed: false,
+            is_left_pressed: false,
+            is_right_pressed: false,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn process_events(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state,
+                        physical_key: PhysicalKey::Code(keycode),
+                        ..
+                    },
+                ..
+            } => {
+                let is_pressed = *state == ElementState::Pressed;
+                match keycode {
+                    KeyCode::KeyW | KeyCode::ArrowUp => {
+                        self.is_forward_pressed = is_pressed;
+                        true
+                    }
+                    KeyCode::KeyA | KeyCode::ArrowLeft => {
+                        self.is_left_pressed = is_pressed;
+                        true
+                    }
+                    KeyCode::KeyS | KeyCode::ArrowDown => {
+                        self.is_backward_pressed = is_pressed;
+                        true
+                    }
+                    KeyCode::KeyD | KeyCode::ArrowRight => {
+                        self.is_right_pressed = is_pressed;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn update_camera(&self, camera: &mut Camera) {
+        use cgmath::InnerSpace;
+        let forward = camera.target - camera.eye;
+        let forward_norm = forward.normalize();
+        let forward_mag = forward.magnitude();
+
+        // Prevents glitching when the camera gets too close to the
+        // center of the scene.
+        if self.is_forward_pressed && forward_mag > self.speed {
+            camera.eye += forward_norm * self.speed;
+        }
+        if self.is_backward_pressed {
+            camera.eye -= forward_norm * self.speed;
+        }
+
+        let right = forward_norm.cross(camera.up);
+
+        // Redo radius calc in case the forward/backward is pressed.
+        let forward = camera.target - camera.eye;
+        let forward_mag = forward.magnitude();
+
+        if self.is_right_pressed {
+            // Rescale the distance between the target and the eye so
+            // that it doesn't change. The eye, therefore, still
+            // lies on the circle made by the target and eye.
+            camera.eye = camera.target - (forward + right * self.speed).normalize() * forward_mag;
+        }
+        if self.is_left_pressed {
+            camera.eye = camera.target - (forward - right * self.speed).normalize() * forward_mag;
+        }
+    }
+
+    pub(crate) fn release(&mut self) {
+        self.is_forward_pressed = false;
+        self.is_backward_pressed = false;
+        self.is_left_pressed = false;
+        self.is_right_pressed = false;
+    }
+
+    pub(crate) fn handle_action(&mut self, action: &str, pressed: bool) {
+        match action {
+            "move_forward" => self.is_forward_pressed = pressed,
+            "move_left" => self.is_left_pressed = pressed,
+            "move_backward" => self.is_backward_pressed = pressed,
+            "move_right" => self.is_right_pressed = pressed,
+            _ => {}
+        }
+    }
+}
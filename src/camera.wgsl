@@ -0,0 +1,13 @@
+// ===== SHARED CAMERA =====
+// Layout of `CameraUniform` in lib.rs. Shaders declare their own binding, since
+// the camera sits in a different group depending on the pipeline.
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+};
@@ -0,0 +1,63 @@
+use cgmath::prelude::*;
+
+use crate::Camera;
+
+// ===== CAMERA DAMPING =====
+// Eases the rendered camera after the one the controllers move, so key
+// presses and mouse flicks turn into smooth motion. Where it is and which
+// way it looks are damped separately, each by how long it takes to close
+// half the gap. Switched off, the camera snaps straight to its input.
+#[derive(Debug, Clone, Copy)]
+pub struct CameraDamping {
+    pub enabled: bool,
+    // Seconds, 0 for no damping
+    pub position_half_life: f32,
+    pub rotation_half_life: f32,
+}
+
+impl CameraDamping {
+    pub fn new(position_half_life: f32, rotation_half_life: f32) -> Self {
+        Self {
+            enabled: true,
+            position_half_life,
+            rotation_half_life,
+        }
+    }
+
+    // How far to close the gap this frame, the same over a second whatever
+    // the framerate
+    fn blend(half_life: f32, dt: f32) -> f32 {
+        if half_life <= 0.0 {
+            1.0
+        } else {
+            1.0 - 0.5f32.powf(dt / half_life)
+        }
+    }
+
+    // Moves `camera` towards `input` by `dt` seconds' worth
+    pub fn apply(&self, input: &Camera, camera: &mut Camera, dt: f32) {
+        camera.up = input.up;
+        if !self.enabled {
+            camera.eye = input.eye;
+            camera.target = input.target;
+            return;
+        }
+        let position = Self::blend(self.position_half_life, dt);
+        let rotation = Self::blend(self.rotation_half_life, dt);
+
+        let (from, to) = (camera.target - camera.eye, input.target - input.eye);
+        let distance = from.magnitude() + (to.magnitude() - from.magnitude()) * position;
+        // Normalized lerp, close enough to a slerp over a frame. Turning
+        // right round has no way to go, so that snaps.
+        let (from, to) = (from.normalize(), to.normalize());
+        let forward = from.lerp(to, rotation);
+        let forward = if forward.magnitude2() > 1e-6 {
+            forward.normalize()
+        } else {
+            to
+        };
+
+        camera.eye += (input.eye - camera.eye) * position;
+        camera.target = camera.eye + forward * distance;
+    }
+}
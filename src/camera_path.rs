@@ -0,0 +1,210 @@
+use cgmath::prelude::*;
+
+use crate::Camera;
+
+// Where the camera is and what it looks at, `time` seconds into the path
+#[derive(Debug, Copy, Clone, serde::Deserialize, serde::Serialize)]
+pub struct CameraKeyframe {
+    pub time: f32,
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+}
+
+impl CameraKeyframe {
+    pub fn from_camera(time: f32, camera: &Camera) -> Self {
+        Self {
+            time,
+            eye: camera.eye.into(),
+            target: camera.target.into(),
+        }
+    }
+}
+
+// Uniform Catmull-Rom from `p1` to `p2`, `u` from 0 to 1 between them
+fn catmull_rom(
+    p0: cgmath::Vector3<f32>,
+    p1: cgmath::Vector3<f32>,
+    p2: cgmath::Vector3<f32>,
+    p3: cgmath::Vector3<f32>,
+    u: f32,
+) -> cgmath::Vector3<f32> {
+    let (u2, u3) = (u * u, u * u * u);
+    (p1 * 2.0
+        + (p2 - p0) * u
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * u2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * u3)
+        * 0.5
+}
+
+// ===== CAMERA PATHS =====
+// A smooth curve through keyframes, passing through each one at its time.
+// The eye and what it looks at are splined separately, so the camera can
+// swing round something while moving past it.
+#[derive(Debug, Clone, Default)]
+pub struct CameraPath {
+    // In time order
+    keyframes: Vec<CameraKeyframe>,
+}
+
+impl CameraPath {
+    pub fn new(mut keyframes: Vec<CameraKeyframe>) -> Self {
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self { keyframes }
+    }
+
+    // Circling `center` at `radius` and `height` above it, once in `duration`
+    // seconds through `steps` keyframes
+    pub fn orbit(
+        center: cgmath::Point3<f32>,
+        radius: f32,
+        height: f32,
+        duration: f32,
+        steps: u32,
+    ) -> Self {
+        let steps = steps.max(3);
+        let keyframes = (0..=steps)
+            .map(|i| {
+                let angle = i as f32 / steps as f32 * std::f32::consts::TAU;
+                let eye = center + cgmath::Vector3::new(angle.cos(), 0.0, angle.sin()) * radius;
+                CameraKeyframe {
+                    time: i as f32 / steps as f32 * duration,
+                    eye: [eye.x, eye.y + height, eye.z],
+                    target: center.into(),
+                }
+            })
+            .collect();
+        Self { keyframes }
+    }
+
+    pub fn keyframes(&self) -> &[CameraKeyframe] {
+        &self.keyframes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keyframes.is_empty()
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    // Adds one `interval` seconds after the last, or at the start
+    pub fn push(&mut self, camera: &Camera, interval: f32) {
+        let time = match self.keyframes.last() {
+            Some(last) => last.time + interval,
+            None => 0.0,
+        };
+        self.keyframes
+            .push(CameraKeyframe::from_camera(time, camera));
+    }
+
+    pub fn clear(&mut self) {
+        self.keyframes.clear();
+    }
+
+    // Eye and target at `time`, held at the ends. None with no keyframes.
+    pub fn sample(&self, time: f32) -> Option<(cgmath::Point3<f32>, cgmath::Point3<f32>)> {
+        let last = self.keyframes.len().checked_sub(1)?;
+        let next = self
+            .keyframes
+            .partition_point(|keyframe| keyframe.time <= time);
+        if next == 0 || next > last {
+            let keyframe = self.keyframes[next.min(last)];
+            return Some((keyframe.eye.into(), keyframe.target.into()));
+        }
+        // The ends repeat to give the first and last segments a neighbour
+        let (i1, i2) = (next - 1, next);
+        let (i0, i3) = (i1.saturating_sub(1), (i2 + 1).min(last));
+        let span = self.keyframes[i2].time - self.keyframes[i1].time;
+        let u = if span > 0.0 {
+            (time - self.keyframes[i1].time) / span
+        } else {
+            1.0
+        };
+        let spline = |get: fn(&CameraKeyframe) -> [f32; 3]| {
+            let [p0, p1, p2, p3] = [i0, i1, i2, i3].map(|i| get(&self.keyframes[i]).into());
+            cgmath::Point3::from_vec(catmull_rom(p0, p1, p2, p3, u))
+        };
+        Some((spline(|k| k.eye), spline(|k| k.target)))
+    }
+
+    // As RON, ready to paste into a scene's `camera_path`
+    pub fn to_ron(&self) -> anyhow::Result<String> {
+        Ok(ron::ser::to_string_pretty(
+            &self.keyframes,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+
+    // Writes the keyframes to `file_name` in res/
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, file_name: &str) -> anyhow::Result<std::path::PathBuf> {
+        let path = crate::resources::resolve(file_name)?;
+        std::fs::write(&path, self.to_ron()?)?;
+        Ok(path)
+    }
+}
+
+// ===== PLAYBACK =====
+// Moves a camera along a path, at `speed` times real time
+#[derive(Debug, Clone)]
+pub struct CameraPathPlayer {
+    pub path: CameraPath,
+    pub playing: bool,
+    pub looping: bool,
+    pub speed: f32,
+    time: f32,
+}
+
+impl CameraPathPlayer {
+    pub fn new(path: CameraPath) -> Self {
+        Self {
+            path,
+            playing: false,
+            looping: true,
+            speed: 1.0,
+            time: 0.0,
+        }
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn play(&mut self) {
+        if self.time >= self.path.duration() {
+            self.time = 0.0;
+        }
+        self.playing = true;
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    // Jumps to `time`, kept within the path
+    pub fn seek(&mut self, time: f32) {
+        self.time = time.clamp(0.0, self.path.duration());
+    }
+
+    // Advances by `dt` seconds and puts `camera` where the path is. Stops at
+    // the end unless it loops.
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: f32) {
+        if self.playing {
+            let duration = self.path.duration();
+            self.time += dt * self.speed;
+            if self.time >= duration {
+                if self.looping && duration > 0.0 {
+                    self.time %= duration;
+                } else {
+                    self.time = duration;
+                    self.playing = false;
+                }
+            }
+        }
+        if let Some((eye, target)) = self.path.sample(self.time) {
+            camera.eye = eye;
+            camera.target = target;
+        }
+    }
+}
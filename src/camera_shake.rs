@@ -0,0 +1,88 @@
+use cgmath::prelude::*;
+
+use crate::Camera;
+
+// Smooth noise from -1 to 1 along `t`, a different curve for each `seed`
+fn noise(seed: u32, t: f32) -> f32 {
+    let hash = |n: i32| {
+        let mut x = (n as u32).wrapping_mul(0x9e37_79b1) ^ seed.wrapping_mul(0x85eb_ca77);
+        x ^= x >> 15;
+        x = x.wrapping_mul(0x2c1b_3c6d);
+        x ^= x >> 12;
+        x as f32 / u32::MAX as f32 * 2.0 - 1.0
+    };
+    let i = t.floor();
+    let f = t - i;
+    let u = f * f * (3.0 - 2.0 * f);
+    let i = i as i32;
+    hash(i) + (hash(i + 1) - hash(i)) * u
+}
+
+// ===== CAMERA SHAKE =====
+// Trauma from 0 to 1 builds up with each hit and wears off over time. The
+// camera turns and moves by noise scaled by its square, so small knocks
+// barely show and big ones shake hard.
+#[derive(Debug, Clone, Copy)]
+pub struct CameraShake {
+    // Trauma lost a second
+    pub decay: f32,
+    // At full trauma, in radians and world units
+    pub max_angle: f32,
+    pub max_offset: f32,
+    // How fast it shakes, in noise cycles a second
+    pub frequency: f32,
+    trauma: f32,
+    time: f32,
+}
+
+impl Default for CameraShake {
+    fn default() -> Self {
+        Self {
+            decay: 1.2,
+            max_angle: 0.05,
+            max_offset: 0.08,
+            frequency: 18.0,
+            trauma: 0.0,
+            time: 0.0,
+        }
+    }
+}
+
+impl CameraShake {
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).clamp(0.0, 1.0);
+    }
+
+    pub fn trauma(&self) -> f32 {
+        self.trauma
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.trauma = (self.trauma - self.decay * dt).max(0.0);
+        self.time += dt;
+    }
+
+    // Turns and moves `camera` by this frame's shake
+    pub fn apply(&self, camera: &mut Camera) {
+        let shake = self.trauma * self.trauma;
+        if shake <= 0.0 {
+            return;
+        }
+        let t = self.time * self.frequency;
+        let angle = |seed| cgmath::Rad(self.max_angle * shake * noise(seed, t));
+        let offset = |seed| self.max_offset * shake * noise(seed, t);
+
+        let forward = camera.target - camera.eye;
+        let distance = forward.magnitude();
+        let forward = forward / distance;
+        let right = forward.cross(camera.up).normalize();
+        let up = right.cross(forward);
+        let rotation = cgmath::Quaternion::from_axis_angle(up, angle(0))
+            * cgmath::Quaternion::from_axis_angle(right, angle(1))
+            * cgmath::Quaternion::from_axis_angle(forward, angle(2));
+
+        camera.eye += right * offset(3) + up * offset(4) + forward * offset(5);
+        camera.target = camera.eye + rotation.rotate_vector(forward) * distance;
+        camera.up = rotation.rotate_vector(up);
+    }
+}
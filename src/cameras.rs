@@ -0,0 +1,282 @@
+use crate::camera_damping::CameraDamping;
+use crate::camera_path::CameraPathPlayer;
+use crate::camera_shake::CameraShake;
+use crate::fly_camera::FlyCamera;
+use crate::follow_camera::FollowCamera;
+use crate::model::Aabb;
+use crate::touch::Gesture;
+use crate::upload::Uploads;
+use crate::viewport::ViewCamera;
+use crate::{Camera, CameraController};
+
+// What moves a camera
+pub enum CameraControl {
+    Orbit(CameraController),
+    Fly(FlyCamera),
+    // Along a spline, for flythroughs
+    Path(CameraPathPlayer),
+    // Chasing something that moves
+    Follow(FollowCamera),
+    // Stays where it was put, for cinematic shots
+    Fixed,
+}
+
+pub struct NamedCamera {
+    pub name: String,
+    pub control: CameraControl,
+    pub damping: CameraDamping,
+    // Where the control has put it, `steady` eases after it and `view.camera`
+    // is that with any shake on top
+    input: Camera,
+    steady: Camera,
+    pub view: ViewCamera,
+}
+
+impl NamedCamera {
+    // Lets go of anything held, it stops getting input when switched away from
+    fn release(&mut self) {
+        match &mut self.control {
+            CameraControl::Orbit(controller) => controller.release(),
+            CameraControl::Fly(fly_camera) => fly_camera.release(),
+            CameraControl::Path(_) | CameraControl::Follow(_) | CameraControl::Fixed => {}
+        }
+    }
+}
+
+// ===== CAMERAS =====
+// Every camera the main view can be seen through, by name. Only the active
+// one takes input, but each keeps its own uniform buffer and bind group up
+// to date, so switching doesn't show up as a jump in per-pixel velocity.
+pub struct Cameras {
+    cameras: Vec<NamedCamera>,
+    active: usize,
+    // Shakes whichever camera is active
+    pub shake: CameraShake,
+}
+
+impl Cameras {
+    // Starts out with one camera, so there's always an active one
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        name: &str,
+        camera: Camera,
+        control: CameraControl,
+        damping: CameraDamping,
+    ) -> Self {
+        let mut cameras = Self {
+            cameras: Vec::new(),
+            active: 0,
+            shake: CameraShake::default(),
+        };
+        cameras.add(
+            device,
+            camera_bind_group_layout,
+            name,
+            camera,
+            control,
+            damping,
+        );
+        cameras
+    }
+
+    pub fn add(
+        &mut self,
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        name: &str,
+        camera: Camera,
+        mut control: CameraControl,
+        damping: CameraDamping,
+    ) {
+        if let CameraControl::Fly(fly_camera) = &mut control {
+            fly_camera.look_from(&camera);
+        }
+        self.cameras.push(NamedCamera {
+            name: name.to_string(),
+            control,
+            damping,
+            input: camera,
+            steady: camera,
+            view: ViewCamera::new(device, camera_bind_group_layout, camera),
+        });
+    }
+
+    // Makes every camera's uniform buffer and bind group again on a new
+    // device, keeping where they are and what moves them
+    pub fn rebind(
+        &mut self,
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) {
+        for camera in &mut self.cameras {
+            camera.view = ViewCamera::new(device, camera_bind_group_layout, camera.view.camera);
+        }
+    }
+
+    pub fn active(&self) -> &NamedCamera {
+        &self.cameras[self.active]
+    }
+
+    pub fn active_mut(&mut self) -> &mut NamedCamera {
+        &mut self.cameras[self.active]
+    }
+
+    // What the active camera sees this frame
+    pub fn camera(&self) -> &Camera {
+        &self.active().view.camera
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.active().view.bind_group
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.cameras.iter().map(|camera| camera.name.as_str())
+    }
+
+    // The first camera that follows a path, with its name
+    pub fn path_mut(&mut self) -> Option<(&str, &mut CameraPathPlayer)> {
+        self.cameras
+            .iter_mut()
+            .find_map(|camera| match &mut camera.control {
+                CameraControl::Path(player) => Some((camera.name.as_str(), player)),
+                _ => None,
+            })
+    }
+
+    // Where every follow camera's target is this frame
+    pub fn set_follow_target(&mut self, transform: cgmath::Matrix4<f32>) {
+        for camera in &mut self.cameras {
+            if let CameraControl::Follow(follow_camera) = &mut camera.control {
+                follow_camera.set_target(transform);
+            }
+        }
+    }
+
+    // Frames the box with the active camera straight away, see
+    // `Camera::frame_aabb`. The far plane moves back to fit all of it in
+    // when it's too close.
+    pub fn frame_aabb(&mut self, aabb: &Aabb) {
+        use cgmath::{InnerSpace, MetricSpace};
+
+        let active = self.active_mut();
+        active.input.aspect = active.view.camera.aspect;
+        active.input.frame_aabb(aabb);
+        active.steady = active.input;
+        let view = &mut active.view.camera;
+        let (znear, zfar) = view.clip_planes();
+        let far_side = active.input.eye.distance(aabb.center()) + aabb.size().magnitude() * 0.5;
+        if far_side > zfar {
+            // Can't fail, it's further than the near plane already is
+            let _ = view.set_clip_planes(znear, far_side * 1.5);
+        }
+        if let CameraControl::Fly(fly_camera) = &mut active.control {
+            fly_camera.look_from(&active.input);
+        }
+    }
+
+    // Switches to the camera called `name`, false when there's none
+    pub fn select(&mut self, name: &str) -> bool {
+        match self.cameras.iter().position(|camera| camera.name == name) {
+            Some(index) => {
+                self.active_mut().release();
+                self.active = index;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Switches to the next camera in the order they were added, returning
+    // its name
+    pub fn cycle(&mut self) -> &str {
+        self.active_mut().release();
+        self.active = (self.active + 1) % self.cameras.len();
+        &self.active().name
+    }
+
+    // Moves the active camera on by `dt` seconds, then uploads every
+    // camera's uniform for an `aspect` wide view
+    pub fn update(&mut self, uploads: &mut Uploads, aspect: f32, dt: f32) {
+        self.shake.update(dt);
+        let shake = self.shake;
+        let active = self.active_mut();
+        match &mut active.control {
+            CameraControl::Orbit(controller) => controller.update_camera(&mut active.input),
+            CameraControl::Fly(fly_camera) => fly_camera.update_camera(&mut active.input, dt),
+            CameraControl::Path(player) => player.update_camera(&mut active.input, dt),
+            CameraControl::Follow(follow_camera) => {
+                follow_camera.update_camera(&mut active.input, dt)
+            }
+            CameraControl::Fixed => {}
+        }
+        active.damping.apply(&active.input, &mut active.steady, dt);
+        let view = &mut active.view.camera;
+        view.eye = active.steady.eye;
+        view.target = active.steady.target;
+        view.up = active.steady.up;
+        shake.apply(view);
+        for camera in &mut self.cameras {
+            camera.view.update(uploads, aspect);
+        }
+    }
+
+    // Damping on or off for all of them, returning whether it's now on
+    pub fn toggle_damping(&mut self) -> bool {
+        let enabled = !self.active().damping.enabled;
+        for camera in &mut self.cameras {
+            camera.damping.enabled = enabled;
+        }
+        enabled
+    }
+
+    // Moving and looking, see `InputMap`
+    pub fn handle_action(&mut self, action: &str, pressed: bool) {
+        match &mut self.active_mut().control {
+            CameraControl::Orbit(controller) => controller.handle_action(action, pressed),
+            CameraControl::Fly(fly_camera) => {
+                fly_camera.handle_action(action, pressed);
+            }
+            CameraControl::Path(_) | CameraControl::Follow(_) | CameraControl::Fixed => {}
+        }
+    }
+
+    // Whether the active camera flies, and so can take mouselook
+    pub fn is_flying(&self) -> bool {
+        matches!(self.active().control, CameraControl::Fly(_))
+    }
+
+    // Turns mouselook on or off for every fly camera
+    pub fn set_mouselook(&mut self, enabled: bool) {
+        for camera in &mut self.cameras {
+            if let CameraControl::Fly(fly_camera) = &mut camera.control {
+                fly_camera.mouselook = enabled;
+            }
+        }
+    }
+
+    pub fn handle_mouse_motion(&mut self, dx: f64, dy: f64) {
+        if let CameraControl::Fly(fly_camera) = &mut self.active_mut().control {
+            fly_camera.handle_mouse_motion(dx, dy);
+        }
+    }
+
+    pub fn handle_scroll(&mut self, lines: f32) {
+        if let CameraControl::Fly(fly_camera) = &mut self.active_mut().control {
+            fly_camera.handle_scroll(lines);
+        }
+    }
+
+    // Touch moves orbit cameras, the others have no target to go round
+    pub fn handle_gesture(&mut self, gesture: Gesture) {
+        let active = self.active_mut();
+        if let CameraControl::Orbit(_) = active.control {
+            gesture.apply(&mut active.input);
+        }
+    }
+
+    pub fn release(&mut self) {
+        self.active_mut().release();
+    }
+}
@@ -0,0 +1,102 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+// ===== FRAME CAPTURE =====
+// Copies a finished frame back from the GPU into an image. Rows in the copy
+// have to start on 256-byte boundaries, so they're padded on the GPU side and
+// the padding's dropped again here. Blocks until the copy is done, which
+// stalls the frame, fine for screenshots and recordings.
+pub fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> anyhow::Result<image::RgbaImage> {
+    use wgpu::TextureFormat::*;
+    let swizzle = match texture.format() {
+        Rgba8Unorm | Rgba8UnormSrgb => false,
+        Bgra8Unorm | Bgra8UnormSrgb => true,
+        format => anyhow::bail!("Can't capture {:?} frames", format),
+    };
+    anyhow::ensure!(
+        texture.usage().contains(wgpu::TextureUsages::COPY_SRC),
+        "The frame can't be copied from"
+    );
+    let (width, height) = (texture.width(), texture.height());
+    let row_bytes = width * 4;
+    let padded_row_bytes = row_bytes.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Capture Buffer"),
+        size: (padded_row_bytes * height) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Capture Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::TexelCopyBufferInfo {
+            buffer: &buffer,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_bytes),
+                rows_per_image: Some(height),
+            },
+        },
+        texture.size(),
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    buffer.map_async(wgpu::MapMode::Read, .., move |result| {
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::PollType::wait_indefinitely())?;
+    receiver
+        .recv()
+        .context("The capture was dropped before it was mapped")??;
+
+    let mut pixels = Vec::with_capacity((row_bytes * height) as usize);
+    {
+        let mapped = buffer.slice(..).get_mapped_range();
+        for row in mapped.chunks_exact(padded_row_bytes as usize) {
+            pixels.extend_from_slice(&row[..row_bytes as usize]);
+        }
+    }
+    buffer.unmap();
+    if swizzle {
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+    image::RgbaImage::from_raw(width, height, pixels).context("Captured the wrong number of pixels")
+}
+
+// Reads the frame back and writes it out as a PNG
+pub fn save_png(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let image = read_texture(device, queue, texture)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Couldn't create {}", dir.display()))?;
+    }
+    image
+        .save_with_format(path, image::ImageFormat::Png)
+        .with_context(|| format!("Couldn't write {}", path.display()))
+}
+
+// `name` in `dir` with the time after it, so captures don't overwrite each
+// other. An empty `extension` names a directory.
+pub fn timestamped_path(dir: &Path, name: &str, extension: &str) -> PathBuf {
+    let millis = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis());
+    dir.join(format!("{}_{}", name, millis))
+        .with_extension(extension)
+}
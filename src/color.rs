@@ -0,0 +1,123 @@
+// ===== COLOR MANAGEMENT =====
+// Every pass shades and blends linear values in the `IntermediateFormat`. Color images are
+// stored sRGB-encoded, so they're uploaded with an sRGB format and the sampler
+// decodes them; data maps (normals, roughness, ...) must skip that decode.
+// Encoding back to sRGB happens exactly once, when the final pass writes the
+// surface: by the hardware for sRGB surfaces, otherwise in the shader
+// (see `needs_shader_encode` and color.wgsl).
+
+// Format of the linear intermediate targets the scene and post-processing
+// render into, trading bandwidth for precision
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum IntermediateFormat {
+    Rgba8,   // Half the bandwidth, but clamps at 1.0 and bands in the darks
+    Rg11b10, // Half the bandwidth and still HDR, with less precision and no alpha
+    #[default]
+    Rgba16Float,
+}
+
+impl IntermediateFormat {
+    pub fn texture_format(self) -> wgpu::TextureFormat {
+        match self {
+            IntermediateFormat::Rgba8 => wgpu::TextureFormat::Rgba8Unorm,
+            IntermediateFormat::Rg11b10 => wgpu::TextureFormat::Rg11b10Ufloat,
+            IntermediateFormat::Rgba16Float => wgpu::TextureFormat::Rgba16Float,
+        }
+    }
+
+    // Device features needed to render into the format
+    pub fn required_features(self) -> wgpu::Features {
+        match self {
+            IntermediateFormat::Rg11b10 => wgpu::Features::RG11B10UFLOAT_RENDERABLE,
+            _ => wgpu::Features::empty(),
+        }
+    }
+
+    // Whether the adapter can render, blend and filter it
+    pub fn is_supported(self, adapter: &wgpu::Adapter) -> bool {
+        let features = adapter.get_texture_format_features(self.texture_format());
+        adapter.features().contains(self.required_features())
+            && features
+                .allowed_usages
+                .contains(wgpu::TextureUsages::RENDER_ATTACHMENT)
+            && features.flags.contains(
+                wgpu::TextureFormatFeatureFlags::BLENDABLE
+                    | wgpu::TextureFormatFeatureFlags::FILTERABLE,
+            )
+    }
+}
+
+// How the texels of an image are encoded
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorSpace {
+    Srgb,   // Albedo, emissive, skyboxes and anything else a person painted
+    Linear, // Data: normal, metallic-roughness and occlusion maps
+}
+
+impl ColorSpace {
+    // 8 bit format that samples as linear values for this kind of image
+    pub fn texture_format(self) -> wgpu::TextureFormat {
+        match self {
+            ColorSpace::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+            ColorSpace::Linear => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
+}
+
+// Whether a pass writing linear color into `format` has to apply the sRGB curve
+// itself. sRGB formats encode on write and float formats stay linear.
+pub fn needs_shader_encode(format: wgpu::TextureFormat) -> bool {
+    matches!(
+        format,
+        wgpu::TextureFormat::Rgba8Unorm
+            | wgpu::TextureFormat::Bgra8Unorm
+            | wgpu::TextureFormat::Rgb10a2Unorm
+    )
+}
+
+// A linear color for a pass that writes it into `format` as it is, from a
+// vertex or uniform rather than through color.wgsl: encoded here where the
+// format has no hardware sRGB, alpha left alone
+pub fn output_color(format: wgpu::TextureFormat, linear: [f32; 4]) -> [f32; 4] {
+    let [r, g, b, a] = linear;
+    if needs_shader_encode(format) {
+        let [r, g, b] = [r, g, b].map(linear_to_srgb);
+        [r, g, b, a]
+    } else {
+        linear
+    }
+}
+
+// Exact piecewise sRGB transfer functions, same as color.wgsl
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Linear color to the bytes of an sRGB texture
+pub fn encode_srgb8(linear: [f32; 3]) -> [u8; 3] {
+    linear.map(|c| (linear_to_srgb(c.clamp(0.0, 1.0)) * 255.0).round() as u8)
+}
+
+// Colors picked in an sRGB color picker need decoding before they're used for
+// lighting or as a clear color of a linear target
+pub fn srgb_color(r: f64, g: f64, b: f64) -> wgpu::Color {
+    let decode = |c: f64| srgb_to_linear(c as f32) as f64;
+    wgpu::Color {
+        r: decode(r),
+        g: decode(g),
+        b: decode(b),
+        a: 1.0,
+    }
+}
@@ -0,0 +1,702 @@
+use anyhow::{bail, Context};
+
+use crate::color::ColorSpace;
+
+// ===== BLOCK COMPRESSED TEXTURES =====
+// BCn images from KTX2 and DDS files. They go to the GPU as they are where
+// the adapter has `TEXTURE_COMPRESSION_BC`, which is most desktops; everywhere
+// else (WebGL, phones) they're decoded to RGBA8 here first.
+
+const KTX2_MAGIC: [u8; 12] = [
+    0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n',
+];
+const DDS_MAGIC: [u8; 4] = *b"DDS ";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlockFormat {
+    Bc1,    // RGB with optional 1 bit alpha
+    Bc1Rgb, // RGB only, opaque
+    Bc3,    // RGBA
+    Bc5,    // Two channels, for normal maps
+    Bc7,    // RGBA, best quality
+}
+
+impl BlockFormat {
+    pub fn block_bytes(self) -> usize {
+        match self {
+            BlockFormat::Bc1 | BlockFormat::Bc1Rgb => 8,
+            BlockFormat::Bc3 | BlockFormat::Bc5 | BlockFormat::Bc7 => 16,
+        }
+    }
+
+    // BC5 holds data, so it has no sRGB variant. There's no RGB-only BC1
+    // format, those go up as RGBA and their black sampled as transparent.
+    pub fn texture_format(self, color_space: ColorSpace) -> wgpu::TextureFormat {
+        let srgb = color_space == ColorSpace::Srgb;
+        match self {
+            BlockFormat::Bc1 | BlockFormat::Bc1Rgb if srgb => wgpu::TextureFormat::Bc1RgbaUnormSrgb,
+            BlockFormat::Bc1 | BlockFormat::Bc1Rgb => wgpu::TextureFormat::Bc1RgbaUnorm,
+            BlockFormat::Bc3 if srgb => wgpu::TextureFormat::Bc3RgbaUnormSrgb,
+            BlockFormat::Bc3 => wgpu::TextureFormat::Bc3RgbaUnorm,
+            BlockFormat::Bc5 => wgpu::TextureFormat::Bc5RgUnorm,
+            BlockFormat::Bc7 if srgb => wgpu::TextureFormat::Bc7RgbaUnormSrgb,
+            BlockFormat::Bc7 => wgpu::TextureFormat::Bc7RgbaUnorm,
+        }
+    }
+
+    fn decode_block(self, block: &[u8]) -> [[u8; 4]; 16] {
+        match self {
+            BlockFormat::Bc1 => decode_bc1(block, true, true),
+            BlockFormat::Bc1Rgb => decode_bc1(block, true, false),
+            BlockFormat::Bc3 => {
+                let mut texels = decode_bc1(&block[8..], false, false);
+                for (texel, alpha) in texels.iter_mut().zip(decode_bc4(&block[..8])) {
+                    texel[3] = alpha;
+                }
+                texels
+            }
+            BlockFormat::Bc5 => {
+                let (red, green) = (decode_bc4(&block[..8]), decode_bc4(&block[8..]));
+                std::array::from_fn(|i| [red[i], green[i], 0, 255])
+            }
+            BlockFormat::Bc7 => decode_bc7(block),
+        }
+    }
+}
+
+// Whether the bytes are a KTX2 or DDS file rather than a PNG or JPEG
+pub fn is_container(data: &[u8]) -> bool {
+    data.starts_with(&KTX2_MAGIC) || data.starts_with(&DDS_MAGIC)
+}
+
+// A 2D image's blocks, level by level from the full size down
+pub struct CompressedImage {
+    pub format: BlockFormat,
+    pub width: u32,
+    pub height: u32,
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl CompressedImage {
+    pub fn from_bytes(data: &[u8]) -> anyhow::Result<Self> {
+        let image = if data.starts_with(&KTX2_MAGIC) {
+            Self::from_ktx2(data)?
+        } else if data.starts_with(&DDS_MAGIC) {
+            Self::from_dds(data)?
+        } else {
+            bail!("not a KTX2 or DDS file");
+        };
+        if image.width == 0 || image.height == 0 || image.levels.is_empty() {
+            bail!("empty image");
+        }
+        for (level, bytes) in image.levels.iter().enumerate() {
+            if bytes.len() < image.level_size(level as u32) {
+                bail!("mip level {} is cut short", level);
+            }
+        }
+        Ok(image)
+    }
+
+    fn from_ktx2(data: &[u8]) -> anyhow::Result<Self> {
+        let u32_at = |offset| read_u32(data, offset);
+        let format = match u32_at(12)? {
+            131 | 132 => BlockFormat::Bc1Rgb, // VK_FORMAT_BC1_RGB_UNORM/SRGB_BLOCK
+            133 | 134 => BlockFormat::Bc1,
+            137 | 138 => BlockFormat::Bc3,
+            141 => BlockFormat::Bc5,
+            145 | 146 => BlockFormat::Bc7,
+            vk_format => bail!("unsupported KTX2 format {}", vk_format),
+        };
+        let (width, height) = (u32_at(20)?, u32_at(24)?);
+        if u32_at(28)? > 1 || u32_at(32)? > 1 || u32_at(36)? > 1 {
+            bail!("only plain 2D KTX2 images are supported, not volumes, arrays or cubemaps");
+        }
+        if u32_at(44)? != 0 {
+            bail!("supercompressed KTX2 files aren't supported");
+        }
+        // A level count of 0 asks the loader to make the mips, there's just the one
+        let level_count = u32_at(40)?.max(1) as usize;
+        if level_count > max_levels(width, height) as usize {
+            bail!(
+                "KTX2 file says it has {} mip levels for a {}x{} image",
+                level_count,
+                width,
+                height
+            );
+        }
+        let mut levels = Vec::with_capacity(level_count);
+        for level in 0..level_count {
+            let entry = 80 + level * 24;
+            let (offset, length) = (read_u64(data, entry)?, read_u64(data, entry + 8)?);
+            let bytes = usize::try_from(offset)
+                .ok()
+                .zip(usize::try_from(length).ok())
+                .and_then(|(offset, length)| data.get(offset..offset.checked_add(length)?))
+                .context("KTX2 mip level past the end of the file")?;
+            levels.push(bytes.to_vec());
+        }
+        Ok(Self {
+            format,
+            width,
+            height,
+            levels,
+        })
+    }
+
+    fn from_dds(data: &[u8]) -> anyhow::Result<Self> {
+        let u32_at = |offset| read_u32(data, offset);
+        let (height, width) = (u32_at(12)?, u32_at(16)?);
+        // Any past 1x1 are ignored
+        let level_count = u32_at(28)?.clamp(1, max_levels(width, height));
+        let four_cc: [u8; 4] = read_u32(data, 84)?.to_le_bytes();
+        let (format, mut offset): (_, usize) = match &four_cc {
+            b"DXT1" => (BlockFormat::Bc1, 128),
+            b"DXT5" => (BlockFormat::Bc3, 128),
+            b"ATI2" | b"BC5U" => (BlockFormat::Bc5, 128),
+            b"DX10" => {
+                let format = match u32_at(128)? {
+                    71 | 72 => BlockFormat::Bc1, // DXGI_FORMAT_BC1_UNORM(_SRGB)
+                    77 | 78 => BlockFormat::Bc3,
+                    83 => BlockFormat::Bc5,
+                    98 | 99 => BlockFormat::Bc7,
+                    dxgi_format => bail!("unsupported DDS format {}", dxgi_format),
+                };
+                if u32_at(132)? != 3 || u32_at(140)? > 1 {
+                    bail!("only plain 2D DDS images are supported");
+                }
+                (format, 148)
+            }
+            four_cc => bail!(
+                "unsupported DDS format {}",
+                String::from_utf8_lossy(four_cc)
+            ),
+        };
+        let mut image = Self {
+            format,
+            width,
+            height,
+            levels: Vec::new(),
+        };
+        for level in 0..level_count {
+            let size = image.level_size(level);
+            let Some(bytes) = offset
+                .checked_add(size)
+                .and_then(|end| data.get(offset..end))
+            else {
+                break; // Fewer mips than the header says, keep what's there
+            };
+            image.levels.push(bytes.to_vec());
+            offset += size;
+        }
+        Ok(image)
+    }
+
+    pub fn level_extent(&self, level: u32) -> (u32, u32) {
+        ((self.width >> level).max(1), (self.height >> level).max(1))
+    }
+
+    // Levels are stored whole blocks wide and tall
+    pub fn blocks(&self, level: u32) -> (u32, u32) {
+        let (width, height) = self.level_extent(level);
+        (width.div_ceil(4), height.div_ceil(4))
+    }
+
+    // Saturates rather than overflowing for sizes from a broken header, no
+    // file is that big
+    fn level_size(&self, level: u32) -> usize {
+        let (blocks_wide, blocks_high) = self.blocks(level);
+        (blocks_wide as usize)
+            .saturating_mul(blocks_high as usize)
+            .saturating_mul(self.format.block_bytes())
+    }
+
+    // The full size level as RGBA8, for adapters that can't sample BCn
+    pub fn decode(&self) -> image::RgbaImage {
+        let (blocks_wide, blocks_high) = self.blocks(0);
+        let mut image = image::RgbaImage::new(self.width, self.height);
+        let block_bytes = self.format.block_bytes();
+        for (i, block) in self.levels[0]
+            .chunks_exact(block_bytes)
+            .take((blocks_wide as usize).saturating_mul(blocks_high as usize))
+            .enumerate()
+        {
+            let (bx, by) = (i as u32 % blocks_wide * 4, i as u32 / blocks_wide * 4);
+            for (texel, rgba) in self.format.decode_block(block).into_iter().enumerate() {
+                let (x, y) = (bx + texel as u32 % 4, by + texel as u32 / 4);
+                if x < self.width && y < self.height {
+                    image.put_pixel(x, y, image::Rgba(rgba));
+                }
+            }
+        }
+        image
+    }
+}
+
+// Levels halving down to 1x1
+fn max_levels(width: u32, height: u32) -> u32 {
+    32 - (width | height | 1).leading_zeros()
+}
+
+fn read_u32(data: &[u8], offset: usize) -> anyhow::Result<u32> {
+    let bytes = offset
+        .checked_add(4)
+        .and_then(|end| data.get(offset..end))
+        .context("texture header cut short")?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> anyhow::Result<u64> {
+    let bytes = offset
+        .checked_add(8)
+        .and_then(|end| data.get(offset..end))
+        .context("texture header cut short")?;
+    Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+// ===== BLOCK DECODERS =====
+// Each turns one 4x4 block into texels in row order
+
+fn rgb565(color: u16) -> [u32; 3] {
+    let (r, g, b) = (
+        (color >> 11) as u32,
+        (color >> 5 & 63) as u32,
+        (color & 31) as u32,
+    );
+    [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2]
+}
+
+// BC3 color blocks always use four colors, BC1 blocks with the endpoints
+// the other way around have three and black, transparent where the format
+// has alpha
+fn decode_bc1(block: &[u8], three_colors: bool, alpha: bool) -> [[u8; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (a, b) = (rgb565(c0), rgb565(c1));
+    let mix = |wa: u32, wb: u32| {
+        let total = wa + wb;
+        let [r, g, b] = std::array::from_fn(|i| ((a[i] * wa + b[i] * wb) / total) as u8);
+        [r, g, b, 255]
+    };
+    let palette = if c0 > c1 || !three_colors {
+        [mix(1, 0), mix(0, 1), mix(2, 1), mix(1, 2)]
+    } else {
+        [
+            mix(1, 0),
+            mix(0, 1),
+            mix(1, 1),
+            [0, 0, 0, if alpha { 0 } else { 255 }],
+        ]
+    };
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    std::array::from_fn(|i| palette[(indices >> (2 * i) & 3) as usize])
+}
+
+// One channel, as BC3 alpha and both BC5 channels are stored
+fn decode_bc4(block: &[u8]) -> [u8; 16] {
+    let (a, b) = (block[0] as u32, block[1] as u32);
+    let mut palette = [0u8; 8];
+    palette[0] = a as u8;
+    palette[1] = b as u8;
+    if a > b {
+        for i in 1..7 {
+            palette[i + 1] = ((a * (7 - i as u32) + b * i as u32) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = ((a * (5 - i as u32) + b * i as u32) / 5) as u8;
+        }
+        palette[6] = 0;
+        palette[7] = 255;
+    }
+    let mut bits = [0u8; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+    std::array::from_fn(|i| palette[(indices >> (3 * i) & 7) as usize])
+}
+
+// BC7 block modes: subsets, partition bits, rotation bits, index selection
+// bits, color bits, alpha bits, per-endpoint p-bits, shared p-bits, index
+// bits and secondary index bits
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    endpoint_pbits: bool,
+    shared_pbits: bool,
+    index_bits: u32,
+    index2_bits: u32,
+}
+
+const fn bc7_mode(m: [u32; 10]) -> Bc7Mode {
+    Bc7Mode {
+        subsets: m[0] as usize,
+        partition_bits: m[1],
+        rotation_bits: m[2],
+        selection_bits: m[3],
+        color_bits: m[4],
+        alpha_bits: m[5],
+        endpoint_pbits: m[6] == 1,
+        shared_pbits: m[7] == 1,
+        index_bits: m[8],
+        index2_bits: m[9],
+    }
+}
+
+const BC7_MODES: [Bc7Mode; 8] = [
+    bc7_mode([3, 4, 0, 0, 4, 0, 1, 0, 3, 0]),
+    bc7_mode([2, 6, 0, 0, 6, 0, 0, 1, 3, 0]),
+    bc7_mode([3, 6, 0, 0, 5, 0, 0, 0, 2, 0]),
+    bc7_mode([2, 6, 0, 0, 7, 0, 1, 0, 2, 0]),
+    bc7_mode([1, 0, 2, 1, 5, 6, 0, 0, 2, 3]),
+    bc7_mode([1, 0, 2, 0, 7, 8, 0, 0, 2, 2]),
+    bc7_mode([1, 0, 0, 0, 7, 7, 1, 0, 4, 0]),
+    bc7_mode([2, 6, 0, 0, 5, 5, 1, 0, 2, 0]),
+];
+
+// Which subset each texel is in, a bit per texel for two subsets...
+const BC7_PARTITIONS_2: [u16; 64] = [
+    0xCCCC, 0x8888, 0xEEEE, 0xECC8, 0xC880, 0xFEEC, 0xFEC8, 0xEC80, 0xC800, 0xFFEC, 0xFE80, 0xE800,
+    0xFFE8, 0xFF00, 0xFFF0, 0xF000, 0xF710, 0x008E, 0x7100, 0x08CE, 0x008C, 0x7310, 0x3100, 0x8CCE,
+    0x088C, 0x3110, 0x6666, 0x366C, 0x17E8, 0x0FF0, 0x718E, 0x399C, 0xAAAA, 0xF0F0, 0x5A5A, 0x33CC,
+    0x3C3C, 0x55AA, 0x9696, 0xA55A, 0x73CE, 0x13C8, 0x324C, 0x3BDC, 0x6996, 0xC33C, 0x9966, 0x0660,
+    0x0272, 0x04E4, 0x4E40, 0x2720, 0xC936, 0x936C, 0x39C6, 0x639C, 0x9336, 0x9CC6, 0x817E, 0xE718,
+    0xCCF0, 0x0FCC, 0x7744, 0xEE22,
+];
+
+// ...and two bits per texel for three
+const BC7_PARTITIONS_3: [u32; 64] = [
+    0xAA685050, 0x6A5A5040, 0x5A5A4200, 0x5450A0A8, 0xA5A50000, 0xA0A05050, 0x5555A0A0, 0x5A5A5050,
+    0xAA550000, 0xAA555500, 0xAAAA5500, 0x90909090, 0x94949494, 0xA4A4A4A4, 0xA9A59450, 0x2A0A4250,
+    0xA5945040, 0x0A425054, 0xA5A5A500, 0x55A0A0A0, 0xA8A85454, 0x6A6A4040, 0xA4A45000, 0x1A1A0500,
+    0x0050A4A4, 0xAAA59090, 0x14696914, 0x69691400, 0xA08585A0, 0xAA821414, 0x50A4A450, 0x6A5A0200,
+    0xA9A58000, 0x5090A0A8, 0xA8A09050, 0x24242424, 0x00AA5500, 0x24924924, 0x24499224, 0x50A50A50,
+    0x500AA550, 0xAAAA4444, 0x66660000, 0xA5A0A5A0, 0x50A050A0, 0x69286928, 0x44AAAA44, 0x66666600,
+    0xAA444444, 0x54A854A8, 0x95809580, 0x96969600, 0xA85454A8, 0x80959580, 0xAA141414, 0x96960000,
+    0xAAAA1414, 0xA05050A0, 0xA0A5A5A0, 0x96000000, 0x40804080, 0xA9A8A9A8, 0xAAAAAA44, 0x2A4A5254,
+];
+
+// The texel whose index drops its top bit, for the second subset of two...
+const BC7_ANCHORS_2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 2, 8, 2, 2, 8, 8, 15, 2, 8,
+    2, 2, 8, 8, 2, 2, 15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, 6, 2, 6, 8, 15, 15, 2,
+    2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+// ...and the second and third of three
+const BC7_ANCHORS_3: [[u8; 64]; 2] = [
+    [
+        3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3, 3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6,
+        8, 5, 15, 15, 8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15, 3, 15, 5, 5, 5, 8,
+        5, 10, 5, 10, 8, 13, 15, 12, 3, 3,
+    ],
+    [
+        15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8, 15, 8, 15, 3, 15, 8, 15, 8, 3,
+        15, 6, 10, 15, 15, 10, 8, 15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8, 15, 3, 15,
+        15, 15, 15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+    ],
+];
+
+const BC7_WEIGHTS: [&[u32]; 3] = [
+    &[0, 21, 43, 64],
+    &[0, 9, 18, 27, 37, 46, 55, 64],
+    &[0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64],
+];
+
+struct Bits {
+    bits: u128,
+    position: u32,
+}
+
+impl Bits {
+    fn read(&mut self, count: u32) -> u32 {
+        let value = (self.bits >> self.position) as u32 & ((1u64 << count) - 1) as u32;
+        self.position += count;
+        value
+    }
+}
+
+fn decode_bc7(block: &[u8]) -> [[u8; 4]; 16] {
+    let mode_number = block[0].trailing_zeros() as usize;
+    let Some(mode) = BC7_MODES.get(mode_number) else {
+        return [[0; 4]; 16]; // Reserved mode, decodes to transparent black
+    };
+    let mut bits = Bits {
+        bits: u128::from_le_bytes(block.try_into().unwrap()),
+        position: mode_number as u32 + 1,
+    };
+    let partition = bits.read(mode.partition_bits) as usize;
+    let rotation = bits.read(mode.rotation_bits);
+    let selection = bits.read(mode.selection_bits);
+
+    // Endpoints as [subset][end][channel], colors first then alpha
+    let mut endpoints = [[[0u32; 4]; 2]; 3];
+    for channel in 0..3 {
+        for subset in &mut endpoints[..mode.subsets] {
+            for end in subset.iter_mut() {
+                end[channel] = bits.read(mode.color_bits);
+            }
+        }
+    }
+    for subset in &mut endpoints[..mode.subsets] {
+        for end in subset.iter_mut() {
+            end[3] = if mode.alpha_bits > 0 {
+                bits.read(mode.alpha_bits)
+            } else {
+                255
+            };
+        }
+    }
+    let mut pbits = [[0u32; 2]; 3];
+    if mode.endpoint_pbits {
+        for subset in &mut pbits[..mode.subsets] {
+            *subset = [bits.read(1), bits.read(1)];
+        }
+    } else if mode.shared_pbits {
+        for subset in &mut pbits[..mode.subsets] {
+            let pbit = bits.read(1);
+            *subset = [pbit, pbit];
+        }
+    }
+    let has_pbits = mode.endpoint_pbits || mode.shared_pbits;
+    let expand = |value: u32, precision: u32| {
+        let value = value << (8 - precision);
+        value | value >> precision
+    };
+    for (subset, pbits) in endpoints[..mode.subsets].iter_mut().zip(pbits) {
+        for (end, pbit) in subset.iter_mut().zip(pbits) {
+            for (channel, value) in end.iter_mut().enumerate() {
+                let precision = if channel < 3 {
+                    mode.color_bits
+                } else if mode.alpha_bits > 0 {
+                    mode.alpha_bits
+                } else {
+                    continue;
+                };
+                *value = if has_pbits {
+                    expand(*value << 1 | pbit, precision + 1)
+                } else {
+                    expand(*value, precision)
+                };
+            }
+        }
+    }
+
+    let subset_of = |texel: usize| match mode.subsets {
+        2 => (BC7_PARTITIONS_2[partition] >> texel & 1) as usize,
+        3 => (BC7_PARTITIONS_3[partition] >> (2 * texel) & 3) as usize,
+        _ => 0,
+    };
+    let is_anchor = |texel: usize| match mode.subsets {
+        2 => texel == 0 || texel == BC7_ANCHORS_2[partition] as usize,
+        3 => {
+            texel == 0
+                || texel == BC7_ANCHORS_3[0][partition] as usize
+                || texel == BC7_ANCHORS_3[1][partition] as usize
+        }
+        _ => texel == 0,
+    };
+    let indices: [u32; 16] =
+        std::array::from_fn(|texel| bits.read(mode.index_bits - is_anchor(texel) as u32));
+    let indices2: [u32; 16] = std::array::from_fn(|texel| {
+        if mode.index2_bits == 0 {
+            0
+        } else {
+            bits.read(mode.index2_bits - (texel == 0) as u32)
+        }
+    });
+
+    std::array::from_fn(|texel| {
+        let [start, end] = endpoints[subset_of(texel)];
+        // Mode 4 and 5 index color and alpha separately, the selection bit
+        // swaps which set does which
+        let (color_index, color_bits, alpha_index, alpha_bits) = if mode.index2_bits == 0 {
+            (
+                indices[texel],
+                mode.index_bits,
+                indices[texel],
+                mode.index_bits,
+            )
+        } else if selection == 0 {
+            (
+                indices[texel],
+                mode.index_bits,
+                indices2[texel],
+                mode.index2_bits,
+            )
+        } else {
+            (
+                indices2[texel],
+                mode.index2_bits,
+                indices[texel],
+                mode.index_bits,
+            )
+        };
+        let interpolate = |channel: usize, index: u32, bits: u32| {
+            let weight = BC7_WEIGHTS[bits as usize - 2][index as usize];
+            ((start[channel] * (64 - weight) + end[channel] * weight + 32) >> 6) as u8
+        };
+        let mut rgba = [
+            interpolate(0, color_index, color_bits),
+            interpolate(1, color_index, color_bits),
+            interpolate(2, color_index, color_bits),
+            interpolate(3, alpha_index, alpha_bits),
+        ];
+        if rotation > 0 {
+            rgba.swap(3, rotation as usize - 1);
+        }
+        rgba
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Packs (value, bit count) fields into a block, lowest bit first
+    fn pack(fields: &[(u32, u32)]) -> [u8; 16] {
+        let (mut bits, mut position) = (0u128, 0);
+        for &(value, count) in fields {
+            bits |= (value as u128) << position;
+            position += count;
+        }
+        assert_eq!(position, 128);
+        bits.to_le_bytes()
+    }
+
+    fn grays(values: [u8; 16], alpha: impl Fn(usize) -> u8) -> [[u8; 4]; 16] {
+        std::array::from_fn(|i| [values[i], values[i], values[i], alpha(i)])
+    }
+
+    #[test]
+    fn bc1_four_colors() {
+        // Red then blue, indices 0 to 3 along each row
+        let block = [0x00, 0xF8, 0x1F, 0x00, 0xE4, 0xE4, 0xE4, 0xE4];
+        let row = [
+            [255, 0, 0, 255],
+            [0, 0, 255, 255],
+            [170, 0, 85, 255],
+            [85, 0, 170, 255],
+        ];
+        let texels = BlockFormat::Bc1.decode_block(&block);
+        assert!(texels.chunks(4).all(|texels| texels == row));
+    }
+
+    #[test]
+    fn bc1_three_colors_and_black() {
+        // Blue then red, so the fourth is black
+        let block = [0x1F, 0x00, 0x00, 0xF8, 0xE4, 0xE4, 0xE4, 0xE4];
+        let row = [
+            [0, 0, 255, 255],
+            [255, 0, 0, 255],
+            [127, 0, 127, 255],
+            [0, 0, 0, 0],
+        ];
+        let texels = BlockFormat::Bc1.decode_block(&block);
+        assert!(texels.chunks(4).all(|texels| texels == row));
+        let texels = BlockFormat::Bc1Rgb.decode_block(&block);
+        assert_eq!(texels[3], [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn bc4_both_palettes() {
+        // Indices 0 to 7 in the first eight texels
+        let indices = [0x88, 0xC6, 0xFA, 0, 0, 0];
+        let decoded = decode_bc4(&[[255, 0].as_slice(), &indices].concat());
+        assert_eq!(decoded[..8], [255, 0, 218, 182, 145, 109, 72, 36]);
+        let decoded = decode_bc4(&[[0, 255].as_slice(), &indices].concat());
+        assert_eq!(decoded[..8], [0, 255, 51, 102, 153, 204, 0, 255]);
+    }
+
+    #[test]
+    fn bc7_mode_6() {
+        // Black to white with p-bits 0 and 1, alpha 254 to 255, index i at texel i
+        let mut fields = vec![(0, 6), (1, 1)];
+        fields.extend([(0, 7), (127, 7), (0, 7), (127, 7), (0, 7), (127, 7)]);
+        fields.extend([(127, 7), (127, 7), (0, 1), (1, 1), (0, 3)]);
+        fields.extend((1..16).map(|i| (i, 4)));
+        let expected = grays(
+            [
+                0, 16, 36, 52, 68, 84, 104, 120, 135, 151, 171, 187, 203, 219, 239, 255,
+            ],
+            |i| if i < 8 { 254 } else { 255 },
+        );
+        assert_eq!(decode_bc7(&pack(&fields)), expected);
+    }
+
+    #[test]
+    fn bc7_mode_1_two_subsets() {
+        // Partition 13 puts the bottom two rows in the second subset, anchored
+        // at the last texel. Gray ramps up in the first subset and down in
+        // the second, p-bits 1 and 0.
+        let mut fields = vec![(0, 1), (1, 1), (13, 6)];
+        for _ in 0..3 {
+            fields.extend([(0, 6), (63, 6), (63, 6), (0, 6)]);
+        }
+        fields.extend([(1, 1), (0, 1), (0, 2)]);
+        fields.extend((1..15).map(|i| (i % 8, 3)));
+        fields.push((3, 2));
+        let expected = grays(
+            [
+                2, 38, 73, 109, 148, 184, 219, 255, 253, 217, 182, 146, 107, 71, 36, 146,
+            ],
+            |_| 255,
+        );
+        assert_eq!(decode_bc7(&pack(&fields)), expected);
+    }
+
+    // An 8x4 BC7 image with both of its levels
+    fn ktx2() -> Vec<u8> {
+        let mut data = KTX2_MAGIC.to_vec();
+        for value in [145, 1, 8, 4, 0, 1, 1, 2, 0] {
+            data.extend(u32::to_le_bytes(value));
+        }
+        data.resize(80, 0);
+        for (offset, length) in [(128u64, 32u64), (160, 16)] {
+            data.extend([offset, length, length].map(u64::to_le_bytes).concat());
+        }
+        data.resize(176, 0);
+        data
+    }
+
+    #[test]
+    fn reads_ktx2() {
+        let image = CompressedImage::from_bytes(&ktx2()).unwrap();
+        assert_eq!(image.format, BlockFormat::Bc7);
+        assert_eq!((image.width, image.height), (8, 4));
+        assert_eq!(
+            image.levels.iter().map(Vec::len).collect::<Vec<_>>(),
+            [32, 16]
+        );
+    }
+
+    #[test]
+    fn reads_dds() {
+        // 8x8 DXT1 with four levels: 4 blocks, then 1 each
+        let mut data = DDS_MAGIC.to_vec();
+        data.resize(128, 0);
+        data[12..16].copy_from_slice(&8u32.to_le_bytes());
+        data[16..20].copy_from_slice(&8u32.to_le_bytes());
+        data[28..32].copy_from_slice(&4u32.to_le_bytes());
+        data[84..88].copy_from_slice(b"DXT1");
+        data.resize(128 + 32 + 8 * 3, 0);
+        let image = CompressedImage::from_bytes(&data).unwrap();
+        assert_eq!(image.format, BlockFormat::Bc1);
+        assert_eq!((image.width, image.height), (8, 8));
+        assert_eq!(image.levels.len(), 4);
+    }
+
+    #[test]
+    fn cut_short_or_out_of_range_is_an_error() {
+        let data = ktx2();
+        assert!(CompressedImage::from_bytes(&data[..170]).is_err());
+        assert!(CompressedImage::from_bytes(&data[..60]).is_err());
+        // First level's offset right at the end of the address space
+        let mut data = data;
+        data[80..88].copy_from_slice(&(u64::MAX - 4).to_le_bytes());
+        assert!(CompressedImage::from_bytes(&data).is_err());
+        assert!(CompressedImage::from_bytes(&DDS_MAGIC).is_err());
+    }
+}
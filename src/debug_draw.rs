@@ -0,0 +1,217 @@
+use cgmath::prelude::*;
+
+use crate::model::Aabb;
+use crate::overlay::OverlayPipeline;
+
+// Line ends drawn at most in a frame, anything past this is left off
+const MAX_VERTICES: usize = 65536;
+const CIRCLE_SEGMENTS: usize = 32;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DebugUniform {
+    view_proj: [[f32; 4]; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DebugVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+impl DebugVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<DebugVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+// ===== DEBUG DRAWING =====
+// Immediate-mode lines in world space: call `line`, `aabb`, `sphere`, `axes`
+// or `arrow` whenever during the frame and everything queued is drawn in one
+// batch by `render`, then cleared. Drawn over the finished frame without a
+// depth test, so nothing hides behind the model.
+pub struct DebugDraw {
+    vertices: Vec<DebugVertex>,
+    uniform_buffer: wgpu::Buffer,
+    vertex_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: OverlayPipeline,
+}
+
+impl DebugDraw {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug Draw Uniform Buffer"),
+            size: std::mem::size_of::<DebugUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug Draw Vertex Buffer"),
+            size: (std::mem::size_of::<DebugVertex>() * MAX_VERTICES) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("debug_draw_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("debug_draw_bind_group"),
+        });
+
+        let pipeline = OverlayPipeline::new(
+            device,
+            format,
+            "Debug Draw",
+            include_str!("debug_draw.wgsl"),
+            &[&bind_group_layout],
+            &[DebugVertex::desc()],
+            wgpu::PrimitiveTopology::LineList,
+        );
+
+        Self {
+            vertices: Vec::new(),
+            uniform_buffer,
+            vertex_buffer,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    pub fn line(&mut self, from: cgmath::Point3<f32>, to: cgmath::Point3<f32>, color: [f32; 4]) {
+        if self.vertices.len() + 2 > MAX_VERTICES {
+            return;
+        }
+        let color = self.pipeline.color(color);
+        self.vertices.extend([from, to].map(|position| DebugVertex {
+            position: position.into(),
+            color,
+        }));
+    }
+
+    // The box's twelve edges
+    pub fn aabb(&mut self, aabb: &Aabb, color: [f32; 4]) {
+        let corner =
+            |i: usize| aabb.point_at([(i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2) as f32]);
+        for i in 0..8 {
+            // Each edge once, from the corner with that axis at its minimum
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corner(i), corner(i | bit), color);
+                }
+            }
+        }
+    }
+
+    // A circle around each axis
+    pub fn sphere(&mut self, center: cgmath::Point3<f32>, radius: f32, color: [f32; 4]) {
+        let axes = [
+            cgmath::Vector3::unit_x(),
+            cgmath::Vector3::unit_y(),
+            cgmath::Vector3::unit_z(),
+        ];
+        for i in 0..3 {
+            let (u, v) = (axes[(i + 1) % 3] * radius, axes[(i + 2) % 3] * radius);
+            let point = |segment: usize| {
+                let angle = segment as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+                center + u * angle.cos() + v * angle.sin()
+            };
+            for segment in 0..CIRCLE_SEGMENTS {
+                self.line(point(segment), point(segment + 1), color);
+            }
+        }
+    }
+
+    // X, Y and Z out from `origin` in red, green and blue
+    pub fn axes(&mut self, origin: cgmath::Point3<f32>, size: f32) {
+        self.line(
+            origin,
+            origin + cgmath::Vector3::unit_x() * size,
+            [1.0, 0.1, 0.1, 1.0],
+        );
+        self.line(
+            origin,
+            origin + cgmath::Vector3::unit_y() * size,
+            [0.1, 1.0, 0.1, 1.0],
+        );
+        self.line(
+            origin,
+            origin + cgmath::Vector3::unit_z() * size,
+            [0.15, 0.3, 1.0, 1.0],
+        );
+    }
+
+    // A line from `from` to `to` with a head a fifth of its length at `to`
+    pub fn arrow(&mut self, from: cgmath::Point3<f32>, to: cgmath::Point3<f32>, color: [f32; 4]) {
+        self.line(from, to, color);
+        let shaft = to - from;
+        let length = shaft.magnitude();
+        if length <= f32::EPSILON {
+            return;
+        }
+        let direction = shaft / length;
+        // Any direction across the shaft, then one across both
+        let across = if direction.y.abs() < 0.9 {
+            direction.cross(cgmath::Vector3::unit_y())
+        } else {
+            direction.cross(cgmath::Vector3::unit_x())
+        }
+        .normalize();
+        let other = direction.cross(across);
+        let head = length * 0.2;
+        let back = to - direction * head;
+        for side in [across, -across, other, -other] {
+            self.line(to, back + side * head * 0.4, color);
+        }
+    }
+
+    // Draws everything queued since the last call over `output` as seen
+    // through `view_proj`, then clears the queue
+    pub fn render(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        view_proj: cgmath::Matrix4<f32>,
+    ) {
+        if self.vertices.is_empty() {
+            return;
+        }
+        let uniform = DebugUniform {
+            view_proj: view_proj.into(),
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.vertices));
+
+        let mut pass = self.pipeline.begin_pass(encoder, "Debug Draw Pass", output);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.draw(0..self.vertices.len() as u32, 0..1);
+        crate::stats::count_draw();
+        self.vertices.clear();
+    }
+}
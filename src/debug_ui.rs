@@ -0,0 +1,114 @@
+use winit::event::WindowEvent;
+use winit::window::Window;
+
+// ===== DEBUG UI =====
+// egui windows drawn over the finished frame, the base for editors and
+// panels that poke at the running scene. `run` builds the UI once a frame
+// from whatever the app adds to the context, `render` draws what it built.
+// While it's hidden it neither takes input nor draws.
+pub struct DebugUi {
+    pub visible: bool,
+    state: egui_winit::State,
+    renderer: egui_wgpu::Renderer,
+    // Built by the latest `run`, drawn by the next `render`
+    primitives: Vec<egui::ClippedPrimitive>,
+    textures_delta: egui::TexturesDelta,
+    pixels_per_point: f32,
+}
+
+impl DebugUi {
+    pub fn new(device: &wgpu::Device, window: &Window, format: wgpu::TextureFormat) -> Self {
+        let context = egui::Context::default();
+        let state = egui_winit::State::new(
+            context,
+            egui::ViewportId::ROOT,
+            window,
+            Some(window.scale_factor() as f32),
+            window.theme(),
+            Some(device.limits().max_texture_dimension_2d as usize),
+        );
+        Self {
+            visible: false,
+            state,
+            renderer: egui_wgpu::Renderer::new(
+                device,
+                format,
+                egui_wgpu::RendererOptions::default(),
+            ),
+            primitives: Vec::new(),
+            textures_delta: egui::TexturesDelta::default(),
+            pixels_per_point: window.scale_factor() as f32,
+        }
+    }
+
+    // Returns whether the UI used the event, in which case the scene
+    // shouldn't also act on it
+    pub fn on_window_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        if !self.visible {
+            return false;
+        }
+        self.state.on_window_event(window, event).consumed
+    }
+
+    // Lays the UI out for this frame, `ui` adds the windows to the context
+    pub fn run(&mut self, window: &Window, ui: impl FnMut(&egui::Context)) {
+        if !self.visible {
+            return;
+        }
+        let input = self.state.take_egui_input(window);
+        let context = self.state.egui_ctx().clone();
+        let output = context.run(input, ui);
+        self.state
+            .handle_platform_output(window, output.platform_output);
+        self.primitives = context.tessellate(output.shapes, output.pixels_per_point);
+        self.textures_delta.append(output.textures_delta);
+        self.pixels_per_point = output.pixels_per_point;
+    }
+
+    // Draws over `output`, which is `width` by `height`
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        let textures_delta = std::mem::take(&mut self.textures_delta);
+        for (id, delta) in &textures_delta.set {
+            self.renderer.update_texture(device, queue, *id, delta);
+        }
+        let screen = egui_wgpu::ScreenDescriptor {
+            size_in_pixels: [width, height],
+            pixels_per_point: self.pixels_per_point,
+        };
+        // Only paint callbacks make command buffers of their own, there aren't any
+        self.renderer
+            .update_buffers(device, queue, encoder, &self.primitives, &screen);
+
+        let mut pass = encoder
+            .begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Debug UI Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: output,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            })
+            .forget_lifetime();
+        self.renderer.render(&mut pass, &self.primitives, &screen);
+        drop(pass);
+
+        for id in &textures_delta.free {
+            self.renderer.free_texture(id);
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use std::collections::VecDeque;
+
+use cgmath::prelude::*;
+use wgpu::util::DeviceExt;
+
+use crate::texture;
+
+// Oldest decals are dropped past this
+pub const MAX_DECALS: usize = 128;
+const SCORCH_SIZE: u32 = 64;
+
+// Unit cube around the origin, the projector box before it's placed
+#[rustfmt::skip]
+const CUBE_VERTICES: [[f32; 3]; 8] = [
+    [-0.5, -0.5, -0.5], [0.5, -0.5, -0.5], [0.5, 0.5, -0.5], [-0.5, 0.5, -0.5],
+    [-0.5, -0.5, 0.5], [0.5, -0.5, 0.5], [0.5, 0.5, 0.5], [-0.5, 0.5, 0.5],
+];
+#[rustfmt::skip]
+const CUBE_INDICES: [u16; 36] = [
+    0, 2, 1, 0, 3, 2, // -Z
+    4, 5, 6, 4, 6, 7, // +Z
+    0, 1, 5, 0, 5, 4, // -Y
+    3, 7, 6, 3, 6, 2, // +Y
+    0, 4, 7, 0, 7, 3, // -X
+    1, 2, 6, 1, 6, 5, // +X
+];
+
+// ===== DECAL =====
+// A box that projects the decal texture down its local Y axis onto whatever
+// geometry is inside it
+#[derive(Debug, Copy, Clone)]
+pub struct Decal {
+    pub position: cgmath::Point3<f32>,
+    pub rotation: cgmath::Quaternion<f32>,
+    pub size: cgmath::Vector3<f32>, // Width, projection depth, length
+    pub opacity: f32,
+}
+
+impl Decal {
+    // Flat on the ground, facing down onto it, turned by `angle` around Y
+    pub fn on_ground(position: cgmath::Point3<f32>, size: f32, angle: cgmath::Rad<f32>) -> Self {
+        Self {
+            position,
+            rotation: cgmath::Quaternion::from_angle_y(angle),
+            size: cgmath::Vector3::new(size, size * 0.5, size),
+            opacity: 1.0,
+        }
+    }
+
+    fn to_raw(self) -> DecalRaw {
+        let model = cgmath::Matrix4::from_translation(self.position.to_vec())
+            * cgmath::Matrix4::from(self.rotation)
+            * cgmath::Matrix4::from_nonuniform_scale(self.size.x, self.size.y, self.size.z);
+        DecalRaw {
+            model: model.into(),
+            // A zero-sized box can't be inverted, and can't hit anything either
+            inv_model: model.invert().unwrap_or_else(cgmath::Matrix4::zero).into(),
+            opacity: self.opacity,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DecalRaw {
+    model: [[f32; 4]; 4],
+    inv_model: [[f32; 4]; 4],
+    opacity: f32,
+    _padding: [f32; 3],
+}
+
+impl DecalRaw {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 9] = wgpu::vertex_attr_array![
+        1 => Float32x4, 2 => Float32x4, 3 => Float32x4, 4 => Float32x4,
+        5 => Float32x4, 6 => Float32x4, 7 => Float32x4, 8 => Float32x4,
+        9 => Float32,
+    ];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<DecalRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+// ===== DECAL SYSTEM =====
+// Draws every decal over the resolved HDR target after the scene pass. The
+// boxes skip the depth test and cull their front faces, so a decal still
+// shows with the camera inside it.
+pub struct DecalSystem {
+    decals: VecDeque<Decal>,
+    dirty: bool,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    instance_buffer: wgpu::Buffer,
+    texture: texture::Texture,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl DecalSystem {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color_format: wgpu::TextureFormat,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        linear_depth: &wgpu::TextureView,
+    ) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Decal Vertex Buffer"),
+            contents: bytemuck::cast_slice(&CUBE_VERTICES),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Decal Index Buffer"),
+            contents: bytemuck::cast_slice(&CUBE_INDICES),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Decal Instance Buffer"),
+            size: (std::mem::size_of::<DecalRaw>() * MAX_DECALS) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let texture = Self::scorch_texture(device, queue);
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("decal_bind_group_layout"),
+        });
+        let bind_group =
+            Self::create_bind_group(device, &bind_group_layout, &texture, linear_depth);
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Decal Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("decal.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Decal Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout, camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Decal Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[
+                    wgpu::VertexBufferLayout {
+                        array_stride: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                        attributes: &wgpu::vertex_attr_array![0 => Float32x3],
+                    },
+                    DecalRaw::desc(),
+                ],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::COLOR,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                // Back faces cover the same pixels and still work from inside the box
+                cull_mode: Some(wgpu::Face::Front),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            decals: VecDeque::new(),
+            dirty: false,
+            vertex_buffer,
+            index_buffer,
+            instance_buffer,
+            texture,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture: &texture::Texture,
+        linear_depth: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(linear_depth),
+                },
+            ],
+            label: Some("decal_bind_group"),
+        })
+    }
+
+    // Dark, ragged blob fading out towards its edge
+    fn scorch_texture(device: &wgpu::Device, queue: &wgpu::Queue) -> texture::Texture {
+        let image = image::RgbaImage::from_fn(SCORCH_SIZE, SCORCH_SIZE, |x, y| {
+            let u = (x as f32 + 0.5) / SCORCH_SIZE as f32 * 2.0 - 1.0;
+            let v = (y as f32 + 0.5) / SCORCH_SIZE as f32 * 2.0 - 1.0;
+            let angle = v.atan2(u);
+            // Wobble the rim so it doesn't look like a perfect circle
+            let radius = 0.75 + 0.1 * (angle * 5.0).sin() + 0.05 * (angle * 13.0).cos();
+            let distance = (u * u + v * v).sqrt() / radius;
+            let alpha = (1.0 - distance).clamp(0.0, 1.0).powf(0.6);
+            image::Rgba([20, 14, 10, (alpha * 230.0) as u8])
+        });
+        // Fixed size RGBA image, this can't fail
+        texture::Texture::from_image(
+            device,
+            queue,
+            &image::DynamicImage::ImageRgba8(image),
+            Some("Scorch Decal"),
+        )
+        .unwrap()
+    }
+
+    pub fn add(&mut self, decal: Decal) {
+        if self.decals.len() == MAX_DECALS {
+            self.decals.pop_front();
+        }
+        self.decals.push_back(decal);
+        self.dirty = true;
+    }
+
+    pub fn clear(&mut self) {
+        self.decals.clear();
+        self.dirty = true;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decals.is_empty()
+    }
+
+    // Uploads the decals if any were added or removed since the last call
+    pub fn update(&mut self, queue: &wgpu::Queue) {
+        if !self.dirty {
+            return;
+        }
+        let data = self
+            .decals
+            .iter()
+            .map(|decal| decal.to_raw())
+            .collect::<Vec<_>>();
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&data));
+        self.dirty = false;
+    }
+
+    // The linear depth target is recreated on resize, so the bind group has to follow it
+    pub fn resize(&mut self, device: &wgpu::Device, linear_depth: &wgpu::TextureView) {
+        self.bind_group =
+            Self::create_bind_group(device, &self.bind_group_layout, &self.texture, linear_depth);
+    }
+
+    // `output` is the resolved HDR target, drawn over without clearing
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        camera_bind_group: &wgpu::BindGroup,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Decal Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_bind_group(1, camera_bind_group, &[]);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        pass.draw_indexed(0..CUBE_INDICES.len() as u32, 0, 0..self.decals.len() as u32);
+        crate::stats::count_draw();
+    }
+}
@@ -0,0 +1,83 @@
+// ===== DECALS =====
+// Each decal is a box drawn over the lit scene. Pixels rebuild their world
+// position from the linear depth target, and only the ones inside the box get
+// the decal texture, projected down its local Y axis.
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(0) @binding(0)
+var t_decal: texture_2d<f32>;
+@group(0) @binding(1)
+var s_decal: sampler;
+// Linear view depth from the scene pass, 0.0 where nothing was drawn
+@group(0) @binding(2)
+var t_linear_depth: texture_2d<f32>;
+
+struct DecalInput {
+    @location(1) model_0: vec4<f32>,
+    @location(2) model_1: vec4<f32>,
+    @location(3) model_2: vec4<f32>,
+    @location(4) model_3: vec4<f32>,
+    @location(5) inv_model_0: vec4<f32>,
+    @location(6) inv_model_1: vec4<f32>,
+    @location(7) inv_model_2: vec4<f32>,
+    @location(8) inv_model_3: vec4<f32>,
+    @location(9) opacity: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) inv_model_0: vec4<f32>,
+    @location(1) inv_model_1: vec4<f32>,
+    @location(2) inv_model_2: vec4<f32>,
+    @location(3) inv_model_3: vec4<f32>,
+    @location(4) opacity: f32,
+};
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>, decal: DecalInput) -> VertexOutput {
+    let model = mat4x4<f32>(decal.model_0, decal.model_1, decal.model_2, decal.model_3);
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * model * vec4<f32>(position, 1.0);
+    out.inv_model_0 = decal.inv_model_0;
+    out.inv_model_1 = decal.inv_model_1;
+    out.inv_model_2 = decal.inv_model_2;
+    out.inv_model_3 = decal.inv_model_3;
+    out.opacity = decal.opacity;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coords = vec2<i32>(in.clip_position.xy);
+    let depth = textureLoad(t_linear_depth, coords, 0).r;
+    if (depth == 0.0) {
+        discard; // Nothing to project onto
+    }
+
+    // Walk along this pixel's view ray until it's `depth` in front of the camera
+    let uv = in.clip_position.xy / vec2<f32>(textureDimensions(t_linear_depth));
+    let ndc = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    let point = camera.inv_view_proj * vec4<f32>(ndc, 0.5, 1.0);
+    let ray = point.xyz / point.w - camera.view_position.xyz;
+    let forward = -vec3<f32>(camera.view[0].z, camera.view[1].z, camera.view[2].z);
+    let world_position = camera.view_position.xyz + ray * (depth / dot(ray, forward));
+
+    let inv_model = mat4x4<f32>(in.inv_model_0, in.inv_model_1, in.inv_model_2, in.inv_model_3);
+    let local = (inv_model * vec4<f32>(world_position, 1.0)).xyz;
+    if (any(abs(local) > vec3<f32>(0.5))) {
+        discard; // Outside the projector box
+    }
+
+    let color = textureSampleLevel(t_decal, s_decal, local.xz + 0.5, 0.0);
+    // Fade towards the top and bottom of the box so the cut-off doesn't show
+    let fade = 1.0 - smoothstep(0.3, 0.5, abs(local.y));
+    return vec4<f32>(color.rgb, color.a * fade * in.opacity);
+}
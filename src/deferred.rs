@@ -0,0 +1,392 @@
+use std::ops::Range;
+
+use crate::hdr::HdrPipeline;
+use crate::light::Lighting;
+use crate::model::{DrawModel, Model};
+use crate::preprocess::Preprocessor;
+use crate::texture;
+
+// ===== G-BUFFER FORMATS =====
+// Albedo is stored sRGB-encoded so 8 bits are enough, normals need the extra precision
+pub const ALBEDO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+pub const NORMAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+pub const MATERIAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+// Emissive light is HDR, it can be well above 1.0
+pub const EMISSIVE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+// Which path the model pass takes each frame
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RenderPath {
+    Forward,
+    Deferred,
+}
+
+// ===== G-BUFFER =====
+// Screen-sized render targets written by the geometry pass
+pub struct GBuffer {
+    pub albedo: wgpu::TextureView,
+    pub normal: wgpu::TextureView,
+    pub material: wgpu::TextureView,
+    pub emissive: wgpu::TextureView,
+}
+
+impl GBuffer {
+    fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let create_target = |format: wgpu::TextureFormat, label: &str| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width: config.width.max(1),
+                        height: config.height.max(1),
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+
+        Self {
+            albedo: create_target(ALBEDO_FORMAT, "GBuffer Albedo"),
+            normal: create_target(NORMAL_FORMAT, "GBuffer Normal"),
+            material: create_target(MATERIAL_FORMAT, "GBuffer Material"),
+            emissive: create_target(EMISSIVE_FORMAT, "GBuffer Emissive"),
+        }
+    }
+}
+
+// ===== DEFERRED RENDERER =====
+pub struct DeferredRenderer {
+    gbuffer: GBuffer,
+    gbuffer_bind_group_layout: wgpu::BindGroupLayout,
+    gbuffer_bind_group: wgpu::BindGroup,
+    geometry_pipeline: wgpu::RenderPipeline,
+    lighting_pipeline: wgpu::RenderPipeline,
+    depth_mode: texture::DepthMode,
+}
+
+impl DeferredRenderer {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        color_format: wgpu::TextureFormat,
+        depth_texture: &texture::Texture,
+        material_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+        lighting: &Lighting,
+        ssao_layout: &wgpu::BindGroupLayout,
+        vertex_layouts: &[wgpu::VertexBufferLayout],
+        depth_mode: texture::DepthMode,
+    ) -> crate::error::Result<Self> {
+        let gbuffer = GBuffer::new(device, config);
+
+        let gbuffer_texture_entry =
+            |binding: u32, sample_type: wgpu::TextureSampleType| wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type,
+                },
+                count: None,
+            };
+        // The lighting pass uses textureLoad, so nothing here needs to be filterable
+        let unfilterable = wgpu::TextureSampleType::Float { filterable: false };
+        let gbuffer_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    gbuffer_texture_entry(0, unfilterable),
+                    gbuffer_texture_entry(1, unfilterable),
+                    gbuffer_texture_entry(2, unfilterable),
+                    // Depth formats may also be bound as unfilterable floats
+                    gbuffer_texture_entry(3, unfilterable),
+                    gbuffer_texture_entry(4, unfilterable),
+                ],
+                label: Some("gbuffer_bind_group_layout"),
+            });
+        let gbuffer_bind_group = Self::create_gbuffer_bind_group(
+            device,
+            &gbuffer_bind_group_layout,
+            &gbuffer,
+            depth_texture,
+        );
+
+        // ===== GEOMETRY PASS PIPELINE =====
+        let mut preprocessor = Preprocessor::new();
+        lighting.shader_defines(&mut preprocessor);
+        preprocessor.define("NORMAL_MAP", "");
+        let geometry_source = preprocessor.process_builtin("deferred_geometry.wgsl")?;
+        let geometry_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Deferred Geometry Shader"),
+            source: wgpu::ShaderSource::Wgsl(geometry_source.into()),
+        });
+        let geometry_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Deferred Geometry Pipeline Layout"),
+            bind_group_layouts: &[material_layout, camera_layout],
+            push_constant_ranges: &[],
+        });
+        let gbuffer_target = |format: wgpu::TextureFormat| {
+            Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })
+        };
+        let geometry_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Deferred Geometry Pipeline"),
+            layout: Some(&geometry_layout),
+            vertex: wgpu::VertexState {
+                module: &geometry_shader,
+                entry_point: Some("vs_main"),
+                buffers: vertex_layouts,
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &geometry_shader,
+                entry_point: Some("fs_main"),
+                targets: &[
+                    gbuffer_target(ALBEDO_FORMAT),
+                    gbuffer_target(NORMAL_FORMAT),
+                    gbuffer_target(MATERIAL_FORMAT),
+                    gbuffer_target(EMISSIVE_FORMAT),
+                ],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: depth_mode.compare(),
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        // ===== LIGHTING PASS PIPELINE =====
+        let lighting_source = preprocessor.process_builtin("deferred_lighting.wgsl")?;
+        let lighting_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Deferred Lighting Shader"),
+            source: wgpu::ShaderSource::Wgsl(lighting_source.into()),
+        });
+        let lighting_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Deferred Lighting Pipeline Layout"),
+                bind_group_layouts: &[
+                    &gbuffer_bind_group_layout,
+                    camera_layout,
+                    &lighting.bind_group_layout,
+                    ssao_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+        let lighting_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Deferred Lighting Pipeline"),
+            layout: Some(&lighting_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &lighting_shader,
+                entry_point: Some("vs_main"),
+                buffers: &[], // Fullscreen triangle is generated in the shader
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &lighting_shader,
+                entry_point: Some("fs_main"),
+                targets: &HdrPipeline::scene_targets(color_format, Some(wgpu::BlendState::REPLACE)),
+                // The shader skips pixels still at the cleared far depth
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &depth_mode.shader_constants(),
+                    ..Default::default()
+                },
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Ok(Self {
+            gbuffer,
+            gbuffer_bind_group_layout,
+            gbuffer_bind_group,
+            geometry_pipeline,
+            lighting_pipeline,
+            depth_mode,
+        })
+    }
+
+    fn create_gbuffer_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        gbuffer: &GBuffer,
+        depth_texture: &texture::Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&gbuffer.albedo),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&gbuffer.normal),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&gbuffer.material),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&gbuffer.emissive),
+                },
+            ],
+            label: Some("gbuffer_bind_group"),
+        })
+    }
+
+    // The G-buffer has to follow the window size, and the depth texture is recreated on resize
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        depth_texture: &texture::Texture,
+    ) {
+        self.gbuffer = GBuffer::new(device, config);
+        self.gbuffer_bind_group = Self::create_gbuffer_bind_group(
+            device,
+            &self.gbuffer_bind_group_layout,
+            &self.gbuffer,
+            depth_texture,
+        );
+    }
+
+    // Fill the G-buffer and the depth buffer with the scene's opaque geometry
+    pub fn render_geometry(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        depth_view: &wgpu::TextureView,
+        model: &Model,
+        instance_buffer: &wgpu::Buffer,
+        lods: &[Range<u32>],
+        camera_bind_group: &wgpu::BindGroup,
+    ) {
+        let clear = |view| {
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })
+        };
+        let mut geometry_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Deferred Geometry Pass"),
+            color_attachments: &[
+                clear(&self.gbuffer.albedo),
+                clear(&self.gbuffer.normal),
+                clear(&self.gbuffer.material),
+                clear(&self.gbuffer.emissive),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.depth_mode.far_depth()),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        geometry_pass.set_pipeline(&self.geometry_pipeline);
+        geometry_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+        geometry_pass.draw_model_lods_instanced(model, lods, camera_bind_group);
+    }
+
+    // Light every covered pixel of the G-buffer into the output view
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_lighting(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        velocity: &wgpu::TextureView,
+        linear_depth: &wgpu::TextureView,
+        clear_color: wgpu::Color,
+        camera_bind_group: &wgpu::BindGroup,
+        lighting_bind_group: &wgpu::BindGroup,
+        ssao_bind_group: &wgpu::BindGroup,
+    ) {
+        let mut lighting_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Deferred Lighting Pass"),
+            color_attachments: &[
+                Some(wgpu::RenderPassColorAttachment {
+                    view: output,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(clear_color),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                }),
+                // Background pixels stay still and infinitely far until the
+                // sky fills them in
+                Some(wgpu::RenderPassColorAttachment {
+                    view: velocity,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                }),
+                Some(wgpu::RenderPassColorAttachment {
+                    view: linear_depth,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                }),
+            ],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        lighting_pass.set_pipeline(&self.lighting_pipeline);
+        lighting_pass.set_bind_group(0, &self.gbuffer_bind_group, &[]);
+        lighting_pass.set_bind_group(1, camera_bind_group, &[]);
+        lighting_pass.set_bind_group(2, lighting_bind_group, &[]);
+        lighting_pass.set_bind_group(3, ssao_bind_group, &[]);
+        lighting_pass.draw(0..3, 0..1);
+        crate::stats::count_draw();
+    }
+}
@@ -0,0 +1,69 @@
+// ===== DEFERRED GEOMETRY PASS =====
+// Writes surface attributes into the G-buffer; lighting happens later in a fullscreen pass.
+// lighting.wgsl and material.wgsl are included for the material sampling code.
+#include "lighting.wgsl"
+#include "material.wgsl"
+#include "camera.wgsl"
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) world_tangent: vec4<f32>,
+};
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+    out.world_normal = (model_matrix * vec4<f32>(model.normal, 0.0)).xyz;
+    out.world_tangent = vec4<f32>((model_matrix * vec4<f32>(model.tangent.xyz, 0.0)).xyz, model.tangent.w);
+    out.clip_position = camera.view_proj * world_position;
+    return out;
+}
+
+// One output per G-buffer target
+struct GBufferOutput {
+    @location(0) albedo: vec4<f32>,
+    @location(1) normal: vec4<f32>,
+    @location(2) material: vec4<f32>,
+    @location(3) emissive: vec4<f32>,
+};
+
+@fragment
+fn fs_main(in: VertexOutput) -> GBufferOutput {
+    let surface = sample_material(in.tex_coords, in.world_normal, in.world_tangent);
+
+    var out: GBufferOutput;
+    out.albedo = vec4<f32>(surface.albedo, surface.alpha);
+    out.normal = vec4<f32>(surface.normal, 0.0);
+    // r = metallic, g = roughness, b = ambient occlusion
+    out.material = vec4<f32>(surface.metallic, surface.roughness, surface.occlusion, 1.0);
+    out.emissive = vec4<f32>(surface.emissive, 1.0);
+    return out;
+}
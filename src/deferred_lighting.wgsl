@@ -0,0 +1,85 @@
+// ===== DEFERRED LIGHTING PASS =====
+// Fullscreen triangle that reconstructs each pixel from the G-buffer and lights it.
+// Lighting functions and bindings come from lighting.wgsl.
+#include "lighting.wgsl"
+#include "camera.wgsl"
+
+// Cleared depth of the far plane, 0.0 with reverse-Z (`DepthMode::shader_constants`)
+override FAR_DEPTH: f32 = 1.0;
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(0) @binding(0)
+var t_albedo: texture_2d<f32>;
+@group(0) @binding(1)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(2)
+var t_material: texture_2d<f32>;
+// Bound as a plain float texture since GL can't textureLoad from depth textures
+@group(0) @binding(3)
+var t_depth: texture_2d<f32>;
+@group(0) @binding(4)
+var t_emissive: texture_2d<f32>;
+
+// Blurred screen-space ambient occlusion from the SSAO pass
+@group(3) @binding(0)
+var t_ssao: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// Three vertices covering the whole screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) velocity: vec4<f32>,
+    @location(2) linear_depth: f32,
+};
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    let coords = vec2<i32>(in.clip_position.xy);
+    let depth = textureLoad(t_depth, coords, 0).r;
+    if (depth == FAR_DEPTH) {
+        discard; // Nothing was drawn here, keep the clear color
+    }
+
+    // Rebuild the world position from depth
+    let ndc = vec4<f32>(in.uv.x * 2.0 - 1.0, 1.0 - in.uv.y * 2.0, depth, 1.0);
+    let world = camera.inv_view_proj * ndc;
+    let world_position = world.xyz / world.w;
+    let view_depth = -(camera.view * vec4<f32>(world_position, 1.0)).z;
+
+    let albedo = textureLoad(t_albedo, coords, 0);
+    let material = textureLoad(t_material, coords, 0);
+
+    var surface: Surface;
+    surface.albedo = albedo.rgb;
+    surface.alpha = albedo.a;
+    surface.normal = textureLoad(t_normal, coords, 0).xyz;
+    surface.metallic = material.r;
+    surface.roughness = material.g;
+    // SSAO only darkens the ambient term, same as the material's own occlusion
+    surface.occlusion = material.b * textureLoad(t_ssao, coords, 0).r;
+    surface.emissive = textureLoad(t_emissive, coords, 0).rgb;
+
+    // Where this point was on screen last frame, for motion blur
+    let prev = camera.prev_view_proj * vec4<f32>(world_position, 1.0);
+
+    var out: FragmentOutput;
+    out.color = vec4<f32>(shade(surface, world_position, camera.view_position.xyz, view_depth), albedo.a);
+    out.velocity = vec4<f32>((ndc.xy - prev.xy / prev.w) * vec2<f32>(0.5, -0.5), 0.0, 0.0);
+    out.linear_depth = view_depth;
+    return out;
+}
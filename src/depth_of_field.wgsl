@@ -0,0 +1,67 @@
+// ===== DEPTH OF FIELD =====
+// Blurs each pixel by its circle of confusion: how far it sits from the focal
+// plane, scaled by the aperture.
+
+struct DepthOfFieldUniform {
+    focal_distance: f32,
+    aperture: f32,
+    max_blur: f32, // Largest circle of confusion radius, in pixels
+    samples: u32,
+};
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> dof: DepthOfFieldUniform;
+@group(0) @binding(3)
+var t_linear_depth: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// Three vertices covering the whole screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+// Circle of confusion radius in pixels for a pixel `depth` away
+fn circle_of_confusion(depth: f32) -> f32 {
+    // 0.0 means nothing was drawn, which is as far away as it gets
+    if (depth <= 0.0) {
+        return dof.max_blur * min(dof.aperture, 1.0);
+    }
+    let coc = dof.aperture * abs(depth - dof.focal_distance) / depth;
+    return dof.max_blur * clamp(coc, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_source));
+    let center = textureSample(t_source, s_source, in.uv);
+    let radius = circle_of_confusion(textureSample(t_linear_depth, s_source, in.uv).r);
+
+    // Golden angle spiral over the disc. Each sample only counts if its own
+    // blur reaches this pixel, so sharp things in focus don't smear outwards.
+    var color = center.rgb;
+    var weight = 1.0;
+    let samples = max(dof.samples, 1u);
+    for (var i = 0u; i < samples; i = i + 1u) {
+        let distance = radius * sqrt((f32(i) + 0.5) / f32(samples));
+        let angle = f32(i) * 2.39996323;
+        let uv = in.uv + vec2<f32>(cos(angle), sin(angle)) * distance * texel;
+        let sample_radius = circle_of_confusion(textureSampleLevel(t_linear_depth, s_source, uv, 0.0).r);
+        let sample_weight = clamp(sample_radius - distance + 1.0, 0.0, 1.0);
+        color = color + textureSampleLevel(t_source, s_source, uv, 0.0).rgb * sample_weight;
+        weight = weight + sample_weight;
+    }
+    return vec4<f32>(color / weight, center.a);
+}
@@ -0,0 +1,280 @@
+use std::sync::Arc;
+
+use winit::window::{Window, WindowId};
+
+use crate::post_process::PostProcessChain;
+use crate::{
+    bloom, cameras, deferred, extra_window, frame_pacing, gizmo, hdr, input_map, light,
+    light_shafts, post_process, scene, sequencer, stats, window_mode, Camera, ShadingMode, State,
+};
+
+// Post-processing effects that can be switched on and off
+type EffectToggle = fn(&mut PostProcessChain) -> Option<&mut bool>;
+const EFFECT_TOGGLES: [EffectToggle; 6] = [
+    |chain| chain.effect_mut::<bloom::Bloom>().map(|e| &mut e.enabled),
+    |chain| {
+        chain
+            .effect_mut::<post_process::MotionBlur>()
+            .map(|e| &mut e.enabled)
+    },
+    |chain| {
+        chain
+            .effect_mut::<post_process::DepthOfField>()
+            .map(|e| &mut e.enabled)
+    },
+    |chain| {
+        chain
+            .effect_mut::<light_shafts::LightShafts>()
+            .map(|e| &mut e.enabled)
+    },
+    |chain| {
+        chain
+            .effect_mut::<post_process::Vignette>()
+            .map(|e| &mut e.enabled)
+    },
+    |chain| {
+        chain
+            .effect_mut::<post_process::Distortion>()
+            .map(|e| &mut e.enabled)
+    },
+];
+
+// ===== DEVICE RECOVERY =====
+// What a state keeps when its device is lost. Everything on the GPU has to
+// be made again on a new device, and the meshes and textures read from res/
+// again since they only live on the GPU. The rest goes over the new state
+// once it's built: the scene as it was with the prefabs spawned since, the
+// cameras where they were and the settings changed since startup.
+pub(crate) struct KeptState {
+    scene: scene::Scene,
+    spawned: Vec<scene::PrefabSpawn>,
+    // Where the gizmo left the fires and the scene's lights
+    fires: Vec<([f32; 3], cgmath::Quaternion<f32>)>,
+    scene_lights: Vec<light::Light>,
+    cameras: cameras::Cameras,
+    // The extra windows stay open, only their surfaces go with the device
+    extra_windows: Vec<(Arc<Window>, Camera)>,
+    particle_window: Option<WindowId>,
+    cursor_grabbed: bool,
+    window_mode: window_mode::WindowMode,
+    input_map: input_map::InputMap,
+    sequencer: sequencer::Sequencer,
+    frame_limiter: frame_pacing::FrameLimiter,
+    present_mode: wgpu::PresentMode,
+    render_scale: f32,
+    render_path: deferred::RenderPath,
+    shading: ShadingMode,
+    msaa_samples: u32,
+    normal_mapping: bool,
+    fire_enabled: bool,
+    fxaa_enabled: bool,
+    ssao_enabled: bool,
+    oit_enabled: bool,
+    hud_enabled: bool,
+    stats_enabled: bool,
+    debug_draw_enabled: bool,
+    split_views_enabled: bool,
+    security_camera_enabled: bool,
+    grid_enabled: bool,
+    lens_flare_enabled: bool,
+    gpu_timing_enabled: Option<bool>,
+    occlusion_enabled: Option<bool>,
+    indirect_draws_enabled: Option<bool>,
+    effects_enabled: [Option<bool>; EFFECT_TOGGLES.len()],
+    tonemap: Option<(hdr::Tonemapper, f32)>,
+    gizmo_mode: Option<gizmo::GizmoMode>,
+    gizmo_target: usize,
+    selected_instance: Option<u32>,
+    #[cfg(feature = "egui")]
+    debug_ui_visible: bool,
+}
+
+impl State {
+    // Everything CPU-side, the GPU objects go with the rest of it
+    pub(crate) fn into_kept(mut self) -> KeptState {
+        // The new state reads res/ again anyway, so it picks up the changes too
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.asset_reload.is_some() {
+            tracing::info!(
+                "Dropped the asset reload in progress, the new device loads from res/ again"
+            );
+        }
+        KeptState {
+            extra_windows: self
+                .extra_windows
+                .iter()
+                .map(|window| (window.window.clone(), window.view.camera))
+                .collect(),
+            particle_window: self.particle_window,
+            effects_enabled: EFFECT_TOGGLES
+                .map(|toggle| toggle(&mut self.post_process).map(|enabled| *enabled)),
+            tonemap: self
+                .post_process
+                .effect_mut::<hdr::Tonemap>()
+                .map(|tonemap| (tonemap.tonemapper, tonemap.exposure)),
+            fires: self
+                .fire_systems
+                .iter()
+                .map(|fire_system| (fire_system.origin, fire_system.rotation))
+                .collect(),
+            scene_lights: self
+                .scene_lights
+                .iter()
+                .filter_map(|&id| self.lighting.light(id).copied())
+                .collect(),
+            scene: self.scene,
+            spawned: self.spawned,
+            cameras: self.cameras,
+            cursor_grabbed: self.cursor_grabbed,
+            window_mode: self.window_mode,
+            input_map: self.input_map,
+            sequencer: self.sequencer,
+            frame_limiter: self.frame_limiter,
+            present_mode: self.config.present_mode,
+            render_scale: self.render_scale,
+            render_path: self.render_path,
+            shading: self.shading,
+            msaa_samples: self.msaa_samples,
+            normal_mapping: self.normal_mapping,
+            fire_enabled: self.fire_enabled,
+            fxaa_enabled: self.fxaa_enabled,
+            ssao_enabled: self.ssao_enabled,
+            oit_enabled: self.oit_enabled,
+            hud_enabled: self.hud_enabled,
+            stats_enabled: self.stats.is_some(),
+            debug_draw_enabled: self.debug_draw_enabled,
+            split_views_enabled: self.split_views_enabled,
+            security_camera_enabled: self.security_camera_enabled,
+            grid_enabled: self.grid.enabled,
+            lens_flare_enabled: self.lens_flare.enabled,
+            gpu_timing_enabled: self.gpu_timer.as_ref().map(|timer| timer.enabled),
+            occlusion_enabled: self.occlusion.as_ref().map(|occlusion| occlusion.enabled),
+            indirect_draws_enabled: self.indirect_draws.as_ref().map(|draws| draws.enabled),
+            gizmo_mode: self.gizmo.mode,
+            gizmo_target: self.gizmo_target,
+            selected_instance: self.selected_instance,
+            #[cfg(feature = "egui")]
+            debug_ui_visible: self.debug_ui.as_ref().is_some_and(|ui| ui.visible),
+        }
+    }
+
+    // Puts what was kept over a state just built on the new device
+    pub(crate) fn restore(&mut self, kept: KeptState) {
+        self.scene = kept.scene;
+        self.spawned = kept.spawned;
+        self.place_scene();
+        // Matched up by order, which only holds if every one of them came back
+        if kept.fires.len() == self.fire_systems.len() {
+            for (i, (origin, rotation)) in kept.fires.into_iter().enumerate() {
+                self.set_fire_origin(i, origin.into());
+                self.fire_systems[i].rotation = rotation;
+            }
+        }
+        if kept.scene_lights.len() == self.scene_lights.len() {
+            for (&id, kept_light) in self.scene_lights.iter().zip(kept.scene_lights) {
+                if let Some(light) = self.lighting.light_mut(id) {
+                    *light = kept_light;
+                }
+            }
+        }
+
+        self.cameras = kept.cameras;
+        self.cameras
+            .rebind(&self.device, &self.camera_bind_group_layout);
+        self.cursor_grabbed = kept.cursor_grabbed;
+        self.window_mode = kept.window_mode;
+        self.input_map = kept.input_map;
+        self.sequencer = kept.sequencer;
+        self.frame_limiter = kept.frame_limiter;
+
+        // The new adapter might not present the same way
+        if let Some(surface) = self.output.surface() {
+            let caps = surface.get_capabilities(&self.adapter);
+            if caps.present_modes.contains(&kept.present_mode) {
+                self.config.present_mode = kept.present_mode;
+            }
+        }
+        self.render_scale = kept.render_scale;
+        self.resize(self.config.width, self.config.height);
+
+        self.render_path = kept.render_path;
+        self.shading = kept.shading;
+        if self.supported_sample_counts.contains(&kept.msaa_samples) {
+            self.msaa_samples = kept.msaa_samples;
+        }
+        self.normal_mapping = kept.normal_mapping;
+        self.update_model_pipeline();
+        self.apply_sample_count();
+
+        for (window, camera) in kept.extra_windows {
+            let id = window.id();
+            match extra_window::ExtraWindow::new(
+                &self.instance,
+                &self.adapter,
+                &self.device,
+                window,
+                self.hdr.format(),
+                self.sample_count(),
+                &self.camera_bind_group_layout,
+                camera,
+            ) {
+                Ok(window) => self.extra_windows.push(window),
+                Err(e) => tracing::warn!(
+                    "Closed window {:?}, it couldn't be shown on the new device: {:#}",
+                    id,
+                    e
+                ),
+            }
+        }
+        self.particle_window = kept
+            .particle_window
+            .filter(|&id| self.extra_windows.iter().any(|window| window.id() == id));
+
+        self.fire_enabled = kept.fire_enabled;
+        self.fxaa_enabled = kept.fxaa_enabled;
+        self.ssao_enabled = kept.ssao_enabled;
+        self.oit_enabled = kept.oit_enabled;
+        self.hud_enabled = kept.hud_enabled;
+        self.stats = kept
+            .stats_enabled
+            .then(|| stats::StatsOverlay::new(&self.device, &self.queue, self.config.format));
+        self.debug_draw_enabled = kept.debug_draw_enabled;
+        self.split_views_enabled = kept.split_views_enabled;
+        self.security_camera_enabled = kept.security_camera_enabled;
+        self.grid.enabled = kept.grid_enabled;
+        self.lens_flare.enabled = kept.lens_flare_enabled;
+        if let (Some(timer), Some(enabled)) = (&mut self.gpu_timer, kept.gpu_timing_enabled) {
+            timer.enabled = enabled;
+        }
+        if let (Some(occlusion), Some(enabled)) = (&mut self.occlusion, kept.occlusion_enabled) {
+            occlusion.enabled = enabled;
+        }
+        if let (Some(draws), Some(enabled)) =
+            (&mut self.indirect_draws, kept.indirect_draws_enabled)
+        {
+            draws.enabled = enabled;
+        }
+        for (toggle, enabled) in EFFECT_TOGGLES.iter().zip(kept.effects_enabled) {
+            if let (Some(flag), Some(enabled)) = (toggle(&mut self.post_process), enabled) {
+                *flag = enabled;
+            }
+        }
+        if let (Some(tonemap), Some((tonemapper, exposure))) =
+            (self.post_process.effect_mut::<hdr::Tonemap>(), kept.tonemap)
+        {
+            tonemap.tonemapper = tonemapper;
+            tonemap.exposure = exposure;
+        }
+        self.post_process.update(&self.queue);
+
+        self.gizmo.mode = kept.gizmo_mode;
+        self.gizmo_target = kept.gizmo_target;
+        self.selected_instance = kept
+            .selected_instance
+            .filter(|&i| (i as usize) < self.instances.len());
+        #[cfg(feature = "egui")]
+        if let Some(debug_ui) = &mut self.debug_ui {
+            debug_ui.visible = kept.debug_ui_visible;
+        }
+    }
+}
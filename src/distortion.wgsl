@@ -0,0 +1,45 @@
+// ===== LENS DISTORTION =====
+// Barrel distortion, sampling each color channel at a slightly different
+// radius for a bit of chromatic aberration towards the edges.
+
+struct DistortionUniform {
+    strength: f32,
+    chromatic_aberration: f32,
+};
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> distortion: DistortionUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// Three vertices covering the whole screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn distort(centered: vec2<f32>, strength: f32) -> vec2<f32> {
+    let r2 = dot(centered, centered);
+    return centered * (1.0 + strength * r2) + vec2<f32>(0.5);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Shrink so the bulged corners still land inside the texture
+    let centered = (in.uv - vec2<f32>(0.5)) / (1.0 + max(distortion.strength, 0.0) * 0.5);
+    let r = textureSample(t_source, s_source, distort(centered, distortion.strength + distortion.chromatic_aberration)).r;
+    let g = textureSample(t_source, s_source, distort(centered, distortion.strength)).g;
+    let b = textureSample(t_source, s_source, distort(centered, distortion.strength - distortion.chromatic_aberration)).b;
+    return vec4<f32>(r, g, b, 1.0);
+}
@@ -0,0 +1,347 @@
+use anyhow::bail;
+use wgpu::util::DeviceExt;
+
+use crate::texture::Texture;
+
+const CUBEMAP_SIZE: u32 = 512;
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTERED_SIZE: u32 = 128;
+// Roughness 0 to 1 across the prefiltered mips, the last one 8 texels wide
+const PREFILTERED_MIPS: u32 = 5;
+
+// Uniforms are bound at offsets this far apart, one per face drawn
+const FACE_UNIFORM_STRIDE: u64 = 256;
+
+// Matches FaceUniform in environment.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FaceUniform {
+    face: u32,
+    roughness: f32,
+    source_size: f32,
+    source_lod: f32,
+}
+
+// ===== ENVIRONMENT MAPS =====
+// An HDR environment for the skybox and image-based lighting: the sky itself
+// as a cubemap, its diffuse irradiance and its reflections prefiltered for
+// increasing roughness down the mips. All made on the GPU from an
+// equirectangular (latitude-longitude) .hdr image.
+pub struct Environment {
+    pub cubemap: Texture,
+    pub irradiance: Texture,
+    pub prefiltered: Texture,
+}
+
+impl Environment {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    // Rendering into and filtering half floats isn't a given on WebGL
+    pub fn is_supported(adapter: &wgpu::Adapter) -> bool {
+        let features = adapter.get_texture_format_features(Self::FORMAT);
+        features
+            .allowed_usages
+            .contains(wgpu::TextureUsages::RENDER_ATTACHMENT)
+            && features
+                .flags
+                .contains(wgpu::TextureFormatFeatureFlags::FILTERABLE)
+    }
+
+    // Mip levels of `prefiltered`, the last one is for roughness 1
+    pub fn prefiltered_mips(&self) -> u32 {
+        self.prefiltered.texture.mip_level_count()
+    }
+
+    pub fn from_equirectangular(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &image::DynamicImage,
+        label: &str,
+    ) -> anyhow::Result<Self> {
+        let (width, height) = (image.width(), image.height());
+        if width == 0 || height == 0 {
+            bail!("environment image {} is empty", label);
+        }
+        let max_size = device.limits().max_texture_dimension_2d;
+        if width > max_size || height > max_size {
+            bail!(
+                "environment image {} is {}x{}, this device goes up to {}",
+                label,
+                width,
+                height,
+                max_size
+            );
+        }
+        let texels = image
+            .to_rgba32f()
+            .into_raw()
+            .into_iter()
+            .map(|value| half::f16::from_f32(value).to_bits())
+            .collect::<Vec<_>>();
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        // Mipmapped, so the cubemap's smaller levels can be drawn from it
+        // without aliasing
+        let equirect = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: size.max_mips(wgpu::TextureDimension::D2),
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                aspect: wgpu::TextureAspect::All,
+                texture: &equirect,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            bytemuck::cast_slice(&texels),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(8 * width),
+                rows_per_image: Some(height),
+            },
+            size,
+        );
+        let equirect_view = equirect.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let cube = |name: &str, size: u32, mips: u32| {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(&format!("{} {}", label, name)),
+                size: wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 6,
+                },
+                mip_level_count: mips,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: Self::FORMAT,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::Cube),
+                ..Default::default()
+            });
+            Texture {
+                texture,
+                view,
+                sampler: sampler.clone(),
+            }
+        };
+        let cubemap_mips = wgpu::Extent3d {
+            width: CUBEMAP_SIZE,
+            height: CUBEMAP_SIZE,
+            depth_or_array_layers: 1,
+        }
+        .max_mips(wgpu::TextureDimension::D2);
+        let cubemap = cube("Cubemap", CUBEMAP_SIZE, cubemap_mips);
+        let irradiance = cube("Irradiance", IRRADIANCE_SIZE, 1);
+        let prefiltered = cube("Prefiltered", PREFILTERED_SIZE, PREFILTERED_MIPS);
+
+        let source_entry = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let uniform_size = wgpu::BufferSize::new(std::mem::size_of::<FaceUniform>() as u64);
+        let layout = |source: wgpu::BindGroupLayoutEntry| {
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    source,
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: true,
+                            min_binding_size: uniform_size,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("environment_bind_group_layout"),
+            })
+        };
+        // The equirectangular image is read at binding 0, the cubemap at 3
+        let equirect_layout = layout(source_entry(0, wgpu::TextureViewDimension::D2));
+        let cube_layout = layout(source_entry(3, wgpu::TextureViewDimension::Cube));
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Environment Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("environment.wgsl").into()),
+        });
+        let pipeline = |layout: &wgpu::BindGroupLayout, entry_point| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Environment Pipeline Layout"),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Environment Pipeline"),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[], // Fullscreen triangle is generated in the shader
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    targets: &[Some(Self::FORMAT.into())],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let equirect_pipeline = pipeline(&equirect_layout, "fs_equirect");
+        let irradiance_pipeline = pipeline(&cube_layout, "fs_irradiance");
+        let prefilter_pipeline = pipeline(&cube_layout, "fs_prefilter");
+
+        // Every face of every level drawn, the sky's first since the
+        // convolutions read from it. Each of the sky's levels comes straight
+        // from the equirectangular image, GL can't sample one face of a cube
+        // on its own to filter it down.
+        let mut draws = Vec::new();
+        for level in 0..cubemap_mips {
+            for face in 0..6 {
+                draws.push((&equirect_pipeline, &cubemap, face, level, 0.0));
+            }
+        }
+        let sky_draws = draws.len();
+        for face in 0..6 {
+            draws.push((&irradiance_pipeline, &irradiance, face, 0, 0.0));
+            for level in 0..PREFILTERED_MIPS {
+                let roughness = level as f32 / (PREFILTERED_MIPS - 1) as f32;
+                draws.push((&prefilter_pipeline, &prefiltered, face, level, roughness));
+            }
+        }
+        let mut uniforms = vec![0u8; draws.len() * FACE_UNIFORM_STRIDE as usize];
+        for (i, &(_, _, face, level, roughness)) in draws.iter().enumerate() {
+            // A face covers a quarter of the image's width
+            let face_size = (CUBEMAP_SIZE >> level).max(1) as f32;
+            let uniform = FaceUniform {
+                face,
+                roughness,
+                source_size: CUBEMAP_SIZE as f32,
+                source_lod: (width as f32 / (4.0 * face_size)).log2().max(0.0),
+            };
+            let offset = i * FACE_UNIFORM_STRIDE as usize;
+            uniforms[offset..offset + std::mem::size_of::<FaceUniform>()]
+                .copy_from_slice(bytemuck::bytes_of(&uniform));
+        }
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Environment Face Uniforms"),
+            contents: &uniforms,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bind_group = |layout, binding, view| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding,
+                        resource: wgpu::BindingResource::TextureView(view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                            buffer: &uniform_buffer,
+                            offset: 0,
+                            size: uniform_size,
+                        }),
+                    },
+                ],
+                label: Some("environment_bind_group"),
+            })
+        };
+        let equirect_bind_group = bind_group(&equirect_layout, 0, &equirect_view);
+        let cube_bind_group = bind_group(&cube_layout, 3, &cubemap.view);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Environment Encoder"),
+        });
+        Texture::generate_mipmaps(device, &mut encoder, &equirect);
+        for (i, &(pipeline, target, face, level, _)) in draws.iter().enumerate() {
+            let view = target.texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_mip_level: level,
+                mip_level_count: Some(1),
+                base_array_layer: face,
+                array_layer_count: Some(1),
+                ..Default::default()
+            });
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Environment Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            let bind_group = if i < sky_draws {
+                &equirect_bind_group
+            } else {
+                &cube_bind_group
+            };
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, bind_group, &[(i as u64 * FACE_UNIFORM_STRIDE) as u32]);
+            pass.draw(0..3, 0..1);
+            crate::stats::count_draw();
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        Ok(Self {
+            cubemap,
+            irradiance,
+            prefiltered,
+        })
+    }
+}
@@ -0,0 +1,137 @@
+// ===== ENVIRONMENT MAPS =====
+// Renders one cube face at a time: the equirectangular image wrapped onto the
+// cube, then the cube convolved into diffuse irradiance and, one mip per
+// roughness, prefiltered for GGX reflections.
+
+const PI: f32 = 3.14159265359;
+const PREFILTER_SAMPLES: u32 = 64u;
+
+struct FaceUniform {
+    face: u32,
+    roughness: f32,
+    source_size: f32, // Width of the source cube's faces
+    source_lod: f32, // Equirectangular mip matching the face being drawn
+};
+
+@group(0) @binding(0)
+var t_equirect: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> face: FaceUniform;
+@group(0) @binding(3)
+var t_cube: texture_cube<f32>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    let ndc = uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+// Direction through a point on the face, in +X, -X, +Y, -Y, +Z, -Z order like
+// `Skybox::gradient_cubemap`. u goes right and v down across the face.
+fn face_direction(ndc: vec2<f32>) -> vec3<f32> {
+    let u = ndc.x;
+    let v = -ndc.y;
+    switch face.face {
+        case 0u: { return normalize(vec3<f32>(1.0, -v, -u)); }
+        case 1u: { return normalize(vec3<f32>(-1.0, -v, u)); }
+        case 2u: { return normalize(vec3<f32>(u, 1.0, v)); }
+        case 3u: { return normalize(vec3<f32>(u, -1.0, -v)); }
+        case 4u: { return normalize(vec3<f32>(u, -v, 1.0)); }
+        default: { return normalize(vec3<f32>(-u, -v, -1.0)); }
+    }
+}
+
+@fragment
+fn fs_equirect(in: VertexOutput) -> @location(0) vec4<f32> {
+    let d = face_direction(in.ndc);
+    let uv = vec2<f32>(atan2(d.z, d.x) / (2.0 * PI) + 0.5, acos(clamp(d.y, -1.0, 1.0)) / PI);
+    return vec4<f32>(textureSampleLevel(t_equirect, s_source, uv, face.source_lod).rgb, 1.0);
+}
+
+// Any unit vector perpendicular to n, for building a basis around it
+fn basis(n: vec3<f32>) -> mat3x3<f32> {
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(n.y) > 0.999) {
+        up = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let tangent = normalize(cross(up, n));
+    let bitangent = cross(n, tangent);
+    return mat3x3<f32>(tangent, bitangent, n);
+}
+
+// Cosine weighted average over the hemisphere, on a grid of angles. The cube
+// is read a few mips down, which blurs away what the grid would miss.
+@fragment
+fn fs_irradiance(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n = face_direction(in.ndc);
+    let tbn = basis(n);
+    let level = max(log2(face.source_size / 32.0), 0.0);
+    let step = 0.05;
+    var irradiance = vec3<f32>(0.0);
+    var count = 0.0;
+    for (var phi = 0.0; phi < 2.0 * PI; phi += step) {
+        for (var theta = 0.0; theta < 0.5 * PI; theta += step) {
+            let local = vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let sample = textureSampleLevel(t_cube, s_source, tbn * local, level).rgb;
+            irradiance += sample * cos(theta) * sin(theta);
+            count += 1.0;
+        }
+    }
+    return vec4<f32>(PI * irradiance / count, 1.0);
+}
+
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(count), f32(reverseBits(i)) * 2.3283064365386963e-10);
+}
+
+// Half vector around n, distributed like GGX at this roughness
+fn importance_sample_ggx(xi: vec2<f32>, tbn: mat3x3<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return normalize(tbn * vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta));
+}
+
+// Assumes the view straight down the normal (Karis's split sum). Each sample
+// reads a mip as blurry as the solid angle it stands for, so few samples do.
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n = face_direction(in.ndc);
+    if (face.roughness <= 0.0) {
+        return vec4<f32>(textureSampleLevel(t_cube, s_source, n, 0.0).rgb, 1.0);
+    }
+    let tbn = basis(n);
+    let a2 = pow(face.roughness, 4.0);
+    let texel_solid_angle = 4.0 * PI / (6.0 * face.source_size * face.source_size);
+    var color = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < PREFILTER_SAMPLES; i++) {
+        let h = importance_sample_ggx(hammersley(i, PREFILTER_SAMPLES), tbn, face.roughness);
+        let l = normalize(2.0 * dot(n, h) * h - n);
+        let n_dot_l = dot(n, l);
+        if (n_dot_l > 0.0) {
+            let n_dot_h = max(dot(n, h), 0.0);
+            let denominator = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+            let distribution = a2 / (PI * denominator * denominator);
+            // With the view along n, n.h and v.h are the same
+            let pdf = distribution / 4.0 + 0.0001;
+            let sample_solid_angle = 1.0 / (f32(PREFILTER_SAMPLES) * pdf);
+            let level = max(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0);
+            color += textureSampleLevel(t_cube, s_source, l, level).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+    return vec4<f32>(color / max(weight, 0.0001), 1.0);
+}
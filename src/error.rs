@@ -0,0 +1,134 @@
+use std::path::PathBuf;
+
+// ===== ERRORS =====
+// What loading and setting up can fail with, for applications embedding the
+// crate to match on: a missing file is `Read` (or `Fetch` on the web) with
+// the path, a broken one `Image`, `Obj` or `Gltf`. Everything else the
+// loaders run into is `Other`, which keeps anyhow's context. It all goes
+// into `anyhow::Error` with `?` like any other error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(
+        "Couldn't find the res directory, looked in {candidates:?}. Set {var} to where it is."
+    )]
+    NoResDir {
+        candidates: Vec<PathBuf>,
+        var: &'static str,
+    },
+    #[error("Couldn't read {}", .path.display())]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    // The request failing, or the server answering with an error status
+    #[error("Couldn't fetch {url}")]
+    Fetch {
+        url: String,
+        // The HTTP status when there was an answer, 404 for a missing file
+        status: Option<u16>,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("Couldn't decode {path}")]
+    Image {
+        path: String,
+        #[source]
+        source: image::ImageError,
+    },
+    #[error("Couldn't parse {path} as OBJ")]
+    Obj {
+        path: String,
+        #[source]
+        source: tobj::LoadError,
+    },
+    #[error("Couldn't parse {path} as glTF")]
+    Gltf {
+        path: String,
+        #[source]
+        source: gltf::Error,
+    },
+    // A built-in shader the preprocessor couldn't put together
+    #[error("Couldn't build {name}")]
+    Shader {
+        name: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("Couldn't create a surface for the window")]
+    Surface(#[from] wgpu::CreateSurfaceError),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl Error {
+    // Whether it's down to a file that isn't there, as opposed to one that's
+    // broken. Optional files like LODs and sockets are skipped over on these.
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            Error::NoResDir { .. } => true,
+            Error::Read { source, .. } => source.kind() == std::io::ErrorKind::NotFound,
+            Error::Fetch { status, .. } => *status == Some(404),
+            _ => false,
+        }
+    }
+
+    // Displays with every cause after it, "Couldn't read res/cube.obj: No
+    // such file or directory", like anyhow's `{:#}`
+    pub fn report(&self) -> Report<'_> {
+        Report(self)
+    }
+}
+
+pub struct Report<'a>(&'a Error);
+
+impl std::fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)?;
+        let mut source = std::error::Error::source(self.0);
+        while let Some(error) = source {
+            write!(f, ": {}", error)?;
+            source = error.source();
+        }
+        Ok(())
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fetch(status: Option<u16>) -> Error {
+        Error::Fetch {
+            url: "http://localhost/res/cube.obj".to_string(),
+            status,
+            source: "request failed".into(),
+        }
+    }
+
+    fn read(kind: std::io::ErrorKind) -> Error {
+        Error::Read {
+            path: "cube.obj".into(),
+            source: kind.into(),
+        }
+    }
+
+    #[test]
+    fn not_found_only_for_missing_files() {
+        assert!(read(std::io::ErrorKind::NotFound).is_not_found());
+        assert!(!read(std::io::ErrorKind::PermissionDenied).is_not_found());
+        assert!(fetch(Some(404)).is_not_found());
+        assert!(!fetch(Some(500)).is_not_found());
+        assert!(!fetch(None).is_not_found());
+    }
+
+    #[test]
+    fn report_shows_the_causes() {
+        assert_eq!(
+            read(std::io::ErrorKind::NotFound).report().to_string(),
+            "Couldn't read cube.obj: entity not found"
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use winit::window::{Window, WindowId};
+
+use crate::{offscreen, viewport, Camera};
+
+// ===== EXTRA WINDOWS =====
+// Another window onto the same scene, with a surface and configuration of its
+// own but drawing with the main window's device, queue and pipelines. The
+// scene is drawn from its camera into an offscreen target, then copied onto
+// its surface, so it never has to match the main surface's format or size.
+pub struct ExtraWindow {
+    pub window: Arc<Window>,
+    surface: wgpu::Surface<'static>,
+    config: wgpu::SurfaceConfiguration,
+    is_surface_configured: bool,
+    pub view: viewport::ViewCamera,
+    target: offscreen::OffscreenTarget,
+    // Onto the surface, made for its format
+    blit: offscreen::Thumbnail,
+}
+
+impl ExtraWindow {
+    // `hdr_format` and `sample_count` have to match the scene pipelines
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        instance: &wgpu::Instance,
+        adapter: &wgpu::Adapter,
+        device: &wgpu::Device,
+        window: Arc<Window>,
+        hdr_format: wgpu::TextureFormat,
+        sample_count: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        camera: Camera,
+    ) -> anyhow::Result<Self> {
+        let surface = instance.create_surface(window.clone())?;
+        let surface_caps = surface.get_capabilities(adapter);
+        let format = surface_caps
+            .formats
+            .iter()
+            .find(|f| f.is_srgb())
+            .or(surface_caps.formats.first())
+            .copied()
+            .context("The adapter can't present to the new window")?;
+        let size = window.inner_size();
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: size.width,
+            height: size.height,
+            present_mode: surface_caps.present_modes[0],
+            alpha_mode: surface_caps.alpha_modes[0],
+            view_formats: vec![],
+            desired_maximum_frame_latency: 2,
+        };
+        let target = offscreen::OffscreenTarget::new(
+            device,
+            size.width.max(1),
+            size.height.max(1),
+            hdr_format,
+            sample_count,
+        );
+        let mut extra = Self {
+            window,
+            surface,
+            config,
+            is_surface_configured: false,
+            view: viewport::ViewCamera::new(device, camera_bind_group_layout, camera),
+            target,
+            blit: offscreen::Thumbnail::new(device, format),
+        };
+        extra.resize(device, size.width, size.height);
+        Ok(extra)
+    }
+
+    pub fn id(&self) -> WindowId {
+        self.window.id()
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.config.width = width;
+        self.config.height = height;
+        self.surface.configure(device, &self.config);
+        self.is_surface_configured = true;
+        self.target.resize(device, width, height);
+    }
+
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.target.set_sample_count(device, sample_count);
+    }
+
+    // Moves the camera's uniforms up to date with its aspect. Written
+    // straight away, the window's frame is submitted apart from the main one.
+    pub fn update(&mut self, queue: &wgpu::Queue) {
+        self.view.write(queue, self.target.aspect());
+    }
+
+    // Draws a frame, `draw_scene` filling the offscreen target from `view`
+    pub fn render(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        draw_scene: impl FnOnce(
+            &mut wgpu::CommandEncoder,
+            &offscreen::OffscreenTarget,
+            &viewport::ViewCamera,
+        ),
+    ) -> Result<(), wgpu::SurfaceError> {
+        if !self.is_surface_configured {
+            return Ok(());
+        }
+        let output = self.surface.get_current_texture()?;
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Extra Window Encoder"),
+        });
+        encoder.push_debug_group("Extra Window Scene");
+        draw_scene(&mut encoder, &self.target, &self.view);
+        encoder.pop_debug_group();
+        self.blit.render(
+            device,
+            &mut encoder,
+            self.target.color_view(),
+            &view,
+            [0, 0, self.config.width, self.config.height],
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+        output.present();
+        Ok(())
+    }
+}
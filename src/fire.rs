@@ -1,3 +1,4 @@
+use cgmath::prelude::*;
 use std::time::Instant;
 use wgpu::util::DeviceExt;
 
@@ -10,6 +11,12 @@ pub struct TimeUniform {
     _padding: [f32; 3], // Uniforms need to be 16-byte aligned
 }
 
+impl Default for TimeUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TimeUniform {
     pub fn new() -> Self {
         Self {
@@ -73,16 +80,33 @@ impl FireParticleVertex {
 pub struct FireSystem {
     particles: Vec<Particle>,
     pub origin: [f32; 3], // Public so we can update it dynamically
+    // Turns the cone, which points along +Z unturned
+    pub rotation: cgmath::Quaternion<f32>,
     cone_angle: f32,
     spawn_rate: f32,
     accumulator: f32,
+    // 1 when full, burned down over `burn_time` seconds. The flames thin out
+    // as it runs low and go out when it's gone
+    pub fuel: f32,
+    pub burn_time: f32,
     start_time: Instant,
+    // Some particles are heavier embers that fall and land on the ground
+    pub ember_chance: f32,
+    pub ground_height: f32,
+    landed_embers: Vec<[f32; 3]>,
 
     // GPU resources
-    pub vertex_buffer: wgpu::Buffer,
+    // A copy per frame in flight, rewritten every frame
+    pub vertex_buffers: FrameBuffers,
     pub time_buffer: wgpu::Buffer,
     pub time_bind_group: wgpu::BindGroup,
     pub render_pipeline: wgpu::RenderPipeline,
+    // Same particles blended as smoke through the OIT pass
+    pub oit_pipeline: wgpu::RenderPipeline,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+    color_format: wgpu::TextureFormat,
+    depth_mode: texture::DepthMode,
 
     // Cached data
     vertices: Vec<FireParticleVertex>,
@@ -94,15 +118,18 @@ struct Particle {
     velocity: [f32; 3],
     life: f32,
     size: f32,
+    ember: bool,
 }
 
 impl FireSystem {
     pub fn new(
         device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
         camera_bind_group_layout: &wgpu::BindGroupLayout,
         origin: [f32; 3],
-    ) -> Self {
+        depth_mode: texture::DepthMode,
+    ) -> Result<Self> {
         // ===== CREATE TIME UNIFORM =====
         let time_uniform = TimeUniform::new();
         let time_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -137,9 +164,10 @@ impl FireSystem {
         });
 
         // ===== LOAD SHADER =====
+        let source = Preprocessor::new().process_builtin("fire_shader.wgsl")?;
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Fire Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("fire_shader.wgsl").into()),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
         });
 
         // ===== CREATE RENDER PIPELINE =====
@@ -149,37 +177,125 @@ impl FireSystem {
                 bind_group_layouts: &[camera_bind_group_layout, &time_bind_group_layout],
                 push_constant_ranges: &[],
             });
+        let (render_pipeline, oit_pipeline) = Self::create_pipelines(
+            device,
+            &render_pipeline_layout,
+            &shader,
+            color_format,
+            sample_count,
+            depth_mode,
+        );
+
+        // Create initial vertex buffers (empty)
+        let vertex_buffers = FrameBuffers::new(
+            device,
+            &wgpu::BufferDescriptor {
+                label: Some("Fire Vertex Buffer"),
+                size: (std::mem::size_of::<FireParticleVertex>() * 1024 * 4) as u64, // Max 1024 particles
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+        );
+
+        Ok(Self {
+            particles: Vec::new(),
+            origin,
+            rotation: cgmath::Quaternion::one(),
+            cone_angle: 0.3,  // ~17 degrees
+            spawn_rate: 50.0, // particles per second
+            accumulator: 0.0,
+            fuel: 1.0,
+            burn_time: 120.0,
+            start_time: Instant::now(),
+            ember_chance: 0.05,
+            ground_height: 0.0,
+            landed_embers: Vec::new(),
+            vertex_buffers,
+            time_buffer,
+            time_bind_group,
+            render_pipeline,
+            oit_pipeline,
+            render_pipeline_layout,
+            shader,
+            color_format,
+            depth_mode,
+            vertices: Vec::new(),
+        })
+    }
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+    // Additive pipeline for the HDR target and the weighted-blended OIT one
+    fn create_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_mode: texture::DepthMode,
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+        let additive = Self::create_pipeline(
+            device,
+            layout,
+            shader,
+            "fs_main",
+            // The velocity and depth targets get the same blend, fs_main adds zero to them
+            &HdrPipeline::scene_targets(
+                color_format,
+                // IMPORTANT: Additive blending for fire!
+                Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                }),
+            ),
+            sample_count,
+            depth_mode,
+        );
+        let oit = Self::create_pipeline(
+            device,
+            layout,
+            shader,
+            "fs_oit",
+            &oit::Oit::color_targets(),
+            sample_count,
+            depth_mode,
+        );
+        (additive, oit)
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        fs_entry_point: &str,
+        targets: &[Option<wgpu::ColorTargetState>],
+        sample_count: u32,
+        depth_mode: texture::DepthMode,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Fire Pipeline"),
-            layout: Some(&render_pipeline_layout),
+            layout: Some(layout),
             vertex: wgpu::VertexState {
-                module: &shader,
+                module: shader,
                 entry_point: Some("vs_main"),
                 buffers: &[FireParticleVertex::desc()],
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
             },
             fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    // IMPORTANT: Additive blending for fire!
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent {
-                            src_factor: wgpu::BlendFactor::SrcAlpha,
-                            dst_factor: wgpu::BlendFactor::One,
-                            operation: wgpu::BlendOperation::Add,
-                        },
-                        alpha: wgpu::BlendComponent {
-                            src_factor: wgpu::BlendFactor::One,
-                            dst_factor: wgpu::BlendFactor::One,
-                            operation: wgpu::BlendOperation::Add,
-                        },
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                module: shader,
+                entry_point: Some(fs_entry_point),
+                targets,
+                // The OIT weights need to know which way depth runs
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &depth_mode.shader_constants(),
+                    ..Default::default()
+                },
             }),
             primitive: wgpu::PrimitiveState {
                 topology: wgpu::PrimitiveTopology::TriangleList,
@@ -193,58 +309,95 @@ impl FireSystem {
             depth_stencil: Some(wgpu::DepthStencilState {
                 format: texture::Texture::DEPTH_FORMAT,
                 depth_write_enabled: false, // Fire doesn't write depth
-                depth_compare: wgpu::CompareFunction::Less,
+                depth_compare: depth_mode.compare(),
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
             }),
             multisample: wgpu::MultisampleState {
-                count: 1,
+                count: sample_count,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
             multiview: None,
             cache: None,
-        });
+        })
+    }
 
-        // Create initial vertex buffer (empty)
-        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Fire Vertex Buffer"),
-            size: (std::mem::size_of::<FireParticleVertex>() * 1024 * 4) as u64, // Max 1024 particles
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
+    // MSAA changes need a new pipeline, the particles themselves are kept
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        (self.render_pipeline, self.oit_pipeline) = Self::create_pipelines(
+            device,
+            &self.render_pipeline_layout,
+            &self.shader,
+            self.color_format,
+            sample_count,
+            self.depth_mode,
+        );
+    }
 
-        Self {
-            particles: Vec::new(),
-            origin,
-            cone_angle: 0.3,  // ~17 degrees
-            spawn_rate: 50.0, // particles per second
-            accumulator: 0.0,
-            start_time: Instant::now(),
-            vertex_buffer,
-            time_buffer,
-            time_bind_group,
-            render_pipeline,
-            vertices: Vec::new(),
-        }
+    // Swaps in a new fire_shader.wgsl, already preprocessed. Nothing changes when
+    // it doesn't compile.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn reload_shader(
+        &mut self,
+        device: &wgpu::Device,
+        source: &str,
+        sample_count: u32,
+    ) -> Result<(), wgpu::Error> {
+        let (shader, pipelines) = crate::hot_reload::catch_validation(device, || {
+            let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Fire Shader"),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            });
+            let pipelines = Self::create_pipelines(
+                device,
+                &self.render_pipeline_layout,
+                &shader,
+                self.color_format,
+                sample_count,
+                self.depth_mode,
+            );
+            (shader, pipelines)
+        })?;
+        self.shader = shader;
+        (self.render_pipeline, self.oit_pipeline) = pipelines;
+        Ok(())
+    }
+
+    // Seconds since the fire system was created, shared with the noise animation
+    pub fn elapsed(&self) -> f32 {
+        self.start_time.elapsed().as_secs_f32()
     }
 
     // Update particles and spawn new ones
     pub fn update(&mut self, dt: f32) {
         // Update existing particles
+        let ground_height = self.ground_height;
+        let landed_embers = &mut self.landed_embers;
         self.particles.retain_mut(|p| {
             p.position[0] += p.velocity[0] * dt;
             p.position[1] += p.velocity[1] * dt;
             p.position[2] += p.velocity[2] * dt;
 
-            p.life += dt * 0.5; // Age rate
-            p.size += dt * 0.3; // Grow over time
+            if p.ember {
+                // Embers fall, stay small, and burn for longer
+                p.velocity[1] -= 4.0 * dt;
+                p.life += dt * 0.25;
+                if p.position[1] <= ground_height {
+                    landed_embers.push([p.position[0], ground_height, p.position[2]]);
+                    return false;
+                }
+            } else {
+                p.life += dt * 0.5; // Age rate
+                p.size += dt * 0.3; // Grow over time
+            }
 
             p.life < 1.0 // Remove dead particles
         });
 
-        // Spawn new particles
-        self.accumulator += dt;
+        // Spawn new particles, fewer the less fuel's left
+        self.fuel = (self.fuel - dt / self.burn_time.max(f32::EPSILON)).max(0.0);
+        self.accumulator += dt * self.fuel.sqrt();
         let spawn_interval = 1.0 / self.spawn_rate;
 
         while self.accumulator >= spawn_interval {
@@ -255,7 +408,7 @@ impl FireSystem {
 
     fn spawn_particle(&mut self) {
         use rand::Rng;
-        let mut rng = rand::thread_rng();
+        let mut rng = rand::rng();
 
         // Random direction within cone
         let angle: f32 = rng.random::<f32>() * self.cone_angle;
@@ -267,17 +420,52 @@ impl FireSystem {
         let dir_z = angle.cos(); // Primary direction is forward (+Z)
 
         let size_rand: f32 = rng.random();
+        let ember = rng.random::<f32>() < self.ember_chance;
         let particle = Particle {
             position: self.origin,
-            velocity: [dir_x * 0.5, dir_y * 0.8, dir_z * 2.0], // Mostly forward (+Z)
+            // Mostly forward (+Z)
+            velocity: self
+                .rotation
+                .rotate_vector(cgmath::Vector3::new(dir_x * 0.5, dir_y * 0.8, dir_z * 2.0))
+                .into(),
             life: 0.0,
-            size: 0.1 + size_rand * 0.1,
+            size: if ember { 0.03 } else { 0.1 + size_rand * 0.1 },
+            ember,
         };
 
         self.particles.push(particle);
     }
 
+    pub fn refuel(&mut self) {
+        self.fuel = 1.0;
+    }
+
+    // Where each live particle is and how fast it's going
+    pub fn particles(&self) -> impl Iterator<Item = ([f32; 3], [f32; 3])> + '_ {
+        self.particles.iter().map(|p| (p.position, p.velocity))
+    }
+
+    pub fn particle_count(&self) -> usize {
+        self.particles.len()
+    }
+
+    // Sphere around every live particle, billboards included
+    pub fn bounds(&self) -> BoundingSphere {
+        let size = self.particles.iter().map(|p| p.size).fold(0.0, f32::max);
+        let bounds = BoundingSphere::from_positions(self.particles.iter().map(|p| p.position));
+        BoundingSphere {
+            radius: bounds.radius + size,
+            ..bounds
+        }
+    }
+
+    // Where embers hit the ground since the last call, for scorch marks
+    pub fn take_landed_embers(&mut self) -> Vec<[f32; 3]> {
+        std::mem::take(&mut self.landed_embers)
+    }
+
     // Convert particles to GPU vertex format
+    #[profiling::function]
     pub fn prepare_vertices(&mut self) {
         self.vertices.clear();
 
@@ -303,38 +491,88 @@ impl FireSystem {
         }
     }
 
-    pub fn render<'a>(
-        &'a mut self,
-        queue: &wgpu::Queue,
-        render_pass: &mut wgpu::RenderPass<'a>,
-        camera_bind_group: &'a wgpu::BindGroup,
+    pub fn render(
+        &mut self,
+        uploads: &mut Uploads,
+        render_pass: &mut wgpu::RenderPass<'_>,
+        camera_bind_group: &wgpu::BindGroup,
     ) {
+        if self.upload(uploads) {
+            self.draw(render_pass, &self.render_pipeline, camera_bind_group);
+        }
+    }
+
+    // Render into a pass started by `Oit::begin` instead of the HDR target
+    pub fn render_oit(
+        &mut self,
+        uploads: &mut Uploads,
+        render_pass: &mut wgpu::RenderPass<'_>,
+        camera_bind_group: &wgpu::BindGroup,
+    ) {
+        if self.upload(uploads) {
+            self.draw(render_pass, &self.oit_pipeline, camera_bind_group);
+        }
+    }
+
+    // Draws this frame's particles again from another camera, after `render`
+    // or `render_oit` has uploaded them
+    pub fn render_view(
+        &self,
+        render_pass: &mut wgpu::RenderPass<'_>,
+        camera_bind_group: &wgpu::BindGroup,
+    ) {
+        if !self.vertices.is_empty() {
+            self.draw(render_pass, &self.render_pipeline, camera_bind_group);
+        }
+    }
+
+    // `render` and `render_oit` upload on their own. Culled emitters call this
+    // instead so `render_view` still gets this frame's particles. Returns false
+    // when there are no particles to draw.
+    pub fn upload(&mut self, uploads: &mut Uploads) -> bool {
         // Update time uniform
-        let elapsed = self.start_time.elapsed().as_secs_f32();
+        let elapsed = self.elapsed();
         let time_uniform = TimeUniform {
             time: elapsed,
             _padding: [0.0; 3],
         };
-        queue.write_buffer(&self.time_buffer, 0, bytemuck::cast_slice(&[time_uniform]));
+        uploads.write(&self.time_buffer, 0, &[time_uniform]);
 
         // Prepare vertices
         self.prepare_vertices();
 
         if self.vertices.is_empty() {
-            return; // Nothing to render
+            return false; // Nothing to render
         }
 
         // Upload vertices to GPU
-        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.vertices));
+        let vertex_buffer = self.vertex_buffers.next(uploads.frame());
+        uploads.write(vertex_buffer, 0, &self.vertices);
+        true
+    }
 
+    fn draw(
+        &self,
+        render_pass: &mut wgpu::RenderPass<'_>,
+        pipeline: &wgpu::RenderPipeline,
+        camera_bind_group: &wgpu::BindGroup,
+    ) {
         // Draw!
-        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_pipeline(pipeline);
         render_pass.set_bind_group(0, camera_bind_group, &[]);
         render_pass.set_bind_group(1, &self.time_bind_group, &[]);
-        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(0, self.vertex_buffers.current().slice(..));
         render_pass.draw(0..self.vertices.len() as u32, 0..1);
+        crate::stats::count_draw();
     }
 }
 
-// Add missing texture import
-use crate::texture;
+use crate::{
+    error::Result,
+    hdr::HdrPipeline,
+    model::BoundingSphere,
+    oit,
+    preprocess::Preprocessor,
+    texture,
+    upload::{FrameBuffers, Uploads},
+};
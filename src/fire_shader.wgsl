@@ -1,10 +1,10 @@
 // ===== FIRE PARTICLE SHADER =====
 // This shader renders procedural fire using billboard particles
 
-// Camera uniform (reuse from your main shader)
-struct CameraUniform {
-    view_proj: mat4x4<f32>,
-};
+#include "camera.wgsl"
+#include "noise.wgsl"
+
+// Same camera bind group as the model shader
 @group(0) @binding(0)
 var<uniform> camera: CameraUniform;
 
@@ -15,56 +15,6 @@ struct TimeUniform {
 @group(1) @binding(0)
 var<uniform> u_time: TimeUniform;
 
-// ===== NOISE FUNCTIONS =====
-// Simple 3D noise function (pseudo-random)
-fn hash(p: vec3<f32>) -> f32 {
-    var p3 = fract(p * 0.1031);
-    p3 += dot(p3, p3.yzx + 33.33);
-    return fract((p3.x + p3.y) * p3.z);
-}
-
-// 3D Perlin-style noise
-fn noise3d(p: vec3<f32>) -> f32 {
-    let i = floor(p);
-    let f = fract(p);
-
-    // Smooth interpolation
-    let u = f * f * (3.0 - 2.0 * f);
-
-    // Sample 8 corners of cube
-    return mix(
-        mix(
-            mix(hash(i + vec3<f32>(0.0, 0.0, 0.0)), hash(i + vec3<f32>(1.0, 0.0, 0.0)), u.x),
-            mix(hash(i + vec3<f32>(0.0, 1.0, 0.0)), hash(i + vec3<f32>(1.0, 1.0, 0.0)), u.x),
-            u.y
-        ),
-        mix(
-            mix(hash(i + vec3<f32>(0.0, 0.0, 1.0)), hash(i + vec3<f32>(1.0, 0.0, 1.0)), u.x),
-            mix(hash(i + vec3<f32>(0.0, 1.0, 1.0)), hash(i + vec3<f32>(1.0, 1.0, 1.0)), u.x),
-            u.y
-        ),
-        u.z
-    );
-}
-
-// Fractal Brownian Motion - layers of noise at different scales
-fn fbm(p: vec3<f32>) -> f32 {
-    var value = 0.0;
-    var amplitude = 0.5;
-    var frequency = 1.0;
-    var p_var = p;
-
-    // 3 octaves (layers) of noise
-    for (var i = 0; i < 3; i++) {
-        value += amplitude * noise3d(p_var * frequency);
-        frequency *= 2.0;  // Each layer is twice as detailed
-        amplitude *= 0.5;  // But half as strong
-        p_var = p_var * 2.0 + 0.5;
-    }
-
-    return value;
-}
-
 // ===== VERTEX SHADER =====
 // Input: Per-particle data
 struct VertexInput {
@@ -125,8 +75,7 @@ fn vs_main(in: VertexInput) -> VertexOutput {
 
 // ===== FRAGMENT SHADER =====
 // This runs for every pixel in each particle quad
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+fn fire_color(in: VertexOutput) -> vec4<f32> {
     // Calculate distance from center of particle (for circular shape)
     let center_dist = length(in.uv - vec2<f32>(0.5, 0.5)) * 2.0;
 
@@ -162,3 +111,50 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
 
     return vec4<f32>(color, alpha);
 }
+
+// The particles are see-through, so they leave the velocity and depth of
+// whatever is behind them alone; zero added under the additive blend changes nothing
+struct AdditiveOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) velocity: vec4<f32>,
+    @location(2) linear_depth: f32,
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> AdditiveOutput {
+    var out: AdditiveOutput;
+    out.color = fire_color(in);
+    out.velocity = vec4<f32>(0.0);
+    out.linear_depth = 0.0;
+    return out;
+}
+
+// ===== OIT FRAGMENT SHADER =====
+// Writes into the weighted-blended OIT targets, so overlapping particles
+// composite like smoke no matter what order they're drawn in
+struct OitOutput {
+    @location(0) accum: vec4<f32>,
+    @location(1) revealage: f32,
+}
+
+// Depth of the far plane, 0.0 with reverse-Z (`DepthMode::shader_constants`)
+override FAR_DEPTH: f32 = 1.0;
+
+// Depth going from 0 at the near plane to 1 at the far plane in either depth mode
+fn distance_depth(depth: f32) -> f32 {
+    return abs(1.0 - FAR_DEPTH - depth);
+}
+
+@fragment
+fn fs_oit(in: VertexOutput) -> OitOutput {
+    let color = fire_color(in);
+    // Closer and more opaque fragments get more weight (McGuire & Bavoil),
+    // clamped so the 16-bit accumulation target can't overflow
+    let depth_weight = pow(1.0 - distance_depth(in.clip_position.z) * 0.9, 3.0);
+    let weight = clamp(pow(min(1.0, color.a * 10.0) + 0.01, 3.0) * 1e3 * depth_weight, 1e-2, 3e2);
+
+    var out: OitOutput;
+    out.accum = vec4<f32>(color.rgb * color.a, color.a) * weight;
+    out.revealage = color.a;
+    return out;
+}
@@ -0,0 +1,145 @@
+use cgmath::prelude::*;
+
+use crate::Camera;
+
+// Keeps the camera from flipping over at the poles
+const MAX_PITCH: f32 = std::f32::consts::FRAC_PI_2 - 0.01;
+// Range the base speed can be scrolled across, in world units a second
+const MIN_SPEED: f32 = 0.25;
+const MAX_SPEED: f32 = 200.0;
+
+// ===== FLY CAMERA =====
+// WASD moves along where the camera looks, dragging with the right mouse
+// button turns it, or just moving the mouse with mouselook on. Shift goes faster, the mouse wheel changes the base
+// speed. The velocity eases towards what the keys ask for rather than
+// jumping to it, so starting and stopping is smooth.
+pub struct FlyCamera {
+    // World units a second
+    pub speed: f32,
+    // What shift multiplies the speed by
+    pub boost: f32,
+    // Radians a pixel of mouse movement turns
+    pub sensitivity: f32,
+    // How quickly the velocity catches up, higher is snappier
+    pub acceleration: f32,
+    // Turns with any mouse movement, for while the cursor is grabbed
+    pub mouselook: bool,
+    yaw: f32,
+    pitch: f32,
+    // The target stays this far in front, so switching back to orbiting
+    // goes round a point as far away as before
+    distance: f32,
+    velocity: cgmath::Vector3<f32>,
+    is_forward_pressed: bool,
+    is_backward_pressed: bool,
+    is_left_pressed: bool,
+    is_right_pressed: bool,
+    is_boosting: bool,
+    is_looking: bool,
+}
+
+impl FlyCamera {
+    pub fn new(speed: f32) -> Self {
+        Self {
+            speed,
+            boost: 4.0,
+            sensitivity: 0.003,
+            acceleration: 10.0,
+            mouselook: false,
+            yaw: 0.0,
+            pitch: 0.0,
+            distance: 1.0,
+            velocity: cgmath::Vector3::zero(),
+            is_forward_pressed: false,
+            is_backward_pressed: false,
+            is_left_pressed: false,
+            is_right_pressed: false,
+            is_boosting: false,
+            is_looking: false,
+        }
+    }
+
+    // Picks up from wherever `camera` is looking, at rest
+    pub fn look_from(&mut self, camera: &Camera) {
+        let forward = camera.target - camera.eye;
+        self.distance = forward.magnitude().max(0.01);
+        let forward = forward / self.distance;
+        self.yaw = forward.z.atan2(forward.x);
+        self.pitch = forward
+            .y
+            .clamp(-1.0, 1.0)
+            .asin()
+            .clamp(-MAX_PITCH, MAX_PITCH);
+        self.velocity = cgmath::Vector3::zero();
+    }
+
+    fn forward(&self) -> cgmath::Vector3<f32> {
+        cgmath::Vector3::new(
+            self.yaw.cos() * self.pitch.cos(),
+            self.pitch.sin(),
+            self.yaw.sin() * self.pitch.cos(),
+        )
+    }
+
+    // Whether the action was one of the camera's, see `InputMap`
+    pub fn handle_action(&mut self, action: &str, pressed: bool) -> bool {
+        match action {
+            "move_forward" => self.is_forward_pressed = pressed,
+            "move_left" => self.is_left_pressed = pressed,
+            "move_backward" => self.is_backward_pressed = pressed,
+            "move_right" => self.is_right_pressed = pressed,
+            "boost" => self.is_boosting = pressed,
+            "look" => self.is_looking = pressed,
+            _ => return false,
+        }
+        true
+    }
+
+    // Raw mouse movement in pixels, only turns while looking
+    pub fn handle_mouse_motion(&mut self, dx: f64, dy: f64) {
+        if !self.is_looking && !self.mouselook {
+            return;
+        }
+        self.yaw += dx as f32 * self.sensitivity;
+        self.pitch = (self.pitch - dy as f32 * self.sensitivity).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    // Each notch up is a fifth faster
+    pub fn handle_scroll(&mut self, lines: f32) {
+        self.speed = (self.speed * 1.2f32.powf(lines)).clamp(MIN_SPEED, MAX_SPEED);
+        tracing::info!("Fly speed: {:.2}", self.speed);
+    }
+
+    // Forgets held keys and buttons, for when it stops getting their releases
+    pub fn release(&mut self) {
+        self.is_forward_pressed = false;
+        self.is_backward_pressed = false;
+        self.is_left_pressed = false;
+        self.is_right_pressed = false;
+        self.is_boosting = false;
+        self.is_looking = false;
+    }
+
+    // `dt` in seconds
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: f32) {
+        let forward = self.forward();
+        let right = forward.cross(camera.up).normalize();
+        let axis = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+        let direction = forward * axis(self.is_forward_pressed, self.is_backward_pressed)
+            + right * axis(self.is_right_pressed, self.is_left_pressed);
+        let mut wanted = if direction.magnitude2() > 0.0 {
+            direction.normalize() * self.speed
+        } else {
+            cgmath::Vector3::zero()
+        };
+        if self.is_boosting {
+            wanted *= self.boost;
+        }
+        // Framerate independent easing
+        let blend = 1.0 - (-self.acceleration * dt).exp();
+        self.velocity += (wanted - self.velocity) * blend;
+
+        camera.eye += self.velocity * dt;
+        camera.target = camera.eye + forward * self.distance;
+    }
+}
@@ -0,0 +1,61 @@
+use cgmath::prelude::*;
+
+use crate::Camera;
+
+// ===== FOLLOW CAMERA =====
+// Chases a moving transform from `offset` behind it, in its own space so the
+// camera swings round when it turns. The eye lags behind where it should be,
+// and the camera looks ahead of the target along the way it's moving.
+pub struct FollowCamera {
+    // From the target, turned with it
+    pub offset: cgmath::Vector3<f32>,
+    // Seconds the eye takes to get halfway to where it should be, 0 to stay
+    // locked on
+    pub lag: f32,
+    // Seconds of the target's velocity to look ahead by
+    pub look_ahead: f32,
+    target: Option<cgmath::Matrix4<f32>>,
+    previous: Option<cgmath::Point3<f32>>,
+    velocity: cgmath::Vector3<f32>,
+}
+
+impl FollowCamera {
+    pub fn new(offset: cgmath::Vector3<f32>, lag: f32, look_ahead: f32) -> Self {
+        Self {
+            offset,
+            lag,
+            look_ahead,
+            target: None,
+            previous: None,
+            velocity: cgmath::Vector3::zero(),
+        }
+    }
+
+    // Where the target is this frame, the camera stays put without one
+    pub fn set_target(&mut self, transform: cgmath::Matrix4<f32>) {
+        self.target = Some(transform);
+    }
+
+    // `dt` in seconds
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: f32) {
+        let Some(transform) = self.target else {
+            return;
+        };
+        let position = cgmath::Point3::from_vec(transform.w.truncate());
+        // Smoothed a little, frame to frame velocity jitters with the framerate
+        if let Some(previous) = self.previous.filter(|_| dt > 0.0) {
+            let velocity = (position - previous) / dt;
+            self.velocity += (velocity - self.velocity) * (1.0 - (-10.0 * dt).exp());
+        }
+        self.previous = Some(position);
+
+        let wanted = position + transform.transform_vector(self.offset);
+        let blend = if self.lag <= 0.0 {
+            1.0
+        } else {
+            1.0 - 0.5f32.powf(dt / self.lag)
+        };
+        camera.eye += (wanted - camera.eye) * blend;
+        camera.target = position + self.velocity * self.look_ahead;
+    }
+}
@@ -0,0 +1,385 @@
+use crate::{deferred, render_graph, State};
+
+// ===== RENDER GRAPH =====
+// Every pass of a frame and the resources it touches. Adding or removing an
+// effect comes down to adding or removing its pass here.
+impl State {
+    pub(crate) fn create_render_graph(
+        surface_format: wgpu::TextureFormat,
+    ) -> render_graph::RenderGraph<Self> {
+        let mut graph = render_graph::RenderGraph::<Self>::new();
+        // Tonemapped scene waiting for FXAA
+        graph.add_texture(
+            "ldr",
+            render_graph::TransientTexture {
+                format: surface_format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+            },
+        );
+        graph.add_output("surface");
+
+        // Skinned vertices are written once and drawn by every pass after this
+        graph
+            .add_pass("skinning", |state, encoder, _| {
+                if let Some(skinning) = &state.skinning {
+                    skinning.render(encoder, &state.skinned_meshes);
+                }
+            })
+            .writes(&["skinned_vertices"])
+            .enabled_if(|state| state.skinning.is_some() && !state.skinned_meshes.is_empty());
+
+        // Shadow cascades have to be rendered before the main pass samples them
+        graph
+            .add_pass("shadows", |state, encoder, _| {
+                state.shadows.render(
+                    encoder,
+                    state.assets.model(state.obj_model),
+                    &state.instance_buffer,
+                    state.instances.len() as u32,
+                );
+            })
+            .reads(&["skinned_vertices"])
+            .writes(&["shadow_map"]);
+
+        // Ambient occlusion is needed by both lighting paths, so it's cleared
+        // to white rather than skipped when disabled
+        graph
+            .add_pass("ssao", |state, encoder, _| {
+                state.ssao.render(
+                    encoder,
+                    state.assets.model(state.obj_model),
+                    &state.visible_instance_buffer,
+                    &state.visible_lods,
+                    state.cameras.bind_group(),
+                );
+            })
+            .reads(&["skinned_vertices"])
+            .writes(&["ssao"])
+            .enabled_if(|state| state.ssao_enabled);
+        graph
+            .add_pass("ssao_clear", |state, encoder, _| state.ssao.clear(encoder))
+            .writes(&["ssao"])
+            .enabled_if(|state| !state.ssao_enabled);
+
+        // The deferred path fills the G-buffer and lights it up front; the main
+        // pass then only adds forward-rendered effects on top
+        graph
+            .add_pass("deferred_geometry", |state, encoder, _| {
+                state.deferred.render_geometry(
+                    encoder,
+                    &state.depth_texture.view,
+                    state.assets.model(state.obj_model),
+                    &state.visible_instance_buffer,
+                    &state.visible_lods,
+                    state.cameras.bind_group(),
+                );
+            })
+            .reads(&["skinned_vertices"])
+            .writes(&["gbuffer", "depth"])
+            .enabled_if(|state| state.render_path == deferred::RenderPath::Deferred);
+        graph
+            .add_pass("deferred_lighting", |state, encoder, _| {
+                state.deferred.render_lighting(
+                    encoder,
+                    state.hdr.view(),
+                    state.hdr.velocity_view(),
+                    state.hdr.linear_depth_view(),
+                    state.clear_color,
+                    state.cameras.bind_group(),
+                    &state.lighting.bind_group,
+                    &state.ssao.bind_group,
+                );
+            })
+            .reads(&["gbuffer", "shadow_map", "ssao"])
+            .writes(&["hdr", "velocity", "linear_depth"])
+            .enabled_if(|state| state.render_path == deferred::RenderPath::Deferred);
+
+        // Instance counts for the GPU-driven draws in the scene pass
+        graph
+            .add_pass("indirect_cull", |state, encoder, _| {
+                if let Some(indirect_draws) = &state.indirect_draws {
+                    indirect_draws.render(encoder);
+                }
+            })
+            .writes(&["draw_args"])
+            .enabled_if(|state| state.gpu_driven());
+
+        // Reads the HDR targets and depth because the deferred path has already filled them
+        graph
+            .add_pass("scene", |state, encoder, _| state.render_scene(encoder))
+            .reads(&[
+                "skinned_vertices",
+                "draw_args",
+                "shadow_map",
+                "ssao",
+                "hdr",
+                "velocity",
+                "linear_depth",
+                "depth",
+            ])
+            .writes(&["hdr", "velocity", "linear_depth", "depth"]);
+
+        // Builds the depth pyramid from the finished scene depth and tests the
+        // instances against it. Nothing reads the results on the GPU, so it's
+        // an output of its own.
+        graph.add_output("occlusion");
+        graph
+            .add_pass("hiz", |state, encoder, _| {
+                if let Some(occlusion) = &mut state.occlusion {
+                    occlusion.render(encoder);
+                }
+            })
+            .reads(&["linear_depth"])
+            .writes(&["occlusion"])
+            .enabled_if(|state| state.occlusion.as_ref().is_some_and(|o| o.enabled));
+
+        // Scorch marks go onto the lit scene, under the OIT smoke and the glare
+        graph
+            .add_pass("decals", |state, encoder, _| {
+                state
+                    .decals
+                    .render(encoder, state.hdr.view(), state.cameras.bind_group());
+            })
+            .reads(&["hdr", "linear_depth"])
+            .writes(&["hdr"])
+            .enabled_if(|state| !state.decals.is_empty());
+
+        // Water goes over the scene and its decals, it sees them through the
+        // surface. Smoke and glare are in front of it.
+        graph
+            .add_pass("water", |state, encoder, _| {
+                if let Some(water) = &state.water {
+                    water.render(encoder, &state.hdr, state.cameras.bind_group());
+                }
+            })
+            .reads(&["hdr", "linear_depth"])
+            .writes(&["hdr"])
+            .enabled_if(|state| state.water.is_some());
+
+        // With OIT on, the fire is blended as smoke in its own pass and then
+        // composited over the resolved scene
+        graph
+            .add_pass("oit", |state, encoder, _| {
+                let mut oit_pass = state.oit.begin(encoder, &state.depth_texture.view);
+                for fire_system in &mut state.fire_systems {
+                    if state.frustum.intersects_sphere(&fire_system.bounds()) {
+                        fire_system.render_oit(
+                            &mut state.uploads,
+                            &mut oit_pass,
+                            state.cameras.bind_group(),
+                        );
+                    } else {
+                        fire_system.upload(&mut state.uploads);
+                    }
+                }
+                drop(oit_pass);
+                state.oit.composite(encoder, state.hdr.view());
+            })
+            .reads(&["hdr", "depth"])
+            .writes(&["hdr"])
+            .enabled_if(|state| state.fire_enabled && state.oit_enabled);
+
+        // Glare from the fire goes on top of everything in the scene, including
+        // OIT smoke, but still before bloom and tonemapping
+        graph
+            .add_pass("lens_flare", |state, encoder, _| {
+                state
+                    .lens_flare
+                    .render(encoder, state.hdr.view(), state.cameras.bind_group());
+            })
+            .reads(&["hdr", "linear_depth"])
+            .writes(&["hdr"])
+            .enabled_if(|state| state.fire_enabled && state.lens_flare.enabled);
+
+        // The selection outline goes over everything so it's never hidden
+        graph
+            .add_pass("outline", |state, encoder, _| {
+                if let Some(instance) = state.selected_instance {
+                    state.outline.render(
+                        encoder,
+                        state.hdr.view(),
+                        state.assets.model(state.obj_model),
+                        &state.instance_buffer,
+                        instance,
+                        state.cameras.bind_group(),
+                    );
+                }
+            })
+            .reads(&["hdr"])
+            .writes(&["hdr"])
+            .enabled_if(|state| state.selected_instance.is_some());
+
+        // SSAO only covers the main camera, other views are lit without it
+        graph
+            .add_pass("ssao_unoccluded", |state, encoder, _| {
+                state.ssao.clear_unoccluded(encoder)
+            })
+            .writes(&["ssao_unoccluded"])
+            .enabled_if(|state| state.split_views_enabled || state.security_camera_enabled);
+
+        // Split views go last so nothing drawn for the main camera ends up in them
+        graph
+            .add_pass("split_views", |state, encoder, _| {
+                state.render_split_views(encoder)
+            })
+            .reads(&[
+                "skinned_vertices",
+                "shadow_map",
+                "ssao_unoccluded",
+                "hdr",
+                "velocity",
+                "linear_depth",
+                "depth",
+            ])
+            .writes(&["hdr", "velocity", "linear_depth", "depth"])
+            .enabled_if(|state| state.split_views_enabled && !state.split_views.is_empty());
+
+        // The security camera's own scene pass, shown in the bottom left corner
+        graph
+            .add_pass("security_camera", |state, encoder, _| {
+                state.render_offscreen(encoder, &state.security_target, &state.security_camera);
+                let size = state.render_config.height / 4;
+                state.thumbnail.render(
+                    &state.device,
+                    encoder,
+                    state.security_target.color_view(),
+                    state.hdr.view(),
+                    [
+                        8,
+                        state.render_config.height.saturating_sub(size + 8),
+                        size,
+                        size,
+                    ],
+                );
+            })
+            .reads(&["skinned_vertices", "shadow_map", "ssao_unoccluded", "hdr"])
+            .writes(&["hdr"])
+            .enabled_if(|state| state.security_camera_enabled);
+
+        // Bloom, screen effects and tonemapping onto the surface, going
+        // through FXAA if it's on
+        graph
+            .add_pass("post_process", |state, encoder, resources| {
+                state.post_process.render(
+                    &state.device,
+                    encoder,
+                    state.hdr.view(),
+                    resources.view("surface"),
+                );
+            })
+            .reads(&["hdr", "velocity", "linear_depth"])
+            .writes(&["surface"])
+            .enabled_if(|state| !state.fxaa_enabled);
+        graph
+            .add_pass("post_process_ldr", |state, encoder, resources| {
+                state.post_process.render(
+                    &state.device,
+                    encoder,
+                    state.hdr.view(),
+                    resources.view("ldr"),
+                );
+            })
+            .reads(&["hdr", "velocity", "linear_depth"])
+            .writes(&["ldr"])
+            .enabled_if(|state| state.fxaa_enabled);
+        graph
+            .add_pass("fxaa", |state, encoder, resources| {
+                state.fxaa.render(encoder, resources.view("surface"));
+            })
+            .reads(&["ldr"])
+            .writes(&["surface"])
+            .enabled_if(|state| state.fxaa_enabled);
+
+        // Over everything, at the surface's size whatever the render scale.
+        // Overlays read the surface too, they draw on what's already there
+        // and mustn't cull the passes that put it there.
+        graph
+            .add_pass("stats", |state, encoder, resources| {
+                let (width, height) = (state.config.width, state.config.height);
+                if let Some(stats) = &mut state.stats {
+                    stats.render(
+                        &state.queue,
+                        encoder,
+                        resources.view("surface"),
+                        width,
+                        height,
+                    );
+                }
+            })
+            .reads(&["surface"])
+            .writes(&["surface"])
+            .enabled_if(|state| state.stats.is_some());
+        graph
+            .add_pass("debug_draw", |state, encoder, resources| {
+                state.draw_debug_shapes();
+                let view_proj = state.camera.build_view_projection_matrix();
+                state.debug_draw.render(
+                    &state.queue,
+                    encoder,
+                    resources.view("surface"),
+                    view_proj,
+                );
+            })
+            .reads(&["surface"])
+            .writes(&["surface"])
+            .enabled_if(|state| state.debug_draw_enabled);
+        graph
+            .add_pass("gizmo", |state, encoder, resources| {
+                let Some(view) = state.gizmo_view() else {
+                    return;
+                };
+                let cursor = [
+                    state.cursor[0] * view.screen_size[0],
+                    state.cursor[1] * view.screen_size[1],
+                ];
+                state.gizmo.render(
+                    &state.queue,
+                    encoder,
+                    resources.view("surface"),
+                    &view,
+                    cursor,
+                );
+            })
+            .reads(&["surface"])
+            .writes(&["surface"])
+            .enabled_if(|state| state.gizmo.mode.is_some());
+        graph
+            .add_pass("hud", |state, encoder, resources| {
+                let (width, height) = (state.config.width, state.config.height);
+                state.draw_hud(width as f32, height as f32);
+                if let Some(sprites) = &mut state.sprites {
+                    sprites.render(
+                        &state.queue,
+                        encoder,
+                        resources.view("surface"),
+                        width,
+                        height,
+                    );
+                }
+            })
+            .reads(&["surface"])
+            .writes(&["surface"])
+            .enabled_if(|state| state.hud_enabled && state.sprites.is_some());
+        #[cfg(feature = "egui")]
+        graph
+            .add_pass("debug_ui", |state, encoder, resources| {
+                let (width, height) = (state.config.width, state.config.height);
+                if let Some(debug_ui) = &mut state.debug_ui {
+                    debug_ui.render(
+                        &state.device,
+                        &state.queue,
+                        encoder,
+                        resources.view("surface"),
+                        width,
+                        height,
+                    );
+                }
+            })
+            .reads(&["surface"])
+            .writes(&["surface"])
+            .enabled_if(|state| state.debug_ui.as_ref().is_some_and(|ui| ui.visible));
+
+        graph
+    }
+}
@@ -0,0 +1,146 @@
+use std::time::{Duration, Instant};
+
+// Sleeps are only trusted to wake up this close to the deadline, the rest of
+// the wait is spent spinning
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
+
+// ===== FRAME LIMITER =====
+// Holds frames back to a steady rate, for present modes without vsync where
+// the GPU would otherwise draw as fast as it can. Sleeps most of the wait and
+// spins the last bit, since sleeps overshoot by a millisecond or more on most
+// systems. Deadlines follow on from each other rather than from when the
+// frame finished, so the rate doesn't drift.
+#[derive(Debug, Clone)]
+pub struct FrameLimiter {
+    frame_time: Option<Duration>,
+    next_frame: Instant,
+}
+
+impl FrameLimiter {
+    // None leaves frames uncapped
+    pub fn new(fps: Option<f32>) -> Self {
+        let mut limiter = Self {
+            frame_time: None,
+            next_frame: Instant::now(),
+        };
+        limiter.set_fps(fps);
+        limiter
+    }
+
+    pub fn fps(&self) -> Option<f32> {
+        self.frame_time
+            .map(|frame_time| 1.0 / frame_time.as_secs_f32())
+    }
+
+    pub fn set_fps(&mut self, fps: Option<f32>) {
+        self.frame_time = fps
+            .filter(|fps| *fps > 0.0)
+            .map(|fps| Duration::from_secs_f32(1.0 / fps));
+        self.next_frame = Instant::now();
+    }
+
+    // Blocks until the next frame is due
+    pub fn wait(&mut self) {
+        let Some(frame_time) = self.frame_time else {
+            return;
+        };
+        let now = Instant::now();
+        // A frame behind, from a hitch or a vsynced present that took longer,
+        // starts over from now rather than rushing to catch up
+        if now > self.next_frame + frame_time {
+            self.next_frame = now;
+        }
+        if let Some(sleep) = self
+            .next_frame
+            .checked_duration_since(now)
+            .and_then(|wait| wait.checked_sub(SPIN_MARGIN))
+        {
+            std::thread::sleep(sleep);
+        }
+        while Instant::now() < self.next_frame {
+            std::hint::spin_loop();
+        }
+        self.next_frame += frame_time;
+    }
+}
+
+// ===== FIXED TIMESTEP =====
+// Turns frame times into whole steps of a fixed length, carrying what's left
+// over into the next frame, so a simulation advances the same way at 30 or
+// 300 frames a second
+#[derive(Debug, Clone)]
+pub struct FixedTimestep {
+    // Seconds
+    pub step: f32,
+    // Most steps a frame takes, time past that is dropped so a long hitch
+    // doesn't make the next frames even slower
+    pub max_steps: u32,
+    accumulator: f32,
+}
+
+impl FixedTimestep {
+    pub fn new(step: f32) -> Self {
+        Self {
+            step,
+            max_steps: 8,
+            accumulator: 0.0,
+        }
+    }
+
+    // How many steps `dt` seconds of frame time make
+    pub fn advance(&mut self, dt: f32) -> u32 {
+        self.accumulator += dt;
+        let steps = (self.accumulator / self.step) as u32;
+        self.accumulator -= steps as f32 * self.step;
+        if steps > self.max_steps {
+            self.accumulator = 0.0;
+        }
+        steps.min(self.max_steps)
+    }
+
+    // How far into the next step the leftover time is, from 0 to 1
+    pub fn alpha(&self) -> f32 {
+        self.accumulator / self.step
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leftover_time_carries_over() {
+        let mut timestep = FixedTimestep::new(0.25);
+        assert_eq!(timestep.advance(0.625), 2);
+        assert_eq!(timestep.alpha(), 0.5);
+        assert_eq!(timestep.advance(0.125), 1);
+        assert_eq!(timestep.alpha(), 0.0);
+        assert_eq!(timestep.advance(0.125), 0);
+        assert_eq!(timestep.alpha(), 0.5);
+    }
+
+    #[test]
+    fn long_hitch_is_capped_and_dropped() {
+        let mut timestep = FixedTimestep::new(0.25);
+        assert_eq!(timestep.advance(10.0), timestep.max_steps);
+        assert_eq!(timestep.alpha(), 0.0);
+    }
+
+    #[test]
+    fn no_fps_is_uncapped() {
+        assert_eq!(FrameLimiter::new(None).fps(), None);
+        assert_eq!(FrameLimiter::new(Some(0.0)).fps(), None);
+        assert!((FrameLimiter::new(Some(60.0)).fps().unwrap() - 60.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn frames_are_held_back() {
+        let mut limiter = FrameLimiter::new(Some(100.0));
+        let start = Instant::now();
+        // The first is due right away, the next two a frame apart
+        for _ in 0..3 {
+            limiter.wait();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+}
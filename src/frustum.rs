@@ -0,0 +1,43 @@
+use cgmath::{InnerSpace, Matrix};
+
+use crate::model::BoundingSphere;
+
+// ===== FRUSTUM =====
+// The six planes of a camera's view volume, pulled straight out of its
+// view-projection matrix. Normals point inwards, so a point is inside when it
+// is in front of all of them. Works for standard and reverse-Z alike since
+// both keep clip space depth between 0 and w.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frustum {
+    planes: [cgmath::Vector4<f32>; 6], // xyz = normal, w = distance
+}
+
+impl Frustum {
+    pub fn from_view_proj(view_proj: cgmath::Matrix4<f32>) -> Self {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|i| view_proj.row(i));
+        let planes = [
+            w + x, // Left
+            w - x, // Right
+            w + y, // Bottom
+            w - y, // Top
+            z,     // Depth 0, near or far depending on the depth mode
+            w - z, // Depth w
+        ]
+        .map(|plane| plane / plane.truncate().magnitude());
+        Self { planes }
+    }
+
+    // In the layout shaders take them, xyz = normal and w = distance
+    pub fn planes(&self) -> [[f32; 4]; 6] {
+        self.planes.map(Into::into)
+    }
+
+    // False only when the sphere is entirely outside one of the planes. Spheres
+    // near a corner can pass without touching the frustum, which is fine for culling.
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        let center = sphere.center;
+        self.planes.iter().all(|plane| {
+            plane.x * center.x + plane.y * center.y + plane.z * center.z + plane.w >= -sphere.radius
+        })
+    }
+}
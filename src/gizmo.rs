@@ -0,0 +1,331 @@
+use cgmath::prelude::*;
+
+use crate::overlay::OverlayPipeline;
+use crate::raycast::Ray;
+
+// Vertices drawn at most, enough for three rings
+const MAX_VERTICES: usize = 512;
+const RING_SEGMENTS: usize = 48;
+// How close the cursor has to be to a handle to grab it
+const GRAB_PIXELS: f32 = 8.0;
+const AXIS_COLORS: [[f32; 4]; 3] = [
+    [0.9, 0.1, 0.1, 1.0],
+    [0.1, 0.8, 0.1, 1.0],
+    [0.15, 0.3, 1.0, 1.0],
+];
+// The handle under the cursor or being dragged
+const ACTIVE_COLOR: [f32; 4] = [1.0, 0.85, 0.1, 1.0];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GizmoMode {
+    Translate,
+    Rotate,
+}
+
+impl GizmoMode {
+    // Off, translate, rotate, then off again
+    pub fn next(mode: Option<Self>) -> Option<Self> {
+        match mode {
+            None => Some(Self::Translate),
+            Some(Self::Translate) => Some(Self::Rotate),
+            Some(Self::Rotate) => None,
+        }
+    }
+}
+
+// How far a drag moved its handle since the last call
+#[derive(Debug, Copy, Clone)]
+pub enum GizmoDelta {
+    Translate(cgmath::Vector3<f32>),
+    Rotate(cgmath::Quaternion<f32>),
+}
+
+fn axis(index: usize) -> cgmath::Vector3<f32> {
+    cgmath::Vector3::new(
+        (index == 0) as u8 as f32,
+        (index == 1) as u8 as f32,
+        (index == 2) as u8 as f32,
+    )
+}
+
+// The point on the line through `origin` along unit `direction` that comes
+// closest to the ray, None when they're close to parallel
+fn closest_on_line(
+    ray: &Ray,
+    origin: cgmath::Point3<f32>,
+    direction: cgmath::Vector3<f32>,
+) -> Option<cgmath::Point3<f32>> {
+    let between = origin - ray.origin;
+    let b = direction.dot(ray.direction);
+    let denominator = 1.0 - b * b;
+    if denominator < 1e-4 {
+        return None;
+    }
+    let s = (b * ray.direction.dot(between) - direction.dot(between)) / denominator;
+    Some(origin + direction * s)
+}
+
+// Where the ray crosses the plane through `origin` facing `normal`
+fn on_plane(
+    ray: &Ray,
+    origin: cgmath::Point3<f32>,
+    normal: cgmath::Vector3<f32>,
+) -> Option<cgmath::Point3<f32>> {
+    let facing = ray.direction.dot(normal);
+    if facing.abs() < 1e-4 {
+        return None;
+    }
+    let distance = (origin - ray.origin).dot(normal) / facing;
+    (distance > 0.0).then(|| ray.at(distance))
+}
+
+// Distance in pixels from `point` to the segment from `a` to `b`
+fn distance_to_segment(point: [f32; 2], a: [f32; 2], b: [f32; 2]) -> f32 {
+    let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+    let length2 = dx * dx + dy * dy;
+    let t = if length2 > 0.0 {
+        (((point[0] - a[0]) * dx + (point[1] - a[1]) * dy) / length2).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let (x, y) = (a[0] + dx * t - point[0], a[1] + dy * t - point[1]);
+    (x * x + y * y).sqrt()
+}
+
+// Where the handles are this frame, as the camera sees them
+#[derive(Debug, Copy, Clone)]
+pub struct GizmoView {
+    pub view_proj: cgmath::Matrix4<f32>,
+    // Of the surface, in pixels
+    pub screen_size: [f32; 2],
+    pub center: cgmath::Point3<f32>,
+    // World length of the handles, scaled to stay the same size on screen
+    pub size: f32,
+}
+
+impl GizmoView {
+    fn clip(&self, point: cgmath::Point3<f32>) -> cgmath::Vector4<f32> {
+        self.view_proj * point.to_homogeneous()
+    }
+
+    // Pixels from the top left, None behind the camera
+    fn screen(&self, point: cgmath::Point3<f32>) -> Option<[f32; 2]> {
+        let clip = self.clip(point);
+        (clip.w > 1e-4).then(|| {
+            [
+                (clip.x / clip.w * 0.5 + 0.5) * self.screen_size[0],
+                (0.5 - clip.y / clip.w * 0.5) * self.screen_size[1],
+            ]
+        })
+    }
+
+    // The handle for `axis` as a line strip: a line out along it, or a ring around it
+    fn handle(&self, mode: GizmoMode, index: usize) -> Vec<cgmath::Point3<f32>> {
+        let direction = axis(index);
+        match mode {
+            GizmoMode::Translate => vec![self.center, self.center + direction * self.size],
+            GizmoMode::Rotate => {
+                let u = axis((index + 1) % 3);
+                let v = axis((index + 2) % 3);
+                (0..=RING_SEGMENTS)
+                    .map(|i| {
+                        let angle = i as f32 / RING_SEGMENTS as f32 * std::f32::consts::TAU;
+                        self.center + (u * angle.cos() + v * angle.sin()) * self.size * 0.8
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    // The axis whose handle passes closest to `cursor`, if any are in reach
+    fn axis_under(&self, mode: GizmoMode, cursor: [f32; 2]) -> Option<usize> {
+        (0..3)
+            .filter_map(|index| {
+                let points = self
+                    .handle(mode, index)
+                    .into_iter()
+                    .map(|point| self.screen(point))
+                    .collect::<Vec<_>>();
+                points
+                    .windows(2)
+                    .filter_map(|pair| Some(distance_to_segment(cursor, pair[0]?, pair[1]?)))
+                    .min_by(f32::total_cmp)
+                    .map(|distance| (index, distance))
+            })
+            .filter(|&(_, distance)| distance <= GRAB_PIXELS)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(index, _)| index)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GizmoVertex {
+    // Already in clip space, so lines running behind the camera clip properly
+    position: [f32; 4],
+    color: [f32; 4],
+}
+
+impl GizmoVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+        wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<GizmoVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+struct Drag {
+    axis: usize,
+    center: cgmath::Point3<f32>,
+    // Where the cursor was on the axis, or on the ring's plane, last time
+    last: cgmath::Point3<f32>,
+}
+
+// ===== TRANSFORM GIZMO =====
+// Handles along the world axes for moving something about, or rings around
+// them for turning it, drawn over the finished frame. Left-dragging a handle
+// moves the cursor's ray along its axis, or around its ring, and reports how
+// far it went each frame for the app to apply to whatever's selected.
+pub struct Gizmo {
+    pub mode: Option<GizmoMode>,
+    drag: Option<Drag>,
+    vertices: Vec<GizmoVertex>,
+    vertex_buffer: wgpu::Buffer,
+    pipeline: OverlayPipeline,
+}
+
+impl Gizmo {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Gizmo Vertex Buffer"),
+            size: (std::mem::size_of::<GizmoVertex>() * MAX_VERTICES) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let pipeline = OverlayPipeline::new(
+            device,
+            format,
+            "Gizmo",
+            include_str!("gizmo.wgsl"),
+            &[],
+            &[GizmoVertex::desc()],
+            wgpu::PrimitiveTopology::LineList,
+        );
+
+        Self {
+            mode: None,
+            drag: None,
+            vertices: Vec::new(),
+            vertex_buffer,
+            pipeline,
+        }
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    // Starts dragging the handle under `cursor`, in pixels, true if there was one
+    pub fn grab(&mut self, view: &GizmoView, cursor: [f32; 2], ray: &Ray) -> bool {
+        let Some(mode) = self.mode else {
+            return false;
+        };
+        let Some(index) = view.axis_under(mode, cursor) else {
+            return false;
+        };
+        let last = match mode {
+            GizmoMode::Translate => closest_on_line(ray, view.center, axis(index)),
+            GizmoMode::Rotate => on_plane(ray, view.center, axis(index)),
+        };
+        self.drag = last.map(|last| Drag {
+            axis: index,
+            center: view.center,
+            last,
+        });
+        self.drag.is_some()
+    }
+
+    // How far the handle's moved since the last call, following `ray` out
+    // through the cursor. None when it's not being dragged, or the ray runs
+    // along the axis or the ring's plane and can't say
+    pub fn drag(&mut self, ray: &Ray) -> Option<GizmoDelta> {
+        let mode = self.mode?;
+        let drag = self.drag.as_mut()?;
+        let direction = axis(drag.axis);
+        match mode {
+            GizmoMode::Translate => {
+                let point = closest_on_line(ray, drag.center, direction)?;
+                let delta = point - drag.last;
+                drag.last = point;
+                drag.center += delta;
+                Some(GizmoDelta::Translate(delta))
+            }
+            GizmoMode::Rotate => {
+                let point = on_plane(ray, drag.center, direction)?;
+                let (from, to) = (drag.last - drag.center, point - drag.center);
+                if from.magnitude2() < 1e-8 || to.magnitude2() < 1e-8 {
+                    return None;
+                }
+                let angle = direction.dot(from.cross(to)).atan2(from.dot(to));
+                drag.last = point;
+                Some(GizmoDelta::Rotate(cgmath::Quaternion::from_axis_angle(
+                    direction,
+                    cgmath::Rad(angle),
+                )))
+            }
+        }
+    }
+
+    // Lets go, true if something was being dragged
+    pub fn release(&mut self) -> bool {
+        self.drag.take().is_some()
+    }
+
+    // Draws the handles over `output`, the one being dragged or under
+    // `cursor` highlighted
+    pub fn render(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        view: &GizmoView,
+        cursor: [f32; 2],
+    ) {
+        let Some(mode) = self.mode else {
+            return;
+        };
+        let active = match &self.drag {
+            Some(drag) => Some(drag.axis),
+            None => view.axis_under(mode, cursor),
+        };
+        self.vertices.clear();
+        for (index, axis_color) in AXIS_COLORS.into_iter().enumerate() {
+            let color = self.pipeline.color(if active == Some(index) {
+                ACTIVE_COLOR
+            } else {
+                axis_color
+            });
+            let points = view.handle(mode, index);
+            for pair in points.windows(2) {
+                if self.vertices.len() + 2 > MAX_VERTICES {
+                    break;
+                }
+                self.vertices.extend(pair.iter().map(|&point| GizmoVertex {
+                    position: view.clip(point).into(),
+                    color,
+                }));
+            }
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.vertices));
+
+        let mut pass = self.pipeline.begin_pass(encoder, "Gizmo Pass", output);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.draw(0..self.vertices.len() as u32, 0..1);
+        crate::stats::count_draw();
+    }
+}
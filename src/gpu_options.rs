@@ -0,0 +1,210 @@
+use anyhow::Context;
+
+use crate::color::IntermediateFormat;
+
+// Read when there's no command line flag saying otherwise, see `from_env`
+#[cfg(not(target_arch = "wasm32"))]
+pub const ADAPTER_VAR: &str = "LEARN_WGPU_ADAPTER";
+#[cfg(not(target_arch = "wasm32"))]
+pub const POWER_VAR: &str = "LEARN_WGPU_POWER";
+#[cfg(not(target_arch = "wasm32"))]
+pub const BACKEND_VAR: &str = "LEARN_WGPU_BACKEND";
+#[cfg(not(target_arch = "wasm32"))]
+pub const INTERMEDIATE_FORMAT_VAR: &str = "LEARN_WGPU_INTERMEDIATE_FORMAT";
+
+// ===== GPU OPTIONS =====
+// Which GPU to render with, for machines with more than one. Without a name
+// wgpu picks by power preference, the integrated GPU for low power and the
+// discrete one for high performance. Forcing a backend helps pin down
+// rendering that differs between them.
+#[derive(Debug, Clone)]
+pub struct GpuOptions {
+    // Part of the adapter's name, any case, e.g. "nvidia" or "llvmpipe"
+    pub adapter: Option<String>,
+    pub power_preference: wgpu::PowerPreference,
+    // The graphics APIs adapters are looked for on
+    pub backends: wgpu::Backends,
+    // Precision of the scene and post-process targets, Rgba8 or Rg11b10 save
+    // bandwidth. Rgba16Float if the adapter can't render into it.
+    pub intermediate_format: IntermediateFormat,
+}
+
+impl Default for GpuOptions {
+    fn default() -> Self {
+        Self {
+            adapter: None,
+            power_preference: wgpu::PowerPreference::default(),
+            // BackendBit::PRIMARY => Vulkan + Metal + DX12 + Browser WebGPU
+            #[cfg(not(target_arch = "wasm32"))]
+            backends: wgpu::Backends::PRIMARY,
+            #[cfg(target_arch = "wasm32")]
+            backends: wgpu::Backends::GL,
+            intermediate_format: IntermediateFormat::default(),
+        }
+    }
+}
+
+impl GpuOptions {
+    pub fn create_instance(&self) -> wgpu::Instance {
+        // The instance is a handle to our GPU
+        wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: self.backends,
+            ..Default::default()
+        })
+    }
+
+    // From ADAPTER_VAR, POWER_VAR, BACKEND_VAR and INTERMEDIATE_FORMAT_VAR,
+    // defaults for whichever aren't set
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut options = Self {
+            adapter: std::env::var(ADAPTER_VAR)
+                .ok()
+                .filter(|name| !name.is_empty()),
+            ..Default::default()
+        };
+        if let Ok(power) = std::env::var(POWER_VAR) {
+            options.power_preference =
+                parse_power_preference(&power).with_context(|| format!("In {}", POWER_VAR))?;
+        }
+        if let Ok(backend) = std::env::var(BACKEND_VAR) {
+            options.backends =
+                parse_backend(&backend).with_context(|| format!("In {}", BACKEND_VAR))?;
+        }
+        if let Ok(format) = std::env::var(INTERMEDIATE_FORMAT_VAR) {
+            options.intermediate_format = parse_intermediate_format(&format)
+                .with_context(|| format!("In {}", INTERMEDIATE_FORMAT_VAR))?;
+        }
+        Ok(options)
+    }
+
+    // The adapter these options ask for, one that can present to `surface`
+    // when there is one
+    pub async fn request_adapter(
+        &self,
+        instance: &wgpu::Instance,
+        surface: Option<&wgpu::Surface<'_>>,
+    ) -> anyhow::Result<wgpu::Adapter> {
+        let adapter = match &self.adapter {
+            #[cfg(not(target_arch = "wasm32"))]
+            Some(name) => {
+                let adapters = instance.enumerate_adapters(self.backends);
+                let names = adapters
+                    .iter()
+                    .map(|adapter| adapter.get_info().name)
+                    .collect::<Vec<_>>();
+                adapters
+                    .into_iter()
+                    .filter(|adapter| {
+                        adapter
+                            .get_info()
+                            .name
+                            .to_lowercase()
+                            .contains(&name.to_lowercase())
+                    })
+                    .find(|adapter| surface.is_none_or(|s| adapter.is_surface_supported(s)))
+                    .with_context(|| {
+                        format!(
+                            "No {:?} adapter matching {:?}{}, there's {:?}",
+                            self.backends,
+                            name,
+                            if surface.is_some() {
+                                " that can draw to the window"
+                            } else {
+                                ""
+                            },
+                            names
+                        )
+                    })?
+            }
+            // The browser only ever offers the one
+            #[cfg(target_arch = "wasm32")]
+            Some(_) => anyhow::bail!("Adapters can't be picked by name on the web"),
+            None => instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: self.power_preference,
+                    compatible_surface: surface,
+                    force_fallback_adapter: false,
+                })
+                .await
+                .with_context(|| format!("No {:?} adapter found", self.backends))?,
+        };
+        log_adapter(&adapter);
+        Ok(adapter)
+    }
+}
+
+// "low", "high" or "none"
+pub fn parse_power_preference(power: &str) -> anyhow::Result<wgpu::PowerPreference> {
+    match power.to_lowercase().as_str() {
+        "low" => Ok(wgpu::PowerPreference::LowPower),
+        "high" => Ok(wgpu::PowerPreference::HighPerformance),
+        "none" => Ok(wgpu::PowerPreference::None),
+        _ => anyhow::bail!(
+            "Unknown power preference {:?}, use low, high or none",
+            power
+        ),
+    }
+}
+
+// "vulkan", "dx12", "metal" or "gl", if wgpu has it on this platform
+pub fn parse_backend(backend: &str) -> anyhow::Result<wgpu::Backends> {
+    let backends = match backend.to_lowercase().as_str() {
+        "vulkan" => wgpu::Backends::VULKAN,
+        "dx12" => wgpu::Backends::DX12,
+        "metal" => wgpu::Backends::METAL,
+        "gl" => wgpu::Backends::GL,
+        _ => anyhow::bail!(
+            "Unknown backend {:?}, use vulkan, dx12, metal or gl",
+            backend
+        ),
+    };
+    let available = wgpu::Instance::enabled_backend_features();
+    anyhow::ensure!(
+        available.contains(backends),
+        "There's no {} on this platform, only {:?}",
+        backend,
+        available
+    );
+    Ok(backends)
+}
+
+// "rgba8", "rg11b10" or "rgba16float"
+pub fn parse_intermediate_format(format: &str) -> anyhow::Result<IntermediateFormat> {
+    match format.to_lowercase().as_str() {
+        "rgba8" => Ok(IntermediateFormat::Rgba8),
+        "rg11b10" => Ok(IntermediateFormat::Rg11b10),
+        "rgba16float" => Ok(IntermediateFormat::Rgba16Float),
+        _ => anyhow::bail!(
+            "Unknown intermediate format {:?}, use rgba8, rg11b10 or rgba16float",
+            format
+        ),
+    }
+}
+
+// Every adapter `options` could pick, for `--list-adapters`
+#[cfg(not(target_arch = "wasm32"))]
+pub fn list_adapters(options: &GpuOptions) -> Vec<wgpu::AdapterInfo> {
+    options
+        .create_instance()
+        .enumerate_adapters(options.backends)
+        .iter()
+        .map(wgpu::Adapter::get_info)
+        .collect()
+}
+
+// What was picked and what it can do. The limits are long, they're only
+// shown at debug level.
+pub fn log_adapter(adapter: &wgpu::Adapter) {
+    let info = adapter.get_info();
+    tracing::info!(
+        "Using {} ({:?}, {:?}, driver {} {})",
+        info.name,
+        info.backend,
+        info.device_type,
+        info.driver,
+        info.driver_info
+    );
+    tracing::info!("Adapter features: {:?}", adapter.features());
+    tracing::debug!("Adapter limits: {:#?}", adapter.limits());
+}
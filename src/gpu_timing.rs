@@ -0,0 +1,169 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Passes timed in a frame, any after these go untimed
+const MAX_PASSES: u32 = 64;
+
+// Where the timestamp copy for the CPU is at, like `hiz::Readback`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Readback {
+    Idle,
+    Copied,  // Recorded this frame, mapped once submitted
+    Mapping, // Waiting for the GPU to finish
+}
+
+// ===== GPU TIMING =====
+// Timestamps written into the command encoder either side of each render
+// graph pass, resolved at the end of the frame and read back a frame or two
+// later. Writing them between passes needs TIMESTAMP_QUERY_INSIDE_ENCODERS as
+// well as TIMESTAMP_QUERY, which Metal on Apple GPUs and the web don't have,
+// so there's no timing there.
+pub struct GpuTimer {
+    pub enabled: bool,
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    // Nanoseconds per tick
+    period: f32,
+    // Passes timed so far this frame, in order
+    passes: Vec<&'static str>,
+    // The passes in the copy being read back
+    readback_passes: Vec<&'static str>,
+    readback: Readback,
+    mapped: Arc<AtomicBool>,
+    timings: Vec<(&'static str, f32)>, // Latest results, in milliseconds
+}
+
+impl GpuTimer {
+    pub const FEATURES: wgpu::Features =
+        wgpu::Features::TIMESTAMP_QUERY.union(wgpu::Features::TIMESTAMP_QUERY_INSIDE_ENCODERS);
+
+    pub fn is_supported(device: &wgpu::Device) -> bool {
+        device.features().contains(Self::FEATURES)
+    }
+
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("GPU Timer Queries"),
+            ty: wgpu::QueryType::Timestamp,
+            count: MAX_PASSES * 2,
+        });
+        let size = (MAX_PASSES * 2) as u64 * std::mem::size_of::<u64>() as u64;
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Timer Resolve Buffer"),
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Timer Readback Buffer"),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Self {
+            enabled: false,
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            period: queue.get_timestamp_period(),
+            passes: Vec::new(),
+            readback_passes: Vec::new(),
+            readback: Readback::Idle,
+            mapped: Arc::new(AtomicBool::new(false)),
+            timings: Vec::new(),
+        }
+    }
+
+    // Records `pass` between two timestamps
+    pub fn time(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        name: &'static str,
+        pass: impl FnOnce(&mut wgpu::CommandEncoder),
+    ) {
+        let index = self.passes.len() as u32;
+        if !self.enabled || index >= MAX_PASSES {
+            pass(encoder);
+            return;
+        }
+        encoder.write_timestamp(&self.query_set, index * 2);
+        pass(encoder);
+        encoder.write_timestamp(&self.query_set, index * 2 + 1);
+        self.passes.push(name);
+    }
+
+    // After the frame's last pass. Only one copy is in flight at a time,
+    // frames in between go unread.
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let passes = std::mem::take(&mut self.passes);
+        if passes.is_empty() || self.readback != Readback::Idle {
+            return;
+        }
+        let count = passes.len() as u32 * 2;
+        encoder.resolve_query_set(&self.query_set, 0..count, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            count as u64 * std::mem::size_of::<u64>() as u64,
+        );
+        self.readback_passes = passes;
+        self.readback = Readback::Copied;
+    }
+
+    // Call once the frame's commands are submitted
+    pub fn after_submit(&mut self) {
+        if self.readback != Readback::Copied {
+            return;
+        }
+        let mapped = self.mapped.clone();
+        self.readback_buffer
+            .map_async(wgpu::MapMode::Read, .., move |result| {
+                if result.is_ok() {
+                    mapped.store(true, Ordering::Release);
+                }
+            });
+        self.readback = Readback::Mapping;
+    }
+
+    // Picks up timings the GPU has finished since the last call, returns
+    // whether there were any
+    pub fn poll(&mut self, device: &wgpu::Device) -> bool {
+        if self.readback != Readback::Mapping {
+            return false;
+        }
+        let _ = device.poll(wgpu::PollType::Poll);
+        if !self.mapped.swap(false, Ordering::Acquire) {
+            return false;
+        }
+        {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            let ticks: &[u64] = bytemuck::cast_slice(&data);
+            self.timings = self
+                .readback_passes
+                .iter()
+                .zip(ticks.chunks_exact(2))
+                .map(|(&name, ticks)| {
+                    // Some drivers wrap, or reorder timestamps across passes
+                    let elapsed = ticks[1].saturating_sub(ticks[0]);
+                    (name, elapsed as f32 * self.period / 1_000_000.0)
+                })
+                .collect();
+        }
+        self.readback_buffer.unmap();
+        self.readback = Readback::Idle;
+        true
+    }
+
+    // Each timed pass and how long it took in milliseconds, from a frame or
+    // two ago
+    pub fn timings(&self) -> &[(&'static str, f32)] {
+        &self.timings
+    }
+
+    pub fn total(&self) -> f32 {
+        self.timings.iter().fold(0.0, |total, (_, ms)| total + ms)
+    }
+}
@@ -0,0 +1,389 @@
+use cgmath::prelude::*;
+use rand::{Rng, SeedableRng};
+use wgpu::util::DeviceExt;
+
+use crate::frustum::Frustum;
+use crate::hdr::HdrPipeline;
+use crate::light::Lighting;
+use crate::model::BoundingSphere;
+use crate::preprocess::Preprocessor;
+use crate::scene::SceneGrass;
+use crate::texture;
+
+// Vertices in a blade, three segments narrowing to a point
+const BLADE_VERTICES: u32 = 7;
+// Height burnt blades are left at, out of their own
+const BURNT_HEIGHT: f32 = 0.3;
+
+#[derive(Debug, Copy, Clone)]
+struct GrassBlade {
+    position: [f32; 3],
+    angle: f32,
+    scale: f32,
+    // Offsets the sway so neighbouring blades don't move in step
+    phase: f32,
+    burnt: bool,
+}
+
+impl GrassBlade {
+    fn to_raw(self) -> GrassRaw {
+        GrassRaw {
+            position: self.position,
+            angle: self.angle,
+            scale: if self.burnt {
+                self.scale * BURNT_HEIGHT
+            } else {
+                self.scale
+            },
+            phase: self.phase,
+            burnt: if self.burnt { 1.0 } else { 0.0 },
+            _padding: 0.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GrassRaw {
+    position: [f32; 3],
+    angle: f32,
+    scale: f32,
+    phase: f32,
+    burnt: f32,
+    _padding: f32,
+}
+
+impl GrassRaw {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+        0 => Float32x3, 1 => Float32, 2 => Float32, 3 => Float32, 4 => Float32,
+    ];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<GrassRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GrassUniform {
+    color: [f32; 3],
+    height: f32,
+    wind_direction: [f32; 2],
+    wind_strength: f32,
+    wind_speed: f32,
+    width: f32,
+    time: f32,
+    _padding: [f32; 2],
+}
+
+// ===== GRASS =====
+// Thousands of blades drawn as one instanced triangle strip each, lit like
+// the models and swaying in the wind in the vertex shader. They're scattered
+// once when the scene loads, burning only rewrites the instance buffer. The
+// whole field is culled as one, and blades don't cast shadows.
+pub struct Grass {
+    pub settings: SceneGrass,
+    blades: Vec<GrassBlade>,
+    dirty: bool,
+    time: f32,
+    bounds: BoundingSphere,
+    instance_buffer: wgpu::Buffer,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+    color_format: wgpu::TextureFormat,
+    depth_mode: texture::DepthMode,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Grass {
+    // `ground` gives the height and normal of the ground at (x, z), or None
+    // where nothing should grow
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        settings: SceneGrass,
+        ground: impl Fn(f32, f32) -> Option<(f32, cgmath::Vector3<f32>)>,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        lighting: &Lighting,
+        ssao_bind_group_layout: &wgpu::BindGroupLayout,
+        depth_mode: texture::DepthMode,
+    ) -> crate::error::Result<Self> {
+        let blades = Self::scatter(&settings, ground);
+        let bounds = BoundingSphere::from_positions(blades.iter().map(|blade| blade.position));
+        let bounds = BoundingSphere {
+            radius: bounds.radius + settings.height + settings.wind_strength,
+            ..bounds
+        };
+
+        // Filled by the first `update`. Never empty, wgpu won't bind a buffer
+        // of nothing.
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Grass Instance Buffer"),
+            size: (std::mem::size_of::<GrassRaw>() * blades.len().max(1)) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Grass Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[Self::uniform(&settings, 0.0)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("grass_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("grass_bind_group"),
+        });
+
+        let mut preprocessor = Preprocessor::new();
+        lighting.shader_defines(&mut preprocessor);
+        let source = preprocessor.process_builtin("grass.wgsl")?;
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Grass Shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Grass Pipeline Layout"),
+            bind_group_layouts: &[
+                &bind_group_layout,
+                camera_bind_group_layout,
+                &lighting.bind_group_layout,
+                ssao_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(
+            device,
+            &pipeline_layout,
+            &shader,
+            color_format,
+            sample_count,
+            depth_mode,
+        );
+
+        Ok(Self {
+            settings,
+            blades,
+            dirty: true,
+            time: 0.0,
+            bounds,
+            instance_buffer,
+            uniform_buffer,
+            bind_group,
+            pipeline_layout,
+            shader,
+            color_format,
+            depth_mode,
+            pipeline,
+        })
+    }
+
+    // Tries `count` random spots, keeping the ones with ground flat enough
+    // to grow on
+    fn scatter(
+        settings: &SceneGrass,
+        ground: impl Fn(f32, f32) -> Option<(f32, cgmath::Vector3<f32>)>,
+    ) -> Vec<GrassBlade> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(settings.seed);
+        let mut blades = Vec::with_capacity(settings.count as usize);
+        for _ in 0..settings.count {
+            let x = settings.center[0] + (rng.random::<f32>() - 0.5) * settings.size;
+            let z = settings.center[1] + (rng.random::<f32>() - 0.5) * settings.size;
+            let angle = rng.random::<f32>() * std::f32::consts::TAU;
+            let scale = 0.5 + rng.random::<f32>() * 0.5;
+            let phase = rng.random::<f32>() * std::f32::consts::TAU;
+            let Some((height, normal)) = ground(x, z) else {
+                continue;
+            };
+            if 1.0 - normal.normalize().y > settings.max_slope {
+                continue;
+            }
+            blades.push(GrassBlade {
+                position: [x, height, z],
+                angle,
+                scale,
+                phase,
+                burnt: false,
+            });
+        }
+        blades
+    }
+
+    fn uniform(settings: &SceneGrass, time: f32) -> GrassUniform {
+        let direction = cgmath::Vector2::from(settings.wind_direction);
+        let direction = if direction.magnitude2() > 0.0 {
+            direction.normalize()
+        } else {
+            direction
+        };
+        GrassUniform {
+            color: settings.color,
+            height: settings.height,
+            wind_direction: direction.into(),
+            wind_strength: settings.wind_strength,
+            wind_speed: settings.wind_speed,
+            width: settings.width,
+            time,
+            _padding: [0.0; 2],
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_mode: texture::DepthMode,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Grass Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: Some("vs_main"),
+                buffers: &[GrassRaw::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: Some("fs_main"),
+                targets: &HdrPipeline::scene_targets(color_format, Some(wgpu::BlendState::REPLACE)),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            // Blades are seen from both sides
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: depth_mode.compare(),
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.blades.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blades.is_empty()
+    }
+
+    // Burns the blades within `radius` of `position` across the ground, and
+    // returns how many weren't burnt already
+    pub fn burn(&mut self, position: cgmath::Point3<f32>, radius: f32) -> usize {
+        let mut burnt = 0;
+        for blade in self.blades.iter_mut().filter(|blade| !blade.burnt) {
+            let [x, y, z] = blade.position;
+            let (dx, dz) = (x - position.x, z - position.z);
+            if dx * dx + dz * dz <= radius * radius && (y - position.y).abs() <= radius + 1.0 {
+                blade.burnt = true;
+                burnt += 1;
+            }
+        }
+        if burnt > 0 {
+            self.dirty = true;
+        }
+        burnt
+    }
+
+    // Every blade grows back
+    pub fn regrow(&mut self) {
+        for blade in &mut self.blades {
+            blade.burnt = false;
+        }
+        self.dirty = true;
+    }
+
+    // Moves the wind along, and uploads the blades if any were burnt since
+    // the last call
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: f32) {
+        self.time += dt;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[Self::uniform(&self.settings, self.time)]),
+        );
+        if !self.dirty {
+            return;
+        }
+        let data = self
+            .blades
+            .iter()
+            .map(|blade| blade.to_raw())
+            .collect::<Vec<_>>();
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&data));
+        self.dirty = false;
+    }
+
+    // MSAA changes need a new pipeline, the blades are kept
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            &self.pipeline_layout,
+            &self.shader,
+            self.color_format,
+            sample_count,
+            self.depth_mode,
+        );
+    }
+
+    // Every blade, unless `frustum` can't see the field
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        lighting_bind_group: &'a wgpu::BindGroup,
+        ssao_bind_group: &'a wgpu::BindGroup,
+        frustum: &Frustum,
+    ) {
+        if self.blades.is_empty() || !frustum.intersects_sphere(&self.bounds) {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        render_pass.set_bind_group(2, lighting_bind_group, &[]);
+        render_pass.set_bind_group(3, ssao_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        render_pass.draw(0..BLADE_VERTICES, 0..self.blades.len() as u32);
+        crate::stats::count_draw();
+    }
+}
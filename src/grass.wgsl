@@ -0,0 +1,121 @@
+// ===== GRASS =====
+// One blade per instance, built from the vertex index as a strip of three
+// segments narrowing to a point. The wind bends the blades more the higher
+// up them it gets.
+#include "lighting.wgsl"
+#include "camera.wgsl"
+
+struct GrassUniform {
+    color: vec3<f32>,
+    height: f32,
+    wind_direction: vec2<f32>,
+    wind_strength: f32,
+    wind_speed: f32,
+    width: f32,
+    time: f32,
+};
+@group(0) @binding(0)
+var<uniform> grass: GrassUniform;
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Blurred screen-space ambient occlusion from the SSAO pass
+@group(3) @binding(0)
+var t_ssao: texture_2d<f32>;
+
+// What burnt blades are left
+const CHARRED: vec3<f32> = vec3<f32>(0.03, 0.025, 0.02);
+
+struct InstanceInput {
+    @location(0) position: vec3<f32>,
+    @location(1) angle: f32,
+    @location(2) scale: f32,
+    @location(3) phase: f32,
+    @location(4) burnt: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) view_depth: f32,
+    // From 0 at the root to 1 at the tip
+    @location(3) along: f32,
+    @location(4) burnt: f32,
+    @location(5) curr_clip: vec4<f32>,
+    @location(6) prev_clip: vec4<f32>,
+};
+
+// Same targets as the model shader
+struct SceneOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) velocity: vec4<f32>,
+    @location(2) linear_depth: f32,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32, blade: InstanceInput) -> VertexOutput {
+    // Pairs of vertices up the sides, then the tip on its own
+    let along = f32(vertex_index / 2u) / 3.0;
+    var side = f32(vertex_index % 2u) - 0.5;
+    if (vertex_index == 6u) {
+        side = 0.0;
+    }
+    let across = vec3<f32>(cos(blade.angle), 0.0, sin(blade.angle));
+    let facing = vec3<f32>(-across.z, 0.0, across.x);
+    let height = grass.height * blade.scale;
+
+    // Gusts rolling across the field, and each blade fluttering in them.
+    // Burnt stubs are too stiff to move.
+    let wind = grass.wind_direction;
+    let wave = dot(blade.position.xz, wind) * 0.35 - grass.time * grass.wind_speed;
+    let gust = sin(wave) * 0.5 + 0.5;
+    let flutter = sin(grass.time * grass.wind_speed * 3.0 + blade.phase) * 0.2;
+    let bend = grass.wind_strength * (gust + flutter) * along * along * (1.0 - blade.burnt);
+
+    let offset = across * side * grass.width * (1.0 - along)
+        + vec3<f32>(wind.x * bend, along * height, wind.y * bend);
+    let world_position = vec4<f32>(blade.position + offset, 1.0);
+
+    var out: VertexOutput;
+    out.world_position = world_position.xyz;
+    // Rounded towards the sky so the blades don't go dark edge on
+    out.world_normal = normalize(facing + vec3<f32>(0.0, 0.6, 0.0));
+    out.view_depth = -(camera.view * world_position).z;
+    out.along = along;
+    out.burnt = blade.burnt;
+    out.clip_position = camera.view_proj * world_position;
+    out.curr_clip = out.clip_position;
+    out.prev_clip = camera.prev_view_proj * world_position;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> SceneOutput {
+    var surface: Surface;
+    var normal = normalize(in.world_normal);
+    // The back faces the other way but still up
+    if (!front_facing) {
+        normal = vec3<f32>(-normal.x, normal.y, -normal.z);
+    }
+    surface.normal = normal;
+    // Darker down in the shade of the others
+    let green = grass.color * mix(0.4, 1.0, in.along);
+    surface.albedo = mix(green, CHARRED, in.burnt);
+    surface.alpha = 1.0;
+    surface.metallic = 0.0;
+    surface.roughness = 0.8;
+    surface.occlusion = textureLoad(t_ssao, vec2<i32>(in.clip_position.xy), 0).r;
+    surface.emissive = vec3<f32>(0.0);
+    let color = shade(surface, in.world_position, camera.view_position.xyz, in.view_depth);
+
+    // NDC to UV: halve, and flip y since UVs point down
+    let curr = in.curr_clip.xy / in.curr_clip.w;
+    let prev = in.prev_clip.xy / in.prev_clip.w;
+    var out: SceneOutput;
+    out.color = vec4<f32>(color, 1.0);
+    out.velocity = vec4<f32>((curr - prev) * vec2<f32>(0.5, -0.5), 0.0, 0.0);
+    out.linear_depth = in.view_depth;
+    return out;
+}
@@ -0,0 +1,156 @@
+use crate::{hdr::HdrPipeline, texture};
+
+// ===== GROUND GRID =====
+// An endless grid on the y = 0 plane with a line every unit, a heavier one
+// every ten, and the X and Z axes picked out in red and blue, plus short RGB
+// axes standing at the origin. Drawn in the main pass after the sky, tested
+// against the scene's depth so the model stands on it, but writes none of
+// its own so it never hides the fire.
+pub struct Grid {
+    pub enabled: bool,
+    pipeline_layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+    color_format: wgpu::TextureFormat,
+    depth_mode: texture::DepthMode,
+    plane_pipeline: wgpu::RenderPipeline,
+    axes_pipeline: wgpu::RenderPipeline,
+}
+
+impl Grid {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        depth_mode: texture::DepthMode,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Grid Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("grid.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Grid Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let (plane_pipeline, axes_pipeline) = Self::create_pipelines(
+            device,
+            &pipeline_layout,
+            &shader,
+            color_format,
+            sample_count,
+            depth_mode,
+        );
+
+        Self {
+            enabled: false,
+            pipeline_layout,
+            shader,
+            color_format,
+            depth_mode,
+            plane_pipeline,
+            axes_pipeline,
+        }
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_mode: texture::DepthMode,
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+        // Blended over the color, leaving velocity and linear depth to what's
+        // behind so post-processing doesn't take the grid for geometry
+        let [color, velocity, linear_depth] =
+            HdrPipeline::scene_targets(color_format, Some(wgpu::BlendState::ALPHA_BLENDING));
+        let targets = [
+            color,
+            velocity.map(|target| wgpu::ColorTargetState {
+                write_mask: wgpu::ColorWrites::empty(),
+                ..target
+            }),
+            linear_depth.map(|target| wgpu::ColorTargetState {
+                write_mask: wgpu::ColorWrites::empty(),
+                ..target
+            }),
+        ];
+        let create = |label, vs_main, fs_main, topology| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: Some(vs_main),
+                    buffers: &[], // Generated in the shader
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point: Some(fs_main),
+                    targets: &targets,
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: depth_mode.compare_or_equal(),
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+                cache: None,
+            })
+        };
+        (
+            create(
+                "Grid Pipeline",
+                "vs_plane",
+                "fs_plane",
+                wgpu::PrimitiveTopology::TriangleList,
+            ),
+            create(
+                "Grid Axes Pipeline",
+                "vs_axes",
+                "fs_axes",
+                wgpu::PrimitiveTopology::LineList,
+            ),
+        )
+    }
+
+    // MSAA changes need new pipelines
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        (self.plane_pipeline, self.axes_pipeline) = Self::create_pipelines(
+            device,
+            &self.pipeline_layout,
+            &self.shader,
+            self.color_format,
+            sample_count,
+            self.depth_mode,
+        );
+    }
+
+    pub fn render(
+        &self,
+        render_pass: &mut wgpu::RenderPass<'_>,
+        camera_bind_group: &wgpu::BindGroup,
+    ) {
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_pipeline(&self.plane_pipeline);
+        render_pass.draw(0..3, 0..1);
+        crate::stats::count_draw();
+        render_pass.set_pipeline(&self.axes_pipeline);
+        render_pass.draw(0..6, 0..1);
+        crate::stats::count_draw();
+    }
+}
@@ -0,0 +1,134 @@
+// ===== GROUND GRID =====
+// A fullscreen triangle that follows each pixel's view ray down to the
+// y = 0 plane, draws grid lines where it lands, and puts the pixel's depth
+// there so the scene's depth test can hide it behind the model.
+
+const MINOR_SPACING: f32 = 1.0;
+const MAJOR_SPACING: f32 = 10.0;
+// Length of the axes standing at the origin
+const AXIS_LENGTH: f32 = 1.0;
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    // Masked off by the pipeline, the grid leaves these alone
+    @location(1) velocity: vec4<f32>,
+    @location(2) linear_depth: f32,
+};
+
+struct PlaneFragmentOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) velocity: vec4<f32>,
+    @location(2) linear_depth: f32,
+    // Where the ray met the plane, rather than the triangle's
+    @builtin(frag_depth) depth: f32,
+};
+
+struct PlaneOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+};
+
+@vertex
+fn vs_plane(@builtin(vertex_index) vertex_index: u32) -> PlaneOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    let ndc = uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    var out: PlaneOutput;
+    out.clip_position = vec4<f32>(ndc, 0.5, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+// How much of a line every `spacing` units covers the pixel at `coord`,
+// about a pixel wide whatever the distance
+fn grid_lines(coord: vec2<f32>, spacing: f32) -> f32 {
+    let cell = coord / spacing;
+    let width = fwidth(cell);
+    let line = abs(fract(cell - 0.5) - 0.5) / width;
+    return 1.0 - min(min(line.x, line.y), 1.0);
+}
+
+// Same for the single line along one axis through the origin
+fn axis_line(coord: f32) -> f32 {
+    return 1.0 - min(abs(coord) / fwidth(coord), 1.0);
+}
+
+@fragment
+fn fs_plane(in: PlaneOutput) -> PlaneFragmentOutput {
+    // Between the clip planes along the pixel's ray, whichever way round
+    // the depth runs, so orthographic views work too
+    let a = camera.inv_view_proj * vec4<f32>(in.ndc, 0.0, 1.0);
+    let b = camera.inv_view_proj * vec4<f32>(in.ndc, 1.0, 1.0);
+    let start = a.xyz / a.w;
+    let end = b.xyz / b.w;
+    let t = -start.y / (end.y - start.y);
+    let position = start + (end - start) * t;
+
+    // Derivatives before anything's discarded
+    let minor = grid_lines(position.xz, MINOR_SPACING);
+    let major = grid_lines(position.xz, MAJOR_SPACING);
+    let x_axis = axis_line(position.z);
+    let z_axis = axis_line(position.x);
+    // Minor lines give way to the major ones once the cells get smaller than
+    // a few pixels, and everything fades out towards the horizon
+    let cells_per_pixel = length(fwidth(position.xz)) / MINOR_SPACING;
+    let minor_fade = 1.0 - smoothstep(0.1, 0.4, cells_per_pixel);
+    let horizon_fade = 1.0 - smoothstep(0.25, 1.0, t);
+
+    if (!(t >= 0.0 && t <= 1.0)) {
+        discard; // The plane's behind the camera, past the far plane, or edge on
+    }
+
+    var color = vec4<f32>(0.5, 0.5, 0.5, max(minor * 0.35 * minor_fade, major * 0.7));
+    color = mix(color, vec4<f32>(0.9, 0.1, 0.1, 1.0), x_axis);
+    color = mix(color, vec4<f32>(0.15, 0.3, 1.0, 1.0), z_axis);
+    color.a *= horizon_fade;
+    if (color.a <= 0.0) {
+        discard;
+    }
+
+    let clip = camera.view_proj * vec4<f32>(position, 1.0);
+    var out: PlaneFragmentOutput;
+    out.color = color;
+    out.velocity = vec4<f32>(0.0);
+    out.linear_depth = 0.0;
+    out.depth = clip.z / clip.w;
+    return out;
+}
+
+struct AxesOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+};
+
+// Three lines out from the origin, two vertices each
+@vertex
+fn vs_axes(@builtin(vertex_index) vertex_index: u32) -> AxesOutput {
+    var direction = vec3<f32>(0.0);
+    direction[vertex_index / 2u] = 1.0;
+    let position = direction * f32(vertex_index % 2u) * AXIS_LENGTH;
+    var out: AxesOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
+    out.color = direction;
+    return out;
+}
+
+@fragment
+fn fs_axes(in: AxesOutput) -> FragmentOutput {
+    var out: FragmentOutput;
+    out.color = vec4<f32>(in.color, 1.0);
+    out.velocity = vec4<f32>(0.0);
+    out.linear_depth = 0.0;
+    return out;
+}
@@ -0,0 +1,352 @@
+use std::any::Any;
+
+use crate::post_process::{FullscreenEffect, PostEffect};
+use crate::{color, texture};
+
+// ===== TONEMAPPER =====
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Tonemapper {
+    Aces,
+    Reinhard,
+}
+
+impl Tonemapper {
+    pub fn next(self) -> Self {
+        match self {
+            Tonemapper::Aces => Tonemapper::Reinhard,
+            Tonemapper::Reinhard => Tonemapper::Aces,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemapUniform {
+    exposure: f32,
+    tonemapper: u32,
+    encode_srgb: u32, // Output isn't sRGB, apply the curve in the shader
+    _padding: u32,
+}
+
+// ===== HDR PIPELINE =====
+// The scene renders into a floating point target so bright things like the
+// additive fire can go past 1.0; the `Tonemap` effect at the end of the
+// post-process chain maps it onto the surface. Next to the color the scene
+// pass writes each pixel's screen-space velocity for motion blur and its
+// linear view depth for depth of field.
+pub struct HdrPipeline {
+    format: wgpu::TextureFormat,
+    texture: texture::Texture,
+    velocity: wgpu::TextureView,
+    linear_depth: wgpu::TextureView,
+    // Multisampled targets that resolve into the three above when MSAA is on
+    msaa_view: Option<wgpu::TextureView>,
+    msaa_velocity: Option<wgpu::TextureView>,
+    msaa_linear_depth: Option<wgpu::TextureView>,
+    sample_count: u32,
+}
+
+impl HdrPipeline {
+    // Motion in UV units since the last frame
+    pub const VELOCITY_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+    // Distance along the view direction, 0.0 where nothing was drawn. The depth
+    // buffer itself can't be filtered, and can't be bound at all with MSAA.
+    pub const LINEAR_DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R16Float;
+
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        Self::with_size(device, config.width, config.height, format, sample_count)
+    }
+
+    // Targets that don't follow the surface, see `OffscreenTarget`
+    pub fn with_size(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let texture = Self::create_texture(device, width, height, format);
+        let velocity = Self::create_aux_view(device, Self::VELOCITY_FORMAT, width, height, 1);
+        let linear_depth =
+            Self::create_aux_view(device, Self::LINEAR_DEPTH_FORMAT, width, height, 1);
+
+        let mut hdr = Self {
+            format,
+            texture,
+            velocity,
+            linear_depth,
+            msaa_view: None,
+            msaa_velocity: None,
+            msaa_linear_depth: None,
+            sample_count,
+        };
+        hdr.set_sample_count(device, sample_count);
+        hdr
+    }
+
+    fn create_texture(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> texture::Texture {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Hdr Texture"),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            // Copied out by passes that need what's been drawn so far, like water
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        texture::Texture {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    fn create_msaa_view(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Option<wgpu::TextureView> {
+        if sample_count <= 1 {
+            return None;
+        }
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Hdr Msaa Texture"),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+    }
+
+    // Velocity and linear depth targets
+    fn create_aux_view(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> wgpu::TextureView {
+        // Multisampled copies are only ever resolved, never bound
+        let usage = if sample_count > 1 {
+            wgpu::TextureUsages::RENDER_ATTACHMENT
+        } else {
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+        };
+        device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("Hdr Aux Texture"),
+                size: wgpu::Extent3d {
+                    width: width.max(1),
+                    height: height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage,
+                view_formats: &[],
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    // The HDR target has to match the surface size
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.texture = Self::create_texture(device, width, height, self.format);
+        self.velocity = Self::create_aux_view(device, Self::VELOCITY_FORMAT, width, height, 1);
+        self.linear_depth =
+            Self::create_aux_view(device, Self::LINEAR_DEPTH_FORMAT, width, height, 1);
+        self.set_sample_count(device, self.sample_count);
+    }
+
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        let size = self.texture.texture.size();
+        self.sample_count = sample_count;
+        self.msaa_view =
+            Self::create_msaa_view(device, size.width, size.height, self.format, sample_count);
+        let msaa_aux = |format| {
+            (sample_count > 1).then(|| {
+                Self::create_aux_view(device, format, size.width, size.height, sample_count)
+            })
+        };
+        self.msaa_velocity = msaa_aux(Self::VELOCITY_FORMAT);
+        self.msaa_linear_depth = msaa_aux(Self::LINEAR_DEPTH_FORMAT);
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    // Resolved scene color, what post-processing reads
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.texture.view
+    }
+
+    // The texture behind `view`, for copying from
+    pub fn texture(&self) -> &wgpu::Texture {
+        &self.texture.texture
+    }
+
+    // View and resolve target for the scene pass's color attachment
+    pub fn color_attachment(&self) -> (&wgpu::TextureView, Option<&wgpu::TextureView>) {
+        match &self.msaa_view {
+            Some(msaa_view) => (msaa_view, Some(&self.texture.view)),
+            None => (&self.texture.view, None),
+        }
+    }
+
+    // Resolved velocity, what motion blur reads
+    pub fn velocity_view(&self) -> &wgpu::TextureView {
+        &self.velocity
+    }
+
+    // View and resolve target for the scene pass's velocity attachment
+    pub fn velocity_attachment(&self) -> (&wgpu::TextureView, Option<&wgpu::TextureView>) {
+        match &self.msaa_velocity {
+            Some(msaa_velocity) => (msaa_velocity, Some(&self.velocity)),
+            None => (&self.velocity, None),
+        }
+    }
+
+    // Resolved linear depth, what depth of field reads
+    pub fn linear_depth_view(&self) -> &wgpu::TextureView {
+        &self.linear_depth
+    }
+
+    pub fn linear_depth_attachment(&self) -> (&wgpu::TextureView, Option<&wgpu::TextureView>) {
+        match &self.msaa_linear_depth {
+            Some(msaa_linear_depth) => (msaa_linear_depth, Some(&self.linear_depth)),
+            None => (&self.linear_depth, None),
+        }
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    // Color, velocity and linear depth targets for pipelines drawn in the scene
+    // pass. All get the same blend so WebGL, which can't blend targets
+    // independently, works too; shaders that shouldn't touch a pixel's velocity
+    // or depth write zero into an additive blend.
+    pub fn scene_targets(
+        color_format: wgpu::TextureFormat,
+        blend: Option<wgpu::BlendState>,
+    ) -> [Option<wgpu::ColorTargetState>; 3] {
+        [
+            color_format,
+            Self::VELOCITY_FORMAT,
+            Self::LINEAR_DEPTH_FORMAT,
+        ]
+        .map(|format| {
+            Some(wgpu::ColorTargetState {
+                format,
+                blend,
+                write_mask: wgpu::ColorWrites::ALL,
+            })
+        })
+    }
+}
+
+// ===== TONEMAP =====
+// Last effect of the post-process chain, writes the displayable result
+pub struct Tonemap {
+    pub exposure: f32,
+    pub tonemapper: Tonemapper,
+    encode_srgb: bool,
+    effect: FullscreenEffect,
+}
+
+impl Tonemap {
+    pub fn new(device: &wgpu::Device, output_format: wgpu::TextureFormat) -> Self {
+        Self {
+            exposure: 1.0,
+            tonemapper: Tonemapper::Aces,
+            encode_srgb: color::needs_shader_encode(output_format),
+            effect: FullscreenEffect::new(
+                device,
+                "Tonemap",
+                wgpu::ShaderSource::Wgsl(
+                    format!(
+                        "{}\n{}",
+                        include_str!("color.wgsl"),
+                        include_str!("hdr.wgsl")
+                    )
+                    .into(),
+                ),
+                output_format,
+                std::mem::size_of::<TonemapUniform>() as u64,
+            ),
+        }
+    }
+}
+
+impl PostEffect for Tonemap {
+    fn name(&self) -> &'static str {
+        "tonemap"
+    }
+
+    fn update(&self, queue: &wgpu::Queue) {
+        self.effect.write_uniform(
+            queue,
+            bytemuck::cast_slice(&[TonemapUniform {
+                exposure: self.exposure,
+                tonemapper: self.tonemapper as u32,
+                encode_srgb: self.encode_srgb as u32,
+                _padding: 0,
+            }]),
+        );
+    }
+
+    fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+    ) {
+        self.effect.render(device, encoder, input, output);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
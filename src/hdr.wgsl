@@ -0,0 +1,63 @@
+// ===== TONEMAPPING =====
+// Maps the HDR scene color down to the displayable range of the surface.
+// color.wgsl is prepended for the sRGB encode.
+
+struct TonemapUniform {
+    exposure: f32,
+    tonemapper: u32, // 0 = ACES, 1 = Reinhard
+    encode_srgb: u32,
+};
+
+@group(0) @binding(0)
+var t_hdr: texture_2d<f32>;
+@group(0) @binding(1)
+var s_hdr: sampler;
+@group(0) @binding(2)
+var<uniform> tonemap: TonemapUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// Three vertices covering the whole screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+// Narkowicz's fit of the ACES filmic curve
+fn aces_tone_map(hdr: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((hdr * (a * hdr + b)) / (hdr * (c * hdr + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn reinhard_tone_map(hdr: vec3<f32>) -> vec3<f32> {
+    return hdr / (hdr + vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let hdr = textureSample(t_hdr, s_hdr, in.uv);
+    let exposed = hdr.rgb * tonemap.exposure;
+
+    var sdr: vec3<f32>;
+    if (tonemap.tonemapper == 0u) {
+        sdr = aces_tone_map(exposed);
+    } else {
+        sdr = reinhard_tone_map(exposed);
+    }
+    // sRGB targets apply the curve on write, anything else needs it here
+    if (tonemap.encode_srgb != 0u) {
+        sdr = linear_to_srgb(sdr);
+    }
+    return vec4<f32>(sdr, 1.0);
+}
@@ -0,0 +1,512 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::model::BoundingSphere;
+
+// Objects past this many are never culled
+pub const MAX_OCCLUSION_OBJECTS: usize = 1024;
+const PYRAMID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullUniform {
+    view_proj: [[f32; 4]; 4],
+    view: [[f32; 4]; 4],
+    viewport: [f32; 2],
+    mip_count: u32,
+    object_count: u32,
+    znear: f32,
+    _padding: [f32; 3],
+}
+
+// Farthest-depth mip chain, rebuilt with the depth target
+struct Pyramid {
+    texture: wgpu::Texture,     // Every level, what the cull pass reads
+    levels: Vec<wgpu::Texture>, // One per mip, what the downsample writes
+    bind_groups: Vec<wgpu::BindGroup>,
+    cull_bind_group: wgpu::BindGroup,
+    mip_sizes: Vec<(u32, u32)>,
+}
+
+// Where the visibility copy for the CPU is at
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Readback {
+    Idle,
+    Copied,  // Recorded this frame, mapped once submitted
+    Mapping, // Waiting for the GPU to finish
+}
+
+// ===== OCCLUSION CULLING =====
+// Builds a Hi-Z pyramid from the frame's linear depth with compute and tests
+// bounding spheres against it (see hiz.wgsl and hiz_cull.wgsl). Results come
+// back to the CPU a frame or two later, so an object that comes out from
+// behind another can pop in late. Needs compute shaders, which WebGL doesn't have.
+pub struct OcclusionCuller {
+    pub enabled: bool,
+    pyramid_layout: wgpu::BindGroupLayout,
+    cull_layout: wgpu::BindGroupLayout,
+    pyramid: Pyramid,
+    init_pipeline: wgpu::ComputePipeline,
+    downsample_pipeline: wgpu::ComputePipeline,
+    cull_pipeline: wgpu::ComputePipeline,
+    uniform_buffer: wgpu::Buffer,
+    sphere_buffer: wgpu::Buffer,
+    visibility_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    object_count: usize,
+    readback: Readback,
+    mapped: Arc<AtomicBool>,
+    visible: Vec<bool>, // Latest results from the GPU
+}
+
+impl OcclusionCuller {
+    pub fn is_supported(device: &wgpu::Device) -> bool {
+        let limits = device.limits();
+        limits.max_compute_workgroups_per_dimension > 0
+            && limits.max_storage_buffers_per_shader_stage > 0
+            && limits.max_storage_textures_per_shader_stage > 0
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        linear_depth: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let pyramid_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: PYRAMID_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("hiz_pyramid_bind_group_layout"),
+        });
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let cull_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(1, true),
+                storage_entry(2, false),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("hiz_cull_bind_group_layout"),
+        });
+
+        let create_pipeline = |label, layout: &wgpu::BindGroupLayout, source, entry_point| {
+            let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(label),
+                source,
+            });
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+        let pyramid_source = || wgpu::ShaderSource::Wgsl(include_str!("hiz.wgsl").into());
+        let init_pipeline = create_pipeline(
+            "Hi-Z Init Pipeline",
+            &pyramid_layout,
+            pyramid_source(),
+            "init",
+        );
+        let downsample_pipeline = create_pipeline(
+            "Hi-Z Downsample Pipeline",
+            &pyramid_layout,
+            pyramid_source(),
+            "downsample",
+        );
+        let cull_pipeline = create_pipeline(
+            "Hi-Z Cull Pipeline",
+            &cull_layout,
+            wgpu::ShaderSource::Wgsl(include_str!("hiz_cull.wgsl").into()),
+            "cull_main",
+        );
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Hi-Z Cull Uniform Buffer"),
+            size: std::mem::size_of::<CullUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let sphere_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Hi-Z Sphere Buffer"),
+            size: (MAX_OCCLUSION_OBJECTS * std::mem::size_of::<[f32; 4]>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let visibility_size = (MAX_OCCLUSION_OBJECTS * std::mem::size_of::<u32>()) as u64;
+        let visibility_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Hi-Z Visibility Buffer"),
+            size: visibility_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Hi-Z Readback Buffer"),
+            size: visibility_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let pyramid = Self::create_pyramid(
+            device,
+            &pyramid_layout,
+            &cull_layout,
+            &uniform_buffer,
+            &sphere_buffer,
+            &visibility_buffer,
+            linear_depth,
+            width,
+            height,
+        );
+
+        Self {
+            enabled: true,
+            pyramid_layout,
+            cull_layout,
+            pyramid,
+            init_pipeline,
+            downsample_pipeline,
+            cull_pipeline,
+            uniform_buffer,
+            sphere_buffer,
+            visibility_buffer,
+            readback_buffer,
+            object_count: 0,
+            readback: Readback::Idle,
+            mapped: Arc::new(AtomicBool::new(false)),
+            visible: Vec::new(),
+        }
+    }
+
+    // The pyramid textures and every bind group that points at them or the depth target
+    #[allow(clippy::too_many_arguments)]
+    fn create_pyramid(
+        device: &wgpu::Device,
+        pyramid_layout: &wgpu::BindGroupLayout,
+        cull_layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        sphere_buffer: &wgpu::Buffer,
+        visibility_buffer: &wgpu::Buffer,
+        linear_depth: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> Pyramid {
+        let (width, height) = (width.max(1), height.max(1));
+        let mip_count = 32 - width.max(height).leading_zeros();
+        let mip_sizes = (0..mip_count)
+            .map(|mip| ((width >> mip).max(1), (height >> mip).max(1)))
+            .collect::<Vec<_>>();
+        let create_texture = |label, (width, height), mip_level_count, usage| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: PYRAMID_FORMAT,
+                usage,
+                view_formats: &[],
+            })
+        };
+        // Every level is built in a texture of its own and then copied into the
+        // mip chain. GL can't write one mip of a texture while reading another.
+        let levels = mip_sizes
+            .iter()
+            .map(|&size| {
+                create_texture(
+                    "Hi-Z Level",
+                    size,
+                    1,
+                    wgpu::TextureUsages::STORAGE_BINDING
+                        | wgpu::TextureUsages::TEXTURE_BINDING
+                        | wgpu::TextureUsages::COPY_SRC,
+                )
+            })
+            .collect::<Vec<_>>();
+        let texture = create_texture(
+            "Hi-Z Pyramid",
+            (width, height),
+            mip_count,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        );
+        let level_views = levels
+            .iter()
+            .map(|level| level.create_view(&wgpu::TextureViewDescriptor::default()))
+            .collect::<Vec<_>>();
+
+        // Each level reads the one before it, level 0 reads the depth target
+        let bind_groups = (0..level_views.len())
+            .map(|mip| {
+                let source = if mip == 0 {
+                    linear_depth
+                } else {
+                    &level_views[mip - 1]
+                };
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: pyramid_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(source),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(&level_views[mip]),
+                        },
+                    ],
+                    label: Some("hiz_pyramid_bind_group"),
+                })
+            })
+            .collect();
+
+        let pyramid_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let cull_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: cull_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: sphere_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: visibility_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&pyramid_view),
+                },
+            ],
+            label: Some("hiz_cull_bind_group"),
+        });
+
+        Pyramid {
+            texture,
+            levels,
+            bind_groups,
+            cull_bind_group,
+            mip_sizes,
+        }
+    }
+
+    // The depth target is recreated with the HDR one, so hand it over again
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        linear_depth: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        self.pyramid = Self::create_pyramid(
+            device,
+            &self.pyramid_layout,
+            &self.cull_layout,
+            &self.uniform_buffer,
+            &self.sphere_buffer,
+            &self.visibility_buffer,
+            linear_depth,
+            width,
+            height,
+        );
+    }
+
+    // Spheres to test this frame, in world space, seen from the given camera
+    pub fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        view_proj: cgmath::Matrix4<f32>,
+        view: cgmath::Matrix4<f32>,
+        znear: f32,
+        spheres: &[BoundingSphere],
+    ) {
+        self.object_count = spheres.len().min(MAX_OCCLUSION_OBJECTS);
+        let spheres = spheres[..self.object_count]
+            .iter()
+            .map(|sphere| {
+                [
+                    sphere.center.x,
+                    sphere.center.y,
+                    sphere.center.z,
+                    sphere.radius,
+                ]
+            })
+            .collect::<Vec<_>>();
+        queue.write_buffer(&self.sphere_buffer, 0, bytemuck::cast_slice(&spheres));
+        let (width, height) = self.pyramid.mip_sizes[0];
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[CullUniform {
+                view_proj: view_proj.into(),
+                view: view.into(),
+                viewport: [width as f32, height as f32],
+                mip_count: self.pyramid.mip_sizes.len() as u32,
+                object_count: self.object_count as u32,
+                znear,
+                _padding: [0.0; 3],
+            }]),
+        );
+    }
+
+    // Builds the pyramid from this frame's depth and tests the spheres against it
+    pub fn render(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Hi-Z Pyramid Pass"),
+            timestamp_writes: None,
+        });
+        for (mip, bind_group) in self.pyramid.bind_groups.iter().enumerate() {
+            let (width, height) = self.pyramid.mip_sizes[mip];
+            pass.set_pipeline(if mip == 0 {
+                &self.init_pipeline
+            } else {
+                &self.downsample_pipeline
+            });
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(width.div_ceil(8), height.div_ceil(8), 1);
+        }
+        drop(pass);
+
+        for (mip, level) in self.pyramid.levels.iter().enumerate() {
+            let (width, height) = self.pyramid.mip_sizes[mip];
+            encoder.copy_texture_to_texture(
+                level.as_image_copy(),
+                wgpu::TexelCopyTextureInfo {
+                    texture: &self.pyramid.texture,
+                    mip_level: mip as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        if self.object_count > 0 {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Hi-Z Cull Pass"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.cull_pipeline);
+            pass.set_bind_group(0, &self.pyramid.cull_bind_group, &[]);
+            pass.dispatch_workgroups((self.object_count as u32).div_ceil(64), 1, 1);
+        }
+
+        // Only one copy is in flight at a time, frames in between go unread
+        if self.readback == Readback::Idle && self.object_count > 0 {
+            let size = (self.object_count * std::mem::size_of::<u32>()) as u64;
+            encoder.copy_buffer_to_buffer(
+                &self.visibility_buffer,
+                0,
+                &self.readback_buffer,
+                0,
+                size,
+            );
+            self.readback = Readback::Copied;
+        }
+    }
+
+    // Call once the frame's commands are submitted
+    pub fn after_submit(&mut self) {
+        if self.readback != Readback::Copied {
+            return;
+        }
+        let mapped = self.mapped.clone();
+        self.readback_buffer
+            .map_async(wgpu::MapMode::Read, .., move |result| {
+                if result.is_ok() {
+                    mapped.store(true, Ordering::Release);
+                }
+            });
+        self.readback = Readback::Mapping;
+    }
+
+    // Picks up results the GPU has finished since the last call
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        if self.readback != Readback::Mapping {
+            return;
+        }
+        let _ = device.poll(wgpu::PollType::Poll);
+        if !self.mapped.swap(false, Ordering::Acquire) {
+            return;
+        }
+        {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            let flags: &[u32] = bytemuck::cast_slice(&data);
+            self.visible = flags[..self.object_count]
+                .iter()
+                .map(|&flag| flag != 0)
+                .collect();
+        }
+        self.readback_buffer.unmap();
+        self.readback = Readback::Idle;
+    }
+
+    // Anything without a result yet counts as visible
+    pub fn is_visible(&self, index: usize) -> bool {
+        !self.enabled || self.visible.get(index).copied().unwrap_or(true)
+    }
+}
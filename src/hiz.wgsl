@@ -0,0 +1,44 @@
+// ===== HI-Z PYRAMID =====
+// Mip chain of the scene's linear depth where every texel holds the farthest
+// depth of the pixels under it. If the nearest point of an object is further
+// away than that, whatever covers those pixels hides the object.
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var t_output: texture_storage_2d<r32float, write>;
+
+// Pixels nothing was drawn to hide nothing
+const FAR: f32 = 1e30;
+
+// Mip 0, copied from the linear depth target
+@compute @workgroup_size(8, 8)
+fn init(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(t_output);
+    if (any(id.xy >= size)) {
+        return;
+    }
+    let depth = textureLoad(t_source, vec2<i32>(id.xy), 0).r;
+    textureStore(t_output, vec2<i32>(id.xy), vec4<f32>(select(depth, FAR, depth == 0.0)));
+}
+
+// Every other mip, from the one above it
+@compute @workgroup_size(8, 8)
+fn downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(t_output);
+    if (any(id.xy >= size)) {
+        return;
+    }
+    let source_size = textureDimensions(t_source);
+    // With an odd source size the last texel also covers the leftover row or column
+    let leftover = select(vec2<u32>(0u), source_size & vec2<u32>(1u), id.xy == size - 1u);
+    let start = id.xy * 2u;
+    let end = min(start + 2u + leftover, source_size);
+    var farthest = 0.0;
+    for (var y = start.y; y < end.y; y += 1u) {
+        for (var x = start.x; x < end.x; x += 1u) {
+            farthest = max(farthest, textureLoad(t_source, vec2<i32>(vec2<u32>(x, y)), 0).r);
+        }
+    }
+    textureStore(t_output, vec2<i32>(id.xy), vec4<f32>(farthest));
+}
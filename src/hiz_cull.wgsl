@@ -0,0 +1,74 @@
+// ===== OCCLUSION TEST =====
+// One thread per bounding sphere. The sphere's screen rectangle picks the mip
+// of the Hi-Z pyramid where it covers a texel or two, and the sphere is hidden
+// when its nearest point is behind the farthest depth in that rectangle.
+
+struct CullUniform {
+    view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    viewport: vec2<f32>, // Size of mip 0
+    mip_count: u32,
+    object_count: u32,
+    znear: f32,
+};
+@group(0) @binding(0)
+var<uniform> cull: CullUniform;
+// World space, xyz = center and w = radius
+@group(0) @binding(1)
+var<storage, read> spheres: array<vec4<f32>>;
+// 1 for visible, 0 for hidden
+@group(0) @binding(2)
+var<storage, read_write> visibility: array<u32>;
+@group(0) @binding(3)
+var t_pyramid: texture_2d<f32>;
+
+fn is_visible(sphere: vec4<f32>) -> bool {
+    let view_center = cull.view * vec4<f32>(sphere.xyz, 1.0);
+    let nearest = -view_center.z - sphere.w;
+    // The corners of the box around the sphere reach sqrt(3) radii out, and
+    // all of them have to be in front of the camera to project
+    if (-view_center.z - sphere.w * 1.7321 <= cull.znear) {
+        return true;
+    }
+
+    var uv_min = vec2<f32>(1.0);
+    var uv_max = vec2<f32>(0.0);
+    for (var i = 0u; i < 8u; i += 1u) {
+        let offset = vec3<f32>(
+            select(-1.0, 1.0, (i & 1u) != 0u),
+            select(-1.0, 1.0, (i & 2u) != 0u),
+            select(-1.0, 1.0, (i & 4u) != 0u),
+        );
+        let clip = cull.view_proj * vec4<f32>(sphere.xyz + offset * sphere.w, 1.0);
+        let uv = clip.xy / clip.w * vec2<f32>(0.5, -0.5) + 0.5;
+        uv_min = min(uv_min, uv);
+        uv_max = max(uv_max, uv);
+    }
+    uv_min = clamp(uv_min, vec2<f32>(0.0), vec2<f32>(1.0));
+    uv_max = clamp(uv_max, vec2<f32>(0.0), vec2<f32>(1.0));
+    if (any(uv_min >= uv_max)) {
+        return true; // Off screen, that's for frustum culling to decide
+    }
+
+    let extent = (uv_max - uv_min) * cull.viewport;
+    let mip = min(u32(ceil(log2(max(max(extent.x, extent.y), 1.0)))), cull.mip_count - 1u);
+    let mip_size = textureDimensions(t_pyramid, mip);
+    // One texel of margin, mips don't line up exactly with odd sizes
+    let lo = max(vec2<i32>(uv_min * vec2<f32>(mip_size)) - 1, vec2<i32>(0));
+    let hi = min(vec2<i32>(uv_max * vec2<f32>(mip_size)) + 1, vec2<i32>(mip_size) - 1);
+    var farthest = 0.0;
+    for (var y = lo.y; y <= hi.y; y += 1) {
+        for (var x = lo.x; x <= hi.x; x += 1) {
+            farthest = max(farthest, textureLoad(t_pyramid, vec2<i32>(x, y), i32(mip)).r);
+        }
+    }
+    return nearest <= farthest;
+}
+
+@compute @workgroup_size(64)
+fn cull_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= cull.object_count) {
+        return;
+    }
+    visibility[id.x] = u32(is_visible(spheres[id.x]));
+}
@@ -0,0 +1,176 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use notify::Watcher;
+
+// Editors save in a few steps (truncate, write, rename), so changes are only
+// reported once the files have been quiet this long
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+// Paths created or modified under a directory, as the OS reports them. The
+// watching happens on notify's own thread, the render loop only drains the
+// channel.
+struct FileEvents {
+    // Stops watching when dropped
+    _watcher: notify::RecommendedWatcher,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+    changed: BTreeSet<PathBuf>,
+    last_event: Instant,
+}
+
+impl FileEvents {
+    fn new(dir: &Path, mode: notify::RecursiveMode) -> anyhow::Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher
+            .watch(dir, mode)
+            .with_context(|| format!("Couldn't watch {}", dir.display()))?;
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            changed: BTreeSet::new(),
+            last_event: Instant::now(),
+        })
+    }
+
+    // Everything that changed since the last call, once it's settled
+    fn poll(&mut self) -> BTreeSet<PathBuf> {
+        for event in self.events.try_iter() {
+            match event {
+                Ok(event) if event.kind.is_create() || event.kind.is_modify() => {
+                    self.changed.extend(event.paths);
+                    self.last_event = Instant::now();
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("File watcher: {}", e),
+            }
+        }
+        if self.last_event.elapsed() < SETTLE_TIME {
+            return BTreeSet::new();
+        }
+        std::mem::take(&mut self.changed)
+    }
+}
+
+// ===== SHADER HOT-RELOAD =====
+// Watches shader sources in src/ through the OS's file notifications, so
+// edits show up without restarting. The built-in copies from include_str!
+// stay the starting point; only a file that changed afterwards is read back
+// from disk. Native only, the web build has no src/ to look at.
+pub struct ShaderWatcher {
+    names: Vec<&'static str>,
+    events: Option<FileEvents>,
+}
+
+impl ShaderWatcher {
+    // `names` are file names in src/, like "fire_shader.wgsl"
+    pub fn new(names: &[&'static str]) -> Self {
+        // The directory rather than each file, editors that save by renaming
+        // over the old file would end the watch on it
+        let events = FileEvents::new(&Self::path(""), notify::RecursiveMode::NonRecursive)
+            .map_err(|e| tracing::warn!("Not watching shaders: {:#}", e))
+            .ok();
+        Self {
+            names: names.to_vec(),
+            events,
+        }
+    }
+
+    fn path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("src")
+            .join(name)
+    }
+
+    // Files that changed since the last call. Doesn't touch the disk, so it
+    // can be called every frame.
+    pub fn poll(&mut self) -> Vec<&'static str> {
+        let Some(events) = &mut self.events else {
+            return Vec::new();
+        };
+        let changed = events.poll();
+        self.names
+            .iter()
+            .copied()
+            .filter(|name| {
+                changed
+                    .iter()
+                    .any(|path| path.file_name().is_some_and(|file| file == *name))
+            })
+            .collect()
+    }
+
+    pub fn read(name: &str) -> anyhow::Result<String> {
+        let path = Self::path(name);
+        std::fs::read_to_string(&path).with_context(|| format!("Couldn't read {}", path.display()))
+    }
+}
+
+// ===== ASSET HOT-RELOAD =====
+// Watches everything under res/ the same way, in whichever directory the
+// loaders resolve it to. Native only, like the shader watcher.
+pub struct AssetWatcher {
+    // res/ and what's happening under it
+    watched: Option<(PathBuf, FileEvents)>,
+}
+
+impl Default for AssetWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AssetWatcher {
+    pub fn new() -> Self {
+        let watched = crate::resources::res_dir()
+            .map_err(anyhow::Error::from)
+            .and_then(|dir| {
+                let events = FileEvents::new(&dir, notify::RecursiveMode::Recursive)?;
+                Ok((dir, events))
+            })
+            .map_err(|e| tracing::warn!("Not watching assets: {:#}", e))
+            .ok();
+        Self { watched }
+    }
+
+    // Files in res/ that are new or changed since the last call, relative to
+    // it with '/' separators. Deleted files aren't reported, the loaders have
+    // their own fallbacks for what's missing.
+    pub fn poll(&mut self) -> Vec<String> {
+        let Some((dir, events)) = &mut self.watched else {
+            return Vec::new();
+        };
+        events
+            .poll()
+            .iter()
+            // Directories show up too when something's added to them
+            .filter(|path| !path.is_dir())
+            .filter_map(|path| path.strip_prefix(&*dir).ok())
+            .map(|relative| {
+                relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/")
+            })
+            .collect()
+    }
+}
+
+// Runs `create` in a validation error scope, so a broken shader or pipeline
+// comes back as an error instead of hitting the device's uncaptured error
+// handler, which panics
+pub fn catch_validation<T>(
+    device: &wgpu::Device,
+    create: impl FnOnce() -> T,
+) -> Result<T, wgpu::Error> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = create();
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(error) => Err(error),
+        None => Ok(value),
+    }
+}
@@ -0,0 +1,230 @@
+use wgpu::util::DeviceExt;
+
+use crate::frustum::Frustum;
+use crate::material_array::MaterialArray;
+use crate::model::{BoundingSphere, Model};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullUniform {
+    planes: [[f32; 4]; 6],
+    object_count: u32,
+    _padding: [u32; 3],
+}
+
+// ===== GPU-DRIVEN DRAWS =====
+// Every mesh of a model drawn once per instance, with one indirect draw per
+// pair in a storage buffer. A compute pass culls them against the frustum by
+// zeroing the instance count of the ones outside (see indirect_cull.wgsl), and
+// each mesh then goes out as a single `multi_draw_indexed_indirect`. The CPU
+// never looks at individual objects, so the cost stays flat with thousands of
+// them. Draws are laid out mesh by mesh, `draws[mesh * instances + instance]`.
+pub struct IndirectDraws {
+    pub enabled: bool,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::ComputePipeline,
+    uniform_buffer: wgpu::Buffer,
+    draw_buffer: wgpu::Buffer,
+    instance_count: u32,
+    object_count: u32,
+}
+
+impl IndirectDraws {
+    // Non-zero `first_instance` in the draws needs this
+    pub const REQUIRED_FEATURES: wgpu::Features = wgpu::Features::INDIRECT_FIRST_INSTANCE;
+
+    pub fn is_supported(adapter: &wgpu::Adapter, device: &wgpu::Device) -> bool {
+        let downlevel = adapter.get_downlevel_capabilities().flags;
+        downlevel.contains(
+            wgpu::DownlevelFlags::INDIRECT_EXECUTION | wgpu::DownlevelFlags::COMPUTE_SHADERS,
+        ) && device.features().contains(Self::REQUIRED_FEATURES)
+            && device.limits().max_storage_buffers_per_shader_stage >= 2
+    }
+
+    // `bounds` holds the world-space sphere of every mesh for every instance,
+    // in the same mesh by mesh order as the draws
+    pub fn new(
+        device: &wgpu::Device,
+        model: &Model,
+        instance_count: u32,
+        bounds: &[BoundingSphere],
+    ) -> Self {
+        let draws = model
+            .meshes
+            .iter()
+            .flat_map(|mesh| {
+                (0..instance_count).map(|instance| wgpu::util::DrawIndexedIndirectArgs {
+                    index_count: mesh.num_elements,
+                    instance_count: 1,
+                    first_index: 0,
+                    base_vertex: 0,
+                    first_instance: instance,
+                })
+            })
+            .flat_map(|args| args.as_bytes().to_vec())
+            .collect::<Vec<_>>();
+        let object_count = model.meshes.len() as u32 * instance_count;
+        assert_eq!(bounds.len(), object_count as usize);
+
+        let draw_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Indirect Draw Buffer"),
+            contents: &draws,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
+        });
+        let spheres = bounds
+            .iter()
+            .map(|sphere| {
+                [
+                    sphere.center.x,
+                    sphere.center.y,
+                    sphere.center.z,
+                    sphere.radius,
+                ]
+            })
+            .collect::<Vec<_>>();
+        let sphere_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Indirect Sphere Buffer"),
+            contents: bytemuck::cast_slice(&spheres),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Indirect Cull Uniform Buffer"),
+            size: std::mem::size_of::<CullUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(1, true),
+                storage_entry(2, false),
+            ],
+            label: Some("indirect_cull_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: sphere_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: draw_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("indirect_cull_bind_group"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Indirect Cull Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("indirect_cull.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Indirect Cull Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Indirect Cull Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("cull_main"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        Self {
+            enabled: false,
+            bind_group,
+            pipeline,
+            uniform_buffer,
+            draw_buffer,
+            instance_count,
+            object_count,
+        }
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, frustum: &Frustum) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[CullUniform {
+                planes: frustum.planes(),
+                object_count: self.object_count,
+                _padding: [0; 3],
+            }]),
+        );
+    }
+
+    // Writes this frame's instance counts, before the pass that draws them
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Indirect Cull Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.dispatch_workgroups(self.object_count.div_ceil(64), 1, 1);
+    }
+
+    // Same bindings as `DrawModel::draw_model_instanced`, or
+    // `MaterialArray::draw_model_instanced` when given packed materials, with
+    // every instance in `instance_buffer` at slot 1. The caller sets the pipeline and the
+    // bind groups past the camera.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        model: &'a Model,
+        materials: Option<&'a MaterialArray>,
+        instance_buffer: &'a wgpu::Buffer,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        let stride = std::mem::size_of::<wgpu::util::DrawIndexedIndirectArgs>() as u64;
+        render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        if let Some(materials) = materials {
+            materials.bind(render_pass);
+        }
+        for (i, mesh) in model.meshes.iter().enumerate() {
+            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            match materials {
+                Some(materials) => materials.select(render_pass, mesh),
+                None => {
+                    render_pass.set_bind_group(0, &model.materials[mesh.material].bind_group, &[])
+                }
+            }
+            render_pass.multi_draw_indexed_indirect(
+                &self.draw_buffer,
+                i as u64 * self.instance_count as u64 * stride,
+                self.instance_count,
+            );
+            crate::stats::count_draw();
+        }
+    }
+}
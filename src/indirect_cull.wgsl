@@ -0,0 +1,41 @@
+// ===== INDIRECT DRAW CULLING =====
+// One thread per object, a mesh drawn for one instance. Objects whose bounding
+// sphere is outside the frustum get an instance count of 0, so their draw in
+// the multi-draw does nothing.
+
+struct CullUniform {
+    planes: array<vec4<f32>, 6>, // Frustum planes, normals pointing inwards
+    object_count: u32,
+};
+@group(0) @binding(0)
+var<uniform> cull: CullUniform;
+// World space, xyz = center and w = radius
+@group(0) @binding(1)
+var<storage, read> spheres: array<vec4<f32>>;
+
+// Same layout as wgpu::util::DrawIndexedIndirectArgs
+struct DrawIndexedIndirectArgs {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+@group(0) @binding(2)
+var<storage, read_write> draws: array<DrawIndexedIndirectArgs>;
+
+@compute @workgroup_size(64)
+fn cull_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= cull.object_count) {
+        return;
+    }
+    let sphere = spheres[id.x];
+    var visible = 1u;
+    for (var i = 0u; i < 6u; i += 1u) {
+        let plane = cull.planes[i];
+        if (dot(plane.xyz, sphere.xyz) + plane.w < -sphere.w) {
+            visible = 0u;
+        }
+    }
+    draws[id.x].instance_count = visible;
+}
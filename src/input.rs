@@ -0,0 +1,613 @@
+use winit::event::{MouseScrollDelta, Touch};
+use winit::event_loop::ActiveEventLoop;
+
+use crate::{
+    bloom, deferred, gizmo, hdr, input_map, light_shafts, post_process, scene, stats, window_mode,
+    ShadingMode, State,
+};
+
+// Seconds between keyframes recorded with F5, and where F8 saves them in res/
+const CAMERA_PATH_INTERVAL: f32 = 2.0;
+#[cfg(not(target_arch = "wasm32"))]
+const CAMERA_PATH_FILE: &str = "camera_path.ron";
+
+// ===== INPUT =====
+// What the window's keys, buttons, touches and scrolling do to the state
+impl State {
+    // Keys and mouse buttons set off whatever actions they're bound to
+    pub(crate) fn handle_input(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        binding: input_map::Binding,
+        pressed: bool,
+    ) {
+        for action in self.input_map.handle(binding, pressed) {
+            self.handle_action(event_loop, &action, pressed);
+        }
+    }
+
+    pub(crate) fn handle_action(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        action: &str,
+        pressed: bool,
+    ) {
+        match (action, pressed) {
+            ("quit", true) if !self.cameras.is_flying() => event_loop.exit(),
+            ("toggle_mouselook", true) if self.cameras.is_flying() => {
+                self.set_cursor_grabbed(!self.cursor_grabbed);
+            }
+            ("cycle_window_mode", true) => {
+                let Some(window) = self.output.window() else {
+                    return;
+                };
+                let mode = self.window_mode.next();
+                match window_mode::set_window_mode(window, mode, None) {
+                    Ok(()) => self.window_mode = mode,
+                    // Can't go exclusive, so skip it
+                    Err(e) if mode == window_mode::WindowMode::Exclusive => {
+                        tracing::warn!("No exclusive fullscreen: {:#}", e);
+                        self.window_mode = window_mode::WindowMode::Windowed;
+                        window.set_fullscreen(None);
+                    }
+                    Err(e) => tracing::error!("Couldn't change the window mode: {:#}", e),
+                }
+                tracing::info!("Window mode: {:?}", self.window_mode);
+            }
+            ("toggle_particle_window", true) => self.toggle_particle_window(event_loop),
+            #[cfg(not(target_arch = "wasm32"))]
+            ("screenshot", true) => self.capture_requested = true,
+            #[cfg(not(target_arch = "wasm32"))]
+            ("toggle_recording", true) => {
+                if self.recording.is_some() {
+                    self.stop_recording();
+                } else {
+                    self.start_recording();
+                }
+            }
+            ("cycle_frame_cap", true) => self.cycle_frame_cap(),
+            ("toggle_vsync", true) => self.toggle_vsync(),
+            ("render_scale_down", true) => self.set_render_scale(self.render_scale - 0.25),
+            ("render_scale_up", true) => self.set_render_scale(self.render_scale + 0.25),
+            ("list_monitors", true) => {
+                let Some(window) = self.output.window() else {
+                    return;
+                };
+                for monitor in window_mode::monitors(window) {
+                    tracing::info!(
+                        "{}: {}x{} at {}x scale",
+                        monitor.name,
+                        monitor.size[0],
+                        monitor.size[1],
+                        monitor.scale_factor
+                    );
+                    for mode in &monitor.video_modes {
+                        tracing::info!(
+                            "  {}x{} {:.2}Hz {}-bit",
+                            mode.size[0],
+                            mode.size[1],
+                            mode.refresh_rate_millihertz as f32 / 1000.0,
+                            mode.bit_depth
+                        );
+                    }
+                }
+            }
+            ("toggle_fire", true) => self.set_fire(!self.fire_enabled),
+            ("toggle_render_path", true) => {
+                self.render_path = match self.render_path {
+                    deferred::RenderPath::Forward => deferred::RenderPath::Deferred,
+                    deferred::RenderPath::Deferred => deferred::RenderPath::Forward,
+                };
+                tracing::info!("Render path: {:?}", self.render_path);
+                self.apply_sample_count();
+            }
+            ("cycle_shading", true) => {
+                self.shading = self.shading.next();
+                // Skip the wireframe view where line polygons aren't supported
+                if self.shading == ShadingMode::Wireframe
+                    && !self
+                        .device
+                        .features()
+                        .contains(wgpu::Features::POLYGON_MODE_LINE)
+                {
+                    self.shading = self.shading.next();
+                }
+                self.update_model_pipeline();
+                // The deferred path has its own geometry pipeline
+                tracing::info!("Shading: {:?} (forward path only)", self.shading);
+            }
+            ("cycle_msaa", true) => {
+                let counts = &self.supported_sample_counts;
+                let index = counts
+                    .iter()
+                    .position(|&count| count == self.msaa_samples)
+                    .unwrap_or(0);
+                self.msaa_samples = counts[(index + 1) % counts.len()];
+                tracing::info!("MSAA: {}x", self.msaa_samples);
+                self.apply_sample_count();
+            }
+            ("toggle_bloom", true) => {
+                if let Some(bloom) = self.post_process.effect_mut::<bloom::Bloom>() {
+                    bloom.enabled = !bloom.enabled;
+                    tracing::info!(
+                        "Bloom {}",
+                        if bloom.enabled { "enabled" } else { "disabled" }
+                    );
+                }
+            }
+            ("toggle_motion_blur", true) => {
+                if let Some(motion_blur) =
+                    self.post_process.effect_mut::<post_process::MotionBlur>()
+                {
+                    motion_blur.enabled = !motion_blur.enabled;
+                    tracing::info!(
+                        "Motion blur {}",
+                        if motion_blur.enabled {
+                            "enabled"
+                        } else {
+                            "disabled"
+                        }
+                    );
+                }
+            }
+            ("toggle_depth_of_field", true) => {
+                if let Some(depth_of_field) =
+                    self.post_process.effect_mut::<post_process::DepthOfField>()
+                {
+                    depth_of_field.enabled = !depth_of_field.enabled;
+                    tracing::info!(
+                        "Depth of field {}",
+                        if depth_of_field.enabled {
+                            "enabled"
+                        } else {
+                            "disabled"
+                        }
+                    );
+                }
+            }
+            ("select_next_instance", true) => {
+                // Steps through the instances, then back to no selection
+                let count = self.instances.len() as u32;
+                self.selected_instance = match self.selected_instance {
+                    None if count > 0 => Some(0),
+                    Some(i) if i + 1 < count => Some(i + 1),
+                    _ => None,
+                };
+                tracing::info!("Selected instance: {:?}", self.selected_instance);
+            }
+            ("spawn_prefab", true) => {
+                // Drops the scene's first prefab somewhere among the models
+                if let Some(prefab) = self.scene.prefabs.keys().next().cloned() {
+                    use rand::Rng;
+                    let mut rng = rand::rng();
+                    let transform = scene::SceneTransform {
+                        position: [
+                            rng.random_range(-12.0..12.0),
+                            0.0,
+                            rng.random_range(-12.0..12.0),
+                        ],
+                        rotation: [0.0, rng.random_range(0.0..360.0), 0.0],
+                    };
+                    match self.spawn(&prefab, transform) {
+                        Ok(()) => {
+                            self.cameras.shake.add_trauma(0.3);
+                            tracing::info!("Spawned {} at {:?}", prefab, transform.position)
+                        }
+                        Err(e) => tracing::error!("Couldn't spawn {}: {:#}", prefab, e),
+                    }
+                }
+            }
+            ("toggle_lens_flare", true) => {
+                self.lens_flare.enabled = !self.lens_flare.enabled;
+                tracing::info!(
+                    "Lens flare {}",
+                    if self.lens_flare.enabled {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                );
+            }
+            ("toggle_light_shafts", true) => {
+                if let Some(light_shafts) =
+                    self.post_process.effect_mut::<light_shafts::LightShafts>()
+                {
+                    light_shafts.enabled = !light_shafts.enabled;
+                    tracing::info!(
+                        "Light shafts {}",
+                        if light_shafts.enabled {
+                            "enabled"
+                        } else {
+                            "disabled"
+                        }
+                    );
+                }
+            }
+            ("toggle_vignette", true) => {
+                if let Some(vignette) = self.post_process.effect_mut::<post_process::Vignette>() {
+                    vignette.enabled = !vignette.enabled;
+                    tracing::info!(
+                        "Vignette {}",
+                        if vignette.enabled {
+                            "enabled"
+                        } else {
+                            "disabled"
+                        }
+                    );
+                }
+            }
+            ("toggle_lens_distortion", true) => {
+                if let Some(distortion) = self.post_process.effect_mut::<post_process::Distortion>()
+                {
+                    distortion.enabled = !distortion.enabled;
+                    tracing::info!(
+                        "Lens distortion {}",
+                        if distortion.enabled {
+                            "enabled"
+                        } else {
+                            "disabled"
+                        }
+                    );
+                }
+            }
+            ("toggle_fxaa", true) => {
+                self.fxaa_enabled = !self.fxaa_enabled;
+                tracing::info!(
+                    "FXAA {}",
+                    if self.fxaa_enabled {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                );
+            }
+            ("toggle_ssao", true) => {
+                self.ssao_enabled = !self.ssao_enabled;
+                tracing::info!(
+                    "SSAO {}",
+                    if self.ssao_enabled {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                );
+            }
+            ("toggle_oit", true) => {
+                self.oit_enabled = !self.oit_enabled;
+                tracing::info!(
+                    "Order-independent transparency {}",
+                    if self.oit_enabled {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                );
+            }
+            #[cfg(feature = "egui")]
+            ("toggle_debug_ui", true) => match &mut self.debug_ui {
+                Some(debug_ui) => {
+                    debug_ui.visible = !debug_ui.visible;
+                    tracing::info!(
+                        "Debug UI {}",
+                        if debug_ui.visible {
+                            "enabled"
+                        } else {
+                            "disabled"
+                        }
+                    );
+                }
+                None => tracing::warn!("There's no window for the debug UI"),
+            },
+            #[cfg(not(feature = "egui"))]
+            ("toggle_debug_ui", true) => {
+                tracing::warn!("Built without the egui feature, there's no debug UI")
+            }
+            ("cycle_gizmo", true) => {
+                self.gizmo.release();
+                self.gizmo.mode = gizmo::GizmoMode::next(self.gizmo.mode);
+                match self.gizmo.mode {
+                    Some(mode) => tracing::info!("{:?} gizmo", mode),
+                    None => tracing::info!("Gizmo disabled"),
+                }
+            }
+            ("next_gizmo_target", true) => {
+                self.gizmo.release();
+                let targets = self.gizmo_targets();
+                if targets.is_empty() {
+                    tracing::warn!("There's nothing for the gizmo to move");
+                } else {
+                    self.gizmo_target = (self.gizmo_target + 1) % targets.len();
+                    tracing::info!("Gizmo on {:?}", targets[self.gizmo_target]);
+                }
+            }
+            ("toggle_grid", true) => {
+                self.grid.enabled = !self.grid.enabled;
+                tracing::info!(
+                    "Grid {}",
+                    if self.grid.enabled {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                );
+            }
+            ("toggle_debug_draw", true) => {
+                self.debug_draw_enabled = !self.debug_draw_enabled;
+                tracing::info!(
+                    "Debug drawing {}",
+                    if self.debug_draw_enabled {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                );
+            }
+            ("toggle_hud", true) => {
+                self.hud_enabled = !self.hud_enabled;
+                tracing::info!(
+                    "HUD {}",
+                    if self.hud_enabled {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                );
+            }
+            ("toggle_stats", true) => {
+                self.stats = match self.stats.take() {
+                    Some(_) => None,
+                    None => Some(stats::StatsOverlay::new(
+                        &self.device,
+                        &self.queue,
+                        self.config.format,
+                    )),
+                };
+                tracing::info!(
+                    "Stats overlay {}",
+                    if self.stats.is_some() {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                );
+            }
+            ("toggle_gpu_timing", true) => match &mut self.gpu_timer {
+                Some(gpu_timer) => {
+                    gpu_timer.enabled = !gpu_timer.enabled;
+                    tracing::info!(
+                        "GPU timing {}",
+                        if gpu_timer.enabled {
+                            "enabled"
+                        } else {
+                            "disabled"
+                        }
+                    );
+                }
+                None => tracing::warn!("The GPU can't time passes"),
+            },
+            ("toggle_split_views", true) => {
+                self.split_views_enabled = !self.split_views_enabled;
+                tracing::info!(
+                    "Split views {}",
+                    if self.split_views_enabled {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                );
+            }
+            ("toggle_security_camera", true) => {
+                self.security_camera_enabled = !self.security_camera_enabled;
+                tracing::info!(
+                    "Security camera {}",
+                    if self.security_camera_enabled {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                );
+            }
+            ("toggle_occlusion_culling", true) => match &mut self.occlusion {
+                Some(occlusion) => {
+                    occlusion.enabled = !occlusion.enabled;
+                    tracing::info!(
+                        "Occlusion culling {}",
+                        if occlusion.enabled {
+                            "enabled"
+                        } else {
+                            "disabled"
+                        }
+                    );
+                }
+                None => tracing::info!("Occlusion culling is not supported"),
+            },
+            ("toggle_indirect_draws", true) => match &mut self.indirect_draws {
+                Some(indirect_draws) => {
+                    indirect_draws.enabled = !indirect_draws.enabled;
+                    tracing::info!(
+                        "GPU-driven draws {}",
+                        if indirect_draws.enabled {
+                            "enabled"
+                        } else {
+                            "disabled"
+                        }
+                    );
+                }
+                None => tracing::info!("GPU-driven draws are not supported"),
+            },
+            ("toggle_normal_mapping", true) => {
+                self.normal_mapping = !self.normal_mapping;
+                self.update_model_pipeline();
+                tracing::info!(
+                    "Normal mapping {} (forward path only)",
+                    if self.normal_mapping {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                );
+            }
+            ("cycle_tonemapper", true) => {
+                if let Some(tonemap) = self.post_process.effect_mut::<hdr::Tonemap>() {
+                    tonemap.tonemapper = tonemap.tonemapper.next();
+                    tracing::info!("Tonemapper: {:?}", tonemap.tonemapper);
+                }
+                self.post_process.update(&self.queue);
+            }
+            ("exposure_up" | "exposure_down", true) => {
+                let step = if action == "exposure_up" { 1.25 } else { 0.8 };
+                if let Some(tonemap) = self.post_process.effect_mut::<hdr::Tonemap>() {
+                    tonemap.exposure = (tonemap.exposure * step).clamp(0.05, 20.0);
+                    tracing::info!("Exposure: {:.2}", tonemap.exposure);
+                }
+                self.post_process.update(&self.queue);
+            }
+            ("toggle_camera_damping", true) => {
+                let enabled = self.cameras.toggle_damping();
+                tracing::info!(
+                    "Camera damping {}",
+                    if enabled { "enabled" } else { "disabled" }
+                );
+            }
+            ("play_sequence", true) => {
+                if self.sequencer.is_playing() {
+                    self.sequencer.stop();
+                    tracing::info!("Sequence stopped at {:.1}s", self.sequencer.time());
+                } else if !self.sequencer.is_empty() {
+                    self.sequencer.play();
+                    tracing::info!("Sequence playing, {:.1}s", self.sequencer.duration());
+                }
+            }
+            ("cycle_camera", true) => {
+                tracing::info!("Camera: {}", self.cameras.cycle());
+            }
+            ("frame_model", true) => {
+                self.frame_model();
+                tracing::info!("Framed the model");
+            }
+            ("fov_up" | "fov_down", true) => {
+                let step = if action == "fov_up" { 5.0 } else { -5.0 };
+                let camera = &mut self.cameras.active_mut().view.camera;
+                camera.set_fovy(camera.fovy() + step);
+                tracing::info!("Field of view: {:.0}°", camera.fovy());
+            }
+            ("far_plane_out" | "far_plane_in", true) => {
+                let step = if action == "far_plane_out" { 2.0 } else { 0.5 };
+                let camera = &mut self.cameras.active_mut().view.camera;
+                let (znear, zfar) = camera.clip_planes();
+                match camera.set_clip_planes(znear, (zfar * step).max(znear * 2.0)) {
+                    Ok(()) => tracing::info!("Far plane: {:.1}", camera.clip_planes().1),
+                    Err(e) => tracing::error!("{:#}", e),
+                }
+            }
+            ("toggle_projection", true) => {
+                let camera = &mut self.cameras.active_mut().view.camera;
+                camera.projection = camera.projection.toggled();
+                tracing::info!("Projection: {:?}", camera.projection);
+            }
+            // Recording a flythrough: F7 to start over, F5 for a keyframe
+            // where the camera is now, F6 to play it back and F8 to save it
+            ("record_path_keyframe", true) => {
+                let camera = self.camera;
+                if let Some((_, player)) = self.cameras.path_mut() {
+                    player.path.push(&camera, CAMERA_PATH_INTERVAL);
+                    tracing::info!(
+                        "Camera path keyframe {} at {:.1}s",
+                        player.path.keyframes().len(),
+                        player.path.duration()
+                    );
+                }
+            }
+            ("play_path", true) => {
+                if let Some((name, player)) = self.cameras.path_mut() {
+                    let name = name.to_string();
+                    if player.playing {
+                        player.pause();
+                        tracing::info!("Camera path paused at {:.1}s", player.time());
+                    } else {
+                        player.play();
+                        tracing::info!("Camera path playing from {:.1}s", player.time());
+                        self.cameras.select(&name);
+                    }
+                }
+            }
+            ("clear_path", true) => {
+                if let Some((_, player)) = self.cameras.path_mut() {
+                    player.pause();
+                    player.path.clear();
+                    player.seek(0.0);
+                    tracing::info!("Camera path cleared");
+                }
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            ("save_path", true) => {
+                if let Some((_, player)) = self.cameras.path_mut() {
+                    match player.path.save(CAMERA_PATH_FILE) {
+                        Ok(path) => tracing::info!("Saved the camera path to {}", path.display()),
+                        Err(e) => tracing::error!("Couldn't save the camera path: {:#}", e),
+                    }
+                }
+            }
+            ("pick", true) => {
+                if !self.grab_gizmo() {
+                    self.move_fire_to_cursor();
+                }
+            }
+            ("pick", false) => self.release_gizmo(),
+            _ => self.cameras.handle_action(action, pressed),
+        }
+    }
+
+    // One finger orbits, two pinch to zoom and pan
+    pub(crate) fn handle_touch(&mut self, touch: &Touch) {
+        let height = self.config.height.max(1) as f32;
+        if let Some(gesture) = self.touch.handle_touch(touch, height) {
+            self.cameras.handle_gesture(gesture);
+        }
+    }
+
+    // Raw movement from the device, so it keeps going at the window's edges
+    pub(crate) fn handle_mouse_motion(&mut self, dx: f64, dy: f64) {
+        self.cameras.handle_mouse_motion(dx, dy);
+    }
+
+    pub(crate) fn set_cursor_grabbed(&mut self, grabbed: bool) {
+        let Some(window) = self.output.window() else {
+            return;
+        };
+        match window_mode::set_cursor_grabbed(window, grabbed) {
+            Ok(mode) => {
+                self.cursor_grabbed = grabbed;
+                self.cameras.set_mouselook(grabbed);
+                tracing::info!(
+                    "Mouselook {}",
+                    if grabbed {
+                        format!("enabled ({:?})", mode)
+                    } else {
+                        "disabled".to_string()
+                    }
+                );
+            }
+            Err(e) => tracing::warn!("No mouselook: {:#}", e),
+        }
+    }
+
+    // Keys and buttons let go of elsewhere never come back released
+    pub(crate) fn release_input(&mut self) {
+        if self.cursor_grabbed {
+            self.set_cursor_grabbed(false);
+        }
+        self.input_map.release();
+        self.cameras.release();
+        self.touch.release();
+    }
+
+    pub(crate) fn handle_scroll(&mut self, delta: MouseScrollDelta) {
+        // Pixel deltas from touchpads, roughly a line every 40
+        let lines = match delta {
+            MouseScrollDelta::LineDelta(_, y) => y,
+            MouseScrollDelta::PixelDelta(position) => position.y as f32 / 40.0,
+        };
+        self.cameras.handle_scroll(lines);
+    }
+}
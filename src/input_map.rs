@@ -0,0 +1,177 @@
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::Context;
+use winit::event::MouseButton;
+use winit::keyboard::KeyCode;
+
+use crate::resources;
+
+// A key or mouse button an action can be bound to
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+use Binding::{Key, Mouse};
+
+// What everything is bound to without a config file, and for actions the
+// file leaves out
+#[rustfmt::skip]
+const DEFAULT_BINDINGS: &[(&str, &[Binding])] = &[
+    // Escape quits, unless a fly camera's active, where it grabs the cursor
+    // for mouselook and lets it go again
+    ("quit", &[Key(KeyCode::Escape)]),
+    ("toggle_mouselook", &[Key(KeyCode::Escape)]),
+    ("list_monitors", &[Key(KeyCode::F10)]),
+    ("cycle_window_mode", &[Key(KeyCode::F11)]),
+    ("toggle_recording", &[Key(KeyCode::F1)]),
+    ("screenshot", &[Key(KeyCode::F2)]),
+    ("toggle_stats", &[Key(KeyCode::F3)]),
+    ("toggle_debug_ui", &[Key(KeyCode::Digit1)]),
+    ("toggle_hud", &[Key(KeyCode::Digit2)]),
+    ("toggle_vsync", &[Key(KeyCode::Digit7)]),
+    ("cycle_frame_cap", &[Key(KeyCode::F4)]),
+    ("toggle_particle_window", &[Key(KeyCode::F12)]),
+    // Cameras
+    ("move_forward", &[Key(KeyCode::KeyW), Key(KeyCode::ArrowUp)]),
+    ("move_backward", &[Key(KeyCode::KeyS), Key(KeyCode::ArrowDown)]),
+    ("move_left", &[Key(KeyCode::KeyA), Key(KeyCode::ArrowLeft)]),
+    ("move_right", &[Key(KeyCode::KeyD), Key(KeyCode::ArrowRight)]),
+    ("boost", &[Key(KeyCode::ShiftLeft), Key(KeyCode::ShiftRight)]),
+    ("look", &[Mouse(MouseButton::Right)]),
+    ("cycle_camera", &[Key(KeyCode::KeyQ)]),
+    ("toggle_camera_damping", &[Key(KeyCode::KeyZ)]),
+    ("toggle_projection", &[Key(KeyCode::Numpad5), Key(KeyCode::Digit5)]),
+    ("frame_model", &[Key(KeyCode::Home)]),
+    ("fov_down", &[Key(KeyCode::BracketLeft)]),
+    ("fov_up", &[Key(KeyCode::BracketRight)]),
+    ("far_plane_in", &[Key(KeyCode::Comma)]),
+    ("far_plane_out", &[Key(KeyCode::Period)]),
+    ("record_path_keyframe", &[Key(KeyCode::F5)]),
+    ("play_path", &[Key(KeyCode::F6)]),
+    ("clear_path", &[Key(KeyCode::F7)]),
+    ("save_path", &[Key(KeyCode::F8)]),
+    ("play_sequence", &[Key(KeyCode::F9)]),
+    // The scene
+    ("pick", &[Mouse(MouseButton::Left)]),
+    ("toggle_fire", &[Key(KeyCode::Space)]),
+    ("spawn_prefab", &[Key(KeyCode::KeyE)]),
+    ("select_next_instance", &[Key(KeyCode::Tab)]),
+    ("cycle_gizmo", &[Key(KeyCode::Digit3)]),
+    ("next_gizmo_target", &[Key(KeyCode::Digit4)]),
+    // Rendering
+    ("toggle_render_path", &[Key(KeyCode::KeyG)]),
+    ("cycle_shading", &[Key(KeyCode::KeyP)]),
+    ("cycle_msaa", &[Key(KeyCode::KeyM)]),
+    ("toggle_normal_mapping", &[Key(KeyCode::KeyR)]),
+    ("toggle_fxaa", &[Key(KeyCode::KeyF)]),
+    ("toggle_ssao", &[Key(KeyCode::KeyO)]),
+    ("toggle_oit", &[Key(KeyCode::KeyI)]),
+    ("toggle_occlusion_culling", &[Key(KeyCode::KeyU)]),
+    ("toggle_indirect_draws", &[Key(KeyCode::KeyY)]),
+    ("toggle_split_views", &[Key(KeyCode::KeyC)]),
+    ("toggle_security_camera", &[Key(KeyCode::KeyX)]),
+    ("toggle_gpu_timing", &[Key(KeyCode::Digit8)]),
+    ("toggle_debug_draw", &[Key(KeyCode::Digit6)]),
+    ("toggle_grid", &[Key(KeyCode::Backquote)]),
+    // Post-processing
+    ("toggle_bloom", &[Key(KeyCode::KeyB)]),
+    ("toggle_motion_blur", &[Key(KeyCode::KeyN)]),
+    ("toggle_depth_of_field", &[Key(KeyCode::KeyK)]),
+    ("toggle_lens_flare", &[Key(KeyCode::KeyJ)]),
+    ("toggle_light_shafts", &[Key(KeyCode::KeyH)]),
+    ("toggle_vignette", &[Key(KeyCode::KeyV)]),
+    ("toggle_lens_distortion", &[Key(KeyCode::KeyL)]),
+    ("render_scale_down", &[Key(KeyCode::Digit9)]),
+    ("render_scale_up", &[Key(KeyCode::Digit0)]),
+    ("cycle_tonemapper", &[Key(KeyCode::KeyT)]),
+    ("exposure_up", &[Key(KeyCode::Equal)]),
+    ("exposure_down", &[Key(KeyCode::Minus)]),
+];
+
+// ===== INPUT MAP =====
+// Named actions and the keys and buttons bound to them, so game code asks
+// for "toggle_fire" rather than checking for Space. A binding can set off
+// more than one action, and an action can have any number of bindings.
+#[derive(Debug, Clone)]
+pub struct InputMap {
+    bindings: BTreeMap<String, Vec<Binding>>,
+    held: HashSet<Binding>,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        Self {
+            bindings: DEFAULT_BINDINGS
+                .iter()
+                .map(|(action, bindings)| (action.to_string(), bindings.to_vec()))
+                .collect(),
+            held: HashSet::new(),
+        }
+    }
+}
+
+impl InputMap {
+    // The defaults with the actions in a .ron file in res/ rebound, a map
+    // from action names to lists of bindings. An empty list unbinds one.
+    pub async fn load(file_name: &str) -> anyhow::Result<Self> {
+        let text = resources::load_string(file_name).await?;
+        let bindings: BTreeMap<String, Vec<Binding>> =
+            ron::from_str(&text).with_context(|| format!("Couldn't parse {}", file_name))?;
+        let mut input_map = Self::default();
+        for (action, bindings) in bindings {
+            if !input_map.bindings.contains_key(&action) {
+                tracing::warn!("{} binds {}, which nothing uses", file_name, action);
+            }
+            input_map.bind(&action, bindings);
+        }
+        Ok(input_map)
+    }
+
+    pub fn bind(&mut self, action: &str, bindings: Vec<Binding>) {
+        self.bindings.insert(action.to_string(), bindings);
+    }
+
+    pub fn bindings(&self, action: &str) -> &[Binding] {
+        self.bindings.get(action).map_or(&[], Vec::as_slice)
+    }
+
+    // Every action and what sets it off, in name order
+    pub fn actions(&self) -> impl Iterator<Item = (&str, &[Binding])> {
+        self.bindings
+            .iter()
+            .map(|(action, bindings)| (action.as_str(), bindings.as_slice()))
+    }
+
+    // Keeps track of what's held, returning the actions `binding` sets off
+    pub fn handle(&mut self, binding: Binding, pressed: bool) -> Vec<String> {
+        if pressed {
+            self.held.insert(binding);
+        } else {
+            self.held.remove(&binding);
+        }
+        self.bindings
+            .iter()
+            .filter(|(_, bindings)| bindings.contains(&binding))
+            .map(|(action, _)| action.clone())
+            .collect()
+    }
+
+    // Whether any of the action's bindings is held down
+    pub fn is_held(&self, action: &str) -> bool {
+        self.bindings(action)
+            .iter()
+            .any(|binding| self.held.contains(binding))
+    }
+
+    // -1 with only `negative` held, 1 with only `positive` and 0 otherwise
+    pub fn axis(&self, negative: &str, positive: &str) -> f32 {
+        self.is_held(positive) as i32 as f32 - self.is_held(negative) as i32 as f32
+    }
+
+    // Forgets what's held, for when the releases won't come
+    pub fn release(&mut self) {
+        self.held.clear();
+    }
+}
@@ -0,0 +1,254 @@
+use wgpu::util::DeviceExt;
+
+// ===== FLARE ELEMENTS =====
+// One sprite of the flare, drawn as an instance
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FlareElement {
+    pub offset: f32, // 0 sits on the light, 1 is mirrored through the screen center
+    pub size: f32,   // Radius as a fraction of the screen height
+    pub ring: f32,   // 0 for a soft disc, 1 for a hollow ring
+    pub _padding: f32,
+    pub color: [f32; 4], // Tint, alpha scales the brightness
+}
+
+impl FlareElement {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 4] =
+        wgpu::vertex_attr_array![0 => Float32, 1 => Float32, 2 => Float32, 3 => Float32x4];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<FlareElement>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+
+    const fn new(offset: f32, size: f32, ring: f32, color: [f32; 4]) -> Self {
+        Self {
+            offset,
+            size,
+            ring,
+            _padding: 0.0,
+            color,
+        }
+    }
+}
+
+// A glow on the light itself followed by ghosts of different sizes
+const DEFAULT_ELEMENTS: [FlareElement; 6] = [
+    FlareElement::new(0.0, 0.35, 0.0, [1.0, 0.8, 0.6, 0.5]),
+    FlareElement::new(0.0, 0.12, 1.0, [1.0, 0.6, 0.3, 0.3]),
+    FlareElement::new(0.4, 0.05, 0.0, [0.6, 0.8, 1.0, 0.2]),
+    FlareElement::new(0.7, 0.1, 1.0, [0.5, 1.0, 0.6, 0.15]),
+    FlareElement::new(1.1, 0.08, 0.0, [1.0, 0.5, 0.8, 0.15]),
+    FlareElement::new(1.5, 0.2, 1.0, [0.6, 0.6, 1.0, 0.1]),
+];
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LensFlareUniform {
+    position: [f32; 3],
+    intensity: f32,
+    color: [f32; 3],
+    _padding: f32,
+}
+
+// ===== LENS FLARE =====
+// Glare sprites for a bright light like the fire. The light is projected onto
+// the screen in the shader, which also tests the linear depth around it so the
+// flare disappears behind the model. Drawn additively into the resolved HDR
+// target, so bloom and the tonemapper treat it like the rest of the scene.
+pub struct LensFlare {
+    pub enabled: bool,
+    pub position: [f32; 3], // World space light position
+    pub color: [f32; 3],
+    pub intensity: f32,
+    uniform_buffer: wgpu::Buffer,
+    element_buffer: wgpu::Buffer,
+    num_elements: u32,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl LensFlare {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        linear_depth: &wgpu::TextureView,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Lens Flare Uniform Buffer"),
+            size: std::mem::size_of::<LensFlareUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let element_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Lens Flare Element Buffer"),
+            contents: bytemuck::cast_slice(&DEFAULT_ELEMENTS),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        // The vertex shader does the occlusion test, so it needs both
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("lens_flare_bind_group_layout"),
+        });
+        let bind_group =
+            Self::create_bind_group(device, &bind_group_layout, &uniform_buffer, linear_depth);
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Lens Flare Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("lens_flare.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Lens Flare Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout, camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Lens Flare Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[FlareElement::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    // Glare only ever adds light
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::One,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent::OVER,
+                    }),
+                    write_mask: wgpu::ColorWrites::COLOR,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            enabled: true,
+            position: [0.0; 3],
+            color: [1.0, 0.6, 0.3],
+            intensity: 1.0,
+            uniform_buffer,
+            element_buffer,
+            num_elements: DEFAULT_ELEMENTS.len() as u32,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        linear_depth: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(linear_depth),
+                },
+            ],
+            label: Some("lens_flare_bind_group"),
+        })
+    }
+
+    // The linear depth target is recreated on resize, so the bind group has to follow it
+    pub fn resize(&mut self, device: &wgpu::Device, linear_depth: &wgpu::TextureView) {
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.uniform_buffer,
+            linear_depth,
+        );
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[LensFlareUniform {
+                position: self.position,
+                intensity: self.intensity,
+                color: self.color,
+                _padding: 0.0,
+            }]),
+        );
+    }
+
+    // `output` is the resolved HDR target, drawn over without clearing
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        camera_bind_group: &wgpu::BindGroup,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Lens Flare Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_bind_group(1, camera_bind_group, &[]);
+        pass.set_vertex_buffer(0, self.element_buffer.slice(..));
+        pass.draw(0..6, 0..self.num_elements);
+        crate::stats::count_draw();
+    }
+}
@@ -0,0 +1,99 @@
+// ===== LENS FLARE =====
+// Sprites strung along the line from a bright light through the middle of the
+// screen, added on top of the HDR scene. The vertex shader checks the linear
+// depth around the light so the flare fades out when something covers it.
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct LensFlareUniform {
+    position: vec3<f32>,
+    intensity: f32,
+    color: vec3<f32>,
+};
+@group(0) @binding(0)
+var<uniform> flare: LensFlareUniform;
+// Linear view depth from the scene pass, 0.0 where nothing was drawn
+@group(0) @binding(1)
+var t_linear_depth: texture_2d<f32>;
+
+struct ElementInput {
+    @location(0) offset: f32, // 0 at the light, 1 mirrored through the middle
+    @location(1) size: f32,   // Radius as a fraction of the screen height
+    @location(2) ring: f32,   // 0 for a soft disc, 1 for a hollow ring
+    @location(3) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) local: vec2<f32>,
+    @location(1) color: vec3<f32>,
+    @location(2) ring: f32,
+};
+
+// How much of the light the depth buffer lets through, from a few pixels around it
+fn visibility(pixel: vec2<f32>, light_depth: f32) -> f32 {
+    let size = vec2<i32>(textureDimensions(t_linear_depth));
+    var visible = 0.0;
+    for (var y = -1; y <= 1; y = y + 1) {
+        for (var x = -1; x <= 1; x = x + 1) {
+            let coords = clamp(vec2<i32>(pixel) + vec2<i32>(x, y) * 4, vec2<i32>(0), size - 1);
+            let depth = textureLoad(t_linear_depth, coords, 0).r;
+            // A little slack so the geometry right behind the light doesn't hide it
+            if (depth == 0.0 || depth > light_depth - 0.05) {
+                visible = visible + 1.0;
+            }
+        }
+    }
+    return visible / 9.0;
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32, element: ElementInput) -> VertexOutput {
+    // Two triangles per sprite
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, -1.0), vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, 1.0), vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+
+    var out: VertexOutput;
+    out.local = corner;
+    out.ring = element.ring;
+    // Degenerate by default, for when the light can't be seen
+    out.clip_position = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    out.color = vec3<f32>(0.0);
+
+    let light_clip = camera.view_proj * vec4<f32>(flare.position, 1.0);
+    // w is the view depth, so this also rejects lights behind the camera
+    if (light_clip.w <= 0.0) {
+        return out;
+    }
+    let light_ndc = light_clip.xy / light_clip.w;
+    let dimensions = vec2<f32>(textureDimensions(t_linear_depth));
+    let pixel = (light_ndc * vec2<f32>(0.5, -0.5) + 0.5) * dimensions;
+    // Fade out as the light leaves the screen
+    let edge = 1.0 - smoothstep(0.8, 1.0, max(abs(light_ndc.x), abs(light_ndc.y)));
+    let brightness = flare.intensity * edge * visibility(pixel, light_clip.w);
+    if (brightness <= 0.0) {
+        return out;
+    }
+
+    let center = mix(light_ndc, -light_ndc, element.offset);
+    let aspect = dimensions.y / dimensions.x;
+    out.clip_position = vec4<f32>(center + corner * element.size * vec2<f32>(aspect, 1.0), 0.0, 1.0);
+    out.color = flare.color * element.color.rgb * element.color.a * brightness;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let distance = length(in.local);
+    let disc = 1.0 - smoothstep(0.0, 1.0, distance);
+    let ring = smoothstep(0.6, 0.85, distance) * (1.0 - smoothstep(0.85, 1.0, distance));
+    let shape = mix(disc * disc, ring, in.ring);
+    return vec4<f32>(in.color * shape, 1.0);
+}
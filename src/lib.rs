@@ -1,4 +1,6 @@
+use anyhow::Context;
 use cgmath::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use wgpu::util::DeviceExt;
 
@@ -6,14 +8,89 @@ use winit::{
     application::ApplicationHandler,
     event::*,
     event_loop::{ActiveEventLoop, EventLoop},
-    keyboard::{KeyCode, PhysicalKey},
-    window::Window,
+    keyboard::PhysicalKey,
+    window::{Window, WindowId},
 };
 
+pub mod animation;
+pub mod assets;
+pub mod atlas;
+pub mod bloom;
+pub mod camera;
+pub mod camera_damping;
+pub mod camera_path;
+pub mod camera_shake;
+pub mod cameras;
+pub mod capture;
+pub mod color;
+pub mod compressed;
+pub mod debug_draw;
+#[cfg(feature = "egui")]
+pub mod debug_ui;
+pub mod decal;
+pub mod deferred;
+mod device_recovery;
+pub mod environment;
+pub mod error;
+pub mod extra_window;
 pub mod fire;
+pub mod fly_camera;
+pub mod follow_camera;
+mod frame_graph;
+pub mod frame_pacing;
+pub mod frustum;
+pub mod fxaa;
+pub mod gizmo;
+pub mod gpu_options;
+pub mod gpu_timing;
+pub mod grass;
+pub mod grid;
+pub mod hdr;
+pub mod hiz;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod hot_reload;
+pub mod indirect;
+mod input;
+pub mod input_map;
+pub mod lens_flare;
+pub mod light;
+pub mod light_shafts;
+pub mod loading;
+pub mod logging;
+pub mod material_array;
 pub mod model;
+pub mod offscreen;
+pub mod oit;
+pub mod outline;
+pub mod output;
+pub mod overlay;
+pub mod post_process;
+pub mod preprocess;
+pub mod raycast;
+pub mod recording;
+pub mod render_graph;
 pub mod resources;
+pub mod scene;
+pub mod sequencer;
+pub mod shader_variants;
+pub mod shadow;
+pub mod shapes;
+pub mod simplify;
+pub mod skinning;
+pub mod skybox;
+pub mod sprites;
+pub mod ssao;
+pub mod stats;
+pub mod terrain;
 pub mod texture;
+pub mod touch;
+pub mod uniform_ring;
+pub mod upload;
+pub mod viewport;
+pub mod water;
+pub mod window_mode;
+
+pub use camera::{Camera, CameraController, Projection, OPENGL_TO_WGPU_MATRIX};
 
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen(start)]
@@ -28,18 +105,51 @@ pub fn run_web() -> Result<(), wasm_bindgen::JsValue> {
 use wasm_bindgen::prelude::*;
 
 use crate::model::{Model, ModelVertex, Vertex};
+use crate::preprocess::Preprocessor;
 pub fn run() -> anyhow::Result<()> {
+    run_with(gpu_options::GpuOptions::default())
+}
+
+// With the GPU picked by `options`
+pub fn run_with(options: gpu_options::GpuOptions) -> anyhow::Result<()> {
+    init_logging();
+    start_profiler();
+
+    let event_loop = EventLoop::with_user_event().build()?;
+    run_app(event_loop, options)
+}
+
+// RUST_LOG and the rest of logging's variables natively, the defaults on the
+// web
+fn init_logging() {
     #[cfg(not(target_arch = "wasm32"))]
-    {
-        env_logger::init();
+    match logging::LogOptions::from_env() {
+        Ok(options) => options.init(),
+        Err(e) => {
+            logging::LogOptions::default().init();
+            tracing::warn!("{:#}", e);
+        }
     }
     #[cfg(target_arch = "wasm32")]
-    {
-        console_log::init_with_level(log::Level::Info).unwrap_throw();
-    }
+    logging::LogOptions::default().init();
+}
 
-    let event_loop = EventLoop::with_user_event().build()?;
+// ===== PROFILING =====
+// CPU scopes around the frame's work, for Tracy with the profile-with-tracy
+// feature or puffin with profile-with-puffin, and compiled out without
+// either. Tracy connects to the client started here while the app runs.
+// Puffin only collects once its scopes are on, and puffin_viewer needs them
+// served by puffin_http, which isn't a dependency.
+fn start_profiler() {
+    #[cfg(feature = "profile-with-tracy")]
+    profiling::tracy_client::Client::start();
+    #[cfg(feature = "profile-with-puffin")]
+    profiling::puffin::set_scopes_on(true);
+}
+
+fn run_app(event_loop: EventLoop<Loading>, options: gpu_options::GpuOptions) -> anyhow::Result<()> {
     let mut app = App::new(
+        options,
         #[cfg(target_arch = "wasm32")]
         &event_loop,
     );
@@ -48,163 +158,94 @@ pub fn run() -> anyhow::Result<()> {
     Ok(())
 }
 
-pub struct Camera {
-    eye: cgmath::Point3<f32>,
-    target: cgmath::Point3<f32>,
-    up: cgmath::Vector3<f32>,
-    aspect: f32,
-    fovy: f32,
-    znear: f32,
-    zfar: f32,
-}
-
-impl Camera {
-    fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
-        // 1.
-        let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
-        // 2.
-        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
-
-        // 3.
-        return OPENGL_TO_WGPU_MATRIX * proj * view;
-    }
-}
-#[rustfmt::skip]
-pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::from_cols(
-    cgmath::Vector4::new(1.0, 0.0, 0.0, 0.0),
-    cgmath::Vector4::new(0.0, 1.0, 0.0, 0.0),
-    cgmath::Vector4::new(0.0, 0.0, 0.5, 0.0),
-    cgmath::Vector4::new(0.0, 0.0, 0.5, 1.0),
-);
-
-// We need this for Rust to store our data correctly for the shaders
-#[repr(C)]
-// This is so we can store this in a buffer
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct CameraUniform {
-    // We can't use cgmath with bytemuck directly, so we'll have
-    // to convert the Matrix4 into a 4x4 f32 array
-    view_proj: [[f32; 4]; 4],
-}
-
-impl CameraUniform {
-    fn new() -> Self {
-        use cgmath::SquareMatrix;
-        Self {
-            view_proj: cgmath::Matrix4::identity().into(),
+// ===== ANDROID =====
+// What android-activity calls in place of main, built into the cdylib. res/
+// isn't packed into the APK, it's read from the app's external data directory
+// (adb push res /sdcard/Android/data/<package>/files/res) unless
+// RES_DIR_VAR says somewhere else. Logs go to stderr, which logcat doesn't
+// show without a redirect.
+#[cfg(target_os = "android")]
+#[no_mangle]
+fn android_main(app: winit::platform::android::activity::AndroidApp) {
+    use winit::platform::android::EventLoopBuilderExtAndroid;
+
+    init_logging();
+    if std::env::var_os(resources::RES_DIR_VAR).is_none() {
+        if let Some(dir) = app.external_data_path() {
+            std::env::set_var(resources::RES_DIR_VAR, dir.join("res"));
         }
     }
-
-    fn update_view_proj(&mut self, camera: &Camera) {
-        self.view_proj = camera.build_view_projection_matrix().into();
-        // if NaN models wont appear
-        // log::info!("Projection Matrix {:?}", self.view_proj);
+    let result = EventLoop::with_user_event()
+        .with_android_app(app)
+        .build()
+        .map_err(anyhow::Error::from)
+        .and_then(|event_loop| run_app(event_loop, Default::default()));
+    if let Err(e) = result {
+        tracing::error!("{:#}", e);
     }
 }
-struct CameraController {
-    speed: f32,
-    is_forward_pressed: bool,
-    is_backward_pressed: bool,
-    is_left_pressed: bool,
-    is_right_pressed: bool,
-}
-
-impl CameraController {
-    fn new(speed: f32) -> Self {
-        Self {
-            speed,
-            is_forward_pressed: false,
-            is_backward_pressed: false,
-            is_left_pressed: false,
-            is_right_pressed: false,
-        }
-    }
-
-    fn process_events(&mut self, event: &WindowEvent) -> bool {
-        match event {
-            WindowEvent::KeyboardInput {
-                event:
-                    KeyEvent {
-                        state,
-                        physical_key: PhysicalKey::Code(keycode),
-                        ..
-                    },
-                ..
-            } => {
-                let is_pressed = *state == ElementState::Pressed;
-                match keycode {
-                    KeyCode::KeyW | KeyCode::ArrowUp => {
-                        self.is_forward_pressed = is_pressed;
-                        true
-                    }
-                    KeyCode::KeyA | KeyCode::ArrowLeft => {
-                        self.is_left_pressed = is_pressed;
-                        true
-                    }
-                    KeyCode::KeyS | KeyCode::ArrowDown => {
-                        self.is_backward_pressed = is_pressed;
-                        true
-                    }
-                    KeyCode::KeyD | KeyCode::ArrowRight => {
-                        self.is_right_pressed = is_pressed;
-                        true
-                    }
-                    _ => false,
-                }
-            }
-            _ => false,
-        }
-    }
-
-    fn update_camera(&self, camera: &mut Camera) {
-        use cgmath::InnerSpace;
-        let forward = camera.target - camera.eye;
-        let forward_norm = forward.normalize();
-        let forward_mag = forward.magnitude();
 
-        // Prevents glitching when the camera gets too close to the
-        // center of the scene.
-        if self.is_forward_pressed && forward_mag > self.speed {
-            camera.eye += forward_norm * self.speed;
-        }
-        if self.is_backward_pressed {
-            camera.eye -= forward_norm * self.speed;
-        }
-
-        let right = forward_norm.cross(camera.up);
-
-        // Redo radius calc in case the forward/backward is pressed.
-        let forward = camera.target - camera.eye;
-        let forward_mag = forward.magnitude();
-
-        if self.is_right_pressed {
-            // Rescale the distance between the target and the eye so
-            // that it doesn't change. The eye, therefore, still
-            // lies on the circle made by the target and eye.
-            camera.eye = camera.target - (forward + right * self.speed).normalize() * forward_mag;
-        }
-        if self.is_left_pressed {
-            camera.eye = camera.target - (forward - right * self.speed).normalize() * forward_mag;
+// ===== HEADLESS =====
+// Renders `frames` frames of the scene at `width` by `height` into a texture
+// without opening a window, saves the last one to `SCREENSHOT_DIR` and
+// returns. For rendering tests in CI and for making thumbnails on a server.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn run_headless(
+    options: gpu_options::GpuOptions,
+    width: u32,
+    height: u32,
+    frames: u32,
+) -> anyhow::Result<()> {
+    init_logging();
+    start_profiler();
+    pollster::block_on(async {
+        let gpu = Gpu::headless(&options, width, height).await?;
+        let material_layout = model::Material::create_bind_group_layout(&gpu.device);
+        let assets = SceneAssets::load(
+            &gpu.device,
+            &gpu.queue,
+            material_layout,
+            skinning::Skinning::is_supported(&gpu.adapter, &gpu.device),
+            environment::Environment::is_supported(&gpu.adapter),
+            SCENE_FILE,
+            None,
+            &loading::LoadProgress::new(SceneAssets::STEPS),
+        )
+        .await?;
+        let mut state = State::new(None, gpu, assets)?;
+        state.resize(width, height);
+        for frame in 0..frames {
+            state.capture_requested = frame + 1 == frames;
+            state.update();
+            state.render()?;
         }
-    }
+        state.device.poll(wgpu::PollType::wait_indefinitely())?;
+        tracing::info!("Rendered {} frames at {}x{}", frames, width, height);
+        Ok(())
+    })
+}
 
-    fn handle_key(&mut self, keycode: KeyCode, pressed: bool) {
-        match keycode {
-            KeyCode::KeyW | KeyCode::ArrowUp => {
-                self.is_forward_pressed = pressed;
-            }
-            KeyCode::KeyA | KeyCode::ArrowLeft => {
-                self.is_left_pressed = pressed;
-            }
-            KeyCode::KeyS | KeyCode::ArrowDown => {
-                self.is_backward_pressed = pressed;
-            }
-            KeyCode::KeyD | KeyCode::ArrowRight => {
-                self.is_right_pressed = pressed;
-            }
-            _ => {}
-        }
-    }
+// Key bindings in res/, see `InputMap::load`
+const INPUT_FILE: &str = "input.ron";
+
+// Where screenshots are written, relative to the working directory
+#[cfg(not(target_arch = "wasm32"))]
+const SCREENSHOT_DIR: &str = "screenshots";
+
+// Recordings go here, as videos or directories of frames. Every frame is
+// captured and plays back at 60 a second.
+#[cfg(not(target_arch = "wasm32"))]
+const RECORDING_DIR: &str = "recordings";
+#[cfg(not(target_arch = "wasm32"))]
+const RECORD_EVERY: u32 = 1;
+#[cfg(not(target_arch = "wasm32"))]
+const RECORD_FPS: f32 = 60.0;
+
+// What the transform gizmo moves: a fire by its index, or one of the scene's lights
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum GizmoTarget {
+    Fire(usize),
+    Light(light::LightId),
 }
 
 struct Instance {
@@ -265,482 +306,3459 @@ impl InstanceRaw {
     }
 }
 
-const NUM_INSTANCES_PER_ROW: u32 = 10;
-const INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> = cgmath::Vector3::new(
-    NUM_INSTANCES_PER_ROW as f32 * 0.5,
-    0.0,
-    NUM_INSTANCES_PER_ROW as f32 * 0.5,
-);
+// ===== SHADING MODE =====
+// Debug views for inspecting the model, swapped in on the forward pipeline
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ShadingMode {
+    Lit,
+    Wireframe,
+    Normals,
+    TexCoords,
+    Flat,
+}
+
+impl ShadingMode {
+    pub fn next(self) -> Self {
+        match self {
+            ShadingMode::Lit => ShadingMode::Wireframe,
+            ShadingMode::Wireframe => ShadingMode::Normals,
+            ShadingMode::Normals => ShadingMode::TexCoords,
+            ShadingMode::TexCoords => ShadingMode::Flat,
+            ShadingMode::Flat => ShadingMode::Lit,
+        }
+    }
+
+    fn fragment_entry_point(self) -> &'static str {
+        match self {
+            ShadingMode::Lit => "fs_main",
+            ShadingMode::Wireframe => "fs_wireframe",
+            ShadingMode::Normals => "fs_normals",
+            ShadingMode::TexCoords => "fs_tex_coords",
+            ShadingMode::Flat => "fs_flat",
+        }
+    }
+
+    fn polygon_mode(self) -> wgpu::PolygonMode {
+        match self {
+            ShadingMode::Wireframe => wgpu::PolygonMode::Line,
+            _ => wgpu::PolygonMode::Fill,
+        }
+    }
+}
+
+// ===== MODEL PIPELINE VARIANTS =====
+// Everything the forward model pipeline is keyed on in its `ShaderVariants`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+struct ModelVariant {
+    normal_map: bool,
+    shading: ShadingMode,
+    sample_count: u32,
+}
+
+impl shader_variants::VariantKey for ModelVariant {
+    fn flags(&self) -> Vec<&'static str> {
+        if self.normal_map {
+            vec!["NORMAL_MAP"]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+// shader.wgsl variants for this device's light binding and the material layout in use
+fn model_shader_variants(
+    mut preprocessor: Preprocessor,
+    device: &wgpu::Device,
+    lighting: &light::Lighting,
+    packed_materials: bool,
+) -> shader_variants::ShaderVariants<ModelVariant> {
+    lighting.shader_defines(&mut preprocessor);
+    if packed_materials {
+        preprocessor.define("PACKED_MATERIALS", "");
+        if material_array::MaterialArray::uses_push_constants(device) {
+            preprocessor.define("PUSH_CONSTANTS", "");
+        }
+    }
+    shader_variants::ShaderVariants::new("Model Shader", "shader.wgsl", preprocessor)
+}
+
+// Files the hot-reloadable shaders are built from, includes too
+#[cfg(not(target_arch = "wasm32"))]
+const FIRE_SHADER_FILES: &[&str] = &["fire_shader.wgsl", "noise.wgsl", "camera.wgsl"];
+#[cfg(not(target_arch = "wasm32"))]
+const MODEL_SHADER_FILES: &[&str] = &[
+    "shader.wgsl",
+    "lighting.wgsl",
+    "material.wgsl",
+    "material_array.wgsl",
+    "camera.wgsl",
+];
+
+// The forward pipeline for `variant`, built into `variants` the first time
+fn model_pipeline(
+    variants: &mut shader_variants::ShaderVariants<ModelVariant>,
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    depth_mode: texture::DepthMode,
+    variant: &ModelVariant,
+) -> anyhow::Result<wgpu::RenderPipeline> {
+    variants.get(device, variant, |shader, variant| {
+        create_render_pipeline(
+            device,
+            layout,
+            shader,
+            color_format,
+            variant.sample_count,
+            variant.shading,
+            depth_mode,
+        )
+    })
+}
+
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    color_format: wgpu::TextureFormat,
+    sample_count: u32,
+    shading: ShadingMode,
+    depth_mode: texture::DepthMode,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some("vs_main"),                         // 1.
+            buffers: &[ModelVertex::desc(), InstanceRaw::desc()], // 2.
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            // 3.
+            module: shader,
+            entry_point: Some(shading.fragment_entry_point()),
+            // 4. Color plus the velocity target motion blur reads
+            targets: &hdr::HdrPipeline::scene_targets(
+                color_format,
+                Some(wgpu::BlendState::REPLACE),
+            ),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList, // 1.
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw, // 2.
+            cull_mode: Some(wgpu::Face::Back),
+            // Line (the wireframe view) requires Features::POLYGON_MODE_LINE
+            polygon_mode: shading.polygon_mode(),
+            // Requires Features::DEPTH_CLIP_CONTROL
+            unclipped_depth: false,
+            // Requires Features::CONSERVATIVE_RASTERIZATION
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: depth_mode.compare(), // 1. tells draw to start from the back
+            stencil: wgpu::StencilState::default(), // 2.
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,              // 2.
+            mask: !0,                         // 3.
+            alpha_to_coverage_enabled: false, // 4.
+        },
+        multiview: None, // 5.
+        cache: None,     // 6.
+    })
+}
+
+// Where an emitter's fire starts, before `placement` moves it. Without a
+// mouth socket, Charizard's mouth is at ~80% of its height, with the flame
+// starting a little in front of the snout at the front of the bounding box.
+fn emitter_origin(
+    model: &Model,
+    emitter: &scene::SceneEmitter,
+    placement: cgmath::Matrix4<f32>,
+) -> [f32; 3] {
+    let offset = cgmath::Vector3::from(emitter.position);
+    let socket = match emitter.socket.as_deref() {
+        Some(name) => match model.socket(name) {
+            Some(socket) => socket.position().to_vec(),
+            None if name == "mouth" => model.aabb().point_at([0.5, 0.8, 1.1]).to_vec(),
+            None => {
+                tracing::warn!("The model has no {} socket for its emitter", name);
+                cgmath::Vector3::zero()
+            }
+        },
+        None => cgmath::Vector3::zero(),
+    };
+    placement
+        .transform_point(cgmath::Point3::from_vec(socket + offset))
+        .into()
+}
+
+// Every copy of the scene's model. Only one model is drawn, entries for
+// other files are left out.
+fn scene_instances(scene: &scene::Scene) -> Vec<Instance> {
+    let path = &scene.models[0].path;
+    scene
+        .models
+        .iter()
+        .filter(|model| &model.path == path)
+        .flat_map(|model| model.instances())
+        .map(|(position, rotation)| Instance { position, rotation })
+        .collect()
+}
+
+// World-space bounds of each instance of the model
+fn instance_bounds(model: &Model, instances: &[Instance]) -> Vec<model::BoundingSphere> {
+    let model_bounds = model.bounds();
+    instances
+        .iter()
+        .map(|instance| model_bounds.transformed(instance.position, instance.rotation))
+        .collect()
+}
+
+// Every mesh of every instance, mesh by mesh, in the order `IndirectDraws` draws them
+fn create_indirect_draws(
+    device: &wgpu::Device,
+    model: &Model,
+    instances: &[Instance],
+) -> indirect::IndirectDraws {
+    let mesh_bounds = model
+        .meshes
+        .iter()
+        .flat_map(|mesh| {
+            instances.iter().map(|instance| {
+                mesh.bounds
+                    .transformed(instance.position, instance.rotation)
+            })
+        })
+        .collect::<Vec<_>>();
+    indirect::IndirectDraws::new(device, model, instances.len() as u32, &mesh_bounds)
+}
+
+// Binds each skin of the rig to its mesh of the model
+fn skin_meshes(
+    device: &wgpu::Device,
+    skinning: Option<&skinning::Skinning>,
+    model: &Model,
+    rig: Option<animation::Rig>,
+) -> (Vec<skinning::SkinnedMesh>, Option<animation::Animator>) {
+    match (skinning, rig) {
+        (Some(skinning), Some(rig)) => {
+            let skinned_meshes = rig
+                .skins
+                .iter()
+                .map(|skin| {
+                    skinning::SkinnedMesh::new(
+                        device,
+                        skinning,
+                        &model.meshes[skin.mesh],
+                        &skin.bind_pose,
+                        &skin.vertices,
+                        skin.joints.len(),
+                    )
+                })
+                .collect();
+            (skinned_meshes, Some(animation::Animator::new(rig)))
+        }
+        _ => (Vec::new(), None),
+    }
+}
 
 pub struct State {
-    surface: wgpu::Surface<'static>,
+    instance: wgpu::Instance,
+    adapter: wgpu::Adapter,
+    output: output::FrameOutput,
     device: wgpu::Device,
     queue: wgpu::Queue,
+    device_lost: Arc<AtomicBool>,
     config: wgpu::SurfaceConfiguration,
+    // The surface's configuration at the size the scene renders at, which
+    // post-processing scales up or down to the surface's
+    render_config: wgpu::SurfaceConfiguration,
+    render_scale: f32,
     is_surface_configured: bool,
     clear_color: wgpu::Color,
+    // Where the mouse is, as a fraction of the window from the top left
+    cursor: [f32; 2],
+    touch: touch::TouchGestures,
+    input_map: input_map::InputMap,
+    window_mode: window_mode::WindowMode,
+    // Hidden and held in the window while a fly camera has mouselook
+    cursor_grabbed: bool,
     render_pipeline: wgpu::RenderPipeline,
-    diffuse_bind_group: wgpu::BindGroup,
-    diffuse_texture: texture::Texture,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    model_variants: shader_variants::ShaderVariants<ModelVariant>,
+    normal_mapping: bool,
+    #[allow(dead_code)]
+    diffuse_material: model::Material,
+    // The active one of `cameras` as of the last update
     camera: Camera,
-    camera_controller: CameraController,
-    camera_buffer: wgpu::Buffer,
-    camera_uniform: CameraUniform,
-    camera_bind_group: wgpu::BindGroup,
+    cameras: cameras::Cameras,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    // Main camera's view volume as of the last update, for culling
+    frustum: frustum::Frustum,
     instances: Vec<Instance>,
     instance_buffer: wgpu::Buffer,
-    window: Arc<Window>,
-    obj_model: Model,
+    // World-space bounds of each instance, in the same order
+    instance_bounds: Vec<model::BoundingSphere>,
+    // Instances that passed frustum and occlusion culling, drawn by the main camera
+    visible_instance_buffer: wgpu::Buffer,
+    // Ranges of `visible_instance_buffer` per level of detail
+    visible_lods: Vec<std::ops::Range<u32>>,
+    occlusion: Option<hiz::OcclusionCuller>,
+    // Times each render graph pass on the GPU while it's enabled
+    gpu_timer: Option<gpu_timing::GpuTimer>,
+    // Results read back since timings were last logged
+    gpu_timing_results: u32,
+    // FPS, frame times and the frame's counters drawn over the frame
+    stats: Option<stats::StatsOverlay>,
+    // 2D sprites over the frame: the crosshair and the fire's fuel bar
+    sprites: Option<sprites::SpriteLayer>,
+    hud_enabled: bool,
+    // Handles for dragging a fire or light about, and which one's selected
+    gizmo: gizmo::Gizmo,
+    gizmo_target: usize,
+    // Lines over the frame for bounds, particle velocities and lights
+    debug_draw: debug_draw::DebugDraw,
+    debug_draw_enabled: bool,
+    // egui windows over the frame, only with a window to take input from
+    #[cfg(feature = "egui")]
+    debug_ui: Option<debug_ui::DebugUi>,
+    // Draws culled and issued from the GPU, an alternative to the visible instances
+    indirect_draws: Option<indirect::IndirectDraws>,
+    // Skins `skinned_meshes` into their vertex buffers ahead of every pass that draws them
+    skinning: Option<skinning::Skinning>,
+    skinned_meshes: Vec<skinning::SkinnedMesh>,
+    // Poses the skeleton behind `skinned_meshes`
+    animator: Option<animation::Animator>,
+    // Everything loaded from res/
+    assets: assets::Assets,
+    obj_model: assets::Handle<Model>,
+    // The model's materials packed for the forward pass
+    material_array: Option<material_array::MaterialArray>,
     depth_texture: texture::Texture,
-    fire_system: fire::FireSystem,
+    // One for each emitter, with the emitter and where it was placed
+    fire_systems: Vec<fire::FireSystem>,
+    emitters: Vec<(scene::SceneEmitter, cgmath::Matrix4<f32>)>,
+    // The scene file as last loaded, and the prefabs spawned since
+    scene: scene::Scene,
+    spawned: Vec<scene::PrefabSpawn>,
+    // Per-frame buffer writes, copied in one batch when the frame is submitted
+    uploads: upload::Uploads,
+    skybox: skybox::Skybox,
+    // Grid lines on y = 0 and the world axes, to see where things sit
+    grid: grid::Grid,
+    // The ground the models stand on, see `set_terrain`
+    terrain: Option<terrain::Terrain>,
+    water: Option<water::Water>,
+    grass: Option<grass::Grass>,
+    shadows: shadow::ShadowCascades,
+    lighting: light::Lighting,
+    // The lights the scene file and its prefabs placed
+    scene_lights: Vec<light::LightId>,
+    // Flickering lights while the fires burn, by fire system
+    fire_lights: Vec<(usize, light::LightId)>,
+    ssao: ssao::Ssao,
+    deferred: deferred::DeferredRenderer,
+    hdr: hdr::HdrPipeline,
+    post_process: post_process::PostProcessChain,
+    fxaa: fxaa::Fxaa,
+    oit: oit::Oit,
+    lens_flare: lens_flare::LensFlare,
+    decals: decal::DecalSystem,
+    outline: outline::Outline,
+    // Instance highlighted with the outline
+    selected_instance: Option<u32>,
+    // Extra cameras drawn over part of the screen, see `render_split_views`
+    split_views: Vec<viewport::SplitView>,
+    split_views_enabled: bool,
+    viewport_clear: viewport::ViewportClear,
+    // Overview camera rendered into its own texture and shown as a thumbnail
+    security_camera: viewport::ViewCamera,
+    security_target: offscreen::OffscreenTarget,
+    security_camera_enabled: bool,
+    // More windows onto the scene, drawn when they ask for a redraw
+    extra_windows: Vec<extra_window::ExtraWindow>,
+    // The one of them looking at the first fire close up
+    particle_window: Option<WindowId>,
+    thumbnail: offscreen::Thumbnail,
+    render_graph: render_graph::RenderGraph<State>,
+    render_path: deferred::RenderPath,
+    shading: ShadingMode,
+    depth_mode: texture::DepthMode,
     last_update: std::time::Instant,
+    // Caps the frame rate, mostly for when vsync is off
+    frame_limiter: frame_pacing::FrameLimiter,
+    // Particles move in steps of the same length whatever the frame rate
+    particle_timestep: frame_pacing::FixedTimestep,
     fire_enabled: bool,
+    // The scene's scripted demo
+    sequencer: sequencer::Sequencer,
+    fxaa_enabled: bool,
+    ssao_enabled: bool,
+    oit_enabled: bool,
+    msaa_samples: u32, // Requested sample count, see `sample_count`
+    supported_sample_counts: Vec<u32>,
+    #[cfg(not(target_arch = "wasm32"))]
+    shader_watcher: hot_reload::ShaderWatcher,
+    #[cfg(not(target_arch = "wasm32"))]
+    asset_watcher: hot_reload::AssetWatcher,
+    // Assets loading in the background after something in res/ changed
+    #[cfg(not(target_arch = "wasm32"))]
+    asset_reload: Option<loading::AssetLoader<SceneAssets>>,
+    // What reloaded materials are created with
+    #[cfg(not(target_arch = "wasm32"))]
+    material_layout: wgpu::BindGroupLayout,
+    #[cfg(not(target_arch = "wasm32"))]
+    material_array_layout: wgpu::BindGroupLayout,
+    // Whether reloads look for an HDR environment
+    #[cfg(not(target_arch = "wasm32"))]
+    hdr_environment: bool,
+    // Drawn instead of the scene's model after being dropped onto the window
+    #[cfg(not(target_arch = "wasm32"))]
+    dropped_model: Option<String>,
+    // Frames the model once the running reload is in, for dropped models
+    #[cfg(not(target_arch = "wasm32"))]
+    frame_on_reload: bool,
+    // Saves the next frame rendered as a PNG
+    #[cfg(not(target_arch = "wasm32"))]
+    capture_requested: bool,
+    // Captures frames as they're rendered, stepping the scene at a fixed rate
+    #[cfg(not(target_arch = "wasm32"))]
+    recording: Option<recording::Recording>,
 }
 
-impl State {
-    // We don't need this to be async right now,
-    // but we will in the next tutorial
-    async fn new(window: Arc<Window>) -> anyhow::Result<State> {
-        let size = window.inner_size();
+// The window's connection to the GPU, made before anything is loaded so the
+// loading screen can draw. Headless there's no window and no surface.
+struct Gpu {
+    // Kept for the surfaces of any more windows
+    instance: wgpu::Instance,
+    surface: Option<wgpu::Surface<'static>>,
+    adapter: wgpu::Adapter,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    config: wgpu::SurfaceConfiguration,
+    intermediate_format: color::IntermediateFormat,
+    // Set from the device lost callback, see `App::recover_device`
+    device_lost: Arc<AtomicBool>,
+}
 
-        // The instance is a handle to our GPU
-        // BackendBit::PRIMARY => Vulkan + Metal + DX12 + Browser WebGPU
-        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
-            #[cfg(not(target_arch = "wasm32"))]
-            backends: wgpu::Backends::PRIMARY,
-            #[cfg(target_arch = "wasm32")]
-            backends: wgpu::Backends::GL,
-            ..Default::default()
-        });
+impl Gpu {
+    async fn new(window: Arc<Window>, options: &gpu_options::GpuOptions) -> anyhow::Result<Gpu> {
+        let size = window.inner_size();
+        let instance = options.create_instance();
+        let surface = instance
+            .create_surface(window.clone())
+            .map_err(error::Error::Surface)?;
+        Self::with_surface(options, instance, Some(surface), size.width, size.height).await
+    }
 
-        let surface = instance.create_surface(window.clone()).unwrap();
+    // Frames go into a texture of this size rather than onto a window
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn headless(
+        options: &gpu_options::GpuOptions,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Gpu> {
+        Self::with_surface(options, options.create_instance(), None, width, height).await
+    }
 
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await?;
+    #[tracing::instrument(skip_all)]
+    async fn with_surface(
+        options: &gpu_options::GpuOptions,
+        instance: wgpu::Instance,
+        surface: Option<wgpu::Surface<'static>>,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Gpu> {
+        let adapter = options.request_adapter(&instance, surface.as_ref()).await?;
+        let intermediate_format = if options.intermediate_format.is_supported(&adapter) {
+            options.intermediate_format
+        } else {
+            tracing::warn!(
+                "{:?} targets aren't supported, using Rgba16Float",
+                options.intermediate_format
+            );
+            color::IntermediateFormat::Rgba16Float
+        };
         let (device, queue) = adapter
             .request_device(&wgpu::DeviceDescriptor {
                 label: None,
-                required_features: wgpu::Features::empty(),
+                // Wireframe shading needs line polygons, which not every backend has
+                required_features: (adapter.features() & wgpu::Features::POLYGON_MODE_LINE)
+                    | (adapter.features() & indirect::IndirectDraws::REQUIRED_FEATURES)
+                    | (adapter.features() & material_array::MaterialArray::PUSH_CONSTANT_FEATURES)
+                    // KTX2 and DDS textures stay compressed in video memory where possible
+                    | (adapter.features() & wgpu::Features::TEXTURE_COMPRESSION_BC)
+                    // Per-pass GPU timings, see `gpu_timing`
+                    | (adapter.features() & gpu_timing::GpuTimer::FEATURES)
+                    | intermediate_format.required_features(),
                 experimental_features: wgpu::ExperimentalFeatures::disabled(),
-                // WebGL doesn't support all of wgpu's features, so if
-                // we're building for the web we'll have to disable some.
-                required_limits: if cfg!(target_arch = "wasm32") {
-                    wgpu::Limits::downlevel_webgl2_defaults()
-                } else {
-                    wgpu::Limits::default()
+                required_limits: wgpu::Limits {
+                    // Room for the per-draw data where there are push constants
+                    max_push_constant_size: adapter
+                        .limits()
+                        .max_push_constant_size
+                        .min(material_array::PUSH_CONSTANT_SIZE),
+                    // WebGL doesn't support all of wgpu's features, so if
+                    // we're building for the web we'll have to disable some.
+                    ..if cfg!(target_arch = "wasm32") {
+                        wgpu::Limits::downlevel_webgl2_defaults()
+                    } else {
+                        wgpu::Limits::default()
+                    }
                 },
                 memory_hints: Default::default(),
                 trace: wgpu::Trace::Off,
             })
             .await?;
+        // Driver crashes and resets, GPUs unplugged or taken away by the OS.
+        // Destroyed is only when `destroy` was called, never here.
+        let device_lost = Arc::new(AtomicBool::new(false));
+        {
+            let device_lost = device_lost.clone();
+            device.set_device_lost_callback(move |reason, message| {
+                if reason == wgpu::DeviceLostReason::Unknown {
+                    tracing::error!("Lost the GPU device: {}", message);
+                    device_lost.store(true, Ordering::Relaxed);
+                }
+            });
+        }
+        // Errors are still fatal like wgpu has them by default, except for
+        // the ones from using the device after it's gone, until it's replaced
+        {
+            let device_lost = device_lost.clone();
+            device.on_uncaptured_error(Arc::new(move |error| {
+                if device_lost.load(Ordering::Relaxed) {
+                    tracing::debug!("On the lost device: {}", error);
+                } else {
+                    panic!("wgpu error: {}", error);
+                }
+            }));
+        }
+
+        let config = match &surface {
+            Some(surface) => {
+                let surface_caps = surface.get_capabilities(&adapter);
+                let surface_format = Self::surface_format(&surface_caps)?;
+                wgpu::SurfaceConfiguration {
+                    // Copyable where it can be, for screenshots
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | (surface_caps.usages & wgpu::TextureUsages::COPY_SRC),
+                    format: surface_format,
+                    width,
+                    height,
+                    present_mode: surface_caps.present_modes[0],
+                    // Android surfaces tend to only offer Inherit
+                    alpha_mode: if surface_caps
+                        .alpha_modes
+                        .contains(&wgpu::CompositeAlphaMode::Opaque)
+                    {
+                        wgpu::CompositeAlphaMode::Opaque
+                    } else {
+                        surface_caps.alpha_modes[0]
+                    },
+                    view_formats: vec![],
+                    desired_maximum_frame_latency: 2,
+                }
+            }
+            // What the headless texture's made with, copyable so frames can
+            // be read back
+            None => wgpu::SurfaceConfiguration {
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                width,
+                height,
+                present_mode: wgpu::PresentMode::Fifo,
+                alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+                view_formats: vec![],
+                desired_maximum_frame_latency: 2,
+            },
+        };
+
+        Ok(Self {
+            instance,
+            surface,
+            adapter,
+            device,
+            queue,
+            config,
+            intermediate_format,
+            device_lost,
+        })
+    }
 
-        let surface_caps = surface.get_capabilities(&adapter);
-        // Shader code in this tutorial assumes an sRGB surface texture. Using a different
-        // one will result in all the colors coming out darker. If you want to support non
-        // sRGB surfaces, you'll need to account for that when drawing to the frame.
-        let surface_format = surface_caps
-            .formats
+    // Prefer an sRGB surface so the hardware encodes the final pass. Other formats
+    // work too, the tonemap pass encodes itself then (see `color`). Desktops
+    // mostly offer Bgra8, Android Rgba8 and sometimes only 10-bit or float
+    // formats, 8-bit ones come first since frame capture reads those.
+    fn surface_format(
+        surface_caps: &wgpu::SurfaceCapabilities,
+    ) -> anyhow::Result<wgpu::TextureFormat> {
+        use wgpu::TextureFormat::*;
+        let formats = &surface_caps.formats;
+        formats
             .iter()
             .find(|f| f.is_srgb())
+            .or_else(|| {
+                formats
+                    .iter()
+                    .find(|f| matches!(f, Rgba8Unorm | Bgra8Unorm))
+            })
+            .or(formats.first())
             .copied()
-            .unwrap_or(surface_caps.formats[0]);
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
-            width: size.width,
-            height: size.height,
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
-            view_formats: vec![],
-            desired_maximum_frame_latency: 2,
-        };
+            .context("The adapter can't present to the window's surface")
+    }
+}
 
-        let diffuse_bytes = include_bytes!("firered.png");
-        let diffuse_texture =
-            texture::Texture::from_bytes(&device, &queue, diffuse_bytes, "firered.png").unwrap();
+// What's shown, a .ron or .json scene file in res/
+const SCENE_FILE: &str = "scene.ron";
+
+// Everything the scene loads from disk, or over the network on the web
+struct SceneAssets {
+    // The layout the model's materials were created with
+    material_layout: wgpu::BindGroupLayout,
+    // Holds the model and cubemap
+    assets: assets::Assets,
+    model: assets::Handle<Model>,
+    cubemap: Option<assets::Handle<texture::Texture>>,
+    // Lights the scene and stands in for the cubemap when there's an HDR sky
+    environment: Option<environment::Environment>,
+    // What goes where, the model above is its first
+    scene: scene::Scene,
+    // The scene's ground, when it has some and the heightmap loaded
+    heightmap: Option<terrain::Heightmap>,
+    ground: Option<assets::Handle<texture::Texture>>,
+    input_map: input_map::InputMap,
+}
 
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        // This should match the filterable field of the
-                        // corresponding Texture entry above.
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
-        let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: Some("diffuse_bind_group"),
+impl SceneAssets {
+    // Calls to `LoadProgress::finished` in `load`
+    const STEPS: u32 = 4;
+
+    #[allow(clippy::too_many_arguments)]
+    #[tracing::instrument(skip_all, fields(scene = scene_file))]
+    async fn load(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        material_layout: wgpu::BindGroupLayout,
+        skinning: bool,
+        hdr_environment: bool,
+        scene_file: &str,
+        model_file: Option<String>,
+        progress: &loading::LoadProgress,
+    ) -> anyhow::Result<SceneAssets> {
+        let mut scene = scene::Scene::load(scene_file).await?;
+        // Drawn in place of the scene's own model, like one dropped onto the
+        // window
+        if let Some(path) = model_file {
+            let entry = scene
+                .models
+                .first_mut()
+                .with_context(|| format!("{} has no models", scene_file))?;
+            entry.path = path;
+            entry.fallback = None;
+        }
+        let models = scene
+            .models
+            .iter()
+            .map(|model| model.path.as_str())
+            .collect::<std::collections::BTreeSet<_>>();
+        if models.len() > 1 {
+            tracing::warn!(
+                "{} has {} different models, only {} is drawn",
+                scene_file,
+                models.len(),
+                scene.models[0].path
+            );
+        }
+        progress.finished("scene");
+
+        let mut assets = assets::Assets::new(device, queue);
+        // Some(angle) recomputes the model's normals, keeping edges past it hard
+        assets.normal_smoothing = None;
+        // Two coarser levels at half the triangles each, None to go without
+        assets.lod_settings = Some(simplify::LodSettings {
+            levels: 2,
+            ratio: 0.5,
         });
-
-        // https://github.com/sotrh/learn-wgpu/issues/623#issuecomment-3215360477
-        let mut camera = Camera {
-            eye: (0.0, 1.0, 2.0).into(),
-            target: (0.0, 0.0, 0.0).into(),
-            up: cgmath::Vector3::unit_y(),
-            aspect: 1.0, // default aspect ratio, this is ` config.width as f32 / config.height as f32 ` in the tutorial
-            fovy: 45.0,  // default field of view
-            znear: 0.1,  // > 0
-            zfar: 100.0, // > znear
+        let entry = &scene.models[0];
+        let model = match (
+            assets
+                .load_model(&entry.path, &material_layout, skinning)
+                .await
+                .map_err(anyhow::Error::from),
+            &entry.fallback,
+        ) {
+            (Ok(model), _) => model,
+            (Err(e), Some(fallback)) => {
+                tracing::warn!("Couldn't load {}, using {}: {:#}", entry.path, fallback, e);
+                assets
+                    .load_model(fallback, &material_layout, skinning)
+                    .await?
+            }
+            (Err(e), None) => return Err(e),
+        };
+        progress.finished("model");
+
+        // An HDR environment lights the scene too, the cubemap only shows
+        // behind it and the gradient stands in when there's neither on disk
+        let environment = if hdr_environment {
+            match resources::load_environment("environment.hdr", device, queue).await {
+                Ok(environment) => Some(environment),
+                Err(e) => {
+                    tracing::info!("No HDR environment: {:#}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let cubemap = match environment {
+            Some(_) => None,
+            None => match assets.load_cubemap("skybox").await {
+                Ok(cubemap) => Some(cubemap),
+                Err(e) => {
+                    tracing::warn!("Couldn't load skybox, using a gradient: {:#}", e);
+                    None
+                }
+            },
+        };
+        progress.finished("skybox");
+
+        // The scene goes without ground rather than failing over it
+        let (heightmap, ground) = match &scene.terrain {
+            Some(terrain) => {
+                let heightmap = match resources::load_binary(&terrain.heightmap)
+                    .await
+                    .map_err(anyhow::Error::from)
+                    .and_then(|data| resources::decode_image(&data))
+                {
+                    Ok(image) => Some(terrain::Heightmap::from_image(&image)),
+                    Err(e) => {
+                        tracing::warn!("Couldn't load {}, no terrain: {:#}", terrain.heightmap, e);
+                        None
+                    }
+                };
+                let ground = match &terrain.texture {
+                    Some(file_name) => match assets
+                        .load_texture(file_name, color::ColorSpace::Srgb)
+                        .await
+                    {
+                        Ok(ground) => Some(ground),
+                        Err(e) => {
+                            tracing::warn!(
+                                "Couldn't load {}, the terrain is plain: {}",
+                                file_name,
+                                e.report()
+                            );
+                            None
+                        }
+                    },
+                    None => None,
+                };
+                (heightmap, ground)
+            }
+            None => (None, None),
+        };
+        progress.finished("terrain");
+
+        // Rebinding keys is optional, the defaults stand in without the file
+        let input_map = match input_map::InputMap::load(INPUT_FILE).await {
+            Ok(input_map) => input_map,
+            Err(e) => {
+                tracing::info!("Default key bindings: {:#}", e);
+                input_map::InputMap::default()
+            }
         };
 
-        // let camera = Camera {
-        //     // position the camera 1 unit up and 2 units back
-        //     // +z is out of the screen
-        //     eye: (0.0, 1.0, 2.0).into(),
-        //     // have it look at the origin
-        //     target: (0.0, 0.0, 0.0).into(),
-        //     // which way is "up"
-        //     up: cgmath::Vector3::unit_y(),
-        //     aspect: config.width as f32 / config.height as f32,
-        //     fovy: 45.0,
-        //     znear: 0.1,
-        //     zfar: 100.0,
-        // };
+        Ok(Self {
+            material_layout,
+            assets,
+            model,
+            cubemap,
+            environment,
+            scene,
+            heightmap,
+            ground,
+            input_map,
+        })
+    }
+}
 
-        let mut camera_uniform = CameraUniform::new();
-        camera_uniform.update_view_proj(&camera);
+// ===== LOADING =====
+// The window while its assets load in the background. It shows the loading
+// screen until they're in, then becomes the `State` with the scene.
+pub struct Loading {
+    window: Arc<Window>,
+    gpu: Gpu,
+    is_surface_configured: bool,
+    screen: loading::LoadingScreen,
+    assets: loading::AssetLoader<SceneAssets>,
+}
 
-        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Camera Buffer"),
-            contents: bytemuck::cast_slice(&[camera_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-        let camera_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("camera_bind_group_layout"),
-            });
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
-            label: Some("camera_bind_group"),
+impl Loading {
+    // `model_file` is drawn in place of the scene's model, see
+    // `SceneAssets::load`
+    #[tracing::instrument(skip_all)]
+    async fn new(
+        window: Arc<Window>,
+        options: &gpu_options::GpuOptions,
+        model_file: Option<String>,
+    ) -> anyhow::Result<Loading> {
+        let gpu = Gpu::new(window.clone(), options).await?;
+        let material_layout = model::Material::create_bind_group_layout(&gpu.device);
+        let skinning = skinning::Skinning::is_supported(&gpu.adapter, &gpu.device);
+        let hdr_environment = environment::Environment::is_supported(&gpu.adapter);
+        let (device, queue) = (gpu.device.clone(), gpu.queue.clone());
+        let assets = loading::AssetLoader::spawn(SceneAssets::STEPS, move |progress| async move {
+            SceneAssets::load(
+                &device,
+                &queue,
+                material_layout,
+                skinning,
+                hdr_environment,
+                SCENE_FILE,
+                model_file,
+                &progress,
+            )
+            .await
         });
-        let camera_controller = CameraController::new(0.2);
+        let screen = loading::LoadingScreen::new(&gpu.device, gpu.config.format);
+        Ok(Self {
+            window,
+            gpu,
+            is_surface_configured: false,
+            screen,
+            assets,
+        })
+    }
 
-        let shader = device.create_shader_module(wgpu::include_wgsl!("shader.wgsl"));
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-        const SPACE_BETWEEN: f32 = 3.0;
-        let instances = (0..NUM_INSTANCES_PER_ROW)
-            .flat_map(|z| {
-                (0..NUM_INSTANCES_PER_ROW).map(move |x| {
-                    let x = SPACE_BETWEEN * (x as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-                    let z = SPACE_BETWEEN * (z as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-
-                    let position = cgmath::Vector3 { x, y: 0.0, z };
-
-                    let rotation = if position.is_zero() {
-                        cgmath::Quaternion::from_axis_angle(
-                            cgmath::Vector3::unit_z(),
-                            cgmath::Deg(0.0),
-                        )
-                    } else {
-                        cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
-                    };
+    // See `FrameOutput::suspend`
+    fn suspend(&mut self) {
+        self.gpu.surface = None;
+        self.is_surface_configured = false;
+    }
 
-                    Instance { position, rotation }
-                })
-            })
-            .collect::<Vec<_>>();
+    fn resume(&mut self) -> anyhow::Result<()> {
+        if self.gpu.surface.is_none() {
+            let surface = self.gpu.instance.create_surface(self.window.clone())?;
+            self.gpu.surface = Some(surface);
+        }
+        let size = self.window.inner_size();
+        self.resize(size.width, size.height);
+        Ok(())
+    }
 
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+    fn resize(&mut self, width: u32, height: u32) {
+        if width > 0 && height > 0 {
+            self.gpu.config.width = width;
+            self.gpu.config.height = height;
+            if let Some(surface) = &self.gpu.surface {
+                surface.configure(&self.gpu.device, &self.gpu.config);
+                self.is_surface_configured = true;
+            }
+        }
+    }
+
+    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        self.window.request_redraw();
+        let (true, Some(surface)) = (self.is_surface_configured, &self.gpu.surface) else {
+            return Ok(());
+        };
+
+        let output = surface.get_current_texture()?;
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = self
+            .gpu
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Loading Encoder"),
+            });
+        let aspect = self.gpu.config.width as f32 / self.gpu.config.height as f32;
+        self.screen.render(
+            &self.gpu.queue,
+            &mut encoder,
+            &view,
+            self.assets.progress(),
+            aspect,
+        );
+        self.gpu.queue.submit(std::iter::once(encoder.finish()));
+        output.present();
+        Ok(())
+    }
+
+    // The scene, built on the window's surface as it's configured now
+    fn into_state(self, assets: SceneAssets) -> anyhow::Result<State> {
+        let (width, height) = (self.gpu.config.width, self.gpu.config.height);
+        let mut state = State::new(Some(self.window), self.gpu, assets)?;
+        state.resize(width, height);
+        Ok(state)
+    }
+}
+
+impl State {
+    // Builds the scene once its assets have loaded, for the window or
+    // headless without one
+    #[tracing::instrument(skip_all)]
+    fn new(window: Option<Arc<Window>>, gpu: Gpu, scene: SceneAssets) -> anyhow::Result<State> {
+        let Gpu {
+            instance,
+            surface,
+            adapter,
+            device,
+            queue,
+            config,
+            intermediate_format,
+            device_lost,
+        } = gpu;
+        let SceneAssets {
+            material_layout: texture_bind_group_layout,
+            mut assets,
+            model: obj_model,
+            cubemap,
+            environment,
+            scene,
+            heightmap,
+            ground,
+            input_map,
+        } = scene;
+
+        let diffuse_bytes = include_bytes!("firered.png");
+        let diffuse_texture =
+            texture::Texture::from_bytes(&device, &queue, diffuse_bytes, "firered.png")?;
+
+        let diffuse_material = model::Material::new(
+            &device,
+            "firered",
+            diffuse_texture,
+            assets.flat_normal_texture(),
+            assets.white_texture(),
+            assets.white_texture(),
+            assets.white_texture(),
+            model::MaterialUniform::default(),
+            &texture_bind_group_layout,
+        );
+
+        // Reverse-Z for the depth precision, set to DepthMode::Standard to compare
+        let depth_mode = texture::DepthMode::ReverseZ;
+
+        // https://github.com/sotrh/learn-wgpu/issues/623#issuecomment-3215360477
+        let camera = Camera {
+            eye: scene.camera.eye.into(),
+            target: scene.camera.target.into(),
+            up: cgmath::Vector3::unit_y(),
+            aspect: 1.0, // default aspect ratio, this is ` config.width as f32 / config.height as f32 ` in the tutorial
+            fovy: scene.camera.fovy,
+            znear: scene.camera.znear, // > 0
+            zfar: scene.camera.zfar,   // > znear
+            depth_mode,
+            projection: Projection::Perspective,
+        };
+
+        // let camera = Camera {
+        //     // position the camera 1 unit up and 2 units back
+        //     // +z is out of the screen
+        //     eye: (0.0, 1.0, 2.0).into(),
+        //     // have it look at the origin
+        //     target: (0.0, 0.0, 0.0).into(),
+        //     // which way is "up"
+        //     up: cgmath::Vector3::unit_y(),
+        //     aspect: config.width as f32 / config.height as f32,
+        //     fovy: 45.0,
+        //     znear: 0.1,
+        //     zfar: 100.0,
+        // };
+
+        let camera_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("camera_bind_group_layout"),
+            });
+        // Q steps through them: orbiting the scene's camera target, flying
+        // from where it starts, and a wide shot from up on the hill
+        let camera_damping = camera_damping::CameraDamping::new(
+            scene.camera.position_damping,
+            scene.camera.rotation_damping,
+        );
+        let mut cameras = cameras::Cameras::new(
+            &device,
+            &camera_bind_group_layout,
+            "orbit",
+            camera,
+            cameras::CameraControl::Orbit(CameraController::new(0.2)),
+            camera_damping,
+        );
+        cameras.add(
+            &device,
+            &camera_bind_group_layout,
+            "fly",
+            camera,
+            cameras::CameraControl::Fly(fly_camera::FlyCamera::new(4.0)),
+            camera_damping,
+        );
+        cameras.add(
+            &device,
+            &camera_bind_group_layout,
+            "cinematic",
+            Camera {
+                eye: (14.0, 6.0, 18.0).into(),
+                target: (0.0, 0.5, 0.0).into(),
+                fovy: 30.0,
+                ..camera
+            },
+            cameras::CameraControl::Fixed,
+            camera_damping,
+        );
+
+        let shadows =
+            shadow::ShadowCascades::new(&device, &[ModelVertex::desc(), InstanceRaw::desc()]);
+
+        let mut lighting = light::Lighting::new(&device, &shadows);
+        if let Some(environment) = &environment {
+            lighting.set_environment(&device, environment);
+        }
+        let ssao = ssao::Ssao::new(
+            &device,
+            &config,
+            &camera_bind_group_layout,
+            &[ModelVertex::desc(), InstanceRaw::desc()],
+            depth_mode,
+        );
+
+        let skinning = if skinning::Skinning::is_supported(&adapter, &device) {
+            Some(skinning::Skinning::new(&device))
+        } else {
+            tracing::warn!(
+                "Compute shaders aren't available, skinned meshes stay in their bind pose"
+            );
+            None
+        };
+
+        let model = assets.model(obj_model);
+        let (skinned_meshes, animator) = skin_meshes(
+            &device,
+            skinning.as_ref(),
+            model,
+            assets.rig(obj_model).cloned(),
+        );
+
+        tracing::info!(
+            "Model loaded with {} meshes, {} materials",
+            model.meshes.len(),
+            model.materials.len()
+        );
+        for (i, mesh) in model.meshes.iter().enumerate() {
+            tracing::info!("  Mesh {}: {} indices", i, mesh.num_elements);
+        }
+
+        // Packs the model's materials into texture arrays so all of its meshes
+        // share one bind group in the forward pass, set to false to bind each
+        // material on its own
+        let packed_materials = true;
+        let material_array_layout =
+            material_array::MaterialArray::create_bind_group_layout(&device);
+        let material_array = if packed_materials {
+            let material_array =
+                material_array::MaterialArray::new(&device, &queue, model, &material_array_layout);
+            if material_array.is_none() {
+                tracing::warn!(
+                    "Can't pack {} materials, binding them one by one",
+                    model.materials.len()
+                );
+            }
+            material_array
+        } else {
+            None
+        };
+
+        // The model shader shares its lighting and material code with the deferred path
+        let mut model_variants = model_shader_variants(
+            Preprocessor::new(),
+            &device,
+            &lighting,
+            material_array.is_some(),
+        );
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout"),
+                bind_group_layouts: &[
+                    if material_array.is_some() {
+                        &material_array_layout
+                    } else {
+                        &texture_bind_group_layout
+                    },
+                    &camera_bind_group_layout,
+                    &lighting.bind_group_layout,
+                    &ssao.bind_group_layout,
+                ],
+                push_constant_ranges: &if material_array.is_some() {
+                    material_array::MaterialArray::push_constant_ranges(&device)
+                } else {
+                    Vec::new()
+                },
+            });
+        let instances = scene_instances(&scene);
+
+        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Instance Buffer"),
             contents: bytemuck::cast_slice(&instance_data),
             usage: wgpu::BufferUsages::VERTEX,
         });
-        let depth_texture =
-            texture::Texture::create_depth_texture(&device, &config, "depth_texture");
-
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"), // 1.
-                buffers: &[ModelVertex::desc(), InstanceRaw::desc()], // 2.
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                // 3.
-                module: &shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState {
-                    // 4.
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList, // 1.
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw, // 2.
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: texture::Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less, // 1. tells draw to start from the back
-                stencil: wgpu::StencilState::default(),     // 2.
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,                         // 2.
-                mask: !0,                         // 3.
-                alpha_to_coverage_enabled: false, // 4.
-            },
-            multiview: None, // 5.
-            cache: None,     // 6.
-        });
+        // MSAA sample counts every multisampled target (HDR, depth, OIT) can use
+        let sample_formats = [
+            intermediate_format.texture_format(),
+            hdr::HdrPipeline::VELOCITY_FORMAT,
+            hdr::HdrPipeline::LINEAR_DEPTH_FORMAT,
+            texture::Texture::DEPTH_FORMAT,
+            oit::Oit::ACCUM_FORMAT,
+            oit::Oit::REVEALAGE_FORMAT,
+        ];
+        let supported_sample_counts = [1, 2, 4, 8]
+            .into_iter()
+            .filter(|&count| {
+                sample_formats.iter().all(|&format| {
+                    adapter
+                        .get_texture_format_features(format)
+                        .flags
+                        .sample_count_supported(count)
+                })
+            })
+            .collect::<Vec<_>>();
+        let msaa_samples = if supported_sample_counts.contains(&4) {
+            4
+        } else {
+            1
+        };
+        // Everything starts single-sampled so the deferred renderer can read the
+        // depth buffer; MSAA is switched on once the state exists
+        let sample_count = 1;
 
-        let obj_model = resources::load_model(
-            "charizard/Charizard.obj",
+        let depth_texture =
+            texture::Texture::create_depth_texture(&device, &config, sample_count, "depth_texture");
+        // Everything in the scene renders in HDR and gets tonemapped at the end
+        let hdr = hdr::HdrPipeline::new(
+            &device,
+            &config,
+            intermediate_format.texture_format(),
+            sample_count,
+        );
+        // Screen effects in the order they run, the tonemapper has to stay last
+        let mut post_process = post_process::PostProcessChain::new(&device, &config, hdr.format());
+        let mut motion_blur = post_process::MotionBlur::new(&device, hdr.format());
+        motion_blur.set_velocity(hdr.velocity_view().clone());
+        post_process.push(motion_blur);
+        // Off by default, for cinematic shots of the model
+        let mut depth_of_field = post_process::DepthOfField::new(&device, hdr.format());
+        depth_of_field.set_linear_depth(hdr.linear_depth_view().clone());
+        post_process.push(depth_of_field);
+        let mut light_shafts = light_shafts::LightShafts::new(&device, hdr.format());
+        light_shafts.set_linear_depth(hdr.linear_depth_view().clone());
+        post_process.push(light_shafts);
+        post_process.push(bloom::Bloom::new(&device, &config, hdr.format()));
+        post_process.push(post_process::Distortion::new(&device, hdr.format()));
+        post_process.push(post_process::Vignette::new(&device, hdr.format()));
+        post_process.push(hdr::Tonemap::new(&device, config.format));
+        post_process.update(&queue);
+        let mut render_graph = Self::create_render_graph(config.format);
+        render_graph.resize(&device, config.width, config.height);
+        let fxaa = fxaa::Fxaa::new(&device, &config, render_graph.texture("ldr"));
+        let oit = oit::Oit::new(&device, &config, hdr.format(), sample_count);
+        let outline = outline::Outline::new(
+            &device,
+            &config,
+            hdr.format(),
+            &camera_bind_group_layout,
+            &[ModelVertex::desc(), InstanceRaw::desc()],
+        );
+        let decals = decal::DecalSystem::new(
             &device,
             &queue,
-            &texture_bind_group_layout,
-        )
-        .await
-        .unwrap();
+            hdr.format(),
+            &camera_bind_group_layout,
+            hdr.linear_depth_view(),
+        );
+        let lens_flare = lens_flare::LensFlare::new(
+            &device,
+            hdr.format(),
+            &camera_bind_group_layout,
+            hdr.linear_depth_view(),
+        );
 
-        log::info!(
-            "Model loaded with {} meshes, {} materials",
-            obj_model.meshes.len(),
-            obj_model.materials.len()
+        let model_variant = ModelVariant {
+            normal_map: true,
+            shading: ShadingMode::Lit,
+            sample_count,
+        };
+        let render_pipeline = model_pipeline(
+            &mut model_variants,
+            &device,
+            &render_pipeline_layout,
+            hdr.format(),
+            depth_mode,
+            &model_variant,
+        )?;
+
+        let cubemap = match (&environment, cubemap) {
+            (Some(environment), _) => environment.cubemap.clone(),
+            (None, Some(cubemap)) => assets.texture(cubemap).clone(),
+            (None, None) => skybox::Skybox::gradient_cubemap(&device, &queue),
+        };
+        let skybox = skybox::Skybox::new(
+            &device,
+            hdr.format(),
+            sample_count,
+            &camera_bind_group_layout,
+            cubemap,
+            depth_mode,
+        );
+        let grid = grid::Grid::new(
+            &device,
+            hdr.format(),
+            sample_count,
+            &camera_bind_group_layout,
+            depth_mode,
         );
-        for (i, mesh) in obj_model.meshes.iter().enumerate() {
-            log::info!("  Mesh {}: {} indices", i, mesh.num_elements);
-        }
 
-        // Create fire system positioned at Charizard's mouth
-        // Based on model analysis:
-        // - Model bounds: Y[0.0 to 0.909], Z[-0.493 to 0.493]
-        // - Mouth is at ~80% height, front of face
-        let fire_origin = [0.0, 0.727, 0.593]; // Jaw height, in front of snout
-        let fire_system =
-            fire::FireSystem::new(&device, &config, &camera_bind_group_layout, fire_origin);
+        let deferred = deferred::DeferredRenderer::new(
+            &device,
+            &config,
+            hdr.format(),
+            &depth_texture,
+            &texture_bind_group_layout,
+            &camera_bind_group_layout,
+            &lighting,
+            &ssao.bind_group_layout,
+            &[ModelVertex::desc(), InstanceRaw::desc()],
+            depth_mode,
+        )?;
+
+        // Picture-in-picture close-up of the first fire, in the top right corner
+        let fire_origin = scene
+            .emitters
+            .first()
+            .map_or(model.bounds().center.into(), |emitter| {
+                emitter_origin(model, emitter, cgmath::Matrix4::identity())
+            });
+        let close_up = Camera {
+            eye: (
+                fire_origin[0] + 0.6,
+                fire_origin[1] + 0.15,
+                fire_origin[2] + 0.5,
+            )
+                .into(),
+            target: (fire_origin[0], fire_origin[1], fire_origin[2] + 0.25).into(),
+            up: cgmath::Vector3::unit_y(),
+            aspect: 1.0, // Follows the viewport in `SplitView::update`
+            fovy: 40.0,
+            znear: 0.05,
+            zfar: 100.0,
+            depth_mode,
+            projection: Projection::Perspective,
+        };
+        // The scene's flythrough, or a slow circle round the fire without one
+        let camera_path = if scene.camera_path.is_empty() {
+            camera_path::CameraPath::orbit(fire_origin.into(), 5.0, 1.5, 24.0, 8)
+        } else {
+            camera_path::CameraPath::new(scene.camera_path.clone())
+        };
+        cameras.add(
+            &device,
+            &camera_bind_group_layout,
+            "path",
+            camera,
+            cameras::CameraControl::Path(camera_path::CameraPathPlayer::new(camera_path)),
+            camera_damping,
+        );
+        let sequencer = sequencer::Sequencer::new(scene.sequence.clone());
+        // Chases the selected instance, or the first fire when none is
+        cameras.add(
+            &device,
+            &camera_bind_group_layout,
+            "follow",
+            camera,
+            cameras::CameraControl::Follow(follow_camera::FollowCamera::new(
+                (0.0, 0.6, 1.6).into(),
+                0.25,
+                0.3,
+            )),
+            camera_damping,
+        );
+        let split_views = vec![viewport::SplitView {
+            view: viewport::ViewCamera::new(&device, &camera_bind_group_layout, close_up),
+            rect: [0.68, 0.02, 0.3, 0.3],
+        }];
+        let viewport_clear =
+            viewport::ViewportClear::new(&device, hdr.format(), sample_count, depth_mode);
+
+        // High up in a corner looking down at the models, like a security camera
+        let security_camera = viewport::ViewCamera::new(
+            &device,
+            &camera_bind_group_layout,
+            Camera {
+                eye: (3.0, 2.5, 3.0).into(),
+                target: (0.0, 0.4, 0.0).into(),
+                up: cgmath::Vector3::unit_y(),
+                aspect: 1.0, // Follows the target in `update`
+                fovy: 60.0,
+                znear: 0.1,
+                zfar: 100.0,
+                depth_mode,
+                projection: Projection::Perspective,
+            },
+        );
+        let security_target =
+            offscreen::OffscreenTarget::new(&device, 256, 256, hdr.format(), sample_count);
+        let thumbnail = offscreen::Thumbnail::new(&device, hdr.format());
+
+        // Occlusion culling tests every instance's bounds against last frame's depth
+        let instance_bounds = instance_bounds(model, &instances);
+        let visible_instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Visible Instance Buffer"),
+            size: (instances.len() * std::mem::size_of::<InstanceRaw>()) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let indirect_draws = if indirect::IndirectDraws::is_supported(&adapter, &device) {
+            Some(create_indirect_draws(&device, model, &instances))
+        } else {
+            None
+        };
+        let gpu_timer = gpu_timing::GpuTimer::is_supported(&device)
+            .then(|| gpu_timing::GpuTimer::new(&device, &queue));
+        let occlusion = if hiz::OcclusionCuller::is_supported(&device) {
+            Some(hiz::OcclusionCuller::new(
+                &device,
+                hdr.linear_depth_view(),
+                config.width,
+                config.height,
+            ))
+        } else {
+            tracing::warn!("Compute shaders aren't available, occlusion culling is off");
+            None
+        };
 
-        Ok(Self {
-            surface,
+        let frustum = camera.frustum();
+        let sprites = sprites::SpriteLayer::new(
+            &device,
+            &queue,
+            config.format,
+            atlas::AtlasBuilder::new(device.limits().max_texture_dimension_2d),
+        )
+        .inspect_err(|e| tracing::warn!("No HUD, its sprites didn't build: {:#}", e))
+        .ok();
+        let gizmo = gizmo::Gizmo::new(&device, config.format);
+        let debug_draw = debug_draw::DebugDraw::new(&device, config.format);
+        #[cfg(feature = "egui")]
+        let debug_ui = window
+            .as_deref()
+            .map(|window| debug_ui::DebugUi::new(&device, window, config.format));
+        let output = match (window, surface) {
+            (Some(window), surface) => output::FrameOutput::Window { window, surface },
+            (None, _) => output::FrameOutput::headless(&device, &config),
+        };
+        let mut state = Self {
+            output,
             device,
             queue,
+            render_config: config.clone(),
+            render_scale: 1.0,
             config,
             is_surface_configured: false,
-            clear_color: wgpu::Color {
-                r: 0.1,
-                g: 0.2,
-                b: 0.3,
-                a: 1.0,
-            },
+            clear_color: color::srgb_color(0.1, 0.2, 0.3),
+            cursor: [0.5, 0.5],
+            touch: touch::TouchGestures::default(),
+            input_map,
+            window_mode: window_mode::WindowMode::Windowed,
+            cursor_grabbed: false,
             render_pipeline,
-            window,
-            diffuse_bind_group,
-            diffuse_texture,
+            render_pipeline_layout,
+            model_variants,
+            normal_mapping: model_variant.normal_map,
+            diffuse_material,
             camera,
-            camera_buffer,
-            camera_bind_group,
-            camera_controller,
-            camera_uniform,
+            cameras,
+            camera_bind_group_layout,
+            frustum,
             instances,
             instance_buffer,
+            instance_bounds,
+            visible_instance_buffer,
+            visible_lods: Vec::new(),
+            occlusion,
+            gpu_timer,
+            gpu_timing_results: 0,
+            stats: None,
+            sprites,
+            hud_enabled: true,
+            gizmo,
+            gizmo_target: 0,
+            debug_draw,
+            debug_draw_enabled: false,
+            #[cfg(feature = "egui")]
+            debug_ui,
+            indirect_draws,
+            skinning,
+            skinned_meshes,
+            animator,
             depth_texture,
+            assets,
             obj_model,
-            fire_system,
+            material_array,
+            fire_systems: Vec::new(),
+            emitters: Vec::new(),
+            scene,
+            spawned: Vec::new(),
+            uploads: upload::Uploads::new(),
+            skybox,
+            grid,
+            terrain: None,
+            water: None,
+            grass: None,
+            shadows,
+            lighting,
+            scene_lights: Vec::new(),
+            fire_lights: Vec::new(),
+            ssao,
+            deferred,
+            hdr,
+            post_process,
+            fxaa,
+            oit,
+            lens_flare,
+            decals,
+            outline,
+            selected_instance: None,
+            split_views,
+            split_views_enabled: false,
+            viewport_clear,
+            security_camera,
+            security_target,
+            security_camera_enabled: false,
+            extra_windows: Vec::new(),
+            particle_window: None,
+            thumbnail,
+            render_graph,
+            render_path: deferred::RenderPath::Forward,
+            shading: ShadingMode::Lit,
+            depth_mode,
             last_update: std::time::Instant::now(),
+            frame_limiter: frame_pacing::FrameLimiter::new(None),
+            particle_timestep: frame_pacing::FixedTimestep::new(1.0 / 60.0),
             fire_enabled: true, // Start with fire on
-        })
+            sequencer,
+            fxaa_enabled: false,
+            oit_enabled: false,
+            ssao_enabled: true,
+            msaa_samples,
+            supported_sample_counts,
+            #[cfg(not(target_arch = "wasm32"))]
+            shader_watcher: hot_reload::ShaderWatcher::new(&[
+                "fire_shader.wgsl",
+                "noise.wgsl",
+                "camera.wgsl",
+                "lighting.wgsl",
+                "material.wgsl",
+                "material_array.wgsl",
+                "shader.wgsl",
+            ]),
+            #[cfg(not(target_arch = "wasm32"))]
+            asset_watcher: hot_reload::AssetWatcher::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            asset_reload: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            material_layout: texture_bind_group_layout,
+            #[cfg(not(target_arch = "wasm32"))]
+            material_array_layout,
+            #[cfg(not(target_arch = "wasm32"))]
+            hdr_environment: environment::Environment::is_supported(&adapter),
+            device_lost,
+            #[cfg(not(target_arch = "wasm32"))]
+            dropped_model: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            frame_on_reload: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            capture_requested: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            recording: None,
+            instance,
+            adapter,
+        };
+        state.set_terrain(heightmap, ground);
+        state.set_water();
+        state.set_grass();
+        state.place_scene();
+        state.apply_sample_count();
+        if state.scene.camera.frame_model {
+            state.frame_model();
+        }
+        Ok(state)
     }
+    #[profiling::function]
+    #[tracing::instrument(level = "trace", skip_all)]
     fn update(&mut self) {
-        self.camera_controller.update_camera(&mut self.camera);
-        self.camera.aspect = self.config.width as f32 / self.config.height as f32;
-        self.camera_uniform.update_view_proj(&self.camera);
-        self.queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[self.camera_uniform]),
-        );
-
-        // Update fire system (only if enabled)
+        #[cfg(not(target_arch = "wasm32"))]
+        self.reload_shaders();
+        #[cfg(not(target_arch = "wasm32"))]
+        self.reload_assets();
         let now = std::time::Instant::now();
         let dt = (now - self.last_update).as_secs_f32();
         self.last_update = now;
+        // What the frame really took, for the stats overlay
+        let frame_time = dt;
+        // Recordings step the same whatever the frame took
+        #[cfg(not(target_arch = "wasm32"))]
+        let dt = self
+            .recording
+            .as_ref()
+            .map_or(dt, recording::Recording::frame_time);
+
+        let follow_target = match self.selected_instance {
+            Some(i) => {
+                let instance = &self.instances[i as usize];
+                Some(
+                    cgmath::Matrix4::from_translation(instance.position)
+                        * cgmath::Matrix4::from(instance.rotation),
+                )
+            }
+            None => self
+                .fire_systems
+                .first()
+                .map(|fire_system| cgmath::Matrix4::from_translation(fire_system.origin.into())),
+        };
+        if let Some(transform) = follow_target {
+            self.cameras.set_follow_target(transform);
+        }
+        // Switched away from flying, by a key or the sequencer
+        if self.cursor_grabbed && !self.cameras.is_flying() {
+            self.set_cursor_grabbed(false);
+        }
+        for action in self.sequencer.update(dt) {
+            self.run_sequence_action(action);
+        }
+        let aspect = self.render_config.width as f32 / self.render_config.height as f32;
+        self.cameras.update(&mut self.uploads, aspect, dt);
+        self.camera = *self.cameras.camera();
+        if self.gizmo.is_dragging() {
+            self.drag_gizmo();
+        }
+        self.shadows.update(
+            &self.device,
+            &self.queue,
+            &self.camera,
+            self.lighting.sun.direction,
+        );
+        for view in &mut self.split_views {
+            view.update(
+                &mut self.uploads,
+                self.render_config.width,
+                self.render_config.height,
+            );
+        }
+        self.security_camera
+            .update(&mut self.uploads, self.security_target.aspect());
+        // The particle window keeps the first fire in the middle, close enough
+        // that it fills most of the view
+        if let Some(fire_system) = self.fire_systems.first() {
+            let bounds = fire_system.bounds();
+            if let Some(window) = self.extra_window_mut(self.particle_window) {
+                let camera = &mut window.view.camera;
+                let distance = bounds.radius.max(0.25) * 2.5;
+                camera.target = bounds.center;
+                camera.eye =
+                    bounds.center + cgmath::Vector3::new(0.0, 0.4, 1.0).normalize() * distance;
+            }
+        }
+        for window in &mut self.extra_windows {
+            window.update(&self.queue);
+        }
+
+        // Results come in most frames while timing's on, logged every 60
+        if let Some(gpu_timer) = &mut self.gpu_timer {
+            if gpu_timer.poll(&self.device) {
+                self.gpu_timing_results += 1;
+                if self.gpu_timing_results.is_multiple_of(60) {
+                    let passes = gpu_timer
+                        .timings()
+                        .iter()
+                        .map(|(name, ms)| format!("{} {:.2}", name, ms))
+                        .collect::<Vec<_>>();
+                    tracing::info!("GPU {:.2} ms: {}", gpu_timer.total(), passes.join(", "));
+                }
+            }
+        }
+        // Draws and uploads are from the last frame rendered, counted whether
+        // or not the overlay's up
+        let counters = stats::Counters {
+            particles: self
+                .fire_systems
+                .iter()
+                .map(fire::FireSystem::particle_count)
+                .sum(),
+            draw_calls: stats::take_draw_calls(),
+            upload_bytes: self.uploads.last_frame_bytes(),
+        };
+        if let Some(stats) = &mut self.stats {
+            stats.record_frame(frame_time);
+            stats.update(counters, self.gpu_timer.as_ref());
+        }
+        #[cfg(feature = "egui")]
+        if let (Some(mut debug_ui), Some(window)) =
+            (self.debug_ui.take(), self.output.window().cloned())
+        {
+            debug_ui.run(&window, |context| self.debug_windows(context, frame_time));
+            self.debug_ui = Some(debug_ui);
+        }
+
+        // Only instances inside the frustum that the latest occlusion results
+        // haven't hidden get drawn. Those results lag a frame or two behind the camera.
+        self.frustum = self.camera.frustum();
+        if let Some(occlusion) = &mut self.occlusion {
+            occlusion.poll(&self.device);
+            occlusion.update(
+                &self.queue,
+                self.camera.build_view_projection_matrix(),
+                self.camera.build_view_matrix(),
+                self.camera.znear,
+                &self.instance_bounds,
+            );
+        }
+        if let Some(indirect_draws) = &self.indirect_draws {
+            indirect_draws.update(&self.queue, &self.frustum);
+        }
+        // Smaller on screen gets a coarser level of detail. Instances are
+        // grouped by level so each level draws as one instance range.
+        let model = self.assets.model(self.obj_model);
+        let mut visible_instances = self
+            .instances
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.frustum.intersects_sphere(&self.instance_bounds[*i]))
+            .filter(|(i, _)| self.occlusion.as_ref().is_none_or(|o| o.is_visible(*i)))
+            .map(|(i, instance)| {
+                let screen_size = self.camera.screen_size(&self.instance_bounds[i]);
+                (model.lod_level(screen_size), instance.to_raw())
+            })
+            .collect::<Vec<_>>();
+        visible_instances.sort_by_key(|(level, _)| *level);
+        self.visible_lods = (0..model.lod_count())
+            .map(|level| {
+                let start = visible_instances.partition_point(|(l, _)| *l < level);
+                let end = visible_instances.partition_point(|(l, _)| *l <= level);
+                start as u32..end as u32
+            })
+            .collect();
+        let visible_instances = visible_instances
+            .into_iter()
+            .map(|(_, instance)| instance)
+            .collect::<Vec<_>>();
+        self.uploads
+            .write(&self.visible_instance_buffer, 0, &visible_instances);
+
+        // Update fire system (only if enabled)
+        let steps = self.particle_timestep.advance(dt);
+        if self.fire_enabled {
+            profiling::scope!("fire");
+            for fire_system in &mut self.fire_systems {
+                for _ in 0..steps {
+                    fire_system.update(self.particle_timestep.step);
+                }
+            }
+        }
+
+        if let Some(animator) = &mut self.animator {
+            animator.update(dt);
+            for (skinned_mesh, skin) in self.skinned_meshes.iter().zip(&animator.rig.skins) {
+                skinned_mesh.update_joints(&mut self.uploads, &animator.joint_matrices(skin));
+            }
+            // Fires on sockets stay on them as the model moves
+            let model = self.assets.model(self.obj_model);
+            for (fire_system, (emitter, placement)) in
+                self.fire_systems.iter_mut().zip(&self.emitters)
+            {
+                if let Some(socket) = emitter
+                    .socket
+                    .as_deref()
+                    .and_then(|name| model.socket(name))
+                {
+                    let transform = placement
+                        * animator.socket_transform(socket)
+                        * cgmath::Matrix4::from_translation(emitter.position.into());
+                    fire_system.origin = transform.w.truncate().into();
+                }
+            }
+        }
+
+        // Embers leave scorch marks where they land, and burn the grass
+        // there when it's set to
+        for fire_system in &mut self.fire_systems {
+            for position in fire_system.take_landed_embers() {
+                use rand::Rng;
+                let angle = cgmath::Rad(rand::rng().random::<f32>() * std::f32::consts::TAU);
+                self.decals
+                    .add(decal::Decal::on_ground(position.into(), 0.25, angle));
+                if let Some(grass) = self.grass.as_mut().filter(|grass| grass.settings.burn) {
+                    grass.burn(position.into(), grass.settings.burn_radius);
+                }
+            }
+        }
+        self.decals.update(&self.queue);
+        if let Some(water) = &mut self.water {
+            water.update(&self.queue, dt);
+        }
+        if let Some(grass) = &mut self.grass {
+            grass.update(&self.queue, dt);
+        }
+
+        // The fires light up their surroundings with flickering point lights
+        if self.fire_enabled {
+            for (i, (fire_system, (emitter, _))) in
+                self.fire_systems.iter().zip(&self.emitters).enumerate()
+            {
+                if emitter.light && !self.fire_lights.iter().any(|&(j, _)| j == i) {
+                    let id = self.lighting.add_light(light::Light::point(
+                        fire_system.origin.into(),
+                        [1.0, 0.55, 0.2],
+                        4.0,
+                        6.0,
+                    ));
+                    self.fire_lights.push((i, id));
+                }
+            }
+        } else {
+            for (_, id) in self.fire_lights.drain(..) {
+                self.lighting.remove_light(id);
+            }
+        }
+        let t = self
+            .fire_systems
+            .first()
+            .map_or(0.0, fire::FireSystem::elapsed);
+        let flicker = 1.0 + 0.25 * (t * 13.0).sin() * (t * 7.3).cos() + 0.1 * (t * 31.0).sin();
+        for &(i, id) in &self.fire_lights {
+            if let Some(fire_light) = self.lighting.light_mut(id) {
+                fire_light.position = self.fire_systems[i].origin.into();
+                fire_light.intensity = 4.0 * flicker;
+            }
+        }
+        // The flare follows the first fire and flickers with its light
+        if let Some(fire_system) = self.fire_systems.first() {
+            self.lens_flare.position = fire_system.origin;
+        }
+        self.lens_flare.intensity = flicker;
+        self.lens_flare.update(&self.queue);
+        self.outline.update(&self.queue);
+        self.lighting.update(&self.device, &mut self.uploads);
+
+        // Depth of field keeps whatever the camera looks at in focus
+        if let Some(depth_of_field) = self.post_process.effect_mut::<post_process::DepthOfField>() {
+            depth_of_field.focal_distance = (self.camera.target - self.camera.eye).magnitude();
+        }
+        // Shafts come from the sun and, while it burns, the fire
+        let mut shaft_sources = vec![light_shafts::ShaftSource {
+            position: (-self.lighting.sun.direction).extend(0.0),
+            color: self.lighting.sun.color,
+            intensity: 0.5,
+        }];
+        if self.fire_enabled {
+            shaft_sources.extend(self.fire_systems.iter().map(|fire_system| {
+                light_shafts::ShaftSource {
+                    position: cgmath::Point3::from(fire_system.origin).to_homogeneous(),
+                    color: [1.0, 0.55, 0.2],
+                    intensity: flicker,
+                }
+            }));
+        }
+        let view_proj = self.camera.build_view_projection_matrix();
+        if let Some(light_shafts) = self.post_process.effect_mut::<light_shafts::LightShafts>() {
+            light_shafts.set_sources(view_proj, &shaft_sources);
+        }
+        self.post_process.update(&self.queue);
+    }
+
+    // Rebuilds the fire and forward model pipelines when their shaders change
+    // on disk. A shader that doesn't compile is logged and the old pipeline
+    // kept. The deferred path keeps the shaders it started with.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload_shaders(&mut self) {
+        let changed = self.shader_watcher.poll();
+        if changed.is_empty() {
+            return;
+        }
+        tracing::info!("Reloading shaders, changed: {:?}", changed);
+
+        if changed.iter().any(|name| FIRE_SHADER_FILES.contains(name)) {
+            let sample_count = self.sample_count();
+            let result = Preprocessor::from_disk()
+                .process("fire_shader.wgsl")
+                .and_then(|source| {
+                    for fire_system in &mut self.fire_systems {
+                        fire_system.reload_shader(&self.device, &source, sample_count)?;
+                    }
+                    Ok(())
+                });
+            if let Err(e) = result {
+                tracing::error!("Couldn't reload fire_shader.wgsl: {}", e);
+            }
+        }
+
+        if changed.iter().any(|name| MODEL_SHADER_FILES.contains(name)) {
+            // A fresh cache, so every variant picks up the change when it's next used
+            let mut variants = model_shader_variants(
+                Preprocessor::from_disk(),
+                &self.device,
+                &self.lighting,
+                self.material_array.is_some(),
+            );
+            match model_pipeline(
+                &mut variants,
+                &self.device,
+                &self.render_pipeline_layout,
+                self.hdr.format(),
+                self.depth_mode,
+                &self.model_variant(),
+            ) {
+                Ok(pipeline) => {
+                    self.model_variants = variants;
+                    self.render_pipeline = pipeline;
+                }
+                Err(e) => tracing::error!("Couldn't reload the model shader: {:#}", e),
+            }
+        }
+    }
+
+    // Reloads the model and skybox in the background when anything in res/
+    // changes, then swaps them in between frames. Frames already submitted
+    // keep the old GPU resources alive until they're done with them.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload_assets(&mut self) {
+        let Some(reload) = &self.asset_reload else {
+            // Changes made while a reload is running are picked up after it
+            let changed = self.asset_watcher.poll();
+            if !changed.is_empty() {
+                tracing::info!("Reloading assets, changed: {:?}", changed);
+                self.start_asset_reload();
+            }
+            return;
+        };
+        let Some(result) = reload.take() else {
+            return;
+        };
+        self.asset_reload = None;
+        match result {
+            Ok(scene) => self.swap_assets(scene),
+            Err(e) => tracing::error!("Couldn't reload assets, keeping the old ones: {:#}", e),
+        }
+    }
+
+    // Loads the scene's assets over again in the background, replacing any
+    // reload that's already running
+    #[cfg(not(target_arch = "wasm32"))]
+    fn start_asset_reload(&mut self) {
+        let (device, queue) = (self.device.clone(), self.queue.clone());
+        let material_layout = self.material_layout.clone();
+        let skinning = self.skinning.is_some();
+        let hdr_environment = self.hdr_environment;
+        let model_file = self.dropped_model.clone();
+        self.asset_reload = Some(loading::AssetLoader::spawn(
+            SceneAssets::STEPS,
+            move |progress| async move {
+                SceneAssets::load(
+                    &device,
+                    &queue,
+                    material_layout,
+                    skinning,
+                    hdr_environment,
+                    SCENE_FILE,
+                    model_file,
+                    &progress,
+                )
+                .await
+            },
+        ));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn swap_assets(&mut self, scene: SceneAssets) {
+        let SceneAssets {
+            assets,
+            model: handle,
+            cubemap,
+            environment,
+            scene,
+            heightmap,
+            ground,
+            input_map,
+            ..
+        } = scene;
+        let model = assets.model(handle);
+        // The forward pipeline layout is made for packed or unpacked materials
+        // and can't switch between them
+        let material_array = match &self.material_array {
+            Some(_) => match material_array::MaterialArray::new(
+                &self.device,
+                &self.queue,
+                model,
+                &self.material_array_layout,
+            ) {
+                Some(material_array) => Some(material_array),
+                None => {
+                    tracing::error!(
+                        "Can't pack the reloaded model's {} materials, restart to see it",
+                        model.materials.len()
+                    );
+                    return;
+                }
+            },
+            None => None,
+        };
+
+        let (skinned_meshes, mut animator) = skin_meshes(
+            &self.device,
+            self.skinning.as_ref(),
+            model,
+            assets.rig(handle).cloned(),
+        );
+        // Carry on with the same clip where it was
+        if let (Some(old), Some(new)) = (&self.animator, &mut animator) {
+            if old.clip < new.rig.clips.len() {
+                new.clip = old.clip;
+                new.time = old.time;
+            }
+        }
+        self.skinned_meshes = skinned_meshes;
+        self.animator = animator;
+        if let Some(environment) = &environment {
+            self.lighting.set_environment(&self.device, environment);
+            self.skybox
+                .set_cubemap(&self.device, environment.cubemap.clone());
+        } else if let Some(cubemap) = cubemap {
+            self.skybox
+                .set_cubemap(&self.device, assets.texture(cubemap).clone());
+        }
+        tracing::info!(
+            "Reloaded the model with {} meshes, {} materials",
+            model.meshes.len(),
+            model.materials.len()
+        );
+        // The old assets go once nothing else holds their GPU resources
+        self.assets = assets;
+        self.obj_model = handle;
+        self.material_array = material_array;
+        self.scene = scene;
+        self.input_map = input_map;
+        self.sequencer = sequencer::Sequencer::new(self.scene.sequence.clone());
+        self.set_terrain(heightmap, ground);
+        self.set_water();
+        self.set_grass();
+        self.place_scene();
+        if self.scene.camera.frame_model || std::mem::take(&mut self.frame_on_reload) {
+            self.frame_model();
+        }
+    }
+
+    // Points the active camera at every instance of the model
+    fn frame_model(&mut self) {
+        let model_aabb = self.assets.model(self.obj_model).aabb();
+        let aabb = self
+            .instances
+            .iter()
+            .map(|instance| model_aabb.transformed(instance.position, instance.rotation))
+            .reduce(model::Aabb::merge)
+            .unwrap_or(model_aabb);
+        self.cameras.frame_aabb(&aabb);
+    }
+
+    // ===== TERRAIN =====
+    // Builds the ground for the scene's terrain settings, or goes without it
+    // when there are none or the heightmap didn't load or its shader didn't
+    // build
+    fn set_terrain(
+        &mut self,
+        heightmap: Option<terrain::Heightmap>,
+        ground: Option<assets::Handle<texture::Texture>>,
+    ) {
+        self.terrain = match (self.scene.terrain.clone(), heightmap) {
+            (Some(settings), Some(heightmap)) => {
+                let ground = match ground {
+                    Some(ground) => self.assets.texture(ground).clone(),
+                    None => self.assets.white_srgb_texture(),
+                };
+                terrain::Terrain::new(
+                    &self.device,
+                    settings,
+                    heightmap,
+                    &ground,
+                    self.hdr.format(),
+                    self.hdr.sample_count(),
+                    &self.camera_bind_group_layout,
+                    &self.lighting,
+                    &self.ssao.bind_group_layout,
+                    self.depth_mode,
+                )
+                .map_err(|e| tracing::error!("No terrain: {}", e.report()))
+                .ok()
+            }
+            _ => None,
+        };
+    }
+
+    // The scene's water, reflecting the skybox's cubemap
+    fn set_water(&mut self) {
+        self.water = self.scene.water.map(|settings| {
+            water::Water::new(
+                &self.device,
+                &self.queue,
+                settings,
+                &self.hdr,
+                &self.camera_bind_group_layout,
+                self.skybox.cubemap.clone(),
+            )
+        });
+    }
+
+    // The scene's grass, scattered over the terrain or the ground plane
+    // without one. None grows under the water.
+    fn set_grass(&mut self) {
+        let Some(settings) = self.scene.grass else {
+            self.grass = None;
+            return;
+        };
+        let terrain = self.terrain.as_ref();
+        let water = self.scene.water;
+        let ground = |x: f32, z: f32| {
+            let (height, normal) = match terrain {
+                Some(terrain) => (terrain.height_at(x, z)?, terrain.normal_at(x, z)?),
+                None => (0.0, cgmath::Vector3::unit_y()),
+            };
+            let underwater = water.is_some_and(|water| {
+                let half = water.size / 2.0;
+                (x - water.position[0]).abs() <= half
+                    && (z - water.position[2]).abs() <= half
+                    && height < water.position[1]
+            });
+            (!underwater).then_some((height, normal))
+        };
+        self.grass = grass::Grass::new(
+            &self.device,
+            settings,
+            ground,
+            self.hdr.format(),
+            self.hdr.sample_count(),
+            &self.camera_bind_group_layout,
+            &self.lighting,
+            &self.ssao.bind_group_layout,
+            self.depth_mode,
+        )
+        .map_err(|e| tracing::error!("No grass: {}", e.report()))
+        .ok();
+    }
+
+    // `position` lifted by the height of the ground under it
+    fn on_ground(&self, mut position: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+        if let Some(height) = self
+            .terrain
+            .as_ref()
+            .and_then(|terrain| terrain.height_at(position.x, position.z))
+        {
+            position.y += height;
+        }
+        position
+    }
+
+    // ===== PREFABS =====
+    // Stamps a copy of one of the scene's prefabs in at `transform`. It stays
+    // when the scene reloads.
+    pub fn spawn(&mut self, prefab: &str, transform: scene::SceneTransform) -> anyhow::Result<()> {
+        if self.place_prefab(prefab, transform)? {
+            self.update_instances();
+        }
+        self.spawned.push(scene::PrefabSpawn {
+            prefab: prefab.to_string(),
+            transform,
+        });
+        Ok(())
+    }
+
+    // Adds the prefab's model instance, lights and fires, true when there's
+    // an instance and the instance buffers need remaking
+    fn place_prefab(
+        &mut self,
+        name: &str,
+        transform: scene::SceneTransform,
+    ) -> anyhow::Result<bool> {
+        let Some(prefab) = self.scene.prefabs.get(name).cloned() else {
+            anyhow::bail!("The scene has no prefab named {}", name);
+        };
+        let mut transform = transform;
+        transform.position = self.on_ground(transform.position.into()).into();
+        let placement = transform.matrix();
+        for light in &prefab.lights {
+            self.scene_lights
+                .push(self.lighting.add_light(light.to_light_at(placement)));
+        }
+        for emitter in prefab.emitters {
+            self.add_emitter(emitter, placement)?;
+        }
+        match prefab.model {
+            Some(model) if model == self.scene.models[0].path => {
+                self.instances.push(Instance {
+                    position: transform.position.into(),
+                    rotation: transform.quaternion(),
+                });
+                Ok(true)
+            }
+            Some(model) => {
+                tracing::warn!(
+                    "Only {} is drawn, {} goes without its {}",
+                    self.scene.models[0].path,
+                    name,
+                    model
+                );
+                Ok(false)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn add_emitter(
+        &mut self,
+        emitter: scene::SceneEmitter,
+        placement: cgmath::Matrix4<f32>,
+    ) -> error::Result<()> {
+        let origin = emitter_origin(self.assets.model(self.obj_model), &emitter, placement);
+        let mut fire_system = fire::FireSystem::new(
+            &self.device,
+            self.hdr.format(),
+            self.hdr.sample_count(),
+            &self.camera_bind_group_layout,
+            origin,
+            self.depth_mode,
+        )?;
+        // Embers land on the ground under the fire
+        fire_system.ground_height = self
+            .on_ground(cgmath::Vector3::new(origin[0], 0.0, origin[2]))
+            .y;
+        self.fire_systems.push(fire_system);
+        self.emitters.push((emitter, placement));
+        Ok(())
+    }
+
+    // Puts everything in the scene file in place, then the prefabs spawned
+    // since it loaded, replacing whatever was there
+    fn place_scene(&mut self) {
+        for id in self.scene_lights.drain(..) {
+            self.lighting.remove_light(id);
+        }
+        for (_, id) in self.fire_lights.drain(..) {
+            self.lighting.remove_light(id);
+        }
+        self.fire_systems.clear();
+        self.emitters.clear();
+
+        let mut instances = scene_instances(&self.scene);
+        for instance in &mut instances {
+            instance.position = self.on_ground(instance.position);
+        }
+        self.instances = instances;
+        for light in &self.scene.lights {
+            self.scene_lights
+                .push(self.lighting.add_light(light.to_light()));
+        }
+        for emitter in self.scene.emitters.clone() {
+            if let Err(e) = self.add_emitter(emitter, cgmath::Matrix4::identity()) {
+                tracing::error!("Couldn't light a fire: {}", e.report());
+            }
+        }
+        let spawns = self
+            .scene
+            .spawns
+            .iter()
+            .chain(&self.spawned)
+            .cloned()
+            .collect::<Vec<_>>();
+        for spawn in spawns {
+            if let Err(e) = self.place_prefab(&spawn.prefab, spawn.transform) {
+                tracing::warn!("Couldn't spawn {}: {:#}", spawn.prefab, e);
+            }
+        }
+        self.update_instances();
+    }
+
+    // Remakes the instance buffers, bounds and indirect draws after
+    // `instances` changed
+    fn update_instances(&mut self) {
+        let instance_data = self
+            .instances
+            .iter()
+            .map(Instance::to_raw)
+            .collect::<Vec<_>>();
+        self.instance_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Instance Buffer"),
+                contents: bytemuck::cast_slice(&instance_data),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+        self.visible_instance_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Visible Instance Buffer"),
+            size: self.instance_buffer.size(),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let model = self.assets.model(self.obj_model);
+        self.instance_bounds = instance_bounds(model, &self.instances);
+        if let Some(indirect_draws) = &mut self.indirect_draws {
+            let enabled = indirect_draws.enabled;
+            *indirect_draws = create_indirect_draws(&self.device, model, &self.instances);
+            indirect_draws.enabled = enabled;
+        }
+        if self
+            .selected_instance
+            .is_some_and(|i| i as usize >= self.instances.len())
+        {
+            self.selected_instance = None;
+        }
+    }
+
+    fn model_variant(&self) -> ModelVariant {
+        ModelVariant {
+            normal_map: self.normal_mapping,
+            shading: self.shading,
+            sample_count: self.sample_count(),
+        }
+    }
+
+    // Switches the forward pipeline to the variant for the current settings,
+    // compiling it the first time
+    fn update_model_pipeline(&mut self) {
+        let variant = self.model_variant();
+        match model_pipeline(
+            &mut self.model_variants,
+            &self.device,
+            &self.render_pipeline_layout,
+            self.hdr.format(),
+            self.depth_mode,
+            &variant,
+        ) {
+            Ok(pipeline) => self.render_pipeline = pipeline,
+            Err(e) => tracing::error!("Couldn't build the model pipeline: {:#}", e),
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        // Minimized, or on the way in or out of fullscreen. The textures at
+        // the old size do until a real size comes.
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.config.width = width;
+        self.config.height = height;
+        self.is_surface_configured = self.output.configure(&self.device, &self.config);
+        // Everything the scene draws into is at the render size
+        let max_size = self.device.limits().max_texture_dimension_2d;
+        let scaled =
+            |size: u32| ((size as f32 * self.render_scale).round() as u32).clamp(1, max_size);
+        self.render_config = wgpu::SurfaceConfiguration {
+            width: scaled(width),
+            height: scaled(height),
+            ..self.config.clone()
+        };
+        self.depth_texture = texture::Texture::create_depth_texture(
+            &self.device,
+            &self.render_config,
+            self.sample_count(),
+            "depth_texture",
+        );
+        // The G-buffer reads a single-sampled depth texture, so it is only
+        // rebuilt while MSAA is off (always the case on the deferred path)
+        if self.sample_count() == 1 {
+            self.deferred
+                .resize(&self.device, &self.render_config, &self.depth_texture);
+        }
+        self.hdr.resize(
+            &self.device,
+            self.render_config.width,
+            self.render_config.height,
+        );
+        if let Some(motion_blur) = self.post_process.effect_mut::<post_process::MotionBlur>() {
+            motion_blur.set_velocity(self.hdr.velocity_view().clone());
+        }
+        if let Some(depth_of_field) = self.post_process.effect_mut::<post_process::DepthOfField>() {
+            depth_of_field.set_linear_depth(self.hdr.linear_depth_view().clone());
+        }
+        if let Some(light_shafts) = self.post_process.effect_mut::<light_shafts::LightShafts>() {
+            light_shafts.set_linear_depth(self.hdr.linear_depth_view().clone());
+        }
+        self.post_process.resize(
+            &self.device,
+            self.render_config.width,
+            self.render_config.height,
+        );
+        self.render_graph.resize(
+            &self.device,
+            self.render_config.width,
+            self.render_config.height,
+        );
+        self.fxaa
+            .resize(&self.device, self.render_graph.texture("ldr"));
+        self.lens_flare
+            .resize(&self.device, self.hdr.linear_depth_view());
+        self.decals
+            .resize(&self.device, self.hdr.linear_depth_view());
+        if let Some(water) = &mut self.water {
+            water.resize(&self.device, &self.hdr);
+        }
+        self.outline.resize(&self.device, &self.render_config);
+        self.oit.resize(
+            &self.device,
+            self.render_config.width,
+            self.render_config.height,
+        );
+        self.ssao.resize(&self.device, &self.render_config);
+        if let Some(occlusion) = &mut self.occlusion {
+            occlusion.resize(
+                &self.device,
+                self.hdr.linear_depth_view(),
+                self.render_config.width,
+                self.render_config.height,
+            );
+        }
+    }
+
+    // Renders the scene at `scale` times the window's size, from 0.5 for
+    // slow GPUs up to 2 for supersampling
+    fn set_render_scale(&mut self, scale: f32) {
+        self.render_scale = scale.clamp(0.5, 2.0);
+        self.resize(self.config.width, self.config.height);
+        tracing::info!(
+            "Render scale {:.2} ({}x{})",
+            self.render_scale,
+            self.render_config.width,
+            self.render_config.height
+        );
+    }
+
+    // The deferred path lights a single-sampled G-buffer, so MSAA only applies
+    // to the forward path
+    fn sample_count(&self) -> u32 {
+        match self.render_path {
+            deferred::RenderPath::Forward => self.msaa_samples,
+            deferred::RenderPath::Deferred => 1,
+        }
+    }
+
+    // Recreate everything that depends on the sample count
+    fn apply_sample_count(&mut self) {
+        let sample_count = self.sample_count();
+        if sample_count == self.hdr.sample_count() {
+            return;
+        }
+        self.update_model_pipeline();
+        for fire_system in &mut self.fire_systems {
+            fire_system.set_sample_count(&self.device, sample_count);
+        }
+        self.skybox.set_sample_count(&self.device, sample_count);
+        self.grid.set_sample_count(&self.device, sample_count);
+        if let Some(terrain) = &mut self.terrain {
+            terrain.set_sample_count(&self.device, sample_count);
+        }
+        if let Some(grass) = &mut self.grass {
+            grass.set_sample_count(&self.device, sample_count);
+        }
+        self.viewport_clear
+            .set_sample_count(&self.device, sample_count);
+        self.security_target
+            .set_sample_count(&self.device, sample_count);
+        for window in &mut self.extra_windows {
+            window.set_sample_count(&self.device, sample_count);
+        }
+        self.hdr.set_sample_count(&self.device, sample_count);
+        self.oit.set_sample_count(&self.device, sample_count);
+        self.resize(self.config.width, self.config.height);
+    }
+
+    // Main HDR pass: forward-lit model (unless the deferred path already lit
+    // it), the sky, and the additive fire
+    fn render_scene(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let deferred = self.render_path == deferred::RenderPath::Deferred;
+
+        let mut render_pass = self.begin_scene_pass(
+            encoder,
+            "Render Pass",
+            &self.hdr,
+            &self.depth_texture.view,
+            !deferred,
+        );
+        // render_pass.set_pipeline(&self.render_pipeline); // 2.
+        // render_pass.set_bind_group(0, &self.diffuse_material.bind_group, &[]);
+        // render_pass.set_bind_group(1, self.cameras.bind_group(), &[]);
+        // render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        // render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        // render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16); // 1.
+        // render_pass.draw_indexed(0..self.num_indices, 0, 0..self.instances.len() as u32);
+
+        if !deferred {
+            render_pass.push_debug_group("Models");
+            render_pass.set_pipeline(&self.render_pipeline);
+            render_pass.set_bind_group(2, &self.lighting.bind_group, &[]);
+            render_pass.set_bind_group(3, &self.ssao.bind_group, &[]);
+
+            // GPU-driven draws cull on the GPU and keep to the full-detail meshes
+            match self.indirect_draws.as_ref().filter(|_| self.gpu_driven()) {
+                Some(indirect_draws) => indirect_draws.draw(
+                    &mut render_pass,
+                    self.assets.model(self.obj_model),
+                    self.material_array.as_ref(),
+                    &self.instance_buffer,
+                    self.cameras.bind_group(),
+                ),
+                None => {
+                    render_pass.set_vertex_buffer(1, self.visible_instance_buffer.slice(..));
+                    material_array::draw_model_lods_instanced(
+                        &mut render_pass,
+                        self.assets.model(self.obj_model),
+                        self.material_array.as_ref(),
+                        &self.visible_lods,
+                        self.cameras.bind_group(),
+                    );
+                }
+            }
+            render_pass.pop_debug_group();
+        }
+
+        // The ground and the grass on it are forward-lit on either path
+        if let Some(terrain) = &self.terrain {
+            render_pass.push_debug_group("Terrain");
+            terrain.render(
+                &mut render_pass,
+                self.cameras.bind_group(),
+                &self.lighting.bind_group,
+                &self.ssao.bind_group,
+                &self.frustum,
+            );
+            render_pass.pop_debug_group();
+        }
+        if let Some(grass) = &self.grass {
+            render_pass.push_debug_group("Grass");
+            grass.render(
+                &mut render_pass,
+                self.cameras.bind_group(),
+                &self.lighting.bind_group,
+                &self.ssao.bind_group,
+                &self.frustum,
+            );
+            render_pass.pop_debug_group();
+        }
+
+        // The sky only fills pixels nothing else has written depth to
+        render_pass.push_debug_group("Skybox");
+        self.skybox
+            .render(&mut render_pass, self.cameras.bind_group());
+        render_pass.pop_debug_group();
+        if self.grid.enabled {
+            render_pass.push_debug_group("Grid");
+            self.grid
+                .render(&mut render_pass, self.cameras.bind_group());
+            render_pass.pop_debug_group();
+        }
+
+        // Render fire system (render after model so fire is on top with proper blending)
+        // The particles are still uploaded when the emitter is culled, other views may see them
+        if self.fire_enabled && !self.oit_enabled {
+            render_pass.push_debug_group("Fire");
+            for fire_system in &mut self.fire_systems {
+                if self.frustum.intersects_sphere(&fire_system.bounds()) {
+                    fire_system.render(
+                        &mut self.uploads,
+                        &mut render_pass,
+                        self.cameras.bind_group(),
+                    );
+                } else {
+                    render_pass.insert_debug_marker("Culled emitter");
+                    fire_system.upload(&mut self.uploads);
+                }
+            }
+            render_pass.pop_debug_group();
+        }
+
+        // 2.
+
+        drop(render_pass);
+    }
+
+    // The forward pass draws through `indirect_draws`. The other paths and
+    // passes keep drawing the visible instances.
+    fn gpu_driven(&self) -> bool {
+        self.render_path == deferred::RenderPath::Forward
+            && self.indirect_draws.as_ref().is_some_and(|d| d.enabled)
+    }
+
+    // Pass over a set of HDR targets and depth that the scene is drawn into. Without
+    // `clear` it keeps what earlier passes (deferred lighting, the main view) left.
+    fn begin_scene_pass<'e>(
+        &self,
+        encoder: &'e mut wgpu::CommandEncoder,
+        label: &str,
+        hdr: &hdr::HdrPipeline,
+        depth: &wgpu::TextureView,
+        clear: bool,
+    ) -> wgpu::RenderPass<'e> {
+        // With MSAA the pass renders multisampled and resolves into the HDR target
+        let (color_view, resolve_target) = hdr.color_attachment();
+        let (velocity_view, velocity_resolve_target) = hdr.velocity_attachment();
+        let (linear_depth_view, linear_depth_resolve_target) = hdr.linear_depth_attachment();
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[
+                Some(wgpu::RenderPassColorAttachment {
+                    view: color_view,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: if clear {
+                            wgpu::LoadOp::Clear(self.clear_color)
+                        } else {
+                            wgpu::LoadOp::Load
+                        },
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                }),
+                Some(wgpu::RenderPassColorAttachment {
+                    view: velocity_view,
+                    resolve_target: velocity_resolve_target,
+                    ops: wgpu::Operations {
+                        load: if clear {
+                            wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT)
+                        } else {
+                            wgpu::LoadOp::Load
+                        },
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                }),
+                Some(wgpu::RenderPassColorAttachment {
+                    view: linear_depth_view,
+                    resolve_target: linear_depth_resolve_target,
+                    ops: wgpu::Operations {
+                        load: if clear {
+                            wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT)
+                        } else {
+                            wgpu::LoadOp::Load
+                        },
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                }),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth,
+                depth_ops: Some(wgpu::Operations {
+                    load: if clear {
+                        wgpu::LoadOp::Clear(self.depth_mode.far_depth())
+                    } else {
+                        wgpu::LoadOp::Load
+                    },
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        })
+    }
+
+    // Each split view gets its own viewport of the HDR targets
+    fn render_split_views(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut render_pass = self.begin_scene_pass(
+            encoder,
+            "Split View Pass",
+            &self.hdr,
+            &self.depth_texture.view,
+            false,
+        );
+        for split_view in &self.split_views {
+            let [x, y, width, height] =
+                split_view.pixel_rect(self.render_config.width, self.render_config.height);
+            if width == 0 || height == 0 {
+                continue;
+            }
+            render_pass.set_viewport(x as f32, y as f32, width as f32, height as f32, 0.0, 1.0);
+            render_pass.set_scissor_rect(x, y, width, height);
+            render_pass.push_debug_group("View");
+            self.viewport_clear.render(&mut render_pass);
+            self.draw_view(&mut render_pass, &split_view.view);
+            render_pass.pop_debug_group();
+        }
+    }
+
+    // Points the scene renderer at an offscreen target instead of the HDR
+    // targets, drawn from `view`'s camera
+    fn render_offscreen(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &offscreen::OffscreenTarget,
+        view: &viewport::ViewCamera,
+    ) {
+        let mut render_pass = self.begin_scene_pass(
+            encoder,
+            "Offscreen Pass",
+            &target.hdr,
+            &target.depth.view,
+            true,
+        );
+        self.draw_view(&mut render_pass, view);
+    }
+
+    // ===== EXTRA WINDOWS =====
+    // Opens another window onto the scene, seen through `camera`
+    fn open_window(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        title: &str,
+        camera: Camera,
+    ) -> anyhow::Result<WindowId> {
+        let window = Arc::new(
+            event_loop.create_window(
+                Window::default_attributes()
+                    .with_title(title)
+                    .with_inner_size(winit::dpi::LogicalSize::new(480, 360)),
+            )?,
+        );
+        let window = extra_window::ExtraWindow::new(
+            &self.instance,
+            &self.adapter,
+            &self.device,
+            window,
+            self.hdr.format(),
+            self.sample_count(),
+            &self.camera_bind_group_layout,
+            camera,
+        )?;
+        let id = window.id();
+        self.extra_windows.push(window);
+        Ok(id)
+    }
+
+    fn close_window(&mut self, id: WindowId) {
+        self.extra_windows.retain(|window| window.id() != id);
+        if self.particle_window == Some(id) {
+            self.particle_window = None;
+        }
+    }
+
+    fn extra_window_mut(&mut self, id: Option<WindowId>) -> Option<&mut extra_window::ExtraWindow> {
+        self.extra_windows
+            .iter_mut()
+            .find(|window| Some(window.id()) == id)
+    }
+
+    fn toggle_particle_window(&mut self, event_loop: &ActiveEventLoop) {
+        if let Some(id) = self.particle_window {
+            self.close_window(id);
+            return;
+        }
+        let camera = Camera {
+            fovy: 45.0,
+            znear: 0.01,
+            ..self.camera
+        };
+        match self.open_window(event_loop, "Particles", camera) {
+            Ok(id) => self.particle_window = Some(id),
+            Err(e) => tracing::error!("Couldn't open the particle window: {:#}", e),
+        }
+    }
 
-        if self.fire_enabled {
-            self.fire_system.update(dt);
+    // What happened to one of the extra windows
+    fn handle_window_event(&mut self, id: WindowId, event: WindowEvent) {
+        match event {
+            WindowEvent::CloseRequested | WindowEvent::Destroyed => self.close_window(id),
+            WindowEvent::Resized(size) => {
+                let device = self.device.clone();
+                if let Some(window) = self.extra_window_mut(Some(id)) {
+                    window.resize(&device, size.width, size.height);
+                }
+            }
+            WindowEvent::RedrawRequested => {
+                let Some(window) = self.extra_windows.iter().find(|window| window.id() == id)
+                else {
+                    return;
+                };
+                window.window.request_redraw();
+                let result = window.render(&self.device, &self.queue, |encoder, target, view| {
+                    self.render_offscreen(encoder, target, view)
+                });
+                match result {
+                    Ok(_) => {}
+                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                        let size = window.window.inner_size();
+                        let device = self.device.clone();
+                        if let Some(window) = self.extra_window_mut(Some(id)) {
+                            window.resize(&device, size.width, size.height);
+                        }
+                    }
+                    Err(e) => tracing::error!("Unable to render {}", e),
+                }
+            }
+            _ => {}
         }
     }
 
-    pub fn resize(&mut self, width: u32, height: u32) {
-        if width > 0 && height > 0 {
-            self.config.width = width;
-            self.config.height = height;
-            self.surface.configure(&self.device, &self.config);
-            self.is_surface_configured = true;
+    // Forward-lit model and ground, the sky and the fire seen through another
+    // camera than the main one, without SSAO
+    fn draw_view<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        view: &'a viewport::ViewCamera,
+    ) {
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.set_bind_group(2, &self.lighting.bind_group, &[]);
+        render_pass.set_bind_group(3, &self.ssao.unoccluded_bind_group, &[]);
+        material_array::draw_model_instanced(
+            render_pass,
+            self.assets.model(self.obj_model),
+            self.material_array.as_ref(),
+            0..self.instances.len() as u32,
+            &view.bind_group,
+        );
+        let frustum = view.camera.frustum();
+        if let Some(terrain) = &self.terrain {
+            terrain.render(
+                render_pass,
+                &view.bind_group,
+                &self.lighting.bind_group,
+                &self.ssao.unoccluded_bind_group,
+                &frustum,
+            );
+        }
+        if let Some(grass) = &self.grass {
+            grass.render(
+                render_pass,
+                &view.bind_group,
+                &self.lighting.bind_group,
+                &self.ssao.unoccluded_bind_group,
+                &frustum,
+            );
+        }
+        self.skybox.render(render_pass, &view.bind_group);
+        if self.grid.enabled {
+            self.grid.render(render_pass, &view.bind_group);
+        }
+        if self.fire_enabled {
+            for fire_system in &self.fire_systems {
+                if frustum.intersects_sphere(&fire_system.bounds()) {
+                    fire_system.render_view(render_pass, &view.bind_group);
+                }
+            }
         }
-        self.depth_texture =
-            texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
     }
 
+    #[profiling::function]
+    #[tracing::instrument(level = "trace", skip_all)]
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        self.window.request_redraw();
+        self.output.request_redraw();
 
         // We can't render unless the surface is configured
         if !self.is_surface_configured {
             return Ok(());
         }
 
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        let frame = self.output.next_frame()?;
+        let view = &frame.view;
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
             });
 
-        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("Render Pass"),
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &view,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(self.clear_color),
-                    store: wgpu::StoreOp::Store,
-                },
-                depth_slice: None,
-            })],
-            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &self.depth_texture.view,
-                depth_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(1.0),
-                    store: wgpu::StoreOp::Store,
-                }),
-                stencil_ops: None,
-            }),
-            occlusion_query_set: None,
-            timestamp_writes: None,
-        });
-        // render_pass.set_pipeline(&self.render_pipeline); // 2.
-        // render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-        // render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
-        // render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-        // render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-        // render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16); // 1.
-        // render_pass.draw_indexed(0..self.num_indices, 0, 0..self.instances.len() as u32);
+        // The graph works out which passes this frame needs and runs them in order
+        let render_graph = std::mem::take(&mut self.render_graph);
+        let mut gpu_timer = self.gpu_timer.take();
+        render_graph.execute(self, &mut encoder, &[("surface", view)], gpu_timer.as_mut());
+        if let Some(gpu_timer) = &mut gpu_timer {
+            gpu_timer.resolve(&mut encoder);
+        }
+        self.gpu_timer = gpu_timer;
+        self.render_graph = render_graph;
 
-        use model::DrawModel;
+        // The frame's uploads are copied ahead of everything that reads them
+        let uploads = self.uploads.finish(&self.device);
+        // submit will accept anything that implements IntoIter
+        self.queue.submit([uploads, encoder.finish()]);
+        self.uploads.recall(&self.device);
+        if let Some(occlusion) = &mut self.occlusion {
+            occlusion.after_submit();
+        }
+        if let Some(gpu_timer) = &mut self.gpu_timer {
+            gpu_timer.after_submit();
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if std::mem::take(&mut self.capture_requested) {
+            match self.capture_frame(&frame.texture) {
+                Ok(path) => tracing::info!("Saved {}", path.display()),
+                Err(e) => tracing::error!("Couldn't save a screenshot: {:#}", e),
+            }
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(recording) = &mut self.recording {
+            let result = if recording.wants_frame() {
+                capture::read_texture(&self.device, &self.queue, &frame.texture)
+                    .and_then(|image| recording.advance(Some(&image)))
+            } else {
+                recording.advance(None)
+            };
+            if let Err(e) = result {
+                tracing::error!("Recording stopped: {:#}", e);
+                self.stop_recording();
+            }
+        }
+        frame.present();
+        profiling::finish_frame!();
 
-        render_pass.set_pipeline(&self.render_pipeline);
-        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        Ok(())
+    }
 
-        render_pass.draw_model_instanced(
-            &self.obj_model,
-            0..self.instances.len() as u32,
-            &self.camera_bind_group,
+    // Writes a frame that's been submitted but not presented to a PNG in
+    // `SCREENSHOT_DIR`
+    #[cfg(not(target_arch = "wasm32"))]
+    fn capture_frame(&self, texture: &wgpu::Texture) -> anyhow::Result<std::path::PathBuf> {
+        let path =
+            capture::timestamped_path(std::path::Path::new(SCREENSHOT_DIR), "screenshot", "png");
+        capture::save_png(&self.device, &self.queue, texture, &path)?;
+        Ok(path)
+    }
+
+    // Records into a video when ffmpeg's there to make one, or else into a
+    // directory of numbered PNGs
+    #[cfg(not(target_arch = "wasm32"))]
+    fn start_recording(&mut self) {
+        let dir = std::path::Path::new(RECORDING_DIR);
+        let size = [self.config.width, self.config.height];
+        let video = capture::timestamped_path(dir, "recording", "mp4");
+        let recording = std::fs::create_dir_all(dir)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| recording::Recording::ffmpeg(&video, size, RECORD_EVERY, RECORD_FPS))
+            .inspect(|_| tracing::info!("Recording to {}", video.display()))
+            .or_else(|e| {
+                let frames = capture::timestamped_path(dir, "recording", "");
+                tracing::info!(
+                    "No video ({:#}), recording frames to {}",
+                    e,
+                    frames.display()
+                );
+                recording::Recording::png_sequence(&frames, RECORD_EVERY, RECORD_FPS)
+            });
+        match recording {
+            Ok(recording) => self.recording = Some(recording),
+            Err(e) => tracing::error!("Couldn't start recording: {:#}", e),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn stop_recording(&mut self) {
+        let Some(recording) = self.recording.take() else {
+            return;
+        };
+        let captured = recording.captured();
+        match recording.finish() {
+            Ok(()) => tracing::info!("Recorded {} frames", captured),
+            Err(e) => tracing::error!("Couldn't finish the recording: {:#}", e),
+        }
+        // Back to real time without one long step
+        self.last_update = std::time::Instant::now();
+    }
+
+    fn set_fire(&mut self, enabled: bool) {
+        // The flames bursting out knock the camera about, lit with a full load of fuel
+        if enabled && !self.fire_enabled {
+            self.cameras.shake.add_trauma(0.6);
+            for fire_system in &mut self.fire_systems {
+                fire_system.refuel();
+            }
+        }
+        self.fire_enabled = enabled;
+        tracing::info!("Fire {}", if enabled { "enabled" } else { "disabled" });
+    }
+
+    // Queues the HUD's sprites for a `width` by `height` surface: a crosshair
+    // in the middle and a bar of the fire's fuel in the bottom left
+    fn draw_hud(&mut self, width: f32, height: f32) {
+        let Some(sprites) = &mut self.sprites else {
+            return;
+        };
+        sprites.sprite_centered(
+            sprites::CROSSHAIR,
+            [width / 2.0, height / 2.0],
+            [1.0, 1.0, 1.0, 0.8],
         );
+        if self.fire_enabled && !self.fire_systems.is_empty() {
+            let fuel = self
+                .fire_systems
+                .iter()
+                .map(|fire_system| fire_system.fuel)
+                .sum::<f32>()
+                / self.fire_systems.len() as f32;
+            // Orange when there's plenty, red as it runs out
+            let color = if fuel > 0.25 {
+                [1.0, 0.45, 0.05, 1.0]
+            } else {
+                [0.9, 0.08, 0.02, 1.0]
+            };
+            sprites.bar([16.0, height - 32.0, 200.0, 12.0], fuel, color);
+        }
+    }
 
-        // Render fire system (render after model so fire is on top with proper blending)
-        if self.fire_enabled {
-            self.fire_system.render(&self.queue, &mut render_pass, &self.camera_bind_group);
+    // Queues the debug lines: a box around each instance of the model, a
+    // sphere around each fire with its particles' velocities, and every
+    // light's position and the way it points
+    fn draw_debug_shapes(&mut self) {
+        let aabb = self.assets.model(self.obj_model).aabb();
+        for instance in &self.instances {
+            self.debug_draw.aabb(
+                &aabb.transformed(instance.position, instance.rotation),
+                [0.2, 1.0, 0.4, 1.0],
+            );
+        }
+        for fire_system in &self.fire_systems {
+            let bounds = fire_system.bounds();
+            self.debug_draw
+                .sphere(bounds.center, bounds.radius, [1.0, 0.5, 0.1, 0.6]);
+            for (position, velocity) in fire_system.particles() {
+                let position = cgmath::Point3::from(position);
+                self.debug_draw.line(
+                    position,
+                    position + cgmath::Vector3::from(velocity) * 0.1,
+                    [1.0, 0.9, 0.3, 0.8],
+                );
+            }
+        }
+        for light in self.lighting.lights() {
+            let position = cgmath::Point3::from_vec(light.position);
+            match light.kind {
+                light::LightKind::Directional => {}
+                light::LightKind::Point => self.debug_draw.axes(position, 0.25),
+                light::LightKind::Spot => {
+                    self.debug_draw.axes(position, 0.25);
+                    self.debug_draw.arrow(
+                        position,
+                        position + light.direction.normalize(),
+                        [1.0, 1.0, 0.6, 1.0],
+                    );
+                }
+            }
         }
+    }
 
-        // 2.
+    // ===== SEQUENCES =====
+    fn run_sequence_action(&mut self, action: sequencer::SequenceAction) {
+        match action {
+            sequencer::SequenceAction::Cut(name) => {
+                if !self.cameras.select(&name) {
+                    tracing::warn!("The sequence cuts to {}, there's no such camera", name);
+                } else if let cameras::CameraControl::Path(player) =
+                    &mut self.cameras.active_mut().control
+                {
+                    player.seek(0.0);
+                    player.play();
+                }
+            }
+            sequencer::SequenceAction::Fire(enabled) => self.set_fire(enabled),
+            sequencer::SequenceAction::Shake(trauma) => self.cameras.shake.add_trauma(trauma),
+            sequencer::SequenceAction::Animation(name) => {
+                let Some(animator) = &mut self.animator else {
+                    return;
+                };
+                match animator.rig.clips.iter().position(|clip| clip.name == name) {
+                    Some(clip) => animator.play(clip),
+                    None => tracing::warn!("The sequence plays {}, the rig has no such clip", name),
+                }
+            }
+        }
+    }
 
-        drop(render_pass);
+    // The closest point on any of the model's instances under `x` and `y`
+    // across the screen, see `Camera::screen_to_ray`
+    fn pick(&self, x: f32, y: f32) -> Option<raycast::RayHit> {
+        self.pick_mesh(x, y).map(|(_, hit)| hit)
+    }
 
-        // submit will accept anything that implements IntoIter
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+    // The same, along with which of the model's meshes it hit
+    fn pick_mesh(&self, x: f32, y: f32) -> Option<(usize, raycast::RayHit)> {
+        let ray = self.camera.screen_to_ray(x, y);
+        let model = self.assets.model(self.obj_model);
+        self.instances
+            .iter()
+            .filter_map(|instance| {
+                let transform = cgmath::Matrix4::from_translation(instance.position)
+                    * cgmath::Matrix4::from(instance.rotation);
+                let inverse = transform.invert()?;
+                let (mesh, hit) = model.raycast_mesh(&ray.transformed(inverse))?;
+                // Instances only turn and move, so distances carry over
+                Some((
+                    mesh,
+                    raycast::RayHit {
+                        distance: hit.distance,
+                        position: transform.transform_point(hit.position),
+                        normal: transform.transform_vector(hit.normal),
+                    },
+                ))
+            })
+            .min_by(|(_, a), (_, b)| a.distance.total_cmp(&b.distance))
+    }
+
+    // ===== DROPPED FILES =====
+    // A model dropped onto the window is loaded in place of the scene's and
+    // framed. Anything else is taken for an image and becomes the base color
+    // of the material under the cursor.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn handle_dropped_file(&mut self, path: &std::path::Path) {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match extension.as_str() {
+            "obj" | "gltf" | "glb" => {
+                tracing::info!("Loading {}", path.display());
+                self.dropped_model = Some(path.to_string_lossy().into_owned());
+                self.frame_on_reload = true;
+                self.start_asset_reload();
+            }
+            _ => {
+                if let Err(e) = self.set_dropped_texture(path) {
+                    tracing::error!("Couldn't use {} as a texture: {:#}", path.display(), e);
+                }
+            }
+        }
+    }
 
+    #[cfg(not(target_arch = "wasm32"))]
+    fn set_dropped_texture(&mut self, path: &std::path::Path) -> anyhow::Result<()> {
+        let data =
+            std::fs::read(path).with_context(|| format!("Couldn't read {}", path.display()))?;
+        let texture = self.assets.load_texture_from_memory(
+            &path.to_string_lossy(),
+            &data,
+            color::ColorSpace::Srgb,
+        )?;
+        let texture = self.assets.texture(texture).clone();
+        // The first material when the cursor's off the model
+        let mesh = self
+            .pick_mesh(self.cursor[0], self.cursor[1])
+            .map_or(0, |(mesh, _)| mesh);
+        let model = self.assets.model_mut(self.obj_model);
+        let index = model.meshes.get(mesh).map_or(0, |mesh| mesh.material);
+        let material = model
+            .materials
+            .get_mut(index)
+            .context("The model has no materials")?;
+        material.set_diffuse_texture(&self.device, texture, &self.material_layout);
+        tracing::info!("{} now uses {}", material.name, path.display());
+        // Packed materials are copied into arrays, so pack them over again
+        if self.material_array.is_some() {
+            self.material_array = material_array::MaterialArray::new(
+                &self.device,
+                &self.queue,
+                self.assets.model(self.obj_model),
+                &self.material_array_layout,
+            );
+        }
         Ok(())
     }
-    fn handle_key(&mut self, event_loop: &ActiveEventLoop, code: KeyCode, is_pressed: bool) {
-        match (code, is_pressed) {
-            (KeyCode::Escape, true) => event_loop.exit(),
-            (KeyCode::Space, true) => {
-                self.fire_enabled = !self.fire_enabled;
-                log::info!("Fire {}", if self.fire_enabled { "enabled" } else { "disabled" });
+
+    // Clicking on the model moves the first fire to where it was clicked,
+    // off any socket it was on
+    fn move_fire_to_cursor(&mut self) {
+        let Some(hit) = self.pick(self.cursor[0], self.cursor[1]) else {
+            return;
+        };
+        if !self.fire_systems.is_empty() {
+            self.set_fire_origin(0, hit.position);
+            tracing::info!("Fire moved to {:?}", self.fire_systems[0].origin);
+        }
+    }
+
+    // Moves fire `i` and its emitter, off any socket it was on, so it stays
+    // there when the scene's placed again
+    fn set_fire_origin(&mut self, i: usize, position: cgmath::Point3<f32>) {
+        let ground_height = self
+            .on_ground(cgmath::Vector3::new(position.x, 0.0, position.z))
+            .y;
+        let (Some(fire_system), Some((emitter, placement))) =
+            (self.fire_systems.get_mut(i), self.emitters.get_mut(i))
+        else {
+            return;
+        };
+        emitter.socket = None;
+        if let Some(inverse) = placement.invert() {
+            emitter.position = inverse.transform_point(position).into();
+        }
+        fire_system.origin = position.into();
+        fire_system.ground_height = ground_height;
+    }
+
+    // ===== TRANSFORM GIZMO =====
+    // Every fire, then every scene light that has a position
+    fn gizmo_targets(&self) -> Vec<GizmoTarget> {
+        let fires = (0..self.fire_systems.len()).map(GizmoTarget::Fire);
+        let lights = self.scene_lights.iter().copied().filter(|&id| {
+            self.lighting
+                .light(id)
+                .is_some_and(|light| light.kind != light::LightKind::Directional)
+        });
+        fires.chain(lights.map(GizmoTarget::Light)).collect()
+    }
+
+    fn gizmo_target(&self) -> Option<GizmoTarget> {
+        let targets = self.gizmo_targets();
+        targets
+            .get(self.gizmo_target % targets.len().max(1))
+            .copied()
+    }
+
+    fn gizmo_target_position(&self, target: GizmoTarget) -> Option<cgmath::Point3<f32>> {
+        match target {
+            GizmoTarget::Fire(i) => self
+                .fire_systems
+                .get(i)
+                .map(|fire_system| fire_system.origin.into()),
+            GizmoTarget::Light(id) => self
+                .lighting
+                .light(id)
+                .map(|light| cgmath::Point3::from_vec(light.position)),
+        }
+    }
+
+    // The handles around the selected target, as the camera sees them
+    fn gizmo_view(&self) -> Option<gizmo::GizmoView> {
+        let center = self.gizmo_target_position(self.gizmo_target()?)?;
+        Some(gizmo::GizmoView {
+            view_proj: self.camera.build_view_projection_matrix(),
+            screen_size: [self.config.width as f32, self.config.height as f32],
+            center,
+            size: self.camera.units_across(center, 0.15),
+        })
+    }
+
+    // Starts dragging a handle if there's one under the cursor
+    fn grab_gizmo(&mut self) -> bool {
+        let Some(view) = self.gizmo_view() else {
+            return false;
+        };
+        let cursor = [
+            self.cursor[0] * view.screen_size[0],
+            self.cursor[1] * view.screen_size[1],
+        ];
+        let ray = self.camera.screen_to_ray(self.cursor[0], self.cursor[1]);
+        self.gizmo.grab(&view, cursor, &ray)
+    }
+
+    // Moves or turns the target as far as the cursor's dragged the handle
+    fn drag_gizmo(&mut self) {
+        let ray = self.camera.screen_to_ray(self.cursor[0], self.cursor[1]);
+        let (Some(delta), Some(target)) = (self.gizmo.drag(&ray), self.gizmo_target()) else {
+            return;
+        };
+        match (target, delta) {
+            (GizmoTarget::Fire(i), gizmo::GizmoDelta::Translate(offset)) => {
+                let origin = cgmath::Point3::from(self.fire_systems[i].origin) + offset;
+                self.set_fire_origin(i, origin);
+            }
+            (GizmoTarget::Fire(i), gizmo::GizmoDelta::Rotate(rotation)) => {
+                let fire_system = &mut self.fire_systems[i];
+                fire_system.rotation = (rotation * fire_system.rotation).normalize();
+            }
+            (GizmoTarget::Light(id), delta) => {
+                if let Some(light) = self.lighting.light_mut(id) {
+                    match delta {
+                        gizmo::GizmoDelta::Translate(offset) => light.position += offset,
+                        gizmo::GizmoDelta::Rotate(rotation) => {
+                            light.direction = rotation.rotate_vector(light.direction)
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Lets go of the handle, logging where the target ended up for the scene file
+    fn release_gizmo(&mut self) {
+        if !self.gizmo.release() {
+            return;
+        }
+        match self.gizmo_target() {
+            Some(GizmoTarget::Fire(i)) => {
+                let fire_system = &self.fire_systems[i];
+                tracing::info!(
+                    "Fire {} at {:?}, turned {:?}",
+                    i,
+                    fire_system.origin,
+                    fire_system.rotation
+                );
+            }
+            Some(GizmoTarget::Light(id)) => {
+                if let Some(light) = self.lighting.light(id) {
+                    tracing::info!(
+                        "Light at {:?}, pointing {:?}",
+                        light.position,
+                        light.direction
+                    );
+                }
+            }
+            None => {}
+        }
+    }
+
+    // ===== DEBUG UI =====
+    // The debug UI's windows, laid out every frame while it's up
+    #[cfg(feature = "egui")]
+    fn debug_windows(&mut self, context: &egui::Context, frame_time: f32) {
+        egui::Window::new("Rendering").show(context, |ui| {
+            ui.label(format!(
+                "{:.2} ms ({:.0} FPS)",
+                frame_time * 1000.0,
+                1.0 / frame_time.max(f32::EPSILON)
+            ));
+            let mut fire = self.fire_enabled;
+            if ui.checkbox(&mut fire, "Fire").changed() {
+                self.set_fire(fire);
+            }
+            ui.checkbox(&mut self.fxaa_enabled, "FXAA");
+            ui.checkbox(&mut self.ssao_enabled, "SSAO");
+            ui.checkbox(&mut self.oit_enabled, "Order-independent transparency");
+            ui.checkbox(&mut self.split_views_enabled, "Split views");
+            ui.checkbox(&mut self.security_camera_enabled, "Security camera");
+        });
+    }
+
+    // ===== FRAME PACING =====
+    // Off, 30, 60 and 144 frames a second in turn
+    fn cycle_frame_cap(&mut self) {
+        const CAPS: [Option<f32>; 4] = [None, Some(30.0), Some(60.0), Some(144.0)];
+        let current = self.frame_limiter.fps().map(f32::round);
+        let next = CAPS
+            .iter()
+            .position(|cap| *cap == current)
+            .map_or(0, |i| (i + 1) % CAPS.len());
+        self.frame_limiter.set_fps(CAPS[next]);
+        match CAPS[next] {
+            Some(fps) => tracing::info!("Frame cap {} fps", fps),
+            None => tracing::info!("Frame cap disabled"),
+        }
+    }
+
+    // Switches between presenting on vertical blank and as soon as a frame's
+    // done, Immediate if the surface has it or else Mailbox
+    fn toggle_vsync(&mut self) {
+        let vsync = matches!(
+            self.config.present_mode,
+            wgpu::PresentMode::Fifo | wgpu::PresentMode::FifoRelaxed
+        );
+        let present_mode = if vsync {
+            let Some(surface) = self.output.surface() else {
+                return;
+            };
+            let caps = surface.get_capabilities(&self.adapter);
+            match [wgpu::PresentMode::Immediate, wgpu::PresentMode::Mailbox]
+                .into_iter()
+                .find(|mode| caps.present_modes.contains(mode))
+            {
+                Some(mode) => mode,
+                None => {
+                    tracing::warn!("The surface can only present with vsync");
+                    return;
+                }
             }
-            _ => self.camera_controller.handle_key(code, is_pressed),
+        } else {
+            // Every surface has Fifo
+            wgpu::PresentMode::Fifo
+        };
+        self.config.present_mode = present_mode;
+        self.output.configure(&self.device, &self.config);
+        tracing::info!(
+            "Vsync {} ({:?})",
+            if vsync { "disabled" } else { "enabled" },
+            present_mode
+        );
+    }
+
+    // The app's gone into the background, see `FrameOutput::suspend`
+    fn suspend(&mut self) {
+        self.output.suspend();
+        self.is_surface_configured = false;
+        self.release_input();
+    }
+
+    fn resume(&mut self) -> anyhow::Result<()> {
+        self.output.resume(&self.instance)?;
+        // The pipelines drawing to it were built for the old surface's format
+        if let Some(surface) = self.output.surface() {
+            let caps = surface.get_capabilities(&self.adapter);
+            anyhow::ensure!(
+                caps.formats.contains(&self.config.format),
+                "The window's surface can't be {:?} any more",
+                self.config.format
+            );
         }
+        if let Some(window) = self.output.window().cloned() {
+            let size = window.inner_size();
+            self.resize(size.width, size.height);
+        }
+        // Carry on from where it was rather than one step the length of
+        // the time away
+        self.last_update = std::time::Instant::now();
+        Ok(())
+    }
+
+    // For a lost surface, made again from the window
+    fn recreate_surface(&mut self) -> anyhow::Result<()> {
+        self.output.suspend();
+        self.resume()
     }
 }
 
 pub struct App {
     #[cfg(target_arch = "wasm32")]
-    proxy: Option<winit::event_loop::EventLoopProxy<State>>,
+    proxy: Option<winit::event_loop::EventLoopProxy<Loading>>,
+    // Which GPU the window's drawn with
+    options: gpu_options::GpuOptions,
+    // Until the assets are in, then `state` takes over
+    loading: Option<Loading>,
     state: Option<State>,
+    // What's put back over the state once it's been built again after the
+    // device was lost
+    kept: Option<device_recovery::KeptState>,
 }
 
 impl App {
-    pub fn new(#[cfg(target_arch = "wasm32")] event_loop: &EventLoop<State>) -> Self {
+    pub fn new(
+        options: gpu_options::GpuOptions,
+        #[cfg(target_arch = "wasm32")] event_loop: &EventLoop<Loading>,
+    ) -> Self {
         #[cfg(target_arch = "wasm32")]
         let proxy = Some(event_loop.create_proxy());
         Self {
+            options,
+            loading: None,
             state: None,
+            kept: None,
             #[cfg(target_arch = "wasm32")]
             proxy,
         }
     }
+
+    // Connects `window` to the GPU and starts loading the scene into it, on
+    // the web once the connection's made
+    fn start_loading(
+        &mut self,
+        window: Arc<Window>,
+        model_file: Option<String>,
+    ) -> anyhow::Result<()> {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            // If we are not on web we can use pollster to
+            // await the
+            self.loading = Some(pollster::block_on(Loading::new(
+                window,
+                &self.options,
+                model_file,
+            ))?);
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            // Run the future asynchronously and use the
+            // proxy to send the results to the event loop
+            if let Some(proxy) = self.proxy.clone() {
+                let options = self.options.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    match Loading::new(window, &options, model_file).await {
+                        Ok(loading) => assert!(proxy.send_event(loading).is_ok()),
+                        Err(e) => tracing::error!("Couldn't set up the GPU: {:#}", e),
+                    }
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn is_device_lost(&self) -> bool {
+        let lost = |device_lost: &AtomicBool| device_lost.load(Ordering::Relaxed);
+        self.state
+            .as_ref()
+            .is_some_and(|state| lost(&state.device_lost))
+            || self
+                .loading
+                .as_ref()
+                .is_some_and(|loading| lost(&loading.gpu.device_lost))
+    }
+
+    // Nothing made on a lost device can be used again, so it's all built
+    // over on a new one from res/ and any model dropped onto the window. The
+    // window stays, and so does what's kept of the state, see
+    // `device_recovery`.
+    #[allow(unused_mut)]
+    fn recover_device(&mut self, event_loop: &ActiveEventLoop) {
+        let mut model_file = None;
+        let window = match (self.state.take(), self.loading.take()) {
+            (Some(state), _) => {
+                #[cfg(not(target_arch = "wasm32"))]
+                {
+                    model_file = state.dropped_model.clone();
+                }
+                let window = state.output.window().cloned();
+                self.kept = Some(state.into_kept());
+                window
+            }
+            (None, Some(loading)) => Some(loading.window),
+            (None, None) => None,
+        };
+        let Some(window) = window else {
+            return;
+        };
+        tracing::warn!("Starting over on a new device");
+        if let Err(e) = self.start_loading(window, model_file) {
+            tracing::error!("Couldn't set up the GPU again: {:#}", e);
+            event_loop.exit();
+        }
+    }
 }
 
-impl ApplicationHandler<State> for App {
+impl ApplicationHandler<Loading> for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        // Back from the background on mobile, the window's still there and
+        // only its surface needs making again
+        let resumed = match (&mut self.state, &mut self.loading) {
+            (Some(state), _) => Some(state.resume()),
+            (None, Some(loading)) => Some(loading.resume()),
+            (None, None) => None,
+        };
+        if let Some(result) = resumed {
+            if let Err(e) = result {
+                tracing::error!("Couldn't resume: {:#}", e);
+                event_loop.exit();
+            }
+            return;
+        }
+
         #[allow(unused_mut)]
         let mut window_attributes = Window::default_attributes();
 
@@ -758,35 +3776,32 @@ impl ApplicationHandler<State> for App {
             window_attributes = window_attributes.with_canvas(Some(html_canvas_element));
         }
 
-        let window = Arc::new(event_loop.create_window(window_attributes).unwrap());
-
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            // If we are not on web we can use pollster to
-            // await the
-            self.state = Some(pollster::block_on(State::new(window)).unwrap());
+        let window = match event_loop.create_window(window_attributes) {
+            Ok(window) => Arc::new(window),
+            Err(e) => {
+                tracing::error!("Couldn't open a window: {}", e);
+                event_loop.exit();
+                return;
+            }
+        };
+        if let Err(e) = self.start_loading(window, None) {
+            tracing::error!("Couldn't set up the GPU: {:#}", e);
+            event_loop.exit();
         }
+    }
 
-        #[cfg(target_arch = "wasm32")]
-        {
-            // Run the future asynchronously and use the
-            // proxy to send the results to the event loop
-            if let Some(proxy) = self.proxy.take() {
-                wasm_bindgen_futures::spawn_local(async move {
-                    assert!(proxy
-                        .send_event(
-                            State::new(window)
-                                .await
-                                .expect("Unable to create canvas!!!")
-                        )
-                        .is_ok())
-                });
-            }
+    // Android and iOS, the surface has to go before this returns
+    fn suspended(&mut self, _event_loop: &ActiveEventLoop) {
+        if let Some(state) = &mut self.state {
+            state.suspend();
+        }
+        if let Some(loading) = &mut self.loading {
+            loading.suspend();
         }
     }
 
     #[allow(unused_mut)]
-    fn user_event(&mut self, _event_loop: &ActiveEventLoop, mut event: State) {
+    fn user_event(&mut self, _event_loop: &ActiveEventLoop, mut event: Loading) {
         // This is where proxy.send_event() ends up
         #[cfg(target_arch = "wasm32")]
         {
@@ -796,52 +3811,129 @@ impl ApplicationHandler<State> for App {
                 event.window.inner_size().height,
             );
         }
-        self.state = Some(event);
+        self.loading = Some(event);
     }
 
     fn window_event(
         &mut self,
         event_loop: &ActiveEventLoop,
-        _window_id: winit::window::WindowId,
+        window_id: WindowId,
         event: WindowEvent,
     ) {
+        if self.is_device_lost() {
+            self.recover_device(event_loop);
+        }
+
+        if let Some(loading) = &mut self.loading {
+            match event {
+                WindowEvent::CloseRequested => event_loop.exit(),
+                WindowEvent::Resized(size) => loading.resize(size.width, size.height),
+                WindowEvent::ScaleFactorChanged { .. } => {
+                    let size = loading.window.inner_size();
+                    loading.resize(size.width, size.height);
+                }
+                WindowEvent::RedrawRequested => {
+                    match loading.render() {
+                        Ok(_) => {}
+                        Err(wgpu::SurfaceError::Outdated) => {
+                            let size = loading.window.inner_size();
+                            loading.resize(size.width, size.height);
+                        }
+                        // Made again from the window, like after a suspend
+                        Err(wgpu::SurfaceError::Lost) => {
+                            loading.suspend();
+                            if let Err(e) = loading.resume() {
+                                tracing::error!("Couldn't remake the surface: {:#}", e);
+                                event_loop.exit();
+                            }
+                        }
+                        Err(e) => tracing::error!("Unable to render {}", e),
+                    }
+                    if let Some(assets) = loading.assets.take() {
+                        let loading = self.loading.take().unwrap();
+                        match assets.and_then(|assets| loading.into_state(assets)) {
+                            Ok(mut state) => {
+                                if let Some(kept) = self.kept.take() {
+                                    state.restore(kept);
+                                }
+                                self.state = Some(state);
+                            }
+                            Err(e) => {
+                                tracing::error!("Couldn't load the scene: {:#}", e);
+                                event_loop.exit();
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+            return;
+        }
+
         let state = match &mut self.state {
             Some(canvas) => canvas,
             None => return,
         };
+        let Some(window) = state.output.window().cloned() else {
+            return;
+        };
+        if window_id != window.id() {
+            state.handle_window_event(window_id, event);
+            return;
+        }
+        // Clicks and keys over the debug UI stay there
+        #[cfg(feature = "egui")]
+        if let Some(debug_ui) = &mut state.debug_ui {
+            if debug_ui.on_window_event(&window, &event) {
+                return;
+            }
+        }
 
         match event {
             WindowEvent::CloseRequested => event_loop.exit(),
             WindowEvent::Resized(size) => state.resize(size.width, size.height),
+            // Moved to a screen with another density, or the density changed
+            // in the system settings. Usually a resize follows but not on
+            // every platform.
+            WindowEvent::ScaleFactorChanged { .. } => {
+                let size = window.inner_size();
+                state.resize(size.width, size.height);
+            }
             WindowEvent::CursorMoved {
                 device_id: _,
                 position,
             } => {
                 // use position to change the color of the screen
-                let window_size = state.window.inner_size();
+                let window_size = window.inner_size();
                 // normalize the pixel values of x,y
                 let r = (position.x / window_size.width as f64).clamp(0.0, 1.0);
                 let g = (position.y / window_size.height as f64).clamp(0.0, 1.0);
                 // add this to the state
-                state.clear_color = wgpu::Color {
-                    r,
-                    g,
-                    b: 0.3,
-                    a: 1.0,
-                };
-                state.window.request_redraw();
+                state.clear_color = color::srgb_color(r, g, 0.3);
+                state.cursor = [r as f32, g as f32];
+                window.request_redraw();
             }
             WindowEvent::RedrawRequested => {
+                // The browser paces frames itself, and can't be blocked
+                #[cfg(not(target_arch = "wasm32"))]
+                state.frame_limiter.wait();
                 state.update();
                 match state.render() {
                     Ok(_) => {}
-                    // Reconfigure the surface if it's lost or outdated
-                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                        let size = state.window.inner_size();
+                    // Reconfigure the surface if it's outdated
+                    Err(wgpu::SurfaceError::Outdated) => {
+                        let size = window.inner_size();
                         state.resize(size.width, size.height);
                     }
+                    // and make it again from the window if it's lost
+                    Err(wgpu::SurfaceError::Lost) => {
+                        if let Err(e) = state.recreate_surface() {
+                            tracing::error!("Couldn't remake the surface: {:#}", e);
+                            event_loop.exit();
+                        }
+                    }
                     Err(e) => {
-                        log::error!("Unable to render {}", e);
+                        tracing::error!("Unable to render {}", e);
                     }
                 }
             }
@@ -853,8 +3945,39 @@ impl ApplicationHandler<State> for App {
                         ..
                     },
                 ..
-            } => state.handle_key(event_loop, code, key_state.is_pressed()),
+            } => state.handle_input(
+                event_loop,
+                input_map::Binding::Key(code),
+                key_state.is_pressed(),
+            ),
+            WindowEvent::MouseInput {
+                state: button_state,
+                button,
+                ..
+            } => state.handle_input(
+                event_loop,
+                input_map::Binding::Mouse(button),
+                button_state.is_pressed(),
+            ),
+            WindowEvent::MouseWheel { delta, .. } => state.handle_scroll(delta),
+            WindowEvent::Touch(touch) => state.handle_touch(&touch),
+            #[cfg(not(target_arch = "wasm32"))]
+            WindowEvent::DroppedFile(path) => state.handle_dropped_file(&path),
+            WindowEvent::Focused(false) => state.release_input(),
             _ => {}
         }
     }
+
+    fn device_event(
+        &mut self,
+        _event_loop: &ActiveEventLoop,
+        _device_id: DeviceId,
+        event: DeviceEvent,
+    ) {
+        if let (Some(state), DeviceEvent::MouseMotion { delta: (dx, dy) }) =
+            (&mut self.state, event)
+        {
+            state.handle_mouse_motion(dx, dy);
+        }
+    }
 }
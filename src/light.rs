@@ -0,0 +1,531 @@
+use bytemuck::Zeroable;
+use cgmath::prelude::*;
+use wgpu::util::DeviceExt;
+
+use crate::environment::Environment;
+use crate::preprocess::Preprocessor;
+use crate::shadow::ShadowCascades;
+use crate::upload::Uploads;
+
+// Lights the storage buffer starts out with room for, it grows as needed
+const INITIAL_LIGHT_CAPACITY: usize = 16;
+// WebGL has no storage buffers, so lights go in a fixed uniform array there
+pub const MAX_UNIFORM_LIGHTS: usize = 16;
+
+// ===== DIRECTIONAL LIGHT =====
+// The "sun" that casts the cascaded shadows
+pub struct DirectionalLight {
+    pub direction: cgmath::Vector3<f32>, // Direction the light travels (from light to scene)
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub ambient: f32,
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        Self {
+            direction: cgmath::Vector3::new(-0.4, -1.0, -0.3).normalize(),
+            color: [1.0, 0.95, 0.9],
+            intensity: 3.0,
+            ambient: 0.15,
+        }
+    }
+}
+
+// ===== DYNAMIC LIGHTS =====
+// Matches the LIGHT_* constants in lighting.wgsl
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LightKind {
+    Point = 0,
+    Directional = 1, // Unshadowed, only uses `direction`
+    Spot = 2,
+}
+
+// Small local lights like the glow from the fire
+#[derive(Debug, Copy, Clone)]
+pub struct Light {
+    pub kind: LightKind,
+    pub position: cgmath::Vector3<f32>,
+    pub direction: cgmath::Vector3<f32>, // Direction the light travels, unused by point lights
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub range: f32, // Light fades to zero at this distance
+    // Spot lights are at full strength inside the inner cone and fade out
+    // towards the outer one. Angles are measured from `direction` to the cone's edge.
+    pub inner_angle: cgmath::Rad<f32>,
+    pub outer_angle: cgmath::Rad<f32>,
+}
+
+impl Light {
+    pub fn point(
+        position: cgmath::Vector3<f32>,
+        color: [f32; 3],
+        intensity: f32,
+        range: f32,
+    ) -> Self {
+        Self {
+            kind: LightKind::Point,
+            position,
+            direction: cgmath::Vector3::unit_z(),
+            color,
+            intensity,
+            range,
+            inner_angle: cgmath::Rad(0.0),
+            outer_angle: cgmath::Rad(0.0),
+        }
+    }
+
+    pub fn directional(direction: cgmath::Vector3<f32>, color: [f32; 3], intensity: f32) -> Self {
+        Self {
+            kind: LightKind::Directional,
+            position: cgmath::Vector3::zero(),
+            direction,
+            color,
+            intensity,
+            range: 0.0,
+            inner_angle: cgmath::Rad(0.0),
+            outer_angle: cgmath::Rad(0.0),
+        }
+    }
+
+    pub fn spot(
+        position: cgmath::Vector3<f32>,
+        direction: cgmath::Vector3<f32>,
+        color: [f32; 3],
+        intensity: f32,
+        range: f32,
+        inner_angle: impl Into<cgmath::Rad<f32>>,
+        outer_angle: impl Into<cgmath::Rad<f32>>,
+    ) -> Self {
+        Self {
+            kind: LightKind::Spot,
+            position,
+            direction,
+            color,
+            intensity,
+            range,
+            inner_angle: inner_angle.into(),
+            outer_angle: outer_angle.into(),
+        }
+    }
+
+    // Aim the light at a point, keeping its position
+    pub fn look_at(&mut self, target: cgmath::Vector3<f32>) {
+        self.direction = target - self.position;
+    }
+
+    fn to_raw(self) -> LightRaw {
+        let direction = if self.direction.magnitude2() > 0.0 {
+            self.direction.normalize()
+        } else {
+            -cgmath::Vector3::unit_y()
+        };
+        // Keep the inner cone inside the outer one so the falloff never divides by zero
+        let outer = self.outer_angle.0.max(0.0);
+        let inner = self.inner_angle.0.clamp(0.0, outer * 0.999);
+        LightRaw {
+            position: self.position.into(),
+            range: self.range,
+            color: self.color,
+            intensity: self.intensity,
+            direction: direction.into(),
+            kind: self.kind as u32,
+            cos_inner: inner.cos(),
+            cos_outer: outer.cos(),
+            _padding: [0; 2],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightRaw {
+    position: [f32; 3],
+    range: f32,
+    color: [f32; 3],
+    intensity: f32,
+    direction: [f32; 3],
+    kind: u32,
+    cos_inner: f32,
+    cos_outer: f32,
+    _padding: [u32; 2],
+}
+
+// Handle returned by `Lighting::add_light`, stays valid until the light is removed
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct LightId(u32);
+
+// ===== LIGHT UNIFORM =====
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightUniform {
+    sun_direction: [f32; 4], // xyz = direction, w = intensity
+    sun_color: [f32; 4],     // rgb = color, a = ambient strength
+    num_lights: u32,
+    environment_intensity: f32, // 0 without an environment, for the flat ambient
+    max_reflection_lod: f32,    // Last mip of the prefiltered environment
+    _padding: u32,
+}
+
+// The irradiance and prefiltered cubemaps image-based lighting samples
+struct EnvironmentMaps {
+    irradiance: wgpu::TextureView,
+    prefiltered: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+    prefiltered_mips: u32, // 0 for the black stand-ins
+}
+
+impl EnvironmentMaps {
+    // 1x1 black cubes to bind until there's an environment
+    fn empty(device: &wgpu::Device) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Empty Environment"),
+            size: wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        Self {
+            irradiance: view.clone(),
+            prefiltered: view,
+            sampler: device.create_sampler(&wgpu::SamplerDescriptor::default()),
+            prefiltered_mips: 0,
+        }
+    }
+}
+
+// ===== SCENE LIGHTING =====
+// Owns the lighting bind group shared by the forward and deferred paths:
+// the sun uniform, the array of dynamic lights, the shadow cascades they sample
+// and the environment maps for image-based lighting.
+pub struct Lighting {
+    pub sun: DirectionalLight,
+    // Scales the environment's light, once there is one it replaces the
+    // sun's flat ambient term
+    pub environment_intensity: f32,
+    lights: Vec<(LightId, Light)>,
+    next_id: u32,
+
+    // False on WebGL, where the lights live in a fixed size uniform array
+    storage: bool,
+    uniform_buffer: wgpu::Buffer,
+    light_buffer: wgpu::Buffer,
+    light_capacity: usize,
+    shadow_uniform: wgpu::Buffer,
+    shadow_view: wgpu::TextureView,
+    shadow_sampler: wgpu::Sampler,
+    environment: EnvironmentMaps,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl Lighting {
+    pub fn new(device: &wgpu::Device, shadows: &ShadowCascades) -> Self {
+        let storage = device.limits().max_storage_buffers_per_shader_stage > 0;
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Light Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[LightUniform::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let light_capacity = if storage {
+            INITIAL_LIGHT_CAPACITY
+        } else {
+            MAX_UNIFORM_LIGHTS
+        };
+        let light_buffer = Self::create_light_buffer(device, storage, light_capacity);
+
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let cube_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::Cube,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                // Sun and light count
+                uniform_entry(0),
+                // Cascade matrices and splits
+                uniform_entry(1),
+                // Shadow map array
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+                // Dynamic lights
+                if storage {
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    }
+                } else {
+                    uniform_entry(4)
+                },
+                // Irradiance and prefiltered environment cubemaps
+                cube_entry(5),
+                cube_entry(6),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("lighting_bind_group_layout"),
+        });
+
+        let shadow_uniform = shadows.uniform_buffer.clone();
+        let shadow_view = shadows.array_view.clone();
+        let shadow_sampler = shadows.sampler.clone();
+        let environment = EnvironmentMaps::empty(device);
+        let bind_group = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            &uniform_buffer,
+            &light_buffer,
+            &shadow_uniform,
+            &shadow_view,
+            &shadow_sampler,
+            &environment,
+        );
+
+        Self {
+            sun: DirectionalLight::default(),
+            environment_intensity: 1.0,
+            lights: Vec::new(),
+            next_id: 0,
+            storage,
+            uniform_buffer,
+            light_buffer,
+            light_capacity,
+            shadow_uniform,
+            shadow_view,
+            shadow_sampler,
+            environment,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    fn create_light_buffer(device: &wgpu::Device, storage: bool, capacity: usize) -> wgpu::Buffer {
+        let usage = if storage {
+            wgpu::BufferUsages::STORAGE
+        } else {
+            wgpu::BufferUsages::UNIFORM
+        };
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light Buffer"),
+            size: (capacity * std::mem::size_of::<LightRaw>()) as wgpu::BufferAddress,
+            usage: usage | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        light_buffer: &wgpu::Buffer,
+        shadow_uniform: &wgpu::Buffer,
+        shadow_view: &wgpu::TextureView,
+        shadow_sampler: &wgpu::Sampler,
+        environment: &EnvironmentMaps,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: shadow_uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(shadow_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(shadow_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&environment.irradiance),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(&environment.prefiltered),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::Sampler(&environment.sampler),
+                },
+            ],
+            label: Some("lighting_bind_group"),
+        })
+    }
+
+    // Declares the light array in lighting.wgsl the way this device can bind it.
+    // Needed by any shader that includes lighting.wgsl.
+    pub fn shader_defines(&self, preprocessor: &mut Preprocessor) {
+        if self.storage {
+            preprocessor.define("STORAGE_LIGHTS", "");
+        }
+        preprocessor.define("MAX_UNIFORM_LIGHTS", MAX_UNIFORM_LIGHTS);
+    }
+
+    // Lights the scene's ambient from the environment from now on
+    pub fn set_environment(&mut self, device: &wgpu::Device, environment: &Environment) {
+        self.environment = EnvironmentMaps {
+            irradiance: environment.irradiance.view.clone(),
+            prefiltered: environment.prefiltered.view.clone(),
+            sampler: environment.prefiltered.sampler.clone(),
+            prefiltered_mips: environment.prefiltered_mips(),
+        };
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.uniform_buffer,
+            &self.light_buffer,
+            &self.shadow_uniform,
+            &self.shadow_view,
+            &self.shadow_sampler,
+            &self.environment,
+        );
+    }
+
+    pub fn add_light(&mut self, light: Light) -> LightId {
+        let id = LightId(self.next_id);
+        self.next_id += 1;
+        self.lights.push((id, light));
+        id
+    }
+
+    pub fn remove_light(&mut self, id: LightId) -> Option<Light> {
+        let index = self
+            .lights
+            .iter()
+            .position(|(light_id, _)| *light_id == id)?;
+        Some(self.lights.remove(index).1)
+    }
+
+    pub fn light(&self, id: LightId) -> Option<&Light> {
+        self.lights
+            .iter()
+            .find(|(light_id, _)| *light_id == id)
+            .map(|(_, light)| light)
+    }
+
+    pub fn light_mut(&mut self, id: LightId) -> Option<&mut Light> {
+        self.lights
+            .iter_mut()
+            .find(|(light_id, _)| *light_id == id)
+            .map(|(_, light)| light)
+    }
+
+    pub fn lights(&self) -> impl Iterator<Item = &Light> {
+        self.lights.iter().map(|(_, light)| light)
+    }
+
+    pub fn update(&mut self, device: &wgpu::Device, uploads: &mut Uploads) {
+        let mut lights = self
+            .lights
+            .iter()
+            .map(|(_, light)| light.to_raw())
+            .collect::<Vec<_>>();
+
+        if lights.len() > self.light_capacity {
+            if self.storage {
+                // Grow the storage buffer, the bind group has to point at the new one
+                self.light_capacity = lights.len().next_power_of_two();
+                self.light_buffer =
+                    Self::create_light_buffer(device, self.storage, self.light_capacity);
+                self.bind_group = Self::create_bind_group(
+                    device,
+                    &self.bind_group_layout,
+                    &self.uniform_buffer,
+                    &self.light_buffer,
+                    &self.shadow_uniform,
+                    &self.shadow_view,
+                    &self.shadow_sampler,
+                    &self.environment,
+                );
+            } else {
+                tracing::warn!(
+                    "{} lights requested, only the first {} are used",
+                    lights.len(),
+                    self.light_capacity
+                );
+                lights.truncate(self.light_capacity);
+            }
+        }
+
+        let mut uniform = LightUniform::zeroed();
+        let direction = self.sun.direction.normalize();
+        uniform.sun_direction = [direction.x, direction.y, direction.z, self.sun.intensity];
+        uniform.sun_color = [
+            self.sun.color[0],
+            self.sun.color[1],
+            self.sun.color[2],
+            self.sun.ambient,
+        ];
+        uniform.num_lights = lights.len() as u32;
+        if self.environment.prefiltered_mips > 0 {
+            uniform.environment_intensity = self.environment_intensity;
+            uniform.max_reflection_lod = (self.environment.prefiltered_mips - 1) as f32;
+        }
+
+        uploads.write(&self.uniform_buffer, 0, &[uniform]);
+        uploads.write(&self.light_buffer, 0, &lights);
+    }
+}
@@ -0,0 +1,139 @@
+use std::any::Any;
+
+use crate::post_process::{FullscreenEffect, PostEffect};
+
+pub const MAX_SHAFT_SOURCES: usize = 2;
+
+// A light that casts shafts, in world space
+#[derive(Debug, Copy, Clone)]
+pub struct ShaftSource {
+    // w = 1 for a point, w = 0 for a direction towards a light infinitely far away
+    pub position: cgmath::Vector4<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShaftSourceRaw {
+    position: [f32; 4], // xy = screen UV, z = linear depth (0 for the sun), w = intensity
+    color: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightShaftsUniform {
+    sources: [ShaftSourceRaw; MAX_SHAFT_SOURCES],
+    num_sources: u32,
+    samples: u32,
+    density: f32,
+    decay: f32,
+}
+
+// ===== LIGHT SHAFTS =====
+// Radial blur towards the sun and the fire light, so beams show up where the
+// model blocks them. Sources are projected onto the screen on the CPU each frame.
+pub struct LightShafts {
+    pub enabled: bool,
+    pub samples: u32,
+    pub density: f32, // How far towards the light each pixel looks
+    pub decay: f32,   // Per-sample falloff, lower keeps shafts short
+    sources: Vec<ShaftSourceRaw>,
+    linear_depth: Option<wgpu::TextureView>,
+    effect: FullscreenEffect,
+}
+
+impl LightShafts {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        Self {
+            enabled: true,
+            samples: 48,
+            density: 0.9,
+            decay: 0.97,
+            sources: Vec::new(),
+            linear_depth: None,
+            effect: FullscreenEffect::with_inputs(
+                device,
+                "Light Shafts",
+                wgpu::ShaderSource::Wgsl(include_str!("light_shafts.wgsl").into()),
+                format,
+                std::mem::size_of::<LightShaftsUniform>() as u64,
+                1,
+            ),
+        }
+    }
+
+    // Recreated with the HDR target, so hand it over again on resize
+    pub fn set_linear_depth(&mut self, linear_depth: wgpu::TextureView) {
+        self.linear_depth = Some(linear_depth);
+    }
+
+    // Projects the lights for this frame's camera. Lights behind the camera are
+    // dropped, and so is anything past `MAX_SHAFT_SOURCES`.
+    pub fn set_sources(&mut self, view_proj: cgmath::Matrix4<f32>, sources: &[ShaftSource]) {
+        self.sources = sources
+            .iter()
+            .filter_map(|source| {
+                let clip = view_proj * source.position;
+                // w is the view depth, which has to be in front of the camera
+                if clip.w <= 0.0 {
+                    return None;
+                }
+                let uv = [clip.x / clip.w * 0.5 + 0.5, 0.5 - clip.y / clip.w * 0.5];
+                let depth = if source.position.w == 0.0 {
+                    0.0
+                } else {
+                    clip.w
+                };
+                let [r, g, b] = source.color;
+                Some(ShaftSourceRaw {
+                    position: [uv[0], uv[1], depth, source.intensity],
+                    color: [r, g, b, 0.0],
+                })
+            })
+            .take(MAX_SHAFT_SOURCES)
+            .collect();
+    }
+}
+
+impl PostEffect for LightShafts {
+    fn name(&self) -> &'static str {
+        "light_shafts"
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled && self.linear_depth.is_some() && !self.sources.is_empty()
+    }
+
+    fn update(&self, queue: &wgpu::Queue) {
+        let mut sources = [ShaftSourceRaw::default(); MAX_SHAFT_SOURCES];
+        sources[..self.sources.len()].copy_from_slice(&self.sources);
+        self.effect.write_uniform(
+            queue,
+            bytemuck::cast_slice(&[LightShaftsUniform {
+                sources,
+                num_sources: self.sources.len() as u32,
+                samples: self.samples,
+                density: self.density,
+                decay: self.decay,
+            }]),
+        );
+    }
+
+    fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+    ) {
+        if let Some(linear_depth) = &self.linear_depth {
+            self.effect
+                .render_with_inputs(device, encoder, input, &[linear_depth], output);
+        }
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
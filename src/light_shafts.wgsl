@@ -0,0 +1,77 @@
+// ===== LIGHT SHAFTS =====
+// Screen-space god rays: every pixel marches towards each light on screen and
+// gathers how much unblocked light it passes, so geometry in front of a light
+// casts visible beams through the air around it.
+
+const MAX_SOURCES: u32 = 2u;
+
+struct ShaftSource {
+    position: vec4<f32>, // xy = screen UV, z = linear depth (0 for the sun), w = intensity
+    color: vec4<f32>,
+};
+
+struct LightShaftsUniform {
+    sources: array<ShaftSource, MAX_SOURCES>,
+    num_sources: u32,
+    samples: u32,
+    density: f32, // How far towards the light the march reaches, 1.0 is all the way
+    decay: f32,   // Falloff per step, so nearby occluders matter most
+};
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> shafts: LightShaftsUniform;
+@group(0) @binding(3)
+var t_linear_depth: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// Three vertices covering the whole screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+// 1.0 where light from `source` reaches this point unblocked
+fn unoccluded(uv: vec2<f32>, source: ShaftSource) -> f32 {
+    let depth = textureSampleLevel(t_linear_depth, s_source, uv, 0.0).r;
+    if (depth == 0.0) {
+        return 1.0; // Empty sky lets everything through
+    }
+    // Only the sky is behind the sun; a point light shines past anything further away
+    return select(step(source.position.z, depth), 0.0, source.position.z == 0.0);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_source, s_source, in.uv);
+    let samples = max(shafts.samples, 1u);
+
+    var shafts_color = vec3<f32>(0.0);
+    for (var s = 0u; s < min(shafts.num_sources, MAX_SOURCES); s = s + 1u) {
+        let source = shafts.sources[s];
+        let step_uv = (source.position.xy - in.uv) * shafts.density / f32(samples);
+        var uv = in.uv;
+        var weight = 1.0;
+        var gathered = 0.0;
+        for (var i = 0u; i < samples; i = i + 1u) {
+            uv = uv + step_uv;
+            // Light only leaks out around the source, not from the whole sky
+            let glow = exp(-8.0 * distance(uv, source.position.xy));
+            gathered = gathered + unoccluded(uv, source) * glow * weight;
+            weight = weight * shafts.decay;
+        }
+        shafts_color = shafts_color + source.color.rgb * source.position.w * gathered / f32(samples);
+    }
+    return vec4<f32>(color.rgb + shafts_color, color.a);
+}
@@ -0,0 +1,219 @@
+// ===== SHARED LIGHTING =====
+// Included by the forward model shader and the deferred shaders, so both paths
+// light surfaces identically. Bound at group 2 by `light::Lighting`, whose
+// `shader_defines` say how the light array is declared.
+
+const NUM_CASCADES: u32 = 4u;
+// Matches `light::LightKind`
+const LIGHT_POINT: u32 = 0u;
+const LIGHT_DIRECTIONAL: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+
+struct Light {
+    position: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    intensity: f32,
+    direction: vec3<f32>, // Direction the light travels
+    kind: u32,
+    cos_inner: f32, // Spot cone angles, stored as cosines
+    cos_outer: f32,
+};
+
+struct LightUniform {
+    sun_direction: vec4<f32>, // w = intensity
+    sun_color: vec4<f32>, // a = ambient strength
+    num_lights: u32,
+    environment_intensity: f32, // 0 without an environment, for the flat ambient
+    max_reflection_lod: f32,
+};
+@group(2) @binding(0)
+var<uniform> lights: LightUniform;
+
+struct ShadowUniform {
+    light_view_proj: array<mat4x4<f32>, NUM_CASCADES>,
+    cascade_splits: vec4<f32>,
+};
+@group(2) @binding(1)
+var<uniform> shadow: ShadowUniform;
+@group(2) @binding(2)
+var t_shadow: texture_depth_2d_array;
+@group(2) @binding(3)
+var s_shadow: sampler_comparison;
+// A fixed size uniform array on WebGL, which has no storage buffers
+#ifdef STORAGE_LIGHTS
+@group(2) @binding(4)
+var<storage, read> light_buffer: array<Light>;
+#else
+@group(2) @binding(4)
+var<uniform> light_buffer: array<Light, MAX_UNIFORM_LIGHTS>;
+#endif
+// Image-based lighting, see `environment::Environment`
+@group(2) @binding(5)
+var t_irradiance: texture_cube<f32>;
+@group(2) @binding(6)
+var t_prefiltered: texture_cube<f32>;
+@group(2) @binding(7)
+var s_environment: sampler;
+
+// Pick the first cascade whose far split contains this fragment
+fn select_cascade(view_depth: f32) -> u32 {
+    for (var i = 0u; i < NUM_CASCADES; i++) {
+        if (view_depth < shadow.cascade_splits[i]) {
+            return i;
+        }
+    }
+    return NUM_CASCADES;
+}
+
+// 3x3 PCF lookup into the selected cascade, 1.0 = fully lit
+fn sample_shadow(world_position: vec3<f32>, view_depth: f32) -> f32 {
+    let cascade = select_cascade(view_depth);
+    if (cascade >= NUM_CASCADES) {
+        return 1.0; // Beyond the shadow distance
+    }
+
+    let light_space = shadow.light_view_proj[cascade] * vec4<f32>(world_position, 1.0);
+    let ndc = light_space.xyz / light_space.w;
+    // NDC -> texture coordinates (y is flipped)
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5, 0.5);
+    if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0) {
+        return 1.0;
+    }
+
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_shadow));
+    var lit = 0.0;
+    for (var x = -1; x <= 1; x++) {
+        for (var y = -1; y <= 1; y++) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            lit += textureSampleCompareLevel(t_shadow, s_shadow, uv + offset, cascade, ndc.z - 0.001);
+        }
+    }
+    return lit / 9.0;
+}
+
+// Smooth inverse-square falloff that reaches zero at the light's range
+fn point_light_attenuation(distance: f32, range: f32) -> f32 {
+    let ratio = clamp(1.0 - pow(distance / range, 4.0), 0.0, 1.0);
+    return ratio * ratio / (distance * distance + 1.0);
+}
+
+// Full strength inside the inner cone, smoothly fading to zero at the outer cone
+fn spot_cone_attenuation(light: Light, light_to_surface: vec3<f32>) -> f32 {
+    let cos_angle = dot(light.direction, light_to_surface);
+    return smoothstep(light.cos_outer, light.cos_inner, cos_angle);
+}
+
+// ===== PBR (metallic-roughness, Cook-Torrance) =====
+const PI: f32 = 3.14159265359;
+
+struct Surface {
+    albedo: vec3<f32>,
+    alpha: f32,
+    normal: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    occlusion: f32,
+    emissive: vec3<f32>, // Added after lighting, bright enough values bloom
+};
+
+// GGX / Trowbridge-Reitz normal distribution
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let denom = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * denom * denom);
+}
+
+// Smith geometry term with the Schlick-GGX approximation for direct lighting
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = (r * r) / 8.0;
+    let ggx_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let ggx_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return ggx_v * ggx_l;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Outgoing radiance for one light arriving from direction l with the given radiance
+fn cook_torrance(surface: Surface, v: vec3<f32>, l: vec3<f32>, radiance: vec3<f32>) -> vec3<f32> {
+    let n = surface.normal;
+    let h = normalize(v + l);
+    let n_dot_l = max(dot(n, l), 0.0);
+    let n_dot_v = max(dot(n, v), 0.0001);
+
+    let f0 = mix(vec3<f32>(0.04), surface.albedo, surface.metallic);
+    let f = fresnel_schlick(max(dot(h, v), 0.0), f0);
+    let d = distribution_ggx(max(dot(n, h), 0.0), surface.roughness);
+    let g = geometry_smith(n_dot_v, n_dot_l, surface.roughness);
+    let specular = d * g * f / (4.0 * n_dot_v * n_dot_l + 0.0001);
+
+    // Metals have no diffuse term
+    let k_d = (vec3<f32>(1.0) - f) * (1.0 - surface.metallic);
+    return (k_d * surface.albedo / PI + specular) * radiance * n_dot_l;
+}
+
+// Karis's analytic fit of the split-sum BRDF, scale and bias for F0, in
+// place of a lookup texture
+fn environment_brdf(n_dot_v: f32, roughness: f32) -> vec2<f32> {
+    let c0 = vec4<f32>(-1.0, -0.0275, -0.572, 0.022);
+    let c1 = vec4<f32>(1.0, 0.0425, 1.04, -0.04);
+    let r = roughness * c0 + c1;
+    let a004 = min(r.x * r.x, exp2(-9.28 * n_dot_v)) * r.x + r.y;
+    return vec2<f32>(-1.04, 1.04) * a004 + r.zw;
+}
+
+// Diffuse light from the irradiance map plus reflections from the
+// prefiltered one, sharper on smoother surfaces
+fn image_based_lighting(surface: Surface, v: vec3<f32>) -> vec3<f32> {
+    let n_dot_v = max(dot(surface.normal, v), 0.0001);
+    let f0 = mix(vec3<f32>(0.04), surface.albedo, surface.metallic);
+    let brdf = environment_brdf(n_dot_v, surface.roughness);
+    let specular_color = f0 * brdf.x + brdf.y;
+    let diffuse_color = surface.albedo * (1.0 - surface.metallic) * (1.0 - specular_color);
+
+    let irradiance = textureSampleLevel(t_irradiance, s_environment, surface.normal, 0.0).rgb;
+    let r = reflect(-v, surface.normal);
+    let lod = surface.roughness * lights.max_reflection_lod;
+    let reflection = textureSampleLevel(t_prefiltered, s_environment, r, lod).rgb;
+    return diffuse_color * irradiance + specular_color * reflection;
+}
+
+// Light a surface with the sun (with shadows) and every dynamic light
+fn shade(surface_in: Surface, world_position: vec3<f32>, view_position: vec3<f32>, view_depth: f32) -> vec3<f32> {
+    var surface = surface_in;
+    surface.normal = normalize(surface.normal);
+    surface.roughness = clamp(surface.roughness, 0.04, 1.0);
+    let v = normalize(view_position - world_position);
+
+    // Sun, w of the direction carries its intensity
+    let sun_dir = -normalize(lights.sun_direction.xyz);
+    let visibility = sample_shadow(world_position, view_depth);
+    let sun_radiance = lights.sun_color.rgb * lights.sun_direction.w * visibility;
+    var color = cook_torrance(surface, v, sun_dir, sun_radiance);
+
+    for (var i = 0u; i < lights.num_lights; i++) {
+        let light = light_buffer[i];
+        if (light.kind == LIGHT_DIRECTIONAL) {
+            color += cook_torrance(surface, v, -light.direction, light.color * light.intensity);
+            continue;
+        }
+        let to_light = light.position - world_position;
+        let distance = length(to_light);
+        var attenuation = point_light_attenuation(distance, light.range);
+        if (light.kind == LIGHT_SPOT) {
+            attenuation *= spot_cone_attenuation(light, -to_light / distance);
+        }
+        let radiance = light.color * light.intensity * attenuation;
+        color += cook_torrance(surface, v, to_light / distance, radiance);
+    }
+
+    var ambient = lights.sun_color.a * surface.albedo;
+    if (lights.environment_intensity > 0.0) {
+        ambient = image_based_lighting(surface, v) * lights.environment_intensity;
+    }
+    return ambient * surface.occlusion + color + surface.emissive;
+}
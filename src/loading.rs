@@ -0,0 +1,240 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::color;
+
+// ===== BACKGROUND LOADING =====
+// How far a background load has got. The loading side calls `finished` after
+// each asset, the window reads `fraction` to draw the progress bar.
+pub struct LoadProgress {
+    total: u32,
+    done: AtomicU32,
+}
+
+impl LoadProgress {
+    // `total` is how many times `finished` will be called
+    pub fn new(total: u32) -> Self {
+        Self {
+            total,
+            done: AtomicU32::new(0),
+        }
+    }
+
+    pub fn finished(&self, name: &str) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        tracing::info!("Loaded {} ({}/{})", name, done, self.total);
+    }
+
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            return 1.0;
+        }
+        (self.done.load(Ordering::Relaxed) as f32 / self.total as f32).min(1.0)
+    }
+}
+
+// Loads assets without blocking the window. Natively `load` runs on a thread
+// of its own; the web has no threads to spare, so there it runs on the
+// browser's event loop between frames. `take` hands the result over once.
+pub struct AssetLoader<T> {
+    progress: Arc<LoadProgress>,
+    result: Arc<Mutex<Option<anyhow::Result<T>>>>,
+}
+
+impl<T: 'static> AssetLoader<T> {
+    fn with_steps(steps: u32) -> Self {
+        Self {
+            progress: Arc::new(LoadProgress::new(steps)),
+            result: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // `steps` is how many times `load` will call `LoadProgress::finished`
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn spawn<F, Fut>(steps: u32, load: F) -> Self
+    where
+        T: Send,
+        F: FnOnce(Arc<LoadProgress>) -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let loader = Self::with_steps(steps);
+        let (progress, result) = (loader.progress.clone(), loader.result.clone());
+        let spawned = std::thread::Builder::new()
+            .name("asset loader".to_string())
+            .spawn(move || {
+                let loaded = pollster::block_on(load(progress));
+                *result.lock().unwrap() = Some(loaded);
+            });
+        // Comes out of `take` like a failed load
+        if let Err(e) = spawned {
+            *loader.result.lock().unwrap() = Some(Err(
+                anyhow::Error::new(e).context("Couldn't start the asset loader thread")
+            ));
+        }
+        loader
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn spawn<F, Fut>(steps: u32, load: F) -> Self
+    where
+        F: FnOnce(Arc<LoadProgress>) -> Fut + 'static,
+        Fut: Future<Output = anyhow::Result<T>> + 'static,
+    {
+        let loader = Self::with_steps(steps);
+        let (progress, result) = (loader.progress.clone(), loader.result.clone());
+        wasm_bindgen_futures::spawn_local(async move {
+            let loaded = load(progress).await;
+            *result.lock().unwrap() = Some(loaded);
+        });
+        loader
+    }
+
+    pub fn progress(&self) -> f32 {
+        self.progress.fraction()
+    }
+
+    // The loaded assets, or the error that stopped them, once loading is over
+    pub fn take(&self) -> Option<anyhow::Result<T>> {
+        self.result.lock().unwrap().take()
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LoadingUniform {
+    background: [f32; 4],
+    track: [f32; 4],
+    fill: [f32; 4],
+    progress: f32,
+    aspect: f32,
+    _padding: [f32; 2],
+}
+
+// ===== LOADING SCREEN =====
+// What the window shows until the scene is ready: a progress bar straight
+// onto the surface, needing nothing that's still loading
+pub struct LoadingScreen {
+    uniform: LoadingUniform,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl LoadingScreen {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let encode = |[r, g, b]: [f32; 3]| color::output_color(format, [r, g, b, 1.0]);
+        let uniform = LoadingUniform {
+            // Same as the scene's clear color
+            background: encode([0.1, 0.2, 0.3].map(color::srgb_to_linear)),
+            track: encode([0.02, 0.02, 0.02]),
+            fill: encode([1.0, 0.35, 0.05]),
+            progress: 0.0,
+            aspect: 1.0,
+            _padding: [0.0; 2],
+        };
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Loading Uniform Buffer"),
+            size: std::mem::size_of::<LoadingUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("loading_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("loading_bind_group"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Loading Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("loading.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Loading Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Loading Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[], // Fullscreen triangle is generated in the shader
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            uniform,
+            uniform_buffer,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    // `progress` goes from 0 to 1, `aspect` is the output's width over height
+    pub fn render(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        progress: f32,
+        aspect: f32,
+    ) {
+        self.uniform.progress = progress;
+        self.uniform.aspect = aspect;
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&self.uniform));
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Loading Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,43 @@
+// ===== LOADING SCREEN =====
+// A progress bar in the middle of a plain background, drawn with a single
+// full-screen triangle
+
+struct LoadingUniform {
+    background: vec4<f32>,
+    track: vec4<f32>,
+    fill: vec4<f32>,
+    progress: f32,
+    // Width over height, keeps the bar the same shape in any window
+    aspect: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> loading: LoadingUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Half the window wide, a fortieth of that tall
+    let x = (in.uv.x - 0.25) / 0.5;
+    let half_height = 0.0125 * loading.aspect;
+    if (x < 0.0 || x > 1.0 || abs(in.uv.y - 0.5) > half_height) {
+        return loading.background;
+    }
+    if (x <= loading.progress) {
+        return loading.fill;
+    }
+    return loading.track;
+}
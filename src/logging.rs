@@ -0,0 +1,137 @@
+#[cfg(not(target_arch = "wasm32"))]
+use anyhow::Context;
+use tracing_subscriber::prelude::*;
+
+// Read when nothing's been set in code, see `from_env`
+#[cfg(not(target_arch = "wasm32"))]
+pub const FILTER_VAR: &str = "RUST_LOG";
+#[cfg(not(target_arch = "wasm32"))]
+pub const FORMAT_VAR: &str = "LEARN_WGPU_LOG_FORMAT";
+#[cfg(not(target_arch = "wasm32"))]
+pub const SPANS_VAR: &str = "LEARN_WGPU_LOG_SPANS";
+
+// Everything from this crate down to info, only warnings and errors from
+// wgpu and the rest, which are chatty at info
+pub const DEFAULT_FILTER: &str = "warn,learn_wgpu=info";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    // One line per event with the spans it's in
+    #[default]
+    Full,
+    // Shorter lines, span fields without their names
+    Compact,
+    // Multiple lines per event, for reading rather than grepping
+    Pretty,
+}
+
+// ===== LOGGING =====
+// Where events from `tracing` go, and wgpu's `log` records with them. Which
+// ones get through is an env-filter directive string, e.g.
+// "learn_wgpu=debug,wgpu_core=warn", see the tracing-subscriber docs. Spans
+// wrap init, asset loading and each frame, so an event like a missing OBJ
+// says which file and which step it was loading. Natively lines go to
+// stderr, on the web to the browser console.
+#[derive(Debug, Clone)]
+pub struct LogOptions {
+    pub filter: String,
+    pub format: LogFormat,
+    // Also an event when a span closes, with how long it was open. Handy to
+    // see what loading spent its time on, too much every frame at trace.
+    pub span_timings: bool,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        Self {
+            filter: DEFAULT_FILTER.to_string(),
+            format: LogFormat::default(),
+            span_timings: false,
+        }
+    }
+}
+
+impl LogOptions {
+    // From FILTER_VAR, FORMAT_VAR and SPANS_VAR, defaults for whichever
+    // aren't set
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut options = Self::default();
+        if let Ok(filter) = std::env::var(FILTER_VAR) {
+            if !filter.is_empty() {
+                options.filter = filter;
+            }
+        }
+        if let Ok(format) = std::env::var(FORMAT_VAR) {
+            options.format =
+                parse_log_format(&format).with_context(|| format!("In {}", FORMAT_VAR))?;
+        }
+        options.span_timings = std::env::var(SPANS_VAR).is_ok_and(|spans| spans == "1");
+        Ok(options)
+    }
+
+    // Installs the subscriber. Left alone if the application embedding this
+    // crate already has one. A filter that doesn't parse is warned about and
+    // DEFAULT_FILTER used instead.
+    pub fn init(&self) {
+        let (filter, bad_filter) = match tracing_subscriber::EnvFilter::try_new(&self.filter) {
+            Ok(filter) => (filter, None),
+            Err(e) => (tracing_subscriber::EnvFilter::new(DEFAULT_FILTER), Some(e)),
+        };
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let layer = {
+            use tracing_subscriber::fmt::format::FmtSpan;
+
+            let layer = tracing_subscriber::fmt::layer()
+                .with_writer(std::io::stderr)
+                .with_span_events(if self.span_timings {
+                    FmtSpan::CLOSE
+                } else {
+                    FmtSpan::NONE
+                });
+            match self.format {
+                LogFormat::Full => layer.boxed(),
+                LogFormat::Compact => layer.compact().boxed(),
+                LogFormat::Pretty => layer.pretty().boxed(),
+            }
+        };
+        #[cfg(target_arch = "wasm32")]
+        let layer = tracing_wasm::WASMLayer::new(
+            tracing_wasm::WASMLayerConfigBuilder::new()
+                .set_report_logs_in_timings(self.span_timings)
+                .build(),
+        );
+
+        if tracing_subscriber::registry()
+            .with(filter)
+            .with(layer)
+            .try_init()
+            .is_err()
+        {
+            tracing::debug!("There's already a subscriber, logging goes there");
+            return;
+        }
+        if let Some(e) = bad_filter {
+            tracing::warn!(
+                "Couldn't use log filter {:?}, using {:?}: {}",
+                self.filter,
+                DEFAULT_FILTER,
+                e
+            );
+        }
+    }
+}
+
+// "full", "compact" or "pretty"
+pub fn parse_log_format(format: &str) -> anyhow::Result<LogFormat> {
+    match format.to_lowercase().as_str() {
+        "full" => Ok(LogFormat::Full),
+        "compact" => Ok(LogFormat::Compact),
+        "pretty" => Ok(LogFormat::Pretty),
+        _ => anyhow::bail!(
+            "Unknown log format {:?}, use full, compact or pretty",
+            format
+        ),
+    }
+}
@@ -1,5 +1,60 @@
-pub use learn_wgpu::run;
-fn main() {
-    run().unwrap();
-    // println!("Hello, world!");
+use learn_wgpu::gpu_options;
+
+fn main() -> anyhow::Result<()> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    // `--adapter <part of its name>`, `--power low|high|none` and
+    // `--backend vulkan|dx12|metal|gl` pick the GPU, over LEARN_WGPU_ADAPTER,
+    // LEARN_WGPU_POWER and LEARN_WGPU_BACKEND. `--intermediate-format
+    // rgba8|rg11b10|rgba16float` over LEARN_WGPU_INTERMEDIATE_FORMAT.
+    let mut options = gpu_options::GpuOptions::from_env()?;
+    if let Some(name) = take_flag(&mut args, "--adapter")? {
+        options.adapter = Some(name);
+    }
+    if let Some(power) = take_flag(&mut args, "--power")? {
+        options.power_preference = gpu_options::parse_power_preference(&power)?;
+    }
+    if let Some(backend) = take_flag(&mut args, "--backend")? {
+        options.backends = gpu_options::parse_backend(&backend)?;
+    }
+    if let Some(format) = take_flag(&mut args, "--intermediate-format")? {
+        options.intermediate_format = gpu_options::parse_intermediate_format(&format)?;
+    }
+    // `--list-adapters` shows what there is to pick from
+    if args.first().map(String::as_str) == Some("--list-adapters") {
+        for info in gpu_options::list_adapters(&options) {
+            println!("{} ({:?}, {:?})", info.name, info.backend, info.device_type);
+        }
+        return Ok(());
+    }
+    // `--headless [frames] [width]x[height]` renders without a window and exits
+    if args.first().map(String::as_str) == Some("--headless") {
+        let frames = args.get(1).and_then(|n| n.parse().ok()).unwrap_or(1);
+        let (width, height) = args
+            .get(2)
+            .and_then(|size| size.split_once('x'))
+            .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+            .unwrap_or((800, 600));
+        exit_on_error(learn_wgpu::run_headless(options, width, height, frames));
+        return Ok(());
+    }
+    exit_on_error(learn_wgpu::run_with(options));
+    Ok(())
+}
+
+// Logged with its causes rather than panicked over, logging's been set up by then
+fn exit_on_error(result: anyhow::Result<()>) {
+    if let Err(e) = result {
+        tracing::error!("{:#}", e);
+        std::process::exit(1);
+    }
+}
+
+// Takes `flag` and the value after it out of `args`
+fn take_flag(args: &mut Vec<String>, flag: &str) -> anyhow::Result<Option<String>> {
+    let Some(i) = args.iter().position(|arg| arg == flag) else {
+        return Ok(None);
+    };
+    args.remove(i);
+    anyhow::ensure!(i < args.len(), "{} needs a value", flag);
+    Ok(Some(args.remove(i)))
 }
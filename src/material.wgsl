@@ -0,0 +1,64 @@
+// ===== PBR MATERIAL =====
+// Material bindings (group 0) and texture sampling shared by the forward model
+// shader and the deferred geometry pass. Needs lighting.wgsl for `Surface`.
+#include "lighting.wgsl"
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var s_material: sampler;
+@group(0) @binding(2)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(3)
+var t_metallic_roughness: texture_2d<f32>;
+@group(0) @binding(4)
+var t_occlusion: texture_2d<f32>;
+@group(0) @binding(6)
+var t_emissive: texture_2d<f32>;
+
+struct MaterialUniform {
+    base_color: vec4<f32>,
+    metallic: f32,
+    roughness: f32,
+    occlusion_strength: f32,
+    normal_scale: f32,
+    emissive: vec4<f32>, // rgb = color, a = strength
+};
+@group(0) @binding(5)
+var<uniform> material: MaterialUniform;
+
+// Apply a tangent-space normal with the per-vertex tangent frame. The
+// interpolated tangent is re-orthogonalized since it drifts across a triangle.
+fn perturb_normal(n: vec3<f32>, tangent: vec4<f32>, tangent_normal: vec3<f32>) -> vec3<f32> {
+    let t = normalize(tangent.xyz - n * dot(n, tangent.xyz));
+    let b = cross(n, t) * tangent.w;
+    let tbn = mat3x3<f32>(t, b, n);
+    return normalize(tbn * tangent_normal);
+}
+
+fn sample_material(uv: vec2<f32>, world_normal: vec3<f32>, world_tangent: vec4<f32>) -> Surface {
+    let albedo = textureSample(t_diffuse, s_material, uv) * material.base_color;
+    // glTF convention: roughness in G, metallic in B
+    let metallic_roughness = textureSample(t_metallic_roughness, s_material, uv);
+    let occlusion = textureSample(t_occlusion, s_material, uv).r;
+    let emissive = textureSample(t_emissive, s_material, uv).rgb;
+
+    var surface: Surface;
+    surface.albedo = albedo.rgb;
+    surface.alpha = albedo.a;
+    // Without NORMAL_MAP the normal map is skipped for the vertex normal
+#ifdef NORMAL_MAP
+    // Z is rebuilt from X and Y so two channel (BC5) normal maps work too
+    let normal_xy = textureSample(t_normal, s_material, uv).xy * 2.0 - 1.0;
+    let normal_z = sqrt(max(1.0 - dot(normal_xy, normal_xy), 0.0));
+    let tangent_normal = vec3<f32>(normal_xy * material.normal_scale, normal_z);
+    surface.normal = perturb_normal(normalize(world_normal), world_tangent, tangent_normal);
+#else
+    surface.normal = normalize(world_normal);
+#endif
+    surface.metallic = metallic_roughness.b * material.metallic;
+    surface.roughness = metallic_roughness.g * material.roughness;
+    surface.occlusion = mix(1.0, occlusion, material.occlusion_strength);
+    surface.emissive = emissive * material.emissive.rgb * material.emissive.a;
+    return surface;
+}
@@ -0,0 +1,427 @@
+use std::ops::Range;
+
+use wgpu::util::DeviceExt;
+
+use crate::color::ColorSpace;
+use crate::model::{MaterialUniform, Mesh, Model};
+use crate::offscreen::Thumbnail;
+use crate::texture::Texture;
+
+// Has to match the factors array in material_array.wgsl
+pub const MAX_MATERIALS: usize = 64;
+
+// Bytes of push constants a draw needs, just the material index
+pub const PUSH_CONSTANT_SIZE: u32 = std::mem::size_of::<u32>() as u32;
+
+// Which material a draw uses, one per dynamic offset when there are no push constants
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DrawUniform {
+    material: u32,
+    _padding: [u32; 3],
+}
+
+// ===== MATERIAL ARRAY =====
+// Every material of a model packed into one bind group: each map becomes a
+// texture array with a layer per material, and the factors an array indexed the
+// same way. Meshes only change the dynamic offset of the bind group to pick
+// their layer, instead of switching to another material's bind group. Maps are
+// resampled to a shared size when packed. Goes with material_array.wgsl, which
+// has the same `sample_material` as material.wgsl.
+// With push constants the material index is pushed per draw and the bind group
+// stays put, otherwise each draw picks it with the dynamic offset.
+pub struct MaterialArray {
+    bind_group: wgpu::BindGroup,
+    // Between the dynamic offsets, None with push constants
+    draw_stride: Option<u32>,
+}
+
+impl MaterialArray {
+    pub const PUSH_CONSTANT_FEATURES: wgpu::Features = wgpu::Features::PUSH_CONSTANTS;
+
+    pub fn uses_push_constants(device: &wgpu::Device) -> bool {
+        device.features().contains(Self::PUSH_CONSTANT_FEATURES)
+            && device.limits().max_push_constant_size >= PUSH_CONSTANT_SIZE
+    }
+
+    // For the pipeline layout, empty when the draw uniform is used instead
+    pub fn push_constant_ranges(device: &wgpu::Device) -> Vec<wgpu::PushConstantRange> {
+        if Self::uses_push_constants(device) {
+            vec![wgpu::PushConstantRange {
+                stages: wgpu::ShaderStages::FRAGMENT,
+                range: 0..PUSH_CONSTANT_SIZE,
+            }]
+        } else {
+            Vec::new()
+        }
+    }
+
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2Array,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let uniform_entry = |binding, has_dynamic_offset| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        // Same bindings as `Material`, plus the draw's material index
+        let mut entries = vec![
+            texture_entry(0),
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            texture_entry(2),
+            texture_entry(3),
+            texture_entry(4),
+            uniform_entry(5, false),
+            texture_entry(6),
+        ];
+        if !Self::uses_push_constants(device) {
+            entries.push(uniform_entry(7, true));
+        }
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: Some("material_array_bind_group_layout"),
+        })
+    }
+
+    // Packs the model's materials, copying every map into its layer on the GPU.
+    // None when the model has more materials than fit.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        model: &Model,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Option<Self> {
+        let materials = &model.materials;
+        if materials.is_empty() || materials.len() > MAX_MATERIALS {
+            return None;
+        }
+
+        // Every layer gets the size of the largest map
+        let max_size = device.limits().max_texture_dimension_2d;
+        let (width, height) = materials
+            .iter()
+            .flat_map(|material| {
+                [
+                    &material.diffuse_texture,
+                    &material.normal_texture,
+                    &material.metallic_roughness_texture,
+                    &material.occlusion_texture,
+                    &material.emissive_texture,
+                ]
+            })
+            .map(|texture| texture.texture.size())
+            .fold((1, 1), |(width, height), size| {
+                (width.max(size.width), height.max(size.height))
+            });
+        let (width, height) = (width.min(max_size), height.min(max_size));
+        // GL picks 2D, array or cube map from the layer count, so pad it past
+        // one and off multiples of six. Spare layers are never sampled.
+        let mut layers = materials.len().max(2) as u32;
+        if layers.is_multiple_of(6) {
+            layers += 1;
+        }
+        let mip_level_count = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        }
+        .max_mips(wgpu::TextureDimension::D2);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Material Array Encoder"),
+        });
+        let mut blits = Vec::<(wgpu::TextureFormat, Thumbnail)>::new();
+        let mut pack = |label, color_space: ColorSpace, textures: Vec<&wgpu::Texture>| {
+            let format = color_space.texture_format();
+            let array = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: layers,
+                },
+                mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            });
+            let blit = match blits.iter().position(|(f, _)| *f == format) {
+                Some(i) => &blits[i].1,
+                None => {
+                    blits.push((format, Thumbnail::new(device, format)));
+                    &blits.last().unwrap().1
+                }
+            };
+            // Every mip is drawn from the map itself, which has mips of its
+            // own. GL can't sample one layer of an array on its own, so the
+            // levels can't be filtered down from each other.
+            for (layer, texture) in textures.iter().enumerate() {
+                let source = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                for level in 0..mip_level_count {
+                    let target = array.create_view(&wgpu::TextureViewDescriptor {
+                        dimension: Some(wgpu::TextureViewDimension::D2),
+                        base_mip_level: level,
+                        mip_level_count: Some(1),
+                        base_array_layer: layer as u32,
+                        array_layer_count: Some(1),
+                        ..Default::default()
+                    });
+                    blit.render(
+                        device,
+                        &mut encoder,
+                        &source,
+                        &target,
+                        [0, 0, (width >> level).max(1), (height >> level).max(1)],
+                    );
+                }
+            }
+            array.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                ..Default::default()
+            })
+        };
+        let diffuse = pack(
+            "Diffuse Array",
+            ColorSpace::Srgb,
+            materials
+                .iter()
+                .map(|m| &m.diffuse_texture.texture)
+                .collect(),
+        );
+        let normal = pack(
+            "Normal Array",
+            ColorSpace::Linear,
+            materials
+                .iter()
+                .map(|m| &m.normal_texture.texture)
+                .collect(),
+        );
+        let metallic_roughness = pack(
+            "Metallic Roughness Array",
+            ColorSpace::Linear,
+            materials
+                .iter()
+                .map(|m| &m.metallic_roughness_texture.texture)
+                .collect(),
+        );
+        let occlusion = pack(
+            "Occlusion Array",
+            ColorSpace::Linear,
+            materials
+                .iter()
+                .map(|m| &m.occlusion_texture.texture)
+                .collect(),
+        );
+        let emissive = pack(
+            "Emissive Array",
+            ColorSpace::Srgb,
+            materials
+                .iter()
+                .map(|m| &m.emissive_texture.texture)
+                .collect(),
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let mut factors = [MaterialUniform::default(); MAX_MATERIALS];
+        for (factor, material) in factors.iter_mut().zip(materials) {
+            *factor = material.factors;
+        }
+        let factors_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Material Array Factors"),
+            contents: bytemuck::cast_slice(&factors),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        // One material index per dynamic offset, unless it's pushed
+        let draws = (!Self::uses_push_constants(device)).then(|| {
+            let draw_stride = device
+                .limits()
+                .min_uniform_buffer_offset_alignment
+                .max(std::mem::size_of::<DrawUniform>() as u32);
+            let mut draws = vec![0u8; draw_stride as usize * materials.len()];
+            for material in 0..materials.len() {
+                let offset = material * draw_stride as usize;
+                let draw = DrawUniform {
+                    material: material as u32,
+                    _padding: [0; 3],
+                };
+                draws[offset..offset + std::mem::size_of::<DrawUniform>()]
+                    .copy_from_slice(bytemuck::bytes_of(&draw));
+            }
+            let draw_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Material Array Draws"),
+                contents: &draws,
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            (draw_buffer, draw_stride)
+        });
+
+        // Same filtering as the per-material samplers
+        let sampler = Texture::create_material_sampler(device);
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&diffuse),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&normal),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&metallic_roughness),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(&occlusion),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: factors_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::TextureView(&emissive),
+            },
+        ];
+        if let Some((draw_buffer, _)) = &draws {
+            entries.push(wgpu::BindGroupEntry {
+                binding: 7,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: draw_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<DrawUniform>() as u64),
+                }),
+            });
+        }
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &entries,
+            label: Some("material_array_bind_group"),
+        });
+
+        Some(Self {
+            bind_group,
+            draw_stride: draws.map(|(_, draw_stride)| draw_stride),
+        })
+    }
+
+    // Sets group 0, before the first `select`
+    pub fn bind<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        let offsets: &[u32] = if self.draw_stride.is_some() {
+            &[0]
+        } else {
+            &[]
+        };
+        render_pass.set_bind_group(0, &self.bind_group, offsets);
+    }
+
+    // Points the draws that follow at the mesh's material
+    pub fn select<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, mesh: &Mesh) {
+        let material = mesh.material as u32;
+        match self.draw_stride {
+            Some(draw_stride) => {
+                render_pass.set_bind_group(0, &self.bind_group, &[material * draw_stride])
+            }
+            None => render_pass.set_push_constants(
+                wgpu::ShaderStages::FRAGMENT,
+                0,
+                bytemuck::bytes_of(&material),
+            ),
+        }
+    }
+
+    // `DrawModel::draw_model_instanced` with the packed materials
+    pub fn draw_model_instanced<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        model: &'a Model,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        self.draw_model_lods_instanced(render_pass, model, &[instances], camera_bind_group);
+    }
+
+    // `DrawModel::draw_model_lods_instanced` with the packed materials
+    pub fn draw_model_lods_instanced<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        model: &'a Model,
+        lods: &[Range<u32>],
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        self.bind(render_pass);
+        for (level, instances) in lods.iter().enumerate().filter(|(_, i)| !i.is_empty()) {
+            for mesh in model.lod_meshes(level) {
+                render_pass.insert_debug_marker(&mesh.name);
+                self.select(render_pass, mesh);
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass
+                    .set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+                crate::stats::count_draw();
+            }
+        }
+    }
+}
+
+// The model with packed materials when given some and a bind group per
+// material otherwise, matching the pipeline layout it was built for
+pub fn draw_model_instanced<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    model: &'a Model,
+    materials: Option<&'a MaterialArray>,
+    instances: Range<u32>,
+    camera_bind_group: &'a wgpu::BindGroup,
+) {
+    use crate::model::DrawModel;
+    match materials {
+        Some(materials) => {
+            materials.draw_model_instanced(render_pass, model, instances, camera_bind_group)
+        }
+        None => render_pass.draw_model_instanced(model, instances, camera_bind_group),
+    }
+}
+
+// `draw_model_instanced` with each level of detail drawing its own instances
+pub fn draw_model_lods_instanced<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    model: &'a Model,
+    materials: Option<&'a MaterialArray>,
+    lods: &[Range<u32>],
+    camera_bind_group: &'a wgpu::BindGroup,
+) {
+    use crate::model::DrawModel;
+    match materials {
+        Some(materials) => {
+            materials.draw_model_lods_instanced(render_pass, model, lods, camera_bind_group)
+        }
+        None => render_pass.draw_model_lods_instanced(model, lods, camera_bind_group),
+    }
+}
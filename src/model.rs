@@ -1,6 +1,12 @@
 use std::ops::Range;
 
-use crate::texture;
+use wgpu::util::DeviceExt;
+
+use crate::animation::Rig;
+use crate::assets::Assets;
+use crate::error::Result;
+use crate::raycast::{MeshBvh, Ray, RayHit};
+use crate::{resources, texture};
 
 pub trait DrawModel<'a> {
     fn draw_mesh(
@@ -23,6 +29,13 @@ pub trait DrawModel<'a> {
         instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
     );
+    // `lods[level]` are the instances to draw at that level of detail
+    fn draw_model_lods_instanced(
+        &mut self,
+        model: &'a Model,
+        lods: &[Range<u32>],
+        camera_bind_group: &'a wgpu::BindGroup,
+    );
 }
 
 impl<'a, 'b> DrawModel<'b> for wgpu::RenderPass<'a>
@@ -45,11 +58,13 @@ where
         instances: Range<u32>,
         camera_bind_group: &'b wgpu::BindGroup,
     ) {
+        self.insert_debug_marker(&mesh.name);
         self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
         self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
         self.set_bind_group(0, &material.bind_group, &[]);
         self.set_bind_group(1, camera_bind_group, &[]);
         self.draw_indexed(0..mesh.num_elements, 0, instances);
+        crate::stats::count_draw();
     }
     fn draw_model(&mut self, model: &'b Model, camera_bind_group: &'b wgpu::BindGroup) {
         self.draw_model_instanced(model, 0..1, camera_bind_group);
@@ -66,25 +81,563 @@ where
             self.draw_mesh_instanced(mesh, material, instances.clone(), camera_bind_group);
         }
     }
+
+    fn draw_model_lods_instanced(
+        &mut self,
+        model: &'b Model,
+        lods: &[Range<u32>],
+        camera_bind_group: &'b wgpu::BindGroup,
+    ) {
+        for (level, instances) in lods.iter().enumerate().filter(|(_, i)| !i.is_empty()) {
+            for mesh in model.lod_meshes(level) {
+                let material = &model.materials[mesh.material];
+                self.draw_mesh_instanced(mesh, material, instances.clone(), camera_bind_group);
+            }
+        }
+    }
+}
+
+// Draws only vertex/index data with no bind groups, for depth-only passes
+// (shadow maps) where the caller sets up its own pipeline and bindings.
+pub trait DrawGeometry<'a> {
+    fn draw_mesh_geometry_instanced(&mut self, mesh: &'a Mesh, instances: Range<u32>);
+    fn draw_model_geometry_instanced(&mut self, model: &'a Model, instances: Range<u32>);
+    fn draw_model_geometry_lods_instanced(&mut self, model: &'a Model, lods: &[Range<u32>]);
+}
+
+impl<'a, 'b> DrawGeometry<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_mesh_geometry_instanced(&mut self, mesh: &'b Mesh, instances: Range<u32>) {
+        self.insert_debug_marker(&mesh.name);
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.draw_indexed(0..mesh.num_elements, 0, instances);
+        crate::stats::count_draw();
+    }
+
+    fn draw_model_geometry_instanced(&mut self, model: &'b Model, instances: Range<u32>) {
+        for mesh in &model.meshes {
+            self.draw_mesh_geometry_instanced(mesh, instances.clone());
+        }
+    }
+
+    fn draw_model_geometry_lods_instanced(&mut self, model: &'b Model, lods: &[Range<u32>]) {
+        for (level, instances) in lods.iter().enumerate().filter(|(_, i)| !i.is_empty()) {
+            for mesh in model.lod_meshes(level) {
+                self.draw_mesh_geometry_instanced(mesh, instances.clone());
+            }
+        }
+    }
 }
 
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
+    pub sockets: Vec<Socket>,
+    // Coarser stand-ins for `meshes`, most detailed first
+    pub lods: Vec<Lod>,
+}
+
+// ===== LEVELS OF DETAIL =====
+// Simpler meshes drawn in place of a model's own once it covers less of the
+// screen than `screen_size`, as a fraction of the screen's height. They use
+// the model's materials. See `Assets::load_model` for where they come from.
+pub struct Lod {
+    pub meshes: Vec<Mesh>,
+    pub screen_size: f32,
+}
+
+impl Lod {
+    // Level 1 takes over below a quarter of the screen, each level after at
+    // half the size of the one before
+    pub fn default_screen_size(level: usize) -> f32 {
+        0.25 * 0.5f32.powi(level as i32 - 1)
+    }
+}
+
+// ===== SOCKETS =====
+// A named point on a model that other things attach to, like the mouth the
+// fire comes out of. Sockets come from glTF empties or a sidecar file, see
+// `resources::load_sockets`.
+#[derive(Clone, Debug)]
+pub struct Socket {
+    pub name: String,
+    pub transform: cgmath::Matrix4<f32>, // In model space, at rest
+    // The skeleton joint an animated model moves it with
+    pub joint: Option<usize>,
+}
+
+impl Socket {
+    pub fn position(&self) -> cgmath::Point3<f32> {
+        cgmath::Point3::new(self.transform.w.x, self.transform.w.y, self.transform.w.z)
+    }
+}
+
+// Scalar multipliers applied on top of the material's texture maps
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub occlusion_strength: f32,
+    pub normal_scale: f32,
+    pub emissive: [f32; 4], // rgb = color, a = strength, values past 1.0 bloom
+}
+
+impl Default for MaterialUniform {
+    fn default() -> Self {
+        Self {
+            base_color: [1.0; 4],
+            metallic: 0.0,
+            roughness: 1.0,
+            occlusion_strength: 1.0,
+            normal_scale: 1.0,
+            emissive: [0.0; 4], // Nothing glows unless asked to
+        }
+    }
 }
 
+// Metallic-roughness PBR material. Texture channels follow the glTF convention:
+// metallic_roughness_texture stores roughness in G and metallic in B.
 pub struct Material {
     pub name: String,
     pub diffuse_texture: texture::Texture,
+    pub normal_texture: texture::Texture,
+    pub metallic_roughness_texture: texture::Texture,
+    pub occlusion_texture: texture::Texture,
+    pub emissive_texture: texture::Texture,
+    pub factors: MaterialUniform,
+    pub factors_buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
 }
 
+impl Material {
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                // Albedo
+                texture_entry(0),
+                // One sampler is shared by every map
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                // Normal
+                texture_entry(2),
+                // Metallic-roughness
+                texture_entry(3),
+                // Ambient occlusion
+                texture_entry(4),
+                // Factors
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Emissive
+                texture_entry(6),
+            ],
+            label: Some("material_bind_group_layout"),
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        name: &str,
+        diffuse_texture: texture::Texture,
+        normal_texture: texture::Texture,
+        metallic_roughness_texture: texture::Texture,
+        occlusion_texture: texture::Texture,
+        emissive_texture: texture::Texture,
+        factors: MaterialUniform,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let factors_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Material Factors", name)),
+            contents: bytemuck::cast_slice(&[factors]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&metallic_roughness_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&occlusion_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: factors_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(&emissive_texture.view),
+                },
+            ],
+            label: Some(name),
+        });
+
+        Self {
+            name: name.to_string(),
+            diffuse_texture,
+            normal_texture,
+            metallic_roughness_texture,
+            occlusion_texture,
+            emissive_texture,
+            factors,
+            factors_buffer,
+            bind_group,
+        }
+    }
+
+    // Rebuilds the material around another base color texture, keeping the
+    // rest. The factors' uniform buffer is made over too.
+    pub fn set_diffuse_texture(
+        &mut self,
+        device: &wgpu::Device,
+        diffuse_texture: texture::Texture,
+        layout: &wgpu::BindGroupLayout,
+    ) {
+        *self = Self::new(
+            device,
+            &self.name,
+            diffuse_texture,
+            self.normal_texture.clone(),
+            self.metallic_roughness_texture.clone(),
+            self.occlusion_texture.clone(),
+            self.emissive_texture.clone(),
+            self.factors,
+            layout,
+        );
+    }
+}
+
+// Named after the OBJ group or glTF mesh it came from. Groups split by
+// material share a name.
 pub struct Mesh {
     pub name: String,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material: usize,
+    pub bounds: BoundingSphere, // In model space
+    pub aabb: Aabb,             // In model space
+    // The vertices as loaded, kept for meshes `set_transform` can move
+    pub rest_vertices: Vec<ModelVertex>,
+    // Triangles for picking, in the rest pose. Empty for levels of detail.
+    pub bvh: MeshBvh,
+}
+
+// A mesh that can be moved gets a vertex buffer the CPU can rewrite
+pub const MOVABLE_VERTEX_USAGE: wgpu::BufferUsages =
+    wgpu::BufferUsages::VERTEX.union(wgpu::BufferUsages::COPY_DST);
+
+impl Mesh {
+    // ===== MESH TRANSFORMS =====
+    // Moves a mesh on its own relative to the rest of its model, like a jaw
+    // or a wing loaded as its own OBJ group. The vertex buffer is rewritten
+    // with the rest vertices transformed, so every pass and level of detail
+    // sharing it draws the mesh where it was put. Bounds keep to the rest
+    // pose. Returns false for meshes without rest vertices, skinned ones.
+    pub fn set_transform(&self, queue: &wgpu::Queue, transform: cgmath::Matrix4<f32>) -> bool {
+        if self.rest_vertices.is_empty() {
+            return false;
+        }
+        let mut vertices = self.rest_vertices.clone();
+        transform_vertices(&mut vertices, transform);
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+        true
+    }
+}
+
+impl Model {
+    // An OBJ or glTF file in res/, with the skeleton and clips when it's a
+    // skinned glTF. Skinned meshes get vertex buffers the skinning pass can
+    // write to when `skinning` is set. `Assets::load_model` goes through
+    // this and adds the sharing and levels of detail.
+    pub async fn load(
+        file_name: &str,
+        assets: &mut Assets,
+        layout: &wgpu::BindGroupLayout,
+        skinning: bool,
+    ) -> Result<(Model, Option<Rig>)> {
+        if file_name.ends_with(".gltf") || file_name.ends_with(".glb") {
+            resources::load_gltf(file_name, assets, layout, skinning).await
+        } else {
+            resources::load_model(file_name, assets, layout)
+                .await
+                .map(|model| (model, None))
+        }
+    }
+
+    // Sphere around every mesh of the model
+    pub fn bounds(&self) -> BoundingSphere {
+        self.meshes
+            .iter()
+            .map(|mesh| mesh.bounds)
+            .reduce(BoundingSphere::merge)
+            .unwrap_or_default()
+    }
+
+    // Level 0 is the model itself
+    pub fn lod_count(&self) -> usize {
+        1 + self.lods.len()
+    }
+
+    pub fn lod_meshes(&self, level: usize) -> &[Mesh] {
+        match level {
+            0 => &self.meshes,
+            _ => &self.lods[level - 1].meshes,
+        }
+    }
+
+    // The coarsest level meant for a model covering `screen_size` of the screen
+    pub fn lod_level(&self, screen_size: f32) -> usize {
+        self.lods
+            .iter()
+            .take_while(|lod| screen_size < lod.screen_size)
+            .count()
+    }
+
+    // Every mesh of an OBJ group or glTF mesh, which materials can split up
+    pub fn meshes_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Mesh> + 'a {
+        self.meshes.iter().filter(move |mesh| mesh.name == name)
+    }
+
+    // Moves every mesh with that name, see `Mesh::set_transform`. Returns
+    // whether any moved.
+    pub fn set_mesh_transform(
+        &self,
+        queue: &wgpu::Queue,
+        name: &str,
+        transform: cgmath::Matrix4<f32>,
+    ) -> bool {
+        let mut moved = false;
+        for mesh in self.meshes_named(name) {
+            moved |= mesh.set_transform(queue, transform);
+        }
+        moved
+    }
+
+    pub fn socket(&self, name: &str) -> Option<&Socket> {
+        self.sockets.iter().find(|socket| socket.name == name)
+    }
+
+    // Box around every mesh of the model
+    pub fn aabb(&self) -> Aabb {
+        self.meshes
+            .iter()
+            .map(|mesh| mesh.aabb)
+            .reduce(Aabb::merge)
+            .unwrap_or_default()
+    }
+
+    // The closest of its meshes a model-space ray hits
+    pub fn raycast(&self, ray: &Ray) -> Option<RayHit> {
+        self.raycast_mesh(ray).map(|(_, hit)| hit)
+    }
+
+    // The same, along with which of `meshes` it hit
+    pub fn raycast_mesh(&self, ray: &Ray) -> Option<(usize, RayHit)> {
+        self.meshes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, mesh)| Some((i, mesh.bvh.raycast(ray)?)))
+            .min_by(|(_, a), (_, b)| a.distance.total_cmp(&b.distance))
+    }
+}
+
+// ===== BOUNDING BOX =====
+// Axis-aligned, in whatever space the positions were in. Tighter than the
+// sphere on long shapes, which makes it the one to place things against.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: cgmath::Point3<f32>,
+    pub max: cgmath::Point3<f32>,
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Self {
+            min: cgmath::Point3::new(0.0, 0.0, 0.0),
+            max: cgmath::Point3::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+impl Aabb {
+    pub fn from_positions(positions: impl Iterator<Item = [f32; 3]>) -> Self {
+        let (min, max) = positions.fold(([f32::MAX; 3], [f32::MIN; 3]), |(min, max), p| {
+            (
+                [min[0].min(p[0]), min[1].min(p[1]), min[2].min(p[2])],
+                [max[0].max(p[0]), max[1].max(p[1]), max[2].max(p[2])],
+            )
+        });
+        if min[0] > max[0] {
+            return Self::default(); // No positions
+        }
+        Self {
+            min: min.into(),
+            max: max.into(),
+        }
+    }
+
+    // Smallest box around both
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            min: cgmath::Point3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: cgmath::Point3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+
+    pub fn center(&self) -> cgmath::Point3<f32> {
+        use cgmath::EuclideanSpace;
+
+        self.min.midpoint(self.max)
+    }
+
+    pub fn size(&self) -> cgmath::Vector3<f32> {
+        self.max - self.min
+    }
+
+    // Box around this one turned and moved, like an `Instance`
+    pub fn transformed(
+        &self,
+        position: cgmath::Vector3<f32>,
+        rotation: cgmath::Quaternion<f32>,
+    ) -> Self {
+        use cgmath::{EuclideanSpace, Rotation};
+
+        let corners = (0..8).map(|i| {
+            let corner = self.point_at([(i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2) as f32]);
+            (rotation.rotate_vector(corner.to_vec()) + position).into()
+        });
+        Self::from_positions(corners)
+    }
+
+    // The point a fraction of the way across the box along each axis, 0 at
+    // `min` and 1 at `max`. Fractions past 0 or 1 land outside it.
+    pub fn point_at(&self, fraction: [f32; 3]) -> cgmath::Point3<f32> {
+        let size = self.size();
+        cgmath::Point3::new(
+            self.min.x + size.x * fraction[0],
+            self.min.y + size.y * fraction[1],
+            self.min.z + size.z * fraction[2],
+        )
+    }
+}
+
+// ===== BOUNDING SPHERE =====
+// Rough bounds for culling, centered on the bounding box rather than the
+// tightest possible fit
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BoundingSphere {
+    pub center: cgmath::Point3<f32>,
+    pub radius: f32,
+}
+
+impl Default for BoundingSphere {
+    fn default() -> Self {
+        Self {
+            center: cgmath::Point3::new(0.0, 0.0, 0.0),
+            radius: 0.0,
+        }
+    }
+}
+
+impl BoundingSphere {
+    pub fn from_positions(positions: impl Iterator<Item = [f32; 3]> + Clone) -> Self {
+        use cgmath::MetricSpace;
+
+        let mut positions = positions.peekable();
+        if positions.peek().is_none() {
+            return Self::default(); // No positions
+        }
+        let center = Aabb::from_positions(positions.clone()).center();
+        let radius = positions
+            .map(|p| center.distance(cgmath::Point3::from(p)))
+            .fold(0.0, f32::max);
+        Self { center, radius }
+    }
+
+    // Smallest sphere around both
+    pub fn merge(self, other: Self) -> Self {
+        use cgmath::{InnerSpace, MetricSpace};
+
+        let distance = self.center.distance(other.center);
+        if distance + other.radius <= self.radius {
+            return self;
+        }
+        if distance + self.radius <= other.radius {
+            return other;
+        }
+        let radius = (distance + self.radius + other.radius) * 0.5;
+        let direction = (other.center - self.center).normalize();
+        Self {
+            center: self.center + direction * (radius - self.radius),
+            radius,
+        }
+    }
+
+    // Moved by a rotation and translation, like an `Instance`
+    pub fn transformed(
+        self,
+        position: cgmath::Vector3<f32>,
+        rotation: cgmath::Quaternion<f32>,
+    ) -> Self {
+        use cgmath::{EuclideanSpace, Rotation};
+
+        Self {
+            center: cgmath::Point3::from_vec(
+                rotation.rotate_vector(self.center.to_vec()) + position,
+            ),
+            radius: self.radius,
+        }
+    }
 }
 
 pub trait Vertex {
@@ -97,6 +650,7 @@ pub struct ModelVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
+    pub tangent: [f32; 4], // xyz = tangent, w = bitangent sign
 }
 
 impl Vertex for ModelVertex {
@@ -121,7 +675,197 @@ impl Vertex for ModelVertex {
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
 }
+
+// Puts vertices through a transform, normals and tangents included. Mirroring
+// transforms flip the tangents' handedness to match.
+pub fn transform_vertices(vertices: &mut [ModelVertex], transform: cgmath::Matrix4<f32>) {
+    use cgmath::{InnerSpace, Matrix, SquareMatrix};
+
+    let linear = cgmath::Matrix3::from_cols(
+        transform.x.truncate(),
+        transform.y.truncate(),
+        transform.z.truncate(),
+    );
+    let normal_matrix = linear
+        .invert()
+        .map_or(linear, |inverse| inverse.transpose());
+    let handedness = linear.determinant().signum();
+    for vertex in vertices {
+        let position = transform * cgmath::Vector3::from(vertex.position).extend(1.0);
+        vertex.position = position.truncate().into();
+        let normal = normal_matrix * cgmath::Vector3::from(vertex.normal);
+        if normal.magnitude2() > 0.0 {
+            vertex.normal = normal.normalize().into();
+        }
+        let [x, y, z, w] = vertex.tangent;
+        let tangent = linear * cgmath::Vector3::new(x, y, z);
+        if tangent.magnitude2() > 0.0 {
+            let tangent = tangent.normalize();
+            vertex.tangent = [tangent.x, tangent.y, tangent.z, w * handedness];
+        }
+    }
+}
+
+// ===== NORMAL GENERATION =====
+// Normals made from the triangles, for files without any or with facetted
+// ones. Each corner gets the face normals around its position, weighted by
+// the angle they make there, leaving out faces turned further than
+// `smoothing_angle` from its own so sharp edges stay sharp. Vertices whose
+// corners end up with different normals are split, the result has one entry
+// per new vertex saying which old vertex it came from.
+pub fn recompute_normals(
+    vertices: &mut Vec<ModelVertex>,
+    indices: &mut [u32],
+    smoothing_angle: cgmath::Deg<f32>,
+) -> Vec<u32> {
+    use cgmath::{Angle, InnerSpace, Vector3, Zero};
+    use std::collections::HashMap;
+
+    // Vertices split for UVs still smooth together
+    let mut welded = HashMap::new();
+    let point_of = vertices
+        .iter()
+        .map(|vertex| {
+            let next = welded.len();
+            *welded
+                .entry(vertex.position.map(f32::to_bits))
+                .or_insert(next)
+        })
+        .collect::<Vec<_>>();
+    let mut around = vec![Vec::new(); welded.len()];
+    let faces = indices
+        .chunks_exact(3)
+        .enumerate()
+        .map(|(t, triangle)| {
+            let p = [0, 1, 2].map(|i| Vector3::from(vertices[triangle[i] as usize].position));
+            for i in 0..3 {
+                let (a, b) = (p[(i + 1) % 3] - p[i], p[(i + 2) % 3] - p[i]);
+                let angle = if a.magnitude2() > 0.0 && b.magnitude2() > 0.0 {
+                    a.angle(b).0
+                } else {
+                    0.0
+                };
+                around[point_of[triangle[i] as usize]].push((t, angle));
+            }
+            let normal = (p[1] - p[0]).cross(p[2] - p[0]);
+            if normal.magnitude2() > 0.0 {
+                normal.normalize()
+            } else {
+                Vector3::zero()
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let threshold = smoothing_angle.cos();
+    let mut split = HashMap::new();
+    let mut smoothed = Vec::with_capacity(vertices.len());
+    let mut origins = Vec::with_capacity(vertices.len());
+    for (t, triangle) in indices.chunks_exact_mut(3).enumerate() {
+        for index in triangle {
+            let vertex = *index as usize;
+            let normal = around[point_of[vertex]]
+                .iter()
+                .filter(|(other, _)| faces[*other].dot(faces[t]) >= threshold)
+                .fold(Vector3::zero(), |sum, (other, angle)| {
+                    sum + faces[*other] * *angle
+                });
+            let normal: [f32; 3] = if normal.magnitude2() > 0.0 {
+                normal.normalize().into()
+            } else {
+                faces[t].into()
+            };
+            *index = *split
+                .entry((vertex, normal.map(f32::to_bits)))
+                .or_insert_with(|| {
+                    smoothed.push(ModelVertex {
+                        normal,
+                        ..vertices[vertex]
+                    });
+                    origins.push(vertex as u32);
+                    smoothed.len() as u32 - 1
+                });
+        }
+    }
+    *vertices = smoothed;
+    origins
+}
+
+// ===== TANGENT GENERATION =====
+// Per-vertex tangents for normal mapping, in the spirit of mikktspace: every
+// triangle's UV-aligned tangent and bitangent are accumulated (weighted by the
+// triangle's size) onto its vertices, then orthogonalized against the normal.
+// The bitangent isn't stored, only which side of the normal it's on.
+pub fn compute_tangents(vertices: &mut [ModelVertex], indices: &[u32]) {
+    use cgmath::{InnerSpace, Vector2, Vector3, Zero};
+
+    let mut tangents = vec![Vector3::zero(); vertices.len()];
+    let mut bitangents = vec![Vector3::zero(); vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [i0, i1, i2] = [
+            triangle[0] as usize,
+            triangle[1] as usize,
+            triangle[2] as usize,
+        ];
+        let p0 = Vector3::from(vertices[i0].position);
+        let p1 = Vector3::from(vertices[i1].position);
+        let p2 = Vector3::from(vertices[i2].position);
+        let uv0 = Vector2::from(vertices[i0].tex_coords);
+        let uv1 = Vector2::from(vertices[i1].tex_coords);
+        let uv2 = Vector2::from(vertices[i2].tex_coords);
+
+        let edge1 = p1 - p0;
+        let edge2 = p2 - p0;
+        let delta_uv1 = uv1 - uv0;
+        let delta_uv2 = uv2 - uv0;
+
+        // Solve edge = delta_uv.x * T + delta_uv.y * B for both edges. Not
+        // dividing by the determinant's magnitude keeps bigger triangles weighted more.
+        let det = delta_uv1.x * delta_uv2.y - delta_uv2.x * delta_uv1.y;
+        if det.abs() < f32::EPSILON {
+            continue; // Degenerate UVs, let the other triangles decide
+        }
+        let sign = det.signum();
+        let tangent = (edge1 * delta_uv2.y - edge2 * delta_uv1.y) * sign;
+        let bitangent = (edge2 * delta_uv1.x - edge1 * delta_uv2.x) * sign;
+
+        for i in [i0, i1, i2] {
+            tangents[i] += tangent;
+            bitangents[i] += bitangent;
+        }
+    }
+
+    for (i, vertex) in vertices.iter_mut().enumerate() {
+        let normal = Vector3::from(vertex.normal);
+        // Gram-Schmidt, so the tangent is perpendicular to the normal
+        let mut tangent = tangents[i] - normal * normal.dot(tangents[i]);
+        if tangent.magnitude2() < 1e-12 {
+            // No usable UVs around this vertex, any perpendicular will do
+            let axis = if normal.x.abs() < 0.9 {
+                Vector3::unit_x()
+            } else {
+                Vector3::unit_y()
+            };
+            tangent = axis - normal * normal.dot(axis);
+            if tangent.magnitude2() < 1e-12 {
+                tangent = Vector3::unit_x(); // Zero normal
+            }
+        }
+        let tangent = tangent.normalize();
+        let handedness = if normal.cross(tangent).dot(bitangents[i]) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        vertex.tangent = [tangent.x, tangent.y, tangent.z, handedness];
+    }
+}
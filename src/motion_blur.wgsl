@@ -0,0 +1,46 @@
+// ===== MOTION BLUR =====
+// Smears each pixel along the screen-space velocity the scene pass wrote.
+
+struct MotionBlurUniform {
+    strength: f32,
+    samples: u32,
+};
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> motion_blur: MotionBlurUniform;
+@group(0) @binding(3)
+var t_velocity: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// Three vertices covering the whole screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let velocity = textureSample(t_velocity, s_source, in.uv).xy * motion_blur.strength;
+    let center = textureSample(t_source, s_source, in.uv);
+
+    // Sample centred on the pixel, half behind and half ahead of where it moved
+    var color = center.rgb;
+    let samples = max(motion_blur.samples, 2u);
+    for (var i = 1u; i < samples; i = i + 1u) {
+        let offset = (f32(i) / f32(samples - 1u) - 0.5) * velocity;
+        color = color + textureSampleLevel(t_source, s_source, in.uv + offset, 0.0).rgb;
+    }
+    return vec4<f32>(color / f32(samples), center.a);
+}
@@ -0,0 +1,51 @@
+// ===== SHARED NOISE =====
+// Value noise and fBm for procedural effects, included by the fire shader.
+
+// Simple 3D noise function (pseudo-random)
+fn hash(p: vec3<f32>) -> f32 {
+    var p3 = fract(p * 0.1031);
+    p3 += dot(p3, p3.yzx + 33.33);
+    return fract((p3.x + p3.y) * p3.z);
+}
+
+// 3D Perlin-style noise
+fn noise3d(p: vec3<f32>) -> f32 {
+    let i = floor(p);
+    let f = fract(p);
+
+    // Smooth interpolation
+    let u = f * f * (3.0 - 2.0 * f);
+
+    // Sample 8 corners of cube
+    return mix(
+        mix(
+            mix(hash(i + vec3<f32>(0.0, 0.0, 0.0)), hash(i + vec3<f32>(1.0, 0.0, 0.0)), u.x),
+            mix(hash(i + vec3<f32>(0.0, 1.0, 0.0)), hash(i + vec3<f32>(1.0, 1.0, 0.0)), u.x),
+            u.y
+        ),
+        mix(
+            mix(hash(i + vec3<f32>(0.0, 0.0, 1.0)), hash(i + vec3<f32>(1.0, 0.0, 1.0)), u.x),
+            mix(hash(i + vec3<f32>(0.0, 1.0, 1.0)), hash(i + vec3<f32>(1.0, 1.0, 1.0)), u.x),
+            u.y
+        ),
+        u.z
+    );
+}
+
+// Fractal Brownian Motion - layers of noise at different scales
+fn fbm(p: vec3<f32>) -> f32 {
+    var value = 0.0;
+    var amplitude = 0.5;
+    var frequency = 1.0;
+    var p_var = p;
+
+    // 3 octaves (layers) of noise
+    for (var i = 0; i < 3; i++) {
+        value += amplitude * noise3d(p_var * frequency);
+        frequency *= 2.0;  // Each layer is twice as detailed
+        amplitude *= 0.5;  // But half as strong
+        p_var = p_var * 2.0 + 0.5;
+    }
+
+    return value;
+}
@@ -0,0 +1,334 @@
+// ===== WEIGHTED-BLENDED OIT =====
+// Transparent surfaces add their weighted color into `accum` and multiply their
+// coverage into `revealage`, both of which are order independent. `composite`
+// then divides the two back out over the opaque scene, so overlapping smoke or
+// glass never has to be sorted.
+pub struct Oit {
+    accum: wgpu::TextureView,
+    revealage: wgpu::TextureView,
+    // Multisampled targets that resolve into the two above when MSAA is on
+    msaa_views: Option<(wgpu::TextureView, wgpu::TextureView)>,
+    sample_count: u32,
+    width: u32,
+    height: u32,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Oit {
+    pub const ACCUM_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    pub const REVEALAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R16Float;
+
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let (width, height) = (config.width.max(1), config.height.max(1));
+        let accum = Self::create_target(device, "Oit Accum", Self::ACCUM_FORMAT, width, height, 1);
+        let revealage = Self::create_target(
+            device,
+            "Oit Revealage",
+            Self::REVEALAGE_FORMAT,
+            width,
+            height,
+            1,
+        );
+        let msaa_views = Self::create_msaa_views(device, width, height, sample_count);
+
+        // Both targets are read with textureLoad, so no sampler is needed
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[texture_entry(0), texture_entry(1)],
+            label: Some("oit_bind_group_layout"),
+        });
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &accum, &revealage);
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Oit Composite Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("oit.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Oit Composite Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Oit Composite Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[], // Fullscreen triangle is generated in the shader
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    // Regular "over" blend of the averaged transparent color
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            accum,
+            revealage,
+            msaa_views,
+            sample_count,
+            width,
+            height,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    // Color targets for pipelines drawing transparent geometry into the OIT pass.
+    // Fragment shaders write the weighted color to location 0 and alpha to location 1.
+    pub fn color_targets() -> [Option<wgpu::ColorTargetState>; 2] {
+        [
+            Some(wgpu::ColorTargetState {
+                format: Self::ACCUM_FORMAT,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            }),
+            Some(wgpu::ColorTargetState {
+                format: Self::REVEALAGE_FORMAT,
+                // revealage *= (1 - alpha)
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::Zero,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrc,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent::REPLACE,
+                }),
+                write_mask: wgpu::ColorWrites::RED,
+            }),
+        ]
+    }
+
+    fn create_target(
+        device: &wgpu::Device,
+        label: &str,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> wgpu::TextureView {
+        // Multisampled targets are only ever resolved, never bound
+        let usage = if sample_count > 1 {
+            wgpu::TextureUsages::RENDER_ATTACHMENT
+        } else {
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+            view_formats: &[],
+        });
+        texture.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    fn create_msaa_views(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> Option<(wgpu::TextureView, wgpu::TextureView)> {
+        if sample_count <= 1 {
+            return None;
+        }
+        Some((
+            Self::create_target(
+                device,
+                "Oit Msaa Accum",
+                Self::ACCUM_FORMAT,
+                width,
+                height,
+                sample_count,
+            ),
+            Self::create_target(
+                device,
+                "Oit Msaa Revealage",
+                Self::REVEALAGE_FORMAT,
+                width,
+                height,
+                sample_count,
+            ),
+        ))
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        accum: &wgpu::TextureView,
+        revealage: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(accum),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(revealage),
+                },
+            ],
+            label: Some("oit_bind_group"),
+        })
+    }
+
+    // The targets have to match the surface size
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.width = width.max(1);
+        self.height = height.max(1);
+        self.accum = Self::create_target(
+            device,
+            "Oit Accum",
+            Self::ACCUM_FORMAT,
+            self.width,
+            self.height,
+            1,
+        );
+        self.revealage = Self::create_target(
+            device,
+            "Oit Revealage",
+            Self::REVEALAGE_FORMAT,
+            self.width,
+            self.height,
+            1,
+        );
+        self.msaa_views =
+            Self::create_msaa_views(device, self.width, self.height, self.sample_count);
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.accum,
+            &self.revealage,
+        );
+    }
+
+    // Has to match the depth buffer the transparent pass tests against
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.sample_count = sample_count;
+        self.msaa_views = Self::create_msaa_views(device, self.width, self.height, sample_count);
+    }
+
+    // Starts the transparent pass: clears both targets and tests against the
+    // opaque depth without writing to it
+    pub fn begin<'a>(
+        &'a self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        depth_view: &'a wgpu::TextureView,
+    ) -> wgpu::RenderPass<'a> {
+        let ((accum_view, accum_resolve), (revealage_view, revealage_resolve)) =
+            match &self.msaa_views {
+                Some((accum, revealage)) => (
+                    (accum, Some(&self.accum)),
+                    (revealage, Some(&self.revealage)),
+                ),
+                None => ((&self.accum, None), (&self.revealage, None)),
+            };
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Oit Pass"),
+            color_attachments: &[
+                Some(wgpu::RenderPassColorAttachment {
+                    view: accum_view,
+                    resolve_target: accum_resolve,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                }),
+                Some(wgpu::RenderPassColorAttachment {
+                    view: revealage_view,
+                    resolve_target: revealage_resolve,
+                    ops: wgpu::Operations {
+                        // Nothing covers the pixel yet, it's fully revealed
+                        load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                }),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        })
+    }
+
+    // Blend the resolved transparent layer over the opaque scene
+    pub fn composite(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Oit Composite Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+        crate::stats::count_draw();
+    }
+}
@@ -0,0 +1,33 @@
+// ===== OIT COMPOSITE =====
+// Averages the weighted transparent color and blends it over the opaque scene
+// by the coverage left in the revealage target.
+
+@group(0) @binding(0)
+var t_accum: texture_2d<f32>;
+@group(0) @binding(1)
+var t_revealage: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+// Three vertices covering the whole screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coords = vec2<i32>(in.clip_position.xy);
+    let revealage = textureLoad(t_revealage, coords, 0).r;
+    if (revealage >= 1.0) {
+        discard; // No transparent surface touched this pixel
+    }
+    let accum = textureLoad(t_accum, coords, 0);
+    let average = accum.rgb / max(accum.a, 0.00001);
+    return vec4<f32>(average, 1.0 - revealage);
+}
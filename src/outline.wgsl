@@ -0,0 +1,68 @@
+// ===== SELECTION OUTLINE =====
+// The selected model is drawn twice: once to mark its pixels in the stencil
+// buffer, then pushed outwards along its normals and colored wherever the
+// stencil isn't marked, which leaves a ring around the silhouette.
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct OutlineUniform {
+    color: vec4<f32>,
+    viewport: vec2<f32>,
+    width: f32, // In pixels
+};
+@group(1) @binding(0)
+var<uniform> outline: OutlineUniform;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(2) normal: vec3<f32>,
+};
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+fn model_matrix(instance: InstanceInput) -> mat4x4<f32> {
+    return mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+}
+
+@vertex
+fn vs_mask(model: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    return camera.view_proj * model_matrix(instance) * vec4<f32>(model.position, 1.0);
+}
+
+@vertex
+fn vs_outline(model: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    let matrix = model_matrix(instance);
+    var clip = camera.view_proj * matrix * vec4<f32>(model.position, 1.0);
+    // Push out in screen space so the outline is as wide up close as far away
+    let normal_clip = (camera.view_proj * matrix * vec4<f32>(model.normal, 0.0)).xy;
+    if (dot(normal_clip, normal_clip) > 0.0) {
+        let offset = normalize(normal_clip) * outline.width * 2.0 / outline.viewport;
+        clip = vec4<f32>(clip.xy + offset * clip.w, clip.zw);
+    }
+    return clip;
+}
+
+// Only the stencil is written, the color target is masked off
+@fragment
+fn fs_mask() -> @location(0) vec4<f32> {
+    return vec4<f32>(0.0);
+}
+
+@fragment
+fn fs_outline() -> @location(0) vec4<f32> {
+    return outline.color;
+}
@@ -0,0 +1,135 @@
+use std::sync::Arc;
+
+use winit::window::Window;
+
+// ===== FRAME OUTPUT =====
+// Where finished frames go: a window's surface to be presented, or without a
+// window a texture of the surface's format that stays on the GPU, for
+// rendering in CI or making thumbnails on a server
+pub enum FrameOutput {
+    Window {
+        window: Arc<Window>,
+        // None while the app's suspended, see `suspend`
+        surface: Option<wgpu::Surface<'static>>,
+    },
+    Headless {
+        texture: wgpu::Texture,
+    },
+}
+
+impl FrameOutput {
+    // Made at the configuration's size, `configure` remakes it
+    pub fn headless(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        Self::Headless {
+            texture: Self::create_texture(device, config),
+        }
+    }
+
+    fn create_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Headless Output"),
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: config.usage,
+            view_formats: &config.view_formats,
+        })
+    }
+
+    pub fn window(&self) -> Option<&Arc<Window>> {
+        match self {
+            Self::Window { window, .. } => Some(window),
+            Self::Headless { .. } => None,
+        }
+    }
+
+    pub fn surface(&self) -> Option<&wgpu::Surface<'static>> {
+        match self {
+            Self::Window { surface, .. } => surface.as_ref(),
+            Self::Headless { .. } => None,
+        }
+    }
+
+    // Whether there was anything to configure, a suspended window has nothing
+    // to draw to until it's resumed
+    pub fn configure(
+        &mut self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> bool {
+        match self {
+            Self::Window {
+                surface: Some(surface),
+                ..
+            } => surface.configure(device, config),
+            Self::Window { surface: None, .. } => return false,
+            Self::Headless { texture } => *texture = Self::create_texture(device, config),
+        }
+        true
+    }
+
+    // Android takes the window's surface away when the app goes into the
+    // background, so it's let go of here and made again in `resume`
+    pub fn suspend(&mut self) {
+        if let Self::Window { surface, .. } = self {
+            *surface = None;
+        }
+    }
+
+    // Needs configuring again after
+    pub fn resume(&mut self, instance: &wgpu::Instance) -> anyhow::Result<()> {
+        if let Self::Window { window, surface } = self {
+            if surface.is_none() {
+                *surface = Some(instance.create_surface(window.clone())?);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn request_redraw(&self) {
+        if let Some(window) = self.window() {
+            window.request_redraw();
+        }
+    }
+
+    // The texture to draw the next frame into
+    pub fn next_frame(&self) -> Result<Frame, wgpu::SurfaceError> {
+        let (surface_texture, texture) = match self {
+            Self::Window { surface, .. } => {
+                let surface = surface.as_ref().ok_or(wgpu::SurfaceError::Lost)?;
+                let surface_texture = surface.get_current_texture()?;
+                let texture = surface_texture.texture.clone();
+                (Some(surface_texture), texture)
+            }
+            Self::Headless { texture } => (None, texture.clone()),
+        };
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Ok(Frame {
+            surface_texture,
+            texture,
+            view,
+        })
+    }
+}
+
+// A frame being drawn, shown once it's presented if it's for a window
+pub struct Frame {
+    surface_texture: Option<wgpu::SurfaceTexture>,
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+}
+
+impl Frame {
+    // After the commands drawing it are submitted
+    pub fn present(self) {
+        if let Some(surface_texture) = self.surface_texture {
+            surface_texture.present();
+        }
+    }
+}
@@ -0,0 +1,648 @@
+use std::any::Any;
+
+// ===== POST EFFECT =====
+// One screen-space effect in the chain. Effects read `input` and write every
+// pixel of `output`; all of them except the last one write HDR.
+pub trait PostEffect {
+    fn name(&self) -> &'static str;
+
+    // Disabled effects are skipped without breaking the chain
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    fn resize(&mut self, _device: &wgpu::Device, _width: u32, _height: u32) {}
+
+    // Upload settings that changed since the last frame
+    fn update(&self, _queue: &wgpu::Queue) {}
+
+    fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+    );
+
+    // Lets `PostProcessChain::effect_mut` hand back the concrete effect
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+// ===== POST-PROCESS CHAIN =====
+// Runs its effects in order, bouncing between two HDR targets so each effect
+// reads the previous one's result. Only the last enabled effect writes the
+// final output, so that one decides the output format (usually the tonemapper).
+pub struct PostProcessChain {
+    format: wgpu::TextureFormat,
+    targets: [wgpu::TextureView; 2],
+    effects: Vec<Box<dyn PostEffect>>,
+}
+
+impl PostProcessChain {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        Self {
+            format,
+            targets: Self::create_targets(device, config.width, config.height, format),
+            effects: Vec::new(),
+        }
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> [wgpu::TextureView; 2] {
+        let create_target = || {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some("Post Process Target"),
+                    size: wgpu::Extent3d {
+                        width: width.max(1),
+                        height: height.max(1),
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        [create_target(), create_target()]
+    }
+
+    pub fn push(&mut self, effect: impl PostEffect + 'static) {
+        self.effects.push(Box::new(effect));
+    }
+
+    // First effect of the given type, for changing its settings at runtime
+    pub fn effect_mut<T: PostEffect + 'static>(&mut self) -> Option<&mut T> {
+        self.effects
+            .iter_mut()
+            .find_map(|effect| effect.as_any_mut().downcast_mut::<T>())
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.targets = Self::create_targets(device, width, height, self.format);
+        for effect in &mut self.effects {
+            effect.resize(device, width, height);
+        }
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue) {
+        for effect in &self.effects {
+            effect.update(queue);
+        }
+    }
+
+    pub fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+    ) {
+        let effects = self
+            .effects
+            .iter()
+            .filter(|effect| effect.enabled())
+            .collect::<Vec<_>>();
+        let mut source = input;
+        for (i, effect) in effects.iter().enumerate() {
+            let target = if i + 1 == effects.len() {
+                output
+            } else {
+                &self.targets[i % 2]
+            };
+            encoder.push_debug_group(effect.name());
+            effect.render(device, encoder, source, target);
+            encoder.pop_debug_group();
+            source = target;
+        }
+    }
+}
+
+// ===== FULLSCREEN EFFECT =====
+// Plumbing shared by single-pass effects: one fullscreen triangle that samples
+// the input (binding 0 and 1) with the effect's settings at binding 2. Effects
+// that need more of the frame (velocity, depth, ...) get extra textures from
+// binding 3 on, sampled with the same sampler.
+pub struct FullscreenEffect {
+    label: &'static str,
+    extra_inputs: u32,
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+}
+
+impl FullscreenEffect {
+    pub fn new(
+        device: &wgpu::Device,
+        label: &'static str,
+        source: wgpu::ShaderSource,
+        output_format: wgpu::TextureFormat,
+        uniform_size: u64,
+    ) -> Self {
+        Self::with_inputs(device, label, source, output_format, uniform_size, 0)
+    }
+
+    pub fn with_inputs(
+        device: &wgpu::Device,
+        label: &'static str,
+        source: wgpu::ShaderSource,
+        output_format: wgpu::TextureFormat,
+        uniform_size: u64,
+        extra_inputs: u32,
+    ) -> Self {
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: uniform_size,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let mut entries = vec![
+            texture_entry(0),
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ];
+        entries.extend((0..extra_inputs).map(|i| texture_entry(3 + i)));
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: Some(label),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source,
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(label),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[], // Fullscreen triangle is generated in the shader
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: output_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            label,
+            extra_inputs,
+            pipeline,
+            bind_group_layout,
+            sampler,
+            uniform_buffer,
+        }
+    }
+
+    pub fn write_uniform(&self, queue: &wgpu::Queue, data: &[u8]) {
+        queue.write_buffer(&self.uniform_buffer, 0, data);
+    }
+
+    // The input changes from frame to frame (ping-pong targets), so the bind
+    // group is built right before drawing
+    pub fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+    ) {
+        self.render_with_inputs(device, encoder, input, &[], output);
+    }
+
+    // `extra_inputs` go to bindings 3 and up, in order
+    pub fn render_with_inputs(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &wgpu::TextureView,
+        extra_inputs: &[&wgpu::TextureView],
+        output: &wgpu::TextureView,
+    ) {
+        assert_eq!(
+            extra_inputs.len(),
+            self.extra_inputs as usize,
+            "{} expects {} extra inputs",
+            self.label,
+            self.extra_inputs
+        );
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(input),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: self.uniform_buffer.as_entire_binding(),
+            },
+        ];
+        entries.extend(
+            extra_inputs
+                .iter()
+                .enumerate()
+                .map(|(i, view)| wgpu::BindGroupEntry {
+                    binding: 3 + i as u32,
+                    resource: wgpu::BindingResource::TextureView(view),
+                }),
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &entries,
+            label: Some(self.label),
+        });
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(self.label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+        crate::stats::count_draw();
+    }
+}
+
+// ===== MOTION BLUR =====
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MotionBlurUniform {
+    strength: f32,
+    samples: u32,
+    _padding: [f32; 2],
+}
+
+// Blurs along the per-pixel velocity from the scene pass, so fast camera
+// moves and quick objects streak instead of stepping between frames
+pub struct MotionBlur {
+    pub enabled: bool,
+    pub strength: f32, // 1.0 blurs over the full distance moved in a frame
+    pub samples: u32,
+    velocity: Option<wgpu::TextureView>,
+    effect: FullscreenEffect,
+}
+
+impl MotionBlur {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        Self {
+            enabled: true,
+            strength: 1.0,
+            samples: 8,
+            velocity: None,
+            effect: FullscreenEffect::with_inputs(
+                device,
+                "Motion Blur",
+                wgpu::ShaderSource::Wgsl(include_str!("motion_blur.wgsl").into()),
+                format,
+                std::mem::size_of::<MotionBlurUniform>() as u64,
+                1,
+            ),
+        }
+    }
+
+    // The velocity target is recreated with the HDR one, so hand it over again on resize
+    pub fn set_velocity(&mut self, velocity: wgpu::TextureView) {
+        self.velocity = Some(velocity);
+    }
+}
+
+impl PostEffect for MotionBlur {
+    fn name(&self) -> &'static str {
+        "motion_blur"
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled && self.velocity.is_some()
+    }
+
+    fn update(&self, queue: &wgpu::Queue) {
+        self.effect.write_uniform(
+            queue,
+            bytemuck::cast_slice(&[MotionBlurUniform {
+                strength: self.strength,
+                samples: self.samples,
+                _padding: [0.0; 2],
+            }]),
+        );
+    }
+
+    fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+    ) {
+        if let Some(velocity) = &self.velocity {
+            self.effect
+                .render_with_inputs(device, encoder, input, &[velocity], output);
+        }
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// ===== DEPTH OF FIELD =====
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DepthOfFieldUniform {
+    focal_distance: f32,
+    aperture: f32,
+    max_blur: f32,
+    samples: u32,
+}
+
+// Keeps things at the focal distance sharp and blurs the rest by their
+// circle of confusion, like a camera lens with a wide aperture
+pub struct DepthOfField {
+    pub enabled: bool,
+    pub focal_distance: f32, // In world units from the camera
+    pub aperture: f32,       // Bigger blurs more, 0.0 keeps everything sharp
+    pub max_blur: f32,       // Largest blur radius in pixels
+    pub samples: u32,
+    linear_depth: Option<wgpu::TextureView>,
+    effect: FullscreenEffect,
+}
+
+impl DepthOfField {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        Self {
+            enabled: false,
+            focal_distance: 2.0,
+            aperture: 0.6,
+            max_blur: 12.0,
+            samples: 32,
+            linear_depth: None,
+            effect: FullscreenEffect::with_inputs(
+                device,
+                "Depth Of Field",
+                wgpu::ShaderSource::Wgsl(include_str!("depth_of_field.wgsl").into()),
+                format,
+                std::mem::size_of::<DepthOfFieldUniform>() as u64,
+                1,
+            ),
+        }
+    }
+
+    // Recreated with the HDR target, so hand it over again on resize
+    pub fn set_linear_depth(&mut self, linear_depth: wgpu::TextureView) {
+        self.linear_depth = Some(linear_depth);
+    }
+}
+
+impl PostEffect for DepthOfField {
+    fn name(&self) -> &'static str {
+        "depth_of_field"
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled && self.linear_depth.is_some()
+    }
+
+    fn update(&self, queue: &wgpu::Queue) {
+        self.effect.write_uniform(
+            queue,
+            bytemuck::cast_slice(&[DepthOfFieldUniform {
+                focal_distance: self.focal_distance,
+                aperture: self.aperture,
+                max_blur: self.max_blur,
+                samples: self.samples,
+            }]),
+        );
+    }
+
+    fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+    ) {
+        if let Some(linear_depth) = &self.linear_depth {
+            self.effect
+                .render_with_inputs(device, encoder, input, &[linear_depth], output);
+        }
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// ===== VIGNETTE =====
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct VignetteUniform {
+    intensity: f32,
+    smoothness: f32,
+    _padding: [f32; 2],
+}
+
+// Darkens the corners of the screen to pull the eye to the middle
+pub struct Vignette {
+    pub enabled: bool,
+    pub intensity: f32,  // How dark the corners get
+    pub smoothness: f32, // How far the falloff reaches into the screen
+    effect: FullscreenEffect,
+}
+
+impl Vignette {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        Self {
+            enabled: true,
+            intensity: 0.35,
+            smoothness: 0.6,
+            effect: FullscreenEffect::new(
+                device,
+                "Vignette",
+                wgpu::ShaderSource::Wgsl(include_str!("vignette.wgsl").into()),
+                format,
+                std::mem::size_of::<VignetteUniform>() as u64,
+            ),
+        }
+    }
+}
+
+impl PostEffect for Vignette {
+    fn name(&self) -> &'static str {
+        "vignette"
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn update(&self, queue: &wgpu::Queue) {
+        self.effect.write_uniform(
+            queue,
+            bytemuck::cast_slice(&[VignetteUniform {
+                intensity: self.intensity,
+                smoothness: self.smoothness,
+                _padding: [0.0; 2],
+            }]),
+        );
+    }
+
+    fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+    ) {
+        self.effect.render(device, encoder, input, output);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// ===== LENS DISTORTION =====
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DistortionUniform {
+    strength: f32,
+    chromatic_aberration: f32,
+    _padding: [f32; 2],
+}
+
+// Barrel distortion with a little color fringing towards the edges
+pub struct Distortion {
+    pub enabled: bool,
+    pub strength: f32, // Positive bulges outwards, negative pinches
+    pub chromatic_aberration: f32,
+    effect: FullscreenEffect,
+}
+
+impl Distortion {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        Self {
+            enabled: false,
+            strength: 0.15,
+            chromatic_aberration: 0.01,
+            effect: FullscreenEffect::new(
+                device,
+                "Distortion",
+                wgpu::ShaderSource::Wgsl(include_str!("distortion.wgsl").into()),
+                format,
+                std::mem::size_of::<DistortionUniform>() as u64,
+            ),
+        }
+    }
+}
+
+impl PostEffect for Distortion {
+    fn name(&self) -> &'static str {
+        "distortion"
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn update(&self, queue: &wgpu::Queue) {
+        self.effect.write_uniform(
+            queue,
+            bytemuck::cast_slice(&[DistortionUniform {
+                strength: self.strength,
+                chromatic_aberration: self.chromatic_aberration,
+                _padding: [0.0; 2],
+            }]),
+        );
+    }
+
+    fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+    ) {
+        self.effect.render(device, encoder, input, output);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -0,0 +1,255 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{bail, Context};
+
+// ===== WGSL PREPROCESSOR =====
+// A few C-style directives on top of WGSL, so shared code like the camera
+// struct, lighting and noise lives in one file:
+//   #include "camera.wgsl"   pastes the file in, once per shader
+//   #define NAME [value]     sets a flag, and replaces NAME with value after it
+//   #ifdef NAME / #ifndef NAME / #else / #endif   keeps lines by flag
+// Directives take a whole line. Files come from the built-in copies, or from
+// src/ on disk when hot reloading.
+#[derive(Clone)]
+pub struct Preprocessor {
+    defines: HashMap<String, String>,
+    load: fn(&str) -> anyhow::Result<Cow<'static, str>>,
+}
+
+// Every shader built through the preprocessor and everything they include
+fn builtin(name: &str) -> anyhow::Result<Cow<'static, str>> {
+    let source = match name {
+        "camera.wgsl" => include_str!("camera.wgsl"),
+        "noise.wgsl" => include_str!("noise.wgsl"),
+        "lighting.wgsl" => include_str!("lighting.wgsl"),
+        "material.wgsl" => include_str!("material.wgsl"),
+        "material_array.wgsl" => include_str!("material_array.wgsl"),
+        "shader.wgsl" => include_str!("shader.wgsl"),
+        "deferred_geometry.wgsl" => include_str!("deferred_geometry.wgsl"),
+        "deferred_lighting.wgsl" => include_str!("deferred_lighting.wgsl"),
+        "fire_shader.wgsl" => include_str!("fire_shader.wgsl"),
+        "terrain.wgsl" => include_str!("terrain.wgsl"),
+        "grass.wgsl" => include_str!("grass.wgsl"),
+        _ => bail!("Unknown shader file {}", name),
+    };
+    Ok(Cow::Borrowed(source))
+}
+
+impl Default for Preprocessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        Self {
+            defines: HashMap::new(),
+            load: builtin,
+        }
+    }
+
+    // Reads files from src/ instead of the copies built into the binary
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_disk() -> Self {
+        Self {
+            defines: HashMap::new(),
+            load: |name| Ok(Cow::Owned(crate::hot_reload::ShaderWatcher::read(name)?)),
+        }
+    }
+
+    // An empty value only sets the flag
+    pub fn define(&mut self, name: &str, value: impl ToString) -> &mut Self {
+        self.defines.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    // The file with its includes pasted in and the directives applied
+    pub fn process(&self, name: &str) -> anyhow::Result<String> {
+        let mut defines = self.defines.clone();
+        let mut included = HashSet::new();
+        let mut output = String::new();
+        self.process_file(name, &mut defines, &mut included, &mut output)?;
+        Ok(output)
+    }
+
+    // `process` for a shader that's built in, as an `Error::Shader` naming
+    // it when it doesn't come together
+    pub fn process_builtin(&self, name: &str) -> crate::error::Result<String> {
+        self.process(name).map_err(|e| crate::error::Error::Shader {
+            name: name.to_string(),
+            source: e.into(),
+        })
+    }
+
+    fn process_file(
+        &self,
+        name: &str,
+        defines: &mut HashMap<String, String>,
+        included: &mut HashSet<String>,
+        output: &mut String,
+    ) -> anyhow::Result<()> {
+        if !included.insert(name.to_string()) {
+            return Ok(());
+        }
+        let source = (self.load)(name)?;
+
+        // Whether each open #ifdef keeps its lines, innermost last
+        let mut conditions = Vec::<bool>::new();
+        for (number, line) in source.lines().enumerate() {
+            let location = || format!("{}:{}", name, number + 1);
+            let active = conditions.iter().all(|&keep| keep);
+            let Some(directive) = line.trim().strip_prefix('#') else {
+                if active {
+                    output.push_str(&substitute(line, defines));
+                    output.push('\n');
+                }
+                continue;
+            };
+            let (keyword, argument) = directive
+                .split_once(char::is_whitespace)
+                .map(|(keyword, argument)| (keyword, argument.trim()))
+                .unwrap_or((directive, ""));
+            match keyword {
+                "ifdef" | "ifndef" => {
+                    if argument.is_empty() {
+                        bail!("{}: #{} needs a name", location(), keyword);
+                    }
+                    conditions.push(defines.contains_key(argument) == (keyword == "ifdef"));
+                }
+                "else" => {
+                    let keep = conditions
+                        .last_mut()
+                        .with_context(|| format!("{}: #else without #ifdef", location()))?;
+                    *keep = !*keep;
+                }
+                "endif" => {
+                    conditions
+                        .pop()
+                        .with_context(|| format!("{}: #endif without #ifdef", location()))?;
+                }
+                // Skipped lines can't include or define anything
+                _ if !active => {}
+                "include" => {
+                    let file = argument
+                        .strip_prefix('"')
+                        .and_then(|argument| argument.strip_suffix('"'))
+                        .with_context(|| format!("{}: expected #include \"file\"", location()))?;
+                    self.process_file(file, defines, included, output)
+                        .with_context(|| format!("included from {}", location()))?;
+                }
+                "define" => {
+                    let (define, value) = argument
+                        .split_once(char::is_whitespace)
+                        .map(|(define, value)| (define, value.trim()))
+                        .unwrap_or((argument, ""));
+                    if define.is_empty() {
+                        bail!("{}: #define needs a name", location());
+                    }
+                    defines.insert(define.to_string(), value.to_string());
+                }
+                _ => bail!("{}: unknown directive #{}", location(), keyword),
+            }
+        }
+        if !conditions.is_empty() {
+            bail!("{}: missing #endif", name);
+        }
+        Ok(())
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// Replaces whole words that are defined with a value
+fn substitute<'a>(line: &'a str, defines: &HashMap<String, String>) -> Cow<'a, str> {
+    if defines.values().all(String::is_empty) {
+        return Cow::Borrowed(line);
+    }
+    let mut output = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find(is_identifier_char) {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c| !is_identifier_char(c)).unwrap_or(rest.len());
+        let word = &rest[..end];
+        match defines.get(word) {
+            Some(value) if !value.is_empty() => output.push_str(value),
+            _ => output.push_str(word),
+        }
+        rest = &rest[end..];
+    }
+    output.push_str(rest);
+    Cow::Owned(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(name: &str) -> anyhow::Result<Cow<'static, str>> {
+        let source = match name {
+            "common.wgsl" => "const PI = 3.14;",
+            "lighting.wgsl" => "#include \"common.wgsl\"\nfn light() {}",
+            "main.wgsl" => "#include \"common.wgsl\"\n#include \"lighting.wgsl\"\nfn main() {}",
+            "defines.wgsl" => "#define SIZE 4\nlet SIZE_X = SIZE * COUNT;",
+            "flags.wgsl" => "#ifdef SHADOWS\nshadowed();\n#else\nlit();\n#endif\n#ifndef SHADOWS\nno_shadows();\n#endif",
+            "unclosed.wgsl" => "#ifdef SHADOWS\nshadowed();",
+            "stray_else.wgsl" => "#else",
+            "missing.wgsl" => "#include \"nowhere.wgsl\"",
+            _ => bail!("no file {}", name),
+        };
+        Ok(Cow::Borrowed(source))
+    }
+
+    fn preprocessor() -> Preprocessor {
+        Preprocessor {
+            defines: HashMap::new(),
+            load: files,
+        }
+    }
+
+    #[test]
+    fn include_pastes_each_file_once() {
+        assert_eq!(
+            preprocessor().process("main.wgsl").unwrap(),
+            "const PI = 3.14;\nfn light() {}\nfn main() {}\n"
+        );
+    }
+
+    #[test]
+    fn define_replaces_whole_words() {
+        assert_eq!(
+            preprocessor()
+                .define("COUNT", 8)
+                .process("defines.wgsl")
+                .unwrap(),
+            "let SIZE_X = 4 * 8;\n"
+        );
+    }
+
+    #[test]
+    fn ifdef_keeps_lines_by_flag() {
+        assert_eq!(
+            preprocessor().process("flags.wgsl").unwrap(),
+            "lit();\nno_shadows();\n"
+        );
+        assert_eq!(
+            preprocessor()
+                .define("SHADOWS", "")
+                .process("flags.wgsl")
+                .unwrap(),
+            "shadowed();\n"
+        );
+    }
+
+    #[test]
+    fn bad_directives_are_errors() {
+        let error = |name| format!("{:#}", preprocessor().process(name).unwrap_err());
+        assert!(error("unclosed.wgsl").contains("missing #endif"));
+        assert!(error("stray_else.wgsl").contains("#else without #ifdef"));
+        assert!(error("missing.wgsl").contains("included from missing.wgsl:1"));
+    }
+}
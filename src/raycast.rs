@@ -0,0 +1,211 @@
+use cgmath::prelude::*;
+
+use crate::model::{Aabb, ModelVertex};
+
+// A half-line from `origin`, `direction` kept unit length so hit distances
+// are in world units
+#[derive(Debug, Copy, Clone)]
+pub struct Ray {
+    pub origin: cgmath::Point3<f32>,
+    pub direction: cgmath::Vector3<f32>,
+}
+
+impl Ray {
+    pub fn new(origin: cgmath::Point3<f32>, direction: cgmath::Vector3<f32>) -> Self {
+        Self {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    pub fn at(&self, distance: f32) -> cgmath::Point3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    // Moved into another space, like into a model's by the inverse of its
+    // instance transform
+    pub fn transformed(&self, transform: cgmath::Matrix4<f32>) -> Self {
+        Self::new(
+            transform.transform_point(self.origin),
+            transform.transform_vector(self.direction),
+        )
+    }
+
+    // Whether it passes through the box closer than `max_distance`, by
+    // clipping it against each pair of planes in turn
+    fn hits_aabb(&self, aabb: &Aabb, max_distance: f32) -> bool {
+        let (mut near, mut far) = (0.0f32, max_distance);
+        for axis in 0..3 {
+            let inverse = 1.0 / self.direction[axis];
+            let t0 = (aabb.min[axis] - self.origin[axis]) * inverse;
+            let t1 = (aabb.max[axis] - self.origin[axis]) * inverse;
+            // Parallel to an axis the slab is all or nothing, and NaN on its edge
+            // leaves the range alone
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+        }
+        near <= far
+    }
+
+    // Distance to a triangle from either side, Möller-Trumbore
+    fn hit_triangle(&self, [a, b, c]: &[cgmath::Point3<f32>; 3]) -> Option<f32> {
+        let (edge1, edge2) = (b - a, c - a);
+        let p = self.direction.cross(edge2);
+        let determinant = edge1.dot(p);
+        if determinant.abs() < 1e-8 {
+            return None; // Parallel to it
+        }
+        let inverse = 1.0 / determinant;
+        let s = self.origin - a;
+        let u = s.dot(p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(edge1);
+        let v = self.direction.dot(q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let distance = edge2.dot(q) * inverse;
+        (distance > 0.0).then_some(distance)
+    }
+}
+
+// Where a ray first met something
+#[derive(Debug, Copy, Clone)]
+pub struct RayHit {
+    pub distance: f32,
+    pub position: cgmath::Point3<f32>,
+    // Of the triangle hit, facing back along the ray
+    pub normal: cgmath::Vector3<f32>,
+}
+
+// A box around `count` triangles from `first`, or with none, around its two
+// children, the next node and the one at `first`
+#[derive(Debug, Copy, Clone)]
+struct Node {
+    aabb: Aabb,
+    first: u32,
+    count: u32,
+}
+
+// Leaves are split until they have at most this many triangles
+const LEAF_TRIANGLES: usize = 4;
+
+// ===== MESH BVH =====
+// A mesh's triangles on the CPU for picking, sorted into a tree of boxes so a
+// ray only tests the few triangles near it. Splits the longest side of each
+// box at the median triangle, which builds fast and is good enough for a ray
+// a click.
+#[derive(Debug, Clone, Default)]
+pub struct MeshBvh {
+    triangles: Vec<[cgmath::Point3<f32>; 3]>,
+    nodes: Vec<Node>,
+}
+
+impl MeshBvh {
+    pub fn new(vertices: &[ModelVertex], indices: &[u32]) -> Self {
+        let mut triangles: Vec<_> = indices
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]].map(|i| vertices[i as usize].position.into()))
+            .collect();
+        let mut nodes = Vec::new();
+        if !triangles.is_empty() {
+            let count = triangles.len();
+            build(&mut nodes, &mut triangles, 0, count);
+        }
+        Self { triangles, nodes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.triangles.is_empty()
+    }
+
+    // The closest triangle the ray goes through, in the mesh's own space
+    pub fn raycast(&self, ray: &Ray) -> Option<RayHit> {
+        let mut closest: Option<(f32, usize)> = None;
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(index) = stack.pop() {
+            let node = self.nodes[index];
+            let max_distance = closest.map_or(f32::INFINITY, |(distance, _)| distance);
+            if !ray.hits_aabb(&node.aabb, max_distance) {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(index + 1);
+                stack.push(node.first as usize);
+                continue;
+            }
+            let first = node.first as usize;
+            for i in first..first + node.count as usize {
+                if let Some(distance) = ray.hit_triangle(&self.triangles[i]) {
+                    if closest.is_none_or(|(closest, _)| distance < closest) {
+                        closest = Some((distance, i));
+                    }
+                }
+            }
+        }
+        let (distance, i) = closest?;
+        let [a, b, c] = self.triangles[i];
+        let normal = (b - a).cross(c - a).normalize();
+        Some(RayHit {
+            distance,
+            position: ray.at(distance),
+            normal: if normal.dot(ray.direction) > 0.0 {
+                -normal
+            } else {
+                normal
+            },
+        })
+    }
+}
+
+// Adds the node for `triangles[start..end]` and everything under it
+fn build(
+    nodes: &mut Vec<Node>,
+    triangles: &mut [[cgmath::Point3<f32>; 3]],
+    start: usize,
+    end: usize,
+) {
+    let aabb = Aabb::from_positions(
+        triangles[start..end]
+            .iter()
+            .flatten()
+            .map(|&position| position.into()),
+    );
+    let index = nodes.len();
+    nodes.push(Node {
+        aabb,
+        first: start as u32,
+        count: (end - start) as u32,
+    });
+    if end - start <= LEAF_TRIANGLES {
+        return;
+    }
+
+    let centroid = |triangle: &[cgmath::Point3<f32>; 3]| {
+        (triangle[0].to_vec() + triangle[1].to_vec() + triangle[2].to_vec()) / 3.0
+    };
+    let size = aabb.size();
+    let axis = if size.x >= size.y && size.x >= size.z {
+        0
+    } else if size.y >= size.z {
+        1
+    } else {
+        2
+    };
+    let middle = (start + end) / 2;
+    triangles[start..end].select_nth_unstable_by(middle - start, |a, b| {
+        centroid(a)[axis].total_cmp(&centroid(b)[axis])
+    });
+
+    // The left child comes right after, the right one after all of that
+    build(nodes, triangles, start, middle);
+    let right = nodes.len();
+    build(nodes, triangles, middle, end);
+    nodes[index].first = right as u32;
+    nodes[index].count = 0;
+}
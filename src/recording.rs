@@ -0,0 +1,138 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+use anyhow::Context;
+
+// Where the captured frames go
+enum Sink {
+    // frame_00000.png, frame_00001.png, ... in `dir`
+    Png { dir: PathBuf },
+    // Raw RGBA frames piped into ffmpeg's stdin
+    Ffmpeg { child: Child, size: [u32; 2] },
+}
+
+// ===== RECORDING =====
+// Captures every `every`th frame, either as numbered PNGs or straight into a
+// video through ffmpeg. While it runs the simulation moves on by a fixed step
+// a frame rather than by the time the frame took, so the result plays back at
+// `fps` whatever the machine managed, and comes out the same every time.
+pub struct Recording {
+    pub every: u32,
+    pub fps: f32,
+    sink: Sink,
+    // Frames rendered and captured since it started
+    frame: u64,
+    captured: u64,
+}
+
+impl Recording {
+    pub fn png_sequence(dir: &Path, every: u32, fps: f32) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Couldn't create {}", dir.display()))?;
+        Ok(Self::new(
+            Sink::Png {
+                dir: dir.to_path_buf(),
+            },
+            every,
+            fps,
+        ))
+    }
+
+    // Frames have to stay `size`, ffmpeg is told it up front
+    pub fn ffmpeg(path: &Path, size: [u32; 2], every: u32, fps: f32) -> anyhow::Result<Self> {
+        let child = Command::new("ffmpeg")
+            .args([
+                "-y",
+                "-loglevel",
+                "error",
+                "-f",
+                "rawvideo",
+                "-pix_fmt",
+                "rgba",
+            ])
+            .args(["-s", &format!("{}x{}", size[0], size[1])])
+            .args(["-r", &fps.to_string(), "-i", "-"])
+            // Most players want 4:2:0, which needs even sizes
+            .args([
+                "-vf",
+                "pad=ceil(iw/2)*2:ceil(ih/2)*2",
+                "-pix_fmt",
+                "yuv420p",
+            ])
+            .arg(path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .context("Couldn't start ffmpeg")?;
+        Ok(Self::new(Sink::Ffmpeg { child, size }, every, fps))
+    }
+
+    fn new(sink: Sink, every: u32, fps: f32) -> Self {
+        Self {
+            every: every.max(1),
+            fps,
+            sink,
+            frame: 0,
+            captured: 0,
+        }
+    }
+
+    // Seconds the simulation moves on by each frame rendered
+    pub fn frame_time(&self) -> f32 {
+        1.0 / (self.fps * self.every as f32)
+    }
+
+    // Whether the frame being rendered is one to capture
+    pub fn wants_frame(&self) -> bool {
+        self.frame.is_multiple_of(self.every as u64)
+    }
+
+    pub fn captured(&self) -> u64 {
+        self.captured
+    }
+
+    // After each frame, captured or not. `image` is the frame when it was
+    // wanted.
+    pub fn advance(&mut self, image: Option<&image::RgbaImage>) -> anyhow::Result<()> {
+        self.frame += 1;
+        let Some(image) = image else {
+            return Ok(());
+        };
+        match &mut self.sink {
+            Sink::Png { dir } => {
+                let path = dir.join(format!("frame_{:05}.png", self.captured));
+                image
+                    .save_with_format(&path, image::ImageFormat::Png)
+                    .with_context(|| format!("Couldn't write {}", path.display()))?;
+            }
+            Sink::Ffmpeg { child, size } => {
+                anyhow::ensure!(
+                    image.dimensions() == (size[0], size[1]),
+                    "The video is {}x{}, the frame is {}x{}",
+                    size[0],
+                    size[1],
+                    image.width(),
+                    image.height()
+                );
+                child
+                    .stdin
+                    .as_mut()
+                    .context("ffmpeg has no input")?
+                    .write_all(image.as_raw())
+                    .context("Couldn't write to ffmpeg")?;
+            }
+        }
+        self.captured += 1;
+        Ok(())
+    }
+
+    // Waits for ffmpeg to finish the video off
+    pub fn finish(self) -> anyhow::Result<()> {
+        if let Sink::Ffmpeg { mut child, .. } = self.sink {
+            drop(child.stdin.take());
+            let status = child.wait().context("ffmpeg didn't finish")?;
+            anyhow::ensure!(status.success(), "ffmpeg failed with {}", status);
+        }
+        Ok(())
+    }
+}
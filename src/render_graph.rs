@@ -0,0 +1,273 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::gpu_timing::GpuTimer;
+
+// ===== RENDER GRAPH =====
+// Passes declare the named resources they read and write ("hdr", "depth",
+// "shadow_map", ...). Every frame the graph skips disabled passes, culls the
+// ones whose results never reach an output, and runs the rest in the order
+// they were added. Textures that only live between two passes are created and
+// resized by the graph itself.
+
+pub type ResourceName = &'static str;
+
+// Closure that records a pass, given the app state `C` and this frame's resources
+pub type PassFn<C> = Box<dyn Fn(&mut C, &mut wgpu::CommandEncoder, &FrameResources)>;
+
+struct Pass<C> {
+    name: &'static str,
+    reads: Vec<ResourceName>,
+    writes: Vec<ResourceName>,
+    enabled: Option<fn(&C) -> bool>,
+    execute: PassFn<C>,
+}
+
+// A texture owned by the graph, always the size of the surface
+#[derive(Debug, Copy, Clone)]
+pub struct TransientTexture {
+    pub format: wgpu::TextureFormat,
+    pub usage: wgpu::TextureUsages,
+}
+
+pub struct RenderGraph<C> {
+    passes: Vec<Pass<C>>,
+    outputs: Vec<ResourceName>,
+    transients: Vec<(ResourceName, TransientTexture)>,
+    views: HashMap<ResourceName, wgpu::TextureView>,
+}
+
+// Views a pass can look up by name: the graph's transient textures plus
+// whatever was imported for this frame (like the surface)
+pub struct FrameResources<'a> {
+    transients: &'a HashMap<ResourceName, wgpu::TextureView>,
+    imported: &'a [(ResourceName, &'a wgpu::TextureView)],
+}
+
+impl FrameResources<'_> {
+    pub fn view(&self, name: ResourceName) -> &wgpu::TextureView {
+        self.imported
+            .iter()
+            .find(|(imported, _)| *imported == name)
+            .map(|(_, view)| *view)
+            .or_else(|| self.transients.get(name))
+            .unwrap_or_else(|| panic!("render graph resource {:?} doesn't exist", name))
+    }
+}
+
+// Returned by `add_pass` to declare what the pass touches
+pub struct PassBuilder<'g, C> {
+    pass: &'g mut Pass<C>,
+}
+
+impl<C> PassBuilder<'_, C> {
+    pub fn reads(self, names: &[ResourceName]) -> Self {
+        self.pass.reads.extend_from_slice(names);
+        self
+    }
+
+    pub fn writes(self, names: &[ResourceName]) -> Self {
+        self.pass.writes.extend_from_slice(names);
+        self
+    }
+
+    // The pass only runs while this returns true
+    pub fn enabled_if(self, enabled: fn(&C) -> bool) -> Self {
+        self.pass.enabled = Some(enabled);
+        self
+    }
+}
+
+impl<C> Default for RenderGraph<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C> RenderGraph<C> {
+    pub fn new() -> Self {
+        Self {
+            passes: Vec::new(),
+            outputs: Vec::new(),
+            transients: Vec::new(),
+            views: HashMap::new(),
+        }
+    }
+
+    pub fn add_pass(
+        &mut self,
+        name: &'static str,
+        execute: impl Fn(&mut C, &mut wgpu::CommandEncoder, &FrameResources) + 'static,
+    ) -> PassBuilder<'_, C> {
+        self.passes.push(Pass {
+            name,
+            reads: Vec::new(),
+            writes: Vec::new(),
+            enabled: None,
+            execute: Box::new(execute),
+        });
+        PassBuilder {
+            pass: self.passes.last_mut().unwrap(),
+        }
+    }
+
+    // Resources the frame exists to produce; passes that don't lead here are culled
+    pub fn add_output(&mut self, name: ResourceName) {
+        self.outputs.push(name);
+    }
+
+    // Declare a texture the graph should create, call `resize` to allocate it
+    pub fn add_texture(&mut self, name: ResourceName, texture: TransientTexture) {
+        self.transients.push((name, texture));
+    }
+
+    pub fn texture(&self, name: ResourceName) -> &wgpu::TextureView {
+        self.views
+            .get(name)
+            .unwrap_or_else(|| panic!("render graph texture {:?} doesn't exist", name))
+    }
+
+    // (Re)create the transient textures at the surface size
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.views = self
+            .transients
+            .iter()
+            .map(|(name, texture)| {
+                let view = device
+                    .create_texture(&wgpu::TextureDescriptor {
+                        label: Some(name),
+                        size: wgpu::Extent3d {
+                            width: width.max(1),
+                            height: height.max(1),
+                            depth_or_array_layers: 1,
+                        },
+                        mip_level_count: 1,
+                        sample_count: 1,
+                        dimension: wgpu::TextureDimension::D2,
+                        format: texture.format,
+                        usage: texture.usage,
+                        view_formats: &[],
+                    })
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+                (*name, view)
+            })
+            .collect();
+    }
+
+    // Indices of the passes to run this frame, in order. Walks the enabled
+    // passes backwards from the outputs, keeping a pass if anything still
+    // needed is among its writes.
+    fn schedule(&self, context: &C) -> Vec<usize> {
+        let mut needed = self.outputs.iter().copied().collect::<HashSet<_>>();
+        let mut scheduled = Vec::new();
+        for (index, pass) in self.passes.iter().enumerate().rev() {
+            if pass.enabled.is_some_and(|enabled| !enabled(context)) {
+                continue;
+            }
+            if !pass.writes.iter().any(|name| needed.contains(name)) {
+                tracing::trace!("Culled render pass {}", pass.name);
+                continue;
+            }
+            // Anything it writes without reading is fully produced here
+            for name in &pass.writes {
+                if !pass.reads.contains(name) {
+                    needed.remove(name);
+                }
+            }
+            needed.extend(pass.reads.iter().copied());
+            scheduled.push(index);
+        }
+        scheduled.reverse();
+        scheduled
+    }
+
+    pub fn execute(
+        &self,
+        context: &mut C,
+        encoder: &mut wgpu::CommandEncoder,
+        imported: &[(ResourceName, &wgpu::TextureView)],
+        mut timer: Option<&mut GpuTimer>,
+    ) {
+        let resources = FrameResources {
+            transients: &self.views,
+            imported,
+        };
+        // Each pass in its own debug group named after it, so frame captures
+        // in RenderDoc or Xcode read like the graph, and timed on the GPU
+        // when there's a timer
+        for index in self.schedule(context) {
+            let pass = &self.passes[index];
+            profiling::scope!(pass.name);
+            encoder.push_debug_group(pass.name);
+            match timer.as_deref_mut() {
+                Some(timer) => timer.time(encoder, pass.name, |encoder| {
+                    (pass.execute)(context, encoder, &resources)
+                }),
+                None => (pass.execute)(context, encoder, &resources),
+            }
+            encoder.pop_debug_group();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Toggles {
+        hud: bool,
+    }
+
+    // Scene into "hdr", post-processing onto the surface, then an overlay
+    // drawing over it
+    fn graph(hud_reads_surface: bool) -> RenderGraph<Toggles> {
+        let mut graph = RenderGraph::<Toggles>::new();
+        graph.add_pass("scene", |_, _, _| {}).writes(&["hdr"]);
+        graph.add_pass("unused", |_, _, _| {}).writes(&["scratch"]);
+        graph
+            .add_pass("post_process", |_, _, _| {})
+            .reads(&["hdr"])
+            .writes(&["surface"]);
+        let hud = graph.add_pass("hud", |_, _, _| {});
+        let hud = if hud_reads_surface {
+            hud.reads(&["surface"])
+        } else {
+            hud
+        };
+        hud.writes(&["surface"]).enabled_if(|toggles| toggles.hud);
+        graph.add_output("surface");
+        graph
+    }
+
+    fn scheduled(graph: &RenderGraph<Toggles>, toggles: &Toggles) -> Vec<&'static str> {
+        graph
+            .schedule(toggles)
+            .into_iter()
+            .map(|index| graph.passes[index].name)
+            .collect()
+    }
+
+    #[test]
+    fn overlay_keeps_what_it_draws_over() {
+        let graph = graph(true);
+        assert_eq!(
+            scheduled(&graph, &Toggles { hud: true }),
+            ["scene", "post_process", "hud"]
+        );
+    }
+
+    #[test]
+    fn disabled_pass_is_skipped() {
+        let graph = graph(true);
+        assert_eq!(
+            scheduled(&graph, &Toggles { hud: false }),
+            ["scene", "post_process"]
+        );
+    }
+
+    #[test]
+    fn write_only_pass_culls_earlier_writers() {
+        // Clearing the surface makes whatever was drawn there before moot
+        let graph = graph(false);
+        assert_eq!(scheduled(&graph, &Toggles { hud: true }), ["hud"]);
+    }
+}
@@ -1,10 +1,62 @@
 use std::io::{BufReader, Cursor};
 
+use anyhow::{anyhow, Context};
+use cgmath::prelude::*;
 use wgpu::util::DeviceExt;
 
+use crate::animation::{
+    AnimationClip, Channel, Interpolation, Joint, Keyframes, MeshSkin, Rig, Skeleton, Transform,
+};
+use crate::assets::{Assets, Handle};
+use crate::color::ColorSpace;
+use crate::compressed::{self, CompressedImage};
+use crate::environment::Environment;
+use crate::error::{Error, Result};
+use crate::raycast::MeshBvh;
+use crate::simplify::{self, LodSettings};
+use crate::skinning::{SkinVertex, SKINNED_VERTEX_USAGE};
 use crate::{model, texture};
 
+// ===== RESOURCE PATHS =====
+// Where res/ is looked for on native, first match wins: the directory in
+// RES_DIR_VAR, a res/ next to the executable (how it's shipped), then the one
+// in the source tree (cargo run, from any working directory).
+#[cfg(not(target_arch = "wasm32"))]
+pub const RES_DIR_VAR: &str = "LEARN_WGPU_RES";
 
+#[cfg(not(target_arch = "wasm32"))]
+fn res_dir_candidates() -> Vec<std::path::PathBuf> {
+    let mut candidates = Vec::new();
+    if let Some(dir) = std::env::var_os(RES_DIR_VAR) {
+        candidates.push(dir.into());
+    }
+    if let Some(exe_dir) = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join("res")))
+    {
+        candidates.push(exe_dir);
+    }
+    candidates.push(std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("res"));
+    candidates
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn res_dir() -> Result<std::path::PathBuf> {
+    let candidates = res_dir_candidates();
+    match candidates.iter().find(|dir| dir.is_dir()) {
+        Some(dir) => Ok(dir.clone()),
+        None => Err(Error::NoResDir {
+            candidates,
+            var: RES_DIR_VAR,
+        }),
+    }
+}
+
+// The path a loader reads `file_name` from, relative to res/
+#[cfg(not(target_arch = "wasm32"))]
+pub fn resolve(file_name: &str) -> Result<std::path::PathBuf> {
+    Ok(res_dir()?.join(file_name))
+}
 
 #[cfg(target_arch = "wasm32")]
 fn format_url(file_name: &str) -> reqwest::Url {
@@ -15,55 +67,478 @@ fn format_url(file_name: &str) -> reqwest::Url {
     base.join(file_name).unwrap()
 }
 
-pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
+// The server answers a missing file with a 404 page, which would otherwise
+// be read as the file
+#[cfg(target_arch = "wasm32")]
+async fn fetch(url: &reqwest::Url) -> Result<reqwest::Response> {
+    tracing::debug!("Fetching {}", url);
+    reqwest::get(url.clone())
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(fetch_error(url))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn fetch_error(url: &reqwest::Url) -> impl FnOnce(reqwest::Error) -> Error + '_ {
+    move |source| Error::Fetch {
+        url: url.to_string(),
+        status: source.status().map(|status| status.as_u16()),
+        source: source.into(),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_error(path: &std::path::Path) -> impl FnOnce(std::io::Error) -> Error + '_ {
+    move |source| Error::Read {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+pub async fn load_string(file_name: &str) -> Result<String> {
     #[cfg(target_arch = "wasm32")]
     let txt = {
         let url = format_url(file_name);
-        reqwest::get(url).await?.text().await?
+        fetch(&url).await?.text().await.map_err(fetch_error(&url))?
     };
     #[cfg(not(target_arch = "wasm32"))]
     let txt = {
-        let path = std::path::Path::new(env!("OUT_DIR"))
-            .join("res")
-            .join(file_name);
-        std::fs::read_to_string(path)?
+        let path = resolve(file_name)?;
+        tracing::debug!("Reading {}", path.display());
+        std::fs::read_to_string(&path).map_err(read_error(&path))?
     };
 
     Ok(txt)
 }
 
-pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
+pub async fn load_binary(file_name: &str) -> Result<Vec<u8>> {
     #[cfg(target_arch = "wasm32")]
     let data = {
         let url = format_url(file_name);
-        reqwest::get(url).await?.bytes().await?.to_vec()
+        fetch(&url)
+            .await?
+            .bytes()
+            .await
+            .map_err(fetch_error(&url))?
+            .to_vec()
     };
     #[cfg(not(target_arch = "wasm32"))]
     let data = {
-        let path = std::path::Path::new(env!("OUT_DIR"))
-            .join("res")
-            .join(file_name);
-        std::fs::read(path)?
+        let path = resolve(file_name)?;
+        tracing::debug!("Reading {}", path.display());
+        std::fs::read(&path).map_err(read_error(&path))?
     };
 
     Ok(data)
 }
 
-pub async fn load_texture(
-    file_name: &str,
+// Any image file's pixels, KTX2 and DDS files included
+pub fn decode_image(data: &[u8]) -> anyhow::Result<image::DynamicImage> {
+    if compressed::is_container(data) {
+        let image = CompressedImage::from_bytes(data)?;
+        return Ok(image::DynamicImage::ImageRgba8(image.decode()));
+    }
+    Ok(image::load_from_memory(data)?)
+}
+
+// Loads a cubemap stored as px/nx/py/ny/pz/nz.png in the given directory
+#[tracing::instrument(skip_all, fields(dir = dir))]
+pub async fn load_cubemap(
+    dir: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
 ) -> anyhow::Result<texture::Texture> {
-    let data = load_binary(file_name).await?;
-    texture::Texture::from_bytes(device, queue, &data, file_name)
+    let mut faces = Vec::with_capacity(6);
+    for face in ["px", "nx", "py", "ny", "pz", "nz"] {
+        let file_name = format!("{}/{}.png", dir, face);
+        let data = load_binary(&file_name).await?;
+        faces.push(
+            image::load_from_memory(&data)
+                .with_context(|| format!("Couldn't decode {}", file_name))?,
+        );
+    }
+    texture::Texture::from_cube_faces(device, queue, &faces, Some(dir))
 }
 
-pub async fn load_model(
+// Loads an equirectangular .hdr image and makes the environment maps from it
+#[tracing::instrument(skip_all, fields(file = file_name))]
+pub async fn load_environment(
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
+) -> anyhow::Result<Environment> {
+    let data = load_binary(file_name).await?;
+    // Read as floats, `load_from_memory` would tone map it down to 8 bits
+    let decoder = image::codecs::hdr::HdrDecoder::new(Cursor::new(data))
+        .with_context(|| format!("{} isn't a Radiance HDR image", file_name))?;
+    let (width, height) = (decoder.metadata().width, decoder.metadata().height);
+    let texels = decoder
+        .read_image_hdr()?
+        .into_iter()
+        .flat_map(|texel| texel.0)
+        .collect();
+    let image = image::Rgb32FImage::from_raw(width, height, texels)
+        .context("HDR image smaller than its header says")?;
+    Environment::from_equirectangular(
+        device,
+        queue,
+        &image::DynamicImage::ImageRgb32F(image),
+        file_name,
+    )
+}
+
+// Flat tangent-space normal pointing straight out of the surface
+pub fn default_normal_texture(device: &wgpu::Device, queue: &wgpu::Queue) -> texture::Texture {
+    texture::Texture::solid_color(
+        device,
+        queue,
+        [128, 128, 255, 255],
+        ColorSpace::Linear.texture_format(),
+        "default_normal",
+    )
+}
+
+pub fn default_white_texture(device: &wgpu::Device, queue: &wgpu::Queue) -> texture::Texture {
+    texture::Texture::solid_color(
+        device,
+        queue,
+        [255, 255, 255, 255],
+        ColorSpace::Linear.texture_format(),
+        "default_white",
+    )
+}
+
+// Common Blinn-Phong exponent to GGX roughness approximation
+fn shininess_to_roughness(shininess: f32) -> f32 {
+    (2.0 / (shininess.max(0.0) + 2.0)).sqrt().clamp(0.04, 1.0)
+}
+
+// "r g b" as written in MTL files
+fn parse_color(text: &str) -> Option<[f32; 3]> {
+    let mut values = text.split_whitespace().map(|v| v.parse::<f32>());
+    match (values.next(), values.next(), values.next()) {
+        (Some(Ok(r)), Some(Ok(g)), Some(Ok(b))) => Some([r, g, b]),
+        _ => None,
+    }
+}
+
+// A texture statement like `map_Bump -bm 0.5 normal.png`, options first and
+// the file name last. The bump multiplier is the only option used.
+struct MtlTexture {
+    file: String,
+    bump_multiplier: f32,
+}
+
+fn parse_texture_statement(obj_dir: &str, statement: &str) -> Option<MtlTexture> {
+    let mut bump_multiplier = 1.0;
+    let mut tokens = statement.split_whitespace().peekable();
+    while let Some(option) = tokens.next_if(|token| token.starts_with('-')) {
+        match option {
+            "-bm" => bump_multiplier = tokens.next()?.parse().ok()?,
+            // Offset, scale and turbulence take one to three numbers
+            "-o" | "-s" | "-t" => {
+                for _ in 0..3 {
+                    tokens.next_if(|token| token.parse::<f32>().is_ok());
+                }
+            }
+            // Base and gain
+            "-mm" => {
+                tokens.next();
+                tokens.next();
+            }
+            // -blendu, -blendv, -boost, -cc, -clamp, -imfchan, -texres, -type
+            _ => {
+                tokens.next();
+            }
+        }
+    }
+    // File names can have spaces in them
+    let file = tokens.collect::<Vec<_>>().join(" ");
+    (!file.is_empty()).then(|| MtlTexture {
+        file: relative_path(obj_dir, &file),
+        bump_multiplier,
+    })
+}
+
+// A map that doesn't load is logged and left to the material's default
+fn loaded_texture(
+    assets: &Assets,
+    loaded: Result<Handle<texture::Texture>>,
+    material: &str,
+) -> Option<texture::Texture> {
+    match loaded {
+        Ok(handle) => Some(assets.texture(handle).clone()),
+        Err(e) => {
+            tracing::warn!("Material {} is missing a map: {}", material, e.report());
+            None
+        }
+    }
+}
+
+// Bump maps are height maps in the MTL spec, though plenty of exporters put
+// a normal map there instead. A grey image is taken for heights.
+fn is_height_map(image: &image::DynamicImage) -> bool {
+    use image::ColorType;
+    match image.color() {
+        ColorType::L8 | ColorType::La8 | ColorType::L16 | ColorType::La16 => true,
+        _ => image
+            .to_rgb8()
+            .pixels()
+            .all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2]),
+    }
+}
+
+// How many texels the full range of a height map rises over
+const BUMP_DEPTH: f32 = 4.0;
+
+// Tangent-space normals from the slopes between neighbouring heights.
+// Image rows run down the texture while V runs up, hence the flipped Y.
+fn height_to_normal_map(heights: &image::DynamicImage) -> image::DynamicImage {
+    let heights = heights.to_luma32f();
+    let (width, height) = heights.dimensions();
+    let at = |x: i64, y: i64| {
+        heights.get_pixel(
+            x.clamp(0, width as i64 - 1) as u32,
+            y.clamp(0, height as i64 - 1) as u32,
+        )[0]
+    };
+    let normals = image::RgbaImage::from_fn(width, height, |x, y| {
+        let (x, y) = (x as i64, y as i64);
+        let dx = (at(x + 1, y) - at(x - 1, y)) * 0.5 * BUMP_DEPTH;
+        let dy = (at(x, y + 1) - at(x, y - 1)) * 0.5 * BUMP_DEPTH;
+        let normal = cgmath::Vector3::new(-dx, dy, 1.0).normalize();
+        let encode = |v: f32| ((v * 0.5 + 0.5) * 255.0).round() as u8;
+        image::Rgba([encode(normal.x), encode(normal.y), encode(normal.z), 255])
+    });
+    image::DynamicImage::ImageRgba8(normals)
+}
+
+// Where a material's roughness comes from, best first: the PBR extension's
+// roughness map, a glossiness (Ns) map or a specular (Ks) map. The last two
+// are smoothness, so they're flipped.
+struct RoughnessMap {
+    texture: MtlTexture,
+    inverted: bool,
+}
+
+// glTF channel layout: roughness in G and metallic in B. A channel without a
+// map stays at 1 so the factor alone drives it. The metallic map is resized
+// to the roughness map if they differ.
+fn pack_metallic_roughness(
+    roughness: Option<(&image::DynamicImage, bool)>,
+    metallic: Option<&image::DynamicImage>,
+) -> image::DynamicImage {
+    let (width, height) = roughness
+        .map(|(image, _)| (image.width(), image.height()))
+        .or(metallic.map(|image| (image.width(), image.height())))
+        .unwrap_or((1, 1));
+    let channel = |image: &image::DynamicImage| {
+        let luma = image.to_luma8();
+        if luma.dimensions() == (width, height) {
+            luma
+        } else {
+            image::imageops::resize(&luma, width, height, image::imageops::FilterType::Triangle)
+        }
+    };
+    let roughness = roughness.map(|(image, inverted)| (channel(image), inverted));
+    let metallic = metallic.map(channel);
+    image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(width, height, |x, y| {
+        let g = match &roughness {
+            Some((image, false)) => image.get_pixel(x, y)[0],
+            Some((image, true)) => 255 - image.get_pixel(x, y)[0],
+            None => 255,
+        };
+        let b = metallic
+            .as_ref()
+            .map_or(255, |image| image.get_pixel(x, y)[0]);
+        image::Rgba([255, g, b, 255])
+    }))
+}
+
+// Builds a material from an MTL entry. Plain MTL describes Phong surfaces,
+// which are mapped onto metallic-roughness as well as they go, and the PBR
+// extension's statements (Pr, Pm, Ke, norm and their maps) are used as they
+// are. Anything missing, including maps that fail to load, gets a default
+// rather than failing the model.
+async fn load_mtl_material(
+    m: &tobj::Material,
+    obj_dir: &str,
+    assets: &mut Assets,
     layout: &wgpu::BindGroupLayout,
-) -> anyhow::Result<model::Model> {
+) -> model::Material {
+    // tobj parses the classic statements and leaves the rest in unknown_param
+    let param = |key: &str| m.unknown_param.get(key).map(String::as_str);
+    let scalar = |key: &str| param(key).and_then(|value| value.trim().parse::<f32>().ok());
+    let map = |statement: Option<&str>| {
+        statement.and_then(|statement| parse_texture_statement(obj_dir, statement))
+    };
+
+    let diffuse_map = map(Some(&m.diffuse_texture));
+    let diffuse_texture = match &diffuse_map {
+        Some(diffuse) => {
+            let loaded = assets.load_texture(&diffuse.file, ColorSpace::Srgb).await;
+            loaded_texture(assets, loaded, &m.name)
+        }
+        None => None,
+    }
+    .unwrap_or_else(|| assets.white_srgb_texture());
+    // Kd tints untextured surfaces. tobj reads a missing Kd as black, which
+    // is far more likely to be an omission than what was meant.
+    let [r, g, b] = match diffuse_map {
+        Some(_) => [1.0; 3],
+        None if m.diffuse == [0.0; 3] => [1.0; 3],
+        None => m.diffuse,
+    };
+    // Dissolve, or its inverse Tr from exporters that write that instead
+    let alpha = match scalar("Tr") {
+        Some(transparency) if m.dissolve == 1.0 => 1.0 - transparency,
+        _ => m.dissolve,
+    };
+
+    // The PBR extension's norm is always a normal map, bump may be heights
+    let (normal_texture, normal_scale) = if let Some(normal) = map(param("norm")) {
+        let loaded = assets.load_texture(&normal.file, ColorSpace::Linear).await;
+        (
+            loaded_texture(assets, loaded, &m.name),
+            normal.bump_multiplier,
+        )
+    } else if let Some(bump) = map(Some(&m.normal_texture)) {
+        let loaded = assets
+            .load_derived_texture(
+                &format!("{} as a normal map", bump.file),
+                &[&bump.file],
+                ColorSpace::Linear,
+                |mut images| {
+                    let image = images.remove(0);
+                    if is_height_map(&image) {
+                        height_to_normal_map(&image)
+                    } else {
+                        image
+                    }
+                },
+            )
+            .await;
+        (
+            loaded_texture(assets, loaded, &m.name),
+            bump.bump_multiplier,
+        )
+    } else {
+        (None, 1.0)
+    };
+    let normal_texture = normal_texture.unwrap_or_else(|| assets.flat_normal_texture());
+
+    let roughness_map = map(param("map_Pr"))
+        .map(|texture| RoughnessMap {
+            texture,
+            inverted: false,
+        })
+        .or_else(|| {
+            map(Some(&m.shininess_texture))
+                .or_else(|| map(Some(&m.specular_texture)))
+                .map(|texture| RoughnessMap {
+                    texture,
+                    inverted: true,
+                })
+        });
+    let metallic_map = map(param("map_Pm"));
+    let metallic_roughness_texture = match (&roughness_map, &metallic_map) {
+        (None, None) => None,
+        (roughness, metallic) => {
+            let files = roughness
+                .iter()
+                .map(|roughness| roughness.texture.file.as_str())
+                .chain(metallic.iter().map(|metallic| metallic.file.as_str()))
+                .collect::<Vec<_>>();
+            let inverted = roughness
+                .as_ref()
+                .is_some_and(|roughness| roughness.inverted);
+            let has_roughness = roughness.is_some();
+            let loaded = assets
+                .load_derived_texture(
+                    &format!("{} as metallic-roughness", files.join(" + ")),
+                    &files,
+                    ColorSpace::Linear,
+                    |images| {
+                        let mut images = images.iter();
+                        let roughness = has_roughness
+                            .then(|| images.next())
+                            .flatten()
+                            .map(|image| (image, inverted));
+                        pack_metallic_roughness(roughness, images.next())
+                    },
+                )
+                .await;
+            loaded_texture(assets, loaded, &m.name)
+        }
+    }
+    .unwrap_or_else(|| assets.white_texture());
+    // Ns is a Phong exponent, Pr overrides it. A map comes with a factor of
+    // 1 so it's used as it is. Dielectrics get a fixed 4% reflectance, the
+    // only part of Ks the metallic-roughness model has room for.
+    let roughness = scalar("Pr").unwrap_or(if roughness_map.is_some() {
+        1.0
+    } else {
+        shininess_to_roughness(m.shininess)
+    });
+    let metallic = scalar("Pm").unwrap_or(if metallic_map.is_some() { 1.0 } else { 0.0 });
+
+    // Plain MTL has no occlusion map
+    let occlusion_texture = assets.white_texture();
+
+    let emissive_map = map(param("map_Ke"));
+    let emissive_texture = match &emissive_map {
+        Some(emissive) => {
+            let loaded = assets.load_texture(&emissive.file, ColorSpace::Srgb).await;
+            loaded_texture(assets, loaded, &m.name)
+        }
+        None => None,
+    }
+    .unwrap_or_else(|| assets.white_texture());
+    let emissive_color = param("Ke")
+        .and_then(parse_color)
+        // A map on its own should still show up
+        .unwrap_or(if emissive_map.is_some() {
+            [1.0; 3]
+        } else {
+            [0.0; 3]
+        });
+
+    let factors = model::MaterialUniform {
+        base_color: [r, g, b, alpha],
+        metallic,
+        roughness,
+        normal_scale,
+        emissive: [emissive_color[0], emissive_color[1], emissive_color[2], 1.0],
+        ..Default::default()
+    };
+    model::Material::new(
+        assets.device(),
+        &m.name,
+        diffuse_texture,
+        normal_texture,
+        metallic_roughness_texture,
+        occlusion_texture,
+        emissive_texture,
+        factors,
+        layout,
+    )
+}
+
+fn obj_error(file_name: &str) -> impl FnOnce(tobj::LoadError) -> Error + '_ {
+    move |source| Error::Obj {
+        path: file_name.to_string(),
+        source,
+    }
+}
+
+// Load models through `Assets::load_model`, which shares their textures
+#[tracing::instrument(skip_all, fields(file = file_name))]
+pub async fn load_model(
+    file_name: &str,
+    assets: &mut Assets,
+    layout: &wgpu::BindGroupLayout,
+) -> Result<model::Model> {
+    let device = assets.device().clone();
     let obj_text = load_string(file_name).await?;
     let obj_cursor = Cursor::new(obj_text);
     let mut obj_reader = BufReader::new(obj_cursor);
@@ -90,55 +565,55 @@ pub async fn load_model(
                 } else {
                     format!("{}/{}", obj_dir, p)
                 };
-                log::info!("Loading material file: {}", mat_path);
-                let mat_text = load_string(&mat_path).await.unwrap();
-                tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+                tracing::info!("Loading material file: {}", mat_path);
+                match load_string(&mat_path).await {
+                    Ok(mat_text) => tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text))),
+                    Err(e) => {
+                        tracing::warn!(
+                            "Couldn't load {}, using default materials: {}",
+                            mat_path,
+                            e.report()
+                        );
+                        Ok((Vec::new(), Default::default()))
+                    }
+                }
             }
         },
     )
-    .await?;
+    .await
+    .map_err(obj_error(file_name))?;
 
     let mut materials = Vec::new();
-    for m in obj_materials? {
-        log::info!(
+    let obj_materials = obj_materials.map_err(obj_error(file_name))?;
+    for m in obj_materials {
+        tracing::info!(
             "Loading material: {} with texture: {}",
             m.name,
             m.diffuse_texture
         );
-        let texture_path = if obj_dir.is_empty() {
-            m.diffuse_texture.clone()
-        } else {
-            format!("{}/{}", obj_dir, m.diffuse_texture)
-        };
-        log::info!("Texture path: {}", texture_path);
-        let diffuse_texture = load_texture(&texture_path, device, queue).await?;
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        materials.push(load_mtl_material(&m, &obj_dir, assets, layout).await);
+    }
+    // Meshes without a material, or with no MTL to find it in, use the defaults
+    if materials.is_empty() {
+        materials.push(model::Material::new(
+            &device,
+            "default",
+            assets.white_srgb_texture(),
+            assets.flat_normal_texture(),
+            assets.white_texture(),
+            assets.white_texture(),
+            assets.white_texture(),
+            model::MaterialUniform::default(),
             layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: None,
-        });
-
-        materials.push(model::Material {
-            name: m.name,
-            diffuse_texture,
-            bind_group,
-        })
+        ));
     }
-    log::info!("Loaded {} materials", materials.len());
+    tracing::info!("Loaded {} materials", materials.len());
 
-    let meshes = models
+    let simplifying = assets.simplifying();
+    let (meshes, mesh_lods): (Vec<_>, Vec<_>) = models
         .into_iter()
         .map(|m| {
-            let vertices = (0..m.mesh.positions.len() / 3)
+            let mut vertices = (0..m.mesh.positions.len() / 3)
                 .map(|i| {
                     if m.mesh.normals.is_empty() {
                         model::ModelVertex {
@@ -147,11 +622,12 @@ pub async fn load_model(
                                 m.mesh.positions[i * 3 + 1],
                                 m.mesh.positions[i * 3 + 2],
                             ],
-                            tex_coords: [
-                                m.mesh.texcoords[i * 2],
-                                1.0 - m.mesh.texcoords[i * 2 + 1],
-                            ],
-                            normal: [0.0, 0.0, 0.0],
+                            tex_coords: match m.mesh.texcoords.get(i * 2..i * 2 + 2) {
+                                Some(&[u, v]) => [u, 1.0 - v],
+                                _ => [0.0; 2],
+                            },
+                            normal: [0.0, 0.0, 0.0], // Recomputed below
+                            tangent: [0.0; 4],       // Filled in below
                         }
                     } else {
                         model::ModelVertex {
@@ -160,54 +636,676 @@ pub async fn load_model(
                                 m.mesh.positions[i * 3 + 1],
                                 m.mesh.positions[i * 3 + 2],
                             ],
-                            tex_coords: [
-                                m.mesh.texcoords[i * 2],
-                                1.0 - m.mesh.texcoords[i * 2 + 1],
-                            ],
+                            tex_coords: match m.mesh.texcoords.get(i * 2..i * 2 + 2) {
+                                Some(&[u, v]) => [u, 1.0 - v],
+                                _ => [0.0; 2],
+                            },
                             normal: [
                                 m.mesh.normals[i * 3],
                                 m.mesh.normals[i * 3 + 1],
                                 m.mesh.normals[i * 3 + 2],
                             ],
+                            tangent: [0.0; 4], // Filled in below
                         }
                     }
                 })
                 .collect::<Vec<_>>();
+            let mut indices = m.mesh.indices;
+            if let Some(angle) = assets.smoothing_angle(!m.mesh.normals.is_empty()) {
+                model::recompute_normals(&mut vertices, &mut indices, angle);
+            }
+            model::compute_tangents(&mut vertices, &indices);
 
             let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", file_name)),
+                label: Some(&format!("{:?} Vertex Buffer", m.name)),
                 contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
+                usage: model::MOVABLE_VERTEX_USAGE,
             });
             let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
+                label: Some(&format!("{:?} Index Buffer", m.name)),
+                contents: bytemuck::cast_slice(&indices),
                 usage: wgpu::BufferUsages::INDEX,
             });
 
-            model::Mesh {
-                name: file_name.to_string(),
+            let mesh = model::Mesh {
+                name: m.name,
                 vertex_buffer,
                 index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
+                num_elements: indices.len() as u32,
                 material: m.mesh.material_id.unwrap_or(0),
-            }
+                bounds: model::BoundingSphere::from_positions(
+                    vertices.iter().map(|vertex| vertex.position),
+                ),
+                aabb: model::Aabb::from_positions(vertices.iter().map(|vertex| vertex.position)),
+                rest_vertices: vertices.clone(),
+                bvh: MeshBvh::new(&vertices, &indices),
+            };
+            let lods = simplifying.map_or_else(Vec::new, |settings| {
+                simplified_lods(&device, &mesh, &vertices, &indices, settings)
+            });
+            (mesh, lods)
         })
-        .collect::<Vec<_>>();
+        .unzip();
 
-    log::info!(
-        "Loaded {} meshes from model {}",
-        meshes.len(),
-        file_name
-    );
+    tracing::info!("Loaded {} meshes from model {}", meshes.len(), file_name);
     for (i, mesh) in meshes.iter().enumerate() {
-        log::info!(
-            "  Mesh {}: {} vertices/indices, material {}",
+        tracing::info!(
+            "  Mesh {} ({}): {} vertices/indices, material {}",
             i,
+            mesh.name,
             mesh.num_elements,
             mesh.material
         );
     }
 
-    Ok(model::Model { meshes, materials })
-}
\ No newline at end of file
+    Ok(model::Model {
+        meshes,
+        materials,
+        sockets: load_sockets(file_name).await,
+        lods: gather_lods(mesh_lods),
+    })
+}
+
+// Levels of detail for one mesh, simplified from its triangles a level at a
+// time. They draw from the mesh's own vertex buffer.
+fn simplified_lods(
+    device: &wgpu::Device,
+    mesh: &model::Mesh,
+    vertices: &[model::ModelVertex],
+    indices: &[u32],
+    settings: LodSettings,
+) -> Vec<model::Mesh> {
+    let triangles = indices.len() / 3;
+    let mut indices = indices.to_vec();
+    (1..=settings.levels)
+        .map(|level| {
+            let target = triangles as f32 * settings.ratio.powi(level as i32);
+            indices = simplify::simplify(vertices, &indices, target as usize);
+            tracing::info!(
+                "  {} level of detail {}: {} of {} triangles",
+                mesh.name,
+                level,
+                indices.len() / 3,
+                triangles
+            );
+            model::Mesh {
+                name: format!("{} LOD {}", mesh.name, level),
+                vertex_buffer: mesh.vertex_buffer.clone(),
+                index_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{:?} LOD {} Index Buffer", mesh.name, level)),
+                    contents: bytemuck::cast_slice(&indices),
+                    usage: wgpu::BufferUsages::INDEX,
+                }),
+                num_elements: indices.len() as u32,
+                material: mesh.material,
+                bounds: mesh.bounds,
+                aabb: mesh.aabb,
+                rest_vertices: Vec::new(), // Moves with the mesh's vertex buffer
+                bvh: MeshBvh::default(),
+            }
+        })
+        .collect()
+}
+
+// Each mesh's generated levels of detail, level by level
+fn gather_lods(mesh_lods: Vec<Vec<model::Mesh>>) -> Vec<model::Lod> {
+    let mut lods = Vec::<model::Lod>::new();
+    for meshes in mesh_lods {
+        for (i, mesh) in meshes.into_iter().enumerate() {
+            if i == lods.len() {
+                lods.push(model::Lod {
+                    meshes: Vec::new(),
+                    screen_size: model::Lod::default_screen_size(i + 1),
+                });
+            }
+            lods[i].meshes.push(mesh);
+        }
+    }
+    lods
+}
+
+// A socket as written in a sidecar file: where it is in model space and how
+// it's turned, in degrees around X, then Y, then Z
+#[derive(serde::Deserialize)]
+struct SocketFile {
+    name: String,
+    position: [f32; 3],
+    #[serde(default)]
+    rotation: [f32; 3],
+}
+
+// Sockets from an optional `<model>.sockets.ron` next to the model file, a
+// list like `[(name: "mouth", position: (0.0, 0.73, 0.59))]`
+pub async fn load_sockets(file_name: &str) -> Vec<model::Socket> {
+    let path = std::path::Path::new(file_name)
+        .with_extension("sockets.ron")
+        .to_string_lossy()
+        .to_string();
+    let text = match load_string(&path).await {
+        Ok(text) => text,
+        // They're optional, most models don't have any
+        Err(e) if e.is_not_found() => return Vec::new(),
+        Err(e) => {
+            tracing::warn!("Couldn't read {}: {}", path, e.report());
+            return Vec::new();
+        }
+    };
+    match ron::from_str::<Vec<SocketFile>>(&text) {
+        Ok(sockets) => sockets
+            .into_iter()
+            .map(|socket| {
+                let [x, y, z] = socket.rotation;
+                model::Socket {
+                    name: socket.name,
+                    transform: cgmath::Matrix4::from_translation(socket.position.into())
+                        * cgmath::Matrix4::from_angle_z(cgmath::Deg(z))
+                        * cgmath::Matrix4::from_angle_y(cgmath::Deg(y))
+                        * cgmath::Matrix4::from_angle_x(cgmath::Deg(x)),
+                    joint: None,
+                }
+            })
+            .collect(),
+        Err(e) => {
+            tracing::warn!("Couldn't read sockets from {}: {}", path, e);
+            Vec::new()
+        }
+    }
+}
+
+// Joins a path from inside a model file onto the model's directory
+fn relative_path(dir: &str, file_name: &str) -> String {
+    if dir.is_empty() {
+        file_name.to_string()
+    } else {
+        format!("{}/{}", dir, file_name)
+    }
+}
+
+// Loads a glTF model, either a .gltf next to its .bin files or a single .glb.
+// Models with skins also come with the skeleton, skins and animation clips to
+// drive them. Unskinned meshes have their node transforms baked in, skinned
+// ones stay in model space for their joints to place, and get vertex buffers
+// the skinning pass can write to when `skinning` is set. Load them through
+// `Assets::load_model`, which shares their textures.
+#[tracing::instrument(skip_all, fields(file = file_name))]
+pub async fn load_gltf(
+    file_name: &str,
+    assets: &mut Assets,
+    layout: &wgpu::BindGroupLayout,
+    skinning: bool,
+) -> Result<(model::Model, Option<Rig>)> {
+    let device = &assets.device().clone();
+    let gltf =
+        gltf::Gltf::from_slice(&load_binary(file_name).await?).map_err(|source| Error::Gltf {
+            path: file_name.to_string(),
+            source,
+        })?;
+    let dir = std::path::Path::new(file_name)
+        .parent()
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let mut buffers = Vec::new();
+    for buffer in gltf.buffers() {
+        let data = match buffer.source() {
+            gltf::buffer::Source::Bin => gltf
+                .blob
+                .clone()
+                .with_context(|| format!("{} has no binary chunk", file_name))?,
+            gltf::buffer::Source::Uri(uri) if uri.starts_with("data:") => {
+                return Err(anyhow!("{}: embedded data URIs aren't supported", file_name).into())
+            }
+            gltf::buffer::Source::Uri(uri) => load_binary(&relative_path(&dir, uri)).await?,
+        };
+        buffers.push(data);
+    }
+    let buffer_data = |buffer: gltf::Buffer| buffers.get(buffer.index()).map(Vec::as_slice);
+
+    let mut materials = Vec::new();
+    for material in gltf.materials() {
+        materials.push(load_gltf_material(&material, file_name, &buffers, assets, layout).await?);
+    }
+    // For primitives without a material of their own
+    let default_material = materials.len();
+    materials.push(model::Material::new(
+        device,
+        "default",
+        assets.white_texture(),
+        assets.flat_normal_texture(),
+        assets.white_texture(),
+        assets.white_texture(),
+        assets.white_texture(),
+        model::MaterialUniform::default(),
+        layout,
+    ));
+
+    // Every node of the scene becomes a joint, parents first
+    let scene = gltf
+        .default_scene()
+        .or_else(|| gltf.scenes().next())
+        .with_context(|| format!("{} has no scene", file_name))?;
+    let mut nodes = Vec::new();
+    let mut joints = Vec::new();
+    let mut joint_of_node = vec![None; gltf.nodes().len()];
+    let mut stack = scene.nodes().map(|node| (node, None)).collect::<Vec<_>>();
+    stack.reverse();
+    while let Some((node, parent)) = stack.pop() {
+        let index = joints.len();
+        joint_of_node[node.index()] = Some(index);
+        let (translation, [x, y, z, w], scale) = node.transform().decomposed();
+        joints.push(Joint {
+            name: node.name().unwrap_or_default().to_string(),
+            parent,
+            rest: Transform {
+                translation: translation.into(),
+                rotation: cgmath::Quaternion::new(w, x, y, z),
+                scale: scale.into(),
+            },
+        });
+        let first_child = stack.len();
+        stack.extend(node.children().map(|child| (child, Some(index))));
+        stack[first_child..].reverse();
+        nodes.push(node);
+    }
+    let skeleton = Skeleton { joints };
+    let rest_globals = skeleton.global_matrices(&skeleton.rest_pose());
+
+    let mut meshes = Vec::new();
+    let mut mesh_lods = Vec::new();
+    let mut skins = Vec::new();
+    // Skinned meshes would need their levels skinned too
+    let simplifying = assets.simplifying().filter(|_| gltf.skins().len() == 0);
+    for (node, global) in nodes.iter().zip(&rest_globals) {
+        let Some(mesh) = node.mesh() else {
+            continue;
+        };
+        let name = mesh.name().unwrap_or(file_name);
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                tracing::warn!(
+                    "Skipping {} primitive drawn as {:?}",
+                    name,
+                    primitive.mode()
+                );
+                continue;
+            }
+            let reader = primitive.reader(buffer_data);
+            let positions = reader
+                .read_positions()
+                .with_context(|| format!("{} has a primitive without positions", name))?
+                .collect::<Vec<_>>();
+            let normals = reader.read_normals().map(Iterator::collect::<Vec<_>>);
+            let tex_coords = reader
+                .read_tex_coords(0)
+                .map(|tex_coords| tex_coords.into_f32().collect::<Vec<_>>());
+            let mut indices = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            let mut vertices = positions
+                .iter()
+                .enumerate()
+                .map(|(i, &position)| model::ModelVertex {
+                    position,
+                    // glTF already has its UV origin in the top left
+                    tex_coords: tex_coords.as_ref().map_or([0.0; 2], |t| t[i]),
+                    normal: normals.as_ref().map_or([0.0; 3], |n| n[i]),
+                    tangent: [0.0; 4], // Filled in below
+                })
+                .collect::<Vec<_>>();
+
+            let mut skin = match (node.skin(), reader.read_joints(0), reader.read_weights(0)) {
+                (Some(skin), Some(skin_joints), Some(weights)) => Some((
+                    skin,
+                    skin_joints
+                        .into_u16()
+                        .zip(weights.into_f32())
+                        .map(|(joints, weights)| SkinVertex {
+                            joints: joints.map(u32::from),
+                            weights,
+                        })
+                        .collect::<Vec<_>>(),
+                )),
+                _ => None,
+            };
+            if let Some(angle) = assets.smoothing_angle(normals.is_some()) {
+                let origins = model::recompute_normals(&mut vertices, &mut indices, angle);
+                if let Some((_, skin_vertices)) = &mut skin {
+                    *skin_vertices = origins.iter().map(|&v| skin_vertices[v as usize]).collect();
+                }
+            }
+            if skin.is_none() {
+                model::transform_vertices(&mut vertices, *global);
+            }
+            model::compute_tangents(&mut vertices, &indices);
+
+            let usage = match (&skin, skinning) {
+                (None, _) => model::MOVABLE_VERTEX_USAGE,
+                (Some(_), true) => SKINNED_VERTEX_USAGE,
+                (Some(_), false) => wgpu::BufferUsages::VERTEX,
+            };
+            let movable = skin.is_none();
+            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{:?} Vertex Buffer", name)),
+                contents: bytemuck::cast_slice(&vertices),
+                usage,
+            });
+            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{:?} Index Buffer", name)),
+                contents: bytemuck::cast_slice(&indices),
+                usage: wgpu::BufferUsages::INDEX,
+            });
+
+            if let Some((skin, skin_vertices)) = skin {
+                let skin_joints = skin
+                    .joints()
+                    .map(|joint| joint_of_node[joint.index()])
+                    .collect::<Option<Vec<_>>>()
+                    .with_context(|| format!("{} is skinned to a joint outside the scene", name))?;
+                let inverse_bind_matrices =
+                    match skin.reader(buffer_data).read_inverse_bind_matrices() {
+                        Some(matrices) => matrices.map(cgmath::Matrix4::from).collect(),
+                        None => vec![cgmath::Matrix4::identity(); skin_joints.len()],
+                    };
+                skins.push(MeshSkin {
+                    mesh: meshes.len(),
+                    joints: skin_joints,
+                    inverse_bind_matrices,
+                    bind_pose: vertices.clone(),
+                    vertices: skin_vertices,
+                });
+            }
+            let mesh = model::Mesh {
+                name: name.to_string(),
+                vertex_buffer,
+                index_buffer,
+                num_elements: indices.len() as u32,
+                material: primitive.material().index().unwrap_or(default_material),
+                bounds: model::BoundingSphere::from_positions(
+                    vertices.iter().map(|vertex| vertex.position),
+                ),
+                aabb: model::Aabb::from_positions(vertices.iter().map(|vertex| vertex.position)),
+                rest_vertices: if movable {
+                    vertices.clone()
+                } else {
+                    Vec::new()
+                },
+                bvh: MeshBvh::new(&vertices, &indices),
+            };
+            if let Some(settings) = simplifying {
+                mesh_lods.push(simplified_lods(
+                    device, &mesh, &vertices, &indices, settings,
+                ));
+            }
+            meshes.push(mesh);
+        }
+    }
+
+    let mut clips = Vec::new();
+    for animation in gltf.animations() {
+        let mut channels = Vec::new();
+        for channel in animation.channels() {
+            let Some(joint) = joint_of_node[channel.target().node().index()] else {
+                continue;
+            };
+            let reader = channel.reader(buffer_data);
+            let Some(times) = reader.read_inputs() else {
+                continue;
+            };
+            let times = times.collect::<Vec<_>>();
+            let cubic =
+                channel.sampler().interpolation() == gltf::animation::Interpolation::CubicSpline;
+            let keyframes = match reader.read_outputs() {
+                Some(gltf::animation::util::ReadOutputs::Translations(values)) => {
+                    Keyframes::Translation(key_values(values.map(Into::into), cubic))
+                }
+                Some(gltf::animation::util::ReadOutputs::Rotations(values)) => {
+                    Keyframes::Rotation(key_values(
+                        values
+                            .into_f32()
+                            .map(|[x, y, z, w]| cgmath::Quaternion::new(w, x, y, z)),
+                        cubic,
+                    ))
+                }
+                Some(gltf::animation::util::ReadOutputs::Scales(values)) => {
+                    Keyframes::Scale(key_values(values.map(Into::into), cubic))
+                }
+                // Morph targets aren't supported
+                _ => continue,
+            };
+            let count = match &keyframes {
+                Keyframes::Translation(values) | Keyframes::Scale(values) => values.len(),
+                Keyframes::Rotation(values) => values.len(),
+            };
+            if count != times.len() {
+                tracing::warn!(
+                    "Skipping an animation channel with {} times but {} values",
+                    times.len(),
+                    count
+                );
+                continue;
+            }
+            let interpolation = match channel.sampler().interpolation() {
+                gltf::animation::Interpolation::Step => Interpolation::Step,
+                _ => Interpolation::Linear,
+            };
+            channels.push(Channel {
+                joint,
+                times,
+                interpolation,
+                keyframes,
+            });
+        }
+        let duration = channels
+            .iter()
+            .filter_map(|channel| channel.times.last().copied())
+            .fold(0.0, f32::max);
+        clips.push(AnimationClip {
+            name: animation.name().unwrap_or("Animation").to_string(),
+            duration,
+            channels,
+        });
+    }
+
+    tracing::info!(
+        "Loaded {} meshes, {} skins and {} animations from model {}",
+        meshes.len(),
+        skins.len(),
+        clips.len(),
+        file_name
+    );
+    for clip in &clips {
+        tracing::info!("  Animation {}: {:.2}s", clip.name, clip.duration);
+    }
+
+    // Empties are sockets, except the bones skins are bound to
+    let bones = gltf
+        .skins()
+        .flat_map(|skin| skin.joints().map(|joint| joint.index()).collect::<Vec<_>>())
+        .collect::<std::collections::HashSet<_>>();
+    let mut sockets = nodes
+        .iter()
+        .zip(&rest_globals)
+        .enumerate()
+        .filter(|(_, (node, _))| {
+            node.mesh().is_none()
+                && node.camera().is_none()
+                && node.children().len() == 0
+                && !bones.contains(&node.index())
+        })
+        .filter_map(|(joint, (node, global))| {
+            Some(model::Socket {
+                name: node.name()?.to_string(),
+                transform: *global,
+                joint: Some(joint),
+            })
+        })
+        .collect::<Vec<_>>();
+    // The sidecar file has the last word
+    for socket in load_sockets(file_name).await {
+        sockets.retain(|existing| existing.name != socket.name);
+        sockets.push(socket);
+    }
+
+    let rig = (!skins.is_empty()).then_some(Rig {
+        skeleton,
+        skins,
+        clips,
+    });
+    Ok((
+        model::Model {
+            meshes,
+            materials,
+            sockets,
+            lods: gather_lods(mesh_lods),
+        },
+        rig,
+    ))
+}
+
+// Cubic spline keys hold an in-tangent, the value and an out-tangent. Only the
+// values are kept and blended linearly.
+fn key_values<T>(values: impl Iterator<Item = T>, cubic: bool) -> Vec<T> {
+    if cubic {
+        values.skip(1).step_by(3).collect()
+    } else {
+        values.collect()
+    }
+}
+
+// Moves vertices from node space into model space
+// Base color and emissive are sRGB, the other maps hold data
+async fn load_gltf_material(
+    material: &gltf::Material<'_>,
+    file_name: &str,
+    buffers: &[Vec<u8>],
+    assets: &mut Assets,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::Material> {
+    let pbr = material.pbr_metallic_roughness();
+    let diffuse_texture = match load_gltf_texture(
+        pbr.base_color_texture().map(|info| info.texture()),
+        file_name,
+        buffers,
+        ColorSpace::Srgb,
+        assets,
+    )
+    .await?
+    {
+        Some(texture) => texture,
+        None => assets.white_texture(),
+    };
+    let normal_texture = match load_gltf_texture(
+        material.normal_texture().map(|normal| normal.texture()),
+        file_name,
+        buffers,
+        ColorSpace::Linear,
+        assets,
+    )
+    .await?
+    {
+        Some(texture) => texture,
+        None => assets.flat_normal_texture(),
+    };
+    let metallic_roughness_texture = match load_gltf_texture(
+        pbr.metallic_roughness_texture().map(|info| info.texture()),
+        file_name,
+        buffers,
+        ColorSpace::Linear,
+        assets,
+    )
+    .await?
+    {
+        Some(texture) => texture,
+        None => assets.white_texture(),
+    };
+    let occlusion_texture = match load_gltf_texture(
+        material
+            .occlusion_texture()
+            .map(|occlusion| occlusion.texture()),
+        file_name,
+        buffers,
+        ColorSpace::Linear,
+        assets,
+    )
+    .await?
+    {
+        Some(texture) => texture,
+        None => assets.white_texture(),
+    };
+    let emissive_texture = match load_gltf_texture(
+        material.emissive_texture().map(|info| info.texture()),
+        file_name,
+        buffers,
+        ColorSpace::Srgb,
+        assets,
+    )
+    .await?
+    {
+        Some(texture) => texture,
+        None => assets.white_texture(),
+    };
+
+    let [r, g, b] = material.emissive_factor();
+    let factors = model::MaterialUniform {
+        base_color: pbr.base_color_factor(),
+        metallic: pbr.metallic_factor(),
+        roughness: pbr.roughness_factor(),
+        occlusion_strength: material
+            .occlusion_texture()
+            .map_or(1.0, |occlusion| occlusion.strength()),
+        normal_scale: material
+            .normal_texture()
+            .map_or(1.0, |normal| normal.scale()),
+        emissive: [r, g, b, 1.0],
+    };
+
+    Ok(model::Material::new(
+        assets.device(),
+        material.name().unwrap_or("glTF material"),
+        diffuse_texture,
+        normal_texture,
+        metallic_roughness_texture,
+        occlusion_texture,
+        emissive_texture,
+        factors,
+        layout,
+    ))
+}
+
+// Images either sit in one of the model's buffers or in a file next to it
+async fn load_gltf_texture(
+    texture: Option<gltf::Texture<'_>>,
+    file_name: &str,
+    buffers: &[Vec<u8>],
+    color_space: ColorSpace,
+    assets: &mut Assets,
+) -> anyhow::Result<Option<texture::Texture>> {
+    let Some(texture) = texture else {
+        return Ok(None);
+    };
+    let image = texture.source();
+    let handle = match image.source() {
+        gltf::image::Source::View { view, .. } => {
+            let start = view.offset();
+            let buffer = buffers
+                .get(view.buffer().index())
+                .context("glTF image is in a missing buffer")?;
+            assets.load_texture_from_memory(
+                &format!("{}#image{}", file_name, image.index()),
+                &buffer[start..start + view.length()],
+                color_space,
+            )?
+        }
+        gltf::image::Source::Uri { uri, .. } => {
+            let dir = std::path::Path::new(file_name)
+                .parent()
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_default();
+            assets
+                .load_texture(&relative_path(&dir, uri), color_space)
+                .await?
+        }
+    };
+    Ok(Some(assets.texture(handle).clone()))
+}
@@ -0,0 +1,407 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context};
+use cgmath::prelude::*;
+
+use crate::camera_path::CameraKeyframe;
+use crate::light::Light;
+use crate::resources;
+use crate::sequencer::SequenceEvent;
+
+// Where something goes: a position and a turn in degrees around X, then Y,
+// then Z, the same as sockets
+#[derive(Debug, Copy, Clone, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct SceneTransform {
+    pub position: [f32; 3],
+    pub rotation: [f32; 3],
+}
+
+impl SceneTransform {
+    pub fn quaternion(&self) -> cgmath::Quaternion<f32> {
+        let [x, y, z] = self.rotation;
+        cgmath::Quaternion::from_angle_z(cgmath::Deg(z))
+            * cgmath::Quaternion::from_angle_y(cgmath::Deg(y))
+            * cgmath::Quaternion::from_angle_x(cgmath::Deg(x))
+    }
+
+    pub fn matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(self.position.into())
+            * cgmath::Matrix4::from(self.quaternion())
+    }
+}
+
+// Rows of copies on the XZ plane, centered on the model's transform
+#[derive(Debug, Copy, Clone, serde::Deserialize)]
+pub struct InstanceGrid {
+    pub count: [u32; 2],
+    pub spacing: f32,
+    // Degrees each copy is turned around the line out from the middle to it
+    #[serde(default)]
+    pub twist: f32,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct SceneModel {
+    // Relative to res/
+    pub path: String,
+    // Loaded instead when `path` can't be
+    #[serde(default)]
+    pub fallback: Option<String>,
+    #[serde(default)]
+    pub transform: SceneTransform,
+    // None for a single copy at the transform
+    #[serde(default)]
+    pub grid: Option<InstanceGrid>,
+}
+
+impl SceneModel {
+    // Position and rotation of every copy
+    pub fn instances(&self) -> Vec<(cgmath::Vector3<f32>, cgmath::Quaternion<f32>)> {
+        let position = cgmath::Vector3::from(self.transform.position);
+        let rotation = self.transform.quaternion();
+        let Some(grid) = self.grid else {
+            return vec![(position, rotation)];
+        };
+        let [columns, rows] = grid.count;
+        (0..rows)
+            .flat_map(|z| {
+                (0..columns).map(move |x| {
+                    let offset = cgmath::Vector3::new(
+                        grid.spacing * (x as f32 - columns as f32 / 2.0),
+                        0.0,
+                        grid.spacing * (z as f32 - rows as f32 / 2.0),
+                    );
+                    let twist = if offset.is_zero() {
+                        cgmath::Quaternion::one()
+                    } else {
+                        cgmath::Quaternion::from_axis_angle(
+                            offset.normalize(),
+                            cgmath::Deg(grid.twist),
+                        )
+                    };
+                    (position + offset, twist * rotation)
+                })
+            })
+            .collect()
+    }
+}
+
+// Angles in degrees, spots aim at a point rather than along a direction
+#[derive(Debug, Clone, serde::Deserialize)]
+pub enum SceneLight {
+    Point {
+        position: [f32; 3],
+        color: [f32; 3],
+        intensity: f32,
+        range: f32,
+    },
+    Spot {
+        position: [f32; 3],
+        target: [f32; 3],
+        color: [f32; 3],
+        intensity: f32,
+        range: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+    Directional {
+        direction: [f32; 3],
+        color: [f32; 3],
+        intensity: f32,
+    },
+}
+
+impl SceneLight {
+    pub fn to_light(&self) -> Light {
+        match *self {
+            SceneLight::Point {
+                position,
+                color,
+                intensity,
+                range,
+            } => Light::point(position.into(), color, intensity, range),
+            SceneLight::Spot {
+                position,
+                target,
+                color,
+                intensity,
+                range,
+                inner_angle,
+                outer_angle,
+            } => {
+                let mut light = Light::spot(
+                    position.into(),
+                    -cgmath::Vector3::unit_y(),
+                    color,
+                    intensity,
+                    range,
+                    cgmath::Deg(inner_angle),
+                    cgmath::Deg(outer_angle),
+                );
+                light.look_at(target.into());
+                light
+            }
+            SceneLight::Directional {
+                direction,
+                color,
+                intensity,
+            } => Light::directional(
+                cgmath::Vector3::from(direction).normalize(),
+                color,
+                intensity,
+            ),
+        }
+    }
+
+    // The light with its position and direction moved by `placement`
+    pub fn to_light_at(&self, placement: cgmath::Matrix4<f32>) -> Light {
+        let mut light = self.to_light();
+        light.position = placement
+            .transform_point(cgmath::Point3::from_vec(light.position))
+            .to_vec();
+        light.direction = placement.transform_vector(light.direction);
+        light
+    }
+}
+
+// Where the main camera starts
+#[derive(Debug, Copy, Clone, serde::Deserialize)]
+#[serde(default)]
+pub struct SceneCamera {
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+    pub fovy: f32, // Degrees
+    pub znear: f32,
+    pub zfar: f32,
+    // Looks at the model from far enough back to fit all of it in, instead
+    // of from `eye`, whenever it loads
+    pub frame_model: bool,
+    // Seconds the camera takes to get halfway to where it's moved or
+    // turned, 0 to follow the input exactly
+    pub position_damping: f32,
+    pub rotation_damping: f32,
+}
+
+impl Default for SceneCamera {
+    fn default() -> Self {
+        Self {
+            eye: [0.0, 1.0, 2.0],
+            target: [0.0, 0.0, 0.0],
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+            frame_model: false,
+            position_damping: 0.08,
+            rotation_damping: 0.05,
+        }
+    }
+}
+
+// A fire. On a socket of the scene's model it follows the socket around,
+// `position` is then an offset from it rather than a place in the world.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default)]
+pub struct SceneEmitter {
+    pub socket: Option<String>,
+    pub position: [f32; 3],
+    // Whether the fire lights its surroundings while it burns
+    pub light: bool,
+}
+
+impl Default for SceneEmitter {
+    fn default() -> Self {
+        Self {
+            socket: None,
+            position: [0.0; 3],
+            light: true,
+        }
+    }
+}
+
+// ===== TERRAIN =====
+// Ground made from a grayscale heightmap in res/, `size` across on X and Z
+// and centered on `position`. Black is at `position`'s height, white
+// `height` above it.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default)]
+pub struct SceneTerrain {
+    pub heightmap: String,
+    // Tiled across the ground, plain white without one
+    pub texture: Option<String>,
+    pub position: [f32; 3],
+    pub size: f32,
+    pub height: f32,
+    // Pieces a side, each culled on its own
+    pub chunks: u32,
+    // Squares a side in each chunk
+    pub resolution: u32,
+    // World units the texture repeats over
+    pub tile: f32,
+}
+
+impl Default for SceneTerrain {
+    fn default() -> Self {
+        Self {
+            heightmap: "terrain/heightmap.png".to_string(),
+            texture: None,
+            position: [0.0; 3],
+            size: 64.0,
+            height: 4.0,
+            chunks: 8,
+            resolution: 16,
+            tile: 4.0,
+        }
+    }
+}
+
+// ===== WATER =====
+// A flat square of water centered on `position`, `size` across
+#[derive(Debug, Copy, Clone, serde::Deserialize)]
+#[serde(default)]
+pub struct SceneWater {
+    pub position: [f32; 3],
+    pub size: f32,
+    // What's under the water fades into this the deeper it is
+    pub color: [f32; 3],
+    // Depth it's faded halfway at
+    pub clarity: f32,
+    // World units the waves repeat over, and how fast they move in units a second
+    pub wave_scale: f32,
+    pub wave_speed: f32,
+}
+
+impl Default for SceneWater {
+    fn default() -> Self {
+        Self {
+            position: [0.0; 3],
+            size: 16.0,
+            color: [0.02, 0.1, 0.12],
+            clarity: 0.5,
+            wave_scale: 3.0,
+            wave_speed: 0.25,
+        }
+    }
+}
+
+// ===== GRASS =====
+// Blades scattered over a square centered on `center` on the XZ plane,
+// standing on the terrain when there is one
+#[derive(Debug, Copy, Clone, serde::Deserialize)]
+#[serde(default)]
+pub struct SceneGrass {
+    pub count: u32,
+    pub center: [f32; 2],
+    pub size: f32,
+    // The same seed scatters them the same way every time
+    pub seed: u64,
+    // Of the tallest blades, the rest are up to half as short
+    pub height: f32,
+    pub width: f32,
+    pub color: [f32; 3],
+    // Ground steeper than this, 0 for flat to 1 for a wall, stays bare
+    pub max_slope: f32,
+    // How far the tips lean in the wind, in world units, and how fast
+    // the gusts come round
+    pub wind_strength: f32,
+    pub wind_speed: f32,
+    pub wind_direction: [f32; 2],
+    // Whether blades an ember lands near are burnt down, and how near
+    pub burn: bool,
+    pub burn_radius: f32,
+}
+
+impl Default for SceneGrass {
+    fn default() -> Self {
+        Self {
+            count: 20000,
+            center: [0.0; 2],
+            size: 32.0,
+            seed: 1,
+            height: 0.5,
+            width: 0.06,
+            color: [0.22, 0.4, 0.08],
+            max_slope: 0.35,
+            wind_strength: 0.15,
+            wind_speed: 1.5,
+            wind_direction: [1.0, 0.3],
+            burn: true,
+            burn_radius: 0.4,
+        }
+    }
+}
+
+// ===== PREFABS =====
+// A template stamped into the scene as often as needed, like a torch or a
+// brazier. Everything in it is placed relative to where it's spawned.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct Prefab {
+    // Only the scene's own model is drawn, a copy of it goes in when it's this
+    pub model: Option<String>,
+    pub emitters: Vec<SceneEmitter>,
+    pub lights: Vec<SceneLight>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct PrefabSpawn {
+    pub prefab: String,
+    #[serde(default)]
+    pub transform: SceneTransform,
+}
+
+// ===== SCENE FILES =====
+// What's in the scene as data rather than code: the models and where they
+// go, the lights, where the camera starts and the fires. Written in RON or
+// JSON, told apart by the file's extension.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Scene {
+    pub models: Vec<SceneModel>,
+    #[serde(default)]
+    pub lights: Vec<SceneLight>,
+    #[serde(default)]
+    pub camera: SceneCamera,
+    #[serde(default)]
+    pub emitters: Vec<SceneEmitter>,
+    // By name, sorted so they're always listed in the same order
+    #[serde(default)]
+    pub prefabs: BTreeMap<String, Prefab>,
+    // Prefabs placed when the scene loads
+    #[serde(default)]
+    pub spawns: Vec<PrefabSpawn>,
+    // Models and prefabs stand on it, their heights are above the ground
+    #[serde(default)]
+    pub terrain: Option<SceneTerrain>,
+    #[serde(default)]
+    pub water: Option<SceneWater>,
+    #[serde(default)]
+    pub grass: Option<SceneGrass>,
+    // The path camera's flythrough, F8 saves one recorded in the app
+    #[serde(default)]
+    pub camera_path: Vec<CameraKeyframe>,
+    // A scripted demo of camera cuts and effects, played with F9
+    #[serde(default)]
+    pub sequence: Vec<SequenceEvent>,
+}
+
+impl Scene {
+    // A .ron or .json file in res/
+    pub async fn load(file_name: &str) -> anyhow::Result<Self> {
+        let text = resources::load_string(file_name).await?;
+        let extension = std::path::Path::new(file_name)
+            .extension()
+            .and_then(|extension| extension.to_str());
+        let scene: Self = match extension {
+            Some("ron") => {
+                ron::from_str(&text).with_context(|| format!("Couldn't parse {}", file_name))?
+            }
+            Some("json") => serde_json::from_str(&text)
+                .with_context(|| format!("Couldn't parse {}", file_name))?,
+            _ => bail!("{} isn't a .ron or .json scene", file_name),
+        };
+        if scene.models.is_empty() {
+            bail!("{} has no models", file_name);
+        }
+        Ok(scene)
+    }
+}
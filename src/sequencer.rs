@@ -0,0 +1,92 @@
+// Something a sequence does when its time comes
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub enum SequenceAction {
+    // Cuts to the camera with that name, path cameras start from the top
+    Cut(String),
+    // Flames on or off
+    Fire(bool),
+    // Knocks the camera about by that much trauma
+    Shake(f32),
+    // Starts the rig's clip with that name over
+    Animation(String),
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct SequenceEvent {
+    // Seconds from the start
+    pub time: f32,
+    pub action: SequenceAction,
+}
+
+// ===== SEQUENCER =====
+// Plays a scripted timeline once through, handing back each action as its
+// time comes. Events at the same time come out in the order they were
+// written.
+#[derive(Debug, Clone, Default)]
+pub struct Sequencer {
+    // In time order
+    events: Vec<SequenceEvent>,
+    playing: bool,
+    time: f32,
+    // The first event still to come
+    next: usize,
+}
+
+impl Sequencer {
+    pub fn new(mut events: Vec<SequenceEvent>) -> Self {
+        // Stable, so ties keep their order
+        events.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self {
+            events,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.events.last().map_or(0.0, |event| event.time)
+    }
+
+    // From the start
+    pub fn play(&mut self) {
+        self.playing = true;
+        self.time = 0.0;
+        self.next = 0;
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = false;
+    }
+
+    // Advances by `dt` seconds, returning what's come due since the last
+    // update. Stops after the last event.
+    pub fn update(&mut self, dt: f32) -> Vec<SequenceAction> {
+        if !self.playing {
+            return Vec::new();
+        }
+        self.time += dt;
+        let first = self.next;
+        self.next += self.events[first..]
+            .iter()
+            .take_while(|event| event.time <= self.time)
+            .count();
+        if self.next == self.events.len() {
+            self.playing = false;
+        }
+        self.events[first..self.next]
+            .iter()
+            .map(|event| event.action.clone())
+            .collect()
+    }
+}
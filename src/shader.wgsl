@@ -1,3 +1,13 @@
+// Lighting and material functions and bindings are shared with the deferred
+// path. PACKED_MATERIALS picks the `MaterialArray` bindings.
+#include "lighting.wgsl"
+#ifdef PACKED_MATERIALS
+#include "material_array.wgsl"
+#else
+#include "material.wgsl"
+#endif
+#include "camera.wgsl"
+
 // Vertex shader
 struct InstanceInput {
     @location(5) model_matrix_0: vec4<f32>,
@@ -6,22 +16,51 @@ struct InstanceInput {
     @location(8) model_matrix_3: vec4<f32>,
 };
 
-struct CameraUniform {
-    view_proj: mat4x4<f32>,
-};
 @group(1) @binding(0) // 1.
 var<uniform> camera: CameraUniform;
 
+// Blurred screen-space ambient occlusion from the SSAO pass
+@group(3) @binding(0)
+var t_ssao: texture_2d<f32>;
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
-    @location(1) tex_coords: vec2<f32>
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec4<f32>,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) world_normal: vec3<f32>,
+    @location(3) view_depth: f32,
+    @location(4) world_tangent: vec4<f32>,
+    // This and last frame's clip positions, for the velocity target
+    @location(5) curr_clip: vec4<f32>,
+    @location(6) prev_clip: vec4<f32>,
 };
 
+// Color plus how far the pixel moved on screen since the last frame and how
+// far away it is
+struct SceneOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) velocity: vec4<f32>,
+    @location(2) linear_depth: f32,
+};
+
+fn scene_output(color: vec4<f32>, in: VertexOutput) -> SceneOutput {
+    // NDC to UV: halve, and flip y since UVs point down
+    let curr = in.curr_clip.xy / in.curr_clip.w;
+    let prev = in.prev_clip.xy / in.prev_clip.w;
+    var out: SceneOutput;
+    out.color = color;
+    out.velocity = vec4<f32>((curr - prev) * vec2<f32>(0.5, -0.5), 0.0, 0.0);
+    out.linear_depth = in.view_depth;
+    return out;
+}
+
 @vertex
 fn vs_main(
     model: VertexInput,
@@ -33,19 +72,56 @@ fn vs_main(
         instance.model_matrix_2,
         instance.model_matrix_3,
     );
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
-    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    out.world_position = world_position.xyz;
+    // Instances only rotate and translate, so the model matrix works for normals too
+    out.world_normal = (model_matrix * vec4<f32>(model.normal, 0.0)).xyz;
+    out.world_tangent = vec4<f32>((model_matrix * vec4<f32>(model.tangent.xyz, 0.0)).xyz, model.tangent.w);
+    // Right-handed view space looks down -Z
+    out.view_depth = -(camera.view * world_position).z;
+    out.clip_position = camera.view_proj * world_position;
+    out.curr_clip = out.clip_position;
+    out.prev_clip = camera.prev_view_proj * world_position;
     return out;
 }
 
 // Fragment shader
-@group(0) @binding(0)
-var t_diffuse: texture_2d<f32>;
-@group(0) @binding(1)
-var s_diffuse: sampler;
+@fragment
+fn fs_main(in: VertexOutput) -> SceneOutput {
+    var surface = sample_material(in.tex_coords, in.world_normal, in.world_tangent);
+    // SSAO only darkens the ambient term, same as the material's own occlusion
+    surface.occlusion *= textureLoad(t_ssao, vec2<i32>(in.clip_position.xy), 0).r;
+    let color = shade(surface, in.world_position, camera.view_position.xyz, in.view_depth);
+    return scene_output(vec4<f32>(color, surface.alpha), in);
+}
+
+// ===== DEBUG VIEWS =====
+// Swapped in for fs_main by the shading mode toggle
+
+@fragment
+fn fs_wireframe(in: VertexOutput) -> SceneOutput {
+    return scene_output(vec4<f32>(0.1, 1.0, 0.3, 1.0), in);
+}
+
+@fragment
+fn fs_normals(in: VertexOutput) -> SceneOutput {
+    return scene_output(vec4<f32>(normalize(in.world_normal) * 0.5 + 0.5, 1.0), in);
+}
+
+@fragment
+fn fs_tex_coords(in: VertexOutput) -> SceneOutput {
+    return scene_output(vec4<f32>(fract(in.tex_coords), 0.0, 1.0), in);
+}
 
+// Lit with the triangle's own normal, so every facet of the mesh shows
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+fn fs_flat(in: VertexOutput) -> SceneOutput {
+    var surface = sample_material(in.tex_coords, in.world_normal, in.world_tangent);
+    // Screen-space y points down, hence dpdy first
+    surface.normal = normalize(cross(dpdy(in.world_position), dpdx(in.world_position)));
+    let color = shade(surface, in.world_position, camera.view_position.xyz, in.view_depth);
+    return scene_output(vec4<f32>(color, surface.alpha), in);
 }
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::preprocess::Preprocessor;
+
+// What sets one pipeline variant apart: the preprocessor flags its shader is
+// compiled with, plus any pipeline state in the key itself
+pub trait VariantKey: Clone + Eq + Hash {
+    fn flags(&self) -> Vec<&'static str>;
+}
+
+// ===== SHADER VARIANTS =====
+// A keyed pipeline cache over one shader file. Each set of flags compiles the
+// shader once and each key builds its pipeline once, the first time it's asked
+// for, and the cached ones are reused after that. Features become flags on one
+// shader instead of a hand-written pipeline per combination.
+pub struct ShaderVariants<K> {
+    label: &'static str,
+    file: &'static str,
+    // Has the defines every variant shares
+    preprocessor: Preprocessor,
+    modules: HashMap<Vec<&'static str>, wgpu::ShaderModule>,
+    pipelines: HashMap<K, wgpu::RenderPipeline>,
+}
+
+impl<K: VariantKey> ShaderVariants<K> {
+    pub fn new(label: &'static str, file: &'static str, preprocessor: Preprocessor) -> Self {
+        Self {
+            label,
+            file,
+            preprocessor,
+            modules: HashMap::new(),
+            pipelines: HashMap::new(),
+        }
+    }
+
+    // The pipeline for `key`, built by `create` from the variant's shader when
+    // it isn't cached yet. Nothing is cached when the shader or pipeline fails.
+    pub fn get(
+        &mut self,
+        device: &wgpu::Device,
+        key: &K,
+        create: impl FnOnce(&wgpu::ShaderModule, &K) -> wgpu::RenderPipeline,
+    ) -> anyhow::Result<wgpu::RenderPipeline> {
+        if let Some(pipeline) = self.pipelines.get(key) {
+            return Ok(pipeline.clone());
+        }
+
+        let mut flags = key.flags();
+        flags.sort_unstable();
+        let module = match self.modules.get(&flags) {
+            Some(module) => module.clone(),
+            None => {
+                let mut preprocessor = self.preprocessor.clone();
+                for flag in &flags {
+                    preprocessor.define(flag, "");
+                }
+                let source = preprocessor.process(self.file)?;
+                let module = validated(device, || {
+                    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                        label: Some(self.label),
+                        source: wgpu::ShaderSource::Wgsl(source.into()),
+                    })
+                })?;
+                tracing::info!("Compiled {} with {:?}", self.label, flags);
+                self.modules.insert(flags, module.clone());
+                module
+            }
+        };
+
+        let pipeline = validated(device, || create(&module, key))?;
+        self.pipelines.insert(key.clone(), pipeline.clone());
+        Ok(pipeline)
+    }
+}
+
+// Catches validation errors where there's an error scope to wait on, the web
+// build can't block on one
+fn validated<T>(device: &wgpu::Device, create: impl FnOnce() -> T) -> anyhow::Result<T> {
+    #[cfg(not(target_arch = "wasm32"))]
+    return Ok(crate::hot_reload::catch_validation(device, create)?);
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = device;
+        Ok(create())
+    }
+}
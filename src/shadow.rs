@@ -0,0 +1,333 @@
+use cgmath::prelude::*;
+use wgpu::util::DeviceExt;
+
+use crate::model::{DrawGeometry, Model};
+use crate::uniform_ring::UniformRing;
+use crate::{Camera, OPENGL_TO_WGPU_MATRIX};
+
+// ===== CASCADE SETTINGS =====
+pub const NUM_CASCADES: usize = 4;
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+pub const SHADOW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+// ===== SHADOW UNIFORM =====
+// Everything the lighting shaders need to pick a cascade and look it up
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ShadowUniform {
+    pub light_view_proj: [[[f32; 4]; 4]; NUM_CASCADES],
+    pub cascade_splits: [f32; NUM_CASCADES], // View-space far distance of each cascade
+}
+
+// Per-cascade uniform used by the depth-only shadow pass
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CascadeUniform {
+    light_view_proj: [[f32; 4]; 4],
+}
+
+// ===== CASCADED SHADOW MAPS =====
+// The shadow maps themselves are bound by `light::Lighting` alongside the lights
+pub struct ShadowCascades {
+    pub max_distance: f32, // Shadows are only rendered up to this view distance
+    pub split_lambda: f32, // 0.0 = uniform splits, 1.0 = logarithmic splits
+
+    uniform: ShadowUniform,
+    pub uniform_buffer: wgpu::Buffer,
+    // Every cascade's matrix in one buffer, picked by dynamic offset
+    cascade_uniforms: UniformRing,
+    cascade_offsets: [u32; NUM_CASCADES],
+
+    #[allow(unused)]
+    texture: wgpu::Texture,
+    layer_views: Vec<wgpu::TextureView>,
+    pub array_view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ShadowCascades {
+    pub fn new(device: &wgpu::Device, vertex_layouts: &[wgpu::VertexBufferLayout]) -> Self {
+        // ===== SHADOW MAP TEXTURE ARRAY =====
+        // One layer per cascade
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shadow Cascades"),
+            size: wgpu::Extent3d {
+                width: SHADOW_MAP_SIZE,
+                height: SHADOW_MAP_SIZE,
+                depth_or_array_layers: NUM_CASCADES as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: SHADOW_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let array_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Shadow Cascades Array View"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        // Each cascade is rendered into its own layer
+        let layer_views = (0..NUM_CASCADES as u32)
+            .map(|i| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Shadow Cascade Layer View"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: i,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        // ===== SHADOW UNIFORM (read by the model shader) =====
+        let uniform = ShadowUniform {
+            light_view_proj: [cgmath::Matrix4::identity().into(); NUM_CASCADES],
+            cascade_splits: [0.0; NUM_CASCADES],
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // ===== PER-CASCADE UNIFORMS (read by the shadow pass) =====
+        let cascade_bind_group_layout = UniformRing::create_bind_group_layout(
+            device,
+            wgpu::ShaderStages::VERTEX,
+            "shadow_cascade_bind_group_layout",
+        );
+        let cascade_uniforms = UniformRing::new::<CascadeUniform>(
+            device,
+            &cascade_bind_group_layout,
+            "Shadow Cascade Buffer",
+            NUM_CASCADES as u32,
+        );
+
+        // ===== DEPTH-ONLY PIPELINE =====
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shadow Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shadow.wgsl").into()),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&cascade_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: vertex_layouts,
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: None, // Depth only
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: SHADOW_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                // Slope-scaled bias keeps the lit side free of shadow acne
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            max_distance: 60.0,
+            split_lambda: 0.75,
+            uniform,
+            uniform_buffer,
+            cascade_uniforms,
+            cascade_offsets: [0; NUM_CASCADES],
+            texture,
+            layer_views,
+            array_view,
+            sampler,
+            pipeline,
+        }
+    }
+
+    // Split the view frustum into NUM_CASCADES ranges using the "practical" split
+    // scheme: a blend between uniform and logarithmic distribution.
+    fn compute_splits(&self, near: f32, far: f32) -> [f32; NUM_CASCADES] {
+        let mut splits = [0.0; NUM_CASCADES];
+        for (i, split) in splits.iter_mut().enumerate() {
+            let p = (i + 1) as f32 / NUM_CASCADES as f32;
+            let log = near * (far / near).powf(p);
+            let uniform = near + (far - near) * p;
+            *split = self.split_lambda * log + (1.0 - self.split_lambda) * uniform;
+        }
+        splits
+    }
+
+    // Fit an orthographic light matrix around the bounding sphere of one frustum slice
+    fn cascade_matrix(
+        &self,
+        camera: &Camera,
+        light_direction: cgmath::Vector3<f32>,
+        near: f32,
+        far: f32,
+    ) -> cgmath::Matrix4<f32> {
+        let view = cgmath::Matrix4::look_at_rh(camera.eye, camera.target, camera.up);
+        let proj = camera.projection_between(near, far);
+        let inv_view_proj = (OPENGL_TO_WGPU_MATRIX * proj * view)
+            .invert()
+            .unwrap_or_else(cgmath::Matrix4::identity);
+
+        // Corners of the slice in world space (wgpu NDC has z in 0..1)
+        let mut corners = Vec::with_capacity(8);
+        for x in [-1.0, 1.0] {
+            for y in [-1.0, 1.0] {
+                for z in [0.0, 1.0] {
+                    let p = inv_view_proj * cgmath::Vector4::new(x, y, z, 1.0);
+                    corners.push(p.truncate() / p.w);
+                }
+            }
+        }
+
+        let center = corners
+            .iter()
+            .fold(cgmath::Vector3::zero(), |acc, c| acc + c)
+            / corners.len() as f32;
+        let radius = corners
+            .iter()
+            .map(|c| (c - center).magnitude())
+            .fold(0.0_f32, f32::max);
+        // Round the radius so the projection size doesn't change as the camera rotates
+        let radius = (radius * 16.0).ceil() / 16.0;
+
+        let direction = light_direction.normalize();
+        let up = if direction.y.abs() > 0.99 {
+            cgmath::Vector3::unit_z()
+        } else {
+            cgmath::Vector3::unit_y()
+        };
+
+        // Pull the light back far enough to catch casters outside the slice
+        let eye = cgmath::Point3::from_vec(center - direction * radius * 2.0);
+        let light_view = cgmath::Matrix4::look_at_rh(eye, cgmath::Point3::from_vec(center), up);
+        let light_proj = cgmath::ortho(-radius, radius, -radius, radius, 0.0, radius * 4.0);
+        let mut light_view_proj = OPENGL_TO_WGPU_MATRIX * light_proj * light_view;
+
+        // Snap the origin to whole shadow-map texels to stop shimmering edges
+        let origin = light_view_proj * cgmath::Vector4::new(0.0, 0.0, 0.0, 1.0);
+        let half_size = SHADOW_MAP_SIZE as f32 * 0.5;
+        let offset_x = (origin.x * half_size).round() / half_size - origin.x;
+        let offset_y = (origin.y * half_size).round() / half_size - origin.y;
+        light_view_proj =
+            cgmath::Matrix4::from_translation(cgmath::Vector3::new(offset_x, offset_y, 0.0))
+                * light_view_proj;
+
+        light_view_proj
+    }
+
+    // Recompute cascade matrices from the current camera and upload them
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera: &Camera,
+        light_direction: cgmath::Vector3<f32>,
+    ) {
+        let near = camera.znear;
+        let far = camera.zfar.min(self.max_distance);
+        let splits = self.compute_splits(near, far);
+
+        let mut slice_near = near;
+        self.cascade_uniforms.reset();
+        for (i, &slice_far) in splits.iter().enumerate() {
+            let matrix: [[f32; 4]; 4] = self
+                .cascade_matrix(camera, light_direction, slice_near, slice_far)
+                .into();
+            self.uniform.light_view_proj[i] = matrix;
+            self.cascade_offsets[i] = self.cascade_uniforms.push(&CascadeUniform {
+                light_view_proj: matrix,
+            });
+            slice_near = slice_far;
+        }
+        self.cascade_uniforms.upload(device, queue);
+
+        self.uniform.cascade_splits = splits;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+    }
+
+    // Render the scene depth into every cascade layer
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &Model,
+        instance_buffer: &wgpu::Buffer,
+        num_instances: u32,
+    ) {
+        for (i, (layer_view, &offset)) in self
+            .layer_views
+            .iter()
+            .zip(&self.cascade_offsets)
+            .enumerate()
+        {
+            encoder.push_debug_group(&format!("Cascade {}", i));
+            let mut shadow_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: layer_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+
+            shadow_pass.set_pipeline(&self.pipeline);
+            shadow_pass.set_bind_group(0, self.cascade_uniforms.bind_group(), &[offset]);
+            shadow_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            shadow_pass.draw_model_geometry_instanced(model, 0..num_instances);
+            drop(shadow_pass);
+            encoder.pop_debug_group();
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use std::f32::consts::{PI, TAU};
+
+use cgmath::prelude::*;
+use cgmath::Vector3;
+use wgpu::util::DeviceExt;
+
+use crate::model::{self, Material, Mesh, Model, ModelVertex};
+use crate::raycast::MeshBvh;
+
+// ===== PRIMITIVE SHAPES =====
+// Meshes made in code, for prototyping and debug geometry. Their vertices
+// have normals, UVs and tangents like loaded ones, so they draw with the
+// model pipeline and its materials. Each is centered on the origin and one
+// unit across, scale them with `model::transform_vertices`.
+#[derive(Clone, Debug, Default)]
+pub struct Shape {
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl Shape {
+    // Each face a quad with the whole texture on it, right way up from the
+    // sides
+    pub fn cube() -> Self {
+        let mut shape = Self::default();
+        for (normal, down) in [
+            (Vector3::unit_x(), -Vector3::unit_y()),
+            (-Vector3::unit_x(), -Vector3::unit_y()),
+            (Vector3::unit_y(), Vector3::unit_z()),
+            (-Vector3::unit_y(), -Vector3::unit_z()),
+            (Vector3::unit_z(), -Vector3::unit_y()),
+            (-Vector3::unit_z(), -Vector3::unit_y()),
+        ] {
+            let right = normal.cross(down);
+            shape.add_surface(1, 1, |u, v| {
+                let position = normal * 0.5 + right * (u - 0.5) + down * (v - 0.5);
+                (position, normal, [u, v])
+            });
+        }
+        shape.finish()
+    }
+
+    // `segments` around the equator, `rings` from pole to pole
+    pub fn uv_sphere(segments: u32, rings: u32) -> Self {
+        let mut shape = Self::default();
+        shape.add_surface(segments.max(3), rings.max(2), |u, v| {
+            let (longitude, latitude) = (u * TAU, v * PI);
+            let normal = Vector3::new(
+                latitude.sin() * longitude.cos(),
+                latitude.cos(),
+                latitude.sin() * longitude.sin(),
+            );
+            (normal * 0.5, normal, [u, v])
+        });
+        shape.finish()
+    }
+
+    // Flat on the XZ plane facing up, split into `subdivisions` squares a side
+    pub fn plane(subdivisions: u32) -> Self {
+        let mut shape = Self::default();
+        let subdivisions = subdivisions.max(1);
+        shape.add_surface(subdivisions, subdivisions, |u, v| {
+            (
+                Vector3::new(u - 0.5, 0.0, v - 0.5),
+                Vector3::unit_y(),
+                [u, v],
+            )
+        });
+        shape.finish()
+    }
+
+    // Standing on the Y axis with flat caps, `segments` around
+    pub fn cylinder(segments: u32) -> Self {
+        let mut shape = Self::default();
+        let segments = segments.max(3);
+        let around = |u: f32| Vector3::new((u * TAU).cos(), 0.0, (u * TAU).sin());
+        shape.add_surface(segments, 1, |u, v| {
+            let normal = around(u);
+            (normal * 0.5 + Vector3::unit_y() * (0.5 - v), normal, [u, v])
+        });
+        // The caps go from the middle out to the rim, the texture laid flat
+        for normal in [Vector3::unit_y(), -Vector3::unit_y()] {
+            shape.add_surface(segments, 1, |u, v| {
+                let position = around(u) * 0.5 * v + normal * 0.5;
+                (position, normal, [position.x + 0.5, position.z + 0.5])
+            });
+        }
+        shape.finish()
+    }
+
+    // Lying on the XZ plane, `segments` around the ring and `sides` around
+    // the tube. `tube_radius` is out of the 0.5 to the outer edge.
+    pub fn torus(segments: u32, sides: u32, tube_radius: f32) -> Self {
+        let mut shape = Self::default();
+        let ring_radius = 0.5 - tube_radius;
+        shape.add_surface(segments.max(3), sides.max(3), |u, v| {
+            let outward = Vector3::new((u * TAU).cos(), 0.0, (u * TAU).sin());
+            let normal = outward * (v * TAU).cos() + Vector3::unit_y() * (v * TAU).sin();
+            (outward * ring_radius + normal * tube_radius, normal, [u, v])
+        });
+        shape.finish()
+    }
+
+    // A grid of `columns` by `rows` quads, `point` giving the position,
+    // normal and texture coordinates at (u, v) across it from 0 to 1.
+    // Triangles are wound to face the way the normals do, the ones that
+    // collapse into a line at a pole are left out.
+    fn add_surface(
+        &mut self,
+        columns: u32,
+        rows: u32,
+        point: impl Fn(f32, f32) -> (Vector3<f32>, Vector3<f32>, [f32; 2]),
+    ) {
+        let first = self.vertices.len() as u32;
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let (position, normal, tex_coords) =
+                    point(column as f32 / columns as f32, row as f32 / rows as f32);
+                self.vertices.push(ModelVertex {
+                    position: position.into(),
+                    tex_coords,
+                    normal: normal.into(),
+                    tangent: [0.0; 4], // Filled in by `finish`
+                });
+            }
+        }
+        let index = |column: u32, row: u32| first + row * (columns + 1) + column;
+        for row in 0..rows {
+            for column in 0..columns {
+                let corners = [
+                    index(column, row),
+                    index(column + 1, row),
+                    index(column, row + 1),
+                    index(column + 1, row + 1),
+                ];
+                for [a, b, c] in [
+                    [corners[0], corners[1], corners[2]],
+                    [corners[1], corners[3], corners[2]],
+                ] {
+                    self.add_triangle(a, b, c);
+                }
+            }
+        }
+    }
+
+    fn add_triangle(&mut self, a: u32, b: u32, c: u32) {
+        let [pa, pb, pc] = [a, b, c].map(|i| Vector3::from(self.vertices[i as usize].position));
+        let face = (pb - pa).cross(pc - pa);
+        if face.magnitude2() < 1e-12 {
+            return;
+        }
+        let normal = [a, b, c]
+            .iter()
+            .map(|&i| Vector3::from(self.vertices[i as usize].normal))
+            .sum::<Vector3<f32>>();
+        if face.dot(normal) >= 0.0 {
+            self.indices.extend([a, b, c]);
+        } else {
+            self.indices.extend([a, c, b]);
+        }
+    }
+
+    fn finish(mut self) -> Self {
+        model::compute_tangents(&mut self.vertices, &self.indices);
+        self
+    }
+
+    pub fn to_mesh(&self, device: &wgpu::Device, name: &str, material: usize) -> Mesh {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Vertex Buffer", name)),
+            contents: bytemuck::cast_slice(&self.vertices),
+            usage: model::MOVABLE_VERTEX_USAGE,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Index Buffer", name)),
+            contents: bytemuck::cast_slice(&self.indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let positions = || self.vertices.iter().map(|vertex| vertex.position);
+        Mesh {
+            name: name.to_string(),
+            vertex_buffer,
+            index_buffer,
+            num_elements: self.indices.len() as u32,
+            material,
+            bounds: model::BoundingSphere::from_positions(positions()),
+            aabb: model::Aabb::from_positions(positions()),
+            rest_vertices: self.vertices.clone(),
+            bvh: MeshBvh::new(&self.vertices, &self.indices),
+        }
+    }
+
+    // A model of just this shape in one material
+    pub fn to_model(&self, device: &wgpu::Device, name: &str, material: Material) -> Model {
+        Model {
+            meshes: vec![self.to_mesh(device, name, 0)],
+            materials: vec![material],
+            sockets: Vec::new(),
+            lods: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,366 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use cgmath::prelude::*;
+use cgmath::Vector3;
+
+use crate::model::ModelVertex;
+
+// How levels of detail get generated for models that don't come with their own
+#[derive(Copy, Clone, Debug)]
+pub struct LodSettings {
+    // Levels past the model itself
+    pub levels: usize,
+    // The share of the previous level's triangles each level keeps
+    pub ratio: f32,
+}
+
+// Sum of squared distances to a set of planes, as the upper half of a
+// symmetric 4x4 matrix
+#[derive(Copy, Clone, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(normal: Vector3<f64>, distance: f64, weight: f64) -> Self {
+        let [a, b, c, d] = [normal.x, normal.y, normal.z, distance];
+        Self([
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ])
+        .scaled(weight)
+    }
+
+    fn scaled(self, weight: f64) -> Self {
+        Self(self.0.map(|q| q * weight))
+    }
+
+    fn add(&mut self, other: &Self) {
+        for (q, other) in self.0.iter_mut().zip(other.0) {
+            *q += other;
+        }
+    }
+
+    fn error(&self, p: Vector3<f64>) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (p.x, p.y, p.z);
+        q[0] * x * x
+            + 2.0 * (q[1] * x * y + q[2] * x * z + q[3] * x)
+            + q[4] * y * y
+            + 2.0 * (q[5] * y * z + q[6] * y)
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9]
+    }
+}
+
+// Moving the vertex `from` onto its neighbor `to`, cheapest first
+struct Collapse {
+    cost: f64,
+    from: usize,
+    to: usize,
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+// ===== MESH SIMPLIFICATION =====
+// Quadric error edge collapse (Garland and Heckbert). Vertices only ever move
+// onto a neighbor, never to a new position, so the result indexes the same
+// vertices and can share their buffer. Vertices split along UV seams are
+// welded by position and collapse together, border vertices stay put so
+// openings keep their outline. Stops at `target_triangles`, or earlier when
+// every collapse left would flip a triangle or pinch the surface.
+pub fn simplify(vertices: &[ModelVertex], indices: &[u32], target_triangles: usize) -> Vec<u32> {
+    let mut welded = HashMap::new();
+    let point_of = vertices
+        .iter()
+        .map(|vertex| {
+            let next = welded.len();
+            *welded
+                .entry(vertex.position.map(f32::to_bits))
+                .or_insert(next)
+        })
+        .collect::<Vec<_>>();
+    let point_count = welded.len();
+    let mut positions = vec![Vector3::zero(); point_count];
+    let mut vertices_at = vec![Vec::new(); point_count];
+    for (vertex, &point) in point_of.iter().enumerate() {
+        positions[point] = Vector3::from(vertices[vertex].position).cast().unwrap();
+        vertices_at[point].push(vertex);
+    }
+
+    let corners = indices
+        .chunks_exact(3)
+        .map(|t| [t[0], t[1], t[2]])
+        .collect::<Vec<_>>();
+    let mut triangles = corners
+        .iter()
+        .map(|t| t.map(|vertex| point_of[vertex as usize]))
+        .collect::<Vec<_>>();
+    let mut alive = triangles
+        .iter()
+        .map(|[a, b, c]| a != b && b != c && a != c)
+        .collect::<Vec<_>>();
+    let mut remaining = alive.iter().filter(|&&alive| alive).count();
+    if remaining <= target_triangles {
+        return indices.to_vec();
+    }
+
+    let mut quadrics = vec![Quadric::default(); point_count];
+    let mut around = vec![Vec::new(); point_count];
+    let mut edges = HashMap::<(usize, usize), u32>::new();
+    for (t, triangle) in triangles.iter().enumerate().filter(|(t, _)| alive[*t]) {
+        let [a, b, c] = triangle.map(|point| positions[point]);
+        let normal = (b - a).cross(c - a);
+        let area = normal.magnitude();
+        if area > 0.0 {
+            let normal = normal / area;
+            let plane = Quadric::from_plane(normal, -normal.dot(a), area);
+            for &point in triangle {
+                quadrics[point].add(&plane);
+            }
+        }
+        for i in 0..3 {
+            let (p, q) = (triangle[i], triangle[(i + 1) % 3]);
+            around[p].push(t);
+            *edges.entry((p.min(q), p.max(q))).or_default() += 1;
+        }
+    }
+    let mut locked = vec![false; point_count];
+    for (&(a, b), &count) in &edges {
+        if count == 1 {
+            locked[a] = true;
+            locked[b] = true;
+        }
+    }
+
+    let cost = |quadrics: &[Quadric], from: usize, to: usize| {
+        let mut quadric = quadrics[from];
+        quadric.add(&quadrics[to]);
+        quadric.error(positions[to])
+    };
+    let mut heap = BinaryHeap::new();
+    for &(a, b) in edges.keys() {
+        for (from, to) in [(a, b), (b, a)] {
+            if !locked[from] {
+                heap.push(Collapse {
+                    cost: cost(&quadrics, from, to),
+                    from,
+                    to,
+                });
+            }
+        }
+    }
+
+    let mut collapsed = vec![false; point_count];
+    while remaining > target_triangles {
+        let Some(collapse) = heap.pop() else {
+            break;
+        };
+        let (from, to) = (collapse.from, collapse.to);
+        if collapsed[from] || collapsed[to] {
+            continue;
+        }
+        // Costs only grow as quadrics merge, an outdated one goes back in line
+        let current = cost(&quadrics, from, to);
+        if current > collapse.cost * (1.0 + 1e-9) + 1e-12 {
+            heap.push(Collapse {
+                cost: current,
+                from,
+                to,
+            });
+            continue;
+        }
+
+        around[from].retain(|&t| alive[t]);
+        let shared = around[from]
+            .iter()
+            .filter(|&&t| triangles[t].contains(&to))
+            .count();
+        if shared == 0 {
+            continue; // No longer neighbors
+        }
+        // More common neighbors than triangles on the edge would fold the
+        // surface onto itself
+        let to_neighbors = neighbors(to, &around, &triangles, &alive);
+        let common = neighbors(from, &around, &triangles, &alive)
+            .iter()
+            .filter(|point| to_neighbors.binary_search(point).is_ok())
+            .count();
+        if common > shared {
+            continue;
+        }
+        let flips = around[from]
+            .iter()
+            .filter(|&&t| !triangles[t].contains(&to))
+            .any(|&t| {
+                let before = triangles[t].map(|point| positions[point]);
+                let after =
+                    triangles[t].map(|point| positions[if point == from { to } else { point }]);
+                let normal = |[a, b, c]: [Vector3<f64>; 3]| (b - a).cross(c - a);
+                normal(before).dot(normal(after)) <= 0.0
+            });
+        if flips {
+            continue;
+        }
+
+        collapsed[from] = true;
+        let quadric = quadrics[from];
+        quadrics[to].add(&quadric);
+        for t in std::mem::take(&mut around[from]) {
+            if triangles[t].contains(&to) {
+                alive[t] = false;
+                remaining -= 1;
+            } else {
+                for point in &mut triangles[t] {
+                    if *point == from {
+                        *point = to;
+                    }
+                }
+                around[to].push(t);
+            }
+        }
+        for point in neighbors(to, &around, &triangles, &alive) {
+            for (from, to) in [(point, to), (to, point)] {
+                if !locked[from] {
+                    heap.push(Collapse {
+                        cost: cost(&quadrics, from, to),
+                        from,
+                        to,
+                    });
+                }
+            }
+        }
+    }
+
+    // A corner that moved takes whichever vertex at its new position has the
+    // closest texture coordinates, so UV seams stay seams
+    let uv = |vertex: usize| cgmath::Vector2::from(vertices[vertex].tex_coords);
+    let mut simplified = Vec::with_capacity(remaining * 3);
+    for (t, triangle) in triangles.iter().enumerate().filter(|(t, _)| alive[*t]) {
+        for (&corner, &point) in corners[t].iter().zip(triangle) {
+            let corner = corner as usize;
+            let vertex = if point_of[corner] == point {
+                corner
+            } else {
+                *vertices_at[point]
+                    .iter()
+                    .min_by(|&&a, &&b| {
+                        let distance = |v| (uv(v) - uv(corner)).magnitude2();
+                        distance(a).total_cmp(&distance(b))
+                    })
+                    .unwrap()
+            };
+            simplified.push(vertex as u32);
+        }
+    }
+    simplified
+}
+
+// The points sharing a live triangle with `point`, sorted
+fn neighbors(
+    point: usize,
+    around: &[Vec<usize>],
+    triangles: &[[usize; 3]],
+    alive: &[bool],
+) -> Vec<usize> {
+    let mut neighbors = around[point]
+        .iter()
+        .filter(|&&t| alive[t])
+        .flat_map(|&t| triangles[t])
+        .filter(|&other| other != point)
+        .collect::<Vec<_>>();
+    neighbors.sort_unstable();
+    neighbors.dedup();
+    neighbors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A flat `n` by `n` grid of quads facing +z
+    fn grid(n: u32) -> (Vec<ModelVertex>, Vec<u32>) {
+        let vertices = (0..=n)
+            .flat_map(|y| (0..=n).map(move |x| (x, y)))
+            .map(|(x, y)| ModelVertex {
+                position: [x as f32, y as f32, 0.0],
+                tex_coords: [x as f32 / n as f32, y as f32 / n as f32],
+                normal: [0.0, 0.0, 1.0],
+                tangent: [1.0, 0.0, 0.0, 1.0],
+            })
+            .collect();
+        let index = |x, y| y * (n + 1) + x;
+        let indices = (0..n)
+            .flat_map(|y| (0..n).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                [
+                    index(x, y),
+                    index(x + 1, y),
+                    index(x + 1, y + 1),
+                    index(x, y),
+                    index(x + 1, y + 1),
+                    index(x, y + 1),
+                ]
+            })
+            .collect();
+        (vertices, indices)
+    }
+
+    #[test]
+    fn already_small_enough_is_unchanged() {
+        let (vertices, indices) = grid(2);
+        assert_eq!(simplify(&vertices, &indices, 8), indices);
+    }
+
+    #[test]
+    fn grid_collapses_without_flipping_or_moving_the_border() {
+        let (vertices, indices) = grid(4);
+        let simplified = simplify(&vertices, &indices, 8);
+        let triangles = simplified.len() / 3;
+        assert_eq!(simplified.len() % 3, 0);
+        assert!(triangles < indices.len() / 3 && triangles >= 8);
+
+        let position = |i: u32| Vector3::from(vertices[i as usize].position);
+        for t in simplified.chunks_exact(3) {
+            let [a, b, c] = [t[0], t[1], t[2]].map(position);
+            assert!((b - a).cross(c - a).z > 0.0, "flipped {:?}", t);
+        }
+        // Every vertex on the edge of the grid is still there
+        for (i, vertex) in vertices.iter().enumerate() {
+            let [x, y, _] = vertex.position;
+            if x == 0.0 || y == 0.0 || x == 4.0 || y == 4.0 {
+                assert!(
+                    simplified.contains(&(i as u32)),
+                    "lost {:?}",
+                    vertex.position
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use wgpu::util::DeviceExt;
+
+use crate::model::{Mesh, ModelVertex};
+use crate::upload::Uploads;
+
+// Threads per workgroup, matches skinning.wgsl
+const WORKGROUP_SIZE: u32 = 64;
+
+// A skinned mesh's vertex buffer is written by the skinning pass as well as drawn from
+pub const SKINNED_VERTEX_USAGE: wgpu::BufferUsages =
+    wgpu::BufferUsages::VERTEX.union(wgpu::BufferUsages::STORAGE);
+
+// Up to four joints per vertex. Weights should add up to 1, a vertex with none
+// stays in its bind pose.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkinVertex {
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
+}
+
+// ===== COMPUTE SKINNING =====
+// Skins meshes on the GPU before anything draws them. A compute pass blends
+// each vertex's bind pose by its joint matrices and writes the result into the
+// mesh's own vertex buffer, so the shadow, SSAO, G-buffer and forward passes
+// all draw the skinned vertices through the normal model pipeline, and the
+// skinning is done once per frame instead of once per pass.
+pub struct Skinning {
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl Skinning {
+    pub fn is_supported(adapter: &wgpu::Adapter, device: &wgpu::Device) -> bool {
+        let downlevel = adapter.get_downlevel_capabilities().flags;
+        downlevel.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+            && device.limits().max_storage_buffers_per_shader_stage >= 4
+    }
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                // Bind pose vertices
+                storage(0, true),
+                // Joints and weights
+                storage(1, true),
+                // Joint matrices
+                storage(2, true),
+                // Skinned vertices
+                storage(3, false),
+            ],
+            label: Some("Skinning Bind Group Layout"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Skinning Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("skinning.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skinning Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Skinning Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("skin_main"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        Self {
+            bind_group_layout,
+            pipeline,
+        }
+    }
+
+    // Skins every mesh in one compute pass
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, meshes: &[SkinnedMesh]) {
+        if meshes.is_empty() {
+            return;
+        }
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Skinning Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        for mesh in meshes {
+            pass.set_bind_group(0, &mesh.bind_group, &[]);
+            pass.dispatch_workgroups(mesh.vertex_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+    }
+}
+
+// The GPU side of one skinned mesh: its bind pose, skin weights and joint
+// matrices, bound to write into the mesh's vertex buffer
+pub struct SkinnedMesh {
+    bind_group: wgpu::BindGroup,
+    joint_buffer: wgpu::Buffer,
+    vertex_count: u32,
+    joint_count: usize,
+}
+
+impl SkinnedMesh {
+    // `mesh.vertex_buffer` has to be created with `SKINNED_VERTEX_USAGE`.
+    // `bind_pose` and `skin` have one entry per vertex of the mesh.
+    pub fn new(
+        device: &wgpu::Device,
+        skinning: &Skinning,
+        mesh: &Mesh,
+        bind_pose: &[ModelVertex],
+        skin: &[SkinVertex],
+        joint_count: usize,
+    ) -> Self {
+        assert!(
+            mesh.vertex_buffer.usage().contains(SKINNED_VERTEX_USAGE),
+            "{} can't be skinned, its vertex buffer isn't a storage buffer",
+            mesh.name
+        );
+        assert_eq!(bind_pose.len(), skin.len());
+        assert_eq!(
+            mesh.vertex_buffer.size(),
+            std::mem::size_of_val(bind_pose) as u64
+        );
+
+        let bind_pose_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Bind Pose", mesh.name)),
+            contents: bytemuck::cast_slice(bind_pose),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let skin_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Skin", mesh.name)),
+            contents: bytemuck::cast_slice(skin),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        // Starts out in the bind pose
+        let identity: [[f32; 4]; 4] = cgmath::Matrix4::from_scale(1.0).into();
+        let joint_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Joints", mesh.name)),
+            contents: bytemuck::cast_slice(&vec![identity; joint_count.max(1)]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &skinning.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: bind_pose_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: skin_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: joint_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: mesh.vertex_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some(&format!("{} Skinning Bind Group", mesh.name)),
+        });
+
+        Self {
+            bind_group,
+            joint_buffer,
+            vertex_count: skin.len() as u32,
+            joint_count,
+        }
+    }
+
+    // One matrix per joint, its model-space transform times its inverse bind matrix
+    pub fn update_joints(&self, uploads: &mut Uploads, joints: &[cgmath::Matrix4<f32>]) {
+        assert_eq!(joints.len(), self.joint_count);
+        let joints = joints
+            .iter()
+            .map(|&joint| joint.into())
+            .collect::<Vec<[[f32; 4]; 4]>>();
+        uploads.write(&self.joint_buffer, 0, &joints);
+    }
+}
@@ -0,0 +1,66 @@
+// ===== COMPUTE SKINNING =====
+// One thread per vertex, blending its bind pose by up to four joint matrices.
+// Vertices are `ModelVertex` read and written as plain floats, 12 per vertex:
+// position (0..3), tex_coords (3..5), normal (5..8) and tangent (8..12).
+
+const STRIDE: u32 = 12u;
+
+struct SkinVertex {
+    joints: vec4<u32>,
+    weights: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<storage, read> bind_pose: array<f32>;
+@group(0) @binding(1)
+var<storage, read> skin: array<SkinVertex>;
+// Joint transform times inverse bind matrix, model space
+@group(0) @binding(2)
+var<storage, read> joints: array<mat4x4<f32>>;
+// The mesh's own vertex buffer
+@group(0) @binding(3)
+var<storage, read_write> skinned: array<f32>;
+
+fn read_vec3(base: u32) -> vec3<f32> {
+    return vec3<f32>(bind_pose[base], bind_pose[base + 1u], bind_pose[base + 2u]);
+}
+
+fn write_vec3(base: u32, v: vec3<f32>) {
+    skinned[base] = v.x;
+    skinned[base + 1u] = v.y;
+    skinned[base + 2u] = v.z;
+}
+
+@compute @workgroup_size(64)
+fn skin_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let vertex = id.x;
+    if (vertex >= arrayLength(&skin)) {
+        return;
+    }
+    let s = skin[vertex];
+    var transform = joints[s.joints.x] * s.weights.x
+        + joints[s.joints.y] * s.weights.y
+        + joints[s.joints.z] * s.weights.z
+        + joints[s.joints.w] * s.weights.w;
+    // Vertices without weights stay in their bind pose
+    if (dot(s.weights, vec4<f32>(1.0)) <= 0.0) {
+        transform = mat4x4<f32>(
+            vec4<f32>(1.0, 0.0, 0.0, 0.0),
+            vec4<f32>(0.0, 1.0, 0.0, 0.0),
+            vec4<f32>(0.0, 0.0, 1.0, 0.0),
+            vec4<f32>(0.0, 0.0, 0.0, 1.0),
+        );
+    }
+
+    let base = vertex * STRIDE;
+    let position = (transform * vec4<f32>(read_vec3(base), 1.0)).xyz;
+    let normal = normalize((transform * vec4<f32>(read_vec3(base + 5u), 0.0)).xyz);
+    let tangent = normalize((transform * vec4<f32>(read_vec3(base + 8u), 0.0)).xyz);
+
+    write_vec3(base, position);
+    skinned[base + 3u] = bind_pose[base + 3u];
+    skinned[base + 4u] = bind_pose[base + 4u];
+    write_vec3(base + 5u, normal);
+    write_vec3(base + 8u, tangent);
+    skinned[base + 11u] = bind_pose[base + 11u];
+}
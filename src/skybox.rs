@@ -0,0 +1,219 @@
+use cgmath::prelude::*;
+
+use crate::{color, hdr::HdrPipeline, texture};
+
+const GRADIENT_FACE_SIZE: u32 = 64;
+
+// ===== SKYBOX =====
+// Draws a cubemap behind the scene instead of a flat clear color. Rendered in
+// the main pass after the opaque geometry so only background pixels pay for it.
+pub struct Skybox {
+    #[allow(unused)]
+    pub cubemap: texture::Texture,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+    color_format: wgpu::TextureFormat,
+    depth_mode: texture::DepthMode,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Skybox {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        cubemap: texture::Texture,
+        depth_mode: texture::DepthMode,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("skybox_bind_group_layout"),
+        });
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &cubemap);
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Skybox Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("skybox.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout, camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(
+            device,
+            &pipeline_layout,
+            &shader,
+            color_format,
+            sample_count,
+            depth_mode,
+        );
+
+        Self {
+            cubemap,
+            bind_group_layout,
+            bind_group,
+            pipeline_layout,
+            shader,
+            color_format,
+            depth_mode,
+            pipeline,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_mode: texture::DepthMode,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Skybox Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: Some("vs_main"),
+                buffers: &[], // Fullscreen triangle is generated in the shader
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &depth_mode.shader_constants(),
+                    ..Default::default()
+                },
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: Some("fs_main"),
+                targets: &HdrPipeline::scene_targets(color_format, Some(wgpu::BlendState::REPLACE)),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                // The sky sits exactly on the cleared far depth
+                depth_compare: depth_mode.compare_or_equal(),
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        cubemap: &texture::Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&cubemap.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&cubemap.sampler),
+                },
+            ],
+            label: Some("skybox_bind_group"),
+        })
+    }
+
+    // A new cubemap only needs a new bind group, the pipeline stays
+    pub fn set_cubemap(&mut self, device: &wgpu::Device, cubemap: texture::Texture) {
+        self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, &cubemap);
+        self.cubemap = cubemap;
+    }
+
+    // MSAA changes need a new pipeline, the cubemap is kept
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            &self.pipeline_layout,
+            &self.shader,
+            self.color_format,
+            sample_count,
+            self.depth_mode,
+        );
+    }
+
+    // Simple sky-to-ground gradient, used when no cubemap images are available
+    pub fn gradient_cubemap(device: &wgpu::Device, queue: &wgpu::Queue) -> texture::Texture {
+        let zenith = cgmath::Vector3::new(0.18, 0.36, 0.75);
+        let horizon = cgmath::Vector3::new(0.75, 0.82, 0.9);
+        let ground = cgmath::Vector3::new(0.25, 0.22, 0.2);
+
+        // Direction through each texel, following the +X, -X, +Y, -Y, +Z, -Z face order
+        let face_direction = |face: usize, u: f32, v: f32| match face {
+            0 => cgmath::Vector3::new(1.0, -v, -u),
+            1 => cgmath::Vector3::new(-1.0, -v, u),
+            2 => cgmath::Vector3::new(u, 1.0, v),
+            3 => cgmath::Vector3::new(u, -1.0, -v),
+            4 => cgmath::Vector3::new(u, -v, 1.0),
+            _ => cgmath::Vector3::new(-u, -v, -1.0),
+        };
+
+        let faces = (0..6)
+            .map(|face| {
+                let image =
+                    image::RgbaImage::from_fn(GRADIENT_FACE_SIZE, GRADIENT_FACE_SIZE, |x, y| {
+                        let u = (x as f32 + 0.5) / GRADIENT_FACE_SIZE as f32 * 2.0 - 1.0;
+                        let v = (y as f32 + 0.5) / GRADIENT_FACE_SIZE as f32 * 2.0 - 1.0;
+                        let height = face_direction(face, u, v).normalize().y;
+                        let color = if height >= 0.0 {
+                            horizon.lerp(zenith, height.sqrt())
+                        } else {
+                            horizon.lerp(ground, (-height * 4.0).min(1.0))
+                        };
+                        // The cubemap is sRGB, so encode the linear color
+                        let [r, g, b] = color::encode_srgb8(color.into());
+                        image::Rgba([r, g, b, 255])
+                    });
+                image::DynamicImage::ImageRgba8(image)
+            })
+            .collect::<Vec<_>>();
+
+        // Six equal square faces, this can't fail
+        texture::Texture::from_cube_faces(device, queue, &faces, Some("Gradient Skybox")).unwrap()
+    }
+
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+        crate::stats::count_draw();
+    }
+}
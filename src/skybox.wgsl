@@ -0,0 +1,67 @@
+// ===== SKYBOX =====
+// Fullscreen triangle at the far plane. Each pixel looks up the cubemap along
+// its view ray, and the LessEqual (GreaterEqual with reverse-Z) depth test
+// keeps it behind everything else.
+
+// Depth of the far plane, 0.0 with reverse-Z (`DepthMode::shader_constants`)
+override FAR_DEPTH: f32 = 1.0;
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(0) @binding(0)
+var t_sky: texture_cube<f32>;
+@group(0) @binding(1)
+var s_sky: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    let ndc = uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    var out: VertexOutput;
+    // Puts every pixel exactly on the far plane
+    out.clip_position = vec4<f32>(ndc, FAR_DEPTH, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) velocity: vec4<f32>,
+    @location(2) linear_depth: f32,
+};
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    // Along the ray through the pixel, between the clip planes, so
+    // orthographic views look straight ahead too
+    let a = camera.inv_view_proj * vec4<f32>(in.ndc, 0.0, 1.0);
+    let b = camera.inv_view_proj * vec4<f32>(in.ndc, 1.0, 1.0);
+    var direction = a.xyz / a.w - b.xyz / b.w;
+    if (dot(direction, camera.view_position.xyz - a.xyz / a.w) > 0.0) {
+        direction = -direction; // Standard depth has the planes the other way round
+    }
+    // The sky is infinitely far away, so only the camera's rotation moves it.
+    // Orthographic views put directions at infinity, they get no velocity.
+    let prev = camera.prev_view_proj * vec4<f32>(direction, 0.0);
+    let prev_ndc = select(in.ndc, prev.xy / prev.w, abs(prev.w) > 1e-6);
+    var out: FragmentOutput;
+    out.color = vec4<f32>(textureSample(t_sky, s_sky, direction).rgb, 1.0);
+    out.velocity = vec4<f32>((in.ndc - prev_ndc) * vec2<f32>(0.5, -0.5), 0.0, 0.0);
+    out.linear_depth = 0.0; // Infinitely far
+    return out;
+}
@@ -0,0 +1,262 @@
+use crate::atlas::{AtlasBuilder, AtlasRegion, TextureAtlas};
+use crate::color::ColorSpace;
+use crate::overlay::OverlayPipeline;
+
+// Sprites drawn at most in a frame, anything past this is left off
+const MAX_SPRITES: usize = 1024;
+// Built-in sprites every layer's atlas has alongside the caller's
+pub const WHITE: &str = "white";
+pub const CROSSHAIR: &str = "crosshair";
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SpriteUniform {
+    screen_size: [f32; 2],
+    _padding: [f32; 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Sprite {
+    // x, y, width, height in pixels from the top left
+    rect: [f32; 4],
+    // min.xy, max.xy of the sprite in the atlas
+    uv: [f32; 4],
+    color: [f32; 4],
+}
+
+impl Sprite {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 3] =
+        wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4, 2 => Float32x4];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Sprite>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+// A thin plus with a gap in the middle, white so it takes the tint it's drawn with
+fn crosshair_image() -> image::DynamicImage {
+    const SIZE: u32 = 17;
+    const CENTER: i32 = SIZE as i32 / 2;
+    image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(SIZE, SIZE, |x, y| {
+        let (dx, dy) = (x as i32 - CENTER, y as i32 - CENTER);
+        let arm = (dx == 0 && dy.abs() > 2) || (dy == 0 && dx.abs() > 2);
+        image::Rgba(if arm { [255; 4] } else { [255, 255, 255, 0] })
+    }))
+}
+
+// ===== SPRITE LAYER =====
+// 2D sprites and HUD elements drawn over the finished frame. Positions are in
+// pixels from the top left of the surface, the projection is a plain
+// orthographic one, and every sprite comes out of one atlas so the whole layer
+// is a single instanced draw. Sprites queued during the frame are drawn and
+// cleared by `render`.
+pub struct SpriteLayer {
+    atlas: TextureAtlas,
+    white: AtlasRegion,
+    sprites: Vec<Sprite>,
+    uniform_buffer: wgpu::Buffer,
+    sprite_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: OverlayPipeline,
+}
+
+impl SpriteLayer {
+    // Packs the builder's sprites, plus `WHITE` and `CROSSHAIR`, into the
+    // layer's atlas
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        mut atlas: AtlasBuilder,
+    ) -> anyhow::Result<Self> {
+        atlas.add(
+            WHITE,
+            &image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+                1,
+                1,
+                image::Rgba([255; 4]),
+            )),
+        );
+        atlas.add(CROSSHAIR, &crosshair_image());
+        let atlas = atlas.build(device, queue, "Sprite Atlas", ColorSpace::Srgb)?;
+        // Just added, so it's there
+        let white = atlas.region(WHITE).unwrap();
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sprite Uniform Buffer"),
+            size: std::mem::size_of::<SpriteUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let sprite_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sprite Instance Buffer"),
+            size: (std::mem::size_of::<Sprite>() * MAX_SPRITES) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("sprite_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&atlas.texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&atlas.texture.sampler),
+                },
+            ],
+            label: Some("sprite_bind_group"),
+        });
+
+        let pipeline = OverlayPipeline::new(
+            device,
+            format,
+            "Sprite",
+            include_str!("sprites.wgsl"),
+            &[&bind_group_layout],
+            &[Sprite::desc()],
+            wgpu::PrimitiveTopology::TriangleList,
+        );
+
+        Ok(Self {
+            atlas,
+            white,
+            sprites: Vec::new(),
+            uniform_buffer,
+            sprite_buffer,
+            bind_group,
+            pipeline,
+        })
+    }
+
+    pub fn region(&self, name: &str) -> Option<AtlasRegion> {
+        self.atlas.region(name)
+    }
+
+    fn push(&mut self, rect: [f32; 4], region: AtlasRegion, color: [f32; 4]) {
+        if self.sprites.len() < MAX_SPRITES {
+            let color = self.pipeline.color(color);
+            self.sprites.push(Sprite {
+                rect,
+                uv: region.to_vec4(),
+                color,
+            });
+        }
+    }
+
+    // A filled rectangle, x, y, width, height in pixels from the top left
+    pub fn rect(&mut self, rect: [f32; 4], color: [f32; 4]) {
+        self.push(rect, self.white, color);
+    }
+
+    // The atlas sprite `name` stretched over `rect` and tinted by `color`.
+    // Names the atlas doesn't have are skipped with a warning
+    pub fn sprite(&mut self, name: &str, rect: [f32; 4], color: [f32; 4]) {
+        match self.atlas.region(name) {
+            Some(region) => self.push(rect, region, color),
+            None => tracing::warn!("No sprite {} in the atlas", name),
+        }
+    }
+
+    // `name` at its size in the atlas, centered on `center`
+    pub fn sprite_centered(&mut self, name: &str, center: [f32; 2], color: [f32; 4]) {
+        if let Some(region) = self.atlas.region(name) {
+            let size = self.atlas.size() as f32;
+            let width = (region.max[0] - region.min[0]) * size;
+            let height = (region.max[1] - region.min[1]) * size;
+            self.push(
+                [
+                    (center[0] - width / 2.0).round(),
+                    (center[1] - height / 2.0).round(),
+                    width,
+                    height,
+                ],
+                region,
+                color,
+            );
+        } else {
+            tracing::warn!("No sprite {} in the atlas", name);
+        }
+    }
+
+    // A bar `fill` of the way full, 0 to 1, with a frame and background
+    pub fn bar(&mut self, rect: [f32; 4], fill: f32, color: [f32; 4]) {
+        let [x, y, width, height] = rect;
+        self.rect(
+            [x - 1.0, y - 1.0, width + 2.0, height + 2.0],
+            [1.0, 1.0, 1.0, 0.6],
+        );
+        self.rect(rect, [0.0, 0.0, 0.0, 0.6]);
+        self.rect([x, y, width * fill.clamp(0.0, 1.0), height], color);
+    }
+
+    // Draws everything queued since the last call over `output`, which is
+    // `width` by `height`, then clears the queue
+    pub fn render(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        if self.sprites.is_empty() {
+            return;
+        }
+        let uniform = SpriteUniform {
+            screen_size: [width.max(1) as f32, height.max(1) as f32],
+            _padding: [0.0; 2],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+        queue.write_buffer(&self.sprite_buffer, 0, bytemuck::cast_slice(&self.sprites));
+
+        let mut pass = self.pipeline.begin_pass(encoder, "Sprite Pass", output);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.sprite_buffer.slice(..));
+        pass.draw(0..6, 0..self.sprites.len() as u32);
+        crate::stats::count_draw();
+        self.sprites.clear();
+    }
+}
@@ -0,0 +1,56 @@
+// ===== SPRITE LAYER =====
+// Atlas sprites on top of the finished frame. Every sprite is an instance with
+// a rectangle in pixels and where it sits in the atlas; the projection is
+// orthographic, pixels straight to clip space.
+
+struct SpriteUniform {
+    screen_size: vec2<f32>,
+    _padding: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> layer: SpriteUniform;
+@group(0) @binding(1)
+var atlas: texture_2d<f32>;
+@group(0) @binding(2)
+var atlas_sampler: sampler;
+
+struct SpriteInput {
+    // x, y, width, height in pixels from the top left
+    @location(0) rect: vec4<f32>,
+    // min.xy, max.xy in the atlas
+    @location(1) uv: vec4<f32>,
+    @location(2) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, sprite: SpriteInput) -> VertexOutput {
+    // Two triangles, corners 0 1 2 and 2 1 3
+    let corner = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+    )[index];
+    let pixel = sprite.rect.xy + corner * sprite.rect.zw;
+    let ndc = pixel / layer.screen_size * 2.0 - 1.0;
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc.x, -ndc.y, 0.0, 1.0);
+    out.uv = mix(sprite.uv.xy, sprite.uv.zw, corner);
+    out.color = sprite.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // The top level only, the smaller mips blur neighbouring sprites together
+    return textureSampleLevel(atlas, atlas_sampler, in.uv, 0.0) * in.color;
+}
@@ -0,0 +1,496 @@
+use std::ops::Range;
+
+use cgmath::prelude::*;
+use rand::Rng;
+use wgpu::util::DeviceExt;
+
+use crate::model::{DrawGeometry, Model};
+use crate::texture;
+
+pub const KERNEL_SIZE: usize = 16;
+const NORMAL_DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const AO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsaoUniform {
+    kernel: [[f32; 4]; KERNEL_SIZE],
+    radius: f32,
+    bias: f32,
+    intensity: f32,
+    _padding: f32,
+}
+
+// Sample points in the +Z hemisphere, packed closer to the center so nearby
+// occluders count more than distant ones
+fn generate_kernel() -> [[f32; 4]; KERNEL_SIZE] {
+    let mut rng = rand::rng();
+    let mut kernel = [[0.0; 4]; KERNEL_SIZE];
+    for (i, sample) in kernel.iter_mut().enumerate() {
+        let direction = cgmath::Vector3::new(
+            rng.random_range(-1.0..1.0),
+            rng.random_range(-1.0..1.0),
+            rng.random_range(0.05..1.0),
+        )
+        .normalize();
+        let t = i as f32 / KERNEL_SIZE as f32;
+        let scale = 0.1 + 0.9 * t * t;
+        let point = direction * rng.random_range(0.0..1.0f32).max(0.1) * scale;
+        *sample = [point.x, point.y, point.z, 0.0];
+    }
+    kernel
+}
+
+// ===== SSAO TARGETS =====
+// Everything that follows the window size
+struct SsaoTargets {
+    normal_depth: wgpu::TextureView,
+    depth: wgpu::TextureView, // Prepass depth testing only, never sampled
+    raw_ao: wgpu::TextureView,
+    ao: wgpu::TextureView,
+    // All white, for views the SSAO wasn't computed for
+    unoccluded: wgpu::TextureView,
+}
+
+impl SsaoTargets {
+    fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let create_target = |format: wgpu::TextureFormat, label: &str| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width: config.width.max(1),
+                        height: config.height.max(1),
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+
+        Self {
+            normal_depth: create_target(NORMAL_DEPTH_FORMAT, "Ssao Normal Depth"),
+            depth: create_target(texture::Texture::DEPTH_FORMAT, "Ssao Depth"),
+            raw_ao: create_target(AO_FORMAT, "Ssao Raw"),
+            ao: create_target(AO_FORMAT, "Ssao Blurred"),
+            unoccluded: create_target(AO_FORMAT, "Ssao Unoccluded"),
+        }
+    }
+}
+
+// ===== SSAO =====
+// Prepass (view normals + depth) -> hemisphere sampling -> blur. The blurred
+// result is bound at group 3 so the lit shaders can darken their ambient term.
+pub struct Ssao {
+    pub radius: f32,
+    pub bias: f32,
+    pub intensity: f32,
+    kernel: [[f32; 4]; KERNEL_SIZE],
+    depth_mode: texture::DepthMode,
+
+    targets: SsaoTargets,
+    uniform_buffer: wgpu::Buffer,
+    input_bind_group_layout: wgpu::BindGroupLayout,
+    ssao_bind_group: wgpu::BindGroup,
+    blur_bind_group: wgpu::BindGroup,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+    // Same layout as `bind_group` but without any occlusion, see `clear_unoccluded`
+    pub unoccluded_bind_group: wgpu::BindGroup,
+
+    prepass_pipeline: wgpu::RenderPipeline,
+    ssao_pipeline: wgpu::RenderPipeline,
+    blur_pipeline: wgpu::RenderPipeline,
+}
+
+impl Ssao {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        camera_layout: &wgpu::BindGroupLayout,
+        vertex_layouts: &[wgpu::VertexBufferLayout],
+        depth_mode: texture::DepthMode,
+    ) -> Self {
+        let radius = 0.5;
+        let bias = 0.025;
+        let intensity = 1.0;
+        let kernel = generate_kernel();
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Ssao Buffer"),
+            contents: bytemuck::cast_slice(&[SsaoUniform {
+                kernel,
+                radius,
+                bias,
+                intensity,
+                _padding: 0.0,
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let targets = SsaoTargets::new(device, config);
+
+        // Inputs are read with textureLoad, so they don't need to be filterable
+        let input_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("ssao_input_bind_group_layout"),
+            });
+
+        // What the lit shaders see at group 3
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            }],
+            label: Some("ssao_bind_group_layout"),
+        });
+
+        let (ssao_bind_group, blur_bind_group, bind_group, unoccluded_bind_group) =
+            Self::create_bind_groups(
+                device,
+                &input_bind_group_layout,
+                &bind_group_layout,
+                &targets,
+                &uniform_buffer,
+            );
+
+        // ===== PREPASS PIPELINE =====
+        let prepass_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Ssao Prepass Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("ssao_prepass.wgsl").into()),
+        });
+        let prepass_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Ssao Prepass Pipeline Layout"),
+                bind_group_layouts: &[camera_layout],
+                push_constant_ranges: &[],
+            });
+        let prepass_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Ssao Prepass Pipeline"),
+            layout: Some(&prepass_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &prepass_shader,
+                entry_point: Some("vs_main"),
+                buffers: vertex_layouts,
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &prepass_shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: NORMAL_DEPTH_FORMAT,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: depth_mode.compare(),
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        // ===== SSAO + BLUR PIPELINES =====
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Ssao Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("ssao.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Ssao Pipeline Layout"),
+            bind_group_layouts: &[&input_bind_group_layout, camera_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |label: &str, entry_point: &str| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[], // Fullscreen triangle is generated in the shader
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: AO_FORMAT,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let ssao_pipeline = create_pipeline("Ssao Pipeline", "fs_ssao");
+        let blur_pipeline = create_pipeline("Ssao Blur Pipeline", "fs_blur");
+
+        Self {
+            radius,
+            bias,
+            intensity,
+            kernel,
+            depth_mode,
+            targets,
+            uniform_buffer,
+            input_bind_group_layout,
+            ssao_bind_group,
+            blur_bind_group,
+            bind_group_layout,
+            bind_group,
+            unoccluded_bind_group,
+            prepass_pipeline,
+            ssao_pipeline,
+            blur_pipeline,
+        }
+    }
+
+    fn create_bind_groups(
+        device: &wgpu::Device,
+        input_layout: &wgpu::BindGroupLayout,
+        output_layout: &wgpu::BindGroupLayout,
+        targets: &SsaoTargets,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> (
+        wgpu::BindGroup,
+        wgpu::BindGroup,
+        wgpu::BindGroup,
+        wgpu::BindGroup,
+    ) {
+        let input_bind_group = |view: &wgpu::TextureView, label: &str| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: input_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some(label),
+            })
+        };
+        let ssao_bind_group = input_bind_group(&targets.normal_depth, "ssao_input_bind_group");
+        let blur_bind_group = input_bind_group(&targets.raw_ao, "ssao_blur_bind_group");
+        let output_bind_group = |view: &wgpu::TextureView, label: &str| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: output_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                }],
+                label: Some(label),
+            })
+        };
+        let bind_group = output_bind_group(&targets.ao, "ssao_bind_group");
+        let unoccluded_bind_group =
+            output_bind_group(&targets.unoccluded, "ssao_unoccluded_bind_group");
+        (
+            ssao_bind_group,
+            blur_bind_group,
+            bind_group,
+            unoccluded_bind_group,
+        )
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.targets = SsaoTargets::new(device, config);
+        (
+            self.ssao_bind_group,
+            self.blur_bind_group,
+            self.bind_group,
+            self.unoccluded_bind_group,
+        ) = Self::create_bind_groups(
+            device,
+            &self.input_bind_group_layout,
+            &self.bind_group_layout,
+            &self.targets,
+            &self.uniform_buffer,
+        );
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[SsaoUniform {
+                kernel: self.kernel,
+                radius: self.radius,
+                bias: self.bias,
+                intensity: self.intensity,
+                _padding: 0.0,
+            }]),
+        );
+    }
+
+    fn fullscreen_pass(
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        pipeline: &wgpu::RenderPipeline,
+        input_bind_group: &wgpu::BindGroup,
+        camera_bind_group: &wgpu::BindGroup,
+        target: &wgpu::TextureView,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, input_bind_group, &[]);
+        pass.set_bind_group(1, camera_bind_group, &[]);
+        pass.draw(0..3, 0..1);
+        crate::stats::count_draw();
+    }
+
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        model: &Model,
+        instance_buffer: &wgpu::Buffer,
+        lods: &[Range<u32>],
+        camera_bind_group: &wgpu::BindGroup,
+    ) {
+        {
+            let mut prepass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Ssao Prepass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.targets.normal_depth,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.targets.depth,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.depth_mode.far_depth()),
+                        store: wgpu::StoreOp::Discard,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            prepass.set_pipeline(&self.prepass_pipeline);
+            prepass.set_bind_group(0, camera_bind_group, &[]);
+            prepass.set_vertex_buffer(1, instance_buffer.slice(..));
+            prepass.draw_model_geometry_lods_instanced(model, lods);
+        }
+
+        Self::fullscreen_pass(
+            encoder,
+            "Ssao Pass",
+            &self.ssao_pipeline,
+            &self.ssao_bind_group,
+            camera_bind_group,
+            &self.targets.raw_ao,
+        );
+        Self::fullscreen_pass(
+            encoder,
+            "Ssao Blur Pass",
+            &self.blur_pipeline,
+            &self.blur_bind_group,
+            camera_bind_group,
+            &self.targets.ao,
+        );
+    }
+
+    // With SSAO off the lit shaders still read the texture, so fill it with
+    // "no occlusion"
+    pub fn clear(&self, encoder: &mut wgpu::CommandEncoder) {
+        Self::clear_target(encoder, "Ssao Clear Pass", &self.targets.ao);
+    }
+
+    // Fills `unoccluded_bind_group`'s texture, for drawing from a camera the
+    // SSAO pass didn't see (like a split-screen view)
+    pub fn clear_unoccluded(&self, encoder: &mut wgpu::CommandEncoder) {
+        Self::clear_target(
+            encoder,
+            "Ssao Unoccluded Clear Pass",
+            &self.targets.unoccluded,
+        );
+    }
+
+    fn clear_target(encoder: &mut wgpu::CommandEncoder, label: &str, target: &wgpu::TextureView) {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+    }
+}
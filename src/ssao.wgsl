@@ -0,0 +1,121 @@
+// ===== SCREEN-SPACE AMBIENT OCCLUSION =====
+// fs_ssao samples a hemisphere kernel around each pixel against the prepass
+// depth; fs_blur smooths away the per-pixel kernel rotation afterwards.
+
+const KERNEL_SIZE: u32 = 16u;
+
+struct SsaoUniform {
+    kernel: array<vec4<f32>, KERNEL_SIZE>,
+    radius: f32,
+    bias: f32,
+    intensity: f32,
+};
+
+// Normal + depth for fs_ssao, the raw occlusion for fs_blur
+@group(0) @binding(0)
+var t_input: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> ssao: SsaoUniform;
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+// Three vertices covering the whole screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    return out;
+}
+
+// View-space position of a pixel from its linear depth. Found along the
+// line through the pixel between the clip planes, so it works for
+// orthographic views as well as perspective ones.
+fn view_position_at(coords: vec2<i32>, dimensions: vec2<f32>, depth: f32) -> vec3<f32> {
+    let uv = (vec2<f32>(coords) + 0.5) / dimensions;
+    let ndc = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    let a = camera.inv_proj * vec4<f32>(ndc, 0.0, 1.0);
+    let b = camera.inv_proj * vec4<f32>(ndc, 1.0, 1.0);
+    let start = a.xyz / a.w;
+    let end = b.xyz / b.w;
+    return mix(start, end, (-depth - start.z) / (end.z - start.z));
+}
+
+@fragment
+fn fs_ssao(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coords = vec2<i32>(in.clip_position.xy);
+    let dimensions = vec2<f32>(textureDimensions(t_input));
+    let normal_depth = textureLoad(t_input, coords, 0);
+    if (normal_depth.w <= 0.0) {
+        return vec4<f32>(1.0); // Background, nothing to occlude
+    }
+
+    let position = view_position_at(coords, dimensions, normal_depth.w);
+    let normal = normalize(normal_depth.xyz);
+
+    // Rotate the kernel around the normal in a 4x4 tile pattern, the blur
+    // pass averages the pattern back out
+    let tile = vec2<u32>(coords) % vec2<u32>(4u);
+    let angle = f32(tile.y * 4u + tile.x) * (6.28318530718 / 16.0);
+    let random = vec3<f32>(cos(angle), sin(angle), 0.0);
+    var tangent = random - normal * dot(random, normal);
+    if (dot(tangent, tangent) < 0.0001) {
+        tangent = cross(normal, vec3<f32>(0.0, 0.0, 1.0));
+    }
+    tangent = normalize(tangent);
+    let bitangent = cross(normal, tangent);
+    let tbn = mat3x3<f32>(tangent, bitangent, normal);
+
+    var occlusion = 0.0;
+    for (var i = 0u; i < KERNEL_SIZE; i++) {
+        let sample_position = position + tbn * ssao.kernel[i].xyz * ssao.radius;
+        let clip = camera.proj * vec4<f32>(sample_position, 1.0);
+        let ndc = clip.xy / clip.w;
+        let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        if (any(uv < vec2<f32>(0.0)) || any(uv >= vec2<f32>(1.0))) {
+            continue;
+        }
+
+        let scene_depth = textureLoad(t_input, vec2<i32>(uv * dimensions), 0).w;
+        if (scene_depth <= 0.0) {
+            continue;
+        }
+        // Ignore geometry far in front of the sample so silhouettes don't get dark halos
+        let range_check = smoothstep(0.0, 1.0, ssao.radius / abs(normal_depth.w - scene_depth));
+        if (scene_depth <= -sample_position.z - ssao.bias) {
+            occlusion += range_check;
+        }
+    }
+
+    let ao = clamp(1.0 - occlusion / f32(KERNEL_SIZE) * ssao.intensity, 0.0, 1.0);
+    return vec4<f32>(ao, ao, ao, 1.0);
+}
+
+// 4x4 box blur, matches the size of the rotation tile
+@fragment
+fn fs_blur(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coords = vec2<i32>(in.clip_position.xy);
+    let max_coords = vec2<i32>(textureDimensions(t_input)) - 1;
+    var ao = 0.0;
+    for (var x = -2; x < 2; x++) {
+        for (var y = -2; y < 2; y++) {
+            let sample_coords = clamp(coords + vec2<i32>(x, y), vec2<i32>(0), max_coords);
+            ao += textureLoad(t_input, sample_coords, 0).r;
+        }
+    }
+    ao /= 16.0;
+    return vec4<f32>(ao, ao, ao, 1.0);
+}
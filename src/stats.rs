@@ -0,0 +1,437 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::gpu_timing::GpuTimer;
+use crate::overlay::OverlayPipeline;
+
+// Frames the frame time graph covers, one bar each
+const HISTORY: usize = 120;
+// Quads drawn at most, anything past this is left off
+const MAX_QUADS: usize = 2048;
+// Pixels per font pixel
+const SCALE: f32 = 2.0;
+// A glyph's cell, the glyph plus the gap after it and below it
+const CELL_WIDTH: f32 = 6.0 * SCALE;
+const CELL_HEIGHT: f32 = 9.0 * SCALE;
+const MARGIN: f32 = 8.0;
+const PADDING: f32 = 6.0;
+const BAR_WIDTH: f32 = 2.0;
+const GRAPH_HEIGHT: f32 = 48.0;
+// Frame time at the top of the graph, and where the line across it is
+const GRAPH_MAX: f32 = 1.0 / 30.0;
+const GRAPH_TARGET: f32 = 1.0 / 60.0;
+
+// Instances of the glyph that's drawn filled in, see stats.wgsl
+const SOLID: u32 = u32::MAX;
+
+// ===== DRAW CALL COUNTING =====
+// wgpu doesn't keep count, so every draw adds itself here with `count_draw`.
+// Indirect draws count once however many draws the GPU makes of them.
+static DRAW_CALLS: AtomicU32 = AtomicU32::new(0);
+
+pub fn count_draw() {
+    DRAW_CALLS.fetch_add(1, Ordering::Relaxed);
+}
+
+// Draws since the last call
+pub fn take_draw_calls() -> u32 {
+    DRAW_CALLS.swap(0, Ordering::Relaxed)
+}
+
+// ===== FONT =====
+// 5x7 glyphs, a row a byte from the top with the leftmost pixel in bit 4.
+// Text is shown in capitals, characters missing here come out blank.
+const GLYPHS: &str = " 0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ.:/-_%()";
+#[rustfmt::skip]
+const FONT: [[u8; 7]; 45] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // space
+    [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E], // 0
+    [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E], // 1
+    [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F], // 2
+    [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E], // 3
+    [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02], // 4
+    [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E], // 5
+    [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E], // 6
+    [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08], // 7
+    [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E], // 8
+    [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C], // 9
+    [0x0E, 0x11, 0x11, 0x11, 0x1F, 0x11, 0x11], // A
+    [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E], // B
+    [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E], // C
+    [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C], // D
+    [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F], // E
+    [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10], // F
+    [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F], // G
+    [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11], // H
+    [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E], // I
+    [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C], // J
+    [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11], // K
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F], // L
+    [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11], // M
+    [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11], // N
+    [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E], // O
+    [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10], // P
+    [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D], // Q
+    [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11], // R
+    [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E], // S
+    [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04], // T
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E], // U
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04], // V
+    [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A], // W
+    [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11], // X
+    [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04], // Y
+    [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F], // Z
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C], // .
+    [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00], // :
+    [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00], // /
+    [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00], // -
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F], // _
+    [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03], // %
+    [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02], // (
+    [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08], // )
+];
+
+fn glyph_index(c: char) -> u32 {
+    GLYPHS.find(c.to_ascii_uppercase()).unwrap_or(0) as u32
+}
+
+// All the glyphs side by side in one row, a byte a pixel
+fn font_pixels() -> Vec<u8> {
+    let mut pixels = vec![0; FONT.len() * 5 * 7];
+    for (glyph, rows) in FONT.iter().enumerate() {
+        for (y, row) in rows.iter().enumerate() {
+            for x in 0..5 {
+                if row & (0x10 >> x) != 0 {
+                    pixels[y * FONT.len() * 5 + glyph * 5 + x] = 255;
+                }
+            }
+        }
+    }
+    pixels
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OverlayUniform {
+    screen_size: [f32; 2],
+    _padding: [f32; 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Quad {
+    // x, y, width, height in pixels from the top left
+    rect: [f32; 4],
+    color: [f32; 4],
+    glyph: u32,
+    _padding: [u32; 3],
+}
+
+impl Quad {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 3] =
+        wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4, 2 => Uint32];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Quad>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+// What the frame did, gathered by the app each update
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Counters {
+    pub particles: usize,
+    pub draw_calls: u32,
+    // Bytes copied through the upload manager
+    pub upload_bytes: u64,
+}
+
+// ===== STATS OVERLAY =====
+// FPS, a graph of recent frame times, the frame's counters and, while GPU
+// timing's on, each pass's time, drawn in a corner over the finished frame.
+// The text uses a built-in bitmap font, so there's nothing to load.
+pub struct StatsOverlay {
+    frame_times: VecDeque<f32>,
+    counters: Counters,
+    gpu_timings: Vec<(&'static str, f32)>,
+    gpu_total: f32,
+    quads: Vec<Quad>,
+    uniform_buffer: wgpu::Buffer,
+    quad_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: OverlayPipeline,
+}
+
+impl StatsOverlay {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, format: wgpu::TextureFormat) -> Self {
+        let font_size = wgpu::Extent3d {
+            width: FONT.len() as u32 * 5,
+            height: 7,
+            depth_or_array_layers: 1,
+        };
+        let font = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Stats Font"),
+            size: font_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                aspect: wgpu::TextureAspect::All,
+                texture: &font,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            &font_pixels(),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(font_size.width),
+                rows_per_image: Some(font_size.height),
+            },
+            font_size,
+        );
+        let font_view = font.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Stats Uniform Buffer"),
+            size: std::mem::size_of::<OverlayUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let quad_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Stats Quad Buffer"),
+            size: (std::mem::size_of::<Quad>() * MAX_QUADS) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("stats_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&font_view),
+                },
+            ],
+            label: Some("stats_bind_group"),
+        });
+
+        let pipeline = OverlayPipeline::new(
+            device,
+            format,
+            "Stats",
+            include_str!("stats.wgsl"),
+            &[&bind_group_layout],
+            &[Quad::desc()],
+            wgpu::PrimitiveTopology::TriangleList,
+        );
+
+        Self {
+            frame_times: VecDeque::with_capacity(HISTORY),
+            counters: Counters::default(),
+            gpu_timings: Vec::new(),
+            gpu_total: 0.0,
+            quads: Vec::new(),
+            uniform_buffer,
+            quad_buffer,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    // Once a frame with how long the frame took, in seconds
+    pub fn record_frame(&mut self, dt: f32) {
+        if self.frame_times.len() == HISTORY {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(dt);
+    }
+
+    // The latest counters, and the latest pass timings while the GPU timer's on
+    pub fn update(&mut self, counters: Counters, gpu_timer: Option<&GpuTimer>) {
+        self.counters = counters;
+        match gpu_timer.filter(|gpu_timer| gpu_timer.enabled) {
+            Some(gpu_timer) => {
+                self.gpu_timings.clear();
+                self.gpu_timings.extend_from_slice(gpu_timer.timings());
+                self.gpu_total = gpu_timer.total();
+            }
+            None => self.gpu_timings.clear(),
+        }
+    }
+
+    fn lines(&self) -> Vec<String> {
+        let total = self.frame_times.iter().sum::<f32>();
+        let average = total / self.frame_times.len().max(1) as f32;
+        let worst = self.frame_times.iter().copied().fold(0.0, f32::max);
+        let mut lines = vec![
+            format!(
+                "FPS {:.1}",
+                if total > 0.0 {
+                    self.frame_times.len() as f32 / total
+                } else {
+                    0.0
+                }
+            ),
+            format!(
+                "FRAME {:.2} MS (MAX {:.2})",
+                average * 1000.0,
+                worst * 1000.0
+            ),
+            format!("PARTICLES {}", self.counters.particles),
+            format!("DRAW CALLS {}", self.counters.draw_calls),
+            format!(
+                "UPLOADS {:.1} KB",
+                self.counters.upload_bytes as f32 / 1024.0
+            ),
+        ];
+        if !self.gpu_timings.is_empty() {
+            lines.push(format!("GPU {:.2} MS", self.gpu_total));
+            lines.extend(
+                self.gpu_timings
+                    .iter()
+                    .map(|(name, ms)| format!(" {} {:.2}", name, ms)),
+            );
+        }
+        lines
+    }
+
+    fn push_quad(&mut self, rect: [f32; 4], color: [f32; 4], glyph: u32) {
+        if self.quads.len() < MAX_QUADS {
+            let color = self.pipeline.color(color);
+            self.quads.push(Quad {
+                rect,
+                color,
+                glyph,
+                _padding: [0; 3],
+            });
+        }
+    }
+
+    fn push_text(&mut self, x: f32, y: f32, text: &str, color: [f32; 4]) {
+        for (i, c) in text.chars().enumerate() {
+            if c == ' ' {
+                continue;
+            }
+            self.push_quad(
+                [x + i as f32 * CELL_WIDTH, y, 5.0 * SCALE, 7.0 * SCALE],
+                color,
+                glyph_index(c),
+            );
+        }
+    }
+
+    // Draws over `output`, which is `width` by `height`
+    pub fn render(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        let lines = self.lines();
+        let graph_width = HISTORY as f32 * BAR_WIDTH;
+        let text_width = lines.iter().map(|line| line.len()).max().unwrap_or(0) as f32 * CELL_WIDTH;
+        let panel_width = text_width.max(graph_width) + PADDING * 2.0;
+        let panel_height = (lines.len() as f32 * CELL_HEIGHT) + GRAPH_HEIGHT + PADDING * 3.0;
+
+        self.quads.clear();
+        self.push_quad(
+            [MARGIN, MARGIN, panel_width, panel_height],
+            [0.0, 0.0, 0.0, 0.6],
+            SOLID,
+        );
+        let left = MARGIN + PADDING;
+        let mut y = MARGIN + PADDING;
+        let white = [1.0, 1.0, 1.0, 1.0];
+
+        // FPS and frame time first, then the graph, then everything else
+        let (header, rest) = lines.split_at(2);
+        for line in header {
+            self.push_text(left, y, line, white);
+            y += CELL_HEIGHT;
+        }
+        let graph_bottom = y + GRAPH_HEIGHT;
+        for i in 0..self.frame_times.len() {
+            let dt = self.frame_times[i];
+            let bar = (dt / GRAPH_MAX).min(1.0) * GRAPH_HEIGHT;
+            let color = if dt <= GRAPH_TARGET * 1.05 {
+                [0.1, 0.8, 0.1, 1.0]
+            } else if dt <= GRAPH_MAX {
+                [0.9, 0.7, 0.05, 1.0]
+            } else {
+                [0.9, 0.1, 0.05, 1.0]
+            };
+            self.push_quad(
+                [
+                    left + i as f32 * BAR_WIDTH,
+                    graph_bottom - bar,
+                    BAR_WIDTH,
+                    bar,
+                ],
+                color,
+                SOLID,
+            );
+        }
+        let target = graph_bottom - GRAPH_TARGET / GRAPH_MAX * GRAPH_HEIGHT;
+        self.push_quad(
+            [left, target, graph_width, 1.0],
+            [1.0, 1.0, 1.0, 0.5],
+            SOLID,
+        );
+        y = graph_bottom + PADDING;
+        for line in rest {
+            self.push_text(left, y, line, white);
+            y += CELL_HEIGHT;
+        }
+
+        let uniform = OverlayUniform {
+            screen_size: [width.max(1) as f32, height.max(1) as f32],
+            _padding: [0.0; 2],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+        queue.write_buffer(&self.quad_buffer, 0, bytemuck::cast_slice(&self.quads));
+
+        let mut pass = self.pipeline.begin_pass(encoder, "Stats Pass", output);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.quad_buffer.slice(..));
+        pass.draw(0..6, 0..self.quads.len() as u32);
+    }
+}
@@ -0,0 +1,67 @@
+// ===== STATS OVERLAY =====
+// Text and bars on top of the finished frame. Every quad is an instance with
+// a rectangle in pixels; glyph quads look their 5x7 cell up in the font
+// texture, the rest are filled solid.
+
+const SOLID: u32 = 0xffffffffu;
+const GLYPH_WIDTH: f32 = 5.0;
+const GLYPH_HEIGHT: f32 = 7.0;
+
+struct OverlayUniform {
+    screen_size: vec2<f32>,
+    _padding: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> overlay: OverlayUniform;
+@group(0) @binding(1)
+var font: texture_2d<f32>;
+
+struct QuadInput {
+    // x, y, width, height in pixels from the top left
+    @location(0) rect: vec4<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) glyph: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) @interpolate(flat) glyph: u32,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, quad: QuadInput) -> VertexOutput {
+    // Two triangles, corners 0 1 2 and 2 1 3
+    let corner = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+    )[index];
+    let pixel = quad.rect.xy + corner * quad.rect.zw;
+    let ndc = pixel / overlay.screen_size * 2.0 - 1.0;
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc.x, -ndc.y, 0.0, 1.0);
+    out.uv = corner;
+    out.color = quad.color;
+    out.glyph = quad.glyph;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    if (in.glyph == SOLID) {
+        return in.color;
+    }
+    let cell = min(in.uv * vec2<f32>(GLYPH_WIDTH, GLYPH_HEIGHT), vec2<f32>(GLYPH_WIDTH - 1.0, GLYPH_HEIGHT - 1.0));
+    let texel = vec2<i32>(i32(in.glyph) * i32(GLYPH_WIDTH) + i32(cell.x), i32(cell.y));
+    let coverage = textureLoad(font, texel, 0).r;
+    if (coverage < 0.5) {
+        discard;
+    }
+    return in.color;
+}
@@ -0,0 +1,356 @@
+use cgmath::prelude::*;
+use wgpu::util::DeviceExt;
+
+use crate::frustum::Frustum;
+use crate::hdr::HdrPipeline;
+use crate::light::Lighting;
+use crate::model::{self, ModelVertex, Vertex};
+use crate::preprocess::Preprocessor;
+use crate::scene::SceneTerrain;
+use crate::texture;
+
+// ===== HEIGHTMAP =====
+// Heights from 0 for black to 1 for white, row by row from the -Z edge
+#[derive(Debug, Clone)]
+pub struct Heightmap {
+    width: u32,
+    depth: u32,
+    heights: Vec<f32>,
+}
+
+impl Heightmap {
+    // 16 bit images keep their precision, color ones go by luminance
+    pub fn from_image(image: &image::DynamicImage) -> Self {
+        let luma = image.to_luma16();
+        Self {
+            width: luma.width(),
+            depth: luma.height(),
+            heights: luma.pixels().map(|p| p.0[0] as f32 / 65535.0).collect(),
+        }
+    }
+
+    // Bilinear between the samples, `u` and `v` across it from 0 to 1
+    pub fn sample(&self, u: f32, v: f32) -> f32 {
+        let x = u.clamp(0.0, 1.0) * (self.width - 1) as f32;
+        let z = v.clamp(0.0, 1.0) * (self.depth - 1) as f32;
+        let (x0, z0) = (x.floor() as u32, z.floor() as u32);
+        let (x1, z1) = ((x0 + 1).min(self.width - 1), (z0 + 1).min(self.depth - 1));
+        let height = |x: u32, z: u32| self.heights[(z * self.width + x) as usize];
+        let (fx, fz) = (x.fract(), z.fract());
+        let top = height(x0, z0) * (1.0 - fx) + height(x1, z0) * fx;
+        let bottom = height(x0, z1) * (1.0 - fx) + height(x1, z1) * fx;
+        top * (1.0 - fz) + bottom * fz
+    }
+}
+
+// One piece of the ground, with its own bounds for culling
+struct TerrainChunk {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_elements: u32,
+    bounds: model::BoundingSphere,
+}
+
+// ===== TERRAIN =====
+// The heightmap as a grid of chunks, lit like the models with one texture
+// tiled across it. Drawn in the scene passes after the models, chunks
+// outside the view are skipped. The ground only receives shadows, it
+// doesn't cast them.
+pub struct Terrain {
+    pub settings: SceneTerrain,
+    heightmap: Heightmap,
+    chunks: Vec<TerrainChunk>,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+    color_format: wgpu::TextureFormat,
+    depth_mode: texture::DepthMode,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Terrain {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        settings: SceneTerrain,
+        heightmap: Heightmap,
+        ground: &texture::Texture,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        lighting: &Lighting,
+        ssao_bind_group_layout: &wgpu::BindGroupLayout,
+        depth_mode: texture::DepthMode,
+    ) -> crate::error::Result<Self> {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("terrain_bind_group_layout"),
+        });
+        // The material sampler clamps, the ground texture has to repeat
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            anisotropy_clamp: 16,
+            ..Default::default()
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&ground.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("terrain_bind_group"),
+        });
+
+        let mut preprocessor = Preprocessor::new();
+        lighting.shader_defines(&mut preprocessor);
+        let source = preprocessor.process_builtin("terrain.wgsl")?;
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Terrain Shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Terrain Pipeline Layout"),
+            bind_group_layouts: &[
+                &bind_group_layout,
+                camera_bind_group_layout,
+                &lighting.bind_group_layout,
+                ssao_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(
+            device,
+            &pipeline_layout,
+            &shader,
+            color_format,
+            sample_count,
+            depth_mode,
+        );
+
+        let mut terrain = Self {
+            settings,
+            heightmap,
+            chunks: Vec::new(),
+            bind_group,
+            pipeline_layout,
+            shader,
+            color_format,
+            depth_mode,
+            pipeline,
+        };
+        let chunks = terrain.settings.chunks.max(1);
+        terrain.chunks = (0..chunks)
+            .flat_map(|z| (0..chunks).map(move |x| (x, z)))
+            .map(|(x, z)| terrain.create_chunk(device, x, z))
+            .collect();
+        Ok(terrain)
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_mode: texture::DepthMode,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Terrain Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: Some("vs_main"),
+                buffers: &[ModelVertex::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: Some("fs_main"),
+                targets: &HdrPipeline::scene_targets(color_format, Some(wgpu::BlendState::REPLACE)),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: depth_mode.compare(),
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    // Where the ground is at (u, v) across the heightmap
+    fn to_world(&self, u: f32, v: f32) -> cgmath::Point3<f32> {
+        let SceneTerrain { position, size, .. } = self.settings;
+        cgmath::Point3::new(
+            position[0] + (u - 0.5) * size,
+            position[1] + self.heightmap.sample(u, v) * self.settings.height,
+            position[2] + (v - 0.5) * size,
+        )
+    }
+
+    // (x, z) in world space across the heightmap, None off its edges
+    fn to_heightmap(&self, x: f32, z: f32) -> Option<(f32, f32)> {
+        let SceneTerrain { position, size, .. } = self.settings;
+        let u = (x - position[0]) / size + 0.5;
+        let v = (z - position[2]) / size + 0.5;
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return None;
+        }
+        Some((u, v))
+    }
+
+    // Height of the ground at (x, z) in world space, None off its edges
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let (u, v) = self.to_heightmap(x, z)?;
+        Some(self.to_world(u, v).y)
+    }
+
+    // Which way the ground faces at (x, z), None off its edges
+    pub fn normal_at(&self, x: f32, z: f32) -> Option<cgmath::Vector3<f32>> {
+        let (u, v) = self.to_heightmap(x, z)?;
+        Some(self.grid_normal(u, v, self.grid_step()))
+    }
+
+    // Spacing of the grid points across the heightmap
+    fn grid_step(&self) -> f32 {
+        1.0 / (self.settings.chunks.max(1) * self.settings.resolution.max(1)) as f32
+    }
+
+    // From the slope between the neighbouring grid points, so it matches
+    // the triangles rather than the heightmap's own resolution
+    fn grid_normal(&self, u: f32, v: f32, step: f32) -> cgmath::Vector3<f32> {
+        let dx = self.to_world(u + step, v).y - self.to_world(u - step, v).y;
+        let dz = self.to_world(u, v + step).y - self.to_world(u, v - step).y;
+        let spacing = 2.0 * step * self.settings.size;
+        cgmath::Vector3::new(-dx, spacing, -dz).normalize()
+    }
+
+    fn create_chunk(&self, device: &wgpu::Device, chunk_x: u32, chunk_z: u32) -> TerrainChunk {
+        let resolution = self.settings.resolution.max(1);
+        let step = self.grid_step();
+        let mut vertices = Vec::with_capacity(((resolution + 1) * (resolution + 1)) as usize);
+        for z in 0..=resolution {
+            for x in 0..=resolution {
+                let u = (chunk_x * resolution + x) as f32 * step;
+                let v = (chunk_z * resolution + z) as f32 * step;
+                let position = self.to_world(u, v);
+                vertices.push(ModelVertex {
+                    position: position.into(),
+                    // In world units so the texture runs on across chunks
+                    tex_coords: [
+                        position.x / self.settings.tile,
+                        position.z / self.settings.tile,
+                    ],
+                    normal: self.grid_normal(u, v, step).into(),
+                    tangent: [0.0; 4],
+                });
+            }
+        }
+        // Counter-clockwise seen from above
+        let index = |x: u32, z: u32| z * (resolution + 1) + x;
+        let mut indices = Vec::with_capacity((resolution * resolution * 6) as usize);
+        for z in 0..resolution {
+            for x in 0..resolution {
+                indices.extend([index(x, z), index(x, z + 1), index(x + 1, z)]);
+                indices.extend([index(x + 1, z), index(x, z + 1), index(x + 1, z + 1)]);
+            }
+        }
+        model::compute_tangents(&mut vertices, &indices);
+
+        let label = format!("Terrain Chunk ({}, {})", chunk_x, chunk_z);
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Vertex Buffer", label)),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Index Buffer", label)),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        TerrainChunk {
+            vertex_buffer,
+            index_buffer,
+            num_elements: indices.len() as u32,
+            bounds: model::BoundingSphere::from_positions(
+                vertices.iter().map(|vertex| vertex.position),
+            ),
+        }
+    }
+
+    // MSAA changes need a new pipeline, the chunks are kept
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            &self.pipeline_layout,
+            &self.shader,
+            self.color_format,
+            sample_count,
+            self.depth_mode,
+        );
+    }
+
+    // The chunks `frustum` can see
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        lighting_bind_group: &'a wgpu::BindGroup,
+        ssao_bind_group: &'a wgpu::BindGroup,
+        frustum: &Frustum,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        render_pass.set_bind_group(2, lighting_bind_group, &[]);
+        render_pass.set_bind_group(3, ssao_bind_group, &[]);
+        for chunk in &self.chunks {
+            if !frustum.intersects_sphere(&chunk.bounds) {
+                continue;
+            }
+            render_pass.set_vertex_buffer(0, chunk.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(chunk.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed(0..chunk.num_elements, 0, 0..1);
+            crate::stats::count_draw();
+        }
+    }
+}
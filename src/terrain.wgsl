@@ -0,0 +1,79 @@
+// ===== TERRAIN =====
+// The heightmap ground, lit with the same functions as the models. Vertices
+// are already in world space.
+#include "lighting.wgsl"
+#include "camera.wgsl"
+
+@group(0) @binding(0)
+var t_ground: texture_2d<f32>;
+@group(0) @binding(1)
+var s_ground: sampler;
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Blurred screen-space ambient occlusion from the SSAO pass
+@group(3) @binding(0)
+var t_ssao: texture_2d<f32>;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) world_normal: vec3<f32>,
+    @location(3) view_depth: f32,
+    @location(4) curr_clip: vec4<f32>,
+    @location(5) prev_clip: vec4<f32>,
+};
+
+// Same targets as the model shader
+struct SceneOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) velocity: vec4<f32>,
+    @location(2) linear_depth: f32,
+};
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    let world_position = vec4<f32>(in.position, 1.0);
+    var out: VertexOutput;
+    out.tex_coords = in.tex_coords;
+    out.world_position = in.position;
+    out.world_normal = in.normal;
+    out.view_depth = -(camera.view * world_position).z;
+    out.clip_position = camera.view_proj * world_position;
+    out.curr_clip = out.clip_position;
+    out.prev_clip = camera.prev_view_proj * world_position;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> SceneOutput {
+    var surface: Surface;
+    surface.normal = normalize(in.world_normal);
+    // Steep slopes come out darker, like bare earth between the grass
+    let steepness = smoothstep(0.15, 0.45, 1.0 - surface.normal.y);
+    surface.albedo = textureSample(t_ground, s_ground, in.tex_coords).rgb * mix(1.0, 0.55, steepness);
+    surface.alpha = 1.0;
+    surface.metallic = 0.0;
+    surface.roughness = 0.9;
+    surface.occlusion = textureLoad(t_ssao, vec2<i32>(in.clip_position.xy), 0).r;
+    surface.emissive = vec3<f32>(0.0);
+    let color = shade(surface, in.world_position, camera.view_position.xyz, in.view_depth);
+
+    // NDC to UV: halve, and flip y since UVs point down
+    let curr = in.curr_clip.xy / in.curr_clip.w;
+    let prev = in.prev_clip.xy / in.prev_clip.w;
+    var out: SceneOutput;
+    out.color = vec4<f32>(color, 1.0);
+    out.velocity = vec4<f32>((curr - prev) * vec2<f32>(0.5, -0.5), 0.0, 0.0);
+    out.linear_depth = in.view_depth;
+    return out;
+}
@@ -1,6 +1,58 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
 use anyhow::*;
 use image::GenericImageView;
 
+use crate::color::ColorSpace;
+use crate::compressed::{self, CompressedImage};
+use crate::offscreen::Thumbnail;
+
+// ===== DEPTH MODE =====
+// Reverse-Z maps the near plane to 1 and the far plane to 0. Combined with a
+// float depth buffer this spreads precision evenly over the whole view distance
+// instead of bunching it up right in front of the camera.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum DepthMode {
+    #[default]
+    Standard,
+    ReverseZ,
+}
+
+impl DepthMode {
+    // Test that keeps the closer fragment
+    pub fn compare(self) -> wgpu::CompareFunction {
+        match self {
+            DepthMode::Standard => wgpu::CompareFunction::Less,
+            DepthMode::ReverseZ => wgpu::CompareFunction::Greater,
+        }
+    }
+
+    // Same, but also passes on equal depth, for things drawn exactly at the far plane
+    pub fn compare_or_equal(self) -> wgpu::CompareFunction {
+        match self {
+            DepthMode::Standard => wgpu::CompareFunction::LessEqual,
+            DepthMode::ReverseZ => wgpu::CompareFunction::GreaterEqual,
+        }
+    }
+
+    // Depth of the far plane, which is also what depth buffers are cleared to
+    pub fn far_depth(self) -> f32 {
+        match self {
+            DepthMode::Standard => 1.0,
+            DepthMode::ReverseZ => 0.0,
+        }
+    }
+
+    // Values for the `FAR_DEPTH` override constant of shaders that look at raw depth
+    pub fn shader_constants(self) -> [(&'static str, f64); 1] {
+        [("FAR_DEPTH", self.far_depth() as f64)]
+    }
+}
+
+// Clones share the GPU texture, which is how materials share loaded images
+#[derive(Clone)]
 pub struct Texture {
     #[allow(unused)]
     pub texture: wgpu::Texture,
@@ -14,19 +66,37 @@ impl Texture {
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
+        sample_count: u32, // Has to match the color target when using MSAA
+        label: &str,
+    ) -> Self {
+        // 2. match screeen size to render correctly
+        Self::create_depth_texture_with_size(
+            device,
+            config.width,
+            config.height,
+            sample_count,
+            label,
+        )
+    }
+
+    // Same, for targets that aren't the size of the surface
+    pub fn create_depth_texture_with_size(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        sample_count: u32,
         label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
-            // 2. match screeen size to render correctly
-            width: config.width.max(1),
-            height: config.height.max(1),
+            width: width.max(1),
+            height: height.max(1),
             depth_or_array_layers: 1,
         };
         let desc = wgpu::TextureDescriptor {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT // 3. rendering to this texture
@@ -66,11 +136,93 @@ impl Texture {
         Self::from_image(device, queue, &img, Some(label))
     }
 
+    // Normal, metallic-roughness and occlusion maps hold data rather than color,
+    // so they must not go through the sRGB decode
+    pub fn from_bytes_linear(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+    ) -> Result<Self> {
+        let img = image::load_from_memory(bytes)?;
+        Self::from_image_with_format(
+            device,
+            queue,
+            &img,
+            Some(label),
+            ColorSpace::Linear.texture_format(),
+        )
+    }
+
+    // An image file in res/, KTX2 and DDS files kept compressed where the
+    // device can sample them. Load through `Assets::load_texture` to share it
+    // between materials.
+    pub async fn from_path(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        file_name: &str,
+        color_space: ColorSpace,
+    ) -> crate::error::Result<Self> {
+        let data = crate::resources::load_binary(file_name).await?;
+        Self::from_encoded(device, queue, &data, file_name, color_space)
+    }
+
+    // An image file's bytes in any of the formats `from_path` reads
+    pub fn from_encoded(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &[u8],
+        label: &str,
+        color_space: ColorSpace,
+    ) -> crate::error::Result<Self> {
+        if compressed::is_container(data) {
+            let image = CompressedImage::from_bytes(data)?;
+            return Self::from_compressed(device, queue, &image, Some(label), color_space)
+                .map_err(Into::into);
+        }
+        let image = image::load_from_memory(data).map_err(|source| crate::error::Error::Image {
+            path: label.to_string(),
+            source,
+        })?;
+        Self::from_image_with_format(
+            device,
+            queue,
+            &image,
+            Some(label),
+            color_space.texture_format(),
+        )
+        .map_err(Into::into)
+    }
+
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
+    ) -> Result<Self> {
+        Self::from_image_with_format(device, queue, img, label, ColorSpace::Srgb.texture_format())
+    }
+
+    // 1x1 texture used when a material doesn't provide a map
+    pub fn solid_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        rgba: [u8; 4],
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        let img =
+            image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(rgba)));
+        // Writing a 1x1 image can't fail
+        Self::from_image_with_format(device, queue, &img, Some(label), format).unwrap()
+    }
+
+    pub fn from_image_with_format(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        format: wgpu::TextureFormat,
     ) -> Result<Self> {
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
@@ -80,14 +232,18 @@ impl Texture {
             height: dimensions.1,
             depth_or_array_layers: 1,
         };
+        // A full mip chain, so textures don't shimmer in the distance
+        let mip_level_count = size.max_mips(wgpu::TextureDimension::D2);
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
             view_formats: &[],
         });
 
@@ -106,14 +262,240 @@ impl Texture {
             },
             size,
         );
+        if mip_level_count > 1 {
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Mipmap Encoder"),
+            });
+            Self::generate_mipmaps(device, &mut encoder, &texture);
+            queue.submit(std::iter::once(encoder.finish()));
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = Self::create_material_sampler(device);
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+        })
+    }
+
+    // Uploads the blocks as they are when the device can sample BCn, otherwise
+    // decodes the top level to RGBA and generates the mips from that
+    pub fn from_compressed(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &CompressedImage,
+        label: Option<&str>,
+        color_space: ColorSpace,
+    ) -> Result<Self> {
+        // BCn textures have to be a whole number of blocks across
+        let whole_blocks = image.width.is_multiple_of(4) && image.height.is_multiple_of(4);
+        if !device
+            .features()
+            .contains(wgpu::Features::TEXTURE_COMPRESSION_BC)
+            || !whole_blocks
+        {
+            let decoded = image::DynamicImage::ImageRgba8(image.decode());
+            return Self::from_image_with_format(
+                device,
+                queue,
+                &decoded,
+                label,
+                color_space.texture_format(),
+            );
+        }
+
+        let size = wgpu::Extent3d {
+            width: image.width,
+            height: image.height,
+            depth_or_array_layers: 1,
+        };
+        // Only the top level has to be whole blocks, smaller ones take up a
+        // whole block anyway (see the copy below). The file's chain can't be
+        // longer than the texture's though.
+        let level_count = image
+            .levels
+            .len()
+            .min(size.max_mips(wgpu::TextureDimension::D2) as usize)
+            as u32;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count: level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: image.format.texture_format(color_space),
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        for level in 0..level_count {
+            let (blocks_wide, blocks_high) = image.blocks(level);
+            queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: level,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                &image.levels[level as usize],
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(blocks_wide * image.format.block_bytes() as u32),
+                    rows_per_image: Some(blocks_high),
+                },
+                // Copies cover whole blocks, past the edge of small levels too
+                wgpu::Extent3d {
+                    width: blocks_wide * 4,
+                    height: blocks_high * 4,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = Self::create_material_sampler(device);
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+        })
+    }
+
+    // Fills every mip level of a 2D texture by filtering the level above it
+    // down. The texture needs a renderable format and RENDER_ATTACHMENT. Not
+    // for arrays or cubemaps: GL can't sample one of their layers on its own.
+    pub fn generate_mipmaps(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) {
+        // Bilinear sampling halfway between four texels of the level above
+        // averages them
+        let blit = Self::mip_blit(device, texture.format());
+        let level_view = |level| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("mip_view"),
+                base_mip_level: level,
+                mip_level_count: Some(1),
+                ..Default::default()
+            })
+        };
+        for level in 1..texture.mip_level_count() {
+            let size = texture.size().mip_level_size(level, texture.dimension());
+            blit.render(
+                device,
+                encoder,
+                &level_view(level - 1),
+                &level_view(level),
+                [0, 0, size.width, size.height],
+            );
+        }
+    }
+
+    // The blit `generate_mipmaps` draws with, built once per format rather
+    // than for every texture. Per thread, GPU objects aren't Send on the web.
+    // Pipelines for a device that's been replaced are let go.
+    fn mip_blit(device: &wgpu::Device, format: wgpu::TextureFormat) -> Rc<Thumbnail> {
+        thread_local! {
+            static MIP_BLITS: RefCell<HashMap<(wgpu::Device, wgpu::TextureFormat), Rc<Thumbnail>>> =
+                RefCell::new(HashMap::new());
+        }
+        MIP_BLITS.with_borrow_mut(|blits| {
+            blits.retain(|(blit_device, _), _| blit_device == device);
+            blits
+                .entry((device.clone(), format))
+                .or_insert_with(|| Rc::new(Thumbnail::new(device, format)))
+                .clone()
+        })
+    }
+
+    // Trilinear with anisotropic filtering, for mipmapped material maps
+    pub fn create_material_sampler(device: &wgpu::Device) -> wgpu::Sampler {
+        device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            anisotropy_clamp: 16,
+            ..Default::default()
+        })
+    }
+
+    // Six square faces in +X, -X, +Y, -Y, +Z, -Z order, viewed as a cube
+    pub fn from_cube_faces(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: &[image::DynamicImage],
+        label: Option<&str>,
+    ) -> Result<Self> {
+        if faces.len() != 6 {
+            bail!("a cubemap needs 6 faces, got {}", faces.len());
+        }
+        let (width, height) = faces[0].dimensions();
+        if width != height
+            || faces
+                .iter()
+                .any(|face| face.dimensions() != (width, height))
+        {
+            bail!("cubemap faces must be square and all the same size");
+        }
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 6,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: ColorSpace::Srgb.texture_format(),
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        for (layer, face) in faces.iter().enumerate() {
+            queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                },
+                &face.to_rgba8(),
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * width),
+                    rows_per_image: Some(height),
+                },
+                wgpu::Extent3d {
+                    depth_or_array_layers: 1,
+                    ..size
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Linear,
             mipmap_filter: wgpu::FilterMode::Nearest,
             ..Default::default()
         });
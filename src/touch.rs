@@ -0,0 +1,116 @@
+use cgmath::prelude::*;
+use winit::event::{Touch, TouchPhase};
+
+use crate::Camera;
+
+// Steepest the camera can look up or down while orbiting, short of straight
+// along `up` where it would flip over
+const MAX_PITCH: f32 = 1.5;
+// Closest pinching can bring the camera to what it orbits
+const MIN_DISTANCE: f32 = 0.1;
+
+// What the fingers on the screen did since the last move, in fractions of
+// the screen's height so it feels the same at any resolution
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gesture {
+    // One finger dragged
+    Orbit { dx: f32, dy: f32 },
+    // Two fingers moved together and apart. `zoom` scales the distance to
+    // the target, under 1 when they spread.
+    Pinch { dx: f32, dy: f32, zoom: f32 },
+}
+
+impl Gesture {
+    // Moves `camera` around its target, dragging across the whole screen
+    // swings it round half a turn. Pinching zooms in and out, and the two
+    // fingers moving together pan with what's under them.
+    pub fn apply(&self, camera: &mut Camera) {
+        let offset = camera.eye - camera.target;
+        let distance = offset.magnitude();
+        let forward = -offset / distance;
+        let right = forward.cross(camera.up).normalize();
+        match *self {
+            Gesture::Orbit { dx, dy } => {
+                let pitch = (forward.dot(camera.up)).asin();
+                let dy = (dy * std::f32::consts::PI).clamp(pitch - MAX_PITCH, pitch + MAX_PITCH);
+                let rotation = cgmath::Quaternion::from_axis_angle(
+                    camera.up.normalize(),
+                    cgmath::Rad(-dx * std::f32::consts::PI),
+                ) * cgmath::Quaternion::from_axis_angle(right, cgmath::Rad(-dy));
+                camera.eye = camera.target + rotation.rotate_vector(offset);
+            }
+            Gesture::Pinch { dx, dy, zoom } => {
+                // How far a screen's height is across at the target
+                let height = 2.0 * distance * (cgmath::Deg(camera.fovy()) / 2.0).tan();
+                let up = right.cross(forward);
+                let pan = (-right * dx + up * dy) * height;
+                camera.target += pan;
+                let distance = (distance * zoom).max(MIN_DISTANCE);
+                camera.eye = camera.target - forward * distance;
+            }
+        }
+    }
+}
+
+// ===== TOUCH GESTURES =====
+// Follows the fingers on the screen and turns their movement into
+// gestures, for phones and tablets where there's no mouse or keyboard
+#[derive(Debug, Default)]
+pub struct TouchGestures {
+    // By touch id, in the order they went down
+    touches: Vec<(u64, [f32; 2])>,
+}
+
+impl TouchGestures {
+    // The gesture a finger moving makes, if it's one of the first two down.
+    // `height` is the screen's in the same units as the touch locations.
+    pub fn handle_touch(&mut self, touch: &Touch, height: f32) -> Option<Gesture> {
+        let location = [
+            touch.location.x as f32 / height,
+            touch.location.y as f32 / height,
+        ];
+        let index = self.touches.iter().position(|(id, _)| *id == touch.id);
+        match (touch.phase, index) {
+            (TouchPhase::Started, None) => {
+                self.touches.push((touch.id, location));
+                None
+            }
+            (TouchPhase::Moved, Some(index)) => {
+                let before = self.touches.clone();
+                self.touches[index].1 = location;
+                match (before.as_slice(), self.touches.as_slice()) {
+                    ([(_, from)], [(_, to)]) => Some(Gesture::Orbit {
+                        dx: to[0] - from[0],
+                        dy: to[1] - from[1],
+                    }),
+                    ([(_, a0), (_, b0), ..], [(_, a1), (_, b1), ..]) if index < 2 => {
+                        let spread = |a: &[f32; 2], b: &[f32; 2]| {
+                            ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2)).sqrt()
+                        };
+                        let (spread0, spread1) = (spread(a0, b0), spread(a1, b1));
+                        Some(Gesture::Pinch {
+                            dx: (a1[0] + b1[0] - a0[0] - b0[0]) * 0.5,
+                            dy: (a1[1] + b1[1] - a0[1] - b0[1]) * 0.5,
+                            zoom: if spread1 > 0.0 {
+                                spread0 / spread1
+                            } else {
+                                1.0
+                            },
+                        })
+                    }
+                    _ => None,
+                }
+            }
+            (TouchPhase::Ended | TouchPhase::Cancelled, Some(index)) => {
+                self.touches.remove(index);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    // Forgets every finger, for when their ends won't come
+    pub fn release(&mut self) {
+        self.touches.clear();
+    }
+}
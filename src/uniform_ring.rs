@@ -0,0 +1,131 @@
+// ===== DYNAMIC UNIFORM RING =====
+// Per-frame uniforms for many objects in one buffer behind one bind group. Each
+// `push` takes the next slot, aligned for a dynamic offset, and returns the
+// offset to set the bind group with for that object's draws. `reset` starts
+// the next frame back at the first slot, and `upload` writes the whole frame at
+// once, growing the buffer first when the frame needed more slots.
+pub struct UniformRing {
+    label: &'static str,
+    layout: wgpu::BindGroupLayout,
+    item_size: u64,
+    stride: u32,
+    capacity: u32,
+    data: Vec<u8>,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl UniformRing {
+    // A single uniform at binding 0, read with a dynamic offset
+    pub fn create_bind_group_layout(
+        device: &wgpu::Device,
+        visibility: wgpu::ShaderStages,
+        label: &str,
+    ) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some(label),
+        })
+    }
+
+    // Room for `capacity` items of `T` before it has to grow
+    pub fn new<T: bytemuck::Pod>(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        label: &'static str,
+        capacity: u32,
+    ) -> Self {
+        let item_size = std::mem::size_of::<T>() as u64;
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let stride = wgpu::util::align_to(item_size, alignment) as u32;
+        let capacity = capacity.max(1);
+        let (buffer, bind_group) =
+            Self::create_buffer(device, layout, label, item_size, stride, capacity);
+        Self {
+            label,
+            layout: layout.clone(),
+            item_size,
+            stride,
+            capacity,
+            data: Vec::with_capacity((stride * capacity) as usize),
+            buffer,
+            bind_group,
+        }
+    }
+
+    fn create_buffer(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        label: &str,
+        item_size: u64,
+        stride: u32,
+        capacity: u32,
+    ) -> (wgpu::Buffer, wgpu::BindGroup) {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: stride as u64 * capacity as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(item_size),
+                }),
+            }],
+            label: Some(label),
+        });
+        (buffer, bind_group)
+    }
+
+    pub fn reset(&mut self) {
+        self.data.clear();
+    }
+
+    // Adds `value` to this frame and returns its dynamic offset
+    pub fn push<T: bytemuck::Pod>(&mut self, value: &T) -> u32 {
+        let bytes = bytemuck::bytes_of(value);
+        debug_assert_eq!(bytes.len() as u64, self.item_size);
+        let offset = self.data.len();
+        self.data.extend_from_slice(bytes);
+        self.data.resize(offset + self.stride as usize, 0);
+        offset as u32
+    }
+
+    // Writes everything pushed since `reset`. Call before submitting the draws
+    // that use the offsets.
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let count = self.data.len() as u32 / self.stride;
+        if count > self.capacity {
+            self.capacity = count.next_power_of_two();
+            (self.buffer, self.bind_group) = Self::create_buffer(
+                device,
+                &self.layout,
+                self.label,
+                self.item_size,
+                self.stride,
+                self.capacity,
+            );
+        }
+        if !self.data.is_empty() {
+            queue.write_buffer(&self.buffer, 0, &self.data);
+        }
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+}
@@ -0,0 +1,137 @@
+use std::ops::Range;
+
+// Staging chunks are allocated this size, or bigger for a larger single write
+const CHUNK_SIZE: wgpu::BufferAddress = 256 * 1024;
+
+// Frames the GPU may still be working on while the next one is recorded
+pub const FRAMES_IN_FLIGHT: usize = 3;
+
+// ===== UPLOAD MANAGER =====
+// Collects the frame's buffer writes and records them as copies in one batch
+// when the frame is submitted. The copies come out of a `StagingBelt`, which
+// hands its staging chunks back for reuse once the GPU has read them, instead
+// of every write getting fresh staging memory like `Queue::write_buffer`.
+// Instances, cameras, lights, joints and particles go through here. The
+// extra windows' cameras and the loading screen write to the queue directly
+// since they're submitted apart from the frame, and so do the renderers'
+// small uniforms written as their passes record. Queue writes land at the
+// same submit, ahead of the frame's commands like the copies here, so order
+// would only matter for a buffer written both ways in one frame.
+pub struct Uploads {
+    belt: wgpu::util::StagingBelt,
+    // Every pending write's bytes back to back
+    data: Vec<u8>,
+    writes: Vec<(wgpu::Buffer, wgpu::BufferAddress, Range<usize>)>,
+    // Counts up with every `finish`
+    frame: u64,
+    // Bytes the last `finish` copied
+    last_frame_bytes: u64,
+}
+
+impl Default for Uploads {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Uploads {
+    pub fn new() -> Self {
+        Self {
+            belt: wgpu::util::StagingBelt::new(CHUNK_SIZE),
+            data: Vec::new(),
+            writes: Vec::new(),
+            frame: 0,
+            last_frame_bytes: 0,
+        }
+    }
+
+    // Index of the frame being recorded, for picking `FrameBuffers` copies
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // What the last frame uploaded through here, for the stats overlay
+    pub fn last_frame_bytes(&self) -> u64 {
+        self.last_frame_bytes
+    }
+
+    // Same as `Queue::write_buffer`, the data lands before the frame's commands run.
+    // Writes are copied in the order they were made.
+    pub fn write<T: bytemuck::Pod>(
+        &mut self,
+        buffer: &wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+        data: &[T],
+    ) {
+        let bytes = bytemuck::cast_slice(data);
+        if bytes.is_empty() {
+            return;
+        }
+        let start = self.data.len();
+        self.data.extend_from_slice(bytes);
+        self.writes
+            .push((buffer.clone(), offset, start..self.data.len()));
+    }
+
+    // The copies for everything written since the last call. Submit it ahead of
+    // the frame's commands, then call `recall`.
+    #[profiling::function]
+    pub fn finish(&mut self, device: &wgpu::Device) -> wgpu::CommandBuffer {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Upload Encoder"),
+        });
+        for (buffer, offset, range) in self.writes.drain(..) {
+            let Some(size) = wgpu::BufferSize::new(range.len() as u64) else {
+                continue;
+            };
+            self.belt
+                .write_buffer(&mut encoder, &buffer, offset, size, device)
+                .copy_from_slice(&self.data[range]);
+        }
+        self.last_frame_bytes = self.data.len() as u64;
+        self.data.clear();
+        self.belt.finish();
+        self.frame += 1;
+        encoder.finish()
+    }
+
+    // After the commands from `finish` are submitted. The staging chunks come
+    // back once the GPU is done copying out of them.
+    #[profiling::function]
+    pub fn recall(&mut self, device: &wgpu::Device) {
+        self.belt.recall();
+        let _ = device.poll(wgpu::PollType::Poll);
+    }
+}
+
+// ===== N-BUFFERED DYNAMIC BUFFERS =====
+// A buffer that's rewritten every frame, with a copy per frame in flight picked
+// by the frame index. Each frame writes its own copy while the GPU may still be
+// drawing from the previous frames' copies, so the write never has to wait on
+// a draw that reads the same memory.
+pub struct FrameBuffers {
+    buffers: Vec<wgpu::Buffer>,
+    current: usize,
+}
+
+impl FrameBuffers {
+    pub fn new(device: &wgpu::Device, descriptor: &wgpu::BufferDescriptor) -> Self {
+        Self {
+            buffers: (0..FRAMES_IN_FLIGHT)
+                .map(|_| device.create_buffer(descriptor))
+                .collect(),
+            current: 0,
+        }
+    }
+
+    // Switches to the copy for `frame` and returns it to be written
+    pub fn next(&mut self, frame: u64) -> &wgpu::Buffer {
+        self.current = (frame % self.buffers.len() as u64) as usize;
+        &self.buffers[self.current]
+    }
+
+    // The copy the latest `next` picked, for drawing
+    pub fn current(&self) -> &wgpu::Buffer {
+        &self.buffers[self.current]
+    }
+}
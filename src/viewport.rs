@@ -0,0 +1,187 @@
+use wgpu::util::DeviceExt;
+
+use crate::upload::Uploads;
+use crate::{camera::CameraUniform, hdr::HdrPipeline, texture, Camera};
+
+// ===== VIEW CAMERAS =====
+// A camera with its own uniform buffer and bind group, for drawing the scene
+// from somewhere other than the main camera. Velocity is tracked per camera.
+pub struct ViewCamera {
+    pub camera: Camera,
+    uniform: CameraUniform,
+    buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl ViewCamera {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        camera: Camera,
+    ) -> Self {
+        let mut uniform = CameraUniform::new();
+        uniform.update_view_proj(&camera);
+        // No motion on the first frame
+        uniform.prev_view_proj = uniform.view_proj;
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("View Camera Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("view_camera_bind_group"),
+        });
+        Self {
+            camera,
+            uniform,
+            buffer,
+            bind_group,
+        }
+    }
+
+    // Uploaded with the frame's other writes
+    pub fn update(&mut self, uploads: &mut Uploads, aspect: f32) {
+        self.camera.aspect = aspect;
+        self.uniform.update_view_proj(&self.camera);
+        uploads.write(&self.buffer, 0, &[self.uniform]);
+    }
+
+    // Written to the queue right away instead, for views drawn in a submit
+    // of their own
+    pub fn write(&mut self, queue: &wgpu::Queue, aspect: f32) {
+        self.camera.aspect = aspect;
+        self.uniform.update_view_proj(&self.camera);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+}
+
+// ===== SPLIT VIEWS =====
+// A view camera drawn into a rectangle of the HDR targets after the main
+// view, like a picture-in-picture close-up
+pub struct SplitView {
+    pub view: ViewCamera,
+    pub rect: [f32; 4], // x, y, width, height as fractions of the surface
+}
+
+impl SplitView {
+    // The rectangle in pixels, kept inside the surface: x, y, width, height
+    pub fn pixel_rect(&self, width: u32, height: u32) -> [u32; 4] {
+        let [x, y, w, h] = self.rect;
+        let x = ((x * width as f32) as u32).min(width);
+        let y = ((y * height as f32) as u32).min(height);
+        let w = ((w * width as f32) as u32).min(width - x);
+        let h = ((h * height as f32) as u32).min(height - y);
+        [x, y, w, h]
+    }
+
+    pub fn update(&mut self, uploads: &mut Uploads, width: u32, height: u32) {
+        let [_, _, w, h] = self.pixel_rect(width, height);
+        let aspect = if w > 0 && h > 0 {
+            w as f32 / h as f32
+        } else {
+            self.view.camera.aspect
+        };
+        self.view.update(uploads, aspect);
+    }
+}
+
+// Resets the current viewport of a scene pass, see viewport_clear.wgsl
+pub struct ViewportClear {
+    shader: wgpu::ShaderModule,
+    color_format: wgpu::TextureFormat,
+    depth_mode: texture::DepthMode,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ViewportClear {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_mode: texture::DepthMode,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Viewport Clear Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("viewport_clear.wgsl").into()),
+        });
+        let pipeline =
+            Self::create_pipeline(device, &shader, color_format, sample_count, depth_mode);
+        Self {
+            shader,
+            color_format,
+            depth_mode,
+            pipeline,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_mode: texture::DepthMode,
+    ) -> wgpu::RenderPipeline {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Viewport Clear Pipeline Layout"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Viewport Clear Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: Some("vs_main"),
+                buffers: &[], // Fullscreen triangle is generated in the shader
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &depth_mode.shader_constants(),
+                    ..Default::default()
+                },
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: Some("fs_main"),
+                targets: &HdrPipeline::scene_targets(color_format, Some(wgpu::BlendState::REPLACE)),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                // Overwrites whatever the main view left behind
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            &self.shader,
+            self.color_format,
+            sample_count,
+            self.depth_mode,
+        );
+    }
+
+    // Only touches the pixels inside the pass's viewport and scissor rect
+    pub fn render(&self, render_pass: &mut wgpu::RenderPass<'_>) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.draw(0..3, 0..1);
+        crate::stats::count_draw();
+    }
+}
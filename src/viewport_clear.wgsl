@@ -0,0 +1,28 @@
+// ===== VIEWPORT CLEAR =====
+// Load ops clear whole attachments, so split views wipe their own rectangle
+// with a fullscreen triangle instead. It resets depth to the far plane and
+// leaves the color for the sky to fill.
+
+// Depth of the far plane, 0.0 with reverse-Z (`DepthMode::shader_constants`)
+override FAR_DEPTH: f32 = 1.0;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), FAR_DEPTH, 1.0);
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) velocity: vec4<f32>,
+    @location(2) linear_depth: f32,
+};
+
+@fragment
+fn fs_main() -> FragmentOutput {
+    var out: FragmentOutput;
+    out.color = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    out.velocity = vec4<f32>(0.0);
+    out.linear_depth = 0.0; // Nothing drawn yet
+    return out;
+}
@@ -0,0 +1,38 @@
+// ===== VIGNETTE =====
+// Darkens the screen towards the corners.
+
+struct VignetteUniform {
+    intensity: f32,
+    smoothness: f32,
+};
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> vignette: VignetteUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// Three vertices covering the whole screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_source, s_source, in.uv);
+    // 0 in the middle, ~1 in the corners
+    let distance = length(in.uv - vec2<f32>(0.5)) * 1.41421356;
+    let falloff = smoothstep(1.0 - vignette.smoothness, 1.0, distance);
+    return vec4<f32>(color.rgb * (1.0 - falloff * vignette.intensity), color.a);
+}
@@ -0,0 +1,373 @@
+use cgmath::prelude::*;
+
+use crate::color::ColorSpace;
+use crate::hdr::HdrPipeline;
+use crate::scene::SceneWater;
+use crate::texture;
+
+const WAVE_NORMALS_SIZE: u32 = 128;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct WaterUniform {
+    position: [f32; 3],
+    size: f32,
+    color: [f32; 3],
+    clarity: f32,
+    time: f32,
+    wave_scale: f32,
+    wave_speed: f32,
+    _padding: f32,
+}
+
+// ===== WATER =====
+// A transparent plane drawn over the lit scene. Two copies of a wave normal
+// map scroll across each other, the sky reflects off the waves by the
+// Fresnel term and what's under the surface shows through a copy of the
+// scene color, bent by the waves and fading into the water's color with
+// depth. Pixels behind something are found from the linear depth target,
+// so it works with MSAA and on either render path.
+pub struct Water {
+    pub settings: SceneWater,
+    time: f32,
+    uniform_buffer: wgpu::Buffer,
+    normals: texture::Texture,
+    normals_sampler: wgpu::Sampler,
+    // The sky it reflects, the skybox's cubemap
+    sky: texture::Texture,
+    // What the scene pass drew, copied each frame before the water goes on it
+    scene_color: texture::Texture,
+    linear_depth: wgpu::TextureView,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Water {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        settings: SceneWater,
+        hdr: &HdrPipeline,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        sky: texture::Texture,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Water Uniform Buffer"),
+            size: std::mem::size_of::<WaterUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let normals = Self::wave_normals(device, queue);
+        // The waves repeat across the surface
+        let normals_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            anisotropy_clamp: 16,
+            ..Default::default()
+        });
+        let scene_color = Self::create_scene_color(device, hdr);
+
+        let texture_entry = |binding, view_dimension, filterable| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension,
+                sample_type: wgpu::TextureSampleType::Float { filterable },
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                // Wave normals
+                texture_entry(0, wgpu::TextureViewDimension::D2, true),
+                sampler_entry(1),
+                // Scene color
+                texture_entry(2, wgpu::TextureViewDimension::D2, true),
+                sampler_entry(3),
+                // Linear depth
+                texture_entry(4, wgpu::TextureViewDimension::D2, false),
+                // Sky
+                texture_entry(5, wgpu::TextureViewDimension::Cube, true),
+                sampler_entry(6),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("water_bind_group_layout"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Water Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("water.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Water Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout, camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Water Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[], // The quad is generated in the shader
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: hdr.format(),
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::COLOR,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            // Seen from above and below
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let linear_depth = hdr.linear_depth_view().clone();
+        let bind_group = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            &normals,
+            &normals_sampler,
+            &scene_color,
+            &linear_depth,
+            &sky,
+            &uniform_buffer,
+        );
+        let water = Self {
+            settings,
+            time: 0.0,
+            uniform_buffer,
+            normals,
+            normals_sampler,
+            sky,
+            scene_color,
+            linear_depth,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        };
+        water.write_uniform(queue);
+        water
+    }
+
+    fn create_scene_color(device: &wgpu::Device, hdr: &HdrPipeline) -> texture::Texture {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Water Scene Color"),
+            size: hdr.texture().size(),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: hdr.format(),
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        texture::Texture {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        normals: &texture::Texture,
+        normals_sampler: &wgpu::Sampler,
+        scene_color: &texture::Texture,
+        linear_depth: &wgpu::TextureView,
+        sky: &texture::Texture,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&normals.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(normals_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&scene_color.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&scene_color.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(linear_depth),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&sky.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::Sampler(&sky.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("water_bind_group"),
+        })
+    }
+
+    fn update_bind_group(&mut self, device: &wgpu::Device) {
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.normals,
+            &self.normals_sampler,
+            &self.scene_color,
+            &self.linear_depth,
+            &self.sky,
+            &self.uniform_buffer,
+        );
+    }
+
+    // Ripples made of waves with whole numbers of crests across the map, so
+    // it tiles. Tangent space with Z out of the surface, like any normal map.
+    fn wave_normals(device: &wgpu::Device, queue: &wgpu::Queue) -> texture::Texture {
+        // Crests across in X and Y, height and phase
+        const WAVES: [(f32, f32, f32, f32); 6] = [
+            (1.0, 2.0, 0.012, 0.0),
+            (3.0, -1.0, 0.008, 1.3),
+            (-2.0, 5.0, 0.004, 2.1),
+            (6.0, 3.0, 0.003, 4.0),
+            (-7.0, -4.0, 0.002, 0.7),
+            (11.0, -9.0, 0.001, 5.2),
+        ];
+        let image = image::RgbaImage::from_fn(WAVE_NORMALS_SIZE, WAVE_NORMALS_SIZE, |x, y| {
+            let u = x as f32 / WAVE_NORMALS_SIZE as f32;
+            let v = y as f32 / WAVE_NORMALS_SIZE as f32;
+            let (mut dx, mut dy) = (0.0, 0.0);
+            for (kx, ky, height, phase) in WAVES {
+                let angle = std::f32::consts::TAU * (kx * u + ky * v) + phase;
+                let slope = height * std::f32::consts::TAU * angle.cos();
+                dx += slope * kx;
+                dy += slope * ky;
+            }
+            let normal = cgmath::Vector3::new(-dx, -dy, 1.0).normalize();
+            let encode = |c: f32| ((c * 0.5 + 0.5) * 255.0).round() as u8;
+            image::Rgba([encode(normal.x), encode(normal.y), encode(normal.z), 255])
+        });
+        // Writing a generated image can't fail
+        texture::Texture::from_image_with_format(
+            device,
+            queue,
+            &image::DynamicImage::ImageRgba8(image),
+            Some("Wave Normals"),
+            ColorSpace::Linear.texture_format(),
+        )
+        .unwrap()
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        let uniform = WaterUniform {
+            position: self.settings.position,
+            size: self.settings.size,
+            color: self.settings.color,
+            clarity: self.settings.clarity,
+            time: self.time,
+            wave_scale: self.settings.wave_scale,
+            wave_speed: self.settings.wave_speed,
+            _padding: 0.0,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    // Moves the waves on
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: f32) {
+        self.time += dt;
+        self.write_uniform(queue);
+    }
+
+    // The copy of the scene color follows the HDR target's size
+    pub fn resize(&mut self, device: &wgpu::Device, hdr: &HdrPipeline) {
+        self.scene_color = Self::create_scene_color(device, hdr);
+        self.linear_depth = hdr.linear_depth_view().clone();
+        self.update_bind_group(device);
+    }
+
+    pub fn set_sky(&mut self, device: &wgpu::Device, sky: texture::Texture) {
+        self.sky = sky;
+        self.update_bind_group(device);
+    }
+
+    // Copies the scene color, then blends the water over it
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        hdr: &HdrPipeline,
+        camera_bind_group: &wgpu::BindGroup,
+    ) {
+        encoder.copy_texture_to_texture(
+            hdr.texture().as_image_copy(),
+            self.scene_color.texture.as_image_copy(),
+            hdr.texture().size(),
+        );
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Water Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: hdr.view(),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        render_pass.draw(0..6, 0..1);
+        crate::stats::count_draw();
+    }
+}
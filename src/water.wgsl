@@ -0,0 +1,129 @@
+// ===== WATER =====
+// A square on the XZ plane blended over the lit scene. What's under it comes
+// from a copy of the scene color, the sky reflects off it, and the wave
+// normals scroll across both.
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct WaterUniform {
+    position: vec3<f32>,
+    size: f32,
+    color: vec3<f32>,
+    clarity: f32,
+    time: f32,
+    wave_scale: f32,
+    wave_speed: f32,
+};
+
+@group(0) @binding(0)
+var t_normals: texture_2d<f32>;
+@group(0) @binding(1)
+var s_normals: sampler;
+@group(0) @binding(2)
+var t_scene: texture_2d<f32>;
+@group(0) @binding(3)
+var s_scene: sampler;
+// Linear view depth from the scene pass, 0.0 where nothing was drawn
+@group(0) @binding(4)
+var t_linear_depth: texture_2d<f32>;
+@group(0) @binding(5)
+var t_sky: texture_cube<f32>;
+@group(0) @binding(6)
+var s_sky: sampler;
+@group(0) @binding(7)
+var<uniform> water: WaterUniform;
+
+// Fraction of the screen the waves can shift what's seen through them
+const REFRACTION_STRENGTH: f32 = 0.03;
+// Head-on reflectance of water
+const WATER_F0: f32 = 0.02;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) view_depth: f32,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    // Two triangles, corners from 0 to 1
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0), vec2<f32>(0.0, 1.0), vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 0.0), vec2<f32>(0.0, 1.0), vec2<f32>(1.0, 1.0),
+    );
+    let corner = (corners[vertex_index] - 0.5) * water.size;
+    let world_position = water.position + vec3<f32>(corner.x, 0.0, corner.y);
+    var out: VertexOutput;
+    out.world_position = world_position;
+    out.view_depth = -(camera.view * vec4<f32>(world_position, 1.0)).z;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    return out;
+}
+
+// How deep the water is along the view ray where the scene has `depth`.
+// Nothing drawn there is as good as bottomless.
+fn thickness(depth: f32, view_depth: f32) -> f32 {
+    if (depth == 0.0) {
+        return 1e4;
+    }
+    return depth - view_depth;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Two copies of the waves crossing at different sizes and speeds
+    let uv = in.world_position.xz / water.wave_scale;
+    let scroll = water.time * water.wave_speed / water.wave_scale;
+    let a = textureSample(t_normals, s_normals, uv + vec2<f32>(scroll, scroll * 0.4)).xyz * 2.0 - 1.0;
+    let b = textureSample(t_normals, s_normals, uv * 0.6 - vec2<f32>(scroll * 0.3, -scroll * 0.7)).xyz * 2.0 - 1.0;
+    // Tangent space Y runs along world Z
+    let ripple = normalize(a + b);
+    var normal = normalize(vec3<f32>(ripple.x, ripple.z, ripple.y));
+
+    let coords = vec2<i32>(in.clip_position.xy);
+    let depth = textureLoad(t_linear_depth, coords, 0).r;
+    if (depth > 0.0 && depth < in.view_depth) {
+        discard; // Something's in front of the water
+    }
+
+    let to_camera = camera.view_position.xyz - in.world_position;
+    let v = normalize(to_camera);
+    // From below the waves face down
+    if (to_camera.y < 0.0) {
+        normal = -normal;
+    }
+
+    // What's under the surface, shifted by the waves unless that picks up
+    // something in front of the water instead
+    let screen_size = vec2<f32>(textureDimensions(t_scene));
+    let screen_uv = in.clip_position.xy / screen_size;
+    var refracted_uv = screen_uv + normal.xz * REFRACTION_STRENGTH;
+    let refracted_coords = clamp(vec2<i32>(refracted_uv * screen_size), vec2<i32>(0), vec2<i32>(screen_size) - 1);
+    var refracted_depth = textureLoad(t_linear_depth, refracted_coords, 0).r;
+    if (refracted_depth > 0.0 && refracted_depth < in.view_depth) {
+        refracted_uv = screen_uv;
+        refracted_depth = depth;
+    }
+    let under = textureSampleLevel(t_scene, s_scene, refracted_uv, 0.0).rgb;
+    // Halfway to the water's color at `clarity` deep
+    let fade = 1.0 - exp2(-thickness(refracted_depth, in.view_depth) / water.clarity);
+    let refraction = mix(under, water.color, fade);
+
+    let reflection = textureSampleLevel(t_sky, s_sky, reflect(-v, normal), 0.0).rgb;
+    let fresnel = WATER_F0 + (1.0 - WATER_F0) * pow(1.0 - max(dot(normal, v), 0.0), 5.0);
+    let color = mix(refraction, reflection, fresnel);
+
+    // Fades out at the shore instead of ending in a hard line
+    let shore = clamp(thickness(depth, in.view_depth) * 4.0, 0.0, 1.0);
+    return vec4<f32>(color, shore);
+}
@@ -0,0 +1,151 @@
+use anyhow::Context;
+use winit::monitor::{MonitorHandle, VideoModeHandle};
+use winit::window::{CursorGrabMode, Fullscreen, Window};
+
+// How the window takes up the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WindowMode {
+    #[default]
+    Windowed,
+    // Covers its monitor without changing the monitor's resolution
+    Borderless,
+    // Takes the monitor over at a video mode of its own
+    Exclusive,
+}
+
+impl WindowMode {
+    pub fn next(self) -> Self {
+        match self {
+            WindowMode::Windowed => WindowMode::Borderless,
+            WindowMode::Borderless => WindowMode::Exclusive,
+            WindowMode::Exclusive => WindowMode::Windowed,
+        }
+    }
+}
+
+// One way a monitor can run in exclusive fullscreen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VideoMode {
+    pub size: [u32; 2],
+    pub refresh_rate_millihertz: u32,
+    pub bit_depth: u16,
+}
+
+impl VideoMode {
+    fn new(handle: &VideoModeHandle) -> Self {
+        Self {
+            size: handle.size().into(),
+            refresh_rate_millihertz: handle.refresh_rate_millihertz(),
+            bit_depth: handle.bit_depth(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Monitor {
+    pub name: String,
+    // In physical pixels, at the resolution it's running at now
+    pub size: [u32; 2],
+    pub scale_factor: f64,
+    // Biggest and fastest first
+    pub video_modes: Vec<VideoMode>,
+}
+
+impl Monitor {
+    fn new(handle: &MonitorHandle) -> Self {
+        let mut video_modes: Vec<_> = handle
+            .video_modes()
+            .map(|mode| VideoMode::new(&mode))
+            .collect();
+        video_modes.sort_by_key(|mode| {
+            std::cmp::Reverse((
+                mode.size[0] * mode.size[1],
+                mode.refresh_rate_millihertz,
+                mode.bit_depth,
+            ))
+        });
+        Self {
+            name: handle
+                .name()
+                .unwrap_or_else(|| "Unnamed monitor".to_string()),
+            size: handle.size().into(),
+            scale_factor: handle.scale_factor(),
+            video_modes,
+        }
+    }
+}
+
+// Every monitor the window could go fullscreen on
+pub fn monitors(window: &Window) -> Vec<Monitor> {
+    window
+        .available_monitors()
+        .map(|monitor| Monitor::new(&monitor))
+        .collect()
+}
+
+// ===== WINDOW MODES =====
+// Puts the window in `mode` on the monitor it's on now. Exclusive fullscreen
+// runs at `resolution` when the monitor has a video mode for it, otherwise
+// at the monitor's current resolution, the fastest refresh rate going for
+// either. The window gets resized along the way, which reconfigures the
+// surface like any other resize.
+pub fn set_window_mode(
+    window: &Window,
+    mode: WindowMode,
+    resolution: Option<[u32; 2]>,
+) -> anyhow::Result<()> {
+    let fullscreen = match mode {
+        WindowMode::Windowed => None,
+        WindowMode::Borderless => Some(Fullscreen::Borderless(window.current_monitor())),
+        WindowMode::Exclusive => {
+            let monitor = window
+                .current_monitor()
+                .or_else(|| window.primary_monitor())
+                .context("The window isn't on a monitor")?;
+            let size: [u32; 2] = resolution.unwrap_or(monitor.size().into());
+            // Highest refresh rate and bit depth first
+            let mut video_modes: Vec<_> = monitor
+                .video_modes()
+                .filter(|mode| <[u32; 2]>::from(mode.size()) == size)
+                .collect();
+            video_modes.sort_by_key(|mode| {
+                std::cmp::Reverse((mode.refresh_rate_millihertz(), mode.bit_depth()))
+            });
+            let video_mode = video_modes.into_iter().next().with_context(|| {
+                format!(
+                    "{} has no {}x{} video mode",
+                    monitor.name().unwrap_or_default(),
+                    size[0],
+                    size[1]
+                )
+            })?;
+            Some(Fullscreen::Exclusive(video_mode))
+        }
+    };
+    window.set_fullscreen(fullscreen);
+    Ok(())
+}
+
+// ===== CURSOR GRAB =====
+// Keeps the cursor in the window and out of sight for mouselook, or gives it
+// back. Locking it in place is best, but Windows and X11 can only confine it
+// to the window and macOS can only lock it, so whichever works is used. The
+// camera turns with raw mouse motion, which carries on at the window's edge.
+pub fn set_cursor_grabbed(window: &Window, grabbed: bool) -> anyhow::Result<CursorGrabMode> {
+    let mode = if grabbed {
+        window
+            .set_cursor_grab(CursorGrabMode::Locked)
+            .map(|_| CursorGrabMode::Locked)
+            .or_else(|_| {
+                window
+                    .set_cursor_grab(CursorGrabMode::Confined)
+                    .map(|_| CursorGrabMode::Confined)
+            })
+            .context("The cursor can't be grabbed here")?
+    } else {
+        window.set_cursor_grab(CursorGrabMode::None)?;
+        CursorGrabMode::None
+    };
+    window.set_cursor_visible(!grabbed);
+    Ok(mode)
+}